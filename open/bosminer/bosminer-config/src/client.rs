@@ -21,6 +21,7 @@
 // contact us at opensource@braiins.com.
 
 use crate::error;
+use crate::{ScheduleConfig, SimulationConfig, StratumV2Config};
 
 use ii_stratum::v2;
 
@@ -32,7 +33,7 @@ use std::fmt;
 use failure::ResultExt;
 
 pub const URL_JAVA_SCRIPT_REGEX: &'static str =
-    "(?:drain|(?:stratum2?\\+tcp(?:\\+insecure)?)):\\/\\/[\\w\\.-]+(?::\\d+)?(?:\\/[\\dA-HJ-NP-Za-km-z]+)?";
+    "(?:drain|simulation|(?:stratum2?\\+tcp(?:\\+insecure)?)):\\/\\/[\\w\\.-]+(?::\\d+)?(?:\\/[\\dA-HJ-NP-Za-km-z]+)?";
 
 #[derive(Clone, Debug)]
 pub enum Protocol {
@@ -40,6 +41,7 @@ pub enum Protocol {
     StratumV1,
     StratumV2(v2::noise::auth::EncodedEd25519PublicKey),
     StratumV2Insecure,
+    Simulation,
 }
 
 impl Protocol {
@@ -47,11 +49,13 @@ impl Protocol {
     pub const SCHEME_STRATUM_V1: &'static str = "stratum+tcp";
     pub const SCHEME_STRATUM_V2: &'static str = "stratum2+tcp";
     pub const SCHEME_STRATUM_V2_INSECURE: &'static str = "stratum2+tcp+insecure";
+    pub const SCHEME_SIMULATION: &'static str = "simulation";
 
     pub const DEFAULT_PORT_DRAIN: u16 = 0;
     pub const DEFAULT_PORT_STRATUM_V1: u16 = 3333;
     pub const DEFAULT_PORT_STRATUM_V2: u16 = 3336;
     pub const DEFAULT_PORT_STRATUM_V2_INSECURE: u16 = 3336;
+    pub const DEFAULT_PORT_SIMULATION: u16 = 0;
 
     pub fn default_port(&self) -> u16 {
         match self {
@@ -59,6 +63,7 @@ impl Protocol {
             Self::StratumV1 => Self::DEFAULT_PORT_STRATUM_V1,
             Self::StratumV2(_) => Self::DEFAULT_PORT_STRATUM_V2,
             Self::StratumV2Insecure => Self::DEFAULT_PORT_STRATUM_V2_INSECURE,
+            Self::Simulation => Self::DEFAULT_PORT_SIMULATION,
         }
     }
 
@@ -89,6 +94,7 @@ impl Protocol {
                 Self::StratumV2(upstream_authority_public_key)
             }
             Self::SCHEME_STRATUM_V2_INSECURE => Self::StratumV2Insecure,
+            Self::SCHEME_SIMULATION => Self::Simulation,
             _ => Err(error::ErrorKind::Client(format!(
                 "unknown protocol '{}'",
                 scheme
@@ -102,6 +108,7 @@ impl Protocol {
             Self::StratumV1 => Self::SCHEME_STRATUM_V1,
             Self::StratumV2(_) => Self::SCHEME_STRATUM_V2,
             Self::StratumV2Insecure => Self::SCHEME_STRATUM_V2_INSECURE,
+            Self::Simulation => Self::SCHEME_SIMULATION,
         }
     }
 }
@@ -115,6 +122,7 @@ impl fmt::Display for Protocol {
                 write!(f, "Stratum V2 (authority key: {})", public_key)
             }
             Protocol::StratumV2Insecure => write!(f, "Stratum V2 Insecure"),
+            Protocol::Simulation => write!(f, "Simulation"),
         }
     }
 }
@@ -154,6 +162,12 @@ pub struct Descriptor {
     pub port: Option<u16>,
     // Currently used only for `#xnsub`: `stratum+tcp://equihash.eu.nicehash.com:3357#xnsub`
     pub fragment: Option<String>,
+    /// Additional settings used only by Stratum V2 clients
+    pub stratum_v2: StratumV2Config,
+    /// Additional settings used only by simulation clients
+    pub simulation: SimulationConfig,
+    /// Daily windows in which the client is preferred by the scheduler
+    pub schedule: Option<ScheduleConfig>,
 }
 
 impl Descriptor {
@@ -209,6 +223,9 @@ impl Descriptor {
             host,
             port,
             fragment,
+            stratum_v2: Default::default(),
+            simulation: Default::default(),
+            schedule: None,
         })
     }
 }
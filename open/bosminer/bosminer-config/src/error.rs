@@ -38,6 +38,10 @@ pub enum ErrorKind {
     /// Error related to client settings.
     #[fail(display = "{}", _0)]
     Client(String),
+
+    /// Error related to the file with pool user (path, reason)
+    #[fail(display = "cannot read user file '{}': {}", _0, _1)]
+    UserFile(String, String),
 }
 
 /// Implement Fail trait instead of use Derive to get more control over custom type.
@@ -23,6 +23,9 @@
 mod client;
 mod error;
 mod group;
+mod schedule;
+mod simulation;
+mod stratum_v2;
 
 // Reexport inner structures
 pub use client::Descriptor as ClientDescriptor;
@@ -33,6 +36,36 @@ pub use client::URL_JAVA_SCRIPT_REGEX as CLIENT_URL_JAVA_SCRIPT_REGEX;
 pub use group::Descriptor as GroupDescriptor;
 pub use group::LoadBalanceStrategy;
 
+pub use schedule::Config as ScheduleConfig;
+pub use schedule::OutsideWindow as ScheduleOutsideWindow;
+pub use schedule::TimeZone as ScheduleTimeZone;
+pub use schedule::Window as ScheduleWindow;
+
+pub use simulation::Config as SimulationConfig;
+
+pub use stratum_v2::AckSequencing as StratumV2AckSequencing;
+pub use stratum_v2::BandwidthLimit as StratumV2BandwidthLimit;
+pub use stratum_v2::Config as StratumV2Config;
+pub use stratum_v2::EarlyShare as StratumV2EarlyShare;
+pub use stratum_v2::FlushedJobSolutions;
+pub use stratum_v2::Handover as StratumV2Handover;
+pub use stratum_v2::JobDispatchLimit as StratumV2JobDispatchLimit;
+pub use stratum_v2::JobDispatchPolicy;
+pub use stratum_v2::JobIdReuse as StratumV2JobIdReuse;
+pub use stratum_v2::MissingTargetPolicy;
+pub use stratum_v2::NonceByteOrder;
+pub use stratum_v2::OutOfMaskVersions;
+pub use stratum_v2::RejectStreak as StratumV2RejectStreak;
+pub use stratum_v2::RestartStorm as StratumV2RestartStorm;
+pub use stratum_v2::ShareOrderingCheck;
+pub use stratum_v2::StartupBudget as StratumV2StartupBudget;
+pub use stratum_v2::StartupTarget as StratumV2StartupTarget;
+pub use stratum_v2::SubmissionWindowPolicy;
+pub use stratum_v2::TargetApplication;
+pub use stratum_v2::TransmitStall as StratumV2TransmitStall;
+pub use stratum_v2::UnknownPoolFlags;
+pub use stratum_v2::UserRedaction as StratumV2UserRedaction;
+
 // reexport common crates
 pub use clap;
 pub use config;
@@ -45,9 +78,17 @@ pub struct PoolConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub enabled: Option<bool>,
     pub url: String,
+    /// Can be omitted when the user is read from a file (see `StratumV2Config::user_file`)
+    #[serde(default)]
     pub user: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stratum_v2: Option<StratumV2Config>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub simulation: Option<SimulationConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<ScheduleConfig>,
 }
 
 // NOTE: `#[serde(deny_unknown_fields)]` cannot be used due to flatten descriptor but the error is
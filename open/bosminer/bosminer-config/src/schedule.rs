@@ -0,0 +1,137 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Optional per-pool schedule of daily time windows in which the pool is preferred by the
+//! scheduler (e.g. mining to an own node only when the electricity is cheap)
+
+use crate::error;
+
+use serde::{Deserialize, Serialize};
+
+/// Time in which the windows are specified
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeZone {
+    /// Local time zone of the system
+    Local,
+    Utc,
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        Self::Local
+    }
+}
+
+/// Treatment of the client while all its windows are closed
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutsideWindow {
+    /// The client stays connected to be activated without delay when a window opens
+    Standby,
+    /// The client is stopped and connects again when a window opens
+    Stop,
+}
+
+impl Default for OutsideWindow {
+    fn default() -> Self {
+        Self::Standby
+    }
+}
+
+/// Daily time window in the `HH:MM` format. A window with the end before the start spans
+/// midnight.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Window {
+    pub start: String,
+    pub end: String,
+}
+
+impl Window {
+    const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+    fn parse_time(time: &str) -> error::Result<u32> {
+        let invalid = || error::ErrorKind::Client(format!("invalid time of day '{}'", time));
+        let mut parts = time.splitn(2, ':');
+        let hours: u32 = parts
+            .next()
+            .and_then(|hours| hours.parse().ok())
+            .ok_or_else(invalid)?;
+        let minutes: u32 = parts
+            .next()
+            .and_then(|minutes| minutes.parse().ok())
+            .ok_or_else(invalid)?;
+        if hours >= 24 || minutes >= 60 {
+            Err(invalid())?
+        }
+        Ok(hours * 60 * 60 + minutes * 60)
+    }
+
+    /// Returns the start and the end of the window in seconds since midnight
+    pub fn bounds(&self) -> error::Result<(u32, u32)> {
+        let start = Self::parse_time(&self.start)?;
+        let end = Self::parse_time(&self.end)?;
+        if start == end {
+            Err(error::ErrorKind::Client(format!(
+                "empty schedule window {}-{}",
+                self.start, self.end
+            )))?
+        }
+        Ok((start, end))
+    }
+
+    /// Check whether the window contains the time in seconds since midnight
+    pub fn contains(bounds: (u32, u32), time: u32) -> bool {
+        let (start, end) = bounds;
+        let time = time % Self::SECONDS_PER_DAY;
+        if start < end {
+            time >= start && time < end
+        } else {
+            time >= start || time < end
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub windows: Vec<Window>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_zone: Option<TimeZone>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outside_window: Option<OutsideWindow>,
+}
+
+impl Config {
+    pub fn validate(&self) -> error::Result<()> {
+        if self.windows.is_empty() {
+            Err(error::ErrorKind::Client(
+                "schedule must have at least one window".to_string(),
+            ))?
+        }
+        for window in &self.windows {
+            window.bounds()?;
+        }
+        Ok(())
+    }
+}
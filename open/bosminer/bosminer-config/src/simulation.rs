@@ -0,0 +1,82 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Optional per-pool settings of the simulation client that generates jobs locally and
+//! acknowledges solutions without any pool
+
+use crate::error;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Seed of the generator of jobs and acknowledgements. The same seed always produces the same
+    /// sequence of jobs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Interval between two generated jobs in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_interval: Option<u64>,
+    /// Difficulty of generated jobs. Difficulty 0 represents the easiest possible target that is
+    /// met by every solution, it is intended for solvers that don't compute real hashes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub difficulty: Option<usize>,
+    /// Number of jobs generated for a single previous block hash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jobs_per_prev_hash: Option<u64>,
+    /// Delay of acknowledgements of submitted solutions in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ack_latency: Option<u64>,
+    /// Probability that a valid solution is rejected (0.0 to 1.0)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reject_probability: Option<f64>,
+}
+
+impl Config {
+    pub const DEFAULT_SEED: u64 = 0;
+    pub const DEFAULT_JOB_INTERVAL: u64 = 10_000;
+    pub const DEFAULT_DIFFICULTY: usize = 512;
+    pub const DEFAULT_JOBS_PER_PREV_HASH: u64 = 60;
+
+    pub fn validate(&self) -> error::Result<()> {
+        if self.job_interval == Some(0) {
+            Err(error::ErrorKind::Client(
+                "job interval must be at least 1 ms".to_string(),
+            ))?
+        }
+        if self.jobs_per_prev_hash == Some(0) {
+            Err(error::ErrorKind::Client(
+                "at least one job has to be generated for each previous block hash".to_string(),
+            ))?
+        }
+        if let Some(probability) = self.reject_probability {
+            if !(probability >= 0.0 && probability <= 1.0) {
+                Err(error::ErrorKind::Client(format!(
+                    "reject probability must be between 0.0 and 1.0 (is {})",
+                    probability
+                )))?
+            }
+        }
+        Ok(())
+    }
+}
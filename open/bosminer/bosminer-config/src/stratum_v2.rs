@@ -0,0 +1,1031 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Optional per-pool settings that tune the behavior of the Stratum V2 client
+
+use crate::error;
+
+use ii_stratum::v2;
+
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Masking of the pool user in logs and in the `Display` output of the client
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UserRedaction {
+    /// Show only a short hash of the user (the same user always maps to the same hash)
+    Hash,
+    /// Show only the first few characters of the user
+    Prefix,
+}
+
+/// Reaction to share submission ordering violations
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareOrderingCheck {
+    /// Only log the violation
+    Log,
+    /// Log the violation and reconnect to the pool
+    Reconnect,
+}
+
+/// Handling of a job that reuses the ID of a known job with different content (a protocol
+/// violation of the pool)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobIdReuse {
+    /// Replace the known job with the new one
+    Overwrite,
+    /// Keep the known job and drop the new one
+    Ignore,
+    /// Reconnect to the pool
+    Reconnect,
+}
+
+impl Default for JobIdReuse {
+    fn default() -> Self {
+        Self::Overwrite
+    }
+}
+
+/// Handling of shares found while the submission window is full
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionWindowPolicy {
+    /// Hold the shares until the pool acknowledges submitted shares. At most `submission_window`
+    /// most recent shares are held, older ones are dropped.
+    Hold,
+    /// Drop the shares
+    Drop,
+}
+
+impl Default for SubmissionWindowPolicy {
+    fn default() -> Self {
+        Self::Hold
+    }
+}
+
+/// Handling of shares found for a job that has been flushed by a new previous block hash. Such
+/// shares are stale, although some pools still credit them within a grace period.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FlushedJobSolutions {
+    /// Count the shares locally without submitting them
+    Drop,
+    /// Submit the shares as any other share
+    Submit,
+    /// Submit the shares and count them separately
+    SubmitAndCount,
+}
+
+impl Default for FlushedJobSolutions {
+    fn default() -> Self {
+        Self::Drop
+    }
+}
+
+/// Handling of shares whose version has rolled bits outside the version rolling mask of the job
+/// (BIP320 bits or less when negotiated with the pool). Such shares are a symptom of a backend
+/// bug and they risk a reject or worse.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutOfMaskVersions {
+    /// Count the shares locally without submitting them
+    Drop,
+    /// Restore the bits outside the mask from the version of the job and submit the share when it
+    /// still meets the target of the pool
+    Mask,
+    /// Submit the shares as they are
+    Submit,
+}
+
+impl Default for OutOfMaskVersions {
+    fn default() -> Self {
+        Self::Drop
+    }
+}
+
+/// Byte order of the nonce in submitted shares
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NonceByteOrder {
+    /// Nonce as specified by the protocol
+    Spec,
+    /// Nonce with reversed bytes (for a pool that interprets it in the opposite byte order)
+    Swapped,
+}
+
+impl Default for NonceByteOrder {
+    fn default() -> Self {
+        Self::Spec
+    }
+}
+
+/// Handling of flags of `SetupConnectionSuccess` that the client doesn't recognize
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownPoolFlags {
+    /// Proceed with the connection as if the flags were not set
+    Ignore,
+    /// Fail the handshake, the pool would be used with an unexpected feature profile
+    Reject,
+}
+
+impl Default for UnknownPoolFlags {
+    fn default() -> Self {
+        Self::Ignore
+    }
+}
+
+/// Point at which a target sent by the pool with `SetTarget` takes effect. Pools differ in their
+/// interpretation and a mismatch causes discrepancies between shares accepted by the pool and
+/// shares accounted locally.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetApplication {
+    /// The target applies from the next job sent by the pool (`NewMiningJob` or
+    /// `SetNewPrevHash`). The job that is being solved keeps its target, i.e. its solutions are
+    /// submitted and accounted with the previous target.
+    NextJob,
+    /// The target applies immediately. The job that is being solved is dispatched again with the
+    /// new target, so every solution found from then on is submitted and accounted with it.
+    /// Solutions found before the target has been received keep the previous target.
+    Immediate,
+}
+
+impl Default for TargetApplication {
+    fn default() -> Self {
+        Self::NextJob
+    }
+}
+
+/// Jobs that are dispatched to the backend as soon as they are received
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobDispatchPolicy {
+    /// Every job for the current previous block hash is dispatched right away, so the backend
+    /// always solves the freshest merkle root (with transactions added by the pool).
+    Immediate,
+    /// Only a new previous block hash starts solving a job. Intermediate job updates for the
+    /// current previous block hash are stored but never dispatched, the backend switches jobs
+    /// less often while it keeps solving a staler merkle root (missing the fees of transactions
+    /// that have arrived since).
+    PrevHashBoundary,
+}
+
+impl Default for JobDispatchPolicy {
+    fn default() -> Self {
+        Self::Immediate
+    }
+}
+
+/// Handling of the first job of a session when the pool has provided no usable target yet
+/// (neither when the channel has been opened nor with `SetTarget`)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingTargetPolicy {
+    /// The job is dispatched right away with difficulty 1. Shares below the target the pool
+    /// expects are rejected until it sets one.
+    Fallback,
+    /// The job is held until the pool sets a target or until the wait elapses, then it falls
+    /// back to difficulty 1. The backend is idle while the job is held.
+    Wait,
+}
+
+impl Default for MissingTargetPolicy {
+    fn default() -> Self {
+        Self::Wait
+    }
+}
+
+/// Target used locally right after the channel is opened, before the pool adjusts the target to
+/// the hashrate of the miner
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct StartupTarget {
+    /// Nominal hashrate of the miner in TH/s
+    pub nominal_hashrate: f64,
+    /// Number of shares per minute the starting target is derived for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shares_per_minute: Option<f64>,
+    /// Duration of the startup window in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window: Option<u64>,
+}
+
+impl StartupTarget {
+    pub const DEFAULT_SHARES_PER_MINUTE: f64 = 20.0;
+    pub const DEFAULT_WINDOW: u64 = 60;
+}
+
+/// Strict verification of the order in which the pool acknowledges shares. It is meant for pools
+/// that guarantee in-order acknowledgement, other pools are free to acknowledge shares in batches
+/// and in any order.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AckSequencing {
+    /// Time in seconds within which shares skipped by an acknowledgement must be acknowledged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gap_window: Option<u64>,
+    /// Reaction to detected sequencing anomalies (`log` by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reaction: Option<ShareOrderingCheck>,
+}
+
+impl AckSequencing {
+    pub const DEFAULT_GAP_WINDOW: u64 = 30;
+}
+
+/// Ceiling on the rate of job switches dispatched to the backend. It protects hardware backends
+/// that cannot cope with frequent job switches. Only jobs that keep the previous block hash are
+/// limited, a new previous block hash is always dispatched right away. A job received while the
+/// limit is reached is held back and a newer job replaces it, the jobs are never queued.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct JobDispatchLimit {
+    /// Number of job switches per minute
+    pub rate: f64,
+    /// Number of job switches that may be dispatched in a row after a quiet period
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+}
+
+impl JobDispatchLimit {
+    pub const DEFAULT_BURST: u32 = 1;
+}
+
+/// Detection of a connection that keeps delivering jobs while share submissions stall. All
+/// values are in milliseconds.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TransmitStall {
+    /// Duration of a share send that is considered abnormally slow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_send: Option<u64>,
+    /// Time after which shares that are not acknowledged while the pool keeps sending frames are
+    /// considered stalled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ack_timeout: Option<u64>,
+    /// Time for which the stall may persist before the connection is restarted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grace: Option<u64>,
+}
+
+impl TransmitStall {
+    pub const DEFAULT_SLOW_SEND: u64 = 500;
+    pub const DEFAULT_ACK_TIMEOUT: u64 = 30_000;
+    pub const DEFAULT_GRACE: u64 = 30_000;
+}
+
+/// Detection of rolling restarts of the pool frontends that close the connections and refuse new
+/// ones for a while. Several short sessions in quick succession switch the client to the "pool
+/// restarting" mode: the connection is retried at a fixed interval and the scheduler doesn't fail
+/// over to another pool until the mode ends. All values are in seconds.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RestartStorm {
+    /// A session that ends within this time after the connection attempt is short
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub short_session: Option<u64>,
+    /// Number of short sessions within `window` that start the mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sessions: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window: Option<u64>,
+    /// Interval of connection attempts while the pool is restarting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_interval: Option<u64>,
+    /// Maximal duration of the mode, the failures are handled as usual afterwards (including the
+    /// failover) until a session outlasts `short_session`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_duration: Option<u64>,
+}
+
+impl RestartStorm {
+    pub const DEFAULT_SHORT_SESSION: u64 = 10;
+    pub const DEFAULT_SESSIONS: usize = 3;
+    pub const DEFAULT_WINDOW: u64 = 60;
+    pub const DEFAULT_RETRY_INTERVAL: u64 = 15;
+    pub const DEFAULT_MAX_DURATION: u64 = 180;
+}
+
+/// Limit of the throughput of the pool connection in bytes per second (e.g. on a metered satellite
+/// or cellular uplink). The size of Stratum frames is counted, the overhead of the encryption and
+/// of TCP is not. A direction is not limited when its rate is not specified.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BandwidthLimit {
+    /// Rate of frames sent to the pool, frames that exceed it are queued
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_rate: Option<u64>,
+    /// Rate of frames received from the pool, further frames are not read from the connection
+    /// while it is exceeded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receive_rate: Option<u64>,
+    /// Number of bytes that may be transferred at once above the rate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u64>,
+}
+
+impl BandwidthLimit {
+    pub const DEFAULT_BURST: u64 = 4096;
+}
+
+/// Detection of shares that keep being rejected for the same reason (e.g. a wrong user or a
+/// misconfigured version rolling). Such a streak indicates a systemic problem rather than bad
+/// luck, it is escalated once when it reaches the threshold. An accepted share ends the streak.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RejectStreak {
+    /// Number of consecutive shares rejected for the same reason that are escalated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<usize>,
+    /// Reaction to the escalated streak (`log` by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reaction: Option<ShareOrderingCheck>,
+}
+
+impl RejectStreak {
+    pub const DEFAULT_THRESHOLD: usize = 10;
+}
+
+/// Accommodation of pools that drop a channel when no share is submitted within a deadline after
+/// the channel has been opened. The client requests an initial target at which the miner is
+/// expected to find `expected_shares` shares within the deadline, later `SetTarget` raises the
+/// difficulty as usual.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct EarlyShare {
+    /// Nominal hashrate of the miner in TH/s
+    pub nominal_hashrate: f64,
+    /// Time in seconds after the channel has been opened within which the pool requires a share
+    pub deadline: u64,
+    /// Number of shares expected within the deadline at the requested target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_shares: Option<f64>,
+}
+
+impl EarlyShare {
+    pub const DEFAULT_EXPECTED_SHARES: f64 = 10.0;
+}
+
+/// Hand-over of the client state to the next instance of the process (e.g. across a firmware
+/// upgrade that restarts the miner). The snapshot is written when the client is stopped and read
+/// when the client is created.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Handover {
+    /// File the snapshot of the client state is written to and read from
+    pub path: PathBuf,
+    /// Maximal age in seconds of a snapshot that is still imported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u64>,
+}
+
+impl Handover {
+    pub const DEFAULT_MAX_AGE: u64 = 300;
+}
+
+/// Overall deadline of the startup. The client has to dispatch the first job within the total
+/// budget or it fails with the most specific error encountered in the meantime. The startup budget
+/// is measured from the start of the client, the reconnect budget from the beginning of every
+/// connection attempt that follows a failure. The budget is split between the phases of the attempt
+/// (connect, handshake and first job), phases that are not configured share the rest of the total
+/// in the ratio 1:2:1. All values are in milliseconds.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct StartupBudget {
+    /// Time from the start of the client to the first dispatched job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    /// Time from the beginning of a reconnect attempt to the first dispatched job (`total` by
+    /// default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reconnect_total: Option<u64>,
+    /// Name resolution, TCP connect and the Noise handshake
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect: Option<u64>,
+    /// Setup of the connection and opening of the channel (including retries)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake: Option<u64>,
+    /// Time from the opened channel to the first dispatched job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_job: Option<u64>,
+}
+
+impl StartupBudget {
+    pub const DEFAULT_TOTAL: u64 = 180_000;
+
+    /// Total budget of the startup (`reconnect` is false) or of a reconnect attempt
+    pub fn resolve_total(&self, reconnect: bool) -> u64 {
+        let total = self.total.unwrap_or(Self::DEFAULT_TOTAL);
+        if reconnect {
+            self.reconnect_total.unwrap_or(total)
+        } else {
+            total
+        }
+    }
+}
+
+/// Settings that adjust the mining target requested by the pool are applied in the following
+/// order of precedence (the later step always wins):
+///
+/// 1. target requested by the pool (`OpenStandardMiningChannelSuccess` or `SetTarget`)
+/// 2. `startup_target` - during the startup window the target derived from the nominal hashrate
+///    is used when it is harder than the target requested by the pool. The window ends early when
+///    the pool sends `SetTarget`. It is disabled when `max_difficulty` or `early_share` is
+///    configured or while a difficulty suggested by an external difficulty manager is in effect.
+/// 3. `max_difficulty` - operator ceiling, the locally applied target is never harder than that
+/// 4. `min_difficulty` - operator floor, the locally applied target is never easier than that
+///    (it wins over the ceiling should they overlap)
+///
+/// There is no multiplier of the share target, the list above is complete. All the adjustments
+/// only affect the target used locally for solving the job. Shares are submitted only when they
+/// meet the target requested by the pool.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Version rolling mask exposed to the backend. It is expected to be a subset of the mask
+    /// negotiated with the pool and it allows reducing the rolling space on hardware that doesn't
+    /// cope well with the full BIP320 range. The negotiated mask is used when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_mask: Option<u32>,
+    /// Maximal difficulty the client honors. Harder targets requested by the pool are clamped to
+    /// this difficulty and the client reports degraded health as long as the clamping lasts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_difficulty: Option<usize>,
+    /// Minimal difficulty the client honors. Easier targets requested by the pool are clamped to
+    /// this difficulty, the same way as with `max_difficulty`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_difficulty: Option<usize>,
+    /// Reconnect to the pool when it requests a difficulty that is this many times outside of the
+    /// configured range (below `min_difficulty / factor` or above `max_difficulty * factor`).
+    /// Such difficulty is only clamped when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub difficulty_reconnect_factor: Option<f64>,
+    /// Dispatch new jobs received from the pool to the backend also when the client is stopping
+    /// or restarting. Such jobs are almost always thrown away so they are not dispatched by
+    /// default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dispatch_jobs_when_stopping: Option<bool>,
+    /// Additional upstream authority public keys accepted when securing the connection (e.g. the
+    /// next key of a pool that is about to rotate it). The key specified in the pool URL is always
+    /// accepted and it is tried first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authority_public_keys: Option<Vec<v2::noise::auth::EncodedEd25519PublicKey>>,
+    /// Verify that submitted shares have strictly monotonic sequence numbers and that the pool
+    /// acknowledges only shares that have been submitted. Intended for diagnostics, disabled by
+    /// default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share_ordering_check: Option<ShareOrderingCheck>,
+    /// Starting target policy applied right after the channel is opened
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startup_target: Option<StartupTarget>,
+    /// Keep raw bytes of frames that carry the user name in the transcript of the last failed
+    /// handshake. The user name is always redacted in the decoded summaries, but it cannot be
+    /// removed from the raw bytes, therefore such frames are recorded without them by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake_transcript_raw_bytes: Option<bool>,
+    /// Mask the user in logs so that they can be shared without leaking account identifiers. The
+    /// user is shown as it is when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_redaction: Option<UserRedaction>,
+    /// Host advertised to the pool in `SetupConnection`. Behind NAT or a load balancer the pool
+    /// may expect a different value than the host the client dials. The host from the pool URL is
+    /// advertised when not specified. It never affects where the client connects to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint_host: Option<String>,
+    /// Port advertised to the pool in `SetupConnection`. The port from the pool URL is advertised
+    /// when not specified. It never affects where the client connects to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint_port: Option<u16>,
+    /// File with the pool user that replaces the user specified in the pool configuration. The
+    /// file is read when connecting to the pool and it is polled for changes during the session.
+    /// A changed user is applied by opening a new session. The user is never reported in logs or
+    /// in the status.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_file: Option<PathBuf>,
+    /// Interval of polling the user file for changes in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_file_poll_interval: Option<u64>,
+    /// Maximal number of submitted shares that haven't been acknowledged by the pool yet. Bounds
+    /// the memory used for tracking the shares and reveals a pool that stopped acknowledging them.
+    /// The number is not limited when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submission_window: Option<usize>,
+    /// Handling of shares found while the submission window is full (`hold` by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submission_window_policy: Option<SubmissionWindowPolicy>,
+    /// Maximal estimate of memory in bytes retained by shares held while the submission window
+    /// is full. The oldest shares are dropped first, the same way as when more than
+    /// `submission_window` shares are held.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub held_solutions_max_bytes: Option<usize>,
+    /// Point at which a target sent by the pool takes effect (`next_job` by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_application: Option<TargetApplication>,
+    /// Interval in seconds of warnings about future jobs that keep accumulating without a new
+    /// previous block hash (a pool that got stuck). The warnings are disabled when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_jobs_log_interval: Option<u64>,
+    /// Verify that the pool acknowledges shares in the order of submission. Acknowledgements are
+    /// accepted in any order when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ack_sequencing: Option<AckSequencing>,
+    /// Grace period in milliseconds for draining submitted shares when the client is restarted.
+    /// The old connection is kept open until the pool acknowledges all shares submitted before
+    /// the restart or until the grace period expires, whichever comes first. No jobs are
+    /// dispatched and no new shares are submitted in the meantime. Acknowledgements received
+    /// during the grace period are accounted as usual, shares that remain unacknowledged are
+    /// accounted as stale before the new connection is opened. Stopping the client and connection
+    /// failures are not affected, all unacknowledged shares are accounted as stale right away.
+    /// The shares are not drained when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restart_drain_grace: Option<u64>,
+    /// Delays in milliseconds between attempts to open the channel again when the pool fails to
+    /// open it for a transient reason. The established connection is kept and the client
+    /// reconnects only when all attempts fail. Errors that cannot be resolved by another attempt
+    /// (e.g. `unknown-user`) are never retried. An empty list disables the retries.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_open_backoff: Option<Vec<u64>>,
+    /// Ceiling on the rate of job switches dispatched to the backend. The rate is not limited
+    /// when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_dispatch_limit: Option<JobDispatchLimit>,
+    /// Reconnect to the pool when it acknowledges shares more than this many times within a
+    /// session while no submitted share has been waiting for acknowledgement (a confused pool).
+    /// Such acknowledgements are only counted when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unexpected_ack_limit: Option<usize>,
+    /// Number of seconds by which ntime of a solution may precede min_ntime of its job (the pool
+    /// may be slightly ahead of the clock of the miner on a job switch). Solutions that precede
+    /// it even more are dropped as hardware errors. No tolerance is allowed when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ntime_tolerance: Option<u32>,
+    /// Median skew in seconds between `min_ntime` sent by the pool and the local clock that is
+    /// reported as an advisory event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_skew_threshold: Option<u64>,
+    /// Number of seconds by which `min_ntime` of a new prevhash may deviate from the time expected
+    /// from the pool (the local clock corrected by the measured skew). A prevhash that deviates
+    /// even more is ignored. `min_ntime` is not validated when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_ntime_tolerance: Option<u64>,
+    /// Number of attempts to send a share submission when sending fails (e.g. a momentary error
+    /// of the transmit direction). The connection is restarted when all attempts fail. A send that
+    /// doesn't complete in time is never retried. Every share is sent only once by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submit_attempts: Option<usize>,
+    /// Number of seconds by which ntime may be rolled forward beyond `min_ntime` of the job.
+    /// Solutions with ntime beyond the window are not submitted because the pool would likely
+    /// reject them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_ntime_roll: Option<u32>,
+    /// Handling of a job that reuses the ID of a known job with different content (`overwrite` by
+    /// default). The reuse is always logged.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id_reuse: Option<JobIdReuse>,
+    /// File that the outcome of every share (accepted, rejected or stale) is appended to as a CSV
+    /// line for offline analysis. Nothing is logged when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share_log: Option<PathBuf>,
+    /// Delay in milliseconds before the first connection attempt after the client has been
+    /// started (reconnects are not delayed). There is no delay when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startup_delay: Option<u64>,
+    /// Upper bound in milliseconds of a random delay added to `startup_delay`. A fleet booting at
+    /// once spreads its initial connections instead of hitting the pool at the same time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startup_jitter: Option<u64>,
+    /// Thresholds of the detection of stalled share submissions on a connection that still
+    /// receives jobs. The connection is restarted well before the receive watchdog would fire.
+    /// Default thresholds are used when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transmit_stall: Option<TransmitStall>,
+    /// Time in seconds after the first job of a session within which the pool is expected to send
+    /// `SetTarget`. A warning is logged once per session when it doesn't (its vardiff is likely
+    /// not working). Nothing is checked when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub set_target_window: Option<u64>,
+    /// Request an initial target that yields a share well within the deadline of a pool that
+    /// drops channels without a share (the startup target policy is not applied then). A drop of
+    /// the channel before the first share is reported with a specific error. Nothing is requested
+    /// when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub early_share: Option<EarlyShare>,
+    /// Handling of shares found for a job flushed by a new previous block hash (`drop` by
+    /// default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flushed_job_solutions: Option<FlushedJobSolutions>,
+    /// Snapshot of the state of the client (remembered target, hashrate estimate, lifetime
+    /// statistics, ...) handed over to the next instance of the process. Nothing is handed over
+    /// when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handover: Option<Handover>,
+    /// Handling of shares with version bits outside the version rolling mask (`drop` by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub out_of_mask_versions: Option<OutOfMaskVersions>,
+    /// Time in seconds after which a read or a write of the pool connection that doesn't complete
+    /// fails and the client reconnects. It detects a half-open connection (e.g. the pool host
+    /// vanished without closing the socket) before the 150s watchdog of the protocol does. TCP
+    /// keepalive is not enabled on the connection and the one of the operating system would take
+    /// hours to notice such a connection. The timeout must exceed the longest quiet period of the
+    /// pool (the interval of its jobs) otherwise healthy connections are dropped. Reads and writes
+    /// are not timed when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub io_timeout: Option<u64>,
+    /// Time in seconds a difficulty suggested by an external difficulty manager stays in effect.
+    /// The client reverts to its local heuristics afterwards unless the suggestion is renewed.
+    /// Suggestions expire after 600 seconds when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub difficulty_suggestion_ttl: Option<u64>,
+    /// Handling of flags of `SetupConnectionSuccess` that the client doesn't recognize (`ignore`
+    /// by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unknown_pool_flags: Option<UnknownPoolFlags>,
+    /// Byte order of the nonce in submitted shares (`spec` by default). The option only serves
+    /// for debugging a pool that deviates from the specification and should not normally be
+    /// changed, the pool rejects all shares submitted with a wrong byte order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce_byte_order: Option<NonceByteOrder>,
+    /// Handling of rolling restarts of the pool. Every failure is handled right away (including
+    /// the failover to another pool) when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restart_storm: Option<RestartStorm>,
+    /// Limit of the throughput of the pool connection. The throughput is unlimited when not
+    /// specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bandwidth_limit: Option<BandwidthLimit>,
+    /// Static tag attached to every submitted share when the pool accepts annotations of
+    /// submissions (it is omitted otherwise). A change takes effect on the next submission without
+    /// reconnecting. Shares are not annotated when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submission_annotation: Option<String>,
+    /// Escalation of shares that are consecutively rejected for the same reason. Rejects are only
+    /// logged one by one when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reject_streak: Option<RejectStreak>,
+    /// Interval in milliseconds within which the polled status document is served from a cached
+    /// copy (500 ms by default). The document is assembled on every poll when it is 0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_refresh_interval: Option<u64>,
+    /// Allow an operator to arm synthetic protocol faults for resilience drills. It has effect
+    /// only in builds with the `fault-injection` feature, faults cannot be armed when not
+    /// specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fault_injection: Option<bool>,
+    /// Jobs that are dispatched to the backend right away (`immediate` by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_dispatch_policy: Option<JobDispatchPolicy>,
+    /// Number of job template refreshes per minute (same-prevhash jobs with a new merkle root)
+    /// below which the pool is reported as serving stale templates (1 per minute by default).
+    /// The advisory is disabled when it is 0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_refresh_floor: Option<f64>,
+    /// Handling of the first job when no target is known (`wait` by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub missing_target_policy: Option<MissingTargetPolicy>,
+    /// Number of milliseconds the first job waits for a target with the `wait` policy (2000 ms
+    /// by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub missing_target_wait: Option<u64>,
+    /// Overall deadline of the startup and of reconnect attempts (3 minutes split between the
+    /// phases by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startup_budget: Option<StartupBudget>,
+    /// Maximal age in seconds of a future job that waits for its prevhash. Older future jobs are
+    /// evicted so that a delayed prevhash cannot promote them. Future jobs are kept until the
+    /// connection is closed when not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub future_job_max_age: Option<u64>,
+}
+
+impl Config {
+    /// Maximal length of the advertised host in bytes (given by the `SetupConnection` message)
+    pub const MAX_ENDPOINT_HOST_LENGTH: usize = 255;
+    /// Maximal length of the user in bytes (given by the `OpenStandardMiningChannel` message)
+    pub const MAX_USER_LENGTH: usize = 255;
+    /// Maximal length of the annotation of submitted shares in bytes
+    pub const MAX_SUBMISSION_ANNOTATION_LENGTH: usize = 32;
+    pub const DEFAULT_USER_FILE_POLL_INTERVAL: u64 = 10;
+    pub const DEFAULT_DIFFICULTY_SUGGESTION_TTL: u64 = 600;
+    pub const DEFAULT_CHANNEL_OPEN_BACKOFF: [u64; 3] = [1000, 2000, 5000];
+    pub const DEFAULT_SUBMIT_ATTEMPTS: usize = 1;
+    pub const DEFAULT_HELD_SOLUTIONS_MAX_BYTES: usize = 256 * 1024;
+    pub const DEFAULT_CLOCK_SKEW_THRESHOLD: u64 = 30;
+    pub const DEFAULT_MAX_NTIME_ROLL: u32 = 120;
+    pub const DEFAULT_STATUS_REFRESH_INTERVAL: u64 = 500;
+    pub const DEFAULT_TEMPLATE_REFRESH_FLOOR: f64 = 1.0;
+    pub const DEFAULT_MISSING_TARGET_WAIT: u64 = 2000;
+    /// The backend must not stay idle for long, the wait for a target is always bounded
+    pub const MAX_MISSING_TARGET_WAIT: u64 = 60_000;
+    /// Blocks with timestamp more than 2 hours in the future are rejected by the network
+    pub const MAX_NTIME_ROLL: u32 = 7200;
+
+    /// Read the pool user from `path`. Surrounding whitespace is removed and the user must not be
+    /// empty or contain control characters.
+    pub fn read_user_file(path: &Path) -> error::Result<String> {
+        let user_file_error =
+            |reason: String| error::ErrorKind::UserFile(path.display().to_string(), reason);
+        let content = fs::read_to_string(path).map_err(|e| user_file_error(e.to_string()))?;
+        let user = content.trim();
+        if user.is_empty() {
+            Err(user_file_error("the file is empty".to_string()))?
+        }
+        if user.len() > Self::MAX_USER_LENGTH {
+            Err(user_file_error(format!(
+                "the user is longer than {} bytes",
+                Self::MAX_USER_LENGTH
+            )))?
+        }
+        if user.chars().any(|c| c.is_control()) {
+            Err(user_file_error(
+                "the user contains control characters".to_string(),
+            ))?
+        }
+        Ok(user.to_string())
+    }
+
+    pub fn validate(&self) -> error::Result<()> {
+        if let Some(endpoint_host) = self.endpoint_host.as_ref() {
+            if endpoint_host.is_empty() || endpoint_host.len() > Self::MAX_ENDPOINT_HOST_LENGTH {
+                Err(error::ErrorKind::Client(format!(
+                    "advertised endpoint host must have 1 to {} bytes (has {})",
+                    Self::MAX_ENDPOINT_HOST_LENGTH,
+                    endpoint_host.len()
+                )))?
+            }
+        }
+        if let Some(annotation) = self.submission_annotation.as_ref() {
+            if annotation.is_empty() || annotation.len() > Self::MAX_SUBMISSION_ANNOTATION_LENGTH {
+                Err(error::ErrorKind::Client(format!(
+                    "submission annotation must have 1 to {} bytes (has {})",
+                    Self::MAX_SUBMISSION_ANNOTATION_LENGTH,
+                    annotation.len()
+                )))?
+            }
+            if !annotation.chars().all(|c| c.is_ascii_graphic()) {
+                Err(error::ErrorKind::Client(
+                    "submission annotation must contain only printable ASCII characters without \
+                     spaces"
+                        .to_string(),
+                ))?
+            }
+        }
+        if let (Some(min_difficulty), Some(max_difficulty)) =
+            (self.min_difficulty, self.max_difficulty)
+        {
+            if min_difficulty > max_difficulty {
+                Err(error::ErrorKind::Client(format!(
+                    "minimal difficulty {} is greater than maximal difficulty {}",
+                    min_difficulty, max_difficulty
+                )))?
+            }
+        }
+        if let Some(factor) = self.difficulty_reconnect_factor {
+            if !(factor >= 1.0) {
+                Err(error::ErrorKind::Client(format!(
+                    "difficulty reconnect factor must be at least 1.0 (is {})",
+                    factor
+                )))?
+            }
+        }
+        if let Some(floor) = self.template_refresh_floor {
+            if !(floor >= 0.0) || !floor.is_finite() {
+                Err(error::ErrorKind::Client(format!(
+                    "template refresh floor must be a non-negative number (is {})",
+                    floor
+                )))?
+            }
+        }
+        if let Some(wait) = self.missing_target_wait {
+            if wait > Self::MAX_MISSING_TARGET_WAIT {
+                Err(error::ErrorKind::Client(format!(
+                    "wait for missing target {}ms exceeds maximum {}ms",
+                    wait,
+                    Self::MAX_MISSING_TARGET_WAIT
+                )))?
+            }
+        }
+        if let Some(startup_budget) = self.startup_budget.as_ref() {
+            for (name, value) in &[
+                ("total", startup_budget.total),
+                ("reconnect total", startup_budget.reconnect_total),
+                ("connect", startup_budget.connect),
+                ("handshake", startup_budget.handshake),
+                ("first job", startup_budget.first_job),
+            ] {
+                if *value == Some(0) {
+                    Err(error::ErrorKind::Client(format!(
+                        "startup budget {} must be at least 1 millisecond",
+                        name
+                    )))?
+                }
+            }
+            let phases = [
+                startup_budget.connect,
+                startup_budget.handshake,
+                startup_budget.first_job,
+            ];
+            let configured: u64 = phases.iter().flatten().sum();
+            let derived = phases.iter().any(Option::is_none);
+            for &(name, reconnect) in &[("total", false), ("reconnect total", true)] {
+                let total = startup_budget.resolve_total(reconnect);
+                // Phases derived from the total must not be left without time
+                if configured > total || (derived && configured == total) {
+                    Err(error::ErrorKind::Client(format!(
+                        "startup budget phases {}ms don't fit into {} {}ms",
+                        configured, name, total
+                    )))?
+                }
+            }
+        }
+        let startup_total = self
+            .startup_budget
+            .as_ref()
+            .map_or(StartupBudget::DEFAULT_TOTAL, |startup_budget| {
+                startup_budget.resolve_total(false)
+            });
+        let startup_delay = self
+            .startup_delay
+            .unwrap_or(0)
+            .saturating_add(self.startup_jitter.unwrap_or(0));
+        if startup_delay >= startup_total {
+            Err(error::ErrorKind::Client(format!(
+                "startup delay {}ms exceeds startup budget {}ms",
+                startup_delay, startup_total
+            )))?
+        }
+        if self.pending_jobs_log_interval == Some(0) {
+            Err(error::ErrorKind::Client(
+                "interval of pending jobs warnings must be at least 1 second".to_string(),
+            ))?
+        }
+        if self.future_job_max_age == Some(0) {
+            Err(error::ErrorKind::Client(
+                "maximal age of future jobs must be at least 1 second".to_string(),
+            ))?
+        }
+        if let Some(early_share) = self.early_share.as_ref() {
+            if !(early_share.nominal_hashrate > 0.0) || !early_share.nominal_hashrate.is_finite() {
+                Err(error::ErrorKind::Client(format!(
+                    "nominal hashrate of early share must be a positive number (is {})",
+                    early_share.nominal_hashrate
+                )))?
+            }
+            if early_share.deadline == 0 {
+                Err(error::ErrorKind::Client(
+                    "deadline of early share must be at least 1 second".to_string(),
+                ))?
+            }
+            if let Some(expected_shares) = early_share.expected_shares {
+                if !(expected_shares > 0.0) || !expected_shares.is_finite() {
+                    Err(error::ErrorKind::Client(format!(
+                        "expected shares of early share must be a positive number (is {})",
+                        expected_shares
+                    )))?
+                }
+            }
+        }
+        if let Some(handover) = self.handover.as_ref() {
+            if handover.max_age == Some(0) {
+                Err(error::ErrorKind::Client(
+                    "maximal age of handed over state must be at least 1 second".to_string(),
+                ))?
+            }
+        }
+        if self.difficulty_suggestion_ttl == Some(0) {
+            Err(error::ErrorKind::Client(
+                "lifetime of difficulty suggestions must be at least 1 second".to_string(),
+            ))?
+        }
+        if let Some(restart_storm) = self.restart_storm.as_ref() {
+            if restart_storm
+                .sessions
+                .map_or(false, |sessions| sessions < 2)
+            {
+                Err(error::ErrorKind::Client(
+                    "restart storm requires at least 2 short sessions".to_string(),
+                ))?
+            }
+            for (name, value) in &[
+                ("short session", restart_storm.short_session),
+                ("window", restart_storm.window),
+                ("retry interval", restart_storm.retry_interval),
+                ("maximal duration", restart_storm.max_duration),
+            ] {
+                if *value == Some(0) {
+                    Err(error::ErrorKind::Client(format!(
+                        "restart storm {} must be at least 1 second",
+                        name
+                    )))?
+                }
+            }
+        }
+        if let Some(bandwidth_limit) = self.bandwidth_limit.as_ref() {
+            for (name, value) in &[
+                ("send rate", bandwidth_limit.send_rate),
+                ("receive rate", bandwidth_limit.receive_rate),
+                ("burst", bandwidth_limit.burst),
+            ] {
+                if *value == Some(0) {
+                    Err(error::ErrorKind::Client(format!(
+                        "bandwidth limit {} must be at least 1 byte",
+                        name
+                    )))?
+                }
+            }
+        }
+        if let Some(reject_streak) = self.reject_streak.as_ref() {
+            if reject_streak
+                .threshold
+                .map_or(false, |threshold| threshold < 2)
+            {
+                Err(error::ErrorKind::Client(
+                    "threshold of reject streak must be at least 2 shares".to_string(),
+                ))?
+            }
+        }
+        if self.io_timeout == Some(0) {
+            Err(error::ErrorKind::Client(
+                "connection read/write timeout must be at least 1 second".to_string(),
+            ))?
+        }
+        if self.set_target_window == Some(0) {
+            Err(error::ErrorKind::Client(
+                "window for receiving a target from the pool must be at least 1 second".to_string(),
+            ))?
+        }
+        if let Some(ack_sequencing) = self.ack_sequencing.as_ref() {
+            if ack_sequencing.gap_window == Some(0) {
+                Err(error::ErrorKind::Client(
+                    "gap window of acknowledgement sequencing must be at least 1 second"
+                        .to_string(),
+                ))?
+            }
+        }
+        if let Some(job_dispatch_limit) = self.job_dispatch_limit.as_ref() {
+            if !(job_dispatch_limit.rate > 0.0) || !job_dispatch_limit.rate.is_finite() {
+                Err(error::ErrorKind::Client(format!(
+                    "job dispatch rate must be a positive number (is {})",
+                    job_dispatch_limit.rate
+                )))?
+            }
+            if job_dispatch_limit.burst == Some(0) {
+                Err(error::ErrorKind::Client(
+                    "job dispatch burst must allow at least one job".to_string(),
+                ))?
+            }
+        }
+        if self.submit_attempts == Some(0) {
+            Err(error::ErrorKind::Client(
+                "share submission must be attempted at least once".to_string(),
+            ))?
+        }
+        if let Some(max_ntime_roll) = self.max_ntime_roll {
+            if max_ntime_roll > Self::MAX_NTIME_ROLL {
+                Err(error::ErrorKind::Client(format!(
+                    "ntime rolling window {}s exceeds maximum {}s",
+                    max_ntime_roll,
+                    Self::MAX_NTIME_ROLL
+                )))?
+            }
+        }
+        if self.held_solutions_max_bytes == Some(0) {
+            Err(error::ErrorKind::Client(
+                "memory of held shares must not be limited to 0 bytes".to_string(),
+            ))?
+        }
+        if self.submission_window == Some(0) {
+            Err(error::ErrorKind::Client(
+                "submission window must allow at least one share".to_string(),
+            ))?
+        }
+        if let Some(user_file) = self.user_file.as_ref() {
+            Self::read_user_file(user_file)?;
+        }
+        Ok(())
+    }
+}
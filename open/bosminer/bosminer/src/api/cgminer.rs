@@ -93,20 +93,9 @@ impl Handler {
             .map_or(0, |share| share.time.get_unix_time().unwrap_or_default());
         let last_share_difficulty = last_share.map_or(0.0, |share| share.difficulty as f64);
 
-        let pool_accepted_shares = accepted.shares.as_f64();
-        let pool_rejected_shares = rejected.shares.as_f64();
-        let pool_stale_shares = stale.shares.as_f64();
-        let pool_total_shares = pool_accepted_shares + pool_rejected_shares + pool_stale_shares;
-        let pool_rejected_ratio = if pool_total_shares != 0.0 {
-            pool_rejected_shares / pool_total_shares * 100.0
-        } else {
-            0.0
-        };
-        let pool_stale_ratio = if pool_total_shares != 0.0 {
-            pool_stale_shares / pool_total_shares * 100.0
-        } else {
-            0.0
-        };
+        // Ratios of a single pool follow the same semantics as the ratios of all pools combined
+        let snapshot = client::aggregate::ClientSnapshot::take(&client, *INTERVAL_1M).await;
+        let pool = client::AggregateStats::new(&[snapshot]);
 
         let last_diff = last_job
             .as_ref()
@@ -118,6 +107,7 @@ impl Handler {
             sync::Status::Running => (response::PoolStatus::Alive, true),
             sync::Status::Created
             | sync::Status::Starting
+            | sync::Status::Connected
             | sync::Status::Stopping
             | sync::Status::Restarting
             | sync::Status::Stopped => (response::PoolStatus::Alive, false),
@@ -157,9 +147,9 @@ impl Handler {
             diff1_shares: valid_backend_diff.solutions,
             proxy_type: "".to_string(),
             proxy: "".to_string(),
-            difficulty_accepted: pool_accepted_shares,
-            difficulty_rejected: pool_rejected_shares,
-            difficulty_stale: pool_stale_shares,
+            difficulty_accepted: pool.accepted.difficulty,
+            difficulty_rejected: pool.rejected.difficulty,
+            difficulty_stale: pool.stale.difficulty,
             last_share_difficulty,
             work_difficulty: last_diff,
             has_stratum: true,
@@ -170,8 +160,8 @@ impl Handler {
             has_vmask: true,
             has_gbt: false,
             best_share: best_share.map(|inner| *inner).unwrap_or_default() as u64,
-            pool_rejected_ratio,
-            pool_stale_ratio,
+            pool_rejected_ratio: pool.rejected_ratio * 100.0,
+            pool_stale_ratio: pool.stale_ratio * 100.0,
             bad_work: *invalid_jobs as u64,
             // TODO: BOSminer does not have coinbase for Stratum V2
             current_block_height: 0,
@@ -448,50 +438,21 @@ impl command::Handler for Handler {
         } * 100.0;
         let work_utility = valid_backend_diff.shares.to_sharerate(elapsed) * 60.0;
 
-        let mut pools_valid_jobs: u64 = 0;
-        let mut pools_accepted = 0;
-        let mut pools_accepted_shares = 0.0;
-        let mut pools_rejected = 0;
-        let mut pools_rejected_shares = 0.0;
-        let mut pools_stale = 0;
-        let mut pools_stale_shares = 0.0;
-
+        let mut client_snapshots = vec![];
         for client in self.get_clients().await {
-            let client_stats = client.stats();
-
-            let valid_jobs = client_stats.valid_jobs().take_snapshot();
-            let accepted = client_stats.accepted().take_snapshot().await;
-            let rejected = client_stats.rejected().take_snapshot().await;
-            let stale = client_stats.stale().take_snapshot().await;
-
-            pools_valid_jobs += *valid_jobs as u64;
-            pools_accepted += accepted.solutions;
-            pools_accepted_shares += accepted.shares.as_f64();
-            pools_rejected += rejected.solutions;
-            pools_rejected_shares += rejected.shares.as_f64();
-            pools_stale += stale.solutions;
-            pools_stale_shares += stale.shares.as_f64();
+            client_snapshots
+                .push(client::aggregate::ClientSnapshot::take(&client, *INTERVAL_1M).await);
         }
+        let pools = client::AggregateStats::new(&client_snapshots);
 
-        let pools_all_solutions = pools_accepted + pools_rejected + pools_stale;
-        let pools_rejected_ratio = if pools_all_solutions != 0 {
-            pools_rejected as f64 / pools_all_solutions as f64
-        } else {
-            0.0
-        } * 100.0;
-        let pools_stale_ratio = if pools_all_solutions != 0 {
-            pools_stale as f64 / pools_all_solutions as f64
-        } else {
-            0.0
-        } * 100.0;
         let pools_utility = if elapsed.as_secs() != 0 {
-            pools_accepted as f64 / elapsed.as_secs() as f64
+            pools.accepted.count as f64 / elapsed.as_secs() as f64
         } else {
-            pools_accepted as f64
+            pools.accepted.count as f64
         } * 60.0;
 
         let backend_rejected_ratio = if backend_valid_solutions != 0 {
-            pools_rejected_shares as f64 / backend_valid_solutions as f64
+            pools.rejected.difficulty / backend_valid_solutions as f64
         } else {
             0.0
         } * 100.0;
@@ -515,14 +476,14 @@ impl command::Handler for Handler {
                 .to_mega_hashes(*INTERVAL_24H, now)
                 .into_f64(),
             found_blocks: network_valid_solutions as u32,
-            getworks: pools_valid_jobs,
-            accepted: pools_accepted,
-            rejected: pools_rejected,
+            getworks: pools.valid_jobs,
+            accepted: pools.accepted.count,
+            rejected: pools.rejected.count,
             hardware_errors: backend_error_solutions as i32,
             utility: pools_utility,
             // TODO: BOSminer does not account this information
             discarded: 0,
-            stale: pools_stale,
+            stale: pools.stale.count,
             // TODO: BOSminer does not account this information
             get_failures: 0,
             local_work: *generated_work as u32,
@@ -532,14 +493,16 @@ impl command::Handler for Handler {
             network_blocks: 0,
             total_mega_hashes,
             work_utility,
-            difficulty_accepted: pools_accepted_shares,
-            difficulty_rejected: pools_rejected_shares,
-            difficulty_stale: pools_stale_shares,
+            difficulty_accepted: pools.accepted.difficulty,
+            difficulty_rejected: pools.rejected.difficulty,
+            difficulty_stale: pools.stale.difficulty,
             best_share: best_share.map(|inner| *inner).unwrap_or_default() as u64,
             device_hardware_ratio: backend_error_ratio,
             device_rejected_ratio: backend_rejected_ratio,
-            pool_rejected_ratio: pools_rejected_ratio,
-            pool_stale_ratio: pools_stale_ratio,
+            // Ratios of all pools are computed from the number of solutions (unlike ratios of a
+            // single pool)
+            pool_rejected_ratio: pools.rejected_count_ratio * 100.0,
+            pool_stale_ratio: pools.stale_count_ratio * 100.0,
             last_getwork: last_work_time,
         })
     }
@@ -25,10 +25,15 @@
 
 mod scheduler;
 
+pub mod aggregate;
 // Sub-modules with client implementation
 pub mod drain;
+pub mod schedule;
+pub mod simulation;
 pub mod stratum_v2;
 pub mod stratum_v2_channels;
+pub mod switches;
+pub mod target_util;
 
 use crate::error;
 use crate::hal;
@@ -41,6 +46,8 @@ use crate::work;
 // Scheduler re-exports
 pub use scheduler::JobExecutor;
 
+pub use aggregate::AggregateStats;
+
 use bosminer_config::{
     ClientDescriptor, ClientProtocol, ClientUserInfo, GroupConfig, GroupDescriptor,
     LoadBalanceStrategy,
@@ -53,6 +60,8 @@ use ii_async_compat::futures;
 use std::slice;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
+use std::time;
 
 #[derive(Debug)]
 pub struct Handle {
@@ -62,6 +71,10 @@ pub struct Handle {
     enabled: AtomicBool,
     engine_sender: Arc<work::EngineSender>,
     solution_sender: mpsc::UnboundedSender<work::Solution>,
+    /// Annotations of switches of the active client
+    switches: switches::Journal,
+    /// Daily windows in which the client is preferred (used only when configured)
+    schedule: StdMutex<Option<schedule::Schedule>>,
 }
 
 impl Handle {
@@ -98,26 +111,38 @@ impl Handle {
                     job_solver,
                 ))
             }
-            ClientProtocol::StratumV2(_) => Arc::new(stratum_v2::StratumClient::new(
-                stratum_v2::ConnectionDetails::from_descriptor(&descriptor),
-                backend_info,
-                job_solver,
-                channel,
-            )),
-            ClientProtocol::StratumV2Insecure => Arc::new(stratum_v2::StratumClient::new(
+            ClientProtocol::StratumV2(_) => Arc::new(stratum_v2::StratumClient::with_handover(
                 stratum_v2::ConnectionDetails::from_descriptor(&descriptor),
                 backend_info,
                 job_solver,
                 channel,
             )),
+            ClientProtocol::StratumV2Insecure => {
+                Arc::new(stratum_v2::StratumClient::with_handover(
+                    stratum_v2::ConnectionDetails::from_descriptor(&descriptor),
+                    backend_info,
+                    job_solver,
+                    channel,
+                ))
+            }
+            ClientProtocol::Simulation => {
+                assert!(
+                    channel.is_none(),
+                    "BUG: protocol 'Simulation' does not support channel"
+                );
+                Arc::new(simulation::SimulationClient::new(&descriptor, job_solver))
+            }
         };
 
+        let schedule = descriptor.schedule.as_ref().map(schedule::Schedule::new);
         Self {
             descriptor: Arc::new(Mutex::new(descriptor)),
             node,
             enabled: AtomicBool::new(false),
             engine_sender,
             solution_sender,
+            switches: Default::default(),
+            schedule: StdMutex::new(schedule),
         }
     }
 
@@ -126,14 +151,52 @@ impl Handle {
         self.descriptor.lock().await.clone()
     }
 
+    /// Endpoint of the client without the user, it identifies the client in annotations of
+    /// switches
+    pub async fn endpoint_key(&self) -> String {
+        self.descriptor.lock().await.get_url(true, true, false)
+    }
+
+    pub fn switches(&self) -> &switches::Journal {
+        &self.switches
+    }
+
+    /// Record the annotation of a switch made by the scheduler in the journal and forward it to
+    /// the client. Repeated annotations of the same switch are ignored.
+    fn annotate_switch(&self, annotation: switches::Annotation) {
+        if self.switches.annotate(annotation.clone()) {
+            self.node.annotate_switch(&annotation);
+        }
+    }
+
     pub async fn change_descriptor(&self, descriptor: ClientDescriptor) {
         // NOTE: Keep descriptor locked to synchronize descriptor changes
         let mut current_descriptor = self.descriptor.lock().await;
 
         self.node.change_connection_details(&descriptor);
+        *self.schedule.lock().expect("BUG: cannot lock schedule") =
+            descriptor.schedule.as_ref().map(schedule::Schedule::new);
         *current_descriptor = descriptor;
     }
 
+    /// Evaluate the schedule of the client at `now`, returns true when the state has changed
+    fn update_schedule_at(&self, now: time::SystemTime) -> bool {
+        self.schedule
+            .lock()
+            .expect("BUG: cannot lock schedule")
+            .as_mut()
+            .map_or(false, |schedule| schedule.update_at(now))
+    }
+
+    /// State of the schedule after the last evaluation
+    pub fn schedule_state(&self) -> schedule::State {
+        self.schedule
+            .lock()
+            .expect("BUG: cannot lock schedule")
+            .as_ref()
+            .map_or(schedule::State::Unscheduled, schedule::Schedule::state)
+    }
+
     pub fn replace_engine_generator(
         &self,
         engine_generator: work::EngineGenerator,
@@ -174,6 +237,12 @@ impl Handle {
         self.is_enabled() && self.status() == crate::sync::Status::Running
     }
 
+    /// The client asks the scheduler not to fail over to another client while it is not running
+    #[inline]
+    pub fn holds_failover(&self) -> bool {
+        self.is_enabled() && self.node.holds_failover()
+    }
+
     #[inline]
     pub fn status(&self) -> crate::sync::Status {
         self.node.status().status()
@@ -574,7 +643,7 @@ impl Manager {
                 let group = self.create_group(group_config.descriptor).await?;
                 if let Some(pool_configs) = group_config.pools {
                     for pool_config in pool_configs {
-                        let descriptor = ClientDescriptor::create(
+                        let mut descriptor = ClientDescriptor::create(
                             pool_config.url.as_str(),
                             &ClientUserInfo::new(
                                 pool_config.user.as_str(),
@@ -583,6 +652,18 @@ impl Manager {
                             pool_config.enabled.unwrap_or(default_pool_enabled),
                         )
                         .map_err(|e| e.to_string())?;
+                        if let Some(stratum_v2) = pool_config.stratum_v2 {
+                            stratum_v2.validate().map_err(|e| e.to_string())?;
+                            descriptor.stratum_v2 = stratum_v2;
+                        }
+                        if let Some(simulation) = pool_config.simulation {
+                            simulation.validate().map_err(|e| e.to_string())?;
+                            descriptor.simulation = simulation;
+                        }
+                        if let Some(schedule) = pool_config.schedule {
+                            schedule.validate().map_err(|e| e.to_string())?;
+                            descriptor.schedule = Some(schedule);
+                        }
                         let client_handle = Handle::new(descriptor, backend_info.cloned(), None);
                         group.push_client(client_handle).await;
                     }
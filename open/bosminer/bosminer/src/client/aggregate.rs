@@ -0,0 +1,469 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Combined view of statistics of all clients ("all pools combined"). The aggregation itself is
+//! pure and synchronous, it works on snapshots of client statistics taken in advance.
+//!
+//! Semantics of the combined values:
+//!
+//! - A client that has never received a valid job and has never submitted any solution is
+//!   considered never connected. It is counted in `clients` and `never_connected` (and in
+//!   `degraded` when it fails) but it is excluded from all ratios and from the hashrate estimate.
+//! - Reject and stale ratios are computed from difficulty of the solutions (like the cgminer
+//!   API does for a single pool): `rejected / (accepted + rejected + stale)`, respectively
+//!   `stale / (accepted + rejected + stale)`. Stale solutions are therefore never part of the
+//!   reject ratio, only of its denominator. The ratios are fractions in the range 0.0..=1.0 and
+//!   they are 0.0 when there is no solution.
+//! - Count ratios follow the same formulas with the number of solutions instead of their
+//!   difficulty (like the cgminer API summary of all pools).
+//! - Each client estimates its hashrate from accepted shares over its own window (it may be
+//!   shorter than the requested interval for a client that has been started recently). The
+//!   combined hashrate is the mean over the longest window of all clients. Every client
+//!   contributes only by the hashes made within its window, i.e. its hashrate is weighted by
+//!   the overlap of its window with the combined window.
+
+use super::Handle;
+
+use crate::stats;
+use crate::sync;
+
+use std::time;
+
+/// Number of solutions and their total difficulty
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Solutions {
+    pub count: u64,
+    pub difficulty: f64,
+}
+
+impl Solutions {
+    fn add(&mut self, other: &Self) {
+        self.count += other.count;
+        self.difficulty += other.difficulty;
+    }
+}
+
+impl From<&stats::MeterSnapshot> for Solutions {
+    fn from(snapshot: &stats::MeterSnapshot) -> Self {
+        Self {
+            count: snapshot.solutions,
+            difficulty: snapshot.shares.as_f64(),
+        }
+    }
+}
+
+/// Hashrate estimated from the shares accepted by the pool
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HashrateEstimate {
+    /// Mean hashrate within the window in MH/s
+    pub mega_hashes: f64,
+    /// Duration of the window the mean has been measured over
+    pub window: time::Duration,
+}
+
+/// Statistics of a single client taken at one instant
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientSnapshot {
+    pub status: sync::Status,
+    pub enabled: bool,
+    pub valid_jobs: u64,
+    pub accepted: Solutions,
+    pub rejected: Solutions,
+    pub stale: Solutions,
+    pub hashrate: HashrateEstimate,
+}
+
+impl ClientSnapshot {
+    /// Take the snapshot of `client` with hashrate estimated over the `interval` (it has to be one
+    /// of the intervals measured by the client statistics)
+    pub async fn take(client: &Handle, interval: time::Duration) -> Self {
+        let client_stats = client.stats();
+        let accepted = client_stats.accepted().take_snapshot().await;
+        let rejected = client_stats.rejected().take_snapshot().await;
+        let stale = client_stats.stale().take_snapshot().await;
+
+        let now = time::Instant::now();
+        let window = interval.min(now.duration_since(*client_stats.start_time()));
+        // The mean of a client running shorter than the interval is measured over the whole
+        // interval, convert it to the mean within the actual window
+        let mega_hashes = if window.as_secs_f64() > 0.0 {
+            accepted.to_mega_hashes(interval, now).into_f64() * interval.as_secs_f64()
+                / window.as_secs_f64()
+        } else {
+            0.0
+        };
+
+        Self {
+            status: client.status(),
+            enabled: client.is_enabled(),
+            valid_jobs: *client_stats.valid_jobs().take_snapshot() as u64,
+            accepted: (&*accepted).into(),
+            rejected: (&*rejected).into(),
+            stale: (&*stale).into(),
+            hashrate: HashrateEstimate {
+                mega_hashes,
+                window,
+            },
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.valid_jobs != 0
+            || self.accepted.count != 0
+            || self.rejected.count != 0
+            || self.stale.count != 0
+    }
+
+    /// The client runs and it is enabled
+    pub fn is_active(&self) -> bool {
+        self.enabled && self.status == sync::Status::Running
+    }
+
+    /// The client is enabled but it is failing or it is recovering from a failure
+    pub fn is_degraded(&self) -> bool {
+        self.enabled
+            && match self.status {
+                sync::Status::Failing
+                | sync::Status::Declining
+                | sync::Status::Retrying
+                | sync::Status::Recovering
+                | sync::Status::Failed => true,
+                sync::Status::Created
+                | sync::Status::Starting
+                | sync::Status::Connected
+                | sync::Status::Running
+                | sync::Status::Stopping
+                | sync::Status::Restarting
+                | sync::Status::Stopped => false,
+            }
+    }
+}
+
+/// Combined statistics of all clients, see the module documentation for the semantics
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AggregateStats {
+    /// Number of all clients
+    pub clients: usize,
+    /// Number of clients that have never been connected
+    pub never_connected: usize,
+    /// Indexes of active clients (into the slice of snapshots)
+    pub active: Vec<usize>,
+    /// Number of degraded clients
+    pub degraded: usize,
+    pub valid_jobs: u64,
+    pub accepted: Solutions,
+    pub rejected: Solutions,
+    pub stale: Solutions,
+    /// Ratios weighted by difficulty of the solutions
+    pub rejected_ratio: f64,
+    pub stale_ratio: f64,
+    /// Ratios of the number of solutions
+    pub rejected_count_ratio: f64,
+    pub stale_count_ratio: f64,
+    pub hashrate: HashrateEstimate,
+}
+
+impl AggregateStats {
+    pub fn new(snapshots: &[ClientSnapshot]) -> Self {
+        let mut aggregate = Self {
+            clients: snapshots.len(),
+            ..Default::default()
+        };
+
+        for (idx, snapshot) in snapshots.iter().enumerate() {
+            if snapshot.is_active() {
+                aggregate.active.push(idx);
+            }
+            if snapshot.is_degraded() {
+                aggregate.degraded += 1;
+            }
+            if !snapshot.is_connected() {
+                aggregate.never_connected += 1;
+                continue;
+            }
+            aggregate.valid_jobs += snapshot.valid_jobs;
+            aggregate.accepted.add(&snapshot.accepted);
+            aggregate.rejected.add(&snapshot.rejected);
+            aggregate.stale.add(&snapshot.stale);
+        }
+
+        let total_difficulty = aggregate.accepted.difficulty
+            + aggregate.rejected.difficulty
+            + aggregate.stale.difficulty;
+        if total_difficulty > 0.0 {
+            aggregate.rejected_ratio = aggregate.rejected.difficulty / total_difficulty;
+            aggregate.stale_ratio = aggregate.stale.difficulty / total_difficulty;
+        }
+        let total_count =
+            aggregate.accepted.count + aggregate.rejected.count + aggregate.stale.count;
+        if total_count > 0 {
+            aggregate.rejected_count_ratio = aggregate.rejected.count as f64 / total_count as f64;
+            aggregate.stale_count_ratio = aggregate.stale.count as f64 / total_count as f64;
+        }
+        aggregate.hashrate = Self::combine_hashrates(
+            snapshots
+                .iter()
+                .filter(|snapshot| snapshot.is_connected())
+                .map(|snapshot| &snapshot.hashrate),
+        );
+        aggregate
+    }
+
+    fn combine_hashrates<'a, I>(estimates: I) -> HashrateEstimate
+    where
+        I: Iterator<Item = &'a HashrateEstimate> + Clone,
+    {
+        let window = estimates
+            .clone()
+            .map(|estimate| estimate.window)
+            .max()
+            .unwrap_or_default();
+        if window.as_secs_f64() == 0.0 {
+            return Default::default();
+        }
+        let mega_hashes = estimates
+            .map(|estimate| estimate.mega_hashes * estimate.window.as_secs_f64())
+            .sum::<f64>()
+            / window.as_secs_f64();
+        HashrateEstimate {
+            mega_hashes,
+            window,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn solutions(count: u64, difficulty: f64) -> Solutions {
+        Solutions { count, difficulty }
+    }
+
+    fn hashrate(mega_hashes: f64, window_secs: u64) -> HashrateEstimate {
+        HashrateEstimate {
+            mega_hashes,
+            window: time::Duration::from_secs(window_secs),
+        }
+    }
+
+    fn snapshot(
+        status: sync::Status,
+        valid_jobs: u64,
+        accepted: Solutions,
+        rejected: Solutions,
+        stale: Solutions,
+        hashrate: HashrateEstimate,
+    ) -> ClientSnapshot {
+        ClientSnapshot {
+            status,
+            enabled: true,
+            valid_jobs,
+            accepted,
+            rejected,
+            stale,
+            hashrate,
+        }
+    }
+
+    fn healthy_pool() -> ClientSnapshot {
+        snapshot(
+            sync::Status::Running,
+            10,
+            solutions(90, 900.0),
+            solutions(5, 50.0),
+            solutions(5, 50.0),
+            hashrate(100.0, 60),
+        )
+    }
+
+    fn never_connected_pool(status: sync::Status) -> ClientSnapshot {
+        snapshot(
+            status,
+            0,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+    }
+
+    struct Scenario {
+        name: &'static str,
+        snapshots: Vec<ClientSnapshot>,
+        expected: AggregateStats,
+    }
+
+    fn scenarios() -> Vec<Scenario> {
+        vec![
+            Scenario {
+                name: "two healthy pools",
+                snapshots: vec![
+                    healthy_pool(),
+                    snapshot(
+                        sync::Status::Running,
+                        5,
+                        solutions(45, 450.0),
+                        solutions(3, 30.0),
+                        solutions(2, 20.0),
+                        hashrate(50.0, 30),
+                    ),
+                ],
+                expected: AggregateStats {
+                    clients: 2,
+                    never_connected: 0,
+                    active: vec![0, 1],
+                    degraded: 0,
+                    valid_jobs: 15,
+                    accepted: solutions(135, 1350.0),
+                    rejected: solutions(8, 80.0),
+                    stale: solutions(7, 70.0),
+                    rejected_ratio: 80.0 / 1500.0,
+                    stale_ratio: 70.0 / 1500.0,
+                    rejected_count_ratio: 8.0 / 150.0,
+                    stale_count_ratio: 7.0 / 150.0,
+                    // (100 * 60 + 50 * 30) / 60
+                    hashrate: hashrate(125.0, 60),
+                },
+            },
+            Scenario {
+                name: "healthy and flapping pool",
+                snapshots: vec![
+                    healthy_pool(),
+                    snapshot(
+                        sync::Status::Retrying,
+                        3,
+                        solutions(10, 100.0),
+                        solutions(10, 100.0),
+                        Default::default(),
+                        hashrate(20.0, 60),
+                    ),
+                ],
+                expected: AggregateStats {
+                    clients: 2,
+                    never_connected: 0,
+                    active: vec![0],
+                    degraded: 1,
+                    valid_jobs: 13,
+                    accepted: solutions(100, 1000.0),
+                    rejected: solutions(15, 150.0),
+                    stale: solutions(5, 50.0),
+                    rejected_ratio: 150.0 / 1200.0,
+                    stale_ratio: 50.0 / 1200.0,
+                    rejected_count_ratio: 15.0 / 120.0,
+                    stale_count_ratio: 5.0 / 120.0,
+                    hashrate: hashrate(120.0, 60),
+                },
+            },
+            Scenario {
+                name: "all pools down",
+                snapshots: vec![
+                    snapshot(
+                        sync::Status::Failed,
+                        2,
+                        solutions(4, 40.0),
+                        solutions(1, 10.0),
+                        Default::default(),
+                        hashrate(0.0, 60),
+                    ),
+                    never_connected_pool(sync::Status::Failed),
+                ],
+                expected: AggregateStats {
+                    clients: 2,
+                    never_connected: 1,
+                    active: vec![],
+                    degraded: 2,
+                    valid_jobs: 2,
+                    accepted: solutions(4, 40.0),
+                    rejected: solutions(1, 10.0),
+                    stale: Default::default(),
+                    rejected_ratio: 0.2,
+                    stale_ratio: 0.0,
+                    rejected_count_ratio: 0.2,
+                    stale_count_ratio: 0.0,
+                    hashrate: hashrate(0.0, 60),
+                },
+            },
+            Scenario {
+                name: "rejects at low difficulty",
+                snapshots: vec![
+                    snapshot(
+                        sync::Status::Running,
+                        4,
+                        solutions(30, 2400.0),
+                        solutions(8, 80.0),
+                        solutions(2, 320.0),
+                        hashrate(40.0, 60),
+                    ),
+                    never_connected_pool(sync::Status::Retrying),
+                ],
+                expected: AggregateStats {
+                    clients: 2,
+                    never_connected: 1,
+                    active: vec![0],
+                    degraded: 1,
+                    valid_jobs: 4,
+                    accepted: solutions(30, 2400.0),
+                    rejected: solutions(8, 80.0),
+                    stale: solutions(2, 320.0),
+                    rejected_ratio: 80.0 / 2800.0,
+                    stale_ratio: 320.0 / 2800.0,
+                    rejected_count_ratio: 8.0 / 40.0,
+                    stale_count_ratio: 2.0 / 40.0,
+                    hashrate: hashrate(40.0, 60),
+                },
+            },
+            Scenario {
+                name: "single never connected pool",
+                snapshots: vec![never_connected_pool(sync::Status::Starting)],
+                expected: AggregateStats {
+                    clients: 1,
+                    never_connected: 1,
+                    ..Default::default()
+                },
+            },
+        ]
+    }
+
+    #[test]
+    fn test_aggregate_stats() {
+        for scenario in scenarios() {
+            let aggregate = AggregateStats::new(&scenario.snapshots);
+            assert_eq!(aggregate, scenario.expected, "{}", scenario.name);
+        }
+    }
+
+    #[test]
+    fn test_disabled_client_is_neither_active_nor_degraded() {
+        let mut running = healthy_pool();
+        running.enabled = false;
+        let mut failed = never_connected_pool(sync::Status::Failed);
+        failed.enabled = false;
+
+        let aggregate = AggregateStats::new(&[running, failed]);
+        assert!(aggregate.active.is_empty());
+        assert_eq!(aggregate.degraded, 0);
+        // Statistics of disabled clients which have been connected are still accounted
+        assert_eq!(aggregate.accepted, solutions(90, 900.0));
+        assert_eq!(aggregate.never_connected, 1);
+    }
+}
@@ -20,6 +20,8 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
+use super::target_util;
+
 use crate::error;
 use crate::job;
 use crate::node;
@@ -158,7 +160,7 @@ impl Difficulty {
         } else {
             Self::MINIMAL_DIFFICULTY
         };
-        ii_bitcoin::Target::from_pool_difficulty(difficulty)
+        target_util::target_from_difficulty(difficulty)
     }
 }
 
@@ -0,0 +1,129 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Evaluation of daily windows in which a client is preferred by the scheduler. Clients with an
+//! open window are selected before unscheduled clients, clients outside of their windows are
+//! never selected.
+//!
+//! The state of the schedule is evaluated from the current wall-clock time only, there are no
+//! timers nor boundaries tracked between evaluations. A step of the wall clock (e.g. by NTP)
+//! therefore results in at most one transition and a step over a whole window in none. To prevent
+//! flapping when the clock wobbles around a boundary, the state is kept unchanged while the time
+//! is within the dead band around any boundary.
+
+use bosminer_config::{ScheduleConfig, ScheduleOutsideWindow, ScheduleTimeZone};
+
+use chrono::Timelike;
+
+use std::time;
+
+/// Client state from the point of view of the scheduler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// The client has no schedule, it is selected when no scheduled client has an open window
+    Unscheduled,
+    /// Some window of the client is open
+    Open,
+    /// All windows of the client are closed
+    Closed(ScheduleOutsideWindow),
+}
+
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    /// Start and end of the windows in seconds since midnight
+    windows: Vec<(u32, u32)>,
+    time_zone: ScheduleTimeZone,
+    outside_window: ScheduleOutsideWindow,
+    /// State after the last evaluation, it is unknown until the first one
+    open: Option<bool>,
+}
+
+impl Schedule {
+    /// Distance from a window boundary within which the state is not changed
+    pub const DEAD_BAND: time::Duration = time::Duration::from_secs(60);
+
+    const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+    pub fn new(config: &ScheduleConfig) -> Self {
+        Self {
+            windows: config
+                .windows
+                .iter()
+                .map(|window| {
+                    window
+                        .bounds()
+                        .expect("BUG: schedule has not been validated")
+                })
+                .collect(),
+            time_zone: config.time_zone.unwrap_or_default(),
+            outside_window: config.outside_window.unwrap_or_default(),
+            open: None,
+        }
+    }
+
+    fn seconds_since_midnight(&self, now: time::SystemTime) -> u32 {
+        match self.time_zone {
+            ScheduleTimeZone::Utc => chrono::DateTime::<chrono::Utc>::from(now)
+                .time()
+                .num_seconds_from_midnight(),
+            ScheduleTimeZone::Local => chrono::DateTime::<chrono::Local>::from(now)
+                .time()
+                .num_seconds_from_midnight(),
+        }
+    }
+
+    fn is_near_boundary(&self, time: u32) -> bool {
+        let dead_band = Self::DEAD_BAND.as_secs() as u32;
+        self.windows
+            .iter()
+            .flat_map(|&(start, end)| vec![start, end])
+            .any(|boundary| {
+                let distance = if time > boundary {
+                    time - boundary
+                } else {
+                    boundary - time
+                };
+                distance.min(Self::SECONDS_PER_DAY - distance) < dead_band
+            })
+    }
+
+    /// Evaluate the schedule at `now`, returns true when the state has changed
+    pub fn update_at(&mut self, now: time::SystemTime) -> bool {
+        let time = self.seconds_since_midnight(now);
+        if self.open.is_some() && self.is_near_boundary(time) {
+            return false;
+        }
+        let open = self
+            .windows
+            .iter()
+            .any(|&bounds| bosminer_config::ScheduleWindow::contains(bounds, time));
+        self.open.replace(open) != Some(open)
+    }
+
+    /// State after the last evaluation, the schedule is closed until it has been evaluated
+    pub fn state(&self) -> State {
+        match self.open {
+            Some(true) => State::Open,
+            _ => State::Closed(self.outside_window),
+        }
+    }
+}
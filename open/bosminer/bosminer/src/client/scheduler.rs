@@ -20,10 +20,14 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
-use crate::client;
-use crate::sync::event;
+use ii_logging::macros::*;
+
+use crate::client::{self, schedule};
+use crate::sync::{self, event};
 use crate::work;
 
+use bosminer_config::ScheduleOutsideWindow;
+
 use futures::channel::mpsc;
 use futures::lock::{Mutex, MutexGuard};
 use ii_async_compat::{futures, FutureExt};
@@ -53,6 +57,11 @@ impl ClientHandle {
         self.client_handle.is_running()
     }
 
+    #[inline]
+    fn holds_failover(&self) -> bool {
+        self.client_handle.holds_failover()
+    }
+
     #[inline]
     fn try_start(&self) -> Result<(), ()> {
         if self.client_handle.is_enabled() {
@@ -143,19 +152,55 @@ impl GroupHandle {
         self.group_handle.descriptor.get_quota()
     }
 
+    /// Evaluate schedules of all clients at `now`, returns true when the state of some schedule
+    /// has changed
+    async fn update_schedules(&self, now: time::SystemTime) -> bool {
+        let scheduler_client_handles = self.group_handle.scheduler_client_handles.lock().await;
+        scheduler_client_handles
+            .iter()
+            .fold(false, |changed, scheduler_client_handle| {
+                scheduler_client_handle
+                    .client_handle
+                    .update_schedule_at(now)
+                    || changed
+            })
+    }
+
     async fn update_status(&mut self) {
         let mut scheduler_client_handles = self.group_handle.scheduler_client_handles.lock().await;
         let mut generated_work_delta = 0;
-
-        self.active_client = None;
         for scheduler_client_handle in scheduler_client_handles.iter_mut() {
             generated_work_delta += scheduler_client_handle.get_delta_and_update_generated_work();
+        }
+
+        // Clients with an open window take precedence over unscheduled clients, clients outside
+        // of their windows are never selected
+        let candidates = scheduler_client_handles
+            .iter()
+            .filter(|scheduler_client_handle| {
+                scheduler_client_handle.client_handle.schedule_state() == schedule::State::Open
+            })
+            .chain(
+                scheduler_client_handles
+                    .iter()
+                    .filter(|scheduler_client_handle| {
+                        scheduler_client_handle.client_handle.schedule_state()
+                            == schedule::State::Unscheduled
+                    }),
+            );
+        self.active_client = None;
+        for scheduler_client_handle in candidates {
             match self.active_client {
                 None => {
                     if scheduler_client_handle.is_running() {
                         self.active_client = Some(scheduler_client_handle.client_handle.clone());
                     } else {
                         let _ = scheduler_client_handle.try_start();
+                        if scheduler_client_handle.holds_failover() {
+                            // Clients with lower priority are neither activated nor stopped while
+                            // the client waits for its pool
+                            break;
+                        }
                     }
                 }
                 Some(_) => {
@@ -163,6 +208,17 @@ impl GroupHandle {
                 }
             }
         }
+        for scheduler_client_handle in scheduler_client_handles.iter() {
+            match scheduler_client_handle.client_handle.schedule_state() {
+                schedule::State::Closed(ScheduleOutsideWindow::Standby) => {
+                    let _ = scheduler_client_handle.try_start();
+                }
+                schedule::State::Closed(ScheduleOutsideWindow::Stop) => {
+                    let _ = scheduler_client_handle.try_delayed_stop();
+                }
+                schedule::State::Open | schedule::State::Unscheduled => {}
+            }
+        }
 
         self.generated_work += generated_work_delta;
     }
@@ -223,6 +279,10 @@ impl PartialEq<Arc<client::Handle>> for ActiveClient {
 /// Responsible for selecting and switching jobs
 struct JobDispatcher {
     active_client: ActiveClient,
+    /// Group of the active client
+    active_group: Option<Arc<client::Group>>,
+    /// Sequence number of the last switch between clients
+    switch_seq_num: u64,
     group_registry: Arc<Mutex<client::GroupRegistry>>,
 }
 
@@ -233,11 +293,15 @@ impl JobDispatcher {
     ) -> Self {
         Self {
             active_client: ActiveClient::None(Arc::new(engine_sender)),
+            active_group: None,
+            switch_seq_num: 0,
             group_registry,
         }
     }
 
-    fn switch_client<T>(&mut self, next_client: T)
+    /// Returns the previously active client when the active client has been switched to another
+    /// one
+    fn switch_client<T>(&mut self, next_client: T) -> Option<Arc<client::Handle>>
     where
         T: Into<Option<Arc<client::Handle>>>,
     {
@@ -247,7 +311,9 @@ impl JobDispatcher {
                     next_client
                         .engine_sender
                         .swap_sender(self.active_client.get_engine_sender());
+                    let prev_client = self.active_client.get_client();
                     self.active_client = ActiveClient::Some(next_client);
+                    return prev_client;
                 }
             }
             None => match &self.active_client {
@@ -257,9 +323,85 @@ impl JobDispatcher {
                 ActiveClient::None(_) => {}
             },
         }
+        None
     }
 
-    async fn select_client(&self, generated_work_delta: u64) -> Option<Arc<client::Handle>> {
+    /// Determine why the scheduler has moved the hashrate from `prev_client` to `next_client`
+    /// from `next_group`
+    fn switch_reason(
+        prev_client: &client::Handle,
+        prev_group: Option<&Arc<client::Group>>,
+        next_client: &client::Handle,
+        next_group: &Arc<client::Group>,
+    ) -> client::switches::Reason {
+        if !prev_client.is_enabled() {
+            client::switches::Reason::Manual
+        } else if let schedule::State::Closed(_) = prev_client.schedule_state() {
+            // The client may have been stopped already when it is not kept in standby
+            client::switches::Reason::Schedule
+        } else if next_client.schedule_state() == schedule::State::Open
+            && prev_client.schedule_state() == schedule::State::Unscheduled
+        {
+            // The unscheduled client is being stopped in favour of the scheduled one
+            client::switches::Reason::Schedule
+        } else if prev_client.status() != sync::Status::Running {
+            client::switches::Reason::Failover(prev_client.status())
+        } else if prev_group.map_or(false, |prev_group| Arc::ptr_eq(prev_group, next_group)) {
+            // The first running client of the group is always selected
+            client::switches::Reason::PrimaryRecovered
+        } else {
+            client::switches::Reason::Quota
+        }
+    }
+
+    /// Record the switch in journals of both clients. It is done after the switch so that the
+    /// annotation never delays it.
+    async fn annotate_switch(
+        &mut self,
+        prev_client: Arc<client::Handle>,
+        next_client: Arc<client::Handle>,
+        reason: client::switches::Reason,
+    ) {
+        self.switch_seq_num += 1;
+        let seq_num = self.switch_seq_num;
+        let time = time::SystemTime::now();
+        let prev_endpoint = prev_client.endpoint_key().await;
+        let next_endpoint = next_client.endpoint_key().await;
+        info!(
+            "Scheduler: switch #{} from {} to {} ({})",
+            seq_num, prev_endpoint, next_endpoint, reason
+        );
+
+        prev_client.annotate_switch(client::switches::Annotation {
+            seq_num,
+            time,
+            role: client::switches::Role::Deactivated,
+            reason,
+            peer: next_endpoint,
+        });
+        next_client.annotate_switch(client::switches::Annotation {
+            seq_num,
+            time,
+            role: client::switches::Role::Activated,
+            reason,
+            peer: prev_endpoint,
+        });
+    }
+
+    /// Make `next_client` from `next_group` the active client
+    async fn activate(&mut self, next_client: Arc<client::Handle>, next_group: Arc<client::Group>) {
+        let prev_group = self.active_group.replace(next_group.clone());
+        if let Some(prev_client) = self.switch_client(next_client.clone()) {
+            let reason =
+                Self::switch_reason(&prev_client, prev_group.as_ref(), &next_client, &next_group);
+            self.annotate_switch(prev_client, next_client, reason).await;
+        }
+    }
+
+    async fn select_client(
+        &self,
+        generated_work_delta: u64,
+    ) -> Option<(Arc<client::Handle>, Arc<client::Group>)> {
         let mut group_registry = self.group_registry.lock().await;
         if group_registry.is_empty() {
             return None;
@@ -278,31 +420,44 @@ impl JobDispatcher {
                 / (total_generated_work + generated_work_delta) as f64;
             let next_error = (scheduler_group_handle.share_ratio - next_group_share_ratio).abs();
             if let Some(active_client) = scheduler_group_handle.active_client.as_ref().cloned() {
+                let group = scheduler_group_handle.group_handle.clone();
                 match next_client {
-                    None => next_client = Some((active_client, next_error)),
-                    Some((_, min_error)) => {
+                    None => next_client = Some((active_client, group, next_error)),
+                    Some((_, _, min_error)) => {
                         if min_error >= next_error {
-                            next_client = Some((active_client, next_error));
+                            next_client = Some((active_client, group, next_error));
                         }
                     }
                 }
             }
         }
-        next_client.map(|(next_client, _)| next_client)
+        next_client.map(|(next_client, group, _)| (next_client, group))
     }
 
-    async fn schedule(&mut self, generated_work_delta: u64) {
+    /// Evaluate schedules of all clients at `now`, returns true when some client has entered or
+    /// left its window
+    async fn update_schedules(&self, now: time::SystemTime) -> bool {
+        let group_registry = self.group_registry.lock().await;
+        let mut changed = false;
+        for scheduler_group_handle in group_registry.iter() {
+            changed |= scheduler_group_handle.update_schedules(now).await;
+        }
+        changed
+    }
+
+    async fn schedule(&mut self, generated_work_delta: u64, now: time::SystemTime) {
+        let schedule_changed = self.update_schedules(now).await;
         match &self.active_client {
             ActiveClient::Some(client_handle) => {
-                if generated_work_delta == 0 && client_handle.is_running() {
+                if generated_work_delta == 0 && client_handle.is_running() && !schedule_changed {
                     // When some client is active and no work has been generated then do nothing
                     return;
                 }
             }
             _ => {}
         }
-        if let Some(next_client) = self.select_client(generated_work_delta).await {
-            self.switch_client(next_client);
+        if let Some((next_client, next_group)) = self.select_client(generated_work_delta).await {
+            self.activate(next_client, next_group).await;
         }
     }
 }
@@ -391,8 +546,384 @@ impl JobExecutor {
 
             self.lock_dispatcher()
                 .await
-                .schedule(generated_work_delta)
+                .schedule(generated_work_delta, time::SystemTime::now())
                 .await;
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sync::event;
+
+    use bosminer_config::{
+        ClientDescriptor, ClientUserInfo, ScheduleConfig, ScheduleTimeZone, ScheduleWindow,
+    };
+    use ii_async_compat::tokio;
+
+    use std::sync::atomic::Ordering;
+
+    fn build_client(url: &str) -> Arc<client::Handle> {
+        build_client_with_schedule(url, None)
+    }
+
+    /// Build a client with UTC `windows`
+    fn build_scheduled_client(
+        url: &str,
+        windows: &[(&str, &str)],
+        outside_window: ScheduleOutsideWindow,
+    ) -> Arc<client::Handle> {
+        let schedule = ScheduleConfig {
+            windows: windows
+                .iter()
+                .map(|(start, end)| ScheduleWindow {
+                    start: start.to_string(),
+                    end: end.to_string(),
+                })
+                .collect(),
+            time_zone: Some(ScheduleTimeZone::Utc),
+            outside_window: Some(outside_window),
+        };
+        schedule.validate().expect("BUG: invalid schedule");
+        build_client_with_schedule(url, Some(schedule))
+    }
+
+    fn build_client_with_schedule(
+        url: &str,
+        schedule: Option<ScheduleConfig>,
+    ) -> Arc<client::Handle> {
+        let mut descriptor =
+            ClientDescriptor::create(url, &ClientUserInfo::new("user", None), true)
+                .expect("BUG: invalid client URL");
+        descriptor.schedule = schedule;
+        let client = client::Handle::new(descriptor, None, None);
+        // Enable the client without starting it, its status is driven by the test
+        client.enabled.store(true, Ordering::Relaxed);
+        Arc::new(client)
+    }
+
+    fn run(client: &client::Handle) {
+        let status = client.node.status();
+        assert!(status.initiate_starting());
+        assert!(status.initiate_running());
+    }
+
+    fn fail(client: &client::Handle) {
+        let status = client.node.status();
+        status.initiate_failing();
+        assert!(status.can_stop());
+    }
+
+    /// Complete the restart initiated by the scheduler as the task of the client would (the
+    /// client connects right away)
+    fn settle(clients: &[&Arc<client::Handle>]) {
+        for client in clients {
+            let status = client.node.status();
+            if status.status() == sync::Status::Restarting {
+                assert!(!status.can_stop());
+                assert!(status.initiate_running());
+            }
+        }
+    }
+
+    /// UTC time of the day in a test
+    fn at(hours: u64, minutes: u64, seconds: u64) -> time::SystemTime {
+        // Midnight (UTC) of an arbitrary day, 2019-04-14
+        const EPOCH_OFFSET_SECS: u64 = 18_000 * 24 * 60 * 60;
+        time::UNIX_EPOCH
+            + time::Duration::from_secs(EPOCH_OFFSET_SECS + hours * 3600 + minutes * 60 + seconds)
+    }
+
+    /// Build a dispatcher of a single group with `clients` in the order of their priority
+    async fn build_dispatcher(clients: &[&Arc<client::Handle>]) -> JobDispatcher {
+        let mut group_registry = client::GroupRegistry::new(event::Monitor::new());
+        let group = group_registry
+            .create_group(Default::default(), 1)
+            .expect("BUG: cannot create group");
+        group.scheduler_client_handles.lock().await.extend(
+            clients
+                .iter()
+                .map(|client| ClientHandle::new((*client).clone())),
+        );
+        JobDispatcher::new(
+            work::EngineSender::new(None),
+            Arc::new(Mutex::new(group_registry)),
+        )
+    }
+
+    fn is_active(dispatcher: &JobDispatcher, client: &Arc<client::Handle>) -> bool {
+        dispatcher.active_client == *client
+    }
+
+    fn switches(client: &client::Handle) -> Vec<(u64, client::switches::Role, String)> {
+        client
+            .switches()
+            .snapshot()
+            .into_iter()
+            .map(|annotation| (annotation.seq_num, annotation.role, annotation.peer))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_switch_annotations() {
+        let event_monitor = event::Monitor::new();
+        let group = Arc::new(client::Group::new(
+            Default::default(),
+            event_monitor.publish(),
+            1,
+        ));
+        let mut dispatcher = JobDispatcher::new(
+            work::EngineSender::new(None),
+            Arc::new(Mutex::new(client::GroupRegistry::new(event_monitor))),
+        );
+        let primary = build_client("drain://pool-a");
+        let backup = build_client("drain://pool-b");
+
+        // Initial activation is not a switch
+        run(&primary);
+        dispatcher.activate(primary.clone(), group.clone()).await;
+        assert!(primary.switches().snapshot().is_empty());
+
+        // Failover to the backup pool
+        fail(&primary);
+        run(&backup);
+        dispatcher.activate(backup.clone(), group.clone()).await;
+        // Repeated scheduling of the same client is not a switch
+        dispatcher.activate(backup.clone(), group.clone()).await;
+        let annotation = backup.switches().snapshot()[0].clone();
+        assert_eq!(
+            annotation.reason,
+            client::switches::Reason::Failover(sync::Status::Failed)
+        );
+        // Duplicate annotation of the same switch is ignored
+        backup.annotate_switch(annotation);
+
+        // Primary pool recovers
+        run(&primary);
+        dispatcher.activate(primary.clone(), group.clone()).await;
+        let annotation = primary.switches().snapshot()[1].clone();
+        assert_eq!(
+            annotation.reason,
+            client::switches::Reason::PrimaryRecovered
+        );
+
+        // Annotations of both clients are paired by sequence numbers
+        assert_eq!(
+            switches(&primary),
+            vec![
+                (
+                    1,
+                    client::switches::Role::Deactivated,
+                    "drain://pool-b".to_string()
+                ),
+                (
+                    2,
+                    client::switches::Role::Activated,
+                    "drain://pool-b".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            switches(&backup),
+            vec![
+                (
+                    1,
+                    client::switches::Role::Activated,
+                    "drain://pool-a".to_string()
+                ),
+                (
+                    2,
+                    client::switches::Role::Deactivated,
+                    "drain://pool-a".to_string()
+                ),
+            ]
+        );
+        for client in [&primary, &backup].iter() {
+            assert_eq!(client.switches().take_switch_count(), 2);
+            assert_eq!(client.switches().take_switch_count(), 0);
+        }
+        assert_eq!(
+            primary.switches().snapshot()[0].time,
+            backup.switches().snapshot()[0].time
+        );
+    }
+
+    #[tokio::test]
+    async fn test_switch_reasons() {
+        let event_monitor = event::Monitor::new();
+        let group_a = Arc::new(client::Group::new(
+            Default::default(),
+            event_monitor.publish(),
+            1,
+        ));
+        let group_b = Arc::new(client::Group::new(
+            Default::default(),
+            event_monitor.publish(),
+            1,
+        ));
+        let mut dispatcher = JobDispatcher::new(
+            work::EngineSender::new(None),
+            Arc::new(Mutex::new(client::GroupRegistry::new(event_monitor))),
+        );
+        let client_a = build_client("drain://pool-a");
+        let client_b = build_client("drain://pool-b");
+        run(&client_a);
+        run(&client_b);
+
+        // Quota slice of another group
+        dispatcher.activate(client_a.clone(), group_a.clone()).await;
+        dispatcher.activate(client_b.clone(), group_b.clone()).await;
+        // Disabled client
+        client_b.enabled.store(false, Ordering::Relaxed);
+        dispatcher.activate(client_a.clone(), group_a.clone()).await;
+
+        let reasons: Vec<_> = client_a
+            .switches()
+            .snapshot()
+            .into_iter()
+            .map(|annotation| annotation.reason)
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                client::switches::Reason::Quota,
+                client::switches::Reason::Manual
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_schedule_two_windows() {
+        let solo = build_scheduled_client(
+            "drain://solo",
+            &[("08:00", "12:00"), ("20:00", "02:00")],
+            ScheduleOutsideWindow::Standby,
+        );
+        let pool = build_client("drain://pool");
+        let mut dispatcher = build_dispatcher(&[&solo, &pool]).await;
+        run(&solo);
+        run(&pool);
+
+        let mut activations = vec![];
+        for minute in 0..24 * 60 {
+            let now = at(minute / 60, minute % 60, 0);
+            dispatcher.schedule(1, now).await;
+            settle(&[&solo, &pool]);
+            let active = if is_active(&dispatcher, &solo) {
+                "solo"
+            } else {
+                "pool"
+            };
+            if activations.last().map(|(_, last)| *last) != Some(active) {
+                activations.push((now, active));
+            }
+        }
+        // Transitions happen after the dead band, the unscheduled client takes over once it has
+        // been started again
+        assert_eq!(
+            activations,
+            vec![
+                (at(0, 0, 0), "solo"),
+                (at(2, 2, 0), "pool"),
+                (at(8, 1, 0), "solo"),
+                (at(12, 2, 0), "pool"),
+                (at(20, 1, 0), "solo"),
+            ]
+        );
+        let reasons: Vec<_> = solo
+            .switches()
+            .snapshot()
+            .into_iter()
+            .map(|annotation| annotation.reason)
+            .collect();
+        assert_eq!(reasons, vec![client::switches::Reason::Schedule; 4]);
+        // The scheduled client has been kept in standby outside of its windows
+        assert_eq!(solo.status(), sync::Status::Running);
+    }
+
+    #[test]
+    fn test_schedule_boundary_debounce() {
+        let config = ScheduleConfig {
+            windows: vec![ScheduleWindow {
+                start: "08:00".to_string(),
+                end: "12:00".to_string(),
+            }],
+            time_zone: Some(ScheduleTimeZone::Utc),
+            outside_window: None,
+        };
+        let mut schedule = schedule::Schedule::new(&config);
+        assert!(schedule.update_at(at(7, 50, 0)));
+        assert_eq!(
+            schedule.state(),
+            schedule::State::Closed(ScheduleOutsideWindow::Standby)
+        );
+
+        // Clock wobbling around the boundary by seconds doesn't change the state
+        for &(minutes, seconds) in [(59, 50), (0, 10), (59, 55), (0, 5), (0, 59)].iter() {
+            let hours = if minutes == 59 { 7 } else { 8 };
+            assert!(!schedule.update_at(at(hours, minutes, seconds)));
+        }
+        assert!(schedule.update_at(at(8, 1, 0)));
+        assert_eq!(schedule.state(), schedule::State::Open);
+        assert!(!schedule.update_at(at(8, 0, 30)));
+        assert!(!schedule.update_at(at(8, 1, 30)));
+        assert_eq!(schedule.state(), schedule::State::Open);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_outside_window() {
+        let standby = build_scheduled_client(
+            "drain://standby",
+            &[("08:00", "12:00")],
+            ScheduleOutsideWindow::Standby,
+        );
+        let stopped = build_scheduled_client(
+            "drain://stopped",
+            &[("08:00", "12:00")],
+            ScheduleOutsideWindow::Stop,
+        );
+        let pool = build_client("drain://pool");
+        let mut dispatcher = build_dispatcher(&[&standby, &stopped, &pool]).await;
+        run(&standby);
+        run(&stopped);
+        run(&pool);
+
+        dispatcher.schedule(1, at(13, 0, 0)).await;
+        assert!(is_active(&dispatcher, &pool));
+        assert_eq!(standby.status(), sync::Status::Running);
+        assert_eq!(stopped.status(), sync::Status::Stopping);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_clock_step() {
+        let solo = build_scheduled_client(
+            "drain://solo",
+            &[("08:00", "12:00")],
+            ScheduleOutsideWindow::Standby,
+        );
+        let pool = build_client("drain://pool");
+        let mut dispatcher = build_dispatcher(&[&solo, &pool]).await;
+        run(&solo);
+        run(&pool);
+
+        // Step over the whole window doesn't switch at all
+        dispatcher.schedule(1, at(7, 30, 0)).await;
+        assert!(is_active(&dispatcher, &pool));
+        dispatcher.schedule(1, at(12, 30, 0)).await;
+        assert!(is_active(&dispatcher, &pool));
+        assert!(pool.switches().snapshot().is_empty());
+
+        // Step across the boundary switches exactly once
+        dispatcher.schedule(1, at(7, 58, 0)).await;
+        dispatcher.schedule(1, at(8, 5, 0)).await;
+        assert!(is_active(&dispatcher, &solo));
+        dispatcher.schedule(1, at(8, 5, 1)).await;
+        // Step back to the boundary is absorbed by the dead band
+        dispatcher.schedule(1, at(8, 0, 20)).await;
+        dispatcher.schedule(1, at(8, 5, 2)).await;
+        assert!(is_active(&dispatcher, &solo));
+        assert_eq!(pool.switches().take_switch_count(), 1);
+        assert_eq!(solo.switches().take_switch_count(), 1);
+    }
+}
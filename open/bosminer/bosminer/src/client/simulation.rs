@@ -0,0 +1,577 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Simulation client generates jobs locally and acknowledges submitted solutions as if they have
+//! been sent to a pool. It allows running the whole mining pipeline (job distribution, backend,
+//! solution routing, statistics and API) on a bench without any network.
+//!
+//! Jobs and acknowledgements are derived from a seed so that the same configuration always
+//! produces the same sequence of jobs. The client feeds the same statistics, events, health and
+//! status document as the Stratum V2 client.
+
+#[cfg(test)]
+mod test;
+
+use ii_logging::macros::*;
+
+use super::stratum_v2::{context, events, health, hourly, notices, status};
+use super::switches;
+use super::target_util;
+
+use crate::error;
+use crate::job;
+use crate::node;
+use crate::stats;
+use crate::sync;
+use crate::work;
+
+use bosminer_config::{ClientDescriptor, SimulationConfig};
+use bosminer_macros::ClientNode;
+
+use ii_bitcoin::{HashTrait, MeetsTarget};
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use futures::lock::Mutex;
+use ii_async_compat::prelude::*;
+use ii_async_compat::select;
+use tokio::time::delay_for;
+
+use std::fmt;
+use std::sync::Mutex as StdMutex;
+use std::sync::{Arc, Weak};
+use std::time;
+
+/// Deterministic generator of pseudo-random numbers (SplitMix64). It is good enough for
+/// simulation and it keeps the sequence stable across platforms and builds.
+#[derive(Debug, Clone)]
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns number uniformly distributed in range [0.0, 1.0)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn next_hash(&mut self) -> ii_bitcoin::DHash {
+        let mut bytes = [0u8; ii_bitcoin::SHA256_DIGEST_SIZE];
+        for chunk in bytes.chunks_mut(std::mem::size_of::<u64>()) {
+            chunk.copy_from_slice(&self.next_u64().to_le_bytes());
+        }
+        ii_bitcoin::DHash::from_slice(&bytes).expect("BUG: cannot convert double hash from slice")
+    }
+}
+
+#[derive(Debug)]
+pub struct Job {
+    client: Weak<SimulationClient>,
+    /// Sequence number of the job within the session
+    pub index: u64,
+    /// Sequence number of the previous block hash the job belongs to
+    pub epoch: u64,
+    prev_hash: ii_bitcoin::DHash,
+    merkle_root: ii_bitcoin::DHash,
+    time: u32,
+    target: ii_bitcoin::Target,
+}
+
+impl job::Bitcoin for Job {
+    fn origin(&self) -> Weak<dyn node::Client> {
+        self.client.clone()
+    }
+
+    fn version(&self) -> u32 {
+        536928256
+    }
+
+    fn version_mask(&self) -> u32 {
+        0x1fffe000
+    }
+
+    fn previous_hash(&self) -> &ii_bitcoin::DHash {
+        &self.prev_hash
+    }
+
+    fn merkle_root(&self) -> &ii_bitcoin::DHash {
+        &self.merkle_root
+    }
+
+    fn time(&self) -> u32 {
+        self.time
+    }
+
+    fn bits(&self) -> u32 {
+        387062484
+    }
+
+    fn target(&self) -> ii_bitcoin::Target {
+        self.target
+    }
+
+    fn is_valid(&self) -> bool {
+        true
+    }
+}
+
+/// Generates deterministic sequence of jobs, the previous block hash changes every
+/// `jobs_per_prev_hash` jobs
+#[derive(Debug)]
+struct JobGenerator {
+    rng: Rng,
+    target: ii_bitcoin::Target,
+    jobs_per_prev_hash: u64,
+    index: u64,
+    epoch: u64,
+    prev_hash: ii_bitcoin::DHash,
+}
+
+impl JobGenerator {
+    const START_TIME: u32 = 1581508326;
+
+    fn new(config: &SimulationConfig) -> Self {
+        let mut rng = Rng::new(config.seed.unwrap_or(SimulationConfig::DEFAULT_SEED));
+        let prev_hash = rng.next_hash();
+        Self {
+            rng,
+            target: SimulationClient::target_from_config(config),
+            jobs_per_prev_hash: config
+                .jobs_per_prev_hash
+                .unwrap_or(SimulationConfig::DEFAULT_JOBS_PER_PREV_HASH),
+            index: 0,
+            epoch: 0,
+            prev_hash,
+        }
+    }
+
+    fn next(&mut self, client: &Arc<SimulationClient>) -> Job {
+        if self.index > 0 && self.index % self.jobs_per_prev_hash == 0 {
+            self.epoch += 1;
+            self.prev_hash = self.rng.next_hash();
+        }
+        let job = Job {
+            client: Arc::downgrade(client),
+            index: self.index,
+            epoch: self.epoch,
+            prev_hash: self.prev_hash,
+            merkle_root: self.rng.next_hash(),
+            time: Self::START_TIME.wrapping_add(self.index as u32),
+            target: self.target,
+        };
+        self.index += 1;
+        job
+    }
+}
+
+/// Acknowledgement of a solution produced by the simulated pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ack {
+    Accepted,
+    /// The solution is rejected with an error code
+    Rejected(&'static str),
+    /// The solution belongs to a job with an outdated previous block hash
+    Stale,
+}
+
+impl Ack {
+    const LOW_DIFFICULTY_SHARE: &'static str = "low-difficulty-share";
+    const SIMULATED_REJECT: &'static str = "simulated-reject";
+}
+
+#[derive(Debug, ClientNode)]
+pub struct SimulationClient {
+    descriptor: StdMutex<ClientDescriptor>,
+    #[member_status]
+    status: sync::StatusMonitor,
+    #[member_client_stats]
+    client_stats: stats::BasicClient,
+    stop_sender: mpsc::Sender<()>,
+    stop_receiver: Mutex<mpsc::Receiver<()>>,
+    last_job: StdMutex<Option<Arc<Job>>>,
+    job_sender: job::Sender,
+    solution_receiver: Mutex<job::SolutionReceiver>,
+    /// Source of identifiers of connections (runs) and sessions
+    ids: context::Counters,
+    health: health::Monitor,
+    events: events::Log,
+    notices: notices::Board,
+    /// Number of solutions submitted to the simulated pool
+    submitted: stats::CounterUsize,
+    /// Number of solutions of jobs that have not been generated by the client
+    foreign_solutions: stats::CounterUsize,
+    hourly_shares: hourly::Ring,
+}
+
+impl SimulationClient {
+    pub fn new(descriptor: &ClientDescriptor, solver: job::Solver) -> Self {
+        let (stop_sender, stop_receiver) = mpsc::channel(1);
+        Self {
+            descriptor: StdMutex::new(descriptor.clone()),
+            status: Default::default(),
+            client_stats: Default::default(),
+            stop_sender,
+            stop_receiver: Mutex::new(stop_receiver),
+            last_job: StdMutex::new(None),
+            job_sender: solver.job_sender,
+            solution_receiver: Mutex::new(solver.solution_receiver),
+            ids: Default::default(),
+            health: Default::default(),
+            events: Default::default(),
+            notices: notices::Board::new(
+                notices::Board::DEFAULT_CAPACITY,
+                notices::Board::DEFAULT_DEDUP_WINDOW,
+            ),
+            submitted: Default::default(),
+            foreign_solutions: Default::default(),
+            hourly_shares: Default::default(),
+        }
+    }
+
+    fn target_from_config(config: &SimulationConfig) -> ii_bitcoin::Target {
+        match config
+            .difficulty
+            .unwrap_or(SimulationConfig::DEFAULT_DIFFICULTY)
+        {
+            0 => ii_bitcoin::Target::from([0xff; 32]),
+            difficulty => target_util::target_from_difficulty(difficulty),
+        }
+    }
+
+    fn descriptor(&self) -> ClientDescriptor {
+        self.descriptor
+            .lock()
+            .expect("BUG: cannot lock descriptor")
+            .clone()
+    }
+
+    /// Configuration of the simulation, changes are applied when the client is started again
+    pub fn config(&self) -> SimulationConfig {
+        self.descriptor().simulation
+    }
+
+    pub fn health(&self) -> health::Health {
+        self.health.health()
+    }
+
+    /// Returns recent advisory events in chronological order
+    pub fn events(&self) -> Vec<events::Record> {
+        self.events.snapshot()
+    }
+
+    /// Returns identifiers of the current run and session
+    pub fn context(&self) -> context::Context {
+        context::Context {
+            client_id: context::client_id(&self.to_string()),
+            connection_id: self.ids.connection_id(),
+            session_id: self.ids.session_id(),
+        }
+    }
+
+    pub fn status_document(&self) -> status::Document {
+        let difficulty =
+            target_util::difficulty_from_target(&Self::target_from_config(&self.config()));
+        let status = self.status.status();
+        status::Document {
+            generated_at: time::SystemTime::now(),
+            status: status.to_string(),
+            ready: status == sync::Status::Running,
+            injected_faults: vec![],
+            context: self.context(),
+            health: self.health(),
+            targets: status::Targets {
+                local_difficulty: difficulty,
+                pool_difficulty: difficulty,
+                startup_policy: false,
+            },
+            pool_messages: self.notices.snapshot(),
+            hourly_shares: self.hourly_shares.snapshot(time::SystemTime::now()),
+            retries: Default::default(),
+            negotiated: None,
+            channel: None,
+            dispatch_limit: None,
+            held_solutions: Default::default(),
+            pool_time_skew: None,
+            template_quality: Default::default(),
+            hashrate: None,
+            accounting: Default::default(),
+            shares: Default::default(),
+            filters: Default::default(),
+            suggested_difficulty: None,
+            restart_storm: None,
+            startup_budget: None,
+            connect_phases: Vec::new(),
+            bandwidth: Default::default(),
+            job_taps: vec![],
+            user_file: None,
+            last_error: None,
+            handshake_transcript: None,
+        }
+    }
+
+    pub fn submitted(&self) -> &stats::CounterUsize {
+        &self.submitted
+    }
+
+    pub fn foreign_solutions(&self) -> &stats::CounterUsize {
+        &self.foreign_solutions
+    }
+
+    pub fn last_job(&self) -> Option<Arc<Job>> {
+        self.last_job
+            .lock()
+            .expect("BUG: cannot lock last job")
+            .clone()
+    }
+
+    fn send_job(&self, job: Arc<Job>) {
+        self.last_job
+            .lock()
+            .expect("BUG: cannot lock last job")
+            .replace(job.clone());
+        self.job_sender.send(job);
+    }
+
+    /// Decide the acknowledgement of a solution the same way a pool does. Valid solutions are
+    /// rejected with the configured probability.
+    fn acknowledge(
+        &self,
+        solution: &work::Solution,
+        rng: &mut Rng,
+        reject_probability: f64,
+    ) -> Ack {
+        let job: &Job = solution.job();
+        let current_epoch = self.last_job().map(|last_job| last_job.epoch);
+        if current_epoch.map_or(false, |epoch| job.epoch < epoch) {
+            Ack::Stale
+        } else if !solution.hash().meets(solution.job_target()) {
+            Ack::Rejected(Ack::LOW_DIFFICULTY_SHARE)
+        } else if rng.next_f64() < reject_probability {
+            Ack::Rejected(Ack::SIMULATED_REJECT)
+        } else {
+            Ack::Accepted
+        }
+    }
+
+    async fn account_ack(&self, context: context::Context, solution: work::Solution, ack: Ack) {
+        let now = time::Instant::now();
+        let wall_time = time::SystemTime::now();
+        let meter = match ack {
+            Ack::Accepted => {
+                info!(
+                    "{} Simulation: accepted solution with nonce={:08x}",
+                    context,
+                    solution.nonce()
+                );
+                self.hourly_shares.account_accepted(
+                    target_util::difficulty_from_target(solution.job_target()),
+                    wall_time,
+                );
+                &self.client_stats.accepted
+            }
+            Ack::Rejected(code) => {
+                info!(
+                    "{} Simulation: rejected solution with nonce={:08x} ({})",
+                    context,
+                    solution.nonce(),
+                    code
+                );
+                if let Some(notice) =
+                    self.notices
+                        .post(notices::Source::SubmitSharesError, code, wall_time)
+                {
+                    self.events.push(context, events::Event::PoolNotice(notice));
+                }
+                self.hourly_shares.account_rejected(wall_time);
+                &self.client_stats.rejected
+            }
+            Ack::Stale => {
+                info!(
+                    "{} Simulation: stale solution with nonce={:08x}",
+                    context,
+                    solution.nonce()
+                );
+                self.hourly_shares.account_stale(wall_time);
+                &self.client_stats.stale
+            }
+        };
+        meter.account_solution(&solution.job_target(), now).await;
+    }
+
+    async fn process_solution(
+        self: &Arc<Self>,
+        context: context::Context,
+        solution: work::Solution,
+        rng: &mut Rng,
+        config: &SimulationConfig,
+    ) {
+        if let Err(mismatch) = solution.checked_job::<Job>(self.clone()) {
+            // The solution is dropped the same way as by the Stratum client
+            self.foreign_solutions.inc();
+            warn!(
+                "{} Simulation: dropping solution of a {}",
+                context, mismatch
+            );
+            return;
+        }
+        let ack = self.acknowledge(
+            &solution,
+            rng,
+            config.reject_probability.unwrap_or_default(),
+        );
+        self.submitted.inc();
+        match config.ack_latency {
+            Some(latency) if latency > 0 => {
+                // The acknowledgement is delivered later without blocking other solutions
+                let client = self.clone();
+                tokio::spawn(async move {
+                    delay_for(time::Duration::from_millis(latency)).await;
+                    client.account_ack(context, solution, ack).await;
+                });
+            }
+            _ => self.account_ack(context, solution, ack).await,
+        }
+    }
+
+    async fn main_loop(self: Arc<Self>) -> error::Result<()> {
+        let config = self.config();
+        let mut solution_receiver = self.solution_receiver.lock().await;
+        let mut generator = JobGenerator::new(&config);
+        // Acknowledgements use a separate sequence so that they don't affect generated jobs
+        let mut ack_rng = Rng::new(!config.seed.unwrap_or(SimulationConfig::DEFAULT_SEED));
+        let mut job_interval = tokio::time::interval(time::Duration::from_millis(
+            config
+                .job_interval
+                .unwrap_or(SimulationConfig::DEFAULT_JOB_INTERVAL),
+        ));
+
+        if self.ids.next_connection_id() > 1 {
+            self.hourly_shares
+                .account_reconnect(time::SystemTime::now());
+        }
+        self.ids.next_session_id();
+        let context = self.context();
+        info!(
+            "{} Simulation: session started (diff={})",
+            context,
+            target_util::difficulty_from_target(&generator.target)
+        );
+
+        while !self.status.is_shutting_down() {
+            select! {
+                _ = job_interval.tick().fuse() => {
+                    let job = Arc::new(generator.next(&self));
+                    self.send_job(job);
+                }
+                solution = solution_receiver.receive().fuse() => {
+                    match solution {
+                        Some(solution) => {
+                            self.process_solution(context, solution, &mut ack_rng, &config).await
+                        }
+                        None => {
+                            // TODO: initiate Destroying and remove error
+                            Err("Standard application shutdown")?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn run(self: Arc<Self>) {
+        if self.status.initiate_running() {
+            if let Err(_) = self.clone().main_loop().await {
+                self.status.initiate_failing();
+            }
+        }
+    }
+
+    async fn main_task(self: Arc<Self>) {
+        loop {
+            let mut stop_receiver = self.stop_receiver.lock().await;
+            select! {
+                _ = self.clone().run().fuse() => {}
+                _ = stop_receiver.next() => {}
+            }
+
+            // Invalidate current job to stop working on it
+            self.job_sender.invalidate();
+            // Solutions of the previous run would be reported as stale by the pool
+            self.solution_receiver.lock().await.flush();
+
+            if self.status.can_stop() {
+                // NOTE: it is not safe to add here any code!
+                break;
+            }
+            // Restarting
+        }
+    }
+}
+
+#[async_trait]
+impl node::Client for SimulationClient {
+    fn start(self: Arc<Self>) {
+        tokio::spawn(self.clone().main_task());
+    }
+
+    fn stop(&self) {
+        if let Err(e) = self.stop_sender.clone().try_send(()) {
+            assert!(
+                e.is_full(),
+                "BUG: Unexpected error in stop sender: {}",
+                e.to_string()
+            );
+        }
+    }
+
+    async fn get_last_job(&self) -> Option<Arc<dyn job::Bitcoin>> {
+        self.last_job().map(|job| job as Arc<dyn job::Bitcoin>)
+    }
+
+    fn change_connection_details(&self, descriptor: &ClientDescriptor) {
+        *self.descriptor.lock().expect("BUG: cannot lock descriptor") = descriptor.clone();
+    }
+
+    fn annotate_switch(&self, annotation: &switches::Annotation) {
+        self.events
+            .push(self.context(), events::Event::Switch(annotation.clone()));
+    }
+}
+
+impl fmt::Display for SimulationClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.descriptor().get_url(true, true, true))
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+use crate::hal;
+
+use bosminer_config::{ClientProtocol, ClientUserInfo};
+
+fn build_client(
+    config: SimulationConfig,
+) -> (Arc<SimulationClient>, mpsc::UnboundedSender<work::Solution>) {
+    let mut descriptor = ClientDescriptor::create(
+        "simulation://bench",
+        &ClientUserInfo::new("user", None),
+        true,
+    )
+    .expect("BUG: cannot create descriptor");
+    descriptor.simulation = config;
+    let (solution_sender, solution_receiver) = mpsc::unbounded();
+    let solver = job::Solver::new(Arc::new(work::EngineSender::new(None)), solution_receiver);
+    (
+        Arc::new(SimulationClient::new(&descriptor, solver)),
+        solution_sender,
+    )
+}
+
+#[derive(Debug)]
+struct TestSolution {
+    nonce: u32,
+    target: ii_bitcoin::Target,
+}
+
+impl hal::BackendSolution for TestSolution {
+    fn nonce(&self) -> u32 {
+        self.nonce
+    }
+
+    fn midstate_idx(&self) -> usize {
+        0
+    }
+
+    fn solution_idx(&self) -> usize {
+        0
+    }
+
+    fn target(&self) -> &ii_bitcoin::Target {
+        &self.target
+    }
+}
+
+fn build_solution(job: Arc<Job>, nonce: u32) -> work::Solution {
+    let midstate = work::Midstate {
+        version: job::Bitcoin::version(job.as_ref()),
+        state: Default::default(),
+    };
+    let time = job.time;
+    work::Solution::new(
+        work::Assignment::new(job, vec![midstate], time),
+        TestSolution {
+            nonce,
+            target: ii_bitcoin::Target::from([0xff; 32]),
+        },
+        None,
+    )
+}
+
+fn generate_jobs(client: &Arc<SimulationClient>, config: &SimulationConfig) -> Vec<Job> {
+    let mut generator = JobGenerator::new(config);
+    (0..6).map(|_| generator.next(client)).collect()
+}
+
+#[test]
+fn test_protocol() {
+    let (client, _) = build_client(Default::default());
+    assert_eq!(client.to_string(), "simulation://user@bench");
+    let descriptor = client.descriptor();
+    assert!(matches!(descriptor.protocol, ClientProtocol::Simulation));
+    assert_eq!(descriptor.protocol.to_string(), "Simulation");
+    assert_eq!(
+        client.status_document().targets.local_difficulty,
+        SimulationConfig::DEFAULT_DIFFICULTY
+    );
+}
+
+#[test]
+fn test_job_sequence() {
+    let (client, _) = build_client(Default::default());
+    let config = SimulationConfig {
+        seed: Some(7),
+        jobs_per_prev_hash: Some(3),
+        ..Default::default()
+    };
+    let jobs = generate_jobs(&client, &config);
+    let epochs: Vec<_> = jobs.iter().map(|job| job.epoch).collect();
+    assert_eq!(epochs, vec![0, 0, 0, 1, 1, 1]);
+    assert_eq!(jobs[0].prev_hash, jobs[2].prev_hash);
+    assert_ne!(jobs[2].prev_hash, jobs[3].prev_hash);
+    assert_ne!(jobs[0].merkle_root, jobs[1].merkle_root);
+
+    // The same seed always produces the same sequence
+    let summary = |jobs: Vec<Job>| -> Vec<_> {
+        jobs.into_iter()
+            .map(|job| (job.index, job.prev_hash, job.merkle_root, job.time))
+            .collect()
+    };
+    assert_eq!(
+        summary(generate_jobs(&client, &config)),
+        summary(generate_jobs(&client, &config))
+    );
+    let other_config = SimulationConfig {
+        seed: Some(8),
+        ..config.clone()
+    };
+    assert_ne!(
+        summary(generate_jobs(&client, &config)),
+        summary(generate_jobs(&client, &other_config))
+    );
+}
+
+#[test]
+fn test_acknowledgement() {
+    let (client, _) = build_client(SimulationConfig {
+        jobs_per_prev_hash: Some(2),
+        ..Default::default()
+    });
+    let config = client.config();
+    let mut generator = JobGenerator::new(&config);
+    let mut rng = Rng::new(0);
+    let old_job = Arc::new(generator.next(&client));
+    client.send_job(old_job.clone());
+
+    // A random hash doesn't meet the target of diff=512
+    let solution = build_solution(old_job.clone(), 0x12345678);
+    assert_eq!(
+        client.acknowledge(&solution, &mut rng, 0.0),
+        Ack::Rejected(Ack::LOW_DIFFICULTY_SHARE)
+    );
+
+    // Jobs with the previous block hash that has been replaced are stale
+    generator.next(&client);
+    client.send_job(Arc::new(generator.next(&client)));
+    let solution = build_solution(old_job, 0x12345678);
+    assert_eq!(client.acknowledge(&solution, &mut rng, 0.0), Ack::Stale);
+}
+
+/// Count rejects the client generates for `count` valid solutions
+fn expected_rejects(seed: u64, reject_probability: f64, count: usize) -> usize {
+    let mut rng = Rng::new(!seed);
+    (0..count)
+        .filter(|_| rng.next_f64() < reject_probability)
+        .count()
+}
+
+async fn acknowledged_solutions(client: &Arc<SimulationClient>) -> (u64, u64, u64) {
+    let stats = &client.client_stats;
+    (
+        stats.accepted.take_snapshot().await.solutions,
+        stats.rejected.take_snapshot().await.solutions,
+        stats.stale.take_snapshot().await.solutions,
+    )
+}
+
+/// Run the client against a solver that solves the last job `count` times and wait for all
+/// acknowledgements
+async fn run_solver_loop(config: SimulationConfig, count: usize) -> Arc<SimulationClient> {
+    let (client, solution_sender) = build_client(config);
+    assert!(client.status.initiate_starting());
+    node::Client::start(client.clone());
+
+    for nonce in 0..count {
+        let job = loop {
+            match client.last_job() {
+                Some(job) => break job,
+                None => delay_for(time::Duration::from_millis(1)).await,
+            }
+        };
+        solution_sender
+            .unbounded_send(build_solution(job, nonce as u32))
+            .expect("BUG: cannot send solution");
+    }
+    for _ in 0..1000 {
+        let (accepted, rejected, stale) = acknowledged_solutions(&client).await;
+        if (accepted + rejected + stale) as usize == count {
+            break;
+        }
+        delay_for(time::Duration::from_millis(1)).await;
+    }
+    node::Client::stop(client.as_ref());
+    client
+}
+
+#[tokio::test]
+async fn test_solver_loop() {
+    let config = SimulationConfig {
+        seed: Some(7),
+        job_interval: Some(1),
+        difficulty: Some(0),
+        jobs_per_prev_hash: Some(u64::max_value()),
+        reject_probability: Some(0.25),
+        ..Default::default()
+    };
+    let client = run_solver_loop(config.clone(), 100).await;
+    let expected_rejected = expected_rejects(7, 0.25, 100);
+    assert!(expected_rejected > 0 && expected_rejected < 100);
+    assert_eq!(
+        acknowledged_solutions(&client).await,
+        (100 - expected_rejected as u64, expected_rejected as u64, 0)
+    );
+    assert_eq!(*client.submitted().take_snapshot(), 100);
+    assert!(*client.client_stats.valid_jobs.take_snapshot() > 0);
+    // Distinct rejects are reported as pool notices
+    let notices = client.status_document().pool_messages;
+    assert_eq!(notices.len(), 1);
+    assert_eq!(notices[0].text, Ack::SIMULATED_REJECT);
+
+    // Delayed acknowledgements are accounted the same way
+    let client = run_solver_loop(
+        SimulationConfig {
+            ack_latency: Some(5),
+            ..config
+        },
+        100,
+    )
+    .await;
+    assert_eq!(
+        acknowledged_solutions(&client).await,
+        (100 - expected_rejected as u64, expected_rejected as u64, 0)
+    );
+}
+
+#[test]
+fn test_config_validation() {
+    assert!(SimulationConfig::default().validate().is_ok());
+    for config in vec![
+        SimulationConfig {
+            job_interval: Some(0),
+            ..Default::default()
+        },
+        SimulationConfig {
+            jobs_per_prev_hash: Some(0),
+            ..Default::default()
+        },
+        SimulationConfig {
+            reject_probability: Some(1.5),
+            ..Default::default()
+        },
+        SimulationConfig {
+            reject_probability: Some(std::f64::NAN),
+            ..Default::default()
+        },
+    ] {
+        assert!(config.validate().is_err());
+    }
+}
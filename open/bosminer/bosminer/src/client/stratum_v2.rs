@@ -21,10 +21,66 @@
 // contact us at opensource@braiins.com.
 
 // Sub-modules with client implementation
+pub mod accounting;
+pub mod annotation;
+pub mod bandwidth;
+pub mod capabilities;
+pub mod clock_skew;
+#[cfg(any(test, feature = "conformance"))]
+pub mod conformance;
+pub mod connect_timing;
+pub mod context;
+pub mod diagnostics;
+pub mod dispatch_limit;
+pub mod events;
+pub mod faults;
+pub mod filters;
+pub mod handover;
+pub mod hashrate;
+pub mod health;
+pub mod held;
+pub mod hourly;
+pub mod io_timeout;
+pub mod job_aliases;
+pub mod job_taps;
+pub mod notices;
+pub mod notifications;
+pub mod ordering;
+pub mod pipeline;
+pub mod probe;
+#[cfg(feature = "reject-injection")]
+pub mod reject_injector;
+pub mod reject_streak;
+pub mod replay;
+pub mod restart_storm;
+pub mod session;
+pub mod share_counts;
+pub mod share_log;
+pub mod spans;
+pub mod startup_budget;
+pub mod status;
+pub mod status_cache;
+pub mod suggestion;
+pub mod target_backfill;
 pub mod telemetry;
+pub mod template_quality;
+pub mod transcript;
+pub mod transmit;
+pub mod user_file;
+
+#[cfg(test)]
+mod test;
+
+pub use capabilities::{capabilities, CapabilityMatrix};
+
+use faults::Injection;
+use spans::Spans;
 
 use ii_logging::macros::*;
 
+use super::switches;
+use super::target_util;
+
 use crate::error;
 use crate::hal;
 use crate::job;
@@ -33,22 +89,33 @@ use crate::stats;
 use crate::sync;
 use crate::work;
 
-use failure::ResultExt;
+use failure::{Fail, ResultExt};
 
-use ii_bitcoin::HashTrait;
+use ii_bitcoin::{HashTrait, MeetsTarget};
 
-use bosminer_config::{ClientDescriptor, ClientProtocol};
+use bosminer_config::{
+    ClientDescriptor, ClientProtocol, FlushedJobSolutions, JobDispatchPolicy, MissingTargetPolicy,
+    NonceByteOrder, OutOfMaskVersions, ShareOrderingCheck, StratumV2AckSequencing,
+    StratumV2BandwidthLimit, StratumV2Config, StratumV2EarlyShare, StratumV2Handover,
+    StratumV2JobIdReuse, StratumV2RejectStreak, StratumV2RestartStorm, StratumV2StartupTarget,
+    StratumV2TransmitStall, StratumV2UserRedaction, SubmissionWindowPolicy, TargetApplication,
+    UnknownPoolFlags,
+};
 use bosminer_macros::ClientNode;
 
 use async_trait::async_trait;
 use futures::channel::mpsc;
+use futures::future::FusedFuture;
 use futures::lock::Mutex;
 use ii_async_compat::prelude::*;
 use ii_async_compat::select;
 
+use std::collections::hash_map::RandomState;
 use std::collections::VecDeque;
 use std::fmt;
+use std::hash::{BuildHasher, Hasher};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Mutex as StdMutex;
 use std::sync::{Arc, Weak};
 use std::time;
@@ -71,7 +138,7 @@ use std::collections::HashMap;
 // TODO: move it to the stratum crate
 const VERSION_MASK: u32 = 0x1fffe000;
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct ConnectionDetails {
     /// TODO temporary field that denotes the protocol, it will be replaced by a `Connector`
     /// object that will have the information about a specific protocol already built-in
@@ -79,6 +146,9 @@ pub struct ConnectionDetails {
     pub user: String,
     pub host: String,
     pub port: u16,
+    /// Shared by all readers of the configuration, it is replaced as a whole when the connection
+    /// details change
+    pub config: Arc<StratumV2Config>,
 }
 
 impl ConnectionDetails {
@@ -88,12 +158,114 @@ impl ConnectionDetails {
             user: descriptor.user.clone(),
             host: descriptor.host.clone(),
             port: descriptor.port(),
+            config: Arc::new(descriptor.stratum_v2.clone()),
+        }
+    }
+
+    /// Number of characters of the user shown with the `prefix` redaction
+    const REDACTED_USER_PREFIX_LENGTH: usize = 4;
+
+    /// User that identifies the client, the user read from the user file is replaced by the path
+    fn user_identity(&self) -> String {
+        match self.config.user_file.as_ref() {
+            Some(user_file) => format!("<{}>", user_file.display()),
+            None => self.user.clone(),
+        }
+    }
+
+    /// User suitable for logging, the user read from the user file is never displayed and the
+    /// configured user is masked according to `user_redaction`
+    fn display_user(&self) -> String {
+        if self.config.user_file.is_some() {
+            return self.user_identity();
+        }
+        match self.config.user_redaction {
+            None => self.user.clone(),
+            Some(StratumV2UserRedaction::Hash) => {
+                let hash = ii_bitcoin::DHash::hash(self.user.as_bytes());
+                format!("<{}>", hex::encode(&hash[..4]))
+            }
+            Some(StratumV2UserRedaction::Prefix) => {
+                let prefix: String = self
+                    .user
+                    .chars()
+                    .take(Self::REDACTED_USER_PREFIX_LENGTH)
+                    .collect();
+                format!("{}***", prefix)
+            }
         }
     }
 
+    /// Host and port the client connects to
     fn get_host_and_port(&self) -> String {
         format!("{}:{}", self.host, self.port)
     }
+
+    /// Key of the pool endpoint that a handed over state belongs to (see `handover`)
+    fn endpoint_key(&self) -> String {
+        self.get_host_and_port()
+    }
+
+    fn handover_max_age(&self) -> time::Duration {
+        time::Duration::from_secs(
+            self.config
+                .handover
+                .as_ref()
+                .and_then(|handover| handover.max_age)
+                .unwrap_or(StratumV2Handover::DEFAULT_MAX_AGE),
+        )
+    }
+
+    /// Timeout of reads and writes of the connection (see `io_timeout`)
+    fn io_timeout(&self) -> Option<time::Duration> {
+        self.config.io_timeout.map(time::Duration::from_secs)
+    }
+
+    /// Limits of sending and of receiving frames
+    fn bandwidth_limits(&self) -> (Option<bandwidth::Limit>, Option<bandwidth::Limit>) {
+        let bandwidth_limit = match self.config.bandwidth_limit.as_ref() {
+            Some(bandwidth_limit) => bandwidth_limit,
+            None => return (None, None),
+        };
+        let burst = bandwidth_limit
+            .burst
+            .unwrap_or(StratumV2BandwidthLimit::DEFAULT_BURST);
+        let limit = |rate: Option<u64>| rate.map(|rate| bandwidth::Limit { rate, burst });
+        (
+            limit(bandwidth_limit.send_rate),
+            limit(bandwidth_limit.receive_rate),
+        )
+    }
+
+    /// Host advertised to the pool (it may differ from the host the client connects to)
+    fn endpoint_host(&self) -> &str {
+        self.config.endpoint_host.as_deref().unwrap_or(&self.host)
+    }
+
+    /// Port advertised to the pool (it may differ from the port the client connects to)
+    fn endpoint_port(&self) -> u16 {
+        self.config.endpoint_port.unwrap_or(self.port)
+    }
+
+    /// The connection is secured with the noise protocol
+    fn is_encrypted(&self) -> bool {
+        match self.protocol {
+            ClientProtocol::StratumV2(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for ConnectionDetails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionDetails")
+            .field("protocol", &self.protocol)
+            .field("user", &self.display_user())
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("config", &self.config)
+            .finish()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -102,11 +274,17 @@ pub struct StratumJob {
     id: u32,
     channel_id: u32,
     version: u32,
+    version_mask: u32,
     prev_hash: ii_bitcoin::DHash,
     merkle_root: ii_bitcoin::DHash,
     time: u32,
+    /// Maximal ntime of solutions, ntime is never rolled beyond it
+    max_time: u32,
     bits: u32,
+    /// Target used locally for solving the job
     target: ii_bitcoin::Target,
+    /// Target requested by the pool, only solutions that meet this target are submitted
+    pool_target: ii_bitcoin::Target,
 }
 
 impl StratumJob {
@@ -115,21 +293,38 @@ impl StratumJob {
         job_msg: &NewMiningJob,
         prevhash_msg: &SetNewPrevHash,
         target: ii_bitcoin::Target,
+        pool_target: ii_bitcoin::Target,
+        session: &session::State,
+        max_ntime_roll: u32,
     ) -> Self {
         Self {
             client: Arc::downgrade(&client),
             id: job_msg.job_id,
-            channel_id: job_msg.channel_id,
+            // The job may have been sent to a group channel, shares are always submitted to the
+            // channel of the session
+            channel_id: session.channel.channel_id,
             version: job_msg.version,
+            version_mask: session.version_mask,
             prev_hash: ii_bitcoin::DHash::from_slice(prevhash_msg.prev_hash.as_ref())
                 .expect("BUG: Stratum: incorrect size of prev hash"),
             merkle_root: ii_bitcoin::DHash::from_slice(job_msg.merkle_root.as_ref())
                 .expect("BUG: Stratum: incorrect size of merkle root"),
             time: prevhash_msg.min_ntime,
+            max_time: prevhash_msg.min_ntime.saturating_add(max_ntime_roll),
             bits: prevhash_msg.nbits,
             target,
+            pool_target,
         }
     }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Restrict the bits of the version that may be rolled by the backend to `mask`
+    pub fn restrict_version_mask(&mut self, mask: u32) {
+        self.version_mask &= mask;
+    }
 }
 
 impl job::Bitcoin for StratumJob {
@@ -142,7 +337,7 @@ impl job::Bitcoin for StratumJob {
     }
 
     fn version_mask(&self) -> u32 {
-        VERSION_MASK
+        self.version_mask
     }
 
     fn previous_hash(&self) -> &ii_bitcoin::DHash {
@@ -157,6 +352,10 @@ impl job::Bitcoin for StratumJob {
         self.time
     }
 
+    fn max_time(&self) -> u32 {
+        self.max_time
+    }
+
     fn bits(&self) -> u32 {
         self.bits
     }
@@ -170,6 +369,8 @@ impl job::Bitcoin for StratumJob {
         //  its presence in the registry. The inequality below was possible in the previous
         //  iteration of the protocol
         // self.block_height >= self.current_block_height.load(Ordering::Relaxed)
+        // NOTE: solutions of jobs flushed by a new prevhash must reach the solution handler,
+        //  they are handled by the configured policy (see `FlushedJobSolutions`)
         true
     }
 }
@@ -180,184 +381,1294 @@ impl job::Bitcoin for StratumJob {
 /// up with the protocol.
 type SolutionQueue = Mutex<VecDeque<(work::Solution, u32)>>;
 
+/// Final state of a submitted solution that determines the meter it is accounted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Accepted,
+    Rejected,
+    /// The solution hasn't been acknowledged before the connection has been closed
+    Stale,
+}
+
+/// Snapshot of a submitted solution that hasn't been acknowledged by the pool yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingSubmission {
+    pub seq_num: u32,
+    /// Time elapsed since the solution has been found
+    pub age: time::Duration,
+    /// Difficulty of the target requested by the pool for the job
+    pub difficulty: usize,
+}
+
 /// Helper task for `StratumClient` that implements Stratum V2 visitor which processes incoming
 /// messages from remote server.
 struct StratumEventHandler {
     client: Arc<StratumClient>,
     all_jobs: HashMap<u32, NewMiningJob>,
     current_prevhash_msg: Option<SetNewPrevHash>,
+    /// Job that has been dispatched most recently
+    active_job_msg: Option<NewMiningJob>,
     /// Mining target for the next job that is to be solved
     current_target: ii_bitcoin::Target,
+    /// Target requested by the pool for the next job (before any local adjustments)
+    current_pool_target: ii_bitcoin::Target,
+    /// State negotiated with the pool for this session
+    session: Arc<session::State>,
+    context: context::Context,
+    /// Target derived from the nominal hashrate that is used until the deadline, see
+    /// `StratumV2Config` for details
+    startup_target: Option<(ii_bitcoin::Target, time::Instant)>,
+    /// Error detected while processing a message that requires restarting the connection
+    fatal_error: Option<error::Error>,
+    /// Time when the frame that is being processed has been received
+    frame_received: Option<time::Instant>,
+    /// Number of future jobs received since the last `SetNewPrevHash` and the time when the
+    /// oldest of them has been received
+    pending_future_jobs: Option<(usize, time::Instant)>,
+    /// Time of the last warning about pending future jobs
+    pending_jobs_warned: Option<time::Instant>,
+    /// Future jobs in the order they have been received along with the time of reception, the
+    /// jobs that have been activated or replaced are skipped
+    future_jobs_received: VecDeque<(u32, time::Instant)>,
+    /// Strict verification of acknowledgement ordering (used only when configured)
+    ack_sequencer: ordering::Sequencer,
+    /// Job update held back by the job dispatch limit, it is replaced by newer job updates
+    held_job_msg: Option<NewMiningJob>,
+    /// Number of acknowledgements received in this session while no share has been submitted
+    unexpected_acks: usize,
+    /// Correction of the target until the pool sends `SetTarget` in this session
+    target_backfill: target_backfill::Backfill,
+    /// Shares consecutively rejected for the same reason in this session
+    reject_streak: reject_streak::Detector,
+    /// Prevhash that references a job that hasn't been received yet and the deadline for
+    /// receiving the job. Only the most recent such prevhash is kept.
+    orphan_prevhash: Option<(SetNewPrevHash, time::Instant)>,
+    /// Time when the first job of this session has been received
+    first_job_received: Option<time::Instant>,
+    /// The pool has sent `SetTarget` in this session
+    set_target_received: bool,
+    /// The warning about missing `SetTarget` has been logged in this session
+    missing_target_warned: bool,
+    /// Time to the first dispatched job of the connection attempt that has opened this session
+    first_job: Option<connect_timing::Pending>,
+    /// The pool has provided a usable target in this session or the missing target has been
+    /// handled by the configured policy
+    target_resolved: bool,
+    /// Job waiting for a target (whether it has been activated by a new prevhash) and the
+    /// deadline of the wait
+    target_wait: Option<(NewMiningJob, bool, time::Instant)>,
 }
 
 impl StratumEventHandler {
-    pub fn new(client: Arc<StratumClient>, current_target: ii_bitcoin::Target) -> Self {
-        Self {
+    /// Time to wait for a job referenced by a prevhash that has arrived ahead of the job
+    const ORPHAN_PREVHASH_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+    pub fn new(
+        client: Arc<StratumClient>,
+        session: Arc<session::State>,
+        context: context::Context,
+    ) -> Self {
+        let init_target = session.init_target;
+        // The state of the job dispatch limit persists across sessions
+        let dispatch_limit = client.config().job_dispatch_limit.clone();
+        client
+            .dispatch_limiter
+            .configure(dispatch_limit, time::Instant::now());
+        let mut handler = Self {
             client,
+            context,
             all_jobs: Default::default(),
             current_prevhash_msg: None,
-            current_target,
+            active_job_msg: None,
+            current_target: init_target,
+            current_pool_target: init_target,
+            session,
+            startup_target: None,
+            fatal_error: None,
+            frame_received: None,
+            pending_future_jobs: None,
+            pending_jobs_warned: None,
+            future_jobs_received: VecDeque::new(),
+            ack_sequencer: Default::default(),
+            held_job_msg: None,
+            unexpected_acks: 0,
+            target_backfill: Default::default(),
+            reject_streak: Default::default(),
+            orphan_prevhash: None,
+            first_job_received: None,
+            set_target_received: false,
+            missing_target_warned: false,
+            first_job: None,
+            target_resolved: session.init_target_provided,
+            target_wait: None,
+        };
+        handler.startup_target = handler.new_startup_target();
+        let remembered_difficulty = handler.client.targets().pool_difficulty;
+        handler.apply_target(init_target);
+        handler.check_remembered_target(remembered_difficulty);
+        handler.check_early_share_target();
+        // Job IDs and share sequence numbers are valid only within a single session
+        handler.client.job_aliases.clear();
+        handler.client.share_ordering.reset();
+        handler.client.pipeline.clear();
+        handler
+    }
+
+    /// Determine the starting target from the nominal hashrate when the startup target policy is
+    /// configured
+    fn new_startup_target(&self) -> Option<(ii_bitcoin::Target, time::Instant)> {
+        let config = self.client.config();
+        if config.max_difficulty.is_some() || config.early_share.is_some() {
+            // Explicit difficulty settings disable the policy, the early share accommodation
+            // must not delay the first share by a harder target
+            return None;
+        }
+        if self
+            .client
+            .effective_suggestion(time::Instant::now())
+            .is_some()
+        {
+            // The external difficulty manager knows the desired share cadence better than the
+            // nominal hashrate
+            return None;
+        }
+        config.startup_target.as_ref().map(|startup_target| {
+            let target = target_util::target_from_hashrate(
+                ii_bitcoin::HashesUnit::TeraHashes(startup_target.nominal_hashrate),
+                startup_target
+                    .shares_per_minute
+                    .unwrap_or(StratumV2StartupTarget::DEFAULT_SHARES_PER_MINUTE),
+            );
+            let window = time::Duration::from_secs(
+                startup_target
+                    .window
+                    .unwrap_or(StratumV2StartupTarget::DEFAULT_WINDOW),
+            );
+            (target, time::Instant::now() + window)
+        })
+    }
+
+    /// Warn when the pool has opened the channel with a harder target than the one requested for
+    /// the early share, the first share may miss the deadline of the pool then
+    fn check_early_share_target(&self) {
+        let early_share = match self.client.early_share() {
+            Some(early_share) => early_share,
+            None => return,
+        };
+        let requested_target = StratumClient::early_share_target(&early_share);
+        if !target_util::is_harder(&self.current_pool_target, &requested_target) {
+            return;
+        }
+        let shares_per_minute = target_util::expected_shares_per_minute(
+            ii_bitcoin::HashesUnit::TeraHashes(early_share.nominal_hashrate),
+            &self.current_pool_target,
+        );
+        warn!(
+            "{} Stratum: pool has opened the channel with diff={} instead of requested diff={}, the first share is expected after {:.0}s (deadline {}s)",
+            self.context,
+            target_util::difficulty_from_target(&self.current_pool_target),
+            target_util::difficulty_from_target(&requested_target),
+            60.0 / shares_per_minute,
+            early_share.deadline
+        );
+    }
+
+    /// Report the target echoed at channel open when it is harder than the target of the previous
+    /// session with `remembered_difficulty` (the echoed target has been adopted already)
+    fn check_remembered_target(&self, remembered_difficulty: usize) {
+        let difficulty = target_util::difficulty_from_target(&self.current_pool_target);
+        if remembered_difficulty == 0 || difficulty <= remembered_difficulty {
+            return;
+        }
+        let correction = target_backfill::Correction {
+            reason: target_backfill::Reason::ChannelOpen,
+            from_difficulty: remembered_difficulty,
+            to_difficulty: difficulty,
+        };
+        info!("{} Stratum: target corrected: {}", self.context, correction);
+        self.client
+            .push_event(self.context, events::Event::TargetCorrected(correction));
+    }
+
+    /// Harden the target by one band when shares of the current target are rejected for low
+    /// difficulty while the pool hasn't set the target in this session yet. Rejects of shares
+    /// solved with an easier target than the current one predate the last correction and are
+    /// ignored.
+    async fn backfill_target(&mut self, code: &str, share_difficulty: usize) {
+        let from_difficulty = target_util::difficulty_from_target(&self.current_pool_target);
+        if share_difficulty < from_difficulty {
+            return;
+        }
+        let to_difficulty = match self.target_backfill.rejected(code, from_difficulty) {
+            Some(difficulty) => difficulty,
+            None => return,
+        };
+        let correction = target_backfill::Correction {
+            reason: target_backfill::Reason::LowDifficultyRejects,
+            from_difficulty,
+            to_difficulty,
+        };
+        warn!(
+            "{} Stratum: pool hasn't set the target, target corrected: {}",
+            self.context, correction
+        );
+        self.client
+            .push_event(self.context, events::Event::TargetCorrected(correction));
+        self.apply_target(target_util::target_from_difficulty(to_difficulty));
+        self.apply_target_to_active_job().await;
+    }
+
+    /// Hand the control over the target to the pool, the caller is responsible for applying
+    /// the pool target
+    fn end_startup_target(&mut self, reason: &str) {
+        if self.startup_target.take().is_some() {
+            info!(
+                "{} Stratum: startup target policy ended ({})",
+                self.context, reason
+            );
+        }
+    }
+
+    /// Determine the target used locally for solving jobs from the target requested by the pool.
+    /// This is the only place where the target policy is enforced, see `StratumV2Config` for the
+    /// order of precedence of all adjustments.
+    fn apply_target(&mut self, pool_target: ii_bitcoin::Target) {
+        let config = self.client.config();
+        let mut target = pool_target;
+
+        // The starting target is never easier than the target requested by the pool
+        if let Some((startup_target, _)) = self.startup_target {
+            target = target_util::harder_of(target, startup_target);
+        }
+
+        let mut clamped = false;
+        if let Some(max_difficulty) = config.max_difficulty {
+            let min_target = target_util::target_from_difficulty(max_difficulty);
+            if target_util::is_harder(&target, &min_target) {
+                target = min_target;
+                clamped = true;
+            }
+        }
+        if let Some(min_difficulty) = config.min_difficulty {
+            let max_target = target_util::target_from_difficulty(min_difficulty);
+            if target_util::is_easier(&target, &max_target) {
+                target = max_target;
+                clamped = true;
+            }
+        }
+
+        if clamped {
+            let requested_difficulty = target_util::difficulty_from_target(&pool_target);
+            let applied_difficulty = target_util::difficulty_from_target(&target);
+            warn!(
+                "{} Stratum: pool requested diff={} which is outside of configured range, using diff={}",
+                self.context,
+                requested_difficulty,
+                applied_difficulty
+            );
+            self.client.clamped_targets.inc();
+            if self
+                .client
+                .health
+                .raise(health::DegradedReason::TargetClamped)
+            {
+                self.client.push_event(
+                    self.context,
+                    events::Event::TargetClamped {
+                        requested_difficulty,
+                        applied_difficulty,
+                    },
+                );
+            }
+        } else if self
+            .client
+            .health
+            .clear(health::DegradedReason::TargetClamped)
+        {
+            info!(
+                "{} Stratum: pool target is within configured range of difficulty again",
+                self.context
+            );
         }
+
+        self.current_pool_target = pool_target;
+        *self
+            .client
+            .targets
+            .lock()
+            .expect("BUG: cannot lock targets") = status::Targets {
+            local_difficulty: target_util::difficulty_from_target(&target),
+            pool_difficulty: target_util::difficulty_from_target(&pool_target),
+            startup_policy: self.startup_target.is_some(),
+        };
+        self.client.set_current_target(target);
+        self.client
+            .hashrate
+            .lock()
+            .expect("BUG: cannot lock hashrate estimator")
+            .set_difficulty(
+                target_util::difficulty_from_target(&target) as f64,
+                time::Instant::now(),
+            );
+        self.current_target = target;
     }
 
     /// Convert new mining job message into StratumJob and send it down the line for solving.
     ///
     /// * `job_msg` - job message used as a base for the StratumJob
-    async fn update_job(&mut self, job_msg: &NewMiningJob) {
-        let job = Arc::new(StratumJob::new(
+    /// * `new_prevhash` - the job has been activated by a new prevhash, work of all preceding jobs
+    ///   is worthless and the work pipeline is asked to flush it
+    async fn update_job(&mut self, job_msg: &NewMiningJob, new_prevhash: bool) {
+        if self.wait_for_target(job_msg, new_prevhash) {
+            return;
+        }
+        if let Some((_, deadline)) = self.startup_target {
+            if time::Instant::now() >= deadline {
+                self.end_startup_target("startup window elapsed");
+                self.apply_target(self.current_pool_target);
+            }
+        }
+        // The configuration is read once, nothing on the dispatch path copies it
+        let config = self.client.config();
+        if !self.may_dispatch_jobs(&config) {
+            info!(
+                "{} Stratum: client is {}, job {} is not dispatched",
+                self.context,
+                self.client.status.status(),
+                job_msg.job_id
+            );
+            return;
+        }
+        let job = StratumJob::new(
             self.client.clone(),
             job_msg,
             self.current_prevhash_msg
                 .as_ref()
                 .expect("TODO: no prevhash"),
             self.current_target,
-        ));
-        self.client.update_last_job(job.clone()).await;
-        self.client.job_sender.lock().await.send(job);
-    }
-
-    fn update_target(&mut self, value: Uint256Bytes) {
-        let new_target: ii_bitcoin::Target = value.into();
-        info!(
-            "Stratum: changing target to {} diff={}",
-            new_target,
-            new_target.get_difficulty()
+            self.current_pool_target,
+            &self.session,
+            config
+                .max_ntime_roll
+                .unwrap_or(StratumV2Config::DEFAULT_MAX_NTIME_ROLL),
         );
-        self.current_target = new_target;
+        let mut job = match self.client.intercept_job(job) {
+            Some(job) => job,
+            None => {
+                info!(
+                    "{} Stratum: job {} has been vetoed by the job interceptor",
+                    self.context, job_msg.job_id
+                );
+                return;
+            }
+        };
+        if self
+            .client
+            .inject_fault(self.context, |faults, now| faults.corrupt_job_target(now))
+        {
+            warn!(
+                "{} Stratum: DRILL: job {} is dispatched with a corrupted target",
+                self.context, job_msg.job_id
+            );
+            job.target = target_util::target_from_difficulty(1);
+        }
+        let job = Arc::new(job);
+        // The path from the received frame to the backend takes only std mutexes which no task
+        // holds across an await point (the configuration and the job interceptor are shared
+        // with API readers that hold them only to copy the value out). The job sender is locked
+        // last and the invalidation is sent under the same lock so that it always follows its
+        // job.
+        {
+            let job_sender = self.client.lock_job_sender();
+            let generation = job_sender.send(job.clone());
+            if let (true, Some(generation)) = (new_prevhash, generation) {
+                // The invalidation always follows its job so that the pipeline is never left
+                // without work
+                let job: Arc<dyn job::Bitcoin> = job.clone();
+                let discarded = job_sender.invalidate_preceding(&job, generation);
+                self.client.invalidations.inc();
+                if let Some(discarded) = discarded {
+                    self.client.discarded_work.add(discarded);
+                }
+            }
+        }
+        self.client.job_taps.send(&job);
+        self.client.job_dispatched();
+        if let Some(first_job) = self.first_job.take() {
+            self.client
+                .lock_connect_history()
+                .first_job(first_job.finish(connect_timing::Outcome::Ok));
+            self.client.finish_startup_budget();
+        }
+        if let Some(frame_received) = self.frame_received.take() {
+            let latency = frame_received.elapsed();
+            trace!(
+                "{} Stratum: job {} dispatched {:?} after the frame has been received",
+                self.context,
+                job_msg.job_id,
+                latency
+            );
+            self.client
+                .dispatch_latency
+                .store(latency.as_micros() as u64 + 1, Ordering::Relaxed);
+        }
+        self.client.update_last_job(job);
+        self.active_job_msg.replace(job_msg.clone());
     }
 
-    async fn process_accepted_shares(&self, success_msg: &SubmitSharesSuccess) {
-        let now = std::time::Instant::now();
-        while let Some((solution, seq_num)) = self.client.solutions.lock().await.pop_front() {
-            info!(
-                "Stratum: accepted solution #{} with nonce={:08x}",
-                seq_num,
-                solution.nonce()
+    /// Dispatch a job update that keeps the previous block hash unless the job dispatch limit has
+    /// been reached. Such job update is held back until the limit allows dispatching it, a newer
+    /// job update replaces it in the meantime.
+    async fn update_job_limited(&mut self, job_msg: &NewMiningJob) {
+        let now = time::Instant::now();
+        if let Some(engagement) = self.client.dispatch_limiter.job_update(now) {
+            warn!(
+                "{} Stratum: job dispatch limit suppressed {} of {} job updates during the last hour, the pool sends jobs much more often than the limit allows",
+                self.context,
+                engagement.suppressed,
+                engagement.updates
+            );
+            self.client.push_event(
+                self.context,
+                events::Event::DispatchLimitEngaged(engagement),
+            );
+        }
+        self.discard_held_job();
+        if self.client.dispatch_limiter.try_acquire(now) {
+            self.update_job(job_msg, false).await;
+        } else {
+            trace!(
+                "{} Stratum: job dispatch limit reached, holding job {}",
+                self.context,
+                job_msg.job_id
             );
+            self.held_job_msg = Some(job_msg.clone());
+        }
+    }
+
+    /// The held job update has been superseded and it is never going to be dispatched
+    fn discard_held_job(&mut self) {
+        if self.held_job_msg.take().is_some() {
+            self.client.dispatch_limiter.suppressed();
+            self.client.suppressed_dispatches.inc();
+        }
+    }
+
+    /// Returns time remaining until the held job update may be dispatched
+    fn held_job_delay(&self) -> Option<time::Duration> {
+        self.held_job_msg.as_ref()?;
+        Some(
             self.client
-                .client_stats
-                .accepted
-                .account_solution(&solution.job_target(), now)
-                .await;
-            if success_msg.last_seq_num == seq_num {
-                // all accepted solutions have been found
-                return;
+                .dispatch_limiter
+                .until_available(time::Instant::now()),
+        )
+    }
+
+    /// Dispatch the held job update when the job dispatch limit allows it
+    async fn dispatch_held_job(&mut self) {
+        if let Some(job_msg) = self.held_job_msg.take() {
+            if self
+                .client
+                .dispatch_limiter
+                .try_acquire(time::Instant::now())
+            {
+                self.update_job(&job_msg, false).await;
+            } else {
+                self.held_job_msg = Some(job_msg);
             }
         }
+    }
+
+    /// Find a job with the same payload as `job_msg`. Immediate jobs are compared with the active
+    /// job, future jobs with already stored future jobs.
+    fn find_duplicate_job(&self, job_msg: &NewMiningJob) -> Option<u32> {
+        let is_duplicate = |other: &NewMiningJob| {
+            other.job_id != job_msg.job_id
+                && other.merkle_root == job_msg.merkle_root
+                && other.version == job_msg.version
+        };
+        if job_msg.future_job {
+            self.all_jobs
+                .values()
+                .find(|other| other.future_job && is_duplicate(other))
+                .map(|other| other.job_id)
+        } else {
+            self.current_prevhash_msg.as_ref()?;
+            self.active_job_msg
+                .as_ref()
+                .filter(|other| is_duplicate(other))
+                .map(|other| other.job_id)
+        }
+    }
+
+    /// Detect a job that reuses the ID of a known job with different content and apply the
+    /// configured policy. Returns false when the job must not be stored.
+    fn check_job_id_reuse(&mut self, job_msg: &NewMiningJob) -> bool {
+        let known = match self.all_jobs.get(&job_msg.job_id) {
+            Some(known) => known,
+            None => return true,
+        };
+        if known.merkle_root == job_msg.merkle_root && known.version == job_msg.version {
+            return true;
+        }
+        self.client.reused_job_ids.inc();
+        let policy = self.client.config().job_id_reuse.unwrap_or_default();
         warn!(
-            "Stratum: last accepted solution #{} hasn't been found!",
-            success_msg.last_seq_num
+            "{} Stratum: pool reused job ID {} for a different job ({:?})",
+            self.context, job_msg.job_id, policy
         );
+        match policy {
+            StratumV2JobIdReuse::Overwrite => true,
+            StratumV2JobIdReuse::Ignore => false,
+            StratumV2JobIdReuse::Reconnect => {
+                self.fatal_error
+                    .get_or_insert(error::Client::JobIdReused(job_msg.job_id).into());
+                false
+            }
+        }
     }
 
-    async fn process_rejected_shares(&self, error_msg: &SubmitSharesError) {
-        let now = std::time::Instant::now();
-        while let Some((solution, seq_num)) = self.client.solutions.lock().await.pop_front() {
-            if error_msg.seq_num == seq_num {
+    /// New jobs are not dispatched when the client is going to be stopped or restarted unless
+    /// configured otherwise. Acknowledgements of submitted shares are always processed.
+    fn may_dispatch_jobs(&self, config: &StratumV2Config) -> bool {
+        match self.client.status.status() {
+            sync::Status::Stopping | sync::Status::Restarting => {
+                config.dispatch_jobs_when_stopping.unwrap_or(false)
+            }
+            _ => true,
+        }
+    }
+
+    /// Dispatch the active job again with the new target when the target is to be applied
+    /// immediately
+    async fn apply_target_to_active_job(&mut self) {
+        // The held job update is going to be dispatched with the new target anyway
+        if self.client.target_application() == TargetApplication::Immediate
+            && self.held_job_msg.is_none()
+        {
+            if let Some(job_msg) = self.active_job_msg.clone() {
                 info!(
-                    "Stratum: rejected solution #{} with nonce={:08x}!",
-                    seq_num,
-                    solution.nonce()
+                    "{} Stratum: applying new target to active job {}",
+                    self.context, job_msg.job_id
                 );
-                self.client
-                    .client_stats
-                    .rejected
-                    .account_solution(&solution.job_target(), now)
-                    .await;
-                // the rejected solution has been found
-                return;
-            } else {
-                // TODO: this is currently not according to stratum V2 specification
-                // preceding solutions are treated as accepted
+                self.update_job_limited(&job_msg).await;
+            }
+        }
+    }
+
+    /// Apply the missing target policy to a job that is about to be dispatched while the pool
+    /// hasn't provided any usable target. Returns true when the job waits for a target.
+    fn wait_for_target(&mut self, job_msg: &NewMiningJob, new_prevhash: bool) -> bool {
+        if self.target_resolved {
+            return false;
+        }
+        // A newer job replaces the waiting one, the wait is not extended
+        if let Some((_, activated, deadline)) = self.target_wait.take() {
+            self.target_wait = Some((job_msg.clone(), activated || new_prevhash, deadline));
+            return true;
+        }
+        let config = self.client.config();
+        match config.missing_target_policy.unwrap_or_default() {
+            MissingTargetPolicy::Fallback => {
                 info!(
-                    "Stratum: accepted solution #{} with nonce={}",
-                    seq_num,
-                    solution.nonce()
-                );
-                self.client
-                    .client_stats
-                    .accepted
-                    .account_solution(&solution.job_target(), now)
-                    .await;
-                warn!(
-                    "Stratum: the solution #{} precedes rejected solution #{}!",
-                    seq_num, error_msg.seq_num
+                    "{} Stratum: no target known at job {}, mining at diff={} (fallback)",
+                    self.context,
+                    job_msg.job_id,
+                    target_util::difficulty_from_target(&self.current_pool_target)
                 );
-                warn!(
-                    "Stratum: the solution #{} is treated as an accepted one",
-                    seq_num
+                self.target_resolved = true;
+                false
+            }
+            MissingTargetPolicy::Wait => {
+                let wait = config
+                    .missing_target_wait
+                    .unwrap_or(StratumV2Config::DEFAULT_MISSING_TARGET_WAIT);
+                info!(
+                    "{} Stratum: no target known at job {}, waiting up to {}ms for the pool to set it",
+                    self.context, job_msg.job_id, wait
                 );
+                self.target_wait = Some((
+                    job_msg.clone(),
+                    new_prevhash,
+                    time::Instant::now() + time::Duration::from_millis(wait),
+                ));
+                true
             }
         }
-        warn!(
-            "Stratum: rejected solution #{} hasn't been found!",
-            error_msg.seq_num
-        );
     }
-}
-
-#[async_trait]
-impl Handler for StratumEventHandler {
-    // The rules for prevhash/mining job pairing are (currently) as follows:
-    //  - when mining job comes
-    //      - store it (by id)
-    //      - start mining it if it doesn't have the future_job flag set
-    //  - when prevhash message comes
-    //      - replace it
-    //      - start mining the job it references (by job id)
-    //      - flush all other jobs
 
-    async fn visit_new_mining_job(&mut self, _header: &Header, job_msg: &NewMiningJob) {
-        // all jobs since last `prevmsg` have to be stored in job table
-        self.all_jobs.insert(job_msg.job_id, job_msg.clone());
-        // TODO: close connection when maximal capacity of `all_jobs` has been reached
+    /// Returns time remaining until the job waiting for a target falls back to difficulty 1
+    fn target_wait_delay(&self) -> Option<time::Duration> {
+        self.target_wait
+            .as_ref()
+            .map(|(_, _, deadline)| deadline.saturating_duration_since(time::Instant::now()))
+    }
 
-        // When not marked as future job, we can start mining on it right away
-        // TODO see the _channels variant when consolidating this version of the client.
-        //  Transform the documentation from there too. Currently, this workaround with
-        //  .is_some() prevents a problem when a server indicates a new job however it doesn't
-        //  send the new prevhash ahead of this job. This scenario is still yet to be investigated
-        //  as it should prevented typically on the V2->V1->upstream translation proxies. These
-        //  proxies should guarantee that no such case like a job without a prevhash would exist.
-        if !job_msg.future_job && self.current_prevhash_msg.is_some() {
-            self.update_job(job_msg).await;
+    /// The pool hasn't set any target in time, dispatch the waiting job with difficulty 1
+    async fn target_wait_expired(&mut self) {
+        if let Some((job_msg, new_prevhash, _)) = self.target_wait.take() {
+            info!(
+                "{} Stratum: no target received in time, mining job {} at diff={} (fallback)",
+                self.context,
+                job_msg.job_id,
+                target_util::difficulty_from_target(&self.current_pool_target)
+            );
+            self.target_resolved = true;
+            self.update_job(&job_msg, new_prevhash).await;
         }
     }
 
-    async fn visit_set_new_prev_hash(&mut self, _header: &Header, prevhash_msg: &SetNewPrevHash) {
-        self.current_prevhash_msg.replace(prevhash_msg.clone());
-
-        // find the future job with ID referenced in prevhash_msg
-        let (_, mut future_job_msg) = self
-            .all_jobs
-            .remove_entry(&prevhash_msg.job_id)
-            .expect("TODO: requested job ID not found");
-
-        // remove all other jobs (they are now invalid)
-        self.all_jobs.retain(|_, _| true);
-        // turn the job into an immediate job
-        future_job_msg.future_job = false;
-        // reinsert the job
-        self.all_jobs
-            .insert(future_job_msg.job_id, future_job_msg.clone());
-
-        // and start immediately solving it
-        self.update_job(&future_job_msg).await;
+    /// Returns false when the target has been ignored
+    fn update_target(&mut self, value: Uint256Bytes) -> bool {
+        let new_target = match target_util::checked_pool_target_from_le_bytes(value.as_ref()) {
+            Ok(target) => target,
+            Err(e) => {
+                // Keep mining with the previous target
+                warn!("{} Stratum: ignoring new target: {}", self.context, e);
+                self.client.invalid_targets.inc();
+                return false;
+            }
+        };
+        info!(
+            "{} Stratum: changing target to {} diff={}",
+            self.context,
+            new_target,
+            target_util::difficulty_from_target(&new_target)
+        );
+        if let Err(e) = self.client.check_pool_difficulty(&new_target) {
+            warn!("{} Stratum: {}, reconnecting", self.context, e);
+            self.fatal_error.replace(e);
+            return false;
+        }
+        // Explicit target from the pool takes over the control immediately
+        self.end_startup_target("pool has set the target");
+        self.target_backfill.stop();
+        self.apply_target(new_target);
+        true
     }
 
-    async fn visit_set_target(&mut self, _header: &Header, target_msg: &SetTarget) {
-        self.update_target(target_msg.max_target);
+    /// Drop or delay the acknowledgement of shares up to `seq_num` when a drill fault is armed.
+    /// Returns true when the acknowledgement has been dropped.
+    async fn inject_ack_fault(&mut self, seq_num: u32) -> bool {
+        let context = self.context;
+        if self
+            .client
+            .inject_fault(context, |faults, now| faults.drop_ack(now))
+        {
+            warn!(
+                "{} Stratum: DRILL: acknowledgement of solution #{} is dropped",
+                context, seq_num
+            );
+            return true;
+        }
+        if let Some(delay) = self
+            .client
+            .inject_fault(context, |faults, now| faults.ack_delay(now))
+        {
+            warn!(
+                "{} Stratum: DRILL: acknowledgement of solution #{} is delayed by {:?}",
+                context, seq_num, delay
+            );
+            tokio::time::delay_for(delay).await;
+        }
+        false
     }
 
-    async fn visit_submit_shares_success(
-        &mut self,
+    /// Check that the pool acknowledges a share that has been submitted and that `count` shares
+    /// ending with `seq_num` are acknowledged in the order of submission (when configured)
+    fn verify_ack(&mut self, seq_num: u32, count: u32) {
+        self.client.lock_transmit().acknowledged();
+        if let Some(check) = self.client.share_ordering_check() {
+            if let Err(violation) = self.client.share_ordering.acknowledged(seq_num) {
+                if let Err(e) =
+                    self.client
+                        .report_ordering_violation(self.context, violation, check)
+                {
+                    self.fatal_error.replace(e);
+                }
+            }
+        }
+        if let Some(ack_sequencing) = self.client.ack_sequencing() {
+            let now = time::Instant::now();
+            self.expire_ack_gaps(now);
+            let anomalies = self.ack_sequencer.acknowledged(seq_num, count, now);
+            self.report_sequencing_anomalies(anomalies, &ack_sequencing);
+        }
+    }
+
+    /// Report gaps in acknowledged shares that haven't been filled within the gap window (when
+    /// strict acknowledgement sequencing is configured)
+    fn expire_ack_gaps(&mut self, now: time::Instant) {
+        if let Some(ack_sequencing) = self.client.ack_sequencing() {
+            let gap_window = time::Duration::from_secs(
+                ack_sequencing
+                    .gap_window
+                    .unwrap_or(StratumV2AckSequencing::DEFAULT_GAP_WINDOW),
+            );
+            let anomalies = self.ack_sequencer.expire(gap_window, now);
+            self.report_sequencing_anomalies(anomalies, &ack_sequencing);
+        }
+    }
+
+    fn report_sequencing_anomalies(
+        &mut self,
+        anomalies: Vec<ordering::Anomaly>,
+        ack_sequencing: &StratumV2AckSequencing,
+    ) {
+        let reaction = ack_sequencing.reaction.unwrap_or(ShareOrderingCheck::Log);
+        for anomaly in anomalies {
+            if let Err(e) = self
+                .client
+                .report_sequencing_anomaly(self.context, anomaly, reaction)
+            {
+                self.fatal_error.get_or_insert(e);
+            }
+        }
+    }
+
+    /// Warn about future jobs that have been pending without a new prevhash for at least the
+    /// configured interval. The warning is repeated at most once per interval while more future
+    /// jobs arrive. Returns true when the warning has been logged.
+    fn warn_pending_future_jobs(&mut self, now: time::Instant) -> bool {
+        let interval = match self.client.pending_jobs_log_interval() {
+            Some(interval) => interval,
+            None => return false,
+        };
+        let (count, oldest) = match self.pending_future_jobs {
+            Some(pending) => pending,
+            None => return false,
+        };
+        let since = self.pending_jobs_warned.unwrap_or(oldest);
+        if now.saturating_duration_since(since) < interval {
+            return false;
+        }
+        warn!(
+            "{} Stratum: {} future jobs received during last {}s are pending, the pool hasn't sent a new prevhash",
+            self.context,
+            count,
+            now.saturating_duration_since(oldest).as_secs()
+        );
+        self.pending_jobs_warned = Some(now);
+        true
+    }
+
+    /// Evict future jobs that have been waiting for a prevhash for at least the configured maximal
+    /// age. A prevhash that references an evicted job is handled as if the job has never been
+    /// received. Returns the number of evicted jobs.
+    fn evict_aged_future_jobs(&mut self, now: time::Instant) -> usize {
+        let max_age = match self.client.future_job_max_age() {
+            Some(max_age) => max_age,
+            None => return 0,
+        };
+        let mut evicted = Vec::new();
+        while let Some((job_id, received)) = self.future_jobs_received.front().copied() {
+            if now.saturating_duration_since(received) < max_age {
+                break;
+            }
+            self.future_jobs_received.pop_front();
+            let future_job = self
+                .all_jobs
+                .get(&job_id)
+                .map_or(false, |job_msg| job_msg.future_job);
+            if future_job {
+                self.all_jobs.remove(&job_id);
+                self.client.pipeline.evicted_future_job(job_id);
+                evicted.push(job_id);
+            }
+        }
+        if !evicted.is_empty() {
+            self.client.aged_future_jobs.add(evicted.len());
+            warn!(
+                "{} Stratum: future jobs {:?} have waited for a prevhash for more than {}s, evicted",
+                self.context,
+                evicted,
+                max_age.as_secs()
+            );
+        }
+        evicted.len()
+    }
+
+    /// Warn once per session when the pool hasn't sent `SetTarget` within the configured window
+    /// after the first job. Unlike a pool that keeps the difficulty at 1, it doesn't adjust the
+    /// target at all. Returns true when the warning has been logged.
+    fn warn_missing_set_target(&mut self, now: time::Instant) -> bool {
+        let window = match self.client.set_target_window() {
+            Some(window) => window,
+            None => return false,
+        };
+        let first_job_received = match self.first_job_received {
+            Some(first_job_received) => first_job_received,
+            None => return false,
+        };
+        if self.set_target_received
+            || self.missing_target_warned
+            || now.saturating_duration_since(first_job_received) < window
+        {
+            return false;
+        }
+        warn!(
+            "{} Stratum: pool hasn't sent any target for {}s since the first job, mining at diff={} (is its vardiff working?)",
+            self.context,
+            now.saturating_duration_since(first_job_received).as_secs(),
+            target_util::difficulty_from_target(&self.current_pool_target)
+        );
+        self.missing_target_warned = true;
+        true
+    }
+
+    /// Account an acknowledgement received while no submitted share has been waiting for it. The
+    /// connection is restarted when there are more of them in the session than configured.
+    fn unexpected_ack(&mut self, seq_num: u32) {
+        warn!(
+            "{} Stratum: pool acknowledged solution #{} while no solution has been submitted",
+            self.context, seq_num
+        );
+        self.client.unexpected_acks.inc();
+        self.unexpected_acks += 1;
+        if let Some(limit) = self.client.config().unexpected_ack_limit {
+            if self.unexpected_acks > limit {
+                self.fatal_error
+                    .get_or_insert(error::Client::UnexpectedAcks(self.unexpected_acks).into());
+            }
+        }
+    }
+
+    async fn process_accepted_shares(&mut self, success_msg: &SubmitSharesSuccess) {
+        let (acknowledged, found) = self
+            .client
+            .take_acknowledged(success_msg.last_seq_num)
+            .await;
+        if acknowledged.is_empty() {
+            self.unexpected_ack(success_msg.last_seq_num);
+            return;
+        }
+        self.target_backfill.accepted();
+        self.reject_streak.accepted();
+        let mut outcomes = Vec::with_capacity(acknowledged.len());
+        for (solution, seq_num) in acknowledged {
+            info!(
+                "{} Stratum: accepted solution #{} with nonce={:08x} diff={}",
+                self.context,
+                seq_num,
+                solution.nonce(),
+                target_util::difficulty_from_target(&solution.job_target())
+            );
+            outcomes.push((Outcome::Accepted, solution, seq_num));
+        }
+        if !found {
+            warn!(
+                "{} Stratum: last accepted solution #{} hasn't been found!",
+                self.context, success_msg.last_seq_num
+            );
+        }
+        self.client.account_solutions(outcomes, None);
+    }
+
+    async fn process_rejected_shares(&mut self, error_msg: &SubmitSharesError) {
+        // Only the sanitized code is logged and stored
+        let code = self.client.post_notice(
+            self.context,
+            notices::Source::SubmitSharesError,
+            &error_msg.code.to_string(),
+        );
+        let (acknowledged, found) = self.client.take_acknowledged(error_msg.seq_num).await;
+        if acknowledged.is_empty() {
+            self.unexpected_ack(error_msg.seq_num);
+            return;
+        }
+        let mut outcomes = Vec::with_capacity(acknowledged.len());
+        let mut rejected_difficulty = None;
+        for (solution, seq_num) in acknowledged {
+            if error_msg.seq_num == seq_num {
+                rejected_difficulty = Some(target_util::difficulty_from_target(
+                    &solution.job::<StratumJob>().pool_target,
+                ));
+                info!(
+                    "{} Stratum: rejected solution #{} with nonce={:08x}!",
+                    self.context,
+                    seq_num,
+                    solution.nonce()
+                );
+                outcomes.push((Outcome::Rejected, solution, seq_num));
+            } else {
+                // TODO: this is currently not according to stratum V2 specification
+                // preceding solutions are treated as accepted
+                info!(
+                    "{} Stratum: accepted solution #{} with nonce={}",
+                    self.context,
+                    seq_num,
+                    solution.nonce()
+                );
+                warn!(
+                    "{} Stratum: the solution #{} precedes rejected solution #{}!",
+                    self.context, seq_num, error_msg.seq_num
+                );
+                warn!(
+                    "{} Stratum: the solution #{} is treated as an accepted one",
+                    self.context, seq_num
+                );
+                self.reject_streak.accepted();
+                outcomes.push((Outcome::Accepted, solution, seq_num));
+            }
+        }
+        if !found {
+            warn!(
+                "{} Stratum: rejected solution #{} hasn't been found!",
+                self.context, error_msg.seq_num
+            );
+        }
+        self.client.account_solutions(outcomes, Some(&code));
+        self.check_reject_streak(&code);
+        if let Some(difficulty) = rejected_difficulty {
+            self.backfill_target(&code, difficulty).await;
+        }
+    }
+
+    /// Escalate shares that are consecutively rejected with `code` once the streak reaches the
+    /// configured threshold. The connection is restarted when configured.
+    fn check_reject_streak(&mut self, code: &str) {
+        let config = match self.client.config().reject_streak.clone() {
+            Some(config) => config,
+            None => return,
+        };
+        let threshold = config
+            .threshold
+            .unwrap_or(StratumV2RejectStreak::DEFAULT_THRESHOLD);
+        let streak = match self.reject_streak.rejected(code, threshold) {
+            Some(streak) => streak,
+            None => return,
+        };
+        warn!(
+            "{} Stratum: {}, the pool or the configuration has a systemic problem",
+            self.context, streak
+        );
+        self.client
+            .push_event(self.context, events::Event::RejectStreak(streak.clone()));
+        match config.reaction.unwrap_or(ShareOrderingCheck::Log) {
+            ShareOrderingCheck::Log => {}
+            ShareOrderingCheck::Reconnect => {
+                self.fatal_error
+                    .get_or_insert(error::Client::RejectStreak(streak.to_string()).into());
+            }
+        }
+    }
+
+    /// Check that a message received from the pool is addressed to the channel of the session.
+    /// Messages that apply to all channels of a group (`group` is set) are also accepted when
+    /// addressed to the group of the channel. Messages for a foreign channel are ignored.
+    fn is_addressed(&self, channel_id: u32, group: bool, message: &str) -> bool {
+        match self.session.channel.addressee(channel_id) {
+            Some(session::Addressee::Channel) => true,
+            Some(session::Addressee::Group) if group => true,
+            _ => {
+                warn!(
+                    "{} Stratum: ignoring {} for foreign channel {} (channel {}, group {:?})",
+                    self.context,
+                    message,
+                    channel_id,
+                    self.session.channel.channel_id,
+                    self.session.channel.group_id
+                );
+                self.client.foreign_channel_messages.inc();
+                false
+            }
+        }
+    }
+
+    /// Measure skew of the pool time against the local clock and validate `min_ntime` of the
+    /// prevhash (when configured). Returns false when the prevhash has to be ignored.
+    fn check_pool_time(&mut self, prevhash_msg: &SetNewPrevHash, now: time::SystemTime) -> bool {
+        let config = self.client.config();
+        let threshold = config
+            .clock_skew_threshold
+            .unwrap_or(StratumV2Config::DEFAULT_CLOCK_SKEW_THRESHOLD);
+        let (plausible, advisory) = {
+            let mut clock_skew = self
+                .client
+                .clock_skew
+                .lock()
+                .expect("BUG: cannot lock clock skew");
+            // The time is validated against the skew measured so far
+            let plausible = config.min_ntime_tolerance.map_or(true, |tolerance| {
+                clock_skew.is_plausible(prevhash_msg.min_ntime, now, tolerance)
+            });
+            let advisory = clock_skew.observe(prevhash_msg.min_ntime, now, threshold);
+            (plausible, advisory)
+        };
+        if let Some(advisory) = advisory {
+            warn!("{} Stratum: {}", self.context, advisory);
+            self.client
+                .push_event(self.context, events::Event::ClockSkew(advisory));
+        }
+        if !plausible {
+            warn!(
+                "{} Stratum: min_ntime {} of new prevhash deviates from the expected pool time, ignoring it",
+                self.context, prevhash_msg.min_ntime
+            );
+            self.client.implausible_prevhashes.inc();
+        }
+        plausible
+    }
+
+    /// Account the job activated by `prevhash_msg` in the template quality and report a pool that
+    /// refreshes templates too rarely
+    fn observe_template_refresh(&self, prevhash_msg: &SetNewPrevHash, job_msg: &NewMiningJob) {
+        let floor = self
+            .client
+            .config()
+            .template_refresh_floor
+            .unwrap_or(StratumV2Config::DEFAULT_TEMPLATE_REFRESH_FLOOR);
+        let advisory = self.client.lock_template_quality().prev_hash(
+            prevhash_msg.prev_hash,
+            job_msg.merkle_root,
+            time::Instant::now(),
+            floor,
+        );
+        if let Some(advisory) = advisory {
+            warn!("{} Stratum: {}", self.context, advisory);
+            self.client
+                .push_event(self.context, events::Event::StaleTemplates(advisory));
+        }
+    }
+
+    /// Apply the prevhash that has been waiting for its job once the job has been received.
+    /// Returns false when there is no such prevhash or the job is still missing.
+    async fn promote_orphan_prevhash(&mut self) -> bool {
+        let job_id = match self.orphan_prevhash.as_ref() {
+            Some((prevhash_msg, _)) => self.client.job_aliases.resolve(prevhash_msg.job_id),
+            None => return false,
+        };
+        if !self.all_jobs.contains_key(&job_id) {
+            return false;
+        }
+        let (prevhash_msg, _) = self
+            .orphan_prevhash
+            .take()
+            .expect("BUG: missing orphan prevhash");
+        info!(
+            "{} Stratum: job {} referenced by the new prevhash has been received",
+            self.context, prevhash_msg.job_id
+        );
+        self.apply_prevhash(&prevhash_msg).await;
+        true
+    }
+
+    /// Returns time remaining until the job referenced by the waiting prevhash has to be received
+    fn orphan_prevhash_delay(&self) -> Option<time::Duration> {
+        self.orphan_prevhash
+            .as_ref()
+            .map(|(_, deadline)| deadline.saturating_duration_since(time::Instant::now()))
+    }
+
+    /// The job referenced by the waiting prevhash hasn't been received in time
+    fn orphan_prevhash_expired(&mut self) -> error::Error {
+        let (prevhash_msg, _) = self
+            .orphan_prevhash
+            .take()
+            .expect("BUG: missing orphan prevhash");
+        warn!(
+            "{} Stratum: job {} referenced by the new prevhash hasn't been received in time, reconnecting",
+            self.context, prevhash_msg.job_id
+        );
+        error::Client::MissingPrevHashJob(prevhash_msg.job_id).into()
+    }
+
+    /// Start mining the job referenced by the prevhash, the job must have been received
+    async fn apply_prevhash(&mut self, prevhash_msg: &SetNewPrevHash) {
+        self.current_prevhash_msg.replace(prevhash_msg.clone());
+        self.client
+            .pipeline
+            .prev_hash(prevhash_msg, time::Instant::now());
+        self.pending_future_jobs = None;
+        self.pending_jobs_warned = None;
+
+        // find the future job with ID referenced in prevhash_msg (possibly via its alias)
+        let job_id = self.client.job_aliases.resolve(prevhash_msg.job_id);
+        let (_, mut future_job_msg) = self
+            .all_jobs
+            .remove_entry(&job_id)
+            .expect("BUG: job referenced by prevhash not found");
+        // The pool may have expired older IDs of the job, keep the most recent one. Aliases are
+        // not valid anymore.
+        future_job_msg.job_id = self.client.job_aliases.latest(job_id);
+        self.client.job_aliases.clear();
+
+        // remove all other jobs (they are now invalid)
+        self.all_jobs.retain(|_, _| true);
+        self.observe_template_refresh(prevhash_msg, &future_job_msg);
+        // turn the job into an immediate job
+        future_job_msg.future_job = false;
+        self.future_jobs_received
+            .retain(|(other, _)| *other != job_id);
+        // reinsert the job
+        self.all_jobs
+            .insert(future_job_msg.job_id, future_job_msg.clone());
+
+        // and start immediately solving it, the job dispatch limit doesn't apply to a new
+        // prevhash as mining on a stale block is worse than any hiccup of the backend
+        self.discard_held_job();
+        self.update_job(&future_job_msg, true).await;
+    }
+}
+
+#[async_trait]
+impl Handler for StratumEventHandler {
+    // The rules for prevhash/mining job pairing are (currently) as follows:
+    //  - when mining job comes
+    //      - store it (by id)
+    //      - start mining it if it doesn't have the future_job flag set
+    //  - when prevhash message comes
+    //      - replace it
+    //      - start mining the job it references (by job id)
+    //      - flush all other jobs
+
+    async fn visit_new_mining_job(&mut self, _header: &Header, job_msg: &NewMiningJob) {
+        if !self.is_addressed(job_msg.channel_id, true, "new mining job") {
+            return;
+        }
+        let now = time::Instant::now();
+        self.client.touch_last_job(now);
+        self.first_job_received.get_or_insert(now);
+        self.warn_missing_set_target(now);
+        self.evict_aged_future_jobs(now);
+        // Duplicate of an already known job is not stored nor dispatched, only its new ID is
+        // remembered. It is handled as a distinct job when the alias cannot be recorded.
+        if let Some(job_id) = self.find_duplicate_job(job_msg) {
+            if self.client.job_aliases.insert(job_msg.job_id, job_id) {
+                self.client.duplicate_jobs.inc();
+                info!(
+                    "{} Stratum: job {} duplicates job {}, not dispatched",
+                    self.context, job_msg.job_id, job_id
+                );
+                self.promote_orphan_prevhash().await;
+                return;
+            }
+        }
+
+        if !self.check_job_id_reuse(job_msg) {
+            return;
+        }
+
+        // all jobs since last `prevmsg` have to be stored in job table
+        self.all_jobs.insert(job_msg.job_id, job_msg.clone());
+        if self.promote_orphan_prevhash().await {
+            return;
+        }
+        // TODO: close connection when maximal capacity of `all_jobs` has been reached
+        if job_msg.future_job {
+            let now = time::Instant::now();
+            self.client.pipeline.future_job(job_msg.job_id, now);
+            // The age of a job whose ID has been reused is counted from the new job
+            self.future_jobs_received
+                .retain(|(other, _)| *other != job_msg.job_id);
+            self.future_jobs_received.push_back((job_msg.job_id, now));
+            let (count, oldest) = self.pending_future_jobs.unwrap_or((0, now));
+            self.pending_future_jobs = Some((count + 1, oldest));
+            self.warn_pending_future_jobs(now);
+        }
+
+        // When not marked as future job, we can start mining on it right away
+        // TODO see the _channels variant when consolidating this version of the client.
+        //  Transform the documentation from there too. Currently, this workaround with
+        //  .is_some() prevents a problem when a server indicates a new job however it doesn't
+        //  send the new prevhash ahead of this job. This scenario is still yet to be investigated
+        //  as it should prevented typically on the V2->V1->upstream translation proxies. These
+        //  proxies should guarantee that no such case like a job without a prevhash would exist.
+        if !job_msg.future_job && self.current_prevhash_msg.is_some() {
+            self.client
+                .lock_template_quality()
+                .template(job_msg.merkle_root, time::Instant::now());
+            match self.client.job_dispatch_policy() {
+                JobDispatchPolicy::Immediate => self.update_job_limited(job_msg).await,
+                JobDispatchPolicy::PrevHashBoundary => {
+                    self.client.deferred_jobs.inc();
+                    trace!(
+                        "{} Stratum: job {} is not dispatched until a new prevhash",
+                        self.context,
+                        job_msg.job_id
+                    );
+                }
+            }
+        }
+    }
+
+    async fn visit_set_new_prev_hash(&mut self, _header: &Header, prevhash_msg: &SetNewPrevHash) {
+        if !self.is_addressed(prevhash_msg.channel_id, true, "new prevhash") {
+            return;
+        }
+        let now = time::Instant::now();
+        self.client.touch_last_job(now);
+        self.warn_missing_set_target(now);
+        if !self.check_pool_time(prevhash_msg, time::SystemTime::now()) {
+            return;
+        }
+        self.evict_aged_future_jobs(now);
+        let job_id = self.client.job_aliases.resolve(prevhash_msg.job_id);
+        if self.all_jobs.contains_key(&job_id) {
+            // A prevhash that is still waiting for its job has been superseded
+            self.orphan_prevhash = None;
+            self.apply_prevhash(prevhash_msg).await;
+        } else {
+            // The messages may have been reordered, the job is expected to arrive shortly
+            info!(
+                "{} Stratum: new prevhash references job {} that hasn't been received yet",
+                self.context, prevhash_msg.job_id
+            );
+            self.client.orphan_prevhashes.inc();
+            self.orphan_prevhash = Some((
+                prevhash_msg.clone(),
+                time::Instant::now() + Self::ORPHAN_PREVHASH_TIMEOUT,
+            ));
+        }
+    }
+
+    async fn visit_set_target(&mut self, _header: &Header, target_msg: &SetTarget) {
+        if !self.is_addressed(target_msg.channel_id, true, "set target") {
+            return;
+        }
+        self.set_target_received = true;
+        if self.update_target(target_msg.max_target) {
+            self.target_resolved = true;
+            match self.target_wait.take() {
+                Some((job_msg, new_prevhash, _)) => {
+                    info!(
+                        "{} Stratum: target received, mining job {} that has waited for it",
+                        self.context, job_msg.job_id
+                    );
+                    self.update_job(&job_msg, new_prevhash).await;
+                }
+                None => self.apply_target_to_active_job().await,
+            }
+        }
+    }
+
+    async fn visit_submit_shares_success(
+        &mut self,
         _header: &Header,
         success_msg: &SubmitSharesSuccess,
     ) {
+        // Shares are acknowledged only to the channel they have been submitted to
+        if !self.is_addressed(success_msg.channel_id, false, "share acknowledgement") {
+            return;
+        }
+        if self.inject_ack_fault(success_msg.last_seq_num).await {
+            return;
+        }
+        self.verify_ack(
+            success_msg.last_seq_num,
+            success_msg.new_submits_accepted_count,
+        );
         self.process_accepted_shares(success_msg).await;
     }
 
     async fn visit_submit_shares_error(&mut self, _header: &Header, error_msg: &SubmitSharesError) {
+        if !self.is_addressed(error_msg.channel_id, false, "share rejection") {
+            return;
+        }
+        if self.inject_ack_fault(error_msg.seq_num).await {
+            return;
+        }
+        self.verify_ack(error_msg.seq_num, 1);
         self.process_rejected_shares(error_msg).await;
     }
 }
@@ -404,6 +1715,24 @@ struct StratumSolutionHandler<S> {
     client: Arc<StratumClient>,
     connection_tx: Arc<Mutex<S>>,
     seq_num: u32,
+    context: context::Context,
+    /// Solutions found while the submission window has been full
+    held: held::Queue<work::Solution>,
+    /// The submission window has been reported as full
+    window_full: bool,
+    /// Time of the last warning about solutions dropped for each reason
+    filter_warned: HashMap<filters::Reason, time::Instant>,
+    /// Time of the last warning about solutions of flushed jobs
+    flushed_job_warned: Option<time::Instant>,
+    /// Time of the last warning about solutions with version bits outside the mask
+    out_of_mask_warned: Option<time::Instant>,
+    /// The omission of the annotation has been reported for this connection
+    annotation_omitted: bool,
+    /// A solution of a foreign job has been reported for this connection
+    foreign_solution_reported: bool,
+    /// Acknowledgements synthesized by the reject injector that haven't been processed yet
+    #[cfg(feature = "reject-injection")]
+    injected_acks: VecDeque<<Framing as ii_wire::Framing>::Rx>,
 }
 
 impl<S, E> StratumSolutionHandler<S>
@@ -415,58 +1744,603 @@ where
         + std::fmt::Debug
         + 'static,
 {
-    fn new(client: Arc<StratumClient>, connection_tx: Arc<Mutex<S>>) -> Self {
+    /// Minimal interval between warnings (and events) about solutions dropped for the same reason
+    const FILTER_LOG_INTERVAL: time::Duration = time::Duration::from_secs(60);
+    /// Minimal interval between warnings about solutions of flushed jobs
+    const FLUSHED_JOB_LOG_INTERVAL: time::Duration = time::Duration::from_secs(60);
+    /// Minimal interval between warnings about solutions with version bits outside the mask
+    const OUT_OF_MASK_LOG_INTERVAL: time::Duration = time::Duration::from_secs(60);
+    /// Delay between attempts to submit a share
+    const SUBMIT_RETRY_DELAY: time::Duration = time::Duration::from_millis(50);
+
+    fn new(
+        client: Arc<StratumClient>,
+        connection_tx: Arc<Mutex<S>>,
+        context: context::Context,
+    ) -> Self {
+        let held = held::Queue::new(client.held_solutions_max_bytes(), client.held_solutions());
+        client.update_held_solutions(held.stats());
         Self {
             client,
             connection_tx,
             seq_num: 0,
+            context,
+            held,
+            window_full: false,
+            filter_warned: HashMap::new(),
+            flushed_job_warned: None,
+            out_of_mask_warned: None,
+            annotation_omitted: false,
+            foreign_solution_reported: false,
+            #[cfg(feature = "reject-injection")]
+            injected_acks: VecDeque::new(),
         }
     }
 
     async fn process_solution(&mut self, solution: work::Solution) -> error::Result<()> {
+        self.client.filter_stats.found().inc();
+        if !self.own_solution(&solution) {
+            return Ok(());
+        }
+        if self.client.is_draining() {
+            // Nothing is submitted on a connection that is being drained, solutions of the
+            // invalidated jobs are thrown away the same way as when the connection is closed
+            self.filter_solutions(filters::Reason::Draining, 1, || {
+                "the connection is being drained".to_string()
+            });
+            return Ok(());
+        }
+        let solution = match self.out_of_mask_version(solution) {
+            Some(solution) => solution,
+            None => return Ok(()),
+        };
         let job: &StratumJob = solution.job();
+        if self.client.is_flushed(job) && !self.flushed_job_solution() {
+            return Ok(());
+        }
+        if !solution.hash().meets(&job.pool_target) {
+            // The job is solved with easier target than the pool requested, such solutions
+            // would be rejected by the pool
+            self.filter_solutions(filters::Reason::BelowPoolTarget, 1, || {
+                "the solution doesn't meet the target of the pool".to_string()
+            });
+            return Ok(());
+        }
+        // The pool rejects solutions with ntime earlier than min_ntime of the job, such solutions
+        // are a symptom of a backend that keeps using a stale base time after a job switch
+        let regression = job.time as i64 - solution.time() as i64;
+        if regression > self.client.ntime_tolerance() as i64 {
+            self.filter_solutions(filters::Reason::NtimeRegression, 1, || {
+                format!("ntime {}s earlier than min_ntime of the job", regression)
+            });
+            return Ok(());
+        }
+        // The backend is expected to respect the maximal time of the job, solutions beyond it
+        // would be rejected by the pool
+        if solution.time() > job.max_time {
+            let (time, max_time) = (solution.time(), job.max_time);
+            self.filter_solutions(filters::Reason::NtimeOverrun, 1, || {
+                format!(
+                    "ntime {} beyond the rolling window of the job (max {})",
+                    time, max_time
+                )
+            });
+            return Ok(());
+        }
 
-        let seq_num = self.seq_num;
-        self.seq_num = self.seq_num.wrapping_add(1);
+        if let Some(window) = self.client.submission_window() {
+            // Held solutions have to be submitted first to keep the order of submissions
+            let outstanding = self.client.solutions.lock().await.len();
+            if !self.held.is_empty() || outstanding >= window {
+                self.hold_solution(solution, window, outstanding);
+                return Ok(());
+            }
+        }
+        self.submit_solution(solution).await
+    }
 
-        let share_msg = SubmitSharesStandard {
-            channel_id: job.channel_id,
-            seq_num,
-            job_id: job.id,
-            nonce: solution.nonce(),
-            ntime: solution.time(),
-            version: solution.version(),
+    /// Handle a solution of a job that has been flushed by a new prevhash according to the
+    /// configured policy. Returns true when the solution is to be submitted.
+    fn flushed_job_solution(&mut self) -> bool {
+        let policy = self.client.flushed_job_solutions_policy();
+        if policy == FlushedJobSolutions::Submit {
+            return true;
+        }
+        self.client.flushed_job_solutions.inc();
+        if policy == FlushedJobSolutions::Drop {
+            self.filter_solutions(filters::Reason::FlushedJob, 1, || {
+                "the job has been flushed by a new prevhash".to_string()
+            });
+            return false;
+        }
+        let now = time::Instant::now();
+        let warn = self.flushed_job_warned.map_or(true, |warned| {
+            now.saturating_duration_since(warned) >= Self::FLUSHED_JOB_LOG_INTERVAL
+        });
+        if warn {
+            warn!(
+                "{} Stratum: submitting solution of a job flushed by a new prevhash ({} such solutions in total)",
+                self.context,
+                *self.client.flushed_job_solutions.take_snapshot()
+            );
+            self.flushed_job_warned = Some(now);
+        }
+        true
+    }
+
+    /// Check that the job of the solution has been generated by this client before the job is
+    /// used by any of the submission paths (held solutions are checked before they are held).
+    /// A foreign solution is dropped, it is never submitted.
+    fn own_solution(&mut self, solution: &work::Solution) -> bool {
+        let mismatch = match solution.checked_job::<StratumJob>(self.client.clone()) {
+            Ok(_) => return true,
+            Err(mismatch) => mismatch,
         };
-        // store solution with sequence number for future server acknowledge
-        self.client
-            .solutions
-            .lock()
-            .await
-            .push_back((solution, seq_num));
-        // send solutions back to the stratum server
-        StratumClient::send_msg(&self.connection_tx, share_msg)
-            .await
-            .context("Cannot send submit to stratum server")?;
-        // the response is handled in a separate task
-        Ok(())
+        self.filter_solutions(filters::Reason::ForeignJob, 1, || {
+            format!("the solution belongs to a {}", mismatch)
+        });
+        if !self.foreign_solution_reported {
+            self.foreign_solution_reported = true;
+            self.client
+                .push_event(self.context, events::Event::ForeignSolution(mismatch));
+        }
+        false
     }
-}
 
-struct StratumConnectionHandler {
+    /// Handle a solution whose version has rolled bits outside the version rolling mask of its
+    /// job (a backend bug) according to the configured policy. Returns the solution that is to be
+    /// processed further.
+    fn out_of_mask_version(&mut self, solution: work::Solution) -> Option<work::Solution> {
+        let job: &StratumJob = solution.job();
+        let (job_version, version_mask) = (job.version, job.version_mask);
+        let version = solution.version();
+        if (version ^ job_version) & !version_mask == 0 {
+            return Some(solution);
+        }
+        self.client.out_of_mask_versions.inc();
+        let policy = self.client.out_of_mask_versions_policy();
+        let describe = || {
+            format!(
+                "version {:#010x} rolled outside the mask {:#010x} of the job version {:#010x}",
+                version, version_mask, job_version
+            )
+        };
+        if policy == OutOfMaskVersions::Drop {
+            self.filter_solutions(filters::Reason::OutOfMaskVersion, 1, describe);
+            return None;
+        }
+        let now = time::Instant::now();
+        let warn = self.out_of_mask_warned.map_or(true, |warned| {
+            now.saturating_duration_since(warned) >= Self::OUT_OF_MASK_LOG_INTERVAL
+        });
+        if warn {
+            warn!(
+                "{} Stratum: {} solution with {} ({} such solutions in total)",
+                self.context,
+                if policy == OutOfMaskVersions::Mask {
+                    "masking"
+                } else {
+                    "submitting"
+                },
+                describe(),
+                *self.client.out_of_mask_versions.take_snapshot()
+            );
+            self.out_of_mask_warned = Some(now);
+        }
+        match policy {
+            OutOfMaskVersions::Drop => None,
+            // The corrected solution has a different hash, it is checked against the pool target
+            // as any other solution
+            OutOfMaskVersions::Mask => Some(
+                solution.with_version((version & version_mask) | (job_version & !version_mask)),
+            ),
+            OutOfMaskVersions::Submit => Some(solution),
+        }
+    }
+
+    /// Account `count` solutions dropped by a local filter for `reason`. This is the only place
+    /// where the drops are counted, the warning (with the detail given by `describe`) and the
+    /// event are rate-limited per reason.
+    fn filter_solutions<F>(&mut self, reason: filters::Reason, count: usize, describe: F)
+    where
+        F: FnOnce() -> String,
+    {
+        if count == 0 {
+            return;
+        }
+        let counter = self.client.filter_stats.dropped(reason);
+        counter.add(count);
+        let now = time::Instant::now();
+        let warn = self.filter_warned.get(&reason).map_or(true, |warned| {
+            now.saturating_duration_since(*warned) >= Self::FILTER_LOG_INTERVAL
+        });
+        if warn {
+            let total = *counter.take_snapshot();
+            warn!(
+                "{} Stratum: dropping solution: {} ({} solutions dropped as {} in total)",
+                self.context,
+                describe(),
+                total,
+                reason
+            );
+            self.client.push_event(
+                self.context,
+                events::Event::SolutionsFiltered(filters::Dropped { reason, total }),
+            );
+            self.filter_warned.insert(reason, now);
+        }
+    }
+
+    /// Handle a solution found while the submission window is full
+    fn hold_solution(&mut self, solution: work::Solution, window: usize, outstanding: usize) {
+        if !self.window_full {
+            warn!(
+                "{} Stratum: {} submitted shares haven't been acknowledged by the pool yet",
+                self.context, outstanding
+            );
+            self.window_full = true;
+        }
+        match self.client.submission_window_policy() {
+            SubmissionWindowPolicy::Hold => {
+                // Keep the most recent solutions
+                let evicted = self.held.push_back(solution, window);
+                self.filter_solutions(filters::Reason::WindowFull, evicted, || {
+                    "the submission window is full, evicting the oldest held solution".to_string()
+                });
+                self.client.update_held_solutions(self.held.stats());
+            }
+            SubmissionWindowPolicy::Drop => {
+                self.filter_solutions(filters::Reason::WindowFull, 1, || {
+                    "the submission window is full".to_string()
+                })
+            }
+        }
+    }
+
+    /// Submit held solutions while there is space in the submission window
+    async fn submit_held(&mut self) -> error::Result<()> {
+        if self.client.is_draining() {
+            return Ok(());
+        }
+        let window = self.client.submission_window().unwrap_or(usize::MAX);
+        loop {
+            if self.client.solutions.lock().await.len() >= window {
+                return Ok(());
+            }
+            self.window_full = false;
+            match self.held.pop_front() {
+                Some(solution) => {
+                    self.client.update_held_solutions(self.held.stats());
+                    self.submit_solution(solution).await?
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    async fn submit_solution(&mut self, solution: work::Solution) -> error::Result<()> {
+        let job: &StratumJob = solution.job();
+        let seq_num = self.seq_num;
+        self.seq_num = self.seq_num.wrapping_add(1);
+        if let Some(check) = self.client.share_ordering_check() {
+            if let Err(violation) = self.client.share_ordering.submitted(seq_num) {
+                self.client
+                    .report_ordering_violation(self.context, violation, check)?;
+            }
+        }
+
+        let share_msg = SubmitSharesStandard {
+            channel_id: job.channel_id,
+            seq_num,
+            // Always use the job ID most recently announced by the pool for the job
+            job_id: self.client.job_aliases.latest(job.id),
+            nonce: match self.client.nonce_byte_order() {
+                NonceByteOrder::Spec => solution.nonce(),
+                NonceByteOrder::Swapped => solution.nonce().swap_bytes(),
+            },
+            ntime: solution.time(),
+            version: solution.version(),
+        };
+        let annotation = self.annotation();
+        #[cfg(feature = "reject-injection")]
+        let injected = self.inject_ack(&share_msg, job)?;
+        #[cfg(not(feature = "reject-injection"))]
+        let injected = false;
+        let blackholed = !injected
+            && self
+                .client
+                .inject_fault(self.context, |faults, now| faults.blackhole_submission(now));
+        // store solution with sequence number for future server acknowledge, the solution is
+        // queued and counted in a single critical section before it is sent (the acknowledgement
+        // may arrive before the send completes)
+        {
+            let mut solutions = self.client.solutions.lock().await;
+            solutions.push_back((solution, seq_num));
+            if let Some(annotation) = annotation.as_ref() {
+                self.client
+                    .lock_annotations()
+                    .submitted(seq_num, annotation.clone());
+            }
+            self.client.submitted.inc();
+            self.client.filter_stats.passed().inc();
+            self.client.lock_transmit().submitted(time::Instant::now());
+            self.client.spans.submitted(job.channel_id, seq_num);
+        }
+        if blackholed {
+            debug!(
+                "{} Stratum: DRILL: solution #{} is not sent to the pool",
+                self.context, seq_num
+            );
+        } else if !injected {
+            // send solutions back to the stratum server
+            self.submit(share_msg, annotation.as_deref()).await?;
+        }
+        // the response is handled in a separate task
+        Ok(())
+    }
+
+    /// Annotation attached to the next share. It is omitted when the pool doesn't accept it, the
+    /// omission is reported once for each connection.
+    fn annotation(&mut self) -> Option<String> {
+        let annotation = self.client.config().submission_annotation.clone()?;
+        if self.client.accepts_annotations() {
+            return Some(annotation);
+        }
+        if !self.annotation_omitted {
+            self.annotation_omitted = true;
+            info!(
+                "{} Stratum: pool doesn't accept annotations of submissions, shares are submitted without the annotation",
+                self.context
+            );
+            self.client
+                .push_event(self.context, events::Event::AnnotationOmitted);
+        }
+        None
+    }
+
+    /// Synthesize acknowledgement of the share when the reject injector is set. Returns false
+    /// when the share has to be submitted to the pool.
+    #[cfg(feature = "reject-injection")]
+    fn inject_ack(
+        &mut self,
+        share_msg: &SubmitSharesStandard,
+        job: &StratumJob,
+    ) -> error::Result<bool> {
+        let mut injector = self
+            .client
+            .reject_injector
+            .lock()
+            .expect("BUG: cannot lock reject injector");
+        let injector = match injector.as_mut() {
+            Some(injector) => injector,
+            None => return Ok(false),
+        };
+        let frame = match injector.next_share() {
+            Some(code) => SubmitSharesError {
+                channel_id: share_msg.channel_id,
+                seq_num: share_msg.seq_num,
+                code: code
+                    .try_into()
+                    .expect("BUG: cannot convert injected error code"),
+            }
+            .try_into()?,
+            None => SubmitSharesSuccess {
+                channel_id: share_msg.channel_id,
+                last_seq_num: share_msg.seq_num,
+                new_submits_accepted_count: 1,
+                new_shares_sum: target_util::difficulty_from_target(&job.pool_target) as u32,
+            }
+            .try_into()?,
+        };
+        self.injected_acks.push_back(frame);
+        Ok(true)
+    }
+
+    /// Send the share to the stratum server. A send that doesn't complete in time means that the
+    /// transmit direction of the connection is wedged and the connection has to be re-established.
+    /// Other send errors are retried up to the configured number of attempts. Solutions are
+    /// submitted one by one, therefore the order of sequence numbers is kept across retries.
+    async fn submit(
+        &self,
+        share_msg: SubmitSharesStandard,
+        annotation: Option<&str>,
+    ) -> error::Result<()> {
+        let attempts = self.client.submit_attempts();
+        let mut attempt = 1;
+        loop {
+            let started = time::Instant::now();
+            let e = match self.send_share(&share_msg, annotation).await {
+                Ok(()) => {
+                    let thresholds = self.client.transmit_thresholds();
+                    self.client.lock_transmit().send_completed(
+                        started,
+                        time::Instant::now(),
+                        &thresholds,
+                    );
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+            if e.kind() == error::ErrorKind::Client(error::Client::SendTimeout) {
+                warn!(
+                    "{} Stratum: submitting share is stuck, the connection will be restarted",
+                    self.context
+                );
+                self.client.wedged_sends.inc();
+            } else if attempt < attempts {
+                warn!(
+                    "{} Stratum: submitting share #{} failed ({}), retrying",
+                    self.context, share_msg.seq_num, e
+                );
+                self.client.submit_retries.inc();
+                attempt += 1;
+                tokio::time::delay_for(Self::SUBMIT_RETRY_DELAY).await;
+                continue;
+            }
+            Err(e).context("Cannot send submit to stratum server")?;
+        }
+    }
+
+    async fn send_share(
+        &self,
+        share_msg: &SubmitSharesStandard,
+        annotation: Option<&str>,
+    ) -> error::Result<()> {
+        match annotation {
+            Some(annotation) => {
+                let frame = self
+                    .client
+                    .annotation_carrier()
+                    .annotate(share_msg.clone(), annotation)?;
+                StratumClient::send_frame(&self.connection_tx, frame).await
+            }
+            None => StratumClient::send_msg(&self.connection_tx, share_msg.clone()).await,
+        }
+    }
+}
+
+/// Error codes of `OpenStandardMiningChannelError` that cannot be resolved by opening the channel
+/// again
+const PERMANENT_CHANNEL_ERRORS: &[&str] = &["unknown-user", "max-target-out-of-range"];
+
+struct StratumConnectionHandler {
     client: Arc<StratumClient>,
     init_target: ii_bitcoin::Target,
+    /// The pool has provided a usable target when the channel has been opened
+    init_target_provided: bool,
+    /// Parameters of `SetupConnectionSuccess` received in this handshake
+    negotiated: Option<status::Negotiated>,
+    /// Identity and extranonce prefix of the channel opened in this handshake
+    channel: Option<(session::ChannelIdentity, Vec<u8>)>,
     status: Option<error::Result<()>>,
+    context: context::Context,
+    transcript: transcript::Recorder,
+    /// Frames received before the channel has been opened, they are handed over to the event
+    /// handler of the session
+    buffered_frames: Vec<<Framing as ii_wire::Framing>::Rx>,
+    /// Phases of this connection attempt
+    timer: connect_timing::Timer,
 }
 
 impl StratumConnectionHandler {
-    pub fn new(client: Arc<StratumClient>) -> Self {
+    /// Maximal number of frames received before the channel has been opened
+    const MAX_BUFFERED_FRAMES: usize = 64;
+
+    pub fn new(client: Arc<StratumClient>, context: context::Context) -> Self {
+        let connection_details = client.connection_details();
+        let transcript = transcript::Recorder::new(
+            connection_details.user,
+            connection_details
+                .config
+                .handshake_transcript_raw_bytes
+                .unwrap_or(false),
+        );
         Self {
             client,
-            init_target: Default::default(),
+            init_target: target_util::difficulty_1_target(),
+            init_target_provided: false,
+            negotiated: None,
+            channel: None,
             status: None,
+            context,
+            transcript,
+            buffered_frames: Vec::new(),
+            timer: connect_timing::Timer::builder().build(),
+        }
+    }
+
+    /// Time the phases of the attempt with `timer` (e.g. with a manual clock)
+    pub fn with_timer(mut self, timer: connect_timing::Timer) -> Self {
+        self.timer = timer;
+        self
+    }
+
+    /// Limit the duration of a single step of the handshake, the step is cut short when the
+    /// startup budget of `client` runs out
+    async fn with_timeout<F, T>(client: &StratumClient, step: F) -> error::Result<T>
+    where
+        F: Future<Output = error::Result<T>>,
+    {
+        let result = step
+            .timeout(client.startup_timeout(StratumClient::CONNECTION_TIMEOUT))
+            .await;
+        match (result, client.startup_budget_error()) {
+            // Failure of the step itself is more specific than the exhausted budget
+            (Ok(Err(e)), _) | (_, Some(e)) => Err(e),
+            (result, None) => result.map_err(|_| {
+                error::ErrorKind::General("Init mining session timeout".to_string())
+            })?,
+        }
+    }
+
+    /// Send a handshake message and record it in the transcript
+    async fn send_msg<M, S>(
+        &mut self,
+        connection_tx: &Arc<Mutex<S>>,
+        message: M,
+    ) -> error::Result<()>
+    where
+        M: TryInto<<Framing as ii_wire::Framing>::Tx, Error = <Framing as ii_wire::Framing>::Error>
+            + fmt::Debug,
+        S: FrameSink,
+    {
+        let summary = format!("{:?}", message);
+        let (bytes, frame) = transcript::serialize_frame(message.try_into()?)?;
+        self.transcript.record(
+            transcript::Direction::Sent,
+            &bytes,
+            summary,
+            time::SystemTime::now(),
+        );
+        StratumClient::send_frame(connection_tx, frame).await
+    }
+
+    /// Failure to read from the connection during the handshake is a reset of the connection
+    /// (typically an overloaded pool that drops the connection right after accepting it). Unlike
+    /// protocol errors it is transient.
+    fn receive_error(e: ii_stratum::error::Error) -> error::Error {
+        match e.kind() {
+            ii_stratum::error::ErrorKind::Io(msg) => error::Client::ConnectionReset(msg).into(),
+            _ => e.into(),
         }
     }
 
+    /// Describe failure of a handshake `step`. Reset of the connection is passed through as it is
+    /// so that it stays recognizable as a transient failure.
+    fn describe_failure(e: error::Error, step: &'static str) -> error::Error {
+        match e.kind() {
+            error::ErrorKind::Client(error::Client::ConnectionReset(_)) => e,
+            _ => e.context(step).into(),
+        }
+    }
+
+    /// Receive a handshake frame and record it in the transcript. The summary of the frame is
+    /// provided by the visitor that handles the decoded message.
+    async fn recv_frame<R>(
+        &mut self,
+        connection_rx: &mut R,
+    ) -> error::Result<<Framing as ii_wire::Framing>::Rx>
+    where
+        R: FrameStream,
+    {
+        let frame = match connection_rx.next().await {
+            Some(Ok(frame)) => frame,
+            Some(Err(e)) => Err(Self::receive_error(e))?,
+            None => Err(error::Client::ConnectionReset(
+                "connection closed".to_string(),
+            ))?,
+        };
+        let summary = format!("Unexpected message: {:x?}", frame.header);
+        let (bytes, frame) = transcript::serialize_frame(frame)?;
+        self.transcript.record(
+            transcript::Direction::Received,
+            &bytes,
+            summary,
+            time::SystemTime::now(),
+        );
+        Ok(frame)
+    }
+
     async fn setup_mining_connection<R, S>(
         &mut self,
         connection_rx: &mut R,
@@ -477,22 +2351,27 @@ impl StratumConnectionHandler {
         S: FrameSink,
     {
         let connection_details = self.client.connection_details();
+        // TODO: resumption of the server-side session (vardiff state) with an opaque token issued
+        //  by the pool cannot be negotiated yet. Neither `SetupConnection` nor
+        //  `OpenStandardMiningChannel(Success)` of `ii_stratum::v2::messages` has a field for the
+        //  token and no extension (see `extensions`) defines messages carrying it. Once the
+        //  protocol crate exposes them, the token captured from the channel-open success (stored
+        //  per `ConnectionDetails::endpoint_key` with its expiry, redacted from diagnostics) is
+        //  presented here and `status::Negotiated` records whether the pool honored it.
         let setup_msg = SetupConnection {
             protocol: 0,
             max_version: 2,
             min_version: 2,
             flags: 0,
-            endpoint_host: Str0_255::from_string(connection_details.host.clone()),
-            endpoint_port: connection_details.port,
+            endpoint_host: Str0_255::try_from(connection_details.endpoint_host())
+                .map_err(|_| "Advertised endpoint host is longer than 255 bytes")?,
+            endpoint_port: connection_details.endpoint_port(),
             device: self.client.backend_info.clone().unwrap_or_default().into(),
         };
-        StratumClient::send_msg(&connection_tx, setup_msg)
+        self.send_msg(&connection_tx, setup_msg)
             .await
             .context("Cannot send stratum setup mining connection")?;
-        let frame = connection_rx
-            .next()
-            .await
-            .ok_or("The remote stratum server was disconnected prematurely")??;
+        let frame = self.recv_frame(connection_rx).await?;
         let response_msg = build_message_from_frame(frame)?;
 
         self.status = None;
@@ -502,6 +2381,11 @@ impl StratumConnectionHandler {
         ))
     }
 
+    /// Opens a standard mining channel
+    /// TODO: extended channels are not supported (`OpenExtendedMiningChannel` and the related
+    ///  messages are not implemented in `ii_stratum`), therefore there is no extended channel
+    ///  configuration to fall back from. A configurable fallback to the standard channel on
+    ///  `OpenExtendedMiningChannelError` belongs here once extended channels are implemented.
     async fn open_channel<R, S>(
         &mut self,
         connection_rx: &mut R,
@@ -511,27 +2395,36 @@ impl StratumConnectionHandler {
         R: FrameStream,
         S: FrameSink,
     {
+        let nominal_hashrate = match self.client.early_share() {
+            Some(early_share) => ii_bitcoin::HashesUnit::TeraHashes(early_share.nominal_hashrate)
+                .into_hashes()
+                .into_f64() as f32,
+            None => 1e9,
+        };
+        let max_target = self.client.channel_max_target(time::Instant::now());
         let channel_msg = OpenStandardMiningChannel {
             req_id: 10, // TODO? come up with request ID sequencing
-            user: self
-                .client
-                .connection_details()
-                .user
-                .clone()
-                .try_into()
-                .expect("BUG: cannot convert 'OpenStandardMiningChannel::user'"),
-            nominal_hashrate: 1e9,
-            // Maximum bitcoin target is 0xffff << 208 (= difficulty 1 share)
-            max_target: ii_bitcoin::Target::default().into(),
+            user: Str0_255::try_from(self.client.worker_name())
+                .map_err(|_| "Worker name is longer than 255 bytes")?,
+            nominal_hashrate,
+            max_target: Uint256Bytes(target_util::target_into_le_bytes(&max_target)),
         };
 
-        StratumClient::send_msg(&connection_tx, channel_msg)
+        self.send_msg(&connection_tx, channel_msg)
             .await
             .context("Cannot send stratum open channel")?;
-        let frame = connection_rx
-            .next()
-            .await
-            .ok_or("The remote stratum server was disconnected prematurely")??;
+        // The pool may send other frames before it responds (e.g. in between attempts to open the
+        // channel), they are kept for the event handler
+        let frame = loop {
+            let frame = self.recv_frame(connection_rx).await?;
+            if Self::is_open_channel_response(&frame.header) {
+                break frame;
+            }
+            if self.buffered_frames.len() >= Self::MAX_BUFFERED_FRAMES {
+                Err("Too many frames received before the stratum channel has been opened")?;
+            }
+            self.buffered_frames.push(frame);
+        };
         let response_msg = build_message_from_frame(frame)?;
 
         self.status = None;
@@ -541,22 +2434,79 @@ impl StratumConnectionHandler {
             .unwrap_or(Err("Unexpected response for stratum open channel".into()))
     }
 
-    async fn connect(&self) -> error::Result<v2::Framed> {
+    fn is_open_channel_response(header: &Header) -> bool {
+        header.extension_type == extensions::BASE
+            && (header.msg_type
+                == v2::messages::MessageType::OpenStandardMiningChannelSuccess as u8
+                || header.msg_type
+                    == v2::messages::MessageType::OpenStandardMiningChannelError as u8)
+    }
+
+    async fn connect(&mut self) -> error::Result<v2::Framed> {
         let connection_details = self.client.connection_details();
-        let addr = ii_wire::Address::from_str(connection_details.get_host_and_port().as_str())?;
-        let mut client = ii_wire::Client::new(addr);
-        // Attempt only once to connect (as the stratum client is being managed externally)
-        let connection = client.next().await?;
+        let addr = ii_wire::Address::from_str(connection_details.get_host_and_port().as_str())
+            .map_err(|e| error::Client::InvalidAddress(e.to_string()))?;
+        // Resolve the host first so that failures of name resolution are told apart from
+        // failures of the connection itself
+        self.timer.start(connect_timing::Phase::Dns, None);
+        let resolved = tokio::net::lookup_host(addr.as_ref())
+            .await
+            .map(|addrs| addrs.collect::<Vec<_>>());
+        self.timer.end(match &resolved {
+            Ok(addrs) if !addrs.is_empty() => connect_timing::Outcome::Ok,
+            _ => connect_timing::Outcome::Failed,
+        });
+        let addrs = match resolved {
+            Ok(addrs) if !addrs.is_empty() => addrs,
+            Ok(_) => Err(error::Client::DnsFailure(format!("{}: no address", addr.0)))?,
+            Err(e) => Err(error::Client::DnsFailure(format!("{}: {}", addr.0, e)))?,
+        };
+        // Attempt only once to connect to each address (as the stratum client is being managed
+        // externally)
+        let mut connect_error = None;
+        let mut connection = None;
+        for socket_addr in addrs {
+            self.timer.start(
+                connect_timing::Phase::TcpConnect,
+                Some(socket_addr.to_string()),
+            );
+            let result = tokio::net::TcpStream::connect(socket_addr).await;
+            self.timer.end(connect_timing::Outcome::of(&result));
+            match result {
+                Ok(stream) => {
+                    connection = Some(stream);
+                    break;
+                }
+                Err(e) => connect_error = Some(e),
+            }
+        }
+        let connection = connection.ok_or_else(|| {
+            error::Client::ConnectFailed(connect_error.map(|e| e.to_string()).unwrap_or_default())
+        })?;
 
         // TODO this will be replaced by a 'connector' that will be set when building stratum
         // client instance
         let client_framed_stream = match connection_details.protocol {
             // V2 secure connector
             ClientProtocol::StratumV2(upstream_authority_public_key) => {
+                let authority_public_keys = std::iter::once(upstream_authority_public_key)
+                    .chain(
+                        connection_details
+                            .config
+                            .authority_public_keys
+                            .clone()
+                            .unwrap_or_default(),
+                    )
+                    .map(|key| key.into_inner())
+                    .collect();
                 let noise_initiator =
-                    v2::noise::Initiator::new(upstream_authority_public_key.into_inner());
+                    v2::noise::Initiator::with_authority_public_keys(authority_public_keys);
                 // Successful noise initiator handshake results in a stream/sink for V2 frames
-                noise_initiator.connect(connection).await?
+                self.timer
+                    .start(connect_timing::Phase::NoiseHandshake, None);
+                let result = noise_initiator.connect(connection).await;
+                self.timer.end(connect_timing::Outcome::of(&result));
+                result?
             }
             // V2 insecure connector
             ClientProtocol::StratumV2Insecure => {
@@ -569,24 +2519,131 @@ impl StratumConnectionHandler {
         Ok(client_framed_stream)
     }
 
-    /// Starts mining session and provides the initial target negotiated by the upstream endpoint
+    async fn handshake<R, S>(
+        &mut self,
+        connection_rx: &mut R,
+        connection_tx: Arc<Mutex<S>>,
+    ) -> error::Result<()>
+    where
+        R: FrameStream,
+        S: FrameSink,
+    {
+        // Nothing negotiated with the previous incarnation of the pool is valid from now on
+        self.client.set_session(None);
+        self.client
+            .start_startup_phase(startup_budget::Phase::Handshake);
+        let client = self.client.clone();
+        self.timer
+            .start(connect_timing::Phase::SetupConnection, None);
+        let result = Self::with_timeout(
+            &client,
+            self.setup_mining_connection(connection_rx, connection_tx.clone()),
+        )
+        .await;
+        self.timer.end(connect_timing::Outcome::of(&result));
+        result.map_err(|e| Self::describe_failure(e, "Cannot setup stratum mining connection"))?;
+
+        // Transient failures of opening the channel are retried on the established connection
+        // before falling back to reconnecting, permanent failures are reported right away
+        let backoff = self.client.channel_open_backoff();
+        let mut retries = backoff.iter();
+        loop {
+            self.timer.start(connect_timing::Phase::ChannelOpen, None);
+            self.client.spans.open_channel_started();
+            let result = Self::with_timeout(
+                &client,
+                self.open_channel(connection_rx, connection_tx.clone()),
+            )
+            .await;
+            self.timer.end(connect_timing::Outcome::of(&result));
+            self.client.spans.open_channel_finished(
+                self.channel.as_ref().map(|(channel, _)| channel.channel_id),
+                connect_timing::Outcome::of(&result),
+            );
+            let e = match result {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            match (e.kind(), retries.next()) {
+                (
+                    error::ErrorKind::Client(error::Client::ChannelOpen {
+                        permanent: false, ..
+                    }),
+                    Some(delay),
+                ) if self.client.retry_startup_step(&e, *delay) => {
+                    info!(
+                        "{} Stratum: {}, retrying in {} ms",
+                        self.context,
+                        e,
+                        delay.as_millis()
+                    );
+                    self.client.channel_open_retries.inc();
+                    tokio::time::delay_for(*delay).await;
+                }
+                _ => Err(Self::describe_failure(e, "Cannot open stratum channel"))?,
+            }
+        }
+    }
+
+    /// Build state of the session from the parameters collected during a successful handshake
+    fn build_session(&mut self) -> session::State {
+        let negotiated = self
+            .negotiated
+            .take()
+            .expect("BUG: handshake succeeded without setting up the connection");
+        let (channel, extranonce_prefix) = self
+            .channel
+            .take()
+            .expect("BUG: handshake succeeded without opening the channel");
+        let version_mask = self.client.effective_version_mask(negotiated.flags);
+        session::State {
+            negotiated,
+            channel,
+            extranonce_prefix,
+            init_target: self.init_target,
+            init_target_provided: self.init_target_provided,
+            version_mask,
+        }
+    }
+
+    /// Starts mining session and provides the state negotiated with the upstream endpoint
+    /// together with the context of the new session, frames received before the channel has
+    /// been opened and the timing of the first job of the session. The state replaces the state
+    /// of the previous session in the client. The transcript of a failed handshake is stored in
+    /// the client, the transcript of a successful one is discarded. The timing of the attempt is
+    /// stored in both cases.
     async fn init_mining_session<R, S>(
         mut self,
         connection_rx: &mut R,
         connection_tx: Arc<Mutex<S>>,
-    ) -> error::Result<ii_bitcoin::Target>
+    ) -> error::Result<(
+        Arc<session::State>,
+        context::Context,
+        Vec<<Framing as ii_wire::Framing>::Rx>,
+        connect_timing::Pending,
+    )>
     where
         R: FrameStream,
         S: FrameSink,
     {
-        self.setup_mining_connection(connection_rx, connection_tx.clone())
-            .await
-            .context("Cannot setup stratum mining connection")?;
-        self.open_channel(connection_rx, connection_tx)
-            .await
-            .context("Cannot open stratum channel")?;
+        if let Err(e) = self.handshake(connection_rx, connection_tx).await {
+            if let error::ErrorKind::Client(error::Client::ConnectionReset(_)) = e.kind() {
+                self.client.handshake_resets.inc();
+            }
+            self.client
+                .store_handshake_transcript(self.context, self.transcript.finish());
+            self.client.lock_connect_history().push(self.timer.finish());
+            self.client.record_failure(self.context, &e);
+            return Err(e);
+        }
 
-        Ok(self.init_target)
+        let session = Arc::new(self.build_session());
+        self.client.set_session(Some(session.clone()));
+        let first_job = self.timer.pending(connect_timing::Phase::FirstJob);
+        self.client
+            .start_startup_phase(startup_budget::Phase::FirstJob);
+        self.client.lock_connect_history().push(self.timer.finish());
+        Ok((session, self.context, self.buffered_frames, first_job))
     }
 }
 
@@ -595,8 +2652,39 @@ impl Handler for StratumConnectionHandler {
     async fn visit_setup_connection_success(
         &mut self,
         _header: &Header,
-        _success_msg: &SetupConnectionSuccess,
+        success_msg: &SetupConnectionSuccess,
     ) {
+        self.transcript
+            .describe_received(format!("{:?}", success_msg));
+        let connection_details = self.client.connection_details();
+        let unknown_flags = success_msg.flags & !capabilities::KNOWN_POOL_FLAGS;
+        if unknown_flags != 0 {
+            match connection_details
+                .config
+                .unknown_pool_flags
+                .unwrap_or_default()
+            {
+                UnknownPoolFlags::Ignore => info!(
+                    "{} Stratum: ignoring unknown flags {:#x} set by the pool",
+                    self.context, unknown_flags
+                ),
+                UnknownPoolFlags::Reject => {
+                    warn!(
+                        "{} Stratum: rejecting connection with unknown flags {:#x} set by the pool",
+                        self.context, unknown_flags
+                    );
+                    self.status = Err(error::Client::UnknownPoolFlags(unknown_flags).into()).into();
+                    return;
+                }
+            }
+        }
+        self.negotiated = Some(status::Negotiated {
+            used_version: success_msg.used_version,
+            flags: success_msg.flags,
+            encrypted: connection_details.is_encrypted(),
+            endpoint_host: connection_details.endpoint_host().to_string(),
+            endpoint_port: connection_details.endpoint_port(),
+        });
         self.status = Ok(()).into();
     }
 
@@ -605,151 +2693,2253 @@ impl Handler for StratumConnectionHandler {
         _header: &Header,
         error_msg: &SetupConnectionError,
     ) {
-        self.status =
-            Err(format!("Setup connection error: {}", error_msg.code.to_string()).into()).into();
+        self.transcript
+            .describe_received(format!("{:?}", error_msg));
+        let code = self.client.post_notice(
+            self.context,
+            notices::Source::SetupConnectionError,
+            &error_msg.code.to_string(),
+        );
+        self.status = Err(format!("Setup connection error: {}", code).into()).into();
+    }
+
+    async fn visit_open_standard_mining_channel_success(
+        &mut self,
+        _header: &Header,
+        success_msg: &OpenStandardMiningChannelSuccess,
+    ) {
+        self.transcript
+            .describe_received(format!("{:?}", success_msg));
+        match target_util::checked_pool_target_from_le_bytes(success_msg.target.as_ref()) {
+            Ok(target) => {
+                if let Err(e) = self.client.check_pool_difficulty(&target) {
+                    self.status = Err(e).into();
+                    return;
+                }
+                self.init_target = target;
+                self.init_target_provided = true;
+            }
+            Err(e) => {
+                // Start mining with the default target, the pool is expected to send a valid
+                // one with `SetTarget`
+                warn!(
+                    "{} Stratum: ignoring initial target: {}, using diff={}",
+                    self.context,
+                    e,
+                    target_util::difficulty_from_target(&self.init_target)
+                );
+                self.client.invalid_targets.inc();
+            }
+        }
+        let channel = session::ChannelIdentity::new(
+            success_msg.channel_id,
+            success_msg.group_channel_id,
+            time::SystemTime::now(),
+        );
+        self.context.session_id = self.client.ids.next_session_id();
+        match channel.group_id {
+            Some(group_id) => info!(
+                "{} Stratum: mining session opened on channel {} in group {}",
+                self.context, channel.channel_id, group_id
+            ),
+            None => info!(
+                "{} Stratum: mining session opened on channel {}",
+                self.context, channel.channel_id
+            ),
+        }
+        self.channel = Some((channel, success_msg.extranonce_prefix.as_ref().to_vec()));
+        self.status = Ok(()).into();
+    }
+
+    async fn visit_open_standard_mining_channel_error(
+        &mut self,
+        _header: &Header,
+        error_msg: &OpenStandardMiningChannelError,
+    ) {
+        self.transcript
+            .describe_received(format!("{:?}", error_msg));
+        let code = self.client.post_notice(
+            self.context,
+            notices::Source::OpenStandardMiningChannelError,
+            &error_msg.code.to_string(),
+        );
+        let permanent = PERMANENT_CHANNEL_ERRORS.contains(&code.as_str());
+        self.status = Err(error::Client::ChannelOpen { code, permanent }.into()).into();
+    }
+}
+
+/// Messages to control the extension channel
+#[derive(Debug)]
+pub enum ExtensionChannelMsg {
+    /// Starts the extension channel
+    Start,
+    /// Stops the extension channel
+    Stop,
+    /// Frame being forwarded into the extension channel
+    Frame(<Framing as ii_wire::Framing>::Tx),
+}
+
+/// Receiver for Stratum <-- Remote direction (stratum client end)
+pub type ExtensionChannelToStratumReceiver = mpsc::Receiver<<Framing as ii_wire::Framing>::Rx>;
+/// Remote sender for Stratum <-- Remote direction (remote end)
+pub type ExtensionChannelToStratumSender = mpsc::Sender<<Framing as ii_wire::Framing>::Tx>;
+
+/// Receiver for Stratum --> Remote direction (remote end)
+pub type ExtensionChannelFromStratumReceiver = mpsc::Receiver<ExtensionChannelMsg>;
+/// Sender for Stratum --> Remote direction (stratum client end)
+pub type ExtensionChannelFromStratumSender = mpsc::Sender<ExtensionChannelMsg>;
+
+/// Transformation of the configured user into the worker name that is sent to the pool when
+/// opening a channel (e.g. prefixing it with a farm ID)
+#[derive(Clone)]
+pub struct WorkerNameHook(Arc<dyn Fn(&str) -> String + Send + Sync>);
+
+impl WorkerNameHook {
+    pub fn new<F>(hook: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        Self(Arc::new(hook))
+    }
+
+    pub fn apply(&self, user: &str) -> String {
+        (self.0)(user)
+    }
+}
+
+impl fmt::Debug for WorkerNameHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WorkerNameHook")
+    }
+}
+
+/// Inspection of each job before it is dispatched to the solver (e.g. to enforce a policy or to
+/// collect metrics). The interceptor passes the job through (possibly transformed) or returns
+/// `None` to skip dispatching it.
+#[derive(Clone)]
+pub struct JobInterceptor(Arc<dyn Fn(StratumJob) -> Option<StratumJob> + Send + Sync>);
+
+impl JobInterceptor {
+    pub fn new<F>(interceptor: F) -> Self
+    where
+        F: Fn(StratumJob) -> Option<StratumJob> + Send + Sync + 'static,
+    {
+        Self(Arc::new(interceptor))
+    }
+
+    pub fn apply(&self, job: StratumJob) -> Option<StratumJob> {
+        (self.0)(job)
+    }
+}
+
+impl fmt::Debug for JobInterceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "JobInterceptor")
+    }
+}
+
+#[derive(Debug, ClientNode)]
+pub struct StratumClient {
+    connection_details: Arc<StdMutex<ConnectionDetails>>,
+    backend_info: Option<hal::BackendInfo>,
+    #[member_status]
+    status: sync::StatusMonitor,
+    /// Accepted, rejected and stale solutions are accounted by a separate task (see `accounting`),
+    /// the meters are eventually consistent and may lag slightly behind the acknowledgements
+    /// received from the pool (or miss solutions dropped when the accounting is stuck)
+    #[member_client_stats]
+    client_stats: stats::BasicClient,
+    /// Records of acknowledged solutions waiting for the accounting into `client_stats`
+    accounting: accounting::Queue,
+    /// Outcomes of shares of the current session and of all sessions
+    share_counts: share_counts::Tiers,
+    stop_sender: mpsc::Sender<()>,
+    stop_receiver: Mutex<mpsc::Receiver<()>>,
+    // Last job has to be weak reference to prevent circular reference (the `StratumJob` keeps
+    // reference to `StratumClient`)
+    last_job: StdMutex<Option<Arc<StratumJob>>>,
+    /// Reference point of the time stored in `last_job_received`
+    created: time::Instant,
+    /// Time (in microseconds since `created` plus one) when the last `NewMiningJob` or
+    /// `SetNewPrevHash` has been received. Zero means that nothing has been received yet.
+    last_job_received: AtomicU64,
+    solutions: SolutionQueue,
+    // NOTE: the job solver is not taken out of the client for the duration of a run (there is
+    // no take/return hand-off that could find it missing). Its halves are owned by the client
+    // and a run that is started while the previous one is still tearing down just waits for
+    // the solution receiver to be released. Both halves are swapped by `replace_solver`.
+    job_sender: StdMutex<job::Sender>,
+    solution_receiver: Mutex<job::SolutionReceiver>,
+    /// Frames received from this channel will be forwarded to the network connection
+    extension_channel_receiver: Mutex<ExtensionChannelToStratumReceiver>,
+    /// Frames intended for the specified extension will be forwarded into this channel (wrapped
+    /// into ExtensionChannelMsg
+    extension_channel_sender: Mutex<ExtensionChannelFromStratumSender>,
+    /// Source of identifiers of connections and sessions
+    ids: context::Counters,
+    health: health::Monitor,
+    /// State of the transmit direction of the current session
+    transmit: StdMutex<transmit::Monitor>,
+    /// Detection of rolling restarts of the pool across connection attempts
+    restart_storm: StdMutex<restart_storm::Detector>,
+    /// Overall deadline of the startup in progress
+    startup_budget: StdMutex<Option<startup_budget::Budget>>,
+    /// Traffic of all connections of the client
+    bandwidth: Arc<bandwidth::Meter>,
+    /// Extension that carries annotations of submitted shares
+    annotation_carrier: StdMutex<Arc<dyn annotation::Carrier>>,
+    /// Annotations of shares waiting for acknowledgement
+    annotations: StdMutex<annotation::Ledger>,
+    /// Timing of the phases of recent connection attempts
+    connect_history: StdMutex<connect_timing::History>,
+    /// Status document served to frequent polls
+    status_cache: status_cache::Cache,
+    /// Synthetic faults armed for a resilience drill
+    faults: faults::Faults,
+    /// Spans of distributed tracing of the connection and of the submissions
+    spans: spans::Tracing,
+    events: events::Log,
+    notifications: notifications::Hub,
+    notices: notices::Board,
+    /// Alternative IDs of jobs that have been announced repeatedly by the pool
+    job_aliases: job_aliases::Aliases,
+    /// Number of jobs that haven't been dispatched because they duplicate a known job
+    duplicate_jobs: stats::CounterUsize,
+    /// Number of jobs that reused the ID of a known job with different content
+    reused_job_ids: stats::CounterUsize,
+    /// Number of future jobs evicted because they have waited for a prevhash for too long
+    aged_future_jobs: stats::CounterUsize,
+    /// Ceiling on the rate of job switches (used only when configured)
+    dispatch_limiter: dispatch_limit::Limiter,
+    /// Number of job updates that have never been dispatched because of the job dispatch limit
+    suppressed_dispatches: stats::CounterUsize,
+    /// Number of job updates that haven't been dispatched because jobs are dispatched only on
+    /// prevhash boundaries
+    deferred_jobs: stats::CounterUsize,
+    /// Number of pool targets that have been clamped to the configured range of difficulty
+    clamped_targets: stats::CounterUsize,
+    /// Verification of share submission ordering (used only when configured)
+    share_ordering: ordering::Verifier,
+    /// Number of detected share ordering violations
+    ordering_violations: stats::CounterUsize,
+    /// Number of detected anomalies in the order of acknowledgements
+    sequencing_anomalies: stats::CounterUsize,
+    /// Number of acknowledgements received while no share has been submitted (protocol anomaly)
+    unexpected_acks: stats::CounterUsize,
+    /// Number of connections that have been established again after the first one
+    connection_retries: stats::CounterUsize,
+    /// Number of attempts to open the channel again on an established connection
+    channel_open_retries: stats::CounterUsize,
+    /// Number of connections reset by the pool before the session has been set up
+    handshake_resets: stats::CounterUsize,
+    /// Targets of the current session
+    targets: StdMutex<status::Targets>,
+    /// Prevhash and future jobs of the current session (published by the event handler)
+    pipeline: pipeline::Published,
+    /// Difficulty suggested by an external difficulty manager, it may have expired already
+    suggestion: StdMutex<Option<suggestion::Suggestion>>,
+    /// State negotiated with the pool for the current session, it is replaced as a whole on
+    /// every successful handshake and cleared when the connection is closed
+    session: StdMutex<Option<Arc<session::State>>>,
+    /// Target currently used for solving jobs (published by the event handler of the session)
+    current_target: StdMutex<Option<ii_bitcoin::Target>>,
+    /// Number of invalid targets received from the pool (protocol errors)
+    invalid_targets: stats::CounterUsize,
+    /// Solutions found by the backend broken down by the local filters
+    filter_stats: filters::Stats,
+    /// Skew between the pool time and the local clock
+    clock_skew: StdMutex<clock_skew::Tracker>,
+    /// Freshness of job templates served by the pool
+    template_quality: StdMutex<template_quality::Tracker>,
+    /// Number of prevhash messages ignored because of implausible `min_ntime`
+    implausible_prevhashes: stats::CounterUsize,
+    /// Number of messages ignored because they have been addressed to a foreign channel
+    foreign_channel_messages: stats::CounterUsize,
+    /// Number of prevhash messages received ahead of the job they reference
+    orphan_prevhashes: stats::CounterUsize,
+    /// Number of share submissions that haven't been sent in time
+    wedged_sends: stats::CounterUsize,
+    /// Number of solutions of jobs flushed by a new prevhash (except when they are submitted
+    /// without counting)
+    flushed_job_solutions: stats::CounterUsize,
+    /// Number of solutions with version bits outside the version rolling mask of their job
+    out_of_mask_versions: stats::CounterUsize,
+    /// Number of channels dropped by the pool before the first share within its deadline (see
+    /// `StratumV2Config::early_share`)
+    early_share_drops: stats::CounterUsize,
+    /// Number of requests to flush work of preceding jobs sent to the work pipeline on a new
+    /// prevhash
+    invalidations: stats::CounterUsize,
+    /// Number of queued work items discarded by the work pipeline on the requests (as reported by
+    /// the pipeline)
+    discarded_work: stats::CounterUsize,
+    /// Number of repeated attempts to send a share submission
+    submit_retries: stats::CounterUsize,
+    /// State of the queue of solutions held back while the submission window is full
+    held_solutions: StdMutex<held::Stats>,
+    /// Secondary consumers of dispatched jobs
+    job_taps: job_taps::Taps<Arc<StratumJob>>,
+    /// Share acceptance per hour for the last 24 hours
+    hourly_shares: hourly::Ring,
+    /// Log of share outcomes (used only when configured)
+    share_log: Option<share_log::Sink>,
+    /// Hashrate estimated from accepted shares with respect to target changes
+    /// TODO: the client opens a single standard channel per connection (see `session::State`),
+    ///  there is no multi-channel mode nor `channels()` snapshot yet. Once channels are opened per
+    ///  hashboard, each channel needs its own estimator (keyed by the channel ID of the accepted
+    ///  solution) exposed in the channel snapshot next to this aggregated one.
+    hashrate: StdMutex<hashrate::Estimator>,
+    /// On-demand probes of the latency of the pool connection
+    prober: probe::Prober,
+    /// Number of solutions queued for acknowledgement by the pool (every one of them is
+    /// eventually accounted as accepted, rejected or stale)
+    submitted: stats::CounterUsize,
+    /// Frames exchanged during the last failed handshake
+    handshake_transcript: StdMutex<Option<transcript::Transcript>>,
+    /// The most recent failure of the client
+    last_error: StdMutex<Option<status::LastError>>,
+    /// Source of the pool user (used only when configured)
+    user_file: StdMutex<Option<user_file::Source>>,
+    /// Transformation of the user into the worker name (identity when not set)
+    worker_name_hook: StdMutex<Option<WorkerNameHook>>,
+    /// Inspection of jobs before they are dispatched (jobs are passed through when not set)
+    job_interceptor: StdMutex<Option<JobInterceptor>>,
+    /// Time between receiving a frame and dispatching the job it has triggered (measured for the
+    /// last dispatched job) in microseconds increased by one, zero when no job has been dispatched
+    dispatch_latency: AtomicU64,
+    #[cfg(feature = "reject-injection")]
+    reject_injector: StdMutex<Option<reject_injector::Injector>>,
+    /// The connection is kept open only to drain submitted shares after restart
+    draining: AtomicBool,
+}
+
+impl StratumClient {
+    /// Interval of checking whether all submitted shares have been drained
+    const DRAIN_POLL_INTERVAL: time::Duration = time::Duration::from_millis(10);
+    const CONNECTION_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+    const EVENT_TIMEOUT: time::Duration = time::Duration::from_secs(150);
+    const SEND_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+    /// Tolerance of the deadline of early share for the latency between the pool and the client
+    const EARLY_SHARE_DEADLINE_SLACK: time::Duration = time::Duration::from_secs(5);
+
+    /// Start a task that plays a dummy role for both communication channels that the stratum
+    /// client uses to talk to stratum extension.
+    fn start_dummy_extension_task(
+        connection_details: ConnectionDetails,
+    ) -> (
+        ExtensionChannelToStratumReceiver,
+        ExtensionChannelFromStratumSender,
+    ) {
+        // Dummy channel for Stratum client --> extension communication
+        let (sender_from_client, mut receiver_from_client) = mpsc::channel(1);
+        // Dummy channel for Stratum client <-- extension communication
+        let (sender_to_client, receiver_to_client) = mpsc::channel(1);
+
+        tokio::spawn(async move {
+            info!(
+                "Stratum extension: starting dummy task[{:?}]... ",
+                connection_details
+            );
+            // Make sure the sender is moved inside the dummy task to prevent it from being
+            // dropped. Otherwise the receiver_to_client would immediately indicate end of stream
+            let _sender_to_client = sender_to_client;
+            //
+            while let Some(message) = receiver_from_client.next().await {
+                info!(
+                    "Stratum extension: dummy task[{:?}] received: {:?},",
+                    connection_details, message
+                );
+            }
+            info!(
+                "Stratum extension: dummy task[{:?}] terminated",
+                connection_details
+            );
+        });
+        (receiver_to_client, sender_from_client)
+    }
+
+    pub fn new(
+        connection_details: ConnectionDetails,
+        backend_info: Option<hal::BackendInfo>,
+        solver: job::Solver,
+        channel: Option<(
+            ExtensionChannelToStratumReceiver,
+            ExtensionChannelFromStratumSender,
+        )>,
+    ) -> Self {
+        let (stop_sender, stop_receiver) = mpsc::channel(1);
+        let share_log =
+            connection_details.config.share_log.as_ref().and_then(
+                |path| match share_log::Sink::open(path) {
+                    Ok(sink) => Some(sink),
+                    Err(e) => {
+                        warn!(
+                            "Stratum: cannot open share log {}, shares won't be logged: {}",
+                            path.display(),
+                            e
+                        );
+                        None
+                    }
+                },
+            );
+
+        // Extract the both channel endpoints that connect the client with the stratum extension
+        // or populate it with dummy endpoints. That way we can handle the endpoints uniformly
+        // regardless whether they are configured or not (see `main_loop()`)
+        // that would handle all events regards
+        let (extension_channel_receiver, extension_channel_sender) = channel.unwrap_or_else(|| {
+            info!(
+                "V2: starting dummy task for client: {:?}",
+                connection_details
+            );
+            Self::start_dummy_extension_task(connection_details.clone())
+        });
+
+        Self {
+            connection_details: Arc::new(StdMutex::new(connection_details)),
+            backend_info,
+            status: Default::default(),
+            client_stats: Default::default(),
+            accounting: Default::default(),
+            share_counts: Default::default(),
+            stop_sender: stop_sender,
+            stop_receiver: Mutex::new(stop_receiver),
+            last_job: StdMutex::new(None),
+            created: time::Instant::now(),
+            last_job_received: AtomicU64::new(0),
+            solutions: Mutex::new(VecDeque::new()),
+            job_sender: StdMutex::new(solver.job_sender),
+            solution_receiver: Mutex::new(solver.solution_receiver),
+            extension_channel_receiver: Mutex::new(extension_channel_receiver),
+            extension_channel_sender: Mutex::new(extension_channel_sender),
+            ids: Default::default(),
+            health: Default::default(),
+            transmit: Default::default(),
+            restart_storm: Default::default(),
+            startup_budget: Default::default(),
+            bandwidth: Default::default(),
+            annotation_carrier: StdMutex::new(Arc::new(annotation::Dormant)),
+            annotations: Default::default(),
+            connect_history: Default::default(),
+            status_cache: Default::default(),
+            faults: Default::default(),
+            spans: Default::default(),
+            events: Default::default(),
+            notifications: Default::default(),
+            notices: Default::default(),
+            job_aliases: Default::default(),
+            duplicate_jobs: Default::default(),
+            reused_job_ids: Default::default(),
+            aged_future_jobs: Default::default(),
+            dispatch_limiter: Default::default(),
+            suppressed_dispatches: Default::default(),
+            deferred_jobs: Default::default(),
+            clamped_targets: Default::default(),
+            share_ordering: Default::default(),
+            ordering_violations: Default::default(),
+            sequencing_anomalies: Default::default(),
+            unexpected_acks: Default::default(),
+            connection_retries: Default::default(),
+            channel_open_retries: Default::default(),
+            handshake_resets: Default::default(),
+            targets: Default::default(),
+            pipeline: Default::default(),
+            suggestion: StdMutex::new(None),
+            session: Default::default(),
+            current_target: StdMutex::new(None),
+            invalid_targets: Default::default(),
+            filter_stats: Default::default(),
+            clock_skew: Default::default(),
+            template_quality: Default::default(),
+            implausible_prevhashes: Default::default(),
+            foreign_channel_messages: Default::default(),
+            orphan_prevhashes: Default::default(),
+            wedged_sends: Default::default(),
+            flushed_job_solutions: Default::default(),
+            out_of_mask_versions: Default::default(),
+            early_share_drops: Default::default(),
+            invalidations: Default::default(),
+            discarded_work: Default::default(),
+            submit_retries: Default::default(),
+            held_solutions: Default::default(),
+            job_taps: Default::default(),
+            submitted: Default::default(),
+            hourly_shares: Default::default(),
+            share_log,
+            hashrate: Default::default(),
+            prober: Default::default(),
+            handshake_transcript: Default::default(),
+            last_error: Default::default(),
+            user_file: Default::default(),
+            worker_name_hook: Default::default(),
+            job_interceptor: Default::default(),
+            dispatch_latency: AtomicU64::new(0),
+            #[cfg(feature = "reject-injection")]
+            reject_injector: Default::default(),
+            draining: AtomicBool::new(false),
+        }
+    }
+
+    /// Create the client with the state handed over by the previous instance of the process when
+    /// the hand-over is configured and its snapshot is present. A snapshot that cannot be
+    /// imported is reported and the client starts from scratch.
+    pub fn with_handover(
+        connection_details: ConnectionDetails,
+        backend_info: Option<hal::BackendInfo>,
+        solver: job::Solver,
+        channel: Option<(
+            ExtensionChannelToStratumReceiver,
+            ExtensionChannelFromStratumSender,
+        )>,
+    ) -> Self {
+        let snapshot = Self::read_handover(&connection_details);
+        let client = Self::new(connection_details, backend_info, solver, channel);
+        if let Some(snapshot) = snapshot {
+            client.restore_state(snapshot);
+        }
+        client
+    }
+
+    /// Create the client with the `state` exported by `export_state`. The snapshot has to belong
+    /// to the endpoint of `connection_details` and it must not be older than the configured
+    /// maximal age (see `StratumV2Handover`).
+    pub fn with_imported_state(
+        connection_details: ConnectionDetails,
+        backend_info: Option<hal::BackendInfo>,
+        solver: job::Solver,
+        channel: Option<(
+            ExtensionChannelToStratumReceiver,
+            ExtensionChannelFromStratumSender,
+        )>,
+        state: &[u8],
+    ) -> handover::Result<Self> {
+        let snapshot = handover::Snapshot::decode(
+            state,
+            &connection_details.endpoint_key(),
+            connection_details.handover_max_age(),
+            time::SystemTime::now(),
+        )?;
+        let client = Self::new(connection_details, backend_info, solver, channel);
+        client.restore_state(snapshot);
+        Ok(client)
+    }
+
+    fn read_handover(connection_details: &ConnectionDetails) -> Option<handover::Snapshot> {
+        let path = &connection_details.config.handover.as_ref()?.path;
+        let state = match std::fs::read(path) {
+            Ok(state) => state,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                warn!(
+                    "Stratum: cannot read handed over state {}: {}",
+                    path.display(),
+                    e
+                );
+                return None;
+            }
+        };
+        match handover::Snapshot::decode(
+            &state,
+            &connection_details.endpoint_key(),
+            connection_details.handover_max_age(),
+            time::SystemTime::now(),
+        ) {
+            Ok(snapshot) => {
+                info!(
+                    "Stratum: importing state of {} handed over in {}",
+                    snapshot.endpoint,
+                    path.display()
+                );
+                Some(snapshot)
+            }
+            Err(e) => {
+                warn!(
+                    "Stratum: handed over state {} is not imported: {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Restore state of a client that hasn't been started yet
+    fn restore_state(&self, snapshot: handover::Snapshot) {
+        *self.targets.lock().expect("BUG: cannot lock targets") = snapshot.targets;
+        if let Some(bucket) = snapshot.hashrate {
+            self.hashrate
+                .lock()
+                .expect("BUG: cannot lock hashrate estimator")
+                .seed(bucket);
+        }
+        let lifetime = snapshot.lifetime;
+        for (meter, totals) in &[
+            (&self.client_stats.accepted, lifetime.accepted),
+            (&self.client_stats.rejected, lifetime.rejected),
+            (&self.client_stats.stale, lifetime.stale),
+        ] {
+            meter.restore(totals.solutions, totals.shares.into());
+        }
+        self.submitted.add(lifetime.submitted as usize);
+        self.connection_retries.add(snapshot.retries.connection);
+        self.channel_open_retries.add(snapshot.retries.channel_open);
+        self.handshake_resets.add(snapshot.retries.handshake_reset);
+        self.hourly_shares.restore(&snapshot.hourly_shares);
+        self.client_stats
+            .best_share
+            .account_difficulty(snapshot.best_share as usize);
+    }
+
+    async fn meter_totals(meter: &stats::Meter) -> handover::Meter {
+        let snapshot = meter.take_snapshot().await;
+        handover::Meter {
+            solutions: snapshot.solutions,
+            shares: snapshot.shares.value(),
+        }
+    }
+
+    /// Returns snapshot of the state that is handed over to the next instance of the process (see
+    /// `handover`)
+    pub async fn export_state(&self) -> Vec<u8> {
+        let snapshot = handover::Snapshot {
+            endpoint: self.connection_details().endpoint_key(),
+            exported_at: time::SystemTime::now(),
+            targets: status::Targets {
+                startup_policy: false,
+                ..self.targets()
+            },
+            hashrate: self.hashrate_buckets().last().cloned(),
+            lifetime: handover::Lifetime {
+                accepted: Self::meter_totals(&self.client_stats.accepted).await,
+                rejected: Self::meter_totals(&self.client_stats.rejected).await,
+                stale: Self::meter_totals(&self.client_stats.stale).await,
+                submitted: *self.submitted.take_snapshot() as u64,
+            },
+            retries: self.retries(),
+            hourly_shares: self
+                .hourly_shares()
+                .into_iter()
+                .filter(|slot| slot.accepted + slot.rejected + slot.stale + slot.reconnects > 0)
+                .collect(),
+            best_share: self
+                .client_stats
+                .best_share
+                .take_snapshot()
+                .map_or(0, |difficulty| *difficulty as u64),
+        };
+        snapshot.encode()
+    }
+
+    /// Write the snapshot of the state for the next instance of the process when the hand-over is
+    /// configured
+    async fn write_handover(&self) {
+        let path = match self.config().handover.as_ref() {
+            Some(handover) => handover.path.clone(),
+            None => return,
+        };
+        let state = self.export_state().await;
+        // The file is replaced at once so that the successor never reads a partial snapshot
+        let partial_path = path.with_extension("partial");
+        if let Err(e) =
+            std::fs::write(&partial_path, state).and_then(|_| std::fs::rename(&partial_path, &path))
+        {
+            warn!(
+                "{} Stratum: cannot write handed over state {}: {}",
+                self.context(),
+                path.display(),
+                e
+            );
+        }
+    }
+
+    pub fn health(&self) -> health::Health {
+        self.health.health()
+    }
+
+    fn lock_connect_history(&self) -> std::sync::MutexGuard<connect_timing::History> {
+        self.connect_history
+            .lock()
+            .expect("BUG: cannot lock connection history")
+    }
+
+    /// Traffic and current rates of the pool connection along with the configured limits
+    pub fn bandwidth(&self) -> bandwidth::Status {
+        let (send_limit, receive_limit) = self.connection_details().bandwidth_limits();
+        self.bandwidth
+            .status_at(time::Instant::now(), send_limit, receive_limit)
+    }
+
+    /// Returns the timing of recent connection attempts in chronological order
+    pub fn connection_history(&self) -> Vec<connect_timing::Attempt> {
+        self.lock_connect_history().attempts()
+    }
+
+    /// Returns recent advisory events in chronological order
+    pub fn events(&self) -> Vec<events::Record> {
+        self.events.snapshot()
+    }
+
+    /// The connected client starts running with the first dispatched job
+    fn job_dispatched(&self) {
+        // Stopping or failing of the client wins over running
+        if self.status.status() == sync::Status::Connected && self.status.initiate_running() {
+            self.notifications
+                .publish(notifications::Notification::StatusChanged(
+                    sync::Status::Running,
+                ));
+        }
+    }
+
+    /// Record the event and publish it to subscribers
+    fn push_event(&self, context: context::Context, event: events::Event) {
+        let record = self.events.push(context, event);
+        self.notifications
+            .publish(notifications::Notification::Event(record));
+    }
+
+    /// Subscribe to notifications about events and changes of the client status, see
+    /// `notifications` for the guarantees at shutdown
+    pub fn subscribe(&self) -> notifications::Subscription {
+        self.notifications.subscribe()
+    }
+
+    /// Returns most recent distinct notices received from the pool
+    pub fn pool_messages(&self) -> Vec<notices::Notice> {
+        self.notices.snapshot()
+    }
+
+    /// Export a chunk of a diagnostic collection for incremental pulling by a remote manager
+    pub fn export_diagnostics(
+        &self,
+        request: &diagnostics::ExportDiagnostics,
+    ) -> diagnostics::Chunk {
+        match request.section {
+            diagnostics::Section::Events => self.events.export(request),
+            diagnostics::Section::PoolNotices => self.notices.export(request),
+            diagnostics::Section::LatencyProbes => self.prober.export(request),
+        }
+    }
+
+    /// Returns identifiers of the current connection and session
+    pub fn context(&self) -> context::Context {
+        context::Context {
+            client_id: self.client_id(),
+            connection_id: self.ids.connection_id(),
+            session_id: self.ids.session_id(),
+        }
+    }
+
+    fn client_id(&self) -> u16 {
+        let connection_details = self.connection_details();
+        context::client_id(&format!(
+            "{}://{}@{}",
+            connection_details.protocol,
+            connection_details.get_host_and_port(),
+            connection_details.user_identity()
+        ))
+    }
+
+    /// Start a new connection attempt and return its context
+    fn new_connection_context(&self) -> context::Context {
+        context::Context {
+            client_id: self.client_id(),
+            connection_id: self.ids.next_connection_id(),
+            session_id: self.ids.session_id(),
+        }
+    }
+
+    /// Returns the state of the job pipeline for support tickets
+    pub fn job_pipeline(&self) -> pipeline::Snapshot {
+        let now = time::Instant::now();
+        let (prev_hash, future_jobs) = self.pipeline.snapshot(now);
+        pipeline::Snapshot {
+            context: self.context(),
+            prev_hash,
+            targets: self.targets(),
+            current_difficulty: self.current_difficulty(),
+            future_jobs,
+            last_job: self.last_job().map(|job| pipeline::Job {
+                job_id: job.id,
+                channel_id: job.channel_id,
+                version: job.version,
+                version_mask: job.version_mask,
+                merkle_root: hex::encode(job.merkle_root.into_inner()),
+                ntime: job.time,
+                max_ntime: job.max_time,
+                nbits: job.bits,
+                difficulty: target_util::difficulty_from_target(&job.target),
+                pool_difficulty: target_util::difficulty_from_target(&job.pool_target),
+            }),
+            last_job_age: self.last_job_age_at(now).map(|age| age.as_millis() as u64),
+            negotiated: self.negotiated(),
+            channel: self.channel_identity(),
+        }
+    }
+
+    /// Serialize `job_pipeline()` to JSON, a single artifact to attach to a support ticket
+    pub fn export_job_pipeline(&self) -> String {
+        serde_json::to_string_pretty(&self.job_pipeline())
+            .expect("BUG: cannot serialize job pipeline")
+    }
+
+    /// Status document assembled from the current state of the client. Consumers that poll the
+    /// status frequently should use `polled_status_document` instead.
+    pub fn status_document(&self) -> status::Document {
+        let status = self.status.status();
+        status::Document {
+            generated_at: time::SystemTime::now(),
+            status: status.to_string(),
+            ready: self.is_ready(status),
+            injected_faults: self.faults.active(time::Instant::now()),
+            context: self.context(),
+            health: self.health(),
+            targets: self.targets(),
+            pool_messages: self.pool_messages(),
+            hourly_shares: self.hourly_shares(),
+            retries: self.retries(),
+            negotiated: self.negotiated(),
+            channel: self.channel_identity(),
+            dispatch_limit: self.dispatch_limit(),
+            held_solutions: self.held_solutions(),
+            pool_time_skew: self.pool_time_skew(),
+            template_quality: self.template_quality(),
+            hashrate: self.hashrate_buckets().last().cloned(),
+            accounting: self.accounting.status_at(time::Instant::now()),
+            shares: self.share_counts.status(),
+            filters: self.filter_stats.status(),
+            suggested_difficulty: self.suggested_difficulty(),
+            restart_storm: self.restart_storm(),
+            startup_budget: self.startup_budget(),
+            connect_phases: self.lock_connect_history().summary(),
+            bandwidth: self.bandwidth(),
+            job_taps: self.job_taps.status(),
+            user_file: self.user_file_status(),
+            last_error: self.last_error(),
+            handshake_transcript: None,
+        }
+    }
+
+    /// The client mines jobs of the pool and submits shares
+    fn is_ready(&self, status: sync::Status) -> bool {
+        status == sync::Status::Running && !self.is_draining()
+    }
+
+    /// Status document that is refreshed at most once within the configured refresh interval,
+    /// see `status_cache`. The status of the client is always current.
+    pub fn polled_status_document(&self) -> status::Document {
+        self.polled_status_document_at(time::Instant::now())
+    }
+
+    fn polled_status_document_at(&self, now: time::Instant) -> status::Document {
+        let interval = time::Duration::from_millis(
+            self.config()
+                .status_refresh_interval
+                .unwrap_or(StratumV2Config::DEFAULT_STATUS_REFRESH_INTERVAL),
+        );
+        let mut document = (*self
+            .status_cache
+            .get(now, interval, || self.status_document()))
+        .clone();
+        let status = self.status.status();
+        document.status = status.to_string();
+        document.ready = self.is_ready(status);
+        document
+    }
+
+    /// Status document extended with diagnostic details
+    pub fn verbose_status_document(&self) -> status::Document {
+        status::Document {
+            handshake_transcript: self.last_handshake_transcript(),
+            ..self.status_document()
+        }
+    }
+
+    /// Returns frames exchanged during the last failed handshake
+    pub fn last_handshake_transcript(&self) -> Option<transcript::Transcript> {
+        self.handshake_transcript
+            .lock()
+            .expect("BUG: cannot lock handshake transcript")
+            .clone()
+    }
+
+    fn user_file_status(&self) -> Option<user_file::Status> {
+        self.user_file
+            .lock()
+            .expect("BUG: cannot lock user file")
+            .as_ref()
+            .map(user_file::Source::status)
+    }
+
+    /// Read the pool user from the user file when it is configured. The user that has been read
+    /// previously is used when the file is not available.
+    fn refresh_user(&self, context: context::Context) -> error::Result<()> {
+        let mut user_file = self.user_file.lock().expect("BUG: cannot lock user file");
+        let path = match self.config().user_file.clone() {
+            Some(path) => path,
+            None => {
+                user_file.take();
+                return Ok(());
+            }
+        };
+        if user_file.as_ref().map(|source| source.path()) != Some(path.as_path()) {
+            user_file.replace(user_file::Source::new(path));
+        }
+        let source = user_file.as_mut().expect("BUG: missing user file");
+
+        let user = match source.read() {
+            Ok(user) => user,
+            Err(e) => match source.user() {
+                Some(user) => {
+                    warn!("{} Stratum: {}, using previously read user", context, e);
+                    user.to_string()
+                }
+                None => return Err(e),
+            },
+        };
+        self.connection_details
+            .lock()
+            .expect("BUG: cannot lock connection details")
+            .user = user;
+        Ok(())
+    }
+
+    /// Transform the worker name with `hook` when opening subsequent channels. The configured user
+    /// is used as it is when no hook is set.
+    pub fn set_worker_name_hook(&self, hook: Option<WorkerNameHook>) {
+        *self
+            .worker_name_hook
+            .lock()
+            .expect("BUG: cannot lock worker name hook") = hook;
+    }
+
+    /// Worker name that is sent to the pool when opening a channel
+    fn worker_name(&self) -> String {
+        let user = self.connection_details().user;
+        match self
+            .worker_name_hook
+            .lock()
+            .expect("BUG: cannot lock worker name hook")
+            .as_ref()
+        {
+            Some(hook) => hook.apply(&user),
+            None => user,
+        }
+    }
+
+    /// Inspect each job with `interceptor` before it is dispatched. A vetoed job is not dispatched
+    /// at all, so the backend keeps solving the previous job (even when the vetoed job has
+    /// switched to a new block).
+    pub fn set_job_interceptor(&self, interceptor: Option<JobInterceptor>) {
+        *self
+            .job_interceptor
+            .lock()
+            .expect("BUG: cannot lock job interceptor") = interceptor;
+    }
+
+    /// Pass `job` through the job interceptor, the interceptor is not called with the lock held
+    fn intercept_job(&self, job: StratumJob) -> Option<StratumJob> {
+        let interceptor = self
+            .job_interceptor
+            .lock()
+            .expect("BUG: cannot lock job interceptor")
+            .clone();
+        match interceptor {
+            Some(interceptor) => interceptor.apply(job),
+            None => Some(job),
+        }
+    }
+
+    /// Replace the extension that carries annotations of submitted shares (see `annotation`)
+    pub fn set_annotation_carrier(&self, carrier: Arc<dyn annotation::Carrier>) {
+        *self
+            .annotation_carrier
+            .lock()
+            .expect("BUG: cannot lock annotation carrier") = carrier;
+    }
+
+    fn annotation_carrier(&self) -> Arc<dyn annotation::Carrier> {
+        self.annotation_carrier
+            .lock()
+            .expect("BUG: cannot lock annotation carrier")
+            .clone()
+    }
+
+    /// The pool accepts annotations of submissions in the current session
+    fn accepts_annotations(&self) -> bool {
+        self.session().map_or(false, |session| {
+            self.annotation_carrier().accepts(&session.negotiated)
+        })
+    }
+
+    fn lock_annotations(&self) -> std::sync::MutexGuard<annotation::Ledger> {
+        self.annotations
+            .lock()
+            .expect("BUG: cannot lock annotations")
+    }
+
+    /// Check the user file for a new user. The change is applied by restarting the session, the
+    /// current session is kept when the file is not available.
+    fn poll_user_file(&self, context: context::Context) -> error::Result<()> {
+        let mut user_file = self.user_file.lock().expect("BUG: cannot lock user file");
+        let source = match user_file.as_mut() {
+            Some(source) => source,
+            None => return Ok(()),
+        };
+        match source.poll() {
+            user_file::Poll::Unchanged => Ok(()),
+            user_file::Poll::Changed => {
+                info!(
+                    "{} Stratum: user in {} has changed, opening a new session",
+                    context,
+                    source.path().display()
+                );
+                Err(error::Client::UserChanged)?
+            }
+            user_file::Poll::Unavailable(reason) => {
+                warn!(
+                    "{} Stratum: user file {} is not available ({}), keeping current session",
+                    context,
+                    source.path().display(),
+                    reason
+                );
+                Ok(())
+            }
+        }
+    }
+
+    fn user_file_poll_interval(&self) -> time::Duration {
+        time::Duration::from_secs(
+            self.config()
+                .user_file_poll_interval
+                .unwrap_or(StratumV2Config::DEFAULT_USER_FILE_POLL_INTERVAL),
+        )
+    }
+
+    fn store_handshake_transcript(
+        &self,
+        context: context::Context,
+        transcript: transcript::Transcript,
+    ) {
+        info!(
+            "{} Stratum: retaining transcript of failed handshake ({} frames)",
+            context,
+            transcript.entries.len()
+        );
+        self.handshake_transcript
+            .lock()
+            .expect("BUG: cannot lock handshake transcript")
+            .replace(transcript);
+    }
+
+    /// Remember the failure that has stopped the client and report it as an event
+    fn record_failure(&self, context: context::Context, e: &error::Error) {
+        let last_error = status::LastError {
+            code: e.error_code(),
+            message: e.to_string(),
+        };
+        self.last_error
+            .lock()
+            .expect("BUG: cannot lock last error")
+            .replace(last_error.clone());
+        // Failures of the individual cycles of a pool restart are summarized when it ends
+        if !self.lock_restart_storm().is_storm() {
+            self.push_event(context, events::Event::Failure(last_error));
+        }
+    }
+
+    pub fn last_error(&self) -> Option<status::LastError> {
+        self.last_error
+            .lock()
+            .expect("BUG: cannot lock last error")
+            .clone()
+    }
+
+    /// Store a string received from the pool and notify the operator about new distinct notices.
+    /// Returns the sanitized string that is safe to be logged.
+    fn post_notice(
+        &self,
+        context: context::Context,
+        source: notices::Source,
+        text: &str,
+    ) -> String {
+        match self.notices.post(source, text, time::SystemTime::now()) {
+            Some(notice) => {
+                info!(
+                    "{} Stratum: pool notice ({:?}): {}",
+                    context, notice.source, notice.text
+                );
+                let text = notice.text.clone();
+                self.push_event(context, events::Event::PoolNotice(notice));
+                text
+            }
+            None => notices::sanitize(text),
+        }
+    }
+
+    pub fn clamped_targets(&self) -> &stats::CounterUsize {
+        &self.clamped_targets
+    }
+
+    /// Number of solutions that meet the local target but not the target requested by the pool
+    pub fn below_pool_target(&self) -> &stats::CounterUsize {
+        self.filter_stats.dropped(filters::Reason::BelowPoolTarget)
+    }
+
+    /// Number of solutions with ntime earlier than min_ntime of the job (hardware errors)
+    pub fn ntime_regressions(&self) -> &stats::CounterUsize {
+        self.filter_stats.dropped(filters::Reason::NtimeRegression)
+    }
+
+    /// Number of solutions with ntime beyond the rolling window of the job
+    pub fn ntime_overruns(&self) -> &stats::CounterUsize {
+        self.filter_stats.dropped(filters::Reason::NtimeOverrun)
+    }
+
+    /// Number of solutions of jobs that have not been generated by the client
+    pub fn foreign_solutions(&self) -> &stats::CounterUsize {
+        self.filter_stats.dropped(filters::Reason::ForeignJob)
+    }
+
+    /// Solutions found by the backend broken down by the local filters
+    pub fn filter_stats(&self) -> &filters::Stats {
+        &self.filter_stats
+    }
+
+    /// Start counting solutions of the local filters from zero
+    pub fn reset_filter_stats(&self) {
+        self.filter_stats.reset();
+    }
+
+    pub fn wedged_sends(&self) -> &stats::CounterUsize {
+        &self.wedged_sends
+    }
+
+    pub fn flushed_job_solutions(&self) -> &stats::CounterUsize {
+        &self.flushed_job_solutions
+    }
+
+    pub fn out_of_mask_versions(&self) -> &stats::CounterUsize {
+        &self.out_of_mask_versions
+    }
+
+    pub fn early_share_drops(&self) -> &stats::CounterUsize {
+        &self.early_share_drops
+    }
+
+    pub fn invalidations(&self) -> &stats::CounterUsize {
+        &self.invalidations
+    }
+
+    pub fn discarded_work(&self) -> &stats::CounterUsize {
+        &self.discarded_work
+    }
+
+    fn early_share(&self) -> Option<StratumV2EarlyShare> {
+        self.config().early_share.clone()
+    }
+
+    fn difficulty_suggestion_ttl(&self) -> time::Duration {
+        time::Duration::from_secs(
+            self.config()
+                .difficulty_suggestion_ttl
+                .unwrap_or(StratumV2Config::DEFAULT_DIFFICULTY_SUGGESTION_TTL),
+        )
+    }
+
+    /// Override the difficulty the client derives locally when opening the channel with
+    /// `difficulty` suggested by an external difficulty manager. The suggestion expires after the
+    /// configured lifetime (see `StratumV2Config::difficulty_suggestion_ttl`) unless it is set
+    /// again. Suggestions outside of the configured range of difficulty are rejected.
+    pub fn set_suggested_difficulty(
+        &self,
+        difficulty: f64,
+        source: suggestion::Source,
+    ) -> error::Result<()> {
+        self.set_suggested_difficulty_at(difficulty, source, time::Instant::now())
+    }
+
+    fn set_suggested_difficulty_at(
+        &self,
+        difficulty: f64,
+        source: suggestion::Source,
+        now: time::Instant,
+    ) -> error::Result<()> {
+        let config = self.config();
+        let invalid = |reason: String| -> error::Error {
+            error::Client::InvalidSuggestion(format!("difficulty {} {}", difficulty, reason)).into()
+        };
+        if !difficulty.is_finite() || difficulty < 1.0 {
+            Err(invalid("is not a number of at least 1".to_string()))?;
+        }
+        if let Some(min_difficulty) = config.min_difficulty {
+            if difficulty < min_difficulty as f64 {
+                Err(invalid(format!("is below the floor {}", min_difficulty)))?;
+            }
+        }
+        if let Some(max_difficulty) = config.max_difficulty {
+            if difficulty > max_difficulty as f64 {
+                Err(invalid(format!("is above the ceiling {}", max_difficulty)))?;
+            }
+        }
+        info!(
+            "Stratum: using difficulty {} suggested by {:?}",
+            difficulty, source
+        );
+        self.suggestion
+            .lock()
+            .expect("BUG: cannot lock difficulty suggestion")
+            .replace(suggestion::Suggestion {
+                difficulty,
+                source,
+                set: now,
+            });
+        Ok(())
+    }
+
+    /// Returns the suggested difficulty unless it has expired
+    fn effective_suggestion(&self, now: time::Instant) -> Option<suggestion::Suggestion> {
+        let ttl = self.difficulty_suggestion_ttl();
+        let mut suggestion = self
+            .suggestion
+            .lock()
+            .expect("BUG: cannot lock difficulty suggestion");
+        if suggestion.map_or(false, |suggestion| suggestion.is_expired(ttl, now)) {
+            info!("Stratum: difficulty suggestion has expired, reverting to local heuristics");
+            suggestion.take();
+        }
+        *suggestion
+    }
+
+    /// Returns the effective difficulty suggestion
+    pub fn suggested_difficulty(&self) -> Option<suggestion::Status> {
+        let now = time::Instant::now();
+        self.effective_suggestion(now)
+            .map(|suggestion| suggestion.status(self.difficulty_suggestion_ttl(), now))
+    }
+
+    /// Maximal target requested when opening the channel. This is the only place where the
+    /// suggested difficulty is resolved, the later step always wins:
+    ///
+    /// 1. difficulty 1 (the pool is free to choose any target)
+    /// 2. `early_share` - the target sized to the deadline of the pool
+    /// 3. difficulty suggested by an external difficulty manager until it expires (the startup
+    ///    target policy is suspended meanwhile, see `new_startup_target`)
+    /// 4. `max_difficulty` ceiling and `min_difficulty` floor limit the suggested difficulty (they
+    ///    may have been changed after the suggestion has been accepted)
+    fn channel_max_target(&self, now: time::Instant) -> ii_bitcoin::Target {
+        let config = self.config();
+        if let Some(suggestion) = self.effective_suggestion(now) {
+            let mut difficulty = suggestion.difficulty.round() as usize;
+            if let Some(max_difficulty) = config.max_difficulty {
+                difficulty = difficulty.min(max_difficulty);
+            }
+            if let Some(min_difficulty) = config.min_difficulty {
+                difficulty = difficulty.max(min_difficulty);
+            }
+            return target_util::target_from_difficulty(difficulty.max(1));
+        }
+        match config.early_share.as_ref() {
+            // A pool that requires a share within a deadline is asked for the target sized to it
+            Some(early_share) => Self::early_share_target(early_share),
+            // Maximum bitcoin target is 0xffff << 208 (= difficulty 1 share)
+            // The pool may echo this value back so it must pass our own target validation
+            None => target_util::difficulty_1_target(),
+        }
+    }
+
+    /// Target at which a miner with the nominal hashrate is expected to find the configured
+    /// number of shares within the deadline of the pool
+    pub fn early_share_target(early_share: &StratumV2EarlyShare) -> ii_bitcoin::Target {
+        let expected_shares = early_share
+            .expected_shares
+            .unwrap_or(StratumV2EarlyShare::DEFAULT_EXPECTED_SHARES);
+        target_util::target_from_hashrate(
+            ii_bitcoin::HashesUnit::TeraHashes(early_share.nominal_hashrate),
+            expected_shares * 60.0 / early_share.deadline as f64,
+        )
+    }
+
+    /// Check whether a session that has failed after `duration` without submitting any share has
+    /// been dropped by a pool that requires a share within the deadline of the early share
+    /// configuration. Returns the deadline when it is the case.
+    fn early_share_deadline_missed(
+        &self,
+        duration: time::Duration,
+        shares_submitted: usize,
+    ) -> Option<time::Duration> {
+        let deadline = time::Duration::from_secs(self.early_share()?.deadline);
+        // The pool measures the deadline from sending the response to the channel open
+        if shares_submitted > 0 || duration + Self::EARLY_SHARE_DEADLINE_SLACK < deadline {
+            return None;
+        }
+        Some(deadline)
+    }
+
+    /// Returns skew between the pool time and the local clock once it has been measured
+    pub fn pool_time_skew(&self) -> Option<clock_skew::Skew> {
+        self.clock_skew
+            .lock()
+            .expect("BUG: cannot lock clock skew")
+            .skew()
+    }
+
+    fn lock_template_quality(&self) -> std::sync::MutexGuard<template_quality::Tracker> {
+        self.template_quality
+            .lock()
+            .expect("BUG: cannot lock template quality")
+    }
+
+    /// Returns freshness of job templates served by the pool
+    pub fn template_quality(&self) -> template_quality::Quality {
+        self.lock_template_quality().quality()
+    }
+
+    /// Measure latency of the pool connection without submitting any work, see `probe` for the
+    /// method and its caveats. Fails right away when the client is not connected.
+    pub async fn probe_latency(&self, timeout: time::Duration) -> error::Result<probe::Probe> {
+        let started = time::Instant::now();
+        let time = time::SystemTime::now();
+        let receiver = self.prober.start().ok_or(error::Client::NotConnected)?;
+        let rtt = match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(received)) => Some(received.saturating_duration_since(started)),
+            // The connection has been closed while waiting
+            Ok(Err(_)) => Err(error::Client::NotConnected)?,
+            Err(_) => None,
+        };
+        let probe = probe::Probe {
+            method: probe::Method::NextFrame,
+            time,
+            rtt_ms: rtt.map(|rtt| rtt.as_millis() as u64),
+        };
+        self.prober.record(probe.clone());
+        Ok(probe)
+    }
+
+    /// Returns recent latency probes in chronological order
+    pub fn latency_probes(&self) -> Vec<probe::Probe> {
+        self.prober.history()
+    }
+
+    /// Returns hashrate estimated in finished buckets in chronological order
+    pub fn hashrate_buckets(&self) -> Vec<hashrate::Bucket> {
+        self.hashrate
+            .lock()
+            .expect("BUG: cannot lock hashrate estimator")
+            .buckets(time::Instant::now())
+    }
+
+    pub fn implausible_prevhashes(&self) -> &stats::CounterUsize {
+        &self.implausible_prevhashes
+    }
+
+    pub fn foreign_channel_messages(&self) -> &stats::CounterUsize {
+        &self.foreign_channel_messages
+    }
+
+    pub fn orphan_prevhashes(&self) -> &stats::CounterUsize {
+        &self.orphan_prevhashes
+    }
+
+    pub fn submit_retries(&self) -> &stats::CounterUsize {
+        &self.submit_retries
+    }
+
+    /// Number of solutions dropped because the submission window has been full
+    pub fn window_drops(&self) -> &stats::CounterUsize {
+        self.filter_stats.dropped(filters::Reason::WindowFull)
+    }
+
+    pub fn submitted(&self) -> &stats::CounterUsize {
+        &self.submitted
+    }
+
+    /// Returns number of share outcomes that haven't been logged because the share log has been
+    /// lagging behind (`None` when the share log is not configured)
+    pub fn share_log_dropped(&self) -> Option<usize> {
+        self.share_log.as_ref().map(|sink| sink.dropped())
+    }
+
+    /// Returns number of failed writes of the share log (`None` when the share log is not
+    /// configured)
+    pub fn share_log_failed_writes(&self) -> Option<usize> {
+        self.share_log.as_ref().map(|sink| sink.failed_writes())
+    }
+
+    /// Returns share acceptance per hour for the last 24 hours in chronological order
+    pub fn hourly_shares(&self) -> Vec<hourly::Slot> {
+        self.hourly_shares.snapshot(time::SystemTime::now())
+    }
+
+    /// Acknowledge submitted shares according to the `injector` instead of submitting them to the
+    /// pool, see `reject_injector` for details
+    #[cfg(feature = "reject-injection")]
+    pub fn set_reject_injector(&self, injector: Option<reject_injector::Injector>) {
+        *self
+            .reject_injector
+            .lock()
+            .expect("BUG: cannot lock reject injector") = injector;
+    }
+
+    /// Arm a synthetic `fault` for a resilience drill, see `faults` for details. It fails unless
+    /// fault injection is enabled in the configuration.
+    #[cfg(feature = "fault-injection")]
+    pub fn arm_fault(&self, fault: faults::Fault) -> error::Result<()> {
+        if self.config().fault_injection != Some(true) {
+            Err(error::Client::FaultInjectionDisabled)?
+        }
+        let context = self.context();
+        warn!("{} Stratum: DRILL: fault armed: {}", context, fault);
+        let replaced = self.faults.arm(fault, time::Instant::now());
+        if let Some(replaced) = replaced {
+            self.push_event(context, events::Event::FaultDisarmed(replaced));
+        }
+        self.push_event(context, events::Event::FaultArmed(fault));
+        Ok(())
+    }
+
+    /// Disarm all synthetic faults before they run out of their scope
+    #[cfg(feature = "fault-injection")]
+    pub fn disarm_faults(&self) {
+        self.faults.disarm_all();
+        self.report_disarmed_faults(self.context());
+    }
+
+    /// Consult the armed faults at an injection point, faults that have run out of their scope
+    /// are reported
+    fn inject_fault<T, F>(&self, context: context::Context, inject: F) -> T
+    where
+        F: FnOnce(&faults::Faults, time::Instant) -> T,
+    {
+        let injected = inject(&self.faults, time::Instant::now());
+        self.report_disarmed_faults(context);
+        injected
+    }
+
+    fn report_disarmed_faults(&self, context: context::Context) {
+        for fault in self.faults.take_disarmed() {
+            warn!("{} Stratum: DRILL: fault disarmed: {}", context, fault);
+            self.push_event(context, events::Event::FaultDisarmed(fault));
+        }
+    }
+
+    /// Close the connection when the forced disconnect armed for a drill is due
+    fn force_disconnect(&self, context: context::Context) -> error::Result<()> {
+        if let Some(fault) = self.inject_fault(context, |faults, now| faults.disconnect(now)) {
+            warn!(
+                "{} Stratum: DRILL: connection is closed: {}",
+                context, fault
+            );
+            Err(error::Client::InjectedFault(fault.to_string()))?
+        }
+        Ok(())
+    }
+
+    pub fn dispatch_latency(&self) -> Option<time::Duration> {
+        match self.dispatch_latency.load(Ordering::Relaxed) {
+            0 => None,
+            latency => Some(time::Duration::from_micros(latency - 1)),
+        }
+    }
+
+    fn ntime_tolerance(&self) -> u32 {
+        self.config().ntime_tolerance.unwrap_or_default()
+    }
+
+    fn transmit_thresholds(&self) -> transmit::Thresholds {
+        let transmit_stall = self.config().transmit_stall.clone().unwrap_or_default();
+        let millis =
+            |value: Option<u64>, default| time::Duration::from_millis(value.unwrap_or(default));
+        transmit::Thresholds {
+            slow_send: millis(
+                transmit_stall.slow_send,
+                StratumV2TransmitStall::DEFAULT_SLOW_SEND,
+            ),
+            ack_timeout: millis(
+                transmit_stall.ack_timeout,
+                StratumV2TransmitStall::DEFAULT_ACK_TIMEOUT,
+            ),
+            grace: millis(transmit_stall.grace, StratumV2TransmitStall::DEFAULT_GRACE),
+        }
+    }
+
+    /// The job sender is locked by the main task and by `replace_solver`, which waits until the
+    /// main loop releases the solution receiver between events. Job dispatch therefore never waits
+    /// for this lock, though it may briefly wait for other locks on its path (see `update_job`).
+    fn lock_job_sender(&self) -> std::sync::MutexGuard<job::Sender> {
+        self.job_sender.lock().expect("BUG: cannot lock job sender")
+    }
+
+    /// Swap the job solver (e.g. when the mining backend is reconfigured without stopping the
+    /// client) and return the previous one.
+    ///
+    /// The solvers are swapped at a safe point: the running main loop holds the solution receiver
+    /// while it processes an event (a frame from the pool that may dispatch a job or a solution
+    /// that is being submitted) and releases it only between events, so no job is dispatched and
+    /// no solution is received during the swap. When the client isn't running the solvers are
+    /// swapped right away. The work of the previous solver is invalidated and the last job is
+    /// dispatched to the new one when the client is running, so mining continues without waiting
+    /// for the next job. Solutions still buffered in the previous solver are not submitted.
+    pub async fn replace_solver(&self, solver: job::Solver) -> job::Solver {
+        let mut solution_receiver = self.solution_receiver.lock().await;
+        let old_solution_receiver =
+            std::mem::replace(&mut *solution_receiver, solver.solution_receiver);
+        let old_job_sender = {
+            let mut job_sender = self.lock_job_sender();
+            let old_job_sender = std::mem::replace(&mut *job_sender, solver.job_sender);
+            old_job_sender.invalidate();
+            if self.status.status() == sync::Status::Running && !self.is_draining() {
+                if let Some(job) = self.last_job() {
+                    job_sender.send(job);
+                }
+            }
+            old_job_sender
+        };
+        info!("{} Stratum: job solver has been replaced", self.context());
+        job::Solver {
+            job_sender: old_job_sender,
+            solution_receiver: old_solution_receiver,
+        }
+    }
+
+    fn lock_transmit(&self) -> std::sync::MutexGuard<transmit::Monitor> {
+        self.transmit
+            .lock()
+            .expect("BUG: cannot lock transmit monitor")
+    }
+
+    /// Classify the transmit direction after a frame has been received. The stall is reported
+    /// once and the connection is restarted when it persists for the grace period.
+    fn check_transmit(&self, context: context::Context, now: time::Instant) -> error::Result<()> {
+        let thresholds = self.transmit_thresholds();
+        let verdict = self.lock_transmit().check_at(now, &thresholds);
+        match verdict {
+            transmit::Verdict::Stalled(stall) => {
+                warn!(
+                    "{} Stratum: share submissions stall while the pool keeps sending frames: {}",
+                    context, stall
+                );
+                self.health.raise(health::DegradedReason::TransmitStalled);
+                self.push_event(context, events::Event::TransmitStalled(stall));
+            }
+            transmit::Verdict::Expired(stall) => {
+                Err(error::Client::TransmitStalled(stall.to_string()))?;
+            }
+            transmit::Verdict::Recovered => {
+                if self.health.clear(health::DegradedReason::TransmitStalled) {
+                    info!("{} Stratum: share submissions flow again", context);
+                }
+            }
+            transmit::Verdict::Healthy | transmit::Verdict::Persisting => {}
+        }
+        Ok(())
+    }
+
+    /// Thresholds of the detection of pool restarts, nothing is detected when not configured
+    fn restart_storm_thresholds(&self) -> Option<restart_storm::Thresholds> {
+        let restart_storm = self.config().restart_storm.clone()?;
+        let secs =
+            |value: Option<u64>, default| time::Duration::from_secs(value.unwrap_or(default));
+        Some(restart_storm::Thresholds {
+            short_session: secs(
+                restart_storm.short_session,
+                StratumV2RestartStorm::DEFAULT_SHORT_SESSION,
+            ),
+            sessions: restart_storm
+                .sessions
+                .unwrap_or(StratumV2RestartStorm::DEFAULT_SESSIONS),
+            window: secs(restart_storm.window, StratumV2RestartStorm::DEFAULT_WINDOW),
+            retry_interval: secs(
+                restart_storm.retry_interval,
+                StratumV2RestartStorm::DEFAULT_RETRY_INTERVAL,
+            ),
+            max_duration: secs(
+                restart_storm.max_duration,
+                StratumV2RestartStorm::DEFAULT_MAX_DURATION,
+            ),
+        })
+    }
+
+    fn lock_restart_storm(&self) -> std::sync::MutexGuard<restart_storm::Detector> {
+        self.restart_storm
+            .lock()
+            .expect("BUG: cannot lock restart storm detector")
+    }
+
+    fn restart_storm_ended(&self, context: context::Context, summary: restart_storm::Summary) {
+        match summary.outcome {
+            restart_storm::Outcome::Recovered => {
+                info!(
+                    "{} Stratum: pool restart has finished: {}",
+                    context, summary
+                )
+            }
+            restart_storm::Outcome::Exceeded => {
+                warn!(
+                    "{} Stratum: pool restart hasn't finished: {}",
+                    context, summary
+                )
+            }
+        }
+        self.push_event(context, events::Event::PoolRestart(summary));
+    }
+
+    /// Account the failure of the session started by the last connection attempt
+    fn restart_storm_session_failed_at(&self, context: context::Context, now: time::Instant) {
+        let thresholds = match self.restart_storm_thresholds() {
+            Some(thresholds) => thresholds,
+            None => return,
+        };
+        let verdict = self.lock_restart_storm().session_failed(now, &thresholds);
+        match verdict {
+            restart_storm::Verdict::Started { cycles } => warn!(
+                "{} Stratum: {} short sessions within {}s, the pool is restarting, retrying every {}s",
+                context,
+                cycles,
+                thresholds.window.as_secs(),
+                thresholds.retry_interval.as_secs()
+            ),
+            restart_storm::Verdict::Ended(summary) => self.restart_storm_ended(context, summary),
+            restart_storm::Verdict::Normal | restart_storm::Verdict::Continuing => {}
+        }
+    }
+
+    /// The current session has outlasted the short session threshold
+    fn restart_storm_session_survived_at(&self, context: context::Context, now: time::Instant) {
+        let summary = self.lock_restart_storm().session_survived(now);
+        if let Some(summary) = summary {
+            self.restart_storm_ended(context, summary);
+        }
+    }
+
+    /// Returns the storm in progress at `now`, the storm that has exceeded the bound is ended
+    fn restart_storm_at(&self, now: time::Instant) -> Option<restart_storm::Status> {
+        let thresholds = self.restart_storm_thresholds()?;
+        let (summary, status) = {
+            let mut restart_storm = self.lock_restart_storm();
+            let summary = restart_storm.expire_at(now, &thresholds);
+            (summary, restart_storm.status(now, &thresholds))
+        };
+        if let Some(summary) = summary {
+            self.restart_storm_ended(self.context(), summary);
+        }
+        status
+    }
+
+    /// Returns the pool restart in progress
+    pub fn restart_storm(&self) -> Option<restart_storm::Status> {
+        self.restart_storm_at(time::Instant::now())
+    }
+
+    /// Delay of the next connection attempt, the connection is retried right away unless the pool
+    /// is restarting
+    fn reconnect_delay_at(&self, now: time::Instant) -> Option<time::Duration> {
+        self.restart_storm_at(now)?;
+        self.restart_storm_thresholds()
+            .map(|thresholds| thresholds.retry_interval)
+    }
+
+    /// Time until the current session outlasts the short session threshold while there is a pool
+    /// restart to recover from
+    fn restart_storm_recovery_delay(&self, now: time::Instant) -> Option<time::Duration> {
+        let thresholds = self.restart_storm_thresholds()?;
+        self.lock_restart_storm().recovery_delay(now, &thresholds)
+    }
+
+    fn submit_attempts(&self) -> usize {
+        self.config()
+            .submit_attempts
+            .unwrap_or(StratumV2Config::DEFAULT_SUBMIT_ATTEMPTS)
+    }
+
+    fn held_solutions_max_bytes(&self) -> usize {
+        self.config()
+            .held_solutions_max_bytes
+            .unwrap_or(StratumV2Config::DEFAULT_HELD_SOLUTIONS_MAX_BYTES)
+    }
+
+    /// Stream of jobs dispatched to the solver from now on. The stream buffers at most `buffer`
+    /// jobs, the oldest job is dropped when the consumer lags behind (see `job_taps`).
+    pub fn job_stream(&self, buffer: usize) -> impl Stream<Item = Arc<StratumJob>> + Send + Unpin {
+        self.job_taps.open(buffer)
+    }
+
+    /// Returns the state of the queue of solutions held back while the submission window is full
+    pub fn held_solutions(&self) -> held::Stats {
+        *self
+            .held_solutions
+            .lock()
+            .expect("BUG: cannot lock held solutions")
+    }
+
+    fn update_held_solutions(&self, stats: held::Stats) {
+        *self
+            .held_solutions
+            .lock()
+            .expect("BUG: cannot lock held solutions") = stats;
+    }
+
+    fn submission_window(&self) -> Option<usize> {
+        self.config().submission_window
+    }
+
+    /// Handling of solutions of jobs flushed by a new prevhash
+    fn flushed_job_solutions_policy(&self) -> FlushedJobSolutions {
+        self.config().flushed_job_solutions.unwrap_or_default()
+    }
+
+    fn nonce_byte_order(&self) -> NonceByteOrder {
+        self.config().nonce_byte_order.unwrap_or_default()
+    }
+
+    fn out_of_mask_versions_policy(&self) -> OutOfMaskVersions {
+        self.config().out_of_mask_versions.unwrap_or_default()
+    }
+
+    /// Returns true when `job` has been flushed by a new prevhash, i.e. the most recently
+    /// dispatched job builds on a different block
+    fn is_flushed(&self, job: &StratumJob) -> bool {
+        self.last_job()
+            .map_or(false, |last_job| last_job.prev_hash != job.prev_hash)
+    }
+
+    fn submission_window_policy(&self) -> SubmissionWindowPolicy {
+        self.config().submission_window_policy.unwrap_or_default()
+    }
+
+    fn channel_open_backoff(&self) -> Vec<time::Duration> {
+        self.config()
+            .channel_open_backoff
+            .clone()
+            .unwrap_or_else(|| StratumV2Config::DEFAULT_CHANNEL_OPEN_BACKOFF.to_vec())
+            .into_iter()
+            .map(time::Duration::from_millis)
+            .collect()
+    }
+
+    /// Delay of the first connection attempt, the random part is drawn again on every call
+    fn startup_delay(&self) -> time::Duration {
+        let config = self.config();
+        let jitter = match config.startup_jitter {
+            // Random keys of the std hasher are a sufficient source of randomness for spreading
+            // connections of a fleet
+            Some(jitter) if jitter > 0 => {
+                RandomState::new().build_hasher().finish() % jitter.saturating_add(1)
+            }
+            _ => 0,
+        };
+        time::Duration::from_millis(config.startup_delay.unwrap_or(0).saturating_add(jitter))
+    }
+
+    fn lock_startup_budget(&self) -> std::sync::MutexGuard<Option<startup_budget::Budget>> {
+        self.startup_budget
+            .lock()
+            .expect("BUG: cannot lock startup budget")
+    }
+
+    /// Start the budget of the startup measured from `started`. The budget of a reconnect attempt
+    /// is used when the client is retrying after a failure.
+    fn begin_startup_budget(&self, started: time::Instant) {
+        let reconnect = matches!(
+            self.status.status(),
+            sync::Status::Retrying | sync::Status::Recovering
+        );
+        let budgets = startup_budget::Budgets::resolve(
+            &self.config().startup_budget.clone().unwrap_or_default(),
+            reconnect,
+        );
+        self.lock_startup_budget()
+            .replace(startup_budget::Budget::new(
+                budgets,
+                reconnect,
+                Arc::new(connect_timing::SystemClock),
+                started,
+            ));
+    }
+
+    fn start_startup_phase(&self, phase: startup_budget::Phase) {
+        if let Some(budget) = self.lock_startup_budget().as_mut() {
+            budget.start(phase);
+        }
+    }
+
+    /// The startup has completed (or failed), the budget is not enforced anymore
+    fn finish_startup_budget(&self) {
+        self.lock_startup_budget().take();
+    }
+
+    /// Timeout of a startup step that is otherwise limited by `timeout`
+    fn startup_timeout(&self, timeout: time::Duration) -> time::Duration {
+        self.lock_startup_budget()
+            .as_ref()
+            .map_or(timeout, |budget| budget.remaining().min(timeout))
+    }
+
+    /// Time remaining until the startup budget runs out
+    fn startup_budget_delay(&self) -> Option<time::Duration> {
+        self.lock_startup_budget()
+            .as_ref()
+            .map(startup_budget::Budget::remaining)
+    }
+
+    /// Returns the error the startup fails with when its budget has run out
+    fn startup_budget_error(&self) -> Option<error::Error> {
+        self.lock_startup_budget()
+            .as_ref()
+            .filter(|budget| budget.is_exhausted())
+            .map(startup_budget::Budget::error)
+    }
+
+    /// Remember failure `e` of a startup step that is to be retried after `delay`. Returns false
+    /// when the budget doesn't leave time for the retry.
+    fn retry_startup_step(&self, e: &error::Error, delay: time::Duration) -> bool {
+        match self.lock_startup_budget().as_mut() {
+            Some(budget) => {
+                budget.record(e.kind());
+                budget.remaining() > delay
+            }
+            None => true,
+        }
+    }
+
+    /// Returns remaining budget of the startup in progress
+    pub fn startup_budget(&self) -> Option<startup_budget::Status> {
+        self.lock_startup_budget()
+            .as_ref()
+            .map(startup_budget::Budget::status)
+    }
+
+    fn restart_drain_grace(&self) -> Option<time::Duration> {
+        self.config()
+            .restart_drain_grace
+            .map(time::Duration::from_millis)
+    }
+
+    fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Keep the connection driven by `run` open after restart has been initiated until the pool
+    /// acknowledges all submitted shares or until the configured grace period expires. Jobs are
+    /// invalidated and no new shares are submitted while draining. Returns immediately when the
+    /// client is not restarting or when the grace period is not configured.
+    async fn drain_submissions<F>(&self, mut run: &mut F)
+    where
+        F: FusedFuture<Output = ()> + Unpin,
+    {
+        let grace = match self.restart_drain_grace() {
+            Some(grace) if self.status.status() == sync::Status::Restarting => grace,
+            _ => return,
+        };
+        let outstanding = self.solutions.lock().await.len();
+        if outstanding == 0 {
+            return;
+        }
+        info!(
+            "{} Stratum: draining {} submitted shares before restart",
+            self.context(),
+            outstanding
+        );
+        self.lock_job_sender().invalidate();
+        self.draining.store(true, Ordering::Relaxed);
+        let drained = async {
+            while !self.solutions.lock().await.is_empty() {
+                tokio::time::delay_for(Self::DRAIN_POLL_INTERVAL).await;
+            }
+        };
+        select! {
+            _ = run => {}
+            _ = drained.fuse() => {}
+            _ = tokio::time::delay_for(grace).fuse() => {}
+        }
+        self.draining.store(false, Ordering::Relaxed);
+    }
+
+    fn pending_jobs_log_interval(&self) -> Option<time::Duration> {
+        self.config()
+            .pending_jobs_log_interval
+            .map(time::Duration::from_secs)
+    }
+
+    fn future_job_max_age(&self) -> Option<time::Duration> {
+        self.config()
+            .future_job_max_age
+            .map(time::Duration::from_secs)
+    }
+
+    fn set_target_window(&self) -> Option<time::Duration> {
+        self.config()
+            .set_target_window
+            .map(time::Duration::from_secs)
+    }
+
+    fn target_application(&self) -> TargetApplication {
+        self.config().target_application.unwrap_or_default()
+    }
+
+    pub fn duplicate_jobs(&self) -> &stats::CounterUsize {
+        &self.duplicate_jobs
+    }
+
+    pub fn reused_job_ids(&self) -> &stats::CounterUsize {
+        &self.reused_job_ids
+    }
+
+    pub fn aged_future_jobs(&self) -> &stats::CounterUsize {
+        &self.aged_future_jobs
+    }
+
+    pub fn suppressed_dispatches(&self) -> &stats::CounterUsize {
+        &self.suppressed_dispatches
+    }
+
+    /// Returns outcomes of shares acknowledged in the current session (since the last connection
+    /// has been established)
+    pub fn session_shares(&self) -> share_counts::Counts {
+        self.share_counts.session()
+    }
+
+    /// Returns outcomes of shares acknowledged since the client has been created
+    pub fn cumulative_shares(&self) -> share_counts::Counts {
+        self.share_counts.cumulative()
+    }
+
+    pub fn deferred_jobs(&self) -> &stats::CounterUsize {
+        &self.deferred_jobs
+    }
+
+    fn job_dispatch_policy(&self) -> JobDispatchPolicy {
+        self.config().job_dispatch_policy.unwrap_or_default()
+    }
+
+    /// Returns state of the job dispatch limit when it is configured
+    pub fn dispatch_limit(&self) -> Option<status::DispatchLimit> {
+        let tokens = self.dispatch_limiter.tokens(time::Instant::now())?;
+        Some(status::DispatchLimit {
+            tokens,
+            suppressed: *self.suppressed_dispatches.take_snapshot(),
+        })
+    }
+
+    pub fn invalid_targets(&self) -> &stats::CounterUsize {
+        &self.invalid_targets
+    }
+
+    /// Returns targets of the current session
+    pub fn targets(&self) -> status::Targets {
+        self.targets
+            .lock()
+            .expect("BUG: cannot lock targets")
+            .clone()
+    }
+
+    fn set_session(&self, session: Option<Arc<session::State>>) {
+        *self.session.lock().expect("BUG: cannot lock session") = session;
+    }
+
+    /// Returns state of the current session, `None` is returned while no session is established
+    pub fn session(&self) -> Option<Arc<session::State>> {
+        self.session
+            .lock()
+            .expect("BUG: cannot lock session")
+            .clone()
+    }
+
+    /// Returns the extranonce prefix of the channel of the current session. The client opens only
+    /// a single standard channel (extended channels are not supported), `None` is returned when
+    /// the pool has not assigned any prefix to it or when no session is established.
+    pub fn extranonce_prefix(&self) -> Option<session::ExtranoncePrefix> {
+        self.session()
+            .and_then(|session| session.extranonce_prefix())
+    }
+
+    /// Returns parameters negotiated with the pool when the session has been set up
+    pub fn negotiated(&self) -> Option<status::Negotiated> {
+        self.session().map(|session| session.negotiated.clone())
+    }
+
+    /// Returns identifiers of the channel of the current session
+    pub fn channel_identity(&self) -> Option<session::ChannelIdentity> {
+        self.session().map(|session| session.channel.clone())
+    }
+
+    /// Returns names of capabilities that are enabled for this client after resolving its
+    /// configuration and flags negotiated with the pool (in the order of `capabilities()`)
+    pub fn active_capabilities(&self) -> Vec<&'static str> {
+        let connection_details = self.connection_details();
+        // Capabilities that depend on the negotiation are resolved from the configuration alone
+        // while no session is established
+        let session = self.session();
+        let encrypted = session.as_ref().map_or_else(
+            || connection_details.is_encrypted(),
+            |session| session.negotiated.encrypted,
+        );
+        let version_mask = session.as_ref().map_or_else(
+            || self.effective_version_mask(0),
+            |session| session.version_mask,
+        );
+        capabilities()
+            .capabilities
+            .into_iter()
+            .map(|capability| capability.name)
+            .filter(|&name| match name {
+                capabilities::NOISE_ENCRYPTION => encrypted,
+                capabilities::VERSION_ROLLING => version_mask != 0,
+                capabilities::SWITCH_JOURNAL => true,
+                capabilities::SHARE_ORDERING_CHECK => {
+                    connection_details.config.share_ordering_check.is_some()
+                }
+                capabilities::ACK_SEQUENCING => connection_details.config.ack_sequencing.is_some(),
+                #[cfg(feature = "fault-injection")]
+                capabilities::FAULT_INJECTION => {
+                    connection_details.config.fault_injection == Some(true)
+                }
+                capabilities::SUBMISSION_ANNOTATION => {
+                    connection_details.config.submission_annotation.is_some()
+                        && self.accepts_annotations()
+                }
+                #[cfg(feature = "reject-injection")]
+                capabilities::REJECT_INJECTION => self
+                    .reject_injector
+                    .lock()
+                    .expect("BUG: cannot lock reject injector")
+                    .is_some(),
+                #[cfg(feature = "tracing-spans")]
+                capabilities::TRACING_SPANS => true,
+                // Unsupported capabilities and capabilities of other clients
+                _ => false,
+            })
+            .collect()
+    }
+
+    fn set_current_target(&self, target: ii_bitcoin::Target) {
+        self.current_target
+            .lock()
+            .expect("BUG: cannot lock current target")
+            .replace(target);
+    }
+
+    /// Returns the target that is currently used for solving jobs, the value is retained after
+    /// the session is closed. `None` is returned before the first session has been opened.
+    pub fn current_target(&self) -> Option<ii_bitcoin::Target> {
+        *self
+            .current_target
+            .lock()
+            .expect("BUG: cannot lock current target")
+    }
+
+    /// Returns the difficulty of `current_target()`
+    pub fn current_difficulty(&self) -> Option<usize> {
+        self.current_target()
+            .map(|target| target_util::difficulty_from_target(&target))
+    }
+
+    pub fn ordering_violations(&self) -> &stats::CounterUsize {
+        &self.ordering_violations
+    }
+
+    pub fn sequencing_anomalies(&self) -> &stats::CounterUsize {
+        &self.sequencing_anomalies
     }
 
-    async fn visit_open_standard_mining_channel_success(
-        &mut self,
-        _header: &Header,
-        success_msg: &OpenStandardMiningChannelSuccess,
-    ) {
-        self.init_target = success_msg.target.into();
-        self.status = Ok(()).into();
+    pub fn unexpected_acks(&self) -> &stats::CounterUsize {
+        &self.unexpected_acks
     }
 
-    async fn visit_open_standard_mining_channel_error(
-        &mut self,
-        _header: &Header,
-        error_msg: &OpenStandardMiningChannelError,
-    ) {
-        self.status =
-            Err(format!("Open channel error: {}", error_msg.code.to_string()).into()).into();
+    /// Returns counters of retries in the connection and channel domain
+    pub fn retries(&self) -> status::Retries {
+        status::Retries {
+            connection: *self.connection_retries.take_snapshot(),
+            channel_open: *self.channel_open_retries.take_snapshot(),
+            handshake_reset: *self.handshake_resets.take_snapshot(),
+        }
     }
-}
 
-/// Messages to control the extension channel
-#[derive(Debug)]
-pub enum ExtensionChannelMsg {
-    /// Starts the extension channel
-    Start,
-    /// Stops the extension channel
-    Stop,
-    /// Frame being forwarded into the extension channel
-    Frame(<Framing as ii_wire::Framing>::Tx),
-}
+    /// Check that the difficulty requested by the pool is not too far outside of the configured
+    /// range, see `StratumV2Config::difficulty_reconnect_factor`
+    fn check_pool_difficulty(&self, pool_target: &ii_bitcoin::Target) -> error::Result<()> {
+        let config = self.config();
+        let factor = match config.difficulty_reconnect_factor {
+            Some(factor) => factor,
+            None => return Ok(()),
+        };
+        let difficulty = target_util::difficulty_from_target(pool_target);
+        if let Some(min_difficulty) = config.min_difficulty {
+            if (difficulty as f64) < min_difficulty as f64 / factor {
+                Err(error::Client::DifficultyOutOfRange(format!(
+                    "diff={} is far below configured minimum {}",
+                    difficulty, min_difficulty
+                )))?
+            }
+        }
+        if let Some(max_difficulty) = config.max_difficulty {
+            if difficulty as f64 > max_difficulty as f64 * factor {
+                Err(error::Client::DifficultyOutOfRange(format!(
+                    "diff={} is far above configured maximum {}",
+                    difficulty, max_difficulty
+                )))?
+            }
+        }
+        Ok(())
+    }
 
-/// Receiver for Stratum <-- Remote direction (stratum client end)
-pub type ExtensionChannelToStratumReceiver = mpsc::Receiver<<Framing as ii_wire::Framing>::Rx>;
-/// Remote sender for Stratum <-- Remote direction (remote end)
-pub type ExtensionChannelToStratumSender = mpsc::Sender<<Framing as ii_wire::Framing>::Tx>;
+    fn share_ordering_check(&self) -> Option<ShareOrderingCheck> {
+        self.config().share_ordering_check
+    }
 
-/// Receiver for Stratum --> Remote direction (remote end)
-pub type ExtensionChannelFromStratumReceiver = mpsc::Receiver<ExtensionChannelMsg>;
-/// Sender for Stratum --> Remote direction (stratum client end)
-pub type ExtensionChannelFromStratumSender = mpsc::Sender<ExtensionChannelMsg>;
+    /// Report share ordering violation. The violation is turned into an error when the
+    /// connection is to be restarted.
+    fn report_ordering_violation(
+        &self,
+        context: context::Context,
+        violation: ordering::Violation,
+        check: ShareOrderingCheck,
+    ) -> error::Result<()> {
+        error!("{} Stratum: {}", context, violation);
+        self.ordering_violations.inc();
+        match check {
+            ShareOrderingCheck::Log => Ok(()),
+            ShareOrderingCheck::Reconnect => {
+                Err(error::Client::ShareOrderingViolation(violation.to_string()))?
+            }
+        }
+    }
 
-#[derive(Debug, ClientNode)]
-pub struct StratumClient {
-    connection_details: Arc<StdMutex<ConnectionDetails>>,
-    backend_info: Option<hal::BackendInfo>,
-    #[member_status]
-    status: sync::StatusMonitor,
-    #[member_client_stats]
-    client_stats: stats::BasicClient,
-    stop_sender: mpsc::Sender<()>,
-    stop_receiver: Mutex<mpsc::Receiver<()>>,
-    // Last job has to be weak reference to prevent circular reference (the `StratumJob` keeps
-    // reference to `StratumClient`)
-    last_job: Mutex<Option<Arc<StratumJob>>>,
-    solutions: SolutionQueue,
-    job_sender: Mutex<job::Sender>,
-    solution_receiver: Mutex<job::SolutionReceiver>,
-    /// Frames received from this channel will be forwarded to the network connection
-    extension_channel_receiver: Mutex<ExtensionChannelToStratumReceiver>,
-    /// Frames intended for the specified extension will be forwarded into this channel (wrapped
-    /// into ExtensionChannelMsg
-    extension_channel_sender: Mutex<ExtensionChannelFromStratumSender>,
-}
+    fn ack_sequencing(&self) -> Option<StratumV2AckSequencing> {
+        self.config().ack_sequencing.clone()
+    }
 
-impl StratumClient {
-    const CONNECTION_TIMEOUT: time::Duration = time::Duration::from_secs(5);
-    const EVENT_TIMEOUT: time::Duration = time::Duration::from_secs(150);
-    const SEND_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+    /// Report anomaly in the order of acknowledgements. The anomaly is recorded as an event and it
+    /// is turned into an error when the connection is to be restarted.
+    fn report_sequencing_anomaly(
+        &self,
+        context: context::Context,
+        anomaly: ordering::Anomaly,
+        reaction: ShareOrderingCheck,
+    ) -> error::Result<()> {
+        error!("{} Stratum: {}", context, anomaly);
+        self.sequencing_anomalies.inc();
+        self.push_event(context, events::Event::SequencingAnomaly(anomaly));
+        match reaction {
+            ShareOrderingCheck::Log => Ok(()),
+            ShareOrderingCheck::Reconnect => {
+                Err(error::Client::ShareOrderingViolation(anomaly.to_string()))?
+            }
+        }
+    }
 
-    /// Start a task that plays a dummy role for both communication channels that the stratum
-    /// client uses to talk to stratum extension.
-    fn start_dummy_extension_task(
-        connection_details: ConnectionDetails,
-    ) -> (
-        ExtensionChannelToStratumReceiver,
-        ExtensionChannelFromStratumSender,
-    ) {
-        // Dummy channel for Stratum client --> extension communication
-        let (sender_from_client, mut receiver_from_client) = mpsc::channel(1);
-        // Dummy channel for Stratum client <-- extension communication
-        let (sender_to_client, receiver_to_client) = mpsc::channel(1);
+    /// Remove solutions up to and including `seq_num` from the queue of submitted solutions. The
+    /// queue is drained in a single critical section so that cancellation of the caller cannot
+    /// leave it half-drained. Returns the removed solutions and whether `seq_num` has been found
+    /// (all queued solutions are removed otherwise).
+    async fn take_acknowledged(&self, seq_num: u32) -> (Vec<(work::Solution, u32)>, bool) {
+        let mut solutions = self.solutions.lock().await;
+        let mut acknowledged = Vec::new();
+        while let Some((solution, solution_seq_num)) = solutions.pop_front() {
+            acknowledged.push((solution, solution_seq_num));
+            if solution_seq_num == seq_num {
+                return (acknowledged, true);
+            }
+        }
+        (acknowledged, false)
+    }
 
-        tokio::spawn(async move {
-            info!(
-                "Stratum extension: starting dummy task[{:?}]... ",
-                connection_details
-            );
-            // Make sure the sender is moved inside the dummy task to prevent it from being
-            // dropped. Otherwise the receiver_to_client would immediately indicate end of stream
-            let _sender_to_client = sender_to_client;
-            //
-            while let Some(message) = receiver_from_client.next().await {
-                info!(
-                    "Stratum extension: dummy task[{:?}] received: {:?},",
-                    connection_details, message
+    /// Account acknowledged solutions with their sequence numbers, `reject_code` is the error
+    /// code of the pool for rejected solutions. The solutions have been removed from the queue of
+    /// submitted solutions and they are not tracked anywhere else, therefore the accounting
+    /// doesn't wait for anything that could be cancelled. The client statistics are updated by
+    /// the accounting task (see `accounting`).
+    fn account_solutions(
+        self: &Arc<Self>,
+        outcomes: Vec<(Outcome, work::Solution, u32)>,
+        reject_code: Option<&str>,
+    ) {
+        if outcomes.is_empty() {
+            return;
+        }
+        let wall_time = time::SystemTime::now();
+        let now = time::Instant::now();
+        for (outcome, solution, seq_num) in outcomes.iter() {
+            let difficulty = target_util::difficulty_from_target(solution.job_target());
+            let annotation = self.lock_annotations().acknowledged(*seq_num);
+            if let Some(sink) = self.share_log.as_ref() {
+                let (outcome, reason) = match outcome {
+                    Outcome::Accepted => (share_log::Outcome::Accepted, ""),
+                    Outcome::Rejected => (
+                        share_log::Outcome::Rejected,
+                        reject_code.unwrap_or_default(),
+                    ),
+                    Outcome::Stale => (share_log::Outcome::Stale, "connection closed"),
+                };
+                sink.log(share_log::Record {
+                    time: wall_time,
+                    seq_num: *seq_num,
+                    outcome,
+                    difficulty,
+                    reason: reason.to_string(),
+                    annotation: annotation.unwrap_or_default(),
+                });
+            }
+            match outcome {
+                Outcome::Accepted => {
+                    self.hourly_shares.account_accepted(difficulty, wall_time);
+                    self.hashrate
+                        .lock()
+                        .expect("BUG: cannot lock hashrate estimator")
+                        .account_share(difficulty as f64, now);
+                }
+                Outcome::Rejected => self.hourly_shares.account_rejected(wall_time),
+                Outcome::Stale => self.hourly_shares.account_stale(wall_time),
+            }
+            self.share_counts.account(*outcome);
+            self.spans.finished(*seq_num, *outcome);
+        }
+        self.accounting
+            .start(Arc::new(StatsSink(Arc::downgrade(self))));
+        for (outcome, solution, _) in outcomes {
+            let record = accounting::Record {
+                outcome,
+                target: solution.job_target(),
+                time: now,
+            };
+            if !self.accounting.push(record) {
+                trace!(
+                    "{} Stratum: accounting queue is full, {:?} solution is not accounted",
+                    self.context(),
+                    outcome
                 );
             }
-            info!(
-                "Stratum extension: dummy task[{:?}] terminated",
-                connection_details
-            );
-        });
-        (receiver_to_client, sender_from_client)
+        }
     }
 
-    pub fn new(
-        connection_details: ConnectionDetails,
-        backend_info: Option<hal::BackendInfo>,
-        solver: job::Solver,
-        channel: Option<(
-            ExtensionChannelToStratumReceiver,
-            ExtensionChannelFromStratumSender,
-        )>,
-    ) -> Self {
-        let (stop_sender, stop_receiver) = mpsc::channel(1);
-
-        // Extract the both channel endpoints that connect the client with the stratum extension
-        // or populate it with dummy endpoints. That way we can handle the endpoints uniformly
-        // regardless whether they are configured or not (see `main_loop()`)
-        // that would handle all events regards
-        let (extension_channel_receiver, extension_channel_sender) = channel.unwrap_or_else(|| {
-            info!(
-                "V2: starting dummy task for client: {:?}",
-                connection_details
-            );
-            Self::start_dummy_extension_task(connection_details.clone())
-        });
+    /// Account all solutions that are still waiting for acknowledgement as stale, the pool won't
+    /// acknowledge them once the connection is closed
+    async fn discard_pending(self: &Arc<Self>) {
+        let outcomes = self
+            .solutions
+            .lock()
+            .await
+            .drain(..)
+            .map(|(solution, seq_num)| (Outcome::Stale, solution, seq_num))
+            .collect();
+        self.account_solutions(outcomes, None);
+    }
 
-        Self {
-            connection_details: Arc::new(StdMutex::new(connection_details)),
-            backend_info,
-            status: Default::default(),
-            client_stats: Default::default(),
-            stop_sender: stop_sender,
-            stop_receiver: Mutex::new(stop_receiver),
-            last_job: Mutex::new(None),
-            solutions: Mutex::new(VecDeque::new()),
-            job_sender: Mutex::new(solver.job_sender),
-            solution_receiver: Mutex::new(solver.solution_receiver),
-            extension_channel_receiver: Mutex::new(extension_channel_receiver),
-            extension_channel_sender: Mutex::new(extension_channel_sender),
-        }
+    /// Returns submitted solutions that are waiting for acknowledgement in the order they have
+    /// been submitted
+    pub async fn pending_submissions(&self) -> Vec<PendingSubmission> {
+        let now = time::Instant::now();
+        self.solutions
+            .lock()
+            .await
+            .iter()
+            .map(|(solution, seq_num)| PendingSubmission {
+                seq_num: *seq_num,
+                age: now.saturating_duration_since(solution.timestamp()),
+                difficulty: target_util::difficulty_from_target(
+                    &solution.job::<StratumJob>().pool_target,
+                ),
+            })
+            .collect()
     }
 
     fn connection_details(&self) -> ConnectionDetails {
@@ -759,8 +4949,80 @@ impl StratumClient {
             .clone()
     }
 
-    async fn update_last_job(&self, job: Arc<StratumJob>) {
-        self.last_job.lock().await.replace(job);
+    /// Configuration of the client, it doesn't copy the configuration (unlike
+    /// `connection_details`) so it is suitable for the paths of jobs and solutions
+    fn config(&self) -> Arc<StratumV2Config> {
+        self.connection_details
+            .lock()
+            .expect("BUG: cannot lock connection details")
+            .config
+            .clone()
+    }
+
+    /// Determine the version rolling mask for jobs of a session with negotiated `flags`. The
+    /// configured mask may only reduce the rolling space so any bits outside of the negotiated
+    /// mask are ignored.
+    fn effective_version_mask(&self, flags: u32) -> u32 {
+        if flags & capabilities::REQUIRES_FIXED_VERSION != 0 {
+            return 0;
+        }
+        match self.config().version_mask {
+            Some(version_mask) => {
+                let (effective_mask, ignored_bits) = Self::clip_version_mask(version_mask);
+                if ignored_bits != 0 {
+                    warn!(
+                        "Stratum: configured version mask {:08x} is not a subset of negotiated \
+                         mask {:08x}, using {:08x} instead",
+                        version_mask, VERSION_MASK, effective_mask
+                    );
+                }
+                effective_mask
+            }
+            None => VERSION_MASK,
+        }
+    }
+
+    /// Splits configured `version_mask` into the bits that are rolled and the bits outside of the
+    /// negotiated mask that are ignored (and warned about)
+    fn clip_version_mask(version_mask: u32) -> (u32, u32) {
+        (version_mask & VERSION_MASK, version_mask & !VERSION_MASK)
+    }
+
+    fn update_last_job(&self, job: Arc<StratumJob>) {
+        self.last_job
+            .lock()
+            .expect("BUG: cannot lock last job")
+            .replace(job);
+    }
+
+    /// Record that a job or prevhash has been received from the pool at `now`
+    fn touch_last_job(&self, now: time::Instant) {
+        let since_created = now.saturating_duration_since(self.created).as_micros() as u64;
+        self.last_job_received
+            .store(since_created + 1, Ordering::Relaxed);
+    }
+
+    fn last_job_age_at(&self, now: time::Instant) -> Option<time::Duration> {
+        match self.last_job_received.load(Ordering::Relaxed) {
+            0 => None,
+            received => {
+                let received = self.created + time::Duration::from_micros(received - 1);
+                Some(now.saturating_duration_since(received))
+            }
+        }
+    }
+
+    /// Time since the last `NewMiningJob` or `SetNewPrevHash` has been received from the pool
+    /// (across connections). It reveals work starvation even while the client is running.
+    pub fn last_job_age(&self) -> Option<time::Duration> {
+        self.last_job_age_at(time::Instant::now())
+    }
+
+    fn last_job(&self) -> Option<Arc<StratumJob>> {
+        self.last_job
+            .lock()
+            .expect("BUG: cannot lock last job")
+            .clone()
     }
 
     /// Send a message down a specified Tx Sink
@@ -778,16 +5040,29 @@ impl StratumClient {
             + 'static,
     {
         let frame = message.try_into()?;
-        match connection_tx
-            .lock()
-            .await
-            .send(frame)
+        Self::send_frame(connection_tx, frame).await
+    }
+
+    /// Send a frame down a specified Tx Sink. The timeout covers also waiting for the sink so that
+    /// a send blocked elsewhere cannot stall the caller indefinitely.
+    async fn send_frame<S, E>(
+        connection_tx: &Arc<Mutex<S>>,
+        frame: <Framing as ii_wire::Framing>::Tx,
+    ) -> error::Result<()>
+    where
+        E: Into<error::Error>,
+        S: Sink<<Framing as ii_wire::Framing>::Tx, Error = E>
+            + std::marker::Unpin
+            + std::fmt::Debug
+            + 'static,
+    {
+        match async { connection_tx.lock().await.send(frame).await }
             .timeout(Self::SEND_TIMEOUT)
             .await
         {
             Ok(Ok(_)) => Ok(()),
             Ok(Err(e)) => Err(e.into()),
-            Err(_) => Err("Cannot send message due to timeout")?,
+            Err(_) => Err(error::Client::SendTimeout)?,
         }
     }
 
@@ -796,16 +5071,26 @@ impl StratumClient {
         frame: <Framing as ii_wire::Framing>::Rx,
         event_handler: &mut StratumEventHandler,
     ) -> error::Result<()> {
+        let received = time::Instant::now();
+        self.prober.frame_received(received);
+        self.lock_transmit().frame_received(received);
         match frame.header.extension_type {
             extensions::BASE => {
+                let now = time::Instant::now();
+                event_handler.frame_received = Some(now);
+                event_handler.expire_ack_gaps(now);
                 let event_msg = build_message_from_frame(frame)?;
                 event_msg.accept(event_handler).await;
+                event_handler.frame_received = None;
+                if let Some(e) = event_handler.fatal_error.take() {
+                    return Err(e);
+                }
             }
             // pass any other extension down the line
             _ => {
                 info!(
-                    "Received protocol extension frame: {:x?} passing down",
-                    frame
+                    "{} Received protocol extension frame: {:x?} passing down",
+                    event_handler.context, frame
                 );
                 // Intentionally capture a potential error as an issue with extension channel
                 // must not cause the client to fail completely
@@ -816,13 +5101,13 @@ impl StratumClient {
                     .try_send(ExtensionChannelMsg::Frame(frame))
                 {
                     info!(
-                        "Cannot pass extension frame, extension channel not available: {:?}",
-                        e
+                        "{} Cannot pass extension frame, extension channel not available: {:?}",
+                        event_handler.context, e
                     );
                 }
             }
         }
-        Ok(())
+        self.check_transmit(event_handler.context, time::Instant::now())
     }
 
     async fn main_loop<R, S>(
@@ -830,14 +5115,26 @@ impl StratumClient {
         mut connection_rx: R,
         connection_tx: Arc<Mutex<S>>,
         mut event_handler: StratumEventHandler,
+        buffered_frames: Vec<<Framing as ii_wire::Framing>::Rx>,
     ) -> error::Result<()>
     where
         R: FrameStream,
         S: FrameSink,
     {
-        let mut solution_receiver = self.solution_receiver.lock().await;
+        // Stall of the previous session doesn't carry over to the new connection
+        self.lock_transmit().reset();
+        self.health.clear(health::DegradedReason::TransmitStalled);
+        {
+            // Jobs of the buffered frames are dispatched at the safe point too
+            let _solution_receiver = self.solution_receiver.lock().await;
+            for frame in buffered_frames {
+                self.handle_frame(frame, &mut event_handler).await?;
+            }
+        }
         let mut extension_channel_rx = self.extension_channel_receiver.lock().await;
-        let mut solution_handler = StratumSolutionHandler::new(self.clone(), connection_tx.clone());
+        let mut solution_handler =
+            StratumSolutionHandler::new(self.clone(), connection_tx.clone(), event_handler.context);
+        let mut user_file_poll = tokio::time::interval(self.user_file_poll_interval());
 
         // Notify the extension user that we are ready to start forwarding its protocol, use a
         // separate block, so that the lock is dropped immediately after the start notification
@@ -848,15 +5145,67 @@ impl StratumClient {
                 .await
                 .try_send(ExtensionChannelMsg::Start)
                 .map_err(|e| {
-                    info!("Stratum extension channel start error: {:?}", e);
+                    info!(
+                        "{} Stratum extension channel start error: {:?}",
+                        event_handler.context, e
+                    );
                 })
                 .expect("BUG: stratum extension channel not available for start");
         }
         while !self.status.is_shutting_down() {
+            let held_job_delay = event_handler.held_job_delay();
+            let held_job_ready = async {
+                match held_job_delay {
+                    Some(delay) => tokio::time::delay_for(delay).await,
+                    None => future::pending().await,
+                }
+            };
+            let target_wait_delay = event_handler.target_wait_delay();
+            let target_wait_expired = async {
+                match target_wait_delay {
+                    Some(delay) => tokio::time::delay_for(delay).await,
+                    None => future::pending().await,
+                }
+            };
+            let orphan_prevhash_delay = event_handler.orphan_prevhash_delay();
+            let orphan_prevhash_expired = async {
+                match orphan_prevhash_delay {
+                    Some(delay) => tokio::time::delay_for(delay).await,
+                    None => future::pending().await,
+                }
+            };
+            let disconnect_delay = self.faults.disconnect_delay(time::Instant::now());
+            let disconnect_forced = async {
+                match disconnect_delay {
+                    Some(delay) => tokio::time::delay_for(delay).await,
+                    None => future::pending().await,
+                }
+            };
+            let restart_storm_delay = self.restart_storm_recovery_delay(time::Instant::now());
+            let restart_storm_recovered = async {
+                match restart_storm_delay {
+                    Some(delay) => tokio::time::delay_for(delay).await,
+                    None => future::pending().await,
+                }
+            };
+            let startup_budget_delay = self.startup_budget_delay();
+            let startup_budget_exhausted = async {
+                match startup_budget_delay {
+                    Some(delay) => tokio::time::delay_for(delay).await,
+                    None => future::pending().await,
+                }
+            };
+            // The solution receiver is released between events, it is the safe point for
+            // `replace_solver`
+            let mut solution_receiver = self.solution_receiver.lock().await;
             select! {
                 frame = connection_rx.next().timeout(Self::EVENT_TIMEOUT).fuse() => {
                     match frame {
-                        Ok(Some(frame)) => self.handle_frame(frame?, &mut event_handler).await?,
+                        Ok(Some(frame)) => {
+                            self.handle_frame(frame?, &mut event_handler).await?;
+                            // Acknowledgements may have freed space in the submission window
+                            solution_handler.submit_held().await?;
+                        }
                         Ok(None) | Err(_) => {
                             Err("The remote stratum server was disconnected prematurely")?;
                         }
@@ -864,9 +5213,37 @@ impl StratumClient {
                 }
                 // Forward extension protocol frames onto the network
                 frame = extension_channel_rx.next().fuse() => {
-                    connection_tx.lock().await
-                        .send(frame.expect("BUG: extension channel must not shutdown!"))
-                        .await?;
+                    Self::send_frame(
+                        &connection_tx,
+                        frame.expect("BUG: extension channel must not shutdown!"),
+                    )
+                    .await?;
+                }
+                _ = user_file_poll.tick().fuse() => {
+                    self.poll_user_file(event_handler.context)?;
+                }
+                _ = held_job_ready.fuse() => {
+                    event_handler.dispatch_held_job().await;
+                }
+                _ = target_wait_expired.fuse() => {
+                    event_handler.target_wait_expired().await;
+                }
+                _ = orphan_prevhash_expired.fuse() => {
+                    Err(event_handler.orphan_prevhash_expired())?;
+                }
+                _ = disconnect_forced.fuse() => {
+                    self.force_disconnect(event_handler.context)?;
+                }
+                _ = restart_storm_recovered.fuse() => {
+                    self.restart_storm_session_survived_at(
+                        event_handler.context,
+                        time::Instant::now(),
+                    );
+                }
+                _ = startup_budget_exhausted.fuse() => {
+                    if let Some(e) = self.startup_budget_error() {
+                        Err(e)?
+                    }
                 }
                 solution = solution_receiver.receive().fuse() => {
                     match solution {
@@ -878,6 +5255,11 @@ impl StratumClient {
                     }
                 }
             }
+            #[cfg(feature = "reject-injection")]
+            while let Some(frame) = solution_handler.injected_acks.pop_front() {
+                self.handle_frame(frame, &mut event_handler).await?;
+                solution_handler.submit_held().await?;
+            }
         }
         Ok(())
     }
@@ -886,56 +5268,133 @@ impl StratumClient {
         self: Arc<Self>,
         connection_rx: R,
         connection_tx: Arc<Mutex<S>>,
-        init_target: ii_bitcoin::Target,
+        session: Arc<session::State>,
+        context: context::Context,
+        buffered_frames: Vec<<Framing as ii_wire::Framing>::Rx>,
+        first_job: connect_timing::Pending,
     ) where
         R: FrameStream,
         S: FrameSink,
     {
-        let event_handler = StratumEventHandler::new(self.clone(), init_target);
+        let mut event_handler = StratumEventHandler::new(self.clone(), session, context);
+        event_handler.first_job = Some(first_job.clone());
+        let started = time::Instant::now();
+        let submitted = *self.submitted.take_snapshot();
         // TODO consider changing main_loop to accept Arc<Self> and build the solution_handler
         //  along with solution handler communication channels inside of the main_loop.
         let client = self.clone();
-        if let Err(_) = client
-            .main_loop(connection_rx, connection_tx, event_handler)
+        if let Err(mut e) = client
+            .main_loop(connection_rx, connection_tx, event_handler, buffered_frames)
             .await
         {
+            // Leaving the loop at shutdown is not a failure
+            if !self.status.is_shutting_down() {
+                if let Some(deadline) = self.early_share_deadline_missed(
+                    started.elapsed(),
+                    *self.submitted.take_snapshot() - submitted,
+                ) {
+                    warn!(
+                        "{} Stratum: pool has dropped the channel without a share within {}s deadline",
+                        context,
+                        deadline.as_secs()
+                    );
+                    self.early_share_drops.inc();
+                    e = error::Client::DroppedBeforeFirstShare(e.to_string()).into();
+                }
+                // Ignored when the first job has been dispatched
+                self.lock_connect_history()
+                    .first_job(first_job.finish(connect_timing::Outcome::Failed));
+                self.record_failure(context, &e);
+            }
             self.status.initiate_failing();
         }
     }
 
     async fn run(self: Arc<Self>) {
-        let connection_handler = StratumConnectionHandler::new(self.clone());
+        let context = self.new_connection_context();
+        self.share_counts.new_session();
+        self.lock_template_quality().new_session();
+        self.lock_restart_storm()
+            .attempt_started(time::Instant::now());
+        if context.connection_id > 1 {
+            self.connection_retries.inc();
+            self.hourly_shares
+                .account_reconnect(time::SystemTime::now());
+        }
+        if let Err(e) = self.refresh_user(context) {
+            info!("{} Cannot determine pool user: {}", context, e);
+            self.record_failure(context, &e);
+            self.status.initiate_failing();
+            return;
+        }
+        let mut connection_handler = StratumConnectionHandler::new(self.clone(), context);
         let connection_details = connection_handler.client.connection_details();
         let host_and_port = connection_details.get_host_and_port();
-        let user = connection_details.user.clone();
+        let user = connection_details.display_user();
 
-        match connection_handler
+        self.start_startup_phase(startup_budget::Phase::Connect);
+        self.spans.connect_started(&host_and_port);
+        let result = match connection_handler
             .connect()
-            .timeout(Self::CONNECTION_TIMEOUT)
+            .timeout(self.startup_timeout(Self::CONNECTION_TIMEOUT))
             .await
-            .map_err(|_| error::ErrorKind::General("Connection timeout".to_string()).into())
         {
-            Ok(Ok(framed_connection)) => {
-                let (framed_sink, mut framed_stream) = framed_connection.split();
-                let framed_sink = Arc::new(Mutex::new(framed_sink));
+            Ok(result) => result.and_then(|framed_connection| {
+                self.startup_budget_error()
+                    .map_or(Ok(framed_connection), Err)
+            }),
+            Err(_) => Err(self
+                .startup_budget_error()
+                .unwrap_or_else(|| error::Client::ConnectTimeout.into())),
+        };
+        self.spans
+            .connect_finished(connect_timing::Outcome::of(&result));
+        match result {
+            Ok(framed_connection) => {
+                // Half-open connections are detected by the timed reads and writes well before the
+                // watchdog of the main loop fires
+                let io_timeout = connection_details.io_timeout();
+                // The throttling doesn't count towards the timeouts
+                let (send_limit, receive_limit) = connection_details.bandwidth_limits();
+                let (framed_sink, framed_stream) = framed_connection.split();
+                let mut framed_stream = bandwidth::ThrottledStream::new(
+                    io_timeout::TimedStream::new(framed_stream, io_timeout),
+                    self.bandwidth.clone(),
+                    receive_limit,
+                );
+                let framed_sink = Arc::new(Mutex::new(bandwidth::ThrottledSink::new(
+                    io_timeout::TimedSink::new(framed_sink, io_timeout),
+                    self.bandwidth.clone(),
+                    send_limit,
+                )));
                 match connection_handler
                     .init_mining_session(&mut framed_stream, framed_sink.clone())
-                    .timeout(Self::CONNECTION_TIMEOUT)
                     .await
-                    .map_err(|_| {
-                        error::ErrorKind::General("Init mining session timeout".to_string()).into()
-                    }) {
-                    Ok(Ok(init_target)) => {
-                        if self.status.initiate_running() {
+                {
+                    Ok((session, context, buffered_frames, first_job)) => {
+                        // The client is running once the first job is dispatched
+                        if self.status.initiate_connected() {
+                            self.notifications
+                                .publish(notifications::Notification::StatusChanged(
+                                    sync::Status::Connected,
+                                ));
+                            self.prober.connect();
                             self.clone()
-                                .run_job_solver(framed_stream, framed_sink, init_target)
+                                .run_job_solver(
+                                    framed_stream,
+                                    framed_sink,
+                                    session,
+                                    context,
+                                    buffered_frames,
+                                    first_job,
+                                )
                                 .await;
                         }
                     }
-                    Ok(Err(e)) | Err(e) => {
+                    Err(e) => {
                         info!(
-                            "Failed to negotiation initial V2 target: at {}, user={} ({:?}",
-                            host_and_port, user, e
+                            "{} Failed to negotiation initial V2 target: at {}, user={} ({:?}",
+                            context, host_and_port, user, e
                         );
                         // TODO consolidate this, so that we have exactly 1 place where we
                         //  initiate failing
@@ -943,11 +5402,14 @@ impl StratumClient {
                     }
                 }
             }
-            Ok(Err(e)) | Err(e) => {
+            Err(e) => {
                 info!(
-                    "Failed to connect to {}, user={} {:?}",
-                    host_and_port, user, e
+                    "{} Failed to connect to {}, user={} {:?}",
+                    context, host_and_port, user, e
                 );
+                self.lock_connect_history()
+                    .push(connection_handler.timer.finish());
+                self.record_failure(context, &e);
                 self.status.initiate_failing()
             }
         }
@@ -958,12 +5420,56 @@ impl StratumClient {
         // Flush all obsolete solutions from previous run
         self.solution_receiver.lock().await.flush();
 
+        let mut startup_delay =
+            Some(self.startup_delay()).filter(|delay| *delay > time::Duration::from_secs(0));
         loop {
+            // The budget of the first attempt is measured from the start of the client (including
+            // the delay of the first connection)
+            self.begin_startup_budget(time::Instant::now());
             let mut stop_receiver = self.stop_receiver.lock().await;
+            // Only the first connection after start is delayed unless the pool is restarting,
+            // stopping interrupts the delay
+            let delay = match startup_delay.take() {
+                Some(delay) => Some((delay, "delaying the first connection")),
+                None => self
+                    .reconnect_delay_at(time::Instant::now())
+                    .map(|delay| (delay, "pool is restarting, delaying the connection")),
+            };
+            let client = self.clone();
+            let mut run = Box::pin(async move {
+                if let Some((delay, reason)) = delay {
+                    info!(
+                        "{} Stratum: {} by {} ms",
+                        client.context(),
+                        reason,
+                        delay.as_millis()
+                    );
+                    tokio::time::delay_for(delay).await;
+                }
+                client.run().await
+            })
+            .fuse();
+            let mut stopped = false;
             select! {
-                _ = self.clone().run().fuse() => {}
-                _ = stop_receiver.next() => {}
+                _ = run => {}
+                _ = stop_receiver.next() => {
+                    stopped = true;
+                    self.drain_submissions(&mut run).await;
+                }
+            }
+            if !stopped
+                && matches!(
+                    self.status.status(),
+                    sync::Status::Failing | sync::Status::Declining
+                )
+            {
+                self.restart_storm_session_failed_at(self.context(), time::Instant::now());
             }
+            // Close the old connection before its unacknowledged shares are accounted as stale
+            drop(run);
+            self.finish_startup_budget();
+            self.prober.disconnect();
+            self.set_session(None);
 
             // Notify the other end that uses the extension channel that it should restart its
             // operation
@@ -983,13 +5489,16 @@ impl StratumClient {
                 );
             }
             // Invalidate current job to stop working on it
-            self.job_sender.lock().await.invalidate();
+            self.lock_job_sender().invalidate();
             // Flush all unprocessed solutions to empty buffer
             // TODO: Count as a discarded solution?
             self.solution_receiver.lock().await.flush();
-            self.solutions.lock().await.clear();
+            self.discard_pending().await;
+            if self.status.status() == sync::Status::Stopping {
+                self.write_handover().await;
+            }
 
-            if self.status.can_stop() {
+            if self.try_finish() {
                 // NOTE: it is not safe to add here any code!
                 // The reason is that at this point the main task can be executed in parallel again
                 break;
@@ -997,11 +5506,49 @@ impl StratumClient {
             // Restarting
         }
     }
+
+    /// Finish stopping of the client unless it has been restarted in the meantime. The terminal
+    /// notification is published when the client has stopped, by that time the connection is
+    /// closed and all events of the session have been published.
+    fn try_finish(&self) -> bool {
+        self.notifications.terminate(|| {
+            if !self.status.can_stop() {
+                return None;
+            }
+            // The client may have been started again right after stopping (`Stopped` ->
+            // `Starting`, `Failed` -> `Retrying`). Starting waits for the terminal notification
+            // before it opens the notifications again.
+            Some(match self.status.status() {
+                sync::Status::Failed | sync::Status::Retrying => sync::Status::Failed,
+                _ => sync::Status::Stopped,
+            })
+        })
+    }
+}
+
+/// Accounting of solutions into the statistics of the client, see `accounting`
+struct StatsSink(Weak<StratumClient>);
+
+#[async_trait]
+impl accounting::Sink for StatsSink {
+    async fn account(&self, record: accounting::Record) {
+        let client = match self.0.upgrade() {
+            Some(client) => client,
+            None => return,
+        };
+        let meter = match record.outcome {
+            Outcome::Accepted => &client.client_stats.accepted,
+            Outcome::Rejected => &client.client_stats.rejected,
+            Outcome::Stale => &client.client_stats.stale,
+        };
+        meter.account_solution(&record.target, record.time).await;
+    }
 }
 
 #[async_trait]
 impl node::Client for StratumClient {
     fn start(self: Arc<Self>) {
+        self.notifications.reopen();
         tokio::spawn(self.clone().main_task());
     }
 
@@ -1016,11 +5563,7 @@ impl node::Client for StratumClient {
     }
 
     async fn get_last_job(&self) -> Option<Arc<dyn job::Bitcoin>> {
-        self.last_job
-            .lock()
-            .await
-            .as_ref()
-            .map(|job| job.clone() as Arc<dyn job::Bitcoin>)
+        self.last_job().map(|job| job as Arc<dyn job::Bitcoin>)
     }
 
     /// Build new connection details from the specified `descriptor`
@@ -1031,6 +5574,14 @@ impl node::Client for StratumClient {
             .expect("BUG: cannot lock connection details") =
             ConnectionDetails::from_descriptor(descriptor);
     }
+
+    fn holds_failover(&self) -> bool {
+        self.restart_storm().is_some()
+    }
+
+    fn annotate_switch(&self, annotation: &switches::Annotation) {
+        self.push_event(self.context(), events::Event::Switch(annotation.clone()));
+    }
 }
 
 impl fmt::Display for StratumClient {
@@ -1039,7 +5590,9 @@ impl fmt::Display for StratumClient {
         write!(
             f,
             "{}://{}@{}",
-            connection_details.protocol, connection_details.host, connection_details.user
+            connection_details.protocol,
+            connection_details.host,
+            connection_details.display_user()
         )
     }
 }
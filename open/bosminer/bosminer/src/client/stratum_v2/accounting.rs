@@ -0,0 +1,184 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Accounting of acknowledged solutions into the client statistics decoupled from processing of
+//! acknowledgements. The statistics take locks that are shared with the aggregation of the API
+//! and a slow aggregation would otherwise back up the acknowledgements (and the submission
+//! window with them). The acknowledgement only queues a record without waiting for anything,
+//! a dedicated task feeds the records into the statistics. When the task falls behind by more
+//! than the capacity of the queue, further records are dropped and counted rather than blocking
+//! the protocol.
+
+use super::Outcome;
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use ii_async_compat::prelude::*;
+
+use serde::Serialize;
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time;
+
+/// Solution that is to be accounted
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub outcome: Outcome,
+    pub target: ii_bitcoin::Target,
+    /// Time when the solution has been acknowledged (or found stale)
+    pub time: time::Instant,
+}
+
+/// Consumer of the records, the statistics of the client in production
+#[async_trait]
+pub trait Sink: Send + Sync + 'static {
+    async fn account(&self, record: Record);
+}
+
+/// Backlog of the accounting that is reported in the status document
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Status {
+    /// Records waiting for the accounting task
+    pub queued: usize,
+    /// Age in milliseconds of the oldest record that hasn't been accounted yet
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oldest_age: Option<u64>,
+    /// Records dropped because the queue has been full
+    pub dropped: usize,
+}
+
+#[derive(Debug)]
+struct Shared {
+    /// Reference point of the time stored in `accounting_since`
+    created: time::Instant,
+    /// Records that have been queued and not accounted yet
+    queued: AtomicUsize,
+    /// Time (in microseconds since `created` plus one) of the record that is being accounted.
+    /// Zero means that the task is idle.
+    accounting_since: AtomicU64,
+    dropped: AtomicUsize,
+}
+
+impl Shared {
+    fn instant(&self, stored: u64) -> Option<time::Instant> {
+        match stored {
+            0 => None,
+            stored => Some(self.created + time::Duration::from_micros(stored - 1)),
+        }
+    }
+
+    fn store(&self, instant: Option<time::Instant>) {
+        let stored = instant.map_or(0, |instant| {
+            instant.saturating_duration_since(self.created).as_micros() as u64 + 1
+        });
+        self.accounting_since.store(stored, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
+pub struct Queue {
+    capacity: usize,
+    shared: Arc<Shared>,
+    sender: mpsc::UnboundedSender<Record>,
+    /// Receiving end that is handed over to the accounting task once it is started
+    receiver: StdMutex<Option<mpsc::UnboundedReceiver<Record>>>,
+}
+
+impl Queue {
+    /// Maximal number of records waiting for the accounting task
+    pub const CAPACITY: usize = 4096;
+
+    pub fn new(capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::unbounded();
+        Self {
+            capacity,
+            shared: Arc::new(Shared {
+                created: time::Instant::now(),
+                queued: AtomicUsize::new(0),
+                accounting_since: AtomicU64::new(0),
+                dropped: AtomicUsize::new(0),
+            }),
+            sender,
+            receiver: StdMutex::new(Some(receiver)),
+        }
+    }
+
+    /// Spawn the accounting task that feeds queued records into `sink` unless it has been already
+    /// started. The task finishes when the queue is dropped.
+    pub fn start(&self, sink: Arc<dyn Sink>) {
+        let receiver = self
+            .receiver
+            .lock()
+            .expect("BUG: cannot lock accounting receiver")
+            .take();
+        if let Some(receiver) = receiver {
+            tokio::spawn(Self::run(self.shared.clone(), receiver, sink));
+        }
+    }
+
+    async fn run(
+        shared: Arc<Shared>,
+        mut receiver: mpsc::UnboundedReceiver<Record>,
+        sink: Arc<dyn Sink>,
+    ) {
+        while let Some(record) = receiver.next().await {
+            // The records are accounted in order, the record that is being accounted is the
+            // oldest one
+            shared.store(Some(record.time));
+            sink.account(record).await;
+            shared.store(None);
+            shared.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Queue `record` for the accounting task without waiting. Returns false when the record has
+    /// been dropped because the queue is full.
+    pub fn push(&self, record: Record) -> bool {
+        if self.shared.queued.fetch_add(1, Ordering::Relaxed) >= self.capacity
+            || self.sender.unbounded_send(record).is_err()
+        {
+            self.shared.queued.fetch_sub(1, Ordering::Relaxed);
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    pub fn status_at(&self, now: time::Instant) -> Status {
+        let accounting_since = self
+            .shared
+            .instant(self.shared.accounting_since.load(Ordering::Relaxed));
+        Status {
+            queued: self.shared.queued.load(Ordering::Relaxed),
+            oldest_age: accounting_since
+                .map(|since| now.saturating_duration_since(since).as_millis() as u64),
+            dropped: self.shared.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Self::new(Self::CAPACITY)
+    }
+}
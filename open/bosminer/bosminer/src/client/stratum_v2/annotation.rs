@@ -0,0 +1,103 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Annotation of submitted shares with a static tag supplied by the operator. The tag is carried
+//! by an extension of the submit message that the pool has to accept in the negotiated session,
+//! the share is submitted without the tag otherwise.
+//!
+//! The `ii_stratum` crate doesn't define such an extension yet. The extension is therefore
+//! abstracted by `Carrier` and the carrier of this build (`Dormant`) never accepts the annotation,
+//! so the annotation stays omitted until the crate provides the extension.
+//!
+//! The annotation is recorded in the share log along with the outcome of the share. The client
+//! doesn't deduplicate shares by any key that would include the annotation.
+
+use ii_stratum::v2::messages::SubmitSharesStandard;
+use ii_stratum::v2::Frame;
+
+use super::status;
+
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fmt;
+
+/// Extension of the submit message that carries the annotation
+pub trait Carrier: fmt::Debug + Send + Sync {
+    /// The pool accepts annotated submissions in a session with `negotiated` parameters
+    fn accepts(&self, negotiated: &status::Negotiated) -> bool;
+
+    /// Build the frame of `share_msg` annotated with `annotation`
+    fn annotate(
+        &self,
+        share_msg: SubmitSharesStandard,
+        annotation: &str,
+    ) -> ii_stratum::error::Result<Frame>;
+}
+
+/// Carrier used while the protocol crate provides no extension of submissions
+#[derive(Debug)]
+pub struct Dormant;
+
+impl Carrier for Dormant {
+    fn accepts(&self, _negotiated: &status::Negotiated) -> bool {
+        false
+    }
+
+    fn annotate(
+        &self,
+        share_msg: SubmitSharesStandard,
+        _annotation: &str,
+    ) -> ii_stratum::error::Result<Frame> {
+        share_msg.try_into()
+    }
+}
+
+/// Annotations of submitted shares that haven't been acknowledged yet
+#[derive(Debug, Default)]
+pub struct Ledger {
+    annotations: VecDeque<(u32, String)>,
+}
+
+impl Ledger {
+    /// Shares that are never acknowledged nor accounted as stale must not grow the ledger
+    /// indefinitely, the oldest annotation is forgotten once the capacity is reached
+    pub const CAPACITY: usize = 1024;
+
+    pub fn submitted(&mut self, seq_num: u32, annotation: String) {
+        self.annotations.retain(|(other, _)| *other != seq_num);
+        if self.annotations.len() >= Self::CAPACITY {
+            self.annotations.pop_front();
+        }
+        self.annotations.push_back((seq_num, annotation));
+    }
+
+    /// Take the annotation of the share with `seq_num`
+    pub fn acknowledged(&mut self, seq_num: u32) -> Option<String> {
+        let index = self
+            .annotations
+            .iter()
+            .position(|(other, _)| *other == seq_num)?;
+        self.annotations
+            .remove(index)
+            .map(|(_, annotation)| annotation)
+    }
+}
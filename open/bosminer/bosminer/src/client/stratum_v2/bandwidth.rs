@@ -0,0 +1,385 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Accounting and throttling of the bandwidth of the pool connection. The adapters are applied at
+//! the framing layer (like the timed reads and writes) since the Noise connection takes the TCP
+//! stream as it is, the size of Stratum frames is counted without the overhead of the encryption
+//! and of TCP.
+//!
+//! Each direction is limited by a token bucket that allows a transfer whenever the bucket is not
+//! in debt. A frame larger than the burst is thus never stuck, the following transfers wait until
+//! the debt is paid off:
+//! - frames sent to the pool are queued by the sink (the senders wait for it)
+//! - frames received from the pool are not read from the connection, the pool is slowed down by
+//!   the TCP flow control
+
+use ii_async_compat::bytes::{BufMut, BytesMut};
+use ii_async_compat::prelude::*;
+use ii_async_compat::tokio;
+
+use futures::task::{Context, Poll};
+
+use ii_stratum::v2::framing::Header;
+use ii_stratum::v2::Frame;
+
+use serde::Serialize;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time;
+
+/// Frame whose size on the wire can be determined
+pub trait WireSize: Sized {
+    /// Returns the size of the frame along with the frame. A frame that is serialized lazily is
+    /// serialized by it, the frame is not serialized again when it is sent.
+    fn wire_size(self) -> ii_stratum::error::Result<(usize, Self)>;
+}
+
+impl WireSize for Frame {
+    fn wire_size(self) -> ii_stratum::error::Result<(usize, Self)> {
+        if let Some(length) = self.header.msg_length {
+            return Ok((Header::SIZE + length as usize, self));
+        }
+        let (header, payload) = self.split();
+        let mut writer = BytesMut::new().writer();
+        payload.serialize_to_writer(&mut writer)?;
+        let payload = writer.into_inner();
+        Ok((
+            Header::SIZE + payload.len(),
+            Frame::from_serialized_payload(
+                header.is_channel_message,
+                header.extension_type,
+                header.msg_type,
+                payload,
+            ),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+/// Limit of a direction resolved from the configuration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit {
+    /// Bytes per second
+    pub rate: u64,
+    /// Bytes that may be transferred at once above the rate
+    pub burst: u64,
+}
+
+/// Token bucket of a single direction
+#[derive(Debug, Clone)]
+pub struct Bucket {
+    limit: Limit,
+    tokens: f64,
+    updated: time::Instant,
+}
+
+impl Bucket {
+    /// The bucket starts full
+    pub fn new(limit: Limit, now: time::Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: time::Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.rate as f64).min(self.limit.burst as f64);
+        self.updated = now;
+    }
+
+    /// Time until the next transfer is allowed, it is `None` when it is allowed right away
+    pub fn delay(&mut self, now: time::Instant) -> Option<time::Duration> {
+        self.refill(now);
+        if self.tokens >= 0.0 {
+            None
+        } else {
+            Some(time::Duration::from_secs_f64(
+                -self.tokens / self.limit.rate as f64,
+            ))
+        }
+    }
+
+    pub fn consume(&mut self, bytes: usize, now: time::Instant) {
+        self.refill(now);
+        self.tokens -= bytes as f64;
+    }
+}
+
+/// Traffic of a single direction
+#[derive(Debug, Default)]
+struct Counter {
+    bytes: u64,
+    throttled: u64,
+    /// Transfers within the rate window
+    recent: VecDeque<(time::Instant, usize)>,
+}
+
+impl Counter {
+    fn expire(&mut self, now: time::Instant) {
+        while let Some((time, _)) = self.recent.front() {
+            if now.saturating_duration_since(*time) < Meter::RATE_WINDOW {
+                break;
+            }
+            self.recent.pop_front();
+        }
+    }
+
+    fn account(&mut self, bytes: usize, now: time::Instant) {
+        self.bytes += bytes as u64;
+        self.recent.push_back((now, bytes));
+        self.expire(now);
+    }
+
+    fn status(&mut self, now: time::Instant, limit: Option<Limit>) -> DirectionStatus {
+        self.expire(now);
+        let recent: usize = self.recent.iter().map(|(_, bytes)| bytes).sum();
+        DirectionStatus {
+            bytes: self.bytes,
+            rate: recent as u64 / Meter::RATE_WINDOW.as_secs(),
+            limit: limit.map(|limit| limit.rate),
+            throttled: self.throttled,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct DirectionStatus {
+    /// Total number of bytes transferred by all connections
+    pub bytes: u64,
+    /// Current rate in bytes per second (averaged over the rate window)
+    pub rate: u64,
+    /// Configured limit in bytes per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+    /// Number of times a transfer has been delayed by the limit
+    pub throttled: u64,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Status {
+    pub send: DirectionStatus,
+    pub receive: DirectionStatus,
+}
+
+/// Traffic of the client that outlives the individual connections
+#[derive(Debug, Default)]
+pub struct Meter {
+    send: StdMutex<Counter>,
+    receive: StdMutex<Counter>,
+}
+
+impl Meter {
+    /// Window over which the current rate is averaged
+    pub const RATE_WINDOW: time::Duration = time::Duration::from_secs(10);
+
+    fn lock(&self, direction: Direction) -> std::sync::MutexGuard<Counter> {
+        match direction {
+            Direction::Send => &self.send,
+            Direction::Receive => &self.receive,
+        }
+        .lock()
+        .expect("BUG: cannot lock bandwidth counter")
+    }
+
+    pub fn account(&self, direction: Direction, bytes: usize, now: time::Instant) {
+        self.lock(direction).account(bytes, now);
+    }
+
+    fn throttled(&self, direction: Direction) {
+        self.lock(direction).throttled += 1;
+    }
+
+    pub fn status_at(
+        &self,
+        now: time::Instant,
+        send_limit: Option<Limit>,
+        receive_limit: Option<Limit>,
+    ) -> Status {
+        Status {
+            send: self.lock(Direction::Send).status(now, send_limit),
+            receive: self.lock(Direction::Receive).status(now, receive_limit),
+        }
+    }
+}
+
+/// Accounting and throttling of a single direction of a connection
+struct Throttle {
+    meter: Arc<Meter>,
+    direction: Direction,
+    bucket: Option<Bucket>,
+    delay: Option<Pin<Box<tokio::time::Delay>>>,
+}
+
+impl Throttle {
+    fn new(meter: Arc<Meter>, direction: Direction, limit: Option<Limit>) -> Self {
+        Self {
+            meter,
+            direction,
+            bucket: limit.map(|limit| Bucket::new(limit, time::Instant::now())),
+            delay: None,
+        }
+    }
+
+    /// Wait until the limit allows the next transfer
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            if let Some(delay) = self.delay.as_mut() {
+                match delay.as_mut().poll(cx) {
+                    Poll::Ready(()) => self.delay = None,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            let bucket = match self.bucket.as_mut() {
+                Some(bucket) => bucket,
+                None => return Poll::Ready(()),
+            };
+            match bucket.delay(time::Instant::now()) {
+                Some(delay) => {
+                    self.meter.throttled(self.direction);
+                    self.delay = Some(Box::pin(tokio::time::delay_for(delay)));
+                }
+                None => return Poll::Ready(()),
+            }
+        }
+    }
+
+    fn account(&mut self, bytes: usize) {
+        let now = time::Instant::now();
+        if let Some(bucket) = self.bucket.as_mut() {
+            bucket.consume(bytes, now);
+        }
+        self.meter.account(self.direction, bytes, now);
+    }
+}
+
+impl fmt::Debug for Throttle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Throttle")
+            .field("direction", &self.direction)
+            .field("bucket", &self.bucket)
+            .field("delayed", &self.delay.is_some())
+            .finish()
+    }
+}
+
+/// Stream of frames that is accounted and that is not read while the limit is exceeded
+#[derive(Debug)]
+pub struct ThrottledStream<S> {
+    inner: S,
+    throttle: Throttle,
+}
+
+impl<S> ThrottledStream<S> {
+    /// Frames are only accounted when `limit` is not set
+    pub fn new(inner: S, meter: Arc<Meter>, limit: Option<Limit>) -> Self {
+        Self {
+            inner,
+            throttle: Throttle::new(meter, Direction::Receive, limit),
+        }
+    }
+}
+
+impl<S, T, E> Stream for ThrottledStream<S>
+where
+    S: Stream<Item = Result<T, E>> + Unpin,
+    T: WireSize,
+    E: From<ii_stratum::error::Error>,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.throttle.poll_ready(cx).is_pending() {
+            return Poll::Pending;
+        }
+        match this.inner.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(frame))) => Poll::Ready(Some(
+                frame
+                    .wire_size()
+                    .map(|(size, frame)| {
+                        this.throttle.account(size);
+                        frame
+                    })
+                    .map_err(Into::into),
+            )),
+            poll => poll,
+        }
+    }
+}
+
+/// Sink of frames that is accounted and that queues frames while the limit is exceeded
+#[derive(Debug)]
+pub struct ThrottledSink<S> {
+    inner: S,
+    throttle: Throttle,
+}
+
+impl<S> ThrottledSink<S> {
+    /// Frames are only accounted when `limit` is not set
+    pub fn new(inner: S, meter: Arc<Meter>, limit: Option<Limit>) -> Self {
+        Self {
+            inner,
+            throttle: Throttle::new(meter, Direction::Send, limit),
+        }
+    }
+}
+
+impl<S, T, E> Sink<T> for ThrottledSink<S>
+where
+    S: Sink<T, Error = E> + Unpin,
+    T: WireSize,
+    E: From<ii_stratum::error::Error>,
+{
+    type Error = E;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+        let this = &mut *self;
+        if this.throttle.poll_ready(cx).is_pending() {
+            return Poll::Pending;
+        }
+        this.inner.poll_ready_unpin(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), E> {
+        let (size, item) = item.wire_size()?;
+        self.throttle.account(size);
+        self.inner.start_send_unpin(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+        self.inner.poll_flush_unpin(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+        self.inner.poll_close_unpin(cx)
+    }
+}
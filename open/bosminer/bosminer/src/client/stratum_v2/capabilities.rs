@@ -0,0 +1,185 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+
+//! Static introspection of optional capabilities of the client. Fleet tooling uses the matrix to
+//! find out which capabilities a particular build supports and how they are controlled without
+//! maintaining a parallel table per firmware version.
+//!
+//! Every capability is listed explicitly in `capabilities()` and its activation for a particular
+//! client is resolved in `StratumClient::active_capabilities()`. Capabilities gated by a cargo
+//! feature are cross-checked against the features of the crate in tests.
+
+use serde::Serialize;
+
+/// Flag of `SetupConnectionSuccess` by which the pool requires the version field to be kept
+/// intact (no version rolling)
+pub const REQUIRES_FIXED_VERSION: u32 = 0x1;
+
+/// All flags of `SetupConnectionSuccess` recognized by the client
+pub const KNOWN_POOL_FLAGS: u32 = REQUIRES_FIXED_VERSION;
+
+/// Availability of a capability in this build
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Status {
+    /// Always available when the client is used
+    CompiledIn,
+    /// The cargo feature that provides the capability is disabled in this build
+    FeatureGatedOff,
+    /// Compiled in and controlled by a configuration key
+    RuntimeFlag { default: bool },
+    /// Not implemented by the client
+    Unsupported,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Capability {
+    pub name: &'static str,
+    /// Cargo feature that provides the capability
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feature: Option<&'static str>,
+    /// Configuration key that controls the capability
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_key: Option<&'static str>,
+    /// Flags of `SetupConnectionSuccess` that make the capability unavailable when set by the pool
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub incompatible_pool_flags: Vec<&'static str>,
+    #[serde(flatten)]
+    pub status: Status,
+}
+
+impl Capability {
+    fn new(name: &'static str, status: Status) -> Self {
+        Self {
+            name,
+            feature: None,
+            config_key: None,
+            incompatible_pool_flags: vec![],
+            status,
+        }
+    }
+
+    /// Capability provided by a cargo `feature` that is `enabled` in this build
+    fn feature_gated(name: &'static str, feature: &'static str, enabled: bool) -> Self {
+        let status = if enabled {
+            Status::CompiledIn
+        } else {
+            Status::FeatureGatedOff
+        };
+        Self {
+            feature: Some(feature),
+            ..Self::new(name, status)
+        }
+    }
+
+    fn config_key(self, config_key: &'static str) -> Self {
+        Self {
+            config_key: Some(config_key),
+            ..self
+        }
+    }
+
+    fn incompatible_pool_flag(mut self, flag: &'static str) -> Self {
+        self.incompatible_pool_flags.push(flag);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CapabilityMatrix {
+    pub capabilities: Vec<Capability>,
+}
+
+impl CapabilityMatrix {
+    pub fn get(&self, name: &str) -> Option<&Capability> {
+        self.capabilities
+            .iter()
+            .find(|capability| capability.name == name)
+    }
+}
+
+pub const NOISE_ENCRYPTION: &str = "noise_encryption";
+pub const VERSION_ROLLING: &str = "version_rolling";
+pub const EXTENDED_CHANNELS: &str = "extended_channels";
+pub const PROXY: &str = "proxy";
+pub const BONDED_CONNECTIONS: &str = "bonded_connections";
+pub const SWITCH_JOURNAL: &str = "switch_journal";
+pub const SIMULATION_CLIENT: &str = "simulation_client";
+pub const SHARE_ORDERING_CHECK: &str = "share_ordering_check";
+pub const ACK_SEQUENCING: &str = "ack_sequencing";
+pub const SUBMISSION_ANNOTATION: &str = "submission_annotation";
+pub const REJECT_INJECTION: &str = "reject_injection";
+pub const CONFORMANCE_VECTORS: &str = "conformance_vectors";
+pub const FAULT_INJECTION: &str = "fault_injection";
+pub const TRACING_SPANS: &str = "tracing_spans";
+
+/// Returns all optional capabilities of the client as compiled in this build
+pub fn capabilities() -> CapabilityMatrix {
+    CapabilityMatrix {
+        capabilities: vec![
+            // Selected by the scheme of the pool URL
+            Capability::new(NOISE_ENCRYPTION, Status::RuntimeFlag { default: true })
+                .config_key("url"),
+            Capability::new(VERSION_ROLLING, Status::RuntimeFlag { default: true })
+                .config_key("stratum_v2.version_mask")
+                .incompatible_pool_flag("REQUIRES_FIXED_VERSION"),
+            Capability::new(EXTENDED_CHANNELS, Status::Unsupported),
+            Capability::new(PROXY, Status::Unsupported),
+            Capability::new(BONDED_CONNECTIONS, Status::Unsupported),
+            Capability::new(SWITCH_JOURNAL, Status::CompiledIn),
+            // Separate client selected by the scheme of the pool URL
+            Capability::new(SIMULATION_CLIENT, Status::CompiledIn).config_key("url"),
+            Capability::new(SHARE_ORDERING_CHECK, Status::RuntimeFlag { default: false })
+                .config_key("stratum_v2.share_ordering_check"),
+            Capability::new(ACK_SEQUENCING, Status::RuntimeFlag { default: false })
+                .config_key("stratum_v2.ack_sequencing"),
+            // Dormant until the protocol crate provides an extension of submissions (see
+            // `annotation`)
+            Capability::new(
+                SUBMISSION_ANNOTATION,
+                Status::RuntimeFlag { default: false },
+            )
+            .config_key("stratum_v2.submission_annotation"),
+            Capability::feature_gated(
+                REJECT_INJECTION,
+                "reject-injection",
+                cfg!(feature = "reject-injection"),
+            ),
+            // Runner of custom test vectors (the bundled vectors are run by the tests)
+            Capability::feature_gated(
+                CONFORMANCE_VECTORS,
+                "conformance",
+                cfg!(feature = "conformance"),
+            ),
+            // Faults are armed only when enabled by the configuration as well
+            Capability::feature_gated(
+                FAULT_INJECTION,
+                "fault-injection",
+                cfg!(feature = "fault-injection"),
+            )
+            .config_key("stratum_v2.fault_injection"),
+            Capability::feature_gated(
+                TRACING_SPANS,
+                "tracing-spans",
+                cfg!(feature = "tracing-spans"),
+            ),
+        ],
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Skew between `min_ntime` of prevhash messages sent by the pool and the local wall-clock time.
+//! A systematic skew means either a broken local time synchronization or a pool with lagging
+//! template generation. The skew is positive when the pool is ahead of the local clock.
+//!
+//! The statistics are robust against occasional outliers: the median of the recent samples is
+//! used as the estimate and the interquartile range as the spread.
+
+use serde::Serialize;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time;
+
+/// Estimate of the skew in seconds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Skew {
+    pub median: i64,
+    /// Interquartile range of the samples
+    pub spread: i64,
+    pub samples: usize,
+}
+
+/// The median skew has exceeded the threshold
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Advisory {
+    pub median: i64,
+    pub threshold: u64,
+}
+
+impl fmt::Display for Advisory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.median > 0 {
+            write!(
+                f,
+                "local clock appears to be {}s behind the pool (threshold {}s), check time synchronization",
+                self.median, self.threshold
+            )
+        } else {
+            write!(
+                f,
+                "pool time appears to be {}s behind the local clock (threshold {}s), the pool may be generating stale templates",
+                -self.median, self.threshold
+            )
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Tracker {
+    capacity: usize,
+    samples: VecDeque<i64>,
+    /// The advisory has been emitted and the median hasn't returned within the threshold since
+    advised: bool,
+}
+
+impl Tracker {
+    /// Number of the most recent samples the statistics are computed from
+    pub const DEFAULT_CAPACITY: usize = 50;
+    /// The skew has to persist over this many samples before it is reported
+    pub const MIN_SAMPLES: usize = 10;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+            advised: false,
+        }
+    }
+
+    /// Skew of `min_ntime` against `now`
+    pub fn sample(min_ntime: u32, now: time::SystemTime) -> i64 {
+        let local = match now.duration_since(time::UNIX_EPOCH) {
+            Ok(since_epoch) => since_epoch.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        };
+        min_ntime as i64 - local
+    }
+
+    /// Record skew of `min_ntime` against `now`. Returns an advisory when the median skew has
+    /// exceeded `threshold` seconds, the advisory is emitted again only after the median returns
+    /// within the threshold.
+    pub fn observe(
+        &mut self,
+        min_ntime: u32,
+        now: time::SystemTime,
+        threshold: u64,
+    ) -> Option<Advisory> {
+        if self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Self::sample(min_ntime, now));
+
+        let skew = self.skew()?;
+        if skew.median.abs() as u64 <= threshold {
+            self.advised = false;
+            return None;
+        }
+        if self.advised || skew.samples < Self::MIN_SAMPLES {
+            return None;
+        }
+        self.advised = true;
+        Some(Advisory {
+            median: skew.median,
+            threshold,
+        })
+    }
+
+    pub fn skew(&self) -> Option<Skew> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<_> = self.samples.iter().cloned().collect();
+        sorted.sort_unstable();
+        let quantile = |q: usize| sorted[(sorted.len() - 1) * q / 4];
+        Some(Skew {
+            median: quantile(2),
+            spread: quantile(3) - quantile(1),
+            samples: sorted.len(),
+        })
+    }
+
+    /// Check that `min_ntime` is within `tolerance` seconds of the time expected from the pool,
+    /// i.e. the local time corrected by the median skew. Any time is valid until the skew is
+    /// known.
+    pub fn is_plausible(&self, min_ntime: u32, now: time::SystemTime, tolerance: u64) -> bool {
+        match self.skew() {
+            Some(skew) => (Self::sample(min_ntime, now) - skew.median).abs() as u64 <= tolerance,
+            None => true,
+        }
+    }
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
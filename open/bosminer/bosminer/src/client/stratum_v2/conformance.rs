@@ -0,0 +1,752 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Conformance test vectors of the message handling of the client. A vector is a sequence of
+//! messages sent by the pool after the channel has been opened together with the outcomes the
+//! client has to produce. The vectors are driven through the same entry point as frames of a live
+//! connection, jobs dispatched to the backend and shares sent to the pool are captured.
+//!
+//! The suite shipped with the client (`conformance/vectors.json`) documents the sequences the
+//! client tolerates. Pool developers can run their own sequences with the `conformance` feature:
+//!
+//! ```text
+//! [{
+//!   "name": "future_job_activated_by_prevhash",
+//!   "config": {},
+//!   "steps": [
+//!     {"receive": {"type": "set_target", "difficulty": 0},
+//!      "expect": {"target_difficulty": 0}},
+//!     {"receive": {"type": "new_mining_job", "job_id": 1, "future_job": true},
+//!      "expect": {"dispatched": []}},
+//!     {"receive": {"type": "set_new_prev_hash", "job_id": 1},
+//!      "expect": {"dispatched": [{"job_id": 1}]}},
+//!     {"solve": {}, "expect": {"submitted": [{"job_id": 1, "seq_num": 0}]}}
+//!   ]
+//! }]
+//! ```
+//!
+//! A step either receives a message from the pool or solves the job dispatched last. Omitted
+//! fields of messages have defaults: channel 0, version `0x20000000`, merkle root filled with the
+//! low byte of the job ID, `min_ntime` equal to the current time (`min_ntime_offset` shifts it),
+//! difficulty 0 stands for the easiest possible target. Every expectation is optional, only the
+//! stated outcomes are checked. A step that makes the client disconnect must state the error code
+//! in `disconnect` and it has to be the last step.
+//!
+//! Regardless of the expectations, every step checks that each solution found so far has been
+//! either submitted, dropped by exactly one local filter or held back (see `filters`).
+
+use super::replay;
+use super::session;
+use super::{
+    ConnectionDetails, StratumClient, StratumEventHandler, StratumJob, StratumSolutionHandler,
+};
+use crate::client::target_util;
+use crate::error;
+use crate::hal;
+use crate::job;
+use crate::work;
+
+use bosminer_config::{ClientProtocol, StratumV2Config};
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use futures::lock::Mutex;
+use ii_async_compat::prelude::*;
+
+use ii_bitcoin::HashTrait;
+
+use ii_stratum::v2::framing::{Framing, Header};
+use ii_stratum::v2::messages::{
+    NewMiningJob, SetNewPrevHash, SetTarget, SubmitSharesError, SubmitSharesStandard,
+    SubmitSharesSuccess,
+};
+use ii_stratum::v2::types::{Str0_32, Uint256Bytes};
+use ii_stratum::v2::{build_message_from_frame, Handler};
+
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::sync::Arc;
+use std::time;
+
+/// Test vectors shipped with the client
+pub const VECTORS: &str = include_str!("conformance/vectors.json");
+
+/// Maximal number of jobs dispatched within a single step
+const MAX_DISPATCHED_JOBS: usize = 64;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Vector {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Configuration of the client, defaults apply to omitted settings
+    #[serde(default)]
+    pub config: StratumV2Config,
+    #[serde(default)]
+    pub session: Session,
+    pub steps: Vec<Step>,
+}
+
+/// Parameters of the session negotiated during the handshake
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Session {
+    /// Channel opened by the pool (0 by default)
+    #[serde(default)]
+    pub channel_id: u32,
+    /// Group channel the channel belongs to (none by default)
+    pub group_id: Option<u32>,
+    /// Difficulty of the target sent when the channel has been opened (1 by default)
+    pub init_difficulty: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub receive: Option<Message>,
+    pub solve: Option<Solve>,
+    #[serde(default)]
+    pub expect: Expect,
+}
+
+/// Message sent by the pool
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    NewMiningJob {
+        #[serde(default)]
+        channel_id: u32,
+        job_id: u32,
+        #[serde(default)]
+        future_job: bool,
+        #[serde(default = "default_version")]
+        version: u32,
+        /// Hex encoded, filled with the low byte of the job ID when not specified
+        merkle_root: Option<String>,
+    },
+    SetNewPrevHash {
+        #[serde(default)]
+        channel_id: u32,
+        job_id: u32,
+        /// Hex encoded, filled with `0xbb` when not specified
+        prev_hash: Option<String>,
+        /// Absolute time, the current time shifted by `min_ntime_offset` is used when not
+        /// specified
+        min_ntime: Option<u32>,
+        #[serde(default)]
+        min_ntime_offset: i64,
+        #[serde(default = "default_nbits")]
+        nbits: u32,
+    },
+    SetTarget {
+        #[serde(default)]
+        channel_id: u32,
+        /// Hex encoded little endian target, it takes precedence over `difficulty`
+        max_target: Option<String>,
+        /// Pool difficulty, 0 stands for the easiest possible target
+        difficulty: Option<usize>,
+    },
+    SubmitSharesSuccess {
+        #[serde(default)]
+        channel_id: u32,
+        last_seq_num: u32,
+        new_submits_accepted_count: u32,
+        #[serde(default)]
+        new_shares_sum: u32,
+    },
+    SubmitSharesError {
+        #[serde(default)]
+        channel_id: u32,
+        seq_num: u32,
+        code: String,
+    },
+}
+
+fn default_version() -> u32 {
+    0x20000000
+}
+
+fn default_nbits() -> u32 {
+    0x1d00ffff
+}
+
+/// Solve the job dispatched last
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Solve {
+    /// Offset of ntime of the solution from `min_ntime` of the job
+    #[serde(default)]
+    pub ntime_offset: i64,
+    /// Number of solutions found
+    #[serde(default = "default_count")]
+    pub count: usize,
+}
+
+fn default_count() -> usize {
+    1
+}
+
+/// Outcomes of a step, only the specified ones are checked
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Expect {
+    /// Jobs dispatched to the backend during the step in the order of dispatch
+    pub dispatched: Option<Vec<ExpectedJob>>,
+    /// Shares sent to the pool during the step in the order of submission
+    pub submitted: Option<Vec<ExpectedShare>>,
+    /// Difficulty of the target applied locally after the step
+    pub target_difficulty: Option<usize>,
+    /// Number of submitted shares waiting for acknowledgement after the step
+    pub pending_submissions: Option<usize>,
+    /// Kinds of events raised during the step (see `events::Event::kind`)
+    pub events: Option<Vec<String>>,
+    /// Values of counters of the client after the step
+    #[serde(default)]
+    pub counters: BTreeMap<String, usize>,
+    /// Error code of the failure that makes the client disconnect
+    pub disconnect: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ExpectedJob {
+    pub job_id: u32,
+    pub channel_id: Option<u32>,
+    pub version: Option<u32>,
+    /// Hex encoded
+    pub merkle_root: Option<String>,
+    /// Difficulty of the target used locally for solving the job
+    pub difficulty: Option<usize>,
+    /// Difficulty of the target requested by the pool
+    pub pool_difficulty: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ExpectedShare {
+    pub job_id: u32,
+    pub channel_id: Option<u32>,
+    pub seq_num: Option<u32>,
+}
+
+/// Vector whose outcomes differ from the expected ones
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub vector: String,
+    pub step: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (step {}): {}", self.vector, self.step, self.reason)
+    }
+}
+
+/// Parse a JSON array of vectors
+pub fn parse(text: &str) -> error::Result<Vec<Vector>> {
+    serde_json::from_str(text)
+        .map_err(|e| error::ErrorKind::General(format!("Invalid test vectors: {}", e)).into())
+}
+
+fn hash_from_hex(hex_hash: &str) -> Result<Uint256Bytes, String> {
+    let bytes = hex::decode(hex_hash).map_err(|e| format!("invalid hex '{}': {}", hex_hash, e))?;
+    let bytes = <[u8; 32]>::try_from(&bytes[..])
+        .map_err(|_| format!("'{}' doesn't have 32 bytes", hex_hash))?;
+    Ok(Uint256Bytes(bytes))
+}
+
+fn target_from_difficulty(difficulty: usize) -> ii_bitcoin::Target {
+    if difficulty == 0 {
+        ii_bitcoin::Target::from([0xff; 32])
+    } else {
+        ii_bitcoin::Target::from_pool_difficulty(difficulty)
+    }
+}
+
+impl Message {
+    fn into_frame(self) -> Result<<Framing as ii_wire::Framing>::Rx, String> {
+        let frame = match self {
+            Self::NewMiningJob {
+                channel_id,
+                job_id,
+                future_job,
+                version,
+                merkle_root,
+            } => NewMiningJob {
+                channel_id,
+                job_id,
+                future_job,
+                version,
+                merkle_root: match merkle_root {
+                    Some(merkle_root) => hash_from_hex(&merkle_root)?,
+                    None => Uint256Bytes([job_id as u8; 32]),
+                },
+            }
+            .try_into(),
+            Self::SetNewPrevHash {
+                channel_id,
+                job_id,
+                prev_hash,
+                min_ntime,
+                min_ntime_offset,
+                nbits,
+            } => {
+                let now = time::SystemTime::now()
+                    .duration_since(time::UNIX_EPOCH)
+                    .expect("BUG: time before epoch")
+                    .as_secs() as i64;
+                SetNewPrevHash {
+                    channel_id,
+                    job_id,
+                    prev_hash: match prev_hash {
+                        Some(prev_hash) => hash_from_hex(&prev_hash)?,
+                        None => Uint256Bytes([0xbb; 32]),
+                    },
+                    min_ntime: min_ntime.unwrap_or((now + min_ntime_offset) as u32),
+                    nbits,
+                }
+                .try_into()
+            }
+            Self::SetTarget {
+                channel_id,
+                max_target,
+                difficulty,
+            } => SetTarget {
+                channel_id,
+                max_target: match (max_target, difficulty) {
+                    (Some(max_target), _) => hash_from_hex(&max_target)?,
+                    (None, Some(difficulty)) => target_from_difficulty(difficulty).into(),
+                    (None, None) => Err("set_target requires max_target or difficulty")?,
+                },
+            }
+            .try_into(),
+            Self::SubmitSharesSuccess {
+                channel_id,
+                last_seq_num,
+                new_submits_accepted_count,
+                new_shares_sum,
+            } => SubmitSharesSuccess {
+                channel_id,
+                last_seq_num,
+                new_submits_accepted_count,
+                new_shares_sum,
+            }
+            .try_into(),
+            Self::SubmitSharesError {
+                channel_id,
+                seq_num,
+                code,
+            } => SubmitSharesError {
+                channel_id,
+                seq_num,
+                code: Str0_32::try_from(code.clone())
+                    .map_err(|_| format!("error code '{}' is too long", code))?,
+            }
+            .try_into(),
+        };
+        frame.map_err(|e| format!("cannot build frame: {}", e))
+    }
+}
+
+#[derive(Debug)]
+struct VectorSolution {
+    target: ii_bitcoin::Target,
+}
+
+impl hal::BackendSolution for VectorSolution {
+    fn nonce(&self) -> u32 {
+        0x12345678
+    }
+
+    fn midstate_idx(&self) -> usize {
+        0
+    }
+
+    fn solution_idx(&self) -> usize {
+        0
+    }
+
+    fn target(&self) -> &ii_bitcoin::Target {
+        &self.target
+    }
+}
+
+/// Collects shares from frames sent by the client
+#[derive(Default)]
+struct ShareCollector {
+    shares: Vec<SubmitSharesStandard>,
+}
+
+#[async_trait]
+impl Handler for ShareCollector {
+    async fn visit_submit_shares_standard(
+        &mut self,
+        _header: &Header,
+        share_msg: &SubmitSharesStandard,
+    ) {
+        self.shares.push(share_msg.clone());
+    }
+}
+
+fn build_client(config: StratumV2Config) -> Arc<StratumClient> {
+    let connection_details = ConnectionDetails {
+        protocol: ClientProtocol::StratumV2Insecure,
+        user: "conformance".to_string(),
+        host: "localhost".to_string(),
+        port: 3336,
+        config: Arc::new(config),
+    };
+    let (_solution_sender, solution_receiver) = mpsc::unbounded();
+    let solver = job::Solver::new(Arc::new(work::EngineSender::new(None)), solution_receiver);
+    Arc::new(StratumClient::new(connection_details, None, solver, None))
+}
+
+/// Build a solution of the job dispatched last with ntime shifted by `ntime_offset`
+fn solve_last_job(client: &StratumClient, ntime_offset: i64) -> Result<work::Solution, String> {
+    let job = client
+        .last_job()
+        .ok_or_else(|| "no job has been dispatched".to_string())?;
+    let time = (job.time as i64 + ntime_offset) as u32;
+    let midstate = work::Midstate {
+        version: job.version,
+        state: Default::default(),
+    };
+    Ok(work::Solution::new(
+        work::Assignment::new(job, vec![midstate], time),
+        VectorSolution {
+            target: Default::default(),
+        },
+        None,
+    ))
+}
+
+fn counter(client: &StratumClient, name: &str) -> Result<usize, String> {
+    let counter = match name {
+        "aged_future_jobs" => client.aged_future_jobs(),
+        "below_pool_target" => client.below_pool_target(),
+        "clamped_targets" => client.clamped_targets(),
+        "duplicate_jobs" => client.duplicate_jobs(),
+        "foreign_channel_messages" => client.foreign_channel_messages(),
+        "foreign_solutions" => client.foreign_solutions(),
+        "implausible_prevhashes" => client.implausible_prevhashes(),
+        "invalid_targets" => client.invalid_targets(),
+        "ntime_overruns" => client.ntime_overruns(),
+        "ntime_regressions" => client.ntime_regressions(),
+        "ordering_violations" => client.ordering_violations(),
+        "orphan_prevhashes" => client.orphan_prevhashes(),
+        "reused_job_ids" => client.reused_job_ids(),
+        "sequencing_anomalies" => client.sequencing_anomalies(),
+        "submitted" => client.submitted(),
+        "suppressed_dispatches" => client.suppressed_dispatches(),
+        "unexpected_acks" => client.unexpected_acks(),
+        _ => Err(format!("unknown counter '{}'", name))?,
+    };
+    Ok(*counter.take_snapshot())
+}
+
+fn check_jobs(expected: &[ExpectedJob], dispatched: &[Arc<StratumJob>]) -> Result<(), String> {
+    let ids: Vec<_> = dispatched.iter().map(|job| job.id).collect();
+    let expected_ids: Vec<_> = expected.iter().map(|job| job.job_id).collect();
+    if ids != expected_ids {
+        Err(format!(
+            "dispatched jobs {:?}, expected {:?}",
+            ids, expected_ids
+        ))?;
+    }
+    for (expected, job) in expected.iter().zip(dispatched) {
+        let mismatch = |field: &str, actual: String, expected: String| -> Result<(), String> {
+            Err(format!(
+                "job {}: {} is {}, expected {}",
+                job.id, field, actual, expected
+            ))
+        };
+        if let Some(channel_id) = expected.channel_id {
+            if job.channel_id != channel_id {
+                mismatch(
+                    "channel_id",
+                    job.channel_id.to_string(),
+                    channel_id.to_string(),
+                )?;
+            }
+        }
+        if let Some(version) = expected.version {
+            if job.version != version {
+                mismatch(
+                    "version",
+                    format!("{:#x}", job.version),
+                    format!("{:#x}", version),
+                )?;
+            }
+        }
+        if let Some(merkle_root) = &expected.merkle_root {
+            let actual = hex::encode(job.merkle_root.into_inner());
+            if actual != merkle_root.to_lowercase() {
+                mismatch("merkle_root", actual, merkle_root.clone())?;
+            }
+        }
+        if let Some(difficulty) = expected.difficulty {
+            let actual = target_util::difficulty_from_target(&job.target);
+            if actual != difficulty {
+                mismatch("difficulty", actual.to_string(), difficulty.to_string())?;
+            }
+        }
+        if let Some(difficulty) = expected.pool_difficulty {
+            let actual = target_util::difficulty_from_target(&job.pool_target);
+            if actual != difficulty {
+                mismatch(
+                    "pool_difficulty",
+                    actual.to_string(),
+                    difficulty.to_string(),
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_shares(expected: &[ExpectedShare], shares: &[SubmitSharesStandard]) -> Result<(), String> {
+    let ids: Vec<_> = shares.iter().map(|share| share.job_id).collect();
+    let expected_ids: Vec<_> = expected.iter().map(|share| share.job_id).collect();
+    if ids != expected_ids {
+        Err(format!(
+            "submitted shares of jobs {:?}, expected {:?}",
+            ids, expected_ids
+        ))?;
+    }
+    for (expected, share) in expected.iter().zip(shares) {
+        if let Some(channel_id) = expected.channel_id {
+            if share.channel_id != channel_id {
+                Err(format!(
+                    "share #{}: channel_id is {}, expected {}",
+                    share.seq_num, share.channel_id, channel_id
+                ))?;
+            }
+        }
+        if let Some(seq_num) = expected.seq_num {
+            if share.seq_num != seq_num {
+                Err(format!(
+                    "share of job {}: seq_num is {}, expected {}",
+                    share.job_id, share.seq_num, seq_num
+                ))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that every solution found so far has been either submitted, dropped by exactly one local
+/// filter or held back (`held` solutions) while the submission window is full
+fn check_filters(client: &StratumClient, held: usize) -> Result<(), String> {
+    let status = client.filter_stats().status();
+    if status.found != status.passed + status.dropped + held {
+        Err(format!(
+            "{} solutions found, but {} submitted, {} dropped ({:?}) and {} held",
+            status.found, status.passed, status.dropped, status.reasons, held
+        ))?;
+    }
+    Ok(())
+}
+
+/// Outcomes of a single step
+struct Outcome {
+    result: error::Result<()>,
+    dispatched: Vec<Arc<StratumJob>>,
+    shares: Vec<SubmitSharesStandard>,
+    events: Vec<&'static str>,
+}
+
+impl Expect {
+    async fn check(&self, client: &StratumClient, outcome: &Outcome) -> Result<(), String> {
+        match (&outcome.result, &self.disconnect) {
+            (Ok(()), None) => {}
+            (Ok(()), Some(code)) => Err(format!("expected disconnect with {}", code))?,
+            (Err(e), None) => Err(format!("unexpected disconnect: {}", e))?,
+            (Err(e), Some(code)) => {
+                if e.error_code() != code.as_str() {
+                    Err(format!(
+                        "disconnected with {} ({}), expected {}",
+                        e.error_code(),
+                        e,
+                        code
+                    ))?;
+                }
+            }
+        }
+        if let Some(expected) = &self.dispatched {
+            check_jobs(expected, &outcome.dispatched)?;
+        }
+        if let Some(expected) = &self.submitted {
+            check_shares(expected, &outcome.shares)?;
+        }
+        if let Some(difficulty) = self.target_difficulty {
+            let actual = client
+                .current_target()
+                .map(|target| target_util::difficulty_from_target(&target));
+            if actual != Some(difficulty) {
+                Err(format!(
+                    "target difficulty is {:?}, expected {}",
+                    actual, difficulty
+                ))?;
+            }
+        }
+        if let Some(pending) = self.pending_submissions {
+            let actual = client.solutions.lock().await.len();
+            if actual != pending {
+                Err(format!(
+                    "{} submissions are pending, expected {}",
+                    actual, pending
+                ))?;
+            }
+        }
+        if let Some(expected) = &self.events {
+            if outcome.events != *expected {
+                Err(format!(
+                    "raised events {:?}, expected {:?}",
+                    outcome.events, expected
+                ))?;
+            }
+        }
+        for (name, value) in &self.counters {
+            let actual = counter(client, name)?;
+            if actual != *value {
+                Err(format!(
+                    "counter {} is {}, expected {}",
+                    name, actual, value
+                ))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Drive the steps of `vector` through a new client and check their outcomes
+pub async fn run(vector: &Vector) -> Result<(), Failure> {
+    let failure = |step: usize, reason: String| Failure {
+        vector: vector.name.clone(),
+        step,
+        reason,
+    };
+
+    let client = build_client(vector.config.clone());
+    let session = session::State {
+        channel: session::ChannelIdentity::new(
+            vector.session.channel_id,
+            vector.session.group_id.unwrap_or(vector.session.channel_id),
+            time::SystemTime::now(),
+        ),
+        init_target: target_from_difficulty(vector.session.init_difficulty.unwrap_or(1)),
+        ..Default::default()
+    };
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Arc::new(session), client.context());
+    let connection_tx = Arc::new(Mutex::new(replay::FrameCollector::default()));
+    let mut solution_handler =
+        StratumSolutionHandler::new(client.clone(), connection_tx.clone(), client.context());
+    let mut jobs = client.job_stream(MAX_DISPATCHED_JOBS);
+
+    for (idx, step) in vector.steps.iter().enumerate() {
+        let events_before = client.events().len();
+        let result = match (&step.receive, &step.solve) {
+            (Some(message), None) => {
+                let frame = message
+                    .clone()
+                    .into_frame()
+                    .map_err(|reason| failure(idx, reason))?;
+                client.handle_frame(frame, &mut event_handler).await
+            }
+            (None, Some(solve)) => {
+                let mut result = Ok(());
+                for _ in 0..solve.count {
+                    let solution = solve_last_job(&client, solve.ntime_offset)
+                        .map_err(|reason| failure(idx, reason))?;
+                    result = solution_handler.process_solution(solution).await;
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                result
+            }
+            _ => Err(failure(
+                idx,
+                "step has to either receive a message or solve a job".to_string(),
+            ))?,
+        };
+
+        let mut dispatched = Vec::new();
+        while let Some(Some(job)) = jobs.next().now_or_never() {
+            dispatched.push(job);
+        }
+        let frames = std::mem::replace(&mut connection_tx.lock().await.frames, Vec::new());
+        let mut collector = ShareCollector::default();
+        for frame in frames {
+            let message = build_message_from_frame(frame)
+                .map_err(|e| failure(idx, format!("client sent invalid frame: {}", e)))?;
+            message.accept(&mut collector).await;
+        }
+        let events = client
+            .events()
+            .iter()
+            .skip(events_before)
+            .map(|record| record.event.kind())
+            .collect();
+
+        let disconnected = result.is_err();
+        let outcome = Outcome {
+            result,
+            dispatched,
+            shares: collector.shares,
+            events,
+        };
+        step.expect
+            .check(&client, &outcome)
+            .await
+            .map_err(|reason| failure(idx, reason))?;
+        check_filters(&client, solution_handler.held.len())
+            .map_err(|reason| failure(idx, reason))?;
+        if disconnected {
+            if idx + 1 < vector.steps.len() {
+                Err(failure(
+                    idx,
+                    "the client has disconnected before the last step".to_string(),
+                ))?;
+            }
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Run all `vectors`, returns the vectors that have failed
+pub async fn run_suite(vectors: &[Vector]) -> Vec<Failure> {
+    let mut failures = Vec::new();
+    for vector in vectors {
+        if let Err(failure) = run(vector).await {
+            failures.push(failure);
+        }
+    }
+    failures
+}
@@ -0,0 +1,319 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Timing of the phases of connection attempts (e.g. for verifying the connection establishment
+//! SLA of the pool). Every attempt is broken down into the phases it has gone through, a failed
+//! attempt records the phase that has failed. The timer is passed along the connect path, the
+//! clock can be replaced for tests.
+//!
+//! Notes:
+//! - the connection is secured by the Noise handshake, there is no TLS
+//! - the client doesn't write any PROXY protocol header
+//! - the latency probe (`probe`) measures an established connection, it doesn't go through the
+//!   connect sequence
+
+use super::diagnostics;
+
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time;
+
+/// Source of monotonic time of the timer
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> time::Instant;
+}
+
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> time::Instant {
+        time::Instant::now()
+    }
+}
+
+/// Phases of a connection attempt in the order they are entered
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Resolution of the pool host
+    Dns,
+    /// TCP connect to a single resolved address
+    TcpConnect,
+    /// Noise handshake of a secure connection (including the verification of the pool
+    /// certificate)
+    NoiseHandshake,
+    /// `SetupConnection` round-trip
+    SetupConnection,
+    /// `OpenStandardMiningChannel` round-trip (every attempt to open the channel)
+    ChannelOpen,
+    /// Time from the opened channel to the first job dispatched to the backend
+    FirstJob,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    Failed,
+}
+
+impl Outcome {
+    /// Outcome of a phase that has produced `result`
+    pub fn of<T, E>(result: &Result<T, E>) -> Self {
+        match result {
+            Ok(_) => Self::Ok,
+            Err(_) => Self::Failed,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PhaseTiming {
+    pub phase: Phase,
+    pub duration_ms: u64,
+    pub outcome: Outcome,
+    /// Address that has been tried by the phase (TCP connect)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Attempt {
+    /// Time when the attempt has started
+    pub time: time::SystemTime,
+    pub phases: Vec<PhaseTiming>,
+    /// Phase in which the attempt has failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_phase: Option<Phase>,
+}
+
+impl Attempt {
+    fn push(&mut self, timing: PhaseTiming) {
+        self.failed_phase = match timing.outcome {
+            Outcome::Ok => None,
+            Outcome::Failed => Some(timing.phase),
+        };
+        self.phases.push(timing);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Builder {
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl Builder {
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// The attempt starts when the timer is built
+    pub fn build(self) -> Timer {
+        Timer {
+            clock: self.clock.unwrap_or_else(|| Arc::new(SystemClock)),
+            attempt: Attempt {
+                time: time::SystemTime::now(),
+                phases: Vec::new(),
+                failed_phase: None,
+            },
+            current: None,
+        }
+    }
+}
+
+/// Phase that has been started and not finished yet
+#[derive(Debug, Clone)]
+pub struct Pending {
+    clock: Arc<dyn Clock>,
+    phase: Phase,
+    address: Option<String>,
+    started: time::Instant,
+}
+
+impl Pending {
+    pub fn finish(&self, outcome: Outcome) -> PhaseTiming {
+        PhaseTiming {
+            phase: self.phase,
+            duration_ms: self
+                .clock
+                .now()
+                .saturating_duration_since(self.started)
+                .as_millis() as u64,
+            outcome,
+            address: self.address.clone(),
+        }
+    }
+}
+
+/// Timer of a single connection attempt
+#[derive(Debug)]
+pub struct Timer {
+    clock: Arc<dyn Clock>,
+    attempt: Attempt,
+    current: Option<Pending>,
+}
+
+impl Timer {
+    pub fn builder() -> Builder {
+        Default::default()
+    }
+
+    /// Start a phase that is timed independently of the timer (it outlives the attempt)
+    pub fn pending(&self, phase: Phase) -> Pending {
+        Pending {
+            clock: self.clock.clone(),
+            phase,
+            address: None,
+            started: self.clock.now(),
+        }
+    }
+
+    /// Start `phase`, the phase that is still running is finished as failed
+    pub fn start(&mut self, phase: Phase, address: Option<String>) {
+        self.end(Outcome::Failed);
+        let mut pending = self.pending(phase);
+        pending.address = address;
+        self.current = Some(pending);
+    }
+
+    /// Finish the running phase
+    pub fn end(&mut self, outcome: Outcome) {
+        if let Some(pending) = self.current.take() {
+            self.attempt.push(pending.finish(outcome));
+        }
+    }
+
+    /// Finish the attempt, the phase that is still running (e.g. interrupted by a timeout) is
+    /// recorded as failed
+    pub fn finish(mut self) -> Attempt {
+        self.end(Outcome::Failed);
+        self.attempt
+    }
+}
+
+/// Aggregation of the durations of a phase over the retained attempts
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PhaseSummary {
+    pub phase: Phase,
+    /// Number of successful executions of the phase
+    pub count: usize,
+    pub failed: usize,
+    /// Median duration of successful executions in milliseconds
+    pub median_ms: u64,
+    /// 95th percentile of the duration of successful executions in milliseconds
+    pub p95_ms: u64,
+}
+
+/// Nearest-rank percentile of sorted `values`
+fn percentile(values: &[u64], percent: usize) -> u64 {
+    let rank = (values.len() * percent + 99) / 100;
+    values[rank.max(1) - 1]
+}
+
+/// Recent connection attempts
+#[derive(Debug)]
+pub struct History {
+    attempts: diagnostics::Ring<Attempt>,
+}
+
+impl History {
+    /// Number of the most recent attempts retained
+    pub const CAPACITY: usize = 64;
+
+    pub fn push(&mut self, attempt: Attempt) {
+        self.attempts.push(attempt);
+    }
+
+    /// Complete the most recent attempt with the time to its first job. Ignored when the time has
+    /// been recorded already or when the attempt has failed.
+    pub fn first_job(&mut self, timing: PhaseTiming) {
+        let index = match self.attempts.len().checked_sub(1) {
+            Some(index) => index,
+            None => return,
+        };
+        let mut attempt = self
+            .attempts
+            .remove(index)
+            .expect("BUG: missing connection attempt");
+        let completed = attempt.failed_phase.is_some()
+            || attempt
+                .phases
+                .iter()
+                .any(|timing| timing.phase == Phase::FirstJob);
+        if !completed {
+            attempt.push(timing);
+        }
+        self.attempts.push(attempt);
+    }
+
+    /// Attempts in chronological order
+    pub fn attempts(&self) -> Vec<Attempt> {
+        self.attempts.iter().cloned().collect()
+    }
+
+    /// Median and 95th percentile of each phase, it is computed on demand
+    pub fn summary(&self) -> Vec<PhaseSummary> {
+        let mut phases: BTreeMap<Phase, (Vec<u64>, usize)> = BTreeMap::new();
+        for timing in self
+            .attempts
+            .iter()
+            .flat_map(|attempt| attempt.phases.iter())
+        {
+            let (durations, failed) = phases.entry(timing.phase).or_default();
+            match timing.outcome {
+                Outcome::Ok => durations.push(timing.duration_ms),
+                Outcome::Failed => *failed += 1,
+            }
+        }
+        phases
+            .into_iter()
+            .map(|(phase, (mut durations, failed))| {
+                durations.sort();
+                let (median_ms, p95_ms) = if durations.is_empty() {
+                    (0, 0)
+                } else {
+                    (percentile(&durations, 50), percentile(&durations, 95))
+                };
+                PhaseSummary {
+                    phase,
+                    count: durations.len(),
+                    failed,
+                    median_ms,
+                    p95_ms,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            attempts: diagnostics::Ring::new(Self::CAPACITY),
+        }
+    }
+}
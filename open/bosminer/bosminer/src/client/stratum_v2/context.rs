@@ -0,0 +1,87 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Identifiers that attribute log lines and events to a specific client, connection attempt and
+//! mining session. They allow joining logs of many miners collected by a single aggregator with
+//! data provided by the API.
+
+use serde::Serialize;
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct Context {
+    /// Short identifier of the client derived from its endpoint
+    pub client_id: u16,
+    /// Sequence number of the connection attempt
+    pub connection_id: u64,
+    /// Sequence number of the mining session (successfully opened channel). It is zero until the
+    /// first channel is opened and then it stays the same until next channel is opened.
+    pub session_id: u64,
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{:04x}/a{}/s{}]",
+            self.client_id, self.connection_id, self.session_id
+        )
+    }
+}
+
+/// Derive short client identifier from the endpoint description. FNV-1a is used because its
+/// output is stable across builds and platforms.
+pub fn client_id(endpoint: &str) -> u16 {
+    let hash = endpoint.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+    (hash >> 16) as u16 ^ hash as u16
+}
+
+/// Monotonic counters that are the source of connection and session identifiers
+#[derive(Debug, Default)]
+pub struct Counters {
+    connection_id: AtomicU64,
+    session_id: AtomicU64,
+}
+
+impl Counters {
+    /// Start a new connection attempt and return its identifier
+    pub fn next_connection_id(&self) -> u64 {
+        self.connection_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Start a new mining session and return its identifier
+    pub fn next_session_id(&self) -> u64 {
+        self.session_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn connection_id(&self) -> u64 {
+        self.connection_id.load(Ordering::Relaxed)
+    }
+
+    pub fn session_id(&self) -> u64 {
+        self.session_id.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Diagnostic collections of the client can be exported incrementally in chunks of limited size.
+//! Every record of a collection is assigned a monotonically increasing id. The id of the last
+//! exported record serves as a cursor for the next request. The cursor stays valid as long as
+//! the record following it hasn't been evicted from the collection, otherwise the export is
+//! flagged as truncated and restarts from the oldest available record.
+
+use serde::Serialize;
+
+use std::collections::VecDeque;
+
+/// Diagnostic collection to be exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Section {
+    Events,
+    PoolNotices,
+    LatencyProbes,
+}
+
+/// Request for the records of `section` following `since_cursor`. Cursor 0 requests the
+/// collection from the beginning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportDiagnostics {
+    pub section: Section,
+    pub since_cursor: u64,
+    /// Maximal total size of serialized records in the chunk. The first record is always
+    /// included so that the export makes progress even with a record larger than the limit.
+    pub max_bytes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Chunk {
+    pub section: Section,
+    /// JSON serialized records in the order of their ids
+    pub records: Vec<String>,
+    /// Cursor to be used in the request for the next chunk
+    pub cursor: u64,
+    /// Records following the requested cursor have been evicted, the chunk starts with the oldest
+    /// available record
+    pub truncated: bool,
+    /// There are more records following the cursor
+    pub more: bool,
+}
+
+/// Collection of limited capacity that assigns ids to the records. The oldest records are
+/// evicted when the capacity is exceeded.
+#[derive(Debug)]
+pub struct Ring<T> {
+    capacity: usize,
+    next_id: u64,
+    /// Highest id that has been evicted
+    evicted_id: u64,
+    records: VecDeque<(u64, T)>,
+}
+
+impl<T> Ring<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            next_id: 1,
+            evicted_id: 0,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Append `record` and return its id
+    pub fn push(&mut self, record: T) -> u64 {
+        while self.records.len() >= self.capacity.max(1) {
+            self.evict();
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.records.push_back((id, record));
+        id
+    }
+
+    fn evict(&mut self) {
+        if let Some((id, _)) = self.records.pop_front() {
+            self.evicted_id = id;
+        }
+    }
+
+    /// Remove the record at `index` (in the chronological order). The removal isn't an
+    /// eviction, the record is expected to be pushed again with a new id.
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        self.records.remove(index).map(|(_, record)| record)
+    }
+
+    /// Id of the oldest record that can be exported
+    pub fn oldest_available_id(&self) -> u64 {
+        self.evicted_id + 1
+    }
+
+    /// Id of the most recent record or 0 when no record has been pushed yet
+    pub fn newest_id(&self) -> u64 {
+        self.next_id - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Records in chronological order
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.records.iter().map(|(_, record)| record)
+    }
+
+    /// Export records following the cursor of `request`. Each record is serialized with
+    /// `serialize` which is passed the id of the record.
+    pub fn export<F>(&self, request: &ExportDiagnostics, mut serialize: F) -> Chunk
+    where
+        F: FnMut(u64, &T) -> String,
+    {
+        let truncated = request.since_cursor < self.evicted_id;
+        let mut pending = self
+            .records
+            .iter()
+            .filter(|(id, _)| *id > request.since_cursor)
+            .peekable();
+
+        let mut chunk = Chunk {
+            section: request.section,
+            records: Vec::new(),
+            cursor: request.since_cursor,
+            truncated,
+            more: false,
+        };
+        let mut size = 0;
+        while let Some((id, record)) = pending.peek() {
+            let serialized = serialize(*id, record);
+            if !chunk.records.is_empty() && size + serialized.len() > request.max_bytes {
+                break;
+            }
+            size += serialized.len();
+            chunk.cursor = *id;
+            chunk.records.push(serialized);
+            pending.next();
+        }
+        chunk.more = pending.peek().is_some();
+        chunk
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+
+//! Optional ceiling on the rate of job switches dispatched to the backend. The limit is enforced
+//! with a token bucket that persists across sessions, so reconnecting to the pool doesn't refill
+//! it. Jobs that bring a new previous block hash are not subject to the limit.
+//!
+//! The limiter also keeps track of the share of job updates it suppresses per hour so that an
+//! operator can be advised when the churn of the pool doesn't match the configured limit.
+
+use bosminer_config::StratumV2JobDispatchLimit;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex as StdMutex;
+use std::time;
+
+const ENGAGEMENT_WINDOW: time::Duration = time::Duration::from_secs(3600);
+
+/// Token bucket that is refilled continuously with `rate` tokens per second up to `burst`
+/// tokens. It starts full.
+#[derive(Debug, Clone)]
+pub struct Bucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled: time::Instant,
+}
+
+impl Bucket {
+    pub fn new(per_minute: f64, burst: u32, now: time::Instant) -> Self {
+        Self {
+            rate: per_minute / 60.0,
+            burst: burst as f64,
+            tokens: burst as f64,
+            refilled: now,
+        }
+    }
+
+    fn refill(&mut self, now: time::Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled = now;
+    }
+
+    /// Returns number of tokens available at `now`
+    pub fn tokens(&mut self, now: time::Instant) -> f64 {
+        self.refill(now);
+        self.tokens
+    }
+
+    /// Returns true when a token has been taken
+    pub fn try_take(&mut self, now: time::Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns time remaining until a token is available
+    pub fn until_token(&mut self, now: time::Instant) -> time::Duration {
+        self.refill(now);
+        let missing = 1.0 - self.tokens;
+        if missing <= 0.0 {
+            time::Duration::from_secs(0)
+        } else {
+            time::Duration::from_secs_f64(missing / self.rate)
+        }
+    }
+}
+
+/// Job updates received from the pool and suppressed by the limiter within one hour
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Engagement {
+    pub updates: u64,
+    pub suppressed: u64,
+}
+
+impl Engagement {
+    /// More than half of the job updates have been suppressed
+    fn is_excessive(&self) -> bool {
+        self.suppressed * 2 > self.updates
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    limit: StratumV2JobDispatchLimit,
+    bucket: Bucket,
+    window_start: time::Instant,
+    engagement: Engagement,
+}
+
+impl State {
+    fn new(limit: StratumV2JobDispatchLimit, now: time::Instant) -> Self {
+        let bucket = Bucket::new(
+            limit.rate,
+            limit
+                .burst
+                .unwrap_or(StratumV2JobDispatchLimit::DEFAULT_BURST),
+            now,
+        );
+        Self {
+            limit,
+            bucket,
+            window_start: now,
+            engagement: Engagement {
+                updates: 0,
+                suppressed: 0,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Limiter {
+    /// The limiter is disabled when there is no state
+    state: StdMutex<Option<State>>,
+    /// The advisory about excessive suppression is emitted only once
+    advised: AtomicBool,
+}
+
+impl Limiter {
+    fn lock_state(&self) -> std::sync::MutexGuard<Option<State>> {
+        self.state
+            .lock()
+            .expect("BUG: cannot lock dispatch limiter")
+    }
+
+    /// Apply configured `limit`. The state of the bucket is kept as long as the limit doesn't
+    /// change.
+    pub fn configure(&self, limit: Option<StratumV2JobDispatchLimit>, now: time::Instant) {
+        let mut state = self.lock_state();
+        match limit {
+            None => {
+                state.take();
+            }
+            Some(limit) => {
+                if state.as_ref().map(|state| &state.limit) != Some(&limit) {
+                    state.replace(State::new(limit, now));
+                }
+            }
+        }
+    }
+
+    /// Account a job update received from the pool. Returns the engagement of the limiter over
+    /// the last hour when it has suppressed more than half of the updates for the first time.
+    pub fn job_update(&self, now: time::Instant) -> Option<Engagement> {
+        let mut state = self.lock_state();
+        let state = state.as_mut()?;
+        let mut excessive = None;
+        if now.saturating_duration_since(state.window_start) >= ENGAGEMENT_WINDOW {
+            if state.engagement.is_excessive() && !self.advised.swap(true, Ordering::Relaxed) {
+                excessive = Some(state.engagement);
+            }
+            state.window_start = now;
+            state.engagement = Engagement {
+                updates: 0,
+                suppressed: 0,
+            };
+        }
+        state.engagement.updates += 1;
+        excessive
+    }
+
+    /// Account a job update that is never going to be dispatched
+    pub fn suppressed(&self) {
+        if let Some(state) = self.lock_state().as_mut() {
+            state.engagement.suppressed += 1;
+        }
+    }
+
+    /// Returns true when a job update may be dispatched (always when the limiter is disabled)
+    pub fn try_acquire(&self, now: time::Instant) -> bool {
+        self.lock_state()
+            .as_mut()
+            .map_or(true, |state| state.bucket.try_take(now))
+    }
+
+    /// Returns time remaining until a job update may be dispatched
+    pub fn until_available(&self, now: time::Instant) -> time::Duration {
+        self.lock_state()
+            .as_mut()
+            .map_or(time::Duration::from_secs(0), |state| {
+                state.bucket.until_token(now)
+            })
+    }
+
+    /// Returns number of job updates that can be dispatched right away (`None` when the limiter
+    /// is disabled)
+    pub fn tokens(&self, now: time::Instant) -> Option<f64> {
+        Some(self.lock_state().as_mut()?.bucket.tokens(now))
+    }
+}
@@ -0,0 +1,267 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Advisory events that are worth the attention of an operator. The client keeps only a limited
+//! number of the most recent events.
+
+use super::clock_skew;
+use super::context;
+use super::diagnostics;
+use super::dispatch_limit;
+use super::faults;
+use super::filters;
+use super::notices;
+use super::ordering;
+use super::reject_streak;
+use super::restart_storm;
+use super::status;
+use super::target_backfill;
+use super::template_quality;
+use super::transmit;
+
+use crate::client::switches;
+use crate::work;
+
+use serde::Serialize;
+
+use std::fmt;
+use std::sync::Mutex as StdMutex;
+use std::time;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// Pool requested a target outside of the configured range of difficulty. Persistent
+    /// clamping means that the pool and our configuration disagree about the size of this miner
+    /// (usually a misconfigured nominal hashrate).
+    TargetClamped {
+        requested_difficulty: usize,
+        applied_difficulty: usize,
+    },
+    /// New distinct notice has been received from the pool
+    PoolNotice(notices::Notice),
+    /// The pool acknowledged shares out of the order of submission (strict sequencing mode)
+    SequencingAnomaly(ordering::Anomaly),
+    /// The job dispatch limit has suppressed more than half of the job updates sent by the pool
+    /// within an hour. The churn of the pool and the configured limit are badly matched. It is
+    /// reported only once.
+    DispatchLimitEngaged(dispatch_limit::Engagement),
+    /// The scheduler has activated or deactivated the client
+    Switch(switches::Annotation),
+    /// Median skew between the pool time and the local clock has exceeded the threshold
+    ClockSkew(clock_skew::Advisory),
+    /// The target has been corrected from the evidence of the session before the pool has set it
+    TargetCorrected(target_backfill::Correction),
+    /// The client has failed and is going to be restarted
+    Failure(status::LastError),
+    /// Share submissions stall while the pool keeps sending frames, the connection is restarted
+    /// unless the stall disappears within the grace period
+    TransmitStalled(transmit::Stall),
+    /// Solutions have been dropped by a local filter instead of being submitted. It is reported
+    /// at most once a minute for each reason.
+    SolutionsFiltered(filters::Dropped),
+    /// The pool has been restarting (rolling restart of its frontends), the failures of the
+    /// individual connection attempts are not reported separately
+    PoolRestart(restart_storm::Summary),
+    /// The pool doesn't accept annotations of submissions, shares are submitted without the
+    /// configured annotation. It is reported once for each connection.
+    AnnotationOmitted,
+    /// Shares have been consecutively rejected for the same reason, which indicates a systemic
+    /// problem (e.g. a misconfigured user). It is reported once per streak.
+    RejectStreak(reject_streak::Streak),
+    /// An operator has armed a synthetic fault for a resilience drill
+    FaultArmed(faults::Fault),
+    /// A synthetic fault has run out of its scope or it has been disarmed by an operator
+    FaultDisarmed(faults::Fault),
+    /// The pool refreshes job templates less often than the configured floor. It is reported at
+    /// most once per session.
+    StaleTemplates(template_quality::Advisory),
+    /// A solution of a job that has not been generated by the client has been received and
+    /// dropped. It is reported once for each connection.
+    ForeignSolution(work::OriginMismatch),
+}
+
+impl Event {
+    /// Name of the kind of the event
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::TargetClamped { .. } => "target_clamped",
+            Self::PoolNotice(_) => "pool_notice",
+            Self::SequencingAnomaly(_) => "sequencing_anomaly",
+            Self::DispatchLimitEngaged(_) => "dispatch_limit_engaged",
+            Self::Switch(_) => "switch",
+            Self::ClockSkew(_) => "clock_skew",
+            Self::TargetCorrected(_) => "target_corrected",
+            Self::Failure(_) => "failure",
+            Self::TransmitStalled(_) => "transmit_stalled",
+            Self::SolutionsFiltered(_) => "solutions_filtered",
+            Self::PoolRestart(_) => "pool_restart",
+            Self::AnnotationOmitted => "annotation_omitted",
+            Self::RejectStreak(_) => "reject_streak",
+            Self::FaultArmed(_) => "fault_armed",
+            Self::FaultDisarmed(_) => "fault_disarmed",
+            Self::StaleTemplates(_) => "stale_templates",
+            Self::ForeignSolution(_) => "foreign_solution",
+        }
+    }
+
+    /// Stable code of the error reported by the event
+    pub fn error_code(&self) -> Option<&'static str> {
+        match self {
+            Self::Failure(last_error) => Some(last_error.code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TargetClamped {
+                requested_difficulty,
+                applied_difficulty,
+            } => write!(
+                f,
+                "target clamped: requested difficulty {}, applied difficulty {}",
+                requested_difficulty, applied_difficulty
+            ),
+            Self::PoolNotice(notice) => write!(f, "pool notice: {}", notice.text),
+            Self::SequencingAnomaly(anomaly) => write!(f, "sequencing anomaly: {}", anomaly),
+            Self::DispatchLimitEngaged(engagement) => write!(
+                f,
+                "dispatch limit engaged: {} of {} job updates suppressed",
+                engagement.suppressed, engagement.updates
+            ),
+            Self::Switch(annotation) => write!(
+                f,
+                "switch: {:?} ({}), peer {}",
+                annotation.role, annotation.reason, annotation.peer
+            ),
+            Self::ClockSkew(advisory) => write!(f, "clock skew: {}", advisory),
+            Self::TargetCorrected(correction) => write!(f, "target corrected: {}", correction),
+            Self::Failure(last_error) => {
+                write!(f, "failure [{}]: {}", last_error.code, last_error.message)
+            }
+            Self::TransmitStalled(stall) => write!(f, "transmit stalled: {}", stall),
+            Self::SolutionsFiltered(dropped) => write!(f, "solutions filtered: {}", dropped),
+            Self::PoolRestart(summary) => write!(f, "pool restart: {}", summary),
+            Self::AnnotationOmitted => write!(
+                f,
+                "annotation omitted: pool doesn't accept annotations of submissions"
+            ),
+            Self::RejectStreak(streak) => write!(f, "reject streak: {}", streak),
+            Self::FaultArmed(fault) => write!(f, "drill: fault armed: {}", fault),
+            Self::FaultDisarmed(fault) => write!(f, "drill: fault disarmed: {}", fault),
+            Self::StaleTemplates(advisory) => write!(f, "stale templates: {}", advisory),
+            Self::ForeignSolution(mismatch) => write!(f, "foreign solution: {}", mismatch),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub time: time::SystemTime,
+    /// Identifiers of the connection and session in which the event occurred
+    pub context: context::Context,
+    pub event: Event,
+}
+
+#[derive(Debug)]
+pub struct Log {
+    records: StdMutex<diagnostics::Ring<Record>>,
+}
+
+/// Form of a record in the diagnostics export
+#[derive(Serialize)]
+struct Exported<'a> {
+    id: u64,
+    time: time::SystemTime,
+    #[serde(flatten)]
+    context: &'a context::Context,
+    event: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+}
+
+impl Log {
+    pub const DEFAULT_CAPACITY: usize = 64;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: StdMutex::new(diagnostics::Ring::new(capacity)),
+        }
+    }
+
+    /// Record the event and return the record
+    pub fn push(&self, context: context::Context, event: Event) -> Record {
+        self.push_at(context, event, time::SystemTime::now())
+    }
+
+    pub fn push_at(
+        &self,
+        context: context::Context,
+        event: Event,
+        now: time::SystemTime,
+    ) -> Record {
+        let record = Record {
+            time: now,
+            context,
+            event,
+        };
+        self.records
+            .lock()
+            .expect("BUG: cannot lock event log")
+            .push(record.clone());
+        record
+    }
+
+    /// Returns all retained events in chronological order
+    pub fn snapshot(&self) -> Vec<Record> {
+        self.records
+            .lock()
+            .expect("BUG: cannot lock event log")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn export(&self, request: &diagnostics::ExportDiagnostics) -> diagnostics::Chunk {
+        self.records
+            .lock()
+            .expect("BUG: cannot lock event log")
+            .export(request, |id, record| {
+                let exported = Exported {
+                    id,
+                    time: record.time,
+                    context: &record.context,
+                    event: record.event.to_string(),
+                    error_code: record.event.error_code(),
+                };
+                serde_json::to_string(&exported).expect("BUG: cannot serialize event")
+            })
+    }
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
@@ -0,0 +1,338 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Synthetic protocol faults for resilience drills (what happens to the rig when the pool starts
+//! misbehaving) without touching the pool. Faults are armed by an operator at runtime through
+//! `StratumClient::arm_fault`, which is available only with the `fault-injection` feature and
+//! only when `fault_injection` is enabled in the configuration.
+//!
+//! The handlers consult `Injection` at the existing seams:
+//! - acknowledgement processing (dropped or delayed acknowledgements)
+//! - job dispatch (corrupted target of the next job)
+//! - submitter (submissions that are never sent)
+//! - main loop of the connection (forced disconnect)
+//!
+//! Builds without the feature consult `Disabled`, whose methods are the empty defaults of the
+//! trait and are optimized away. Every fault disarms itself after its scope, armed and disarmed
+//! faults are recorded as events and active faults are listed in the status document so that a
+//! drill cannot be mistaken for a real incident.
+
+use serde::Serialize;
+
+use std::fmt;
+use std::time;
+
+/// Fault armed by an operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fault {
+    /// Drop the next `count` acknowledgements received from the pool
+    DropAcks { count: usize },
+    /// Delay processing of the next `count` acknowledgements by `delay_ms`
+    DelayAcks { delay_ms: u64, count: usize },
+    /// Dispatch the next job with the easiest possible target
+    CorruptNextJobTarget,
+    /// Close the connection `after_secs` after arming. The connection that is active at that time
+    /// is closed, the next connection is closed right away when the client is not connected.
+    ForceDisconnect { after_secs: u64 },
+    /// Don't send shares to the pool for `secs`, the shares are accounted as submitted
+    BlackholeSubmissions { secs: u64 },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DropAcks { count } => write!(f, "drop next {} acknowledgements", count),
+            Self::DelayAcks { delay_ms, count } => {
+                write!(f, "delay next {} acknowledgements by {}ms", count, delay_ms)
+            }
+            Self::CorruptNextJobTarget => write!(f, "corrupt target of the next job"),
+            Self::ForceDisconnect { after_secs } => {
+                write!(f, "force disconnect in {}s", after_secs)
+            }
+            Self::BlackholeSubmissions { secs } => write!(f, "blackhole submissions for {}s", secs),
+        }
+    }
+}
+
+/// Fault that is armed at the moment
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Active {
+    pub fault: Fault,
+    pub armed_at: time::SystemTime,
+    /// Number of remaining occurrences of a fault limited by a count
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining: Option<usize>,
+    /// Time in milliseconds until a fault limited by time disarms (or triggers)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_ms: Option<u64>,
+}
+
+/// Points of the handlers at which faults are injected. The defaults inject nothing.
+pub trait Injection {
+    /// Returns true when the received acknowledgement is to be dropped
+    fn drop_ack(&self, _now: time::Instant) -> bool {
+        false
+    }
+
+    /// Returns delay of processing of the received acknowledgement
+    fn ack_delay(&self, _now: time::Instant) -> Option<time::Duration> {
+        None
+    }
+
+    /// Returns true when the target of the job that is being dispatched is to be corrupted
+    fn corrupt_job_target(&self, _now: time::Instant) -> bool {
+        false
+    }
+
+    /// Returns true when the share is not to be sent to the pool
+    fn blackhole_submission(&self, _now: time::Instant) -> bool {
+        false
+    }
+
+    /// Returns time remaining until the connection is to be closed
+    fn disconnect_delay(&self, _now: time::Instant) -> Option<time::Duration> {
+        None
+    }
+
+    /// Returns the forced disconnect when the connection is to be closed right now
+    fn disconnect(&self, _now: time::Instant) -> Option<Fault> {
+        None
+    }
+
+    fn active(&self, _now: time::Instant) -> Vec<Active> {
+        Vec::new()
+    }
+
+    /// Returns faults that have been disarmed since the last call
+    fn take_disarmed(&self) -> Vec<Fault> {
+        Vec::new()
+    }
+}
+
+/// Injection used when the client is built without the `fault-injection` feature
+#[derive(Debug, Default)]
+pub struct Disabled;
+
+impl Injection for Disabled {}
+
+#[cfg(feature = "fault-injection")]
+pub type Faults = Injector;
+#[cfg(not(feature = "fault-injection"))]
+pub type Faults = Disabled;
+
+#[cfg(feature = "fault-injection")]
+pub use injector::Injector;
+
+#[cfg(feature = "fault-injection")]
+mod injector {
+    use super::*;
+
+    use std::sync::Mutex as StdMutex;
+
+    #[derive(Debug)]
+    struct Armed {
+        fault: Fault,
+        armed_at: time::SystemTime,
+        /// Remaining occurrences of a fault limited by a count
+        remaining: usize,
+        /// Deadline of a fault limited by time
+        deadline: Option<time::Instant>,
+    }
+
+    #[derive(Debug, Default)]
+    struct State {
+        armed: Vec<Armed>,
+        disarmed: Vec<Fault>,
+    }
+
+    impl State {
+        /// Disarm blackholing that has run out of its time
+        fn expire(&mut self, now: time::Instant) {
+            let disarmed = &mut self.disarmed;
+            self.armed
+                .retain(|armed| match (armed.fault, armed.deadline) {
+                    (Fault::BlackholeSubmissions { .. }, Some(deadline)) if now >= deadline => {
+                        disarmed.push(armed.fault);
+                        false
+                    }
+                    _ => true,
+                });
+        }
+
+        /// Consume a single occurrence of the armed fault selected by `select`, the fault is
+        /// disarmed with its last occurrence
+        fn consume<T, F>(&mut self, now: time::Instant, select: F) -> Option<T>
+        where
+            F: Fn(&Fault) -> Option<T>,
+        {
+            self.expire(now);
+            let index = self
+                .armed
+                .iter()
+                .position(|armed| select(&armed.fault).is_some())?;
+            let value = select(&self.armed[index].fault);
+            self.armed[index].remaining -= 1;
+            if self.armed[index].remaining == 0 {
+                let armed = self.armed.remove(index);
+                self.disarmed.push(armed.fault);
+            }
+            value
+        }
+    }
+
+    /// Faults armed by an operator
+    #[derive(Debug, Default)]
+    pub struct Injector {
+        state: StdMutex<State>,
+    }
+
+    impl Injector {
+        fn lock_state(&self) -> std::sync::MutexGuard<State> {
+            self.state.lock().expect("BUG: cannot lock injected faults")
+        }
+
+        /// Arm `fault` at `now`, it replaces an armed fault of the same kind. Returns the replaced
+        /// fault.
+        pub fn arm(&self, fault: Fault, now: time::Instant) -> Option<Fault> {
+            let (remaining, deadline) = match fault {
+                Fault::DropAcks { count } | Fault::DelayAcks { count, .. } => (count, None),
+                Fault::CorruptNextJobTarget => (1, None),
+                Fault::ForceDisconnect { after_secs: secs }
+                | Fault::BlackholeSubmissions { secs } => {
+                    (1, Some(now + time::Duration::from_secs(secs)))
+                }
+            };
+            let mut state = self.lock_state();
+            let replaced = state
+                .armed
+                .iter()
+                .position(|armed| {
+                    std::mem::discriminant(&armed.fault) == std::mem::discriminant(&fault)
+                })
+                .map(|index| state.armed.remove(index).fault);
+            if remaining > 0 {
+                state.armed.push(Armed {
+                    fault,
+                    armed_at: time::SystemTime::now(),
+                    remaining,
+                    deadline,
+                });
+            }
+            replaced
+        }
+
+        /// Disarm all faults, the faults are reported as disarmed
+        pub fn disarm_all(&self) {
+            let mut state = self.lock_state();
+            let armed: Vec<_> = state.armed.drain(..).map(|armed| armed.fault).collect();
+            state.disarmed.extend(armed);
+        }
+    }
+
+    impl Injection for Injector {
+        fn drop_ack(&self, now: time::Instant) -> bool {
+            self.lock_state()
+                .consume(now, |fault| match fault {
+                    Fault::DropAcks { .. } => Some(()),
+                    _ => None,
+                })
+                .is_some()
+        }
+
+        fn ack_delay(&self, now: time::Instant) -> Option<time::Duration> {
+            self.lock_state().consume(now, |fault| match fault {
+                Fault::DelayAcks { delay_ms, .. } => Some(time::Duration::from_millis(*delay_ms)),
+                _ => None,
+            })
+        }
+
+        fn corrupt_job_target(&self, now: time::Instant) -> bool {
+            self.lock_state()
+                .consume(now, |fault| match fault {
+                    Fault::CorruptNextJobTarget => Some(()),
+                    _ => None,
+                })
+                .is_some()
+        }
+
+        fn blackhole_submission(&self, now: time::Instant) -> bool {
+            let mut state = self.lock_state();
+            state.expire(now);
+            state.armed.iter().any(|armed| match armed.fault {
+                Fault::BlackholeSubmissions { .. } => true,
+                _ => false,
+            })
+        }
+
+        fn disconnect_delay(&self, now: time::Instant) -> Option<time::Duration> {
+            self.lock_state()
+                .armed
+                .iter()
+                .find_map(|armed| match armed.fault {
+                    Fault::ForceDisconnect { .. } => armed.deadline,
+                    _ => None,
+                })
+                .map(|deadline| deadline.saturating_duration_since(now))
+        }
+
+        fn disconnect(&self, now: time::Instant) -> Option<Fault> {
+            let mut state = self.lock_state();
+            let index = match state.armed.iter().position(|armed| match armed.fault {
+                Fault::ForceDisconnect { .. } => {
+                    armed.deadline.map_or(false, |deadline| now >= deadline)
+                }
+                _ => false,
+            }) {
+                Some(index) => index,
+                None => return None,
+            };
+            let armed = state.armed.remove(index);
+            state.disarmed.push(armed.fault);
+            Some(armed.fault)
+        }
+
+        fn active(&self, now: time::Instant) -> Vec<Active> {
+            let mut state = self.lock_state();
+            state.expire(now);
+            state
+                .armed
+                .iter()
+                .map(|armed| Active {
+                    fault: armed.fault,
+                    armed_at: armed.armed_at,
+                    remaining: match armed.deadline {
+                        Some(_) => None,
+                        None => Some(armed.remaining),
+                    },
+                    remaining_ms: armed
+                        .deadline
+                        .map(|deadline| deadline.saturating_duration_since(now).as_millis() as u64),
+                })
+                .collect()
+        }
+
+        fn take_disarmed(&self) -> Vec<Fault> {
+            std::mem::take(&mut self.lock_state().disarmed)
+        }
+    }
+}
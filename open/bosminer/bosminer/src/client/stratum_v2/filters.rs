@@ -0,0 +1,173 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Accounting of solutions dropped by local filters before they are submitted to the pool. Every
+//! solution found by the backend is either submitted, dropped for exactly one reason or held
+//! back while the submission window is full:
+//!
+//! `found == passed + sum(dropped) + held`
+//!
+//! Names of the reasons are part of the status document, they are never renamed nor removed
+//! (see `golden/filter_reasons.json`).
+
+use crate::stats;
+
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Reason of dropping a solution locally
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    /// The connection is being drained
+    Draining,
+    /// Version bits are rolled outside the version rolling mask of the job
+    OutOfMaskVersion,
+    /// The job has been flushed by a new prevhash
+    FlushedJob,
+    /// The solution meets the local target but not the target requested by the pool
+    BelowPoolTarget,
+    /// Ntime is earlier than `min_ntime` of the job
+    NtimeRegression,
+    /// Ntime is beyond the rolling window of the job
+    NtimeOverrun,
+    /// The submission window has been full
+    WindowFull,
+    /// The job of the solution has not been generated by the client (checked first)
+    ForeignJob,
+}
+
+impl Reason {
+    pub const COUNT: usize = 8;
+
+    /// All reasons in the order they have been introduced
+    pub const ALL: [Self; Self::COUNT] = [
+        Self::Draining,
+        Self::OutOfMaskVersion,
+        Self::FlushedJob,
+        Self::BelowPoolTarget,
+        Self::NtimeRegression,
+        Self::NtimeOverrun,
+        Self::WindowFull,
+        Self::ForeignJob,
+    ];
+
+    /// Stable name of the reason as serialized in the status document
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Draining => "draining",
+            Self::OutOfMaskVersion => "out_of_mask_version",
+            Self::FlushedJob => "flushed_job",
+            Self::BelowPoolTarget => "below_pool_target",
+            Self::NtimeRegression => "ntime_regression",
+            Self::NtimeOverrun => "ntime_overrun",
+            Self::WindowFull => "window_full",
+            Self::ForeignJob => "foreign_job",
+        }
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Solutions dropped for a single reason (reported as an advisory event)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dropped {
+    pub reason: Reason,
+    /// Solutions dropped for the reason in total
+    pub total: usize,
+}
+
+impl fmt::Display for Dropped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({} in total)", self.reason, self.total)
+    }
+}
+
+/// Breakdown of solutions found by the backend as reported in the status document
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Status {
+    /// Solutions received from the backend
+    pub found: usize,
+    /// Solutions that have passed all filters and have been submitted
+    pub passed: usize,
+    /// Solutions dropped locally in total
+    pub dropped: usize,
+    /// Solutions dropped locally for each reason
+    pub reasons: BTreeMap<Reason, usize>,
+}
+
+/// Counters of the filters
+#[derive(Debug, Default)]
+pub struct Stats {
+    found: stats::CounterUsize,
+    passed: stats::CounterUsize,
+    dropped: [stats::CounterUsize; Reason::COUNT],
+}
+
+impl Stats {
+    pub fn found(&self) -> &stats::CounterUsize {
+        &self.found
+    }
+
+    pub fn passed(&self) -> &stats::CounterUsize {
+        &self.passed
+    }
+
+    pub fn dropped(&self, reason: Reason) -> &stats::CounterUsize {
+        &self.dropped[reason as usize]
+    }
+
+    /// Solutions dropped locally for any reason
+    pub fn total_dropped(&self) -> usize {
+        Reason::ALL
+            .iter()
+            .map(|reason| *self.dropped(*reason).take_snapshot())
+            .sum()
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            found: *self.found.take_snapshot(),
+            passed: *self.passed.take_snapshot(),
+            dropped: self.total_dropped(),
+            reasons: Reason::ALL
+                .iter()
+                .map(|reason| (*reason, *self.dropped(*reason).take_snapshot()))
+                .collect(),
+        }
+    }
+
+    /// Start counting from zero
+    pub fn reset(&self) {
+        self.found.reset();
+        self.passed.reset();
+        for counter in self.dropped.iter() {
+            counter.reset();
+        }
+    }
+}
@@ -0,0 +1,383 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Compact binary snapshot of the client state that is handed over to the next instance of the
+//! process (e.g. across a firmware upgrade). A session cannot be resumed at the protocol level,
+//! the snapshot only shortens the recovery: the successor starts from the remembered target and
+//! hashrate estimate and continues the statistics of its predecessor. Nothing that is scoped to a
+//! session (job IDs, sequence numbers, negotiated parameters) nor anything that carries
+//! credentials (the user) is included.
+//!
+//! All integers are little endian:
+//!
+//! ```text
+//! magic     [u8; 4]   "BSV2"
+//! version   u8        incremented only on incompatible changes
+//! length    u32       length of the payload
+//! payload   [u8]      fields in the order of `Snapshot::encode_payload`
+//! checksum  [u8; 4]   first 4 bytes of SHA256d of all preceding bytes
+//! ```
+//!
+//! New fields are only ever appended to the end of the payload without changing the version, the
+//! decoder ignores any payload that follows the fields it knows.
+
+use super::hashrate;
+use super::hourly;
+use super::status;
+
+use ii_bitcoin::HashTrait as _;
+
+use failure::Fail;
+
+use std::convert::TryInto;
+use std::time;
+
+const MAGIC: &[u8; 4] = b"BSV2";
+const VERSION: u8 = 1;
+const HEADER_LENGTH: usize = 4 + 1 + 4;
+const CHECKSUM_LENGTH: usize = 4;
+
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub enum Error {
+    #[fail(display = "the snapshot is truncated")]
+    Truncated,
+    #[fail(display = "the data is not a snapshot of the client state")]
+    BadMagic,
+    #[fail(display = "unsupported version {} of the snapshot", _0)]
+    UnsupportedVersion(u8),
+    #[fail(display = "checksum of the snapshot doesn't match")]
+    ChecksumMismatch,
+    #[fail(display = "malformed snapshot: {}", _0)]
+    Malformed(String),
+    #[fail(
+        display = "the snapshot is {}s old (at most {}s is accepted)",
+        age, max_age
+    )]
+    Expired { age: u64, max_age: u64 },
+    #[fail(
+        display = "the snapshot belongs to endpoint {} instead of {}",
+        found, expected
+    )]
+    EndpointMismatch { expected: String, found: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Totals of a meter of the client statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Meter {
+    pub solutions: u64,
+    /// Sum of difficulties of the solutions
+    pub shares: u64,
+}
+
+/// Statistics accumulated over the lifetime of the client
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Lifetime {
+    pub accepted: Meter,
+    pub rejected: Meter,
+    pub stale: Meter,
+    pub submitted: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Key of the pool endpoint the state belongs to (see `ConnectionDetails::endpoint_key`)
+    pub endpoint: String,
+    pub exported_at: time::SystemTime,
+    /// Difficulties of the last session, the startup policy is not carried over
+    pub targets: status::Targets,
+    /// Hashrate estimate of the last finished bucket
+    pub hashrate: Option<hashrate::Bucket>,
+    pub lifetime: Lifetime,
+    /// History of connection attempts
+    pub retries: status::Retries,
+    /// Non-empty hourly slots in chronological order
+    pub hourly_shares: Vec<hourly::Slot>,
+    /// Difficulty of the best share (zero when there is none)
+    pub best_share: u64,
+}
+
+fn checksum(data: &[u8]) -> [u8; CHECKSUM_LENGTH] {
+    let hash = ii_bitcoin::DHash::hash(data).into_inner();
+    let mut checksum = [0; CHECKSUM_LENGTH];
+    checksum.copy_from_slice(&hash[..CHECKSUM_LENGTH]);
+    checksum
+}
+
+fn millis_since_epoch(time: time::SystemTime) -> u64 {
+    time.duration_since(time::UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn time_from_millis(millis: u64) -> time::SystemTime {
+    time::UNIX_EPOCH + time::Duration::from_millis(millis)
+}
+
+#[derive(Default)]
+struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.u64(value.to_bits());
+    }
+
+    fn string(&mut self, value: &str) {
+        let bytes = &value.as_bytes()[..value.len().min(u16::max_value() as usize)];
+        self.u16(bytes.len() as u16);
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.data.len() < length {
+            return Err(Error::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(
+            self.bytes(2)?.try_into().expect("BUG: wrong length"),
+        ))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(
+            self.bytes(4)?.try_into().expect("BUG: wrong length"),
+        ))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(
+            self.bytes(8)?.try_into().expect("BUG: wrong length"),
+        ))
+    }
+
+    fn f64(&mut self) -> Result<f64> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        let value = self.u64()?;
+        value
+            .try_into()
+            .map_err(|_| Error::Malformed(format!("value {} is out of range", value)))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let length = self.u16()? as usize;
+        String::from_utf8(self.bytes(length)?.to_vec())
+            .map_err(|_| Error::Malformed("string is not valid UTF-8".to_string()))
+    }
+}
+
+impl Snapshot {
+    fn encode_meter(writer: &mut Writer, meter: &Meter) {
+        writer.u64(meter.solutions);
+        writer.u64(meter.shares);
+    }
+
+    fn decode_meter(reader: &mut Reader) -> Result<Meter> {
+        Ok(Meter {
+            solutions: reader.u64()?,
+            shares: reader.u64()?,
+        })
+    }
+
+    fn encode_payload(&self, writer: &mut Writer) {
+        writer.string(&self.endpoint);
+        writer.u64(millis_since_epoch(self.exported_at));
+        writer.u64(self.targets.local_difficulty as u64);
+        writer.u64(self.targets.pool_difficulty as u64);
+        match self.hashrate.as_ref() {
+            Some(bucket) => {
+                writer.u8(1);
+                writer.u64(bucket.shares);
+                writer.u64(bucket.regimes as u64);
+                writer.f64(bucket.raw);
+                writer.f64(bucket.smoothed);
+            }
+            None => writer.u8(0),
+        }
+        Self::encode_meter(writer, &self.lifetime.accepted);
+        Self::encode_meter(writer, &self.lifetime.rejected);
+        Self::encode_meter(writer, &self.lifetime.stale);
+        writer.u64(self.lifetime.submitted);
+        writer.u64(self.retries.connection as u64);
+        writer.u64(self.retries.channel_open as u64);
+        writer.u64(self.retries.handshake_reset as u64);
+        // The ring retains at most 24 slots
+        let slots = &self.hourly_shares[..self.hourly_shares.len().min(u8::max_value() as usize)];
+        writer.u8(slots.len() as u8);
+        for slot in slots {
+            writer.u64(millis_since_epoch(slot.time));
+            writer.u64(slot.accepted);
+            writer.u64(slot.accepted_difficulty);
+            writer.u64(slot.rejected);
+            writer.u64(slot.stale);
+            writer.u64(slot.reconnects);
+        }
+        writer.u64(self.best_share);
+    }
+
+    fn decode_payload(reader: &mut Reader) -> Result<Self> {
+        let endpoint = reader.string()?;
+        let exported_at = time_from_millis(reader.u64()?);
+        let targets = status::Targets {
+            local_difficulty: reader.usize()?,
+            pool_difficulty: reader.usize()?,
+            startup_policy: false,
+        };
+        let hashrate = match reader.u8()? {
+            0 => None,
+            1 => Some(hashrate::Bucket {
+                shares: reader.u64()?,
+                regimes: reader.usize()?,
+                raw: reader.f64()?,
+                smoothed: reader.f64()?,
+            }),
+            tag => Err(Error::Malformed(format!("invalid hashrate tag {}", tag)))?,
+        };
+        let lifetime = Lifetime {
+            accepted: Self::decode_meter(reader)?,
+            rejected: Self::decode_meter(reader)?,
+            stale: Self::decode_meter(reader)?,
+            submitted: reader.u64()?,
+        };
+        let retries = status::Retries {
+            connection: reader.usize()?,
+            channel_open: reader.usize()?,
+            handshake_reset: reader.usize()?,
+        };
+        let slot_count = reader.u8()?;
+        let mut hourly_shares = Vec::with_capacity(slot_count as usize);
+        for _ in 0..slot_count {
+            hourly_shares.push(hourly::Slot {
+                time: time_from_millis(reader.u64()?),
+                accepted: reader.u64()?,
+                accepted_difficulty: reader.u64()?,
+                rejected: reader.u64()?,
+                stale: reader.u64()?,
+                reconnects: reader.u64()?,
+            });
+        }
+        let best_share = reader.u64()?;
+        Ok(Self {
+            endpoint,
+            exported_at,
+            targets,
+            hashrate,
+            lifetime,
+            retries,
+            hourly_shares,
+            best_share,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Writer::default();
+        self.encode_payload(&mut payload);
+
+        let mut writer = Writer::default();
+        writer.data.extend_from_slice(MAGIC);
+        writer.u8(VERSION);
+        writer
+            .data
+            .extend_from_slice(&(payload.data.len() as u32).to_le_bytes());
+        writer.data.extend_from_slice(&payload.data);
+        let checksum = checksum(&writer.data);
+        writer.data.extend_from_slice(&checksum);
+        writer.data
+    }
+
+    /// Decode and validate the snapshot. Only a snapshot of the `endpoint` that has been exported
+    /// at most `max_age` before `now` is accepted.
+    pub fn decode(
+        data: &[u8],
+        endpoint: &str,
+        max_age: time::Duration,
+        now: time::SystemTime,
+    ) -> Result<Self> {
+        if data.len() < HEADER_LENGTH + CHECKSUM_LENGTH {
+            return Err(Error::Truncated);
+        }
+        let mut reader = Reader { data };
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let length = reader.u32()? as usize;
+        let payload = reader.bytes(length)?;
+        let expected_checksum = reader.bytes(CHECKSUM_LENGTH)?;
+        if checksum(&data[..HEADER_LENGTH + length]) != expected_checksum {
+            return Err(Error::ChecksumMismatch);
+        }
+
+        // Fields appended by later releases are ignored
+        let snapshot = Self::decode_payload(&mut Reader { data: payload })?;
+        if snapshot.endpoint != endpoint {
+            return Err(Error::EndpointMismatch {
+                expected: endpoint.to_string(),
+                found: snapshot.endpoint,
+            });
+        }
+        // A snapshot from the future is accepted, the clock may have been adjusted meanwhile
+        let age = now.duration_since(snapshot.exported_at).unwrap_or_default();
+        if age > max_age {
+            return Err(Error::Expired {
+                age: age.as_secs(),
+                max_age: max_age.as_secs(),
+            });
+        }
+        Ok(snapshot)
+    }
+}
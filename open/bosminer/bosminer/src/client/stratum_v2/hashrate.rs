@@ -0,0 +1,234 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Hashrate estimated from shares accepted by the pool in consecutive time buckets.
+//!
+//! The raw estimate of a bucket is the sum of difficulties of the accepted shares divided by the
+//! length of the bucket. When the target changes within the bucket, the raw sum is dominated by
+//! the few shares of the highest difficulty: a 4x vardiff step right after a burst of low
+//! difficulty shares makes the raw estimate swing even though the hashrate is steady.
+//!
+//! The smoothed estimate splits the bucket into regimes of constant difficulty delimited by the
+//! target changes. The number of shares found in a regime of length `t` at difficulty `D` by
+//! hashrate `h` (in difficulty 1 shares per second) is a Poisson sample with mean `h * t / D`.
+//! The regime alone estimates the hashrate as `n * D / t` with variance `h * D / t`. Combining
+//! the regimes with weights proportional to their precision `t / D` gives
+//!
+//! ```text
+//! h = sum(n) / sum(t / D)
+//! ```
+//!
+//! which is the maximum likelihood estimate over the whole bucket. Shares are counted relative to
+//! the difficulty of their regime (a share solved for a previous target that is accepted after
+//! the change counts as `D_share / D`). Accounting of a share is O(1), the exposure `sum(t / D)`
+//! is accumulated on target changes and the weighting is done when the bucket is finished.
+
+use serde::Serialize;
+
+use std::collections::VecDeque;
+use std::time;
+
+/// Number of hashes needed on average to find a share of difficulty 1
+const HASHES_PER_SHARE: f64 = 4_294_967_296.0;
+
+/// Estimates of a finished bucket in hashes per second
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Bucket {
+    /// Number of accepted shares
+    pub shares: u64,
+    /// Number of target regimes within the bucket
+    pub regimes: usize,
+    /// Sum of difficulties of the accepted shares over the length of the bucket
+    pub raw: f64,
+    /// Precision weighted combination of the target regimes
+    pub smoothed: f64,
+}
+
+#[derive(Debug)]
+pub struct Estimator {
+    length: time::Duration,
+    capacity: usize,
+    /// Beginning of the current bucket
+    started: Option<time::Instant>,
+    /// Difficulty of the current regime
+    difficulty: Option<f64>,
+    /// Beginning of the current regime within the current bucket
+    regime_started: Option<time::Instant>,
+    /// Accepted shares of the current bucket
+    shares: u64,
+    /// Shares of the current bucket relative to the difficulty of their regime
+    regime_shares: f64,
+    /// Sum of difficulties of the current bucket
+    difficulty_sum: f64,
+    /// Exposure `sum(t / D)` of the finished regimes of the current bucket
+    exposure: f64,
+    regimes: usize,
+    /// Finished buckets in chronological order
+    buckets: VecDeque<Bucket>,
+}
+
+impl Estimator {
+    pub const DEFAULT_LENGTH: time::Duration = time::Duration::from_secs(60);
+    /// Number of finished buckets retained by default
+    pub const DEFAULT_CAPACITY: usize = 15;
+
+    pub fn new(length: time::Duration, capacity: usize) -> Self {
+        assert!(length > time::Duration::from_secs(0));
+        Self {
+            length,
+            capacity,
+            started: None,
+            difficulty: None,
+            regime_started: None,
+            shares: 0,
+            regime_shares: 0.0,
+            difficulty_sum: 0.0,
+            exposure: 0.0,
+            regimes: 0,
+            buckets: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Exposure of the current regime up to `end`
+    fn regime_exposure(&self, end: time::Instant) -> f64 {
+        match (self.difficulty, self.regime_started) {
+            (Some(difficulty), Some(regime_started)) => {
+                end.saturating_duration_since(regime_started).as_secs_f64() / difficulty
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn push_bucket(&mut self, bucket: Bucket) {
+        if self.buckets.len() >= self.capacity {
+            self.buckets.pop_front();
+        }
+        self.buckets.push_back(bucket);
+    }
+
+    /// Finish the current bucket at `end`
+    fn finish_bucket(&mut self, end: time::Instant) {
+        let exposure = self.exposure + self.regime_exposure(end);
+        let smoothed = if exposure > 0.0 {
+            self.regime_shares / exposure * HASHES_PER_SHARE
+        } else {
+            0.0
+        };
+        self.push_bucket(Bucket {
+            shares: self.shares,
+            regimes: self.regimes,
+            raw: self.difficulty_sum / self.length.as_secs_f64() * HASHES_PER_SHARE,
+            smoothed,
+        });
+        self.shares = 0;
+        self.regime_shares = 0.0;
+        self.difficulty_sum = 0.0;
+        self.exposure = 0.0;
+        self.regimes = if self.difficulty.is_some() { 1 } else { 0 };
+    }
+
+    /// Finish all buckets that end before `now`
+    fn roll(&mut self, now: time::Instant) {
+        let started = match self.started {
+            Some(started) => started,
+            None => {
+                self.started = Some(now);
+                self.regime_started = Some(now);
+                return;
+            }
+        };
+        let elapsed = now.saturating_duration_since(started);
+        if elapsed < self.length {
+            return;
+        }
+        self.finish_bucket(started + self.length);
+        // Buckets without any share are finished at once, at most `capacity` of them matter
+        let finished = (elapsed.as_secs_f64() / self.length.as_secs_f64()) as u32;
+        for _ in 1..finished.min(self.capacity as u32 + 1) {
+            self.push_bucket(Bucket {
+                shares: 0,
+                regimes: self.regimes,
+                raw: 0.0,
+                smoothed: 0.0,
+            });
+        }
+        let next = started + self.length * finished;
+        self.started = Some(next);
+        self.regime_started = Some(next);
+    }
+
+    /// Record a change of the difficulty of shares at `now`
+    pub fn set_difficulty(&mut self, difficulty: f64, now: time::Instant) {
+        self.roll(now);
+        if self.difficulty == Some(difficulty) {
+            return;
+        }
+        match self.difficulty {
+            // Regime that hasn't lasted any time is just replaced
+            Some(_) if self.regime_started == Some(now) => {}
+            Some(_) => {
+                self.exposure += self.regime_exposure(now);
+                self.regime_started = Some(now);
+                self.regimes += 1;
+            }
+            None => self.regimes += 1,
+        }
+        self.difficulty = Some(difficulty);
+    }
+
+    /// Account a share of `difficulty` accepted at `now`. The difficulty of the current regime is
+    /// assumed when no change has been recorded yet.
+    pub fn account_share(&mut self, difficulty: f64, now: time::Instant) {
+        if self.difficulty.is_none() {
+            self.set_difficulty(difficulty, now);
+        } else {
+            self.roll(now);
+        }
+        let regime_difficulty = self.difficulty.expect("BUG: missing regime difficulty");
+        self.shares += 1;
+        self.regime_shares += difficulty / regime_difficulty;
+        self.difficulty_sum += difficulty;
+    }
+
+    /// Start with `bucket` estimated before (e.g. by the previous instance of the process) until
+    /// the first bucket is finished. Nothing is done when some bucket has been finished already.
+    pub fn seed(&mut self, bucket: Bucket) {
+        if self.buckets.is_empty() {
+            self.push_bucket(bucket);
+        }
+    }
+
+    /// Returns finished buckets in chronological order (finishing all buckets that end before
+    /// `now`)
+    pub fn buckets(&mut self, now: time::Instant) -> Vec<Bucket> {
+        if self.started.is_some() {
+            self.roll(now);
+        }
+        self.buckets.iter().cloned().collect()
+    }
+}
+
+impl Default for Estimator {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_LENGTH, Self::DEFAULT_CAPACITY)
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Health of the Stratum V2 client is tracked as a set of independent degradation reasons. Each
+//! feature raises its own reason and clears it once the condition disappears so that features
+//! don't override each other.
+
+use serde::Serialize;
+
+use std::collections::BTreeSet;
+use std::sync::Mutex as StdMutex;
+
+/// Conditions that make the client work in a degraded mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DegradedReason {
+    /// Pool requests a target that is outside of the configured range of difficulty
+    TargetClamped,
+    /// Share submissions stall while the pool keeps sending jobs
+    TransmitStalled,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    Healthy,
+    /// List of all active degradation reasons
+    Degraded(Vec<DegradedReason>),
+}
+
+impl Health {
+    #[inline]
+    pub fn is_healthy(&self) -> bool {
+        *self == Health::Healthy
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Monitor {
+    reasons: StdMutex<BTreeSet<DegradedReason>>,
+}
+
+impl Monitor {
+    fn lock_reasons(&self) -> std::sync::MutexGuard<BTreeSet<DegradedReason>> {
+        self.reasons
+            .lock()
+            .expect("BUG: cannot lock health reasons")
+    }
+
+    /// Returns true when the `reason` hasn't been active before
+    pub fn raise(&self, reason: DegradedReason) -> bool {
+        self.lock_reasons().insert(reason)
+    }
+
+    /// Returns true when the `reason` has been active before
+    pub fn clear(&self, reason: DegradedReason) -> bool {
+        self.lock_reasons().remove(&reason)
+    }
+
+    pub fn is_raised(&self, reason: DegradedReason) -> bool {
+        self.lock_reasons().contains(&reason)
+    }
+
+    pub fn health(&self) -> Health {
+        let reasons = self.lock_reasons();
+        if reasons.is_empty() {
+            Health::Healthy
+        } else {
+            Health::Degraded(reasons.iter().cloned().collect())
+        }
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Queue of solutions held back while the submission window is full. The queue is capped by the
+//! number of entries and by an estimate of the memory retained by the entries. Parts shared by
+//! several entries (the job) are accounted only once, they are tracked by their address for as
+//! long as any entry in the queue refers to them.
+
+use crate::work;
+
+use serde::Serialize;
+
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+
+/// Estimate of memory retained by a queued entry. The estimate has to be cheap, shared parts must
+/// not be traversed.
+pub trait Retained {
+    /// Memory retained by the entry itself excluding the shared part
+    fn retained_size(&self) -> usize {
+        mem::size_of_val(self)
+    }
+
+    /// Address and size of the part that may be shared with other entries
+    fn shared(&self) -> Option<(usize, usize)> {
+        None
+    }
+}
+
+impl Retained for work::Solution {
+    fn retained_size(&self) -> usize {
+        self.retained_size()
+    }
+
+    fn shared(&self) -> Option<(usize, usize)> {
+        Some(self.shared_job())
+    }
+}
+
+/// Number of entries evicted by each of the caps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Evictions {
+    pub count_cap: usize,
+    pub byte_cap: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Stats {
+    pub entries: usize,
+    /// Estimate of memory retained by the entries
+    pub bytes: usize,
+    pub evictions: Evictions,
+    pub high_water_entries: usize,
+    pub high_water_bytes: usize,
+}
+
+#[derive(Debug)]
+struct Shared {
+    /// Number of entries that refer to the shared part
+    refs: usize,
+    size: usize,
+}
+
+#[derive(Debug)]
+pub struct Queue<T> {
+    /// Entries along with their own retained size
+    entries: VecDeque<(T, usize)>,
+    shared: HashMap<usize, Shared>,
+    max_bytes: usize,
+    stats: Stats,
+}
+
+impl<T: Retained> Queue<T> {
+    /// Build an empty queue that continues the eviction counters and high-water marks of `stats`
+    pub fn new(max_bytes: usize, stats: Stats) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            shared: HashMap::new(),
+            max_bytes,
+            stats: Stats {
+                entries: 0,
+                bytes: 0,
+                ..stats
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Queue the entry and evict the oldest entries until both caps are met. The new entry itself
+    /// is never evicted. Returns the number of evicted entries.
+    pub fn push_back(&mut self, entry: T, max_entries: usize) -> usize {
+        let mut evicted = 0;
+        while !self.entries.is_empty() && self.entries.len() >= max_entries {
+            self.pop_front();
+            self.stats.evictions.count_cap += 1;
+            evicted += 1;
+        }
+
+        let size = entry.retained_size();
+        self.stats.bytes += size;
+        if let Some((address, shared_size)) = entry.shared() {
+            let shared = self.shared.entry(address).or_insert(Shared {
+                refs: 0,
+                size: shared_size,
+            });
+            if shared.refs == 0 {
+                self.stats.bytes += shared.size;
+            }
+            shared.refs += 1;
+        }
+        self.entries.push_back((entry, size));
+
+        while self.entries.len() > 1 && self.stats.bytes > self.max_bytes {
+            self.pop_front();
+            self.stats.evictions.byte_cap += 1;
+            evicted += 1;
+        }
+
+        self.stats.entries = self.entries.len();
+        self.stats.high_water_entries = self.stats.high_water_entries.max(self.stats.entries);
+        self.stats.high_water_bytes = self.stats.high_water_bytes.max(self.stats.bytes);
+        evicted
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        let (entry, size) = self.entries.pop_front()?;
+        self.stats.bytes -= size;
+        if let Some((address, _)) = entry.shared() {
+            let shared = self
+                .shared
+                .get_mut(&address)
+                .expect("BUG: missing shared part of held entry");
+            shared.refs -= 1;
+            if shared.refs == 0 {
+                self.stats.bytes -= shared.size;
+                self.shared.remove(&address);
+            }
+        }
+        self.stats.entries = self.entries.len();
+        Some(entry)
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Share acceptance accumulated per wall-clock hour for the last 24 hours. Slots are keyed by the
+//! hour since the Unix epoch, so hours during which the miner hasn't been running show up as
+//! empty slots. The ring rolls over lazily whenever something is accounted or a snapshot is
+//! taken, no timer is involved.
+
+use serde::Serialize;
+
+use std::collections::VecDeque;
+use std::sync::Mutex as StdMutex;
+use std::time;
+
+const SECS_PER_HOUR: u64 = 3600;
+
+/// Hour since the Unix epoch, time before the epoch is accounted to the first hour
+fn hour_of(time: time::SystemTime) -> u64 {
+    time.duration_since(time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() / SECS_PER_HOUR)
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Slot {
+    /// Beginning of the hour
+    pub time: time::SystemTime,
+    pub accepted: u64,
+    /// Sum of difficulties of accepted shares
+    pub accepted_difficulty: u64,
+    pub rejected: u64,
+    pub stale: u64,
+    /// Number of connection attempts that followed a previous one
+    pub reconnects: u64,
+}
+
+impl Slot {
+    fn new(hour: u64) -> Self {
+        Self {
+            time: time::UNIX_EPOCH + time::Duration::from_secs(hour * SECS_PER_HOUR),
+            accepted: 0,
+            accepted_difficulty: 0,
+            rejected: 0,
+            stale: 0,
+            reconnects: 0,
+        }
+    }
+
+    fn hour(&self) -> u64 {
+        hour_of(self.time)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Ring {
+    /// Non-empty slots in chronological order, at most `HOURS` of them
+    slots: StdMutex<VecDeque<Slot>>,
+}
+
+impl Ring {
+    /// Number of hours retained in the ring
+    pub const HOURS: u64 = 24;
+
+    /// Update the slot of the hour that contains `now`. Slots that are out of the window are
+    /// dropped and so is an update that is older than the window (time going backwards).
+    fn update<F>(&self, now: time::SystemTime, f: F)
+    where
+        F: FnOnce(&mut Slot),
+    {
+        let hour = hour_of(now);
+        let mut slots = self.slots.lock().expect("BUG: cannot lock hourly slots");
+        let newest_hour = slots.back().map_or(hour, |slot| slot.hour().max(hour));
+        Self::roll_over(&mut slots, newest_hour);
+        if hour + Self::HOURS <= newest_hour {
+            return;
+        }
+        let idx = match slots.iter().position(|slot| slot.hour() >= hour) {
+            Some(idx) if slots[idx].hour() == hour => idx,
+            Some(idx) => {
+                slots.insert(idx, Slot::new(hour));
+                idx
+            }
+            None => {
+                slots.push_back(Slot::new(hour));
+                slots.len() - 1
+            }
+        };
+        f(&mut slots[idx]);
+    }
+
+    /// Drop slots that are older than the window ending with `newest_hour`
+    fn roll_over(slots: &mut VecDeque<Slot>, newest_hour: u64) {
+        while slots
+            .front()
+            .map_or(false, |slot| slot.hour() + Self::HOURS <= newest_hour)
+        {
+            slots.pop_front();
+        }
+    }
+
+    pub fn account_accepted(&self, difficulty: usize, now: time::SystemTime) {
+        self.update(now, |slot| {
+            slot.accepted += 1;
+            slot.accepted_difficulty = slot.accepted_difficulty.saturating_add(difficulty as u64);
+        });
+    }
+
+    pub fn account_rejected(&self, now: time::SystemTime) {
+        self.update(now, |slot| slot.rejected += 1);
+    }
+
+    pub fn account_stale(&self, now: time::SystemTime) {
+        self.update(now, |slot| slot.stale += 1);
+    }
+
+    pub fn account_reconnect(&self, now: time::SystemTime) {
+        self.update(now, |slot| slot.reconnects += 1);
+    }
+
+    /// Merge `slots` (e.g. handed over by the previous instance of the process) into the ring.
+    /// Slots that are out of the window are dropped.
+    pub fn restore(&self, slots: &[Slot]) {
+        for restored in slots {
+            self.update(restored.time, |slot| {
+                slot.accepted += restored.accepted;
+                slot.accepted_difficulty = slot
+                    .accepted_difficulty
+                    .saturating_add(restored.accepted_difficulty);
+                slot.rejected += restored.rejected;
+                slot.stale += restored.stale;
+                slot.reconnects += restored.reconnects;
+            });
+        }
+    }
+
+    /// Returns all `HOURS` slots of the window that ends with the hour containing `now` in
+    /// chronological order. Hours without any activity are represented by empty slots.
+    pub fn snapshot(&self, now: time::SystemTime) -> Vec<Slot> {
+        let mut slots = self.slots.lock().expect("BUG: cannot lock hourly slots");
+        let current_hour = slots
+            .back()
+            .map_or(hour_of(now), |slot| slot.hour().max(hour_of(now)));
+        Self::roll_over(&mut slots, current_hour);
+        let first_hour = (current_hour + 1).saturating_sub(Self::HOURS);
+        let mut slots = slots.iter().peekable();
+        (first_hour..=current_hour)
+            .map(|hour| match slots.peek() {
+                Some(slot) if slot.hour() == hour => {
+                    slots.next().expect("BUG: missing slot").clone()
+                }
+                _ => Slot::new(hour),
+            })
+            .collect()
+    }
+
+    /// Forget all accumulated data. The ring survives new sessions and reconnects, it is meant
+    /// to be reset only together with the lifetime statistics of the client.
+    pub fn reset(&self) {
+        self.slots
+            .lock()
+            .expect("BUG: cannot lock hourly slots")
+            .clear();
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Timed reads and writes of the framed connection. A half-open TCP connection (e.g. the pool
+//! host has disappeared without closing the socket) reports neither data nor an error, a read or
+//! a write that stays pending for longer than the timeout fails with `io::ErrorKind::TimedOut`
+//! instead. The adapters are applied at the framing layer since the socket is non-blocking and
+//! `SO_RCVTIMEO`/`SO_SNDTIMEO` have no effect on it.
+//!
+//! The read deadline measures the time since the last received frame regardless of how many times
+//! the stream has been polled, a read that is abandoned and started again doesn't extend it.
+
+use futures::task::{Context, Poll};
+use ii_async_compat::prelude::*;
+use ii_async_compat::tokio;
+
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::time;
+
+/// Deadline of a pending operation that is armed when the operation becomes pending and disarmed
+/// once it completes
+struct Deadline {
+    timeout: Option<time::Duration>,
+    delay: Option<Pin<Box<tokio::time::Delay>>>,
+}
+
+impl Deadline {
+    fn new(timeout: Option<time::Duration>) -> Self {
+        Self {
+            timeout,
+            delay: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.delay = None;
+    }
+
+    /// Check the deadline of an operation that is pending, the deadline is armed by the first
+    /// check. Returns the error the operation should fail with once the deadline has expired.
+    fn expired(&mut self, cx: &mut Context<'_>, operation: &str) -> Option<io::Error> {
+        let timeout = self.timeout?;
+        let delay = self
+            .delay
+            .get_or_insert_with(|| Box::pin(tokio::time::delay_for(timeout)));
+        match delay.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                self.delay = None;
+                Some(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "{} has not completed within {}s",
+                        operation,
+                        timeout.as_secs()
+                    ),
+                ))
+            }
+            Poll::Pending => None,
+        }
+    }
+
+    /// Fail the `poll` of an operation that has been pending for longer than the timeout
+    fn check<T, E>(
+        &mut self,
+        poll: Poll<Result<T, E>>,
+        cx: &mut Context<'_>,
+        operation: &str,
+    ) -> Poll<Result<T, E>>
+    where
+        E: From<io::Error>,
+    {
+        match poll {
+            Poll::Pending => match self.expired(cx, operation) {
+                Some(e) => Poll::Ready(Err(e.into())),
+                None => Poll::Pending,
+            },
+            poll => {
+                self.reset();
+                poll
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Deadline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Deadline")
+            .field("timeout", &self.timeout)
+            .field("armed", &self.delay.is_some())
+            .finish()
+    }
+}
+
+/// Stream of frames whose read fails when no frame arrives within the timeout
+#[derive(Debug)]
+pub struct TimedStream<S> {
+    inner: S,
+    deadline: Deadline,
+}
+
+impl<S> TimedStream<S> {
+    /// Nothing is timed when `timeout` is not set
+    pub fn new(inner: S, timeout: Option<time::Duration>) -> Self {
+        Self {
+            inner,
+            deadline: Deadline::new(timeout),
+        }
+    }
+}
+
+impl<S, T, E> Stream for TimedStream<S>
+where
+    S: Stream<Item = Result<T, E>> + Unpin,
+    E: From<io::Error>,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        match this.inner.poll_next_unpin(cx) {
+            Poll::Ready(item) => {
+                this.deadline.reset();
+                Poll::Ready(item)
+            }
+            Poll::Pending => match this.deadline.expired(cx, "read") {
+                Some(e) => Poll::Ready(Some(Err(e.into()))),
+                None => Poll::Pending,
+            },
+        }
+    }
+}
+
+/// Sink of frames whose write (including waiting for the space in the socket) fails when it
+/// doesn't complete within the timeout
+#[derive(Debug)]
+pub struct TimedSink<S> {
+    inner: S,
+    deadline: Deadline,
+}
+
+impl<S> TimedSink<S> {
+    /// Nothing is timed when `timeout` is not set
+    pub fn new(inner: S, timeout: Option<time::Duration>) -> Self {
+        Self {
+            inner,
+            deadline: Deadline::new(timeout),
+        }
+    }
+}
+
+impl<S, T, E> Sink<T> for TimedSink<S>
+where
+    S: Sink<T, Error = E> + Unpin,
+    E: From<io::Error>,
+{
+    type Error = E;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+        let this = &mut *self;
+        let poll = this.inner.poll_ready_unpin(cx);
+        this.deadline.check(poll, cx, "write")
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), E> {
+        self.inner.start_send_unpin(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+        let this = &mut *self;
+        let poll = this.inner.poll_flush_unpin(cx);
+        this.deadline.check(poll, cx, "write")
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+        self.inner.poll_close_unpin(cx)
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Pools may announce byte-identical jobs under new job IDs. Such duplicates are not dispatched
+//! again, the new job ID is only recorded as an alias of the job that carries the same payload.
+
+use std::collections::HashMap;
+use std::sync::Mutex as StdMutex;
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Maps alias to the ID of the job that has been stored/dispatched
+    jobs: HashMap<u32, u32>,
+    /// Most recent ID announced by the pool for each aliased job
+    latest: HashMap<u32, u32>,
+}
+
+/// Bounded map of job ID aliases. It is valid only until the next prevhash change.
+#[derive(Debug)]
+pub struct Aliases {
+    capacity: usize,
+    inner: StdMutex<Inner>,
+}
+
+impl Aliases {
+    pub const DEFAULT_CAPACITY: usize = 64;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: StdMutex::new(Default::default()),
+        }
+    }
+
+    /// Record `alias` as a new ID of the job `job_id`. Returns false when the capacity has been
+    /// reached and the alias cannot be recorded.
+    pub fn insert(&self, alias: u32, job_id: u32) -> bool {
+        let mut inner = self.inner.lock().expect("BUG: cannot lock job aliases");
+        let job_id = inner.jobs.get(&job_id).cloned().unwrap_or(job_id);
+        if alias == job_id {
+            // The pool has announced the original ID again
+            inner.latest.remove(&job_id);
+            return true;
+        }
+        if inner.jobs.len() >= self.capacity && !inner.jobs.contains_key(&alias) {
+            return false;
+        }
+        inner.jobs.insert(alias, job_id);
+        inner.latest.insert(job_id, alias);
+        true
+    }
+
+    /// Returns ID of the stored job that is referenced by `job_id`
+    pub fn resolve(&self, job_id: u32) -> u32 {
+        let inner = self.inner.lock().expect("BUG: cannot lock job aliases");
+        inner.jobs.get(&job_id).cloned().unwrap_or(job_id)
+    }
+
+    /// Returns the ID most recently announced by the pool for the job referenced by `job_id`
+    pub fn latest(&self, job_id: u32) -> u32 {
+        let inner = self.inner.lock().expect("BUG: cannot lock job aliases");
+        let job_id = inner.jobs.get(&job_id).cloned().unwrap_or(job_id);
+        inner.latest.get(&job_id).cloned().unwrap_or(job_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner
+            .lock()
+            .expect("BUG: cannot lock job aliases")
+            .jobs
+            .len()
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().expect("BUG: cannot lock job aliases");
+        inner.jobs.clear();
+        inner.latest.clear();
+    }
+}
+
+impl Default for Aliases {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
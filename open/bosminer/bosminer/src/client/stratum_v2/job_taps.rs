@@ -0,0 +1,194 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Secondary consumers of dispatched jobs (e.g. job analytics). Every tap receives a clone of
+//! each job dispatched to the solver. Sending into a tap never blocks the dispatch: each tap
+//! buffers a bounded number of jobs and when the consumer lags behind, the oldest buffered job is
+//! dropped and counted. Dropping the tap releases its buffer immediately.
+
+use futures::task::{Context, Poll, Waker};
+use ii_async_compat::prelude::*;
+
+use serde::Serialize;
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Weak};
+use std::sync::{Mutex as StdMutex, MutexGuard as StdMutexGuard};
+
+#[derive(Debug)]
+struct Buffer<T> {
+    items: VecDeque<T>,
+    waker: Option<Waker>,
+    /// No more items are going to be sent
+    closed: bool,
+    dropped: usize,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    id: usize,
+    capacity: usize,
+    buffer: StdMutex<Buffer<T>>,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> StdMutexGuard<Buffer<T>> {
+        self.buffer.lock().expect("BUG: cannot lock job tap")
+    }
+}
+
+/// Diagnostics of a single tap
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Status {
+    pub id: usize,
+    /// Number of items waiting for the consumer
+    pub buffered: usize,
+    /// Number of items dropped because the consumer has lagged behind
+    pub dropped: usize,
+}
+
+/// Receiving end of a tap
+#[derive(Debug)]
+pub struct Tap<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Tap<T> {
+    pub fn id(&self) -> usize {
+        self.shared.id
+    }
+}
+
+impl<T> Stream for Tap<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut buffer = self.shared.lock();
+        match buffer.items.pop_front() {
+            Some(item) => Poll::Ready(Some(item)),
+            None if buffer.closed => Poll::Ready(None),
+            None => {
+                buffer.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Registry<T> {
+    next_id: usize,
+    taps: Vec<Weak<Shared<T>>>,
+}
+
+/// Sending ends of all open taps
+#[derive(Debug)]
+pub struct Taps<T> {
+    registry: StdMutex<Registry<T>>,
+}
+
+impl<T: Clone> Taps<T> {
+    fn lock(&self) -> StdMutexGuard<Registry<T>> {
+        self.registry.lock().expect("BUG: cannot lock job taps")
+    }
+
+    /// Open a new tap that buffers at most `capacity` items (at least one)
+    pub fn open(&self, capacity: usize) -> Tap<T> {
+        let mut registry = self.lock();
+        let id = registry.next_id;
+        registry.next_id += 1;
+        let shared = Arc::new(Shared {
+            id,
+            capacity: capacity.max(1),
+            buffer: StdMutex::new(Buffer {
+                items: VecDeque::new(),
+                waker: None,
+                closed: false,
+                dropped: 0,
+            }),
+        });
+        registry.taps.push(Arc::downgrade(&shared));
+        Tap { shared }
+    }
+
+    /// Send a clone of `item` into every open tap, the taps that have been dropped are removed
+    pub fn send(&self, item: &T) {
+        self.lock().taps.retain(|tap| match tap.upgrade() {
+            Some(shared) => {
+                let mut buffer = shared.lock();
+                if buffer.items.len() >= shared.capacity {
+                    buffer.items.pop_front();
+                    buffer.dropped += 1;
+                }
+                buffer.items.push_back(item.clone());
+                if let Some(waker) = buffer.waker.take() {
+                    waker.wake();
+                }
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// Returns diagnostics of all open taps
+    pub fn status(&self) -> Vec<Status> {
+        self.lock()
+            .taps
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|shared| {
+                let buffer = shared.lock();
+                Status {
+                    id: shared.id,
+                    buffered: buffer.items.len(),
+                    dropped: buffer.dropped,
+                }
+            })
+            .collect()
+    }
+}
+
+impl<T> Default for Taps<T> {
+    fn default() -> Self {
+        Self {
+            registry: StdMutex::new(Registry {
+                next_id: 0,
+                taps: Vec::new(),
+            }),
+        }
+    }
+}
+
+impl<T> Drop for Taps<T> {
+    /// End the streams of all open taps
+    fn drop(&mut self) {
+        let registry = self.registry.get_mut().expect("BUG: cannot lock job taps");
+        for shared in registry.taps.iter().filter_map(Weak::upgrade) {
+            let mut buffer = shared.lock();
+            buffer.closed = true;
+            if let Some(waker) = buffer.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Human readable strings received from the pool (error codes, maintenance notices etc.) are
+//! collected so that they can be presented to the operator. The strings are fully controlled by
+//! the remote side so they are always sanitized before they are stored or logged.
+
+use super::diagnostics;
+
+use serde::Serialize;
+
+use std::sync::Mutex as StdMutex;
+use std::time;
+
+/// Maximal number of characters retained from a single pool string
+pub const MAX_LENGTH: usize = 128;
+
+/// Strip all control characters and limit the length of a string received from the pool
+pub fn sanitize(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_LENGTH)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Message that carried the notice
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    SetupConnectionError,
+    OpenStandardMiningChannelError,
+    SubmitSharesError,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notice {
+    pub time: time::SystemTime,
+    pub source: Source,
+    /// Sanitized text of the notice
+    pub text: String,
+}
+
+/// Form of a notice in the diagnostics export
+#[derive(Serialize)]
+struct Exported<'a> {
+    id: u64,
+    #[serde(flatten)]
+    notice: &'a Notice,
+}
+
+/// Keeps the most recent distinct notices received from the pool
+#[derive(Debug)]
+pub struct Board {
+    /// Identical notices received within this window are reported only once
+    dedup_window: time::Duration,
+    notices: StdMutex<diagnostics::Ring<Notice>>,
+}
+
+impl Board {
+    pub const DEFAULT_CAPACITY: usize = 16;
+    pub const DEFAULT_DEDUP_WINDOW: time::Duration = time::Duration::from_secs(600);
+
+    pub fn new(capacity: usize, dedup_window: time::Duration) -> Self {
+        Self {
+            dedup_window,
+            notices: StdMutex::new(diagnostics::Ring::new(capacity)),
+        }
+    }
+
+    /// Store a notice received from the pool. Returns the sanitized notice only when it is a new
+    /// distinct one that the operator should be notified about.
+    pub fn post(&self, source: Source, text: &str, now: time::SystemTime) -> Option<Notice> {
+        let text = sanitize(text);
+        if text.is_empty() {
+            return None;
+        }
+
+        let mut notices = self.notices.lock().expect("BUG: cannot lock pool notices");
+        if let Some((idx, previous)) = notices
+            .iter()
+            .enumerate()
+            .find(|(_, notice)| notice.source == source && notice.text == text)
+        {
+            // Time going backwards is treated as a repetition within the window
+            let elapsed = now.duration_since(previous.time).unwrap_or_default();
+            if elapsed < self.dedup_window {
+                return None;
+            }
+            // The notice is repeated after a long time, move it to the most recent position
+            notices.remove(idx);
+        }
+        let notice = Notice {
+            time: now,
+            source,
+            text,
+        };
+        notices.push(notice.clone());
+        Some(notice)
+    }
+
+    /// Returns retained notices in chronological order
+    pub fn snapshot(&self) -> Vec<Notice> {
+        self.notices
+            .lock()
+            .expect("BUG: cannot lock pool notices")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn export(&self, request: &diagnostics::ExportDiagnostics) -> diagnostics::Chunk {
+        self.notices
+            .lock()
+            .expect("BUG: cannot lock pool notices")
+            .export(request, |id, notice| {
+                serde_json::to_string(&Exported { id, notice })
+                    .expect("BUG: cannot serialize pool notice")
+            })
+    }
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY, Self::DEFAULT_DEDUP_WINDOW)
+    }
+}
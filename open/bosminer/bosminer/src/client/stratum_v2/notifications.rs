@@ -0,0 +1,183 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Notifications about advisory events and changes of the client status delivered to subscribers
+//! (e.g. API bridges).
+//!
+//! Shutdown contract: when the client stops, a terminal `StatusChanged` notification (`Stopped`
+//! or `Failed`) is published after all events of the stopped session and the subscription ends
+//! right after it. Subscribing to a stopped client yields the terminal notification immediately.
+//! A subscriber that doesn't keep up receives `Lagged` with the number of skipped notifications
+//! and continues with the oldest retained one, the terminal notification is never skipped because
+//! it is the last one published.
+
+use super::events;
+
+use crate::sync;
+
+use ii_async_compat::tokio::sync::broadcast;
+
+use std::fmt;
+use std::sync::Mutex as StdMutex;
+
+#[derive(Debug, Clone)]
+pub enum Notification {
+    /// New advisory event has been recorded
+    Event(events::Record),
+    /// Status of the client has changed
+    StatusChanged(sync::Status),
+}
+
+impl Notification {
+    pub fn is_terminal(&self) -> bool {
+        match self {
+            Self::StatusChanged(sync::Status::Stopped)
+            | Self::StatusChanged(sync::Status::Failed) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The subscriber hasn't kept up and the specified number of notifications has been skipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lagged(pub u64);
+
+impl fmt::Display for Lagged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "subscriber lagged behind by {} notifications", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct Subscription {
+    receiver: broadcast::Receiver<Notification>,
+    /// Terminal notification of a client that has been stopped before subscribing
+    terminal: Option<Notification>,
+    ended: bool,
+}
+
+impl Subscription {
+    /// Receive the next notification. `None` is returned once the terminal notification has been
+    /// received (or the client has been dropped).
+    pub async fn recv(&mut self) -> Result<Option<Notification>, Lagged> {
+        if self.ended {
+            return Ok(None);
+        }
+        if let Some(terminal) = self.terminal.take() {
+            self.ended = true;
+            return Ok(Some(terminal));
+        }
+        match self.receiver.recv().await {
+            Ok(notification) => {
+                self.ended = notification.is_terminal();
+                Ok(Some(notification))
+            }
+            Err(broadcast::RecvError::Lagged(skipped)) => Err(Lagged(skipped)),
+            Err(broadcast::RecvError::Closed) => {
+                self.ended = true;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Sequencing layer over the broadcast channel. All notifications are published under a single
+/// lock so that the terminal notification cannot overtake any other notification and so that
+/// subscribing cannot race with stopping.
+#[derive(Debug)]
+pub struct Hub {
+    sender: broadcast::Sender<Notification>,
+    /// Terminal notification of the stopped client (`None` while the client is not stopped)
+    terminal: StdMutex<Option<Notification>>,
+}
+
+impl Hub {
+    pub const DEFAULT_CAPACITY: usize = 64;
+
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self {
+            sender,
+            terminal: StdMutex::new(None),
+        }
+    }
+
+    pub fn subscribe(&self) -> Subscription {
+        let terminal = self
+            .terminal
+            .lock()
+            .expect("BUG: cannot lock notifications");
+        Subscription {
+            receiver: self.sender.subscribe(),
+            terminal: terminal.clone(),
+            ended: false,
+        }
+    }
+
+    /// Publish the notification to all current subscribers. Notifications of a stopped client
+    /// are dropped.
+    pub fn publish(&self, notification: Notification) {
+        let terminal = self
+            .terminal
+            .lock()
+            .expect("BUG: cannot lock notifications");
+        if terminal.is_none() {
+            // Sending fails only when there are no subscribers
+            let _ = self.sender.send(notification);
+        }
+    }
+
+    /// The client has been started again, subsequent subscriptions are live
+    pub fn reopen(&self) {
+        self.terminal
+            .lock()
+            .expect("BUG: cannot lock notifications")
+            .take();
+    }
+
+    /// Publish the terminal notification when `stop` reports the final status of the client.
+    /// The notification is published atomically with the status transition.
+    pub fn terminate<F>(&self, stop: F) -> bool
+    where
+        F: FnOnce() -> Option<sync::Status>,
+    {
+        let mut terminal = self
+            .terminal
+            .lock()
+            .expect("BUG: cannot lock notifications");
+        match stop() {
+            Some(status) => {
+                let notification = Notification::StatusChanged(status);
+                let _ = self.sender.send(notification.clone());
+                terminal.replace(notification);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
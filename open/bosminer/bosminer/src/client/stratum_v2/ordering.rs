@@ -0,0 +1,315 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Optional verification of share submission ordering. Sequence numbers of submitted shares are
+//! expected to be strictly monotonic (wrapping) and the pool may acknowledge only sequence numbers
+//! that have actually been submitted.
+//!
+//! Pools that guarantee in-order acknowledgement can be verified more strictly with `Sequencer`.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex as StdMutex;
+use std::time;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// Share has been submitted with unexpected sequence number
+    NonMonotonicSubmit { expected: u32, actual: u32 },
+    /// The pool acknowledged sequence number that hasn't been submitted
+    UnknownAck(u32),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NonMonotonicSubmit { expected, actual } => write!(
+                f,
+                "share submitted with seq_num={}, expected seq_num={}",
+                actual, expected
+            ),
+            Self::UnknownAck(seq_num) => write!(
+                f,
+                "pool acknowledged seq_num={} that hasn't been submitted",
+                seq_num
+            ),
+        }
+    }
+}
+
+/// Range of sequence numbers submitted within the current session
+#[derive(Debug, Clone, Copy)]
+struct Range {
+    first: u32,
+    last: u32,
+}
+
+impl Range {
+    fn contains(&self, seq_num: u32) -> bool {
+        seq_num.wrapping_sub(self.first) <= self.last.wrapping_sub(self.first)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Verifier {
+    submitted: StdMutex<Option<Range>>,
+}
+
+impl Verifier {
+    /// Start a new session, nothing has been submitted yet
+    pub fn reset(&self) {
+        self.submitted
+            .lock()
+            .expect("BUG: cannot lock submitted range")
+            .take();
+    }
+
+    /// Account share submitted with `seq_num`
+    pub fn submitted(&self, seq_num: u32) -> Result<(), Violation> {
+        let mut submitted = self
+            .submitted
+            .lock()
+            .expect("BUG: cannot lock submitted range");
+        match submitted.as_mut() {
+            None => {
+                submitted.replace(Range {
+                    first: seq_num,
+                    last: seq_num,
+                });
+            }
+            Some(range) => {
+                let expected = range.last.wrapping_add(1);
+                if seq_num != expected {
+                    return Err(Violation::NonMonotonicSubmit {
+                        expected,
+                        actual: seq_num,
+                    });
+                }
+                range.last = seq_num;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that acknowledged `seq_num` has been submitted
+    pub fn acknowledged(&self, seq_num: u32) -> Result<(), Violation> {
+        let submitted = self
+            .submitted
+            .lock()
+            .expect("BUG: cannot lock submitted range");
+        match submitted.as_ref() {
+            Some(range) if range.contains(seq_num) => Ok(()),
+            _ => Err(Violation::UnknownAck(seq_num)),
+        }
+    }
+}
+
+/// Anomaly in the order in which the pool acknowledges shares
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anomaly {
+    /// The pool acknowledged a share that has already been acknowledged (duplicate or regressed
+    /// acknowledgement)
+    Regressed { expected: u32, received: u32 },
+    /// Shares skipped by the acknowledgement of `received` haven't been acknowledged within the
+    /// gap window
+    UnfilledGap {
+        first: u32,
+        last: u32,
+        received: u32,
+        age: time::Duration,
+    },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Regressed { expected, received } => write!(
+                f,
+                "pool acknowledged seq_num={} that has already been acknowledged, expected \
+                 seq_num={} or higher",
+                received, expected
+            ),
+            Self::UnfilledGap {
+                first,
+                last,
+                received,
+                age,
+            } => write!(
+                f,
+                "shares with seq_num={}..={} skipped by acknowledgement of seq_num={} haven't \
+                 been acknowledged for {}s",
+                first,
+                last,
+                received,
+                age.as_secs()
+            ),
+        }
+    }
+}
+
+/// Range of sequence numbers skipped by an acknowledgement. Sequence numbers are unwrapped so
+/// that they can be compared directly.
+#[derive(Debug, Clone, Copy)]
+struct Gap {
+    first: i64,
+    last: i64,
+    /// Acknowledged sequence number that created the gap
+    received: u32,
+    since: time::Instant,
+}
+
+impl Gap {
+    fn anomaly(&self, now: time::Instant) -> Anomaly {
+        Anomaly::UnfilledGap {
+            first: self.first as u32,
+            last: self.last as u32,
+            received: self.received,
+            age: now.saturating_duration_since(self.since),
+        }
+    }
+}
+
+/// Strict verification of acknowledgement ordering. Every acknowledgement is expected to follow
+/// the highest acknowledged sequence number. Acknowledgements that skip ahead leave gaps which
+/// must be filled within the gap window. The first acknowledgement of the session sets the
+/// baseline.
+///
+/// Outstanding gaps are kept in an ordered set of intervals with at most `MAX_GAPS` entries,
+/// gaps above the limit are reported as unfilled right away.
+#[derive(Debug, Default)]
+pub struct Sequencer {
+    /// Highest acknowledged (unwrapped) sequence number
+    highest: Option<i64>,
+    /// Gaps ordered by sequence numbers (and therefore also by the time of their creation)
+    gaps: VecDeque<Gap>,
+}
+
+impl Sequencer {
+    pub const MAX_GAPS: usize = 64;
+
+    /// Start a new session, nothing has been acknowledged yet
+    pub fn reset(&mut self) {
+        self.highest = None;
+        self.gaps.clear();
+    }
+
+    /// Number of sequence numbers that haven't been acknowledged yet below the highest one
+    pub fn outstanding(&self) -> u64 {
+        self.gaps
+            .iter()
+            .map(|gap| (gap.last - gap.first + 1) as u64)
+            .sum()
+    }
+
+    /// Map `seq_num` to the unwrapped sequence number nearest to `highest`
+    fn unwrap(highest: i64, seq_num: u32) -> i64 {
+        highest + i64::from(seq_num.wrapping_sub(highest as u32) as i32)
+    }
+
+    /// Remove `first..=last` from the gaps and return the number of sequence numbers that have
+    /// been removed
+    fn fill(&mut self, first: i64, last: i64) -> i64 {
+        let mut filled = 0;
+        let mut remaining = VecDeque::with_capacity(self.gaps.len() + 1);
+        for gap in self.gaps.drain(..) {
+            let low = cmp::max(gap.first, first);
+            let high = cmp::min(gap.last, last);
+            if low > high {
+                remaining.push_back(gap);
+                continue;
+            }
+            filled += high - low + 1;
+            if gap.first < low {
+                remaining.push_back(Gap {
+                    last: low - 1,
+                    ..gap
+                });
+            }
+            if high < gap.last {
+                remaining.push_back(Gap {
+                    first: high + 1,
+                    ..gap
+                });
+            }
+        }
+        self.gaps = remaining;
+        filled
+    }
+
+    /// Account acknowledgement of `count` consecutive shares ending with `last_seq_num` and
+    /// return detected anomalies
+    pub fn acknowledged(
+        &mut self,
+        last_seq_num: u32,
+        count: u32,
+        now: time::Instant,
+    ) -> Vec<Anomaly> {
+        let highest = match self.highest {
+            Some(highest) => highest,
+            None => {
+                self.highest = Some(i64::from(last_seq_num));
+                return vec![];
+            }
+        };
+        let last = Self::unwrap(highest, last_seq_num);
+        let first = last - i64::from(cmp::max(count, 1)) + 1;
+
+        let mut anomalies = vec![];
+        let behind = cmp::min(last, highest);
+        if first <= behind && self.fill(first, behind) < behind - first + 1 {
+            anomalies.push(Anomaly::Regressed {
+                expected: (highest + 1) as u32,
+                received: last_seq_num,
+            });
+        }
+        if last > highest {
+            if first > highest + 1 {
+                self.gaps.push_back(Gap {
+                    first: highest + 1,
+                    last: first - 1,
+                    received: last_seq_num,
+                    since: now,
+                });
+            }
+            self.highest = Some(last);
+        }
+        while self.gaps.len() > Self::MAX_GAPS {
+            let gap = self.gaps.pop_front().expect("BUG: missing gap");
+            anomalies.push(gap.anomaly(now));
+        }
+        anomalies
+    }
+
+    /// Remove gaps that haven't been filled within `window` and return them as anomalies
+    pub fn expire(&mut self, window: time::Duration, now: time::Instant) -> Vec<Anomaly> {
+        let mut anomalies = vec![];
+        while let Some(gap) = self.gaps.front() {
+            if now.saturating_duration_since(gap.since) < window {
+                break;
+            }
+            anomalies.push(gap.anomaly(now));
+            self.gaps.pop_front();
+        }
+        anomalies
+    }
+}
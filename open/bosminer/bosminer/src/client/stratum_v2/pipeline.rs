@@ -0,0 +1,167 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Snapshot of the job pipeline (prevhash, target, future jobs and the job dispatched last) that
+//! an operator attaches to a support ticket. The messages are processed by the event handler of
+//! the session, it publishes the parts of its state that are not available from the client.
+
+use super::context;
+use super::session;
+use super::status;
+
+use ii_stratum::v2::messages::SetNewPrevHash;
+
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::sync::Mutex as StdMutex;
+use std::time;
+
+/// Prevhash that the current jobs build on
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PrevHash {
+    /// Job activated by the prevhash
+    pub job_id: u32,
+    /// Hash in the byte order of the protocol (hex)
+    pub prev_hash: String,
+    pub min_ntime: u32,
+    pub nbits: u32,
+    /// Milliseconds since the prevhash has been received
+    pub age: u64,
+}
+
+/// Future job waiting for a prevhash
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FutureJob {
+    pub job_id: u32,
+    /// Milliseconds since the job has been received
+    pub age: u64,
+}
+
+/// Job dispatched to the backend most recently
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Job {
+    pub job_id: u32,
+    pub channel_id: u32,
+    pub version: u32,
+    pub version_mask: u32,
+    /// Merkle root in the byte order of the protocol (hex)
+    pub merkle_root: String,
+    pub ntime: u32,
+    /// Maximal ntime of solutions of the job
+    pub max_ntime: u32,
+    pub nbits: u32,
+    /// Difficulty used locally for solving the job
+    pub difficulty: usize,
+    /// Difficulty requested by the pool for the job
+    pub pool_difficulty: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Snapshot {
+    /// Identifiers of the current connection and session
+    #[serde(flatten)]
+    pub context: context::Context,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev_hash: Option<PrevHash>,
+    pub targets: status::Targets,
+    /// Difficulty of the target that is currently used for solving jobs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_difficulty: Option<usize>,
+    /// Future jobs received since the last prevhash ordered by job ID
+    pub future_jobs: Vec<FutureJob>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_job: Option<Job>,
+    /// Milliseconds since the last job or prevhash has been received from the pool
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_job_age: Option<u64>,
+    /// Parameters negotiated with the pool, missing until the connection has been set up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub negotiated: Option<status::Negotiated>,
+    /// Identifiers of the channel of the current session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<session::ChannelIdentity>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    prev_hash: Option<(SetNewPrevHash, time::Instant)>,
+    future_jobs: BTreeMap<u32, time::Instant>,
+}
+
+/// Parts of the state of the event handler published for the snapshot
+#[derive(Debug, Default)]
+pub struct Published {
+    inner: StdMutex<Inner>,
+}
+
+impl Published {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().expect("BUG: cannot lock job pipeline")
+    }
+
+    /// Forget the state of the previous session
+    pub fn clear(&self) {
+        *self.lock() = Default::default();
+    }
+
+    pub fn future_job(&self, job_id: u32, now: time::Instant) {
+        self.lock().future_jobs.insert(job_id, now);
+    }
+
+    /// Future job has been evicted before any prevhash has referenced it
+    pub fn evicted_future_job(&self, job_id: u32) {
+        self.lock().future_jobs.remove(&job_id);
+    }
+
+    /// New prevhash invalidates all future jobs received before it
+    pub fn prev_hash(&self, prevhash_msg: &SetNewPrevHash, now: time::Instant) {
+        let mut inner = self.lock();
+        inner.prev_hash = Some((prevhash_msg.clone(), now));
+        inner.future_jobs.clear();
+    }
+
+    /// Returns the published prevhash and future jobs with ages relative to `now`
+    pub fn snapshot(&self, now: time::Instant) -> (Option<PrevHash>, Vec<FutureJob>) {
+        let age = |received: time::Instant| now.saturating_duration_since(received).as_millis();
+        let inner = self.lock();
+        let prev_hash = inner
+            .prev_hash
+            .as_ref()
+            .map(|(prevhash_msg, received)| PrevHash {
+                job_id: prevhash_msg.job_id,
+                prev_hash: hex::encode(prevhash_msg.prev_hash.as_ref()),
+                min_ntime: prevhash_msg.min_ntime,
+                nbits: prevhash_msg.nbits,
+                age: age(*received) as u64,
+            });
+        let future_jobs = inner
+            .future_jobs
+            .iter()
+            .map(|(job_id, received)| FutureJob {
+                job_id: *job_id,
+                age: age(*received) as u64,
+            })
+            .collect();
+        (prev_hash, future_jobs)
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! On-demand probe of the latency of the pool connection that doesn't submit any work.
+//!
+//! Stratum V2 (as implemented by `ii_stratum`) has no request/response exchange suitable for an
+//! echo and the framed transport cannot send an empty write. The probe therefore measures the
+//! time to the next frame received from the pool. The caveat is that the result is only an upper
+//! bound of the round-trip time which depends on the traffic of the pool: a pool that sends
+//! nothing within the timeout yields a timeout even when the connection is healthy.
+
+use super::diagnostics;
+
+use futures::channel::oneshot;
+use serde::Serialize;
+
+use std::sync::Mutex as StdMutex;
+use std::time;
+
+/// Protocol exchange used for measuring the latency
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Method {
+    /// Time to the next frame received from the pool
+    NextFrame,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Probe {
+    pub method: Method,
+    /// Time when the probe has started
+    pub time: time::SystemTime,
+    /// Measured latency in milliseconds, missing when the probe has timed out
+    pub rtt_ms: Option<u64>,
+}
+
+/// Form of a probe in the diagnostics export
+#[derive(Serialize)]
+struct Exported<'a> {
+    id: u64,
+    #[serde(flatten)]
+    probe: &'a Probe,
+}
+
+#[derive(Debug)]
+struct State {
+    connected: bool,
+    /// Probes waiting for the next frame
+    pending: Vec<oneshot::Sender<time::Instant>>,
+    history: diagnostics::Ring<Probe>,
+}
+
+#[derive(Debug)]
+pub struct Prober {
+    state: StdMutex<State>,
+}
+
+impl Prober {
+    /// Number of the most recent probes retained for trending
+    pub const HISTORY_CAPACITY: usize = 16;
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("BUG: cannot lock latency prober")
+    }
+
+    /// The connection with the pool has been established
+    pub fn connect(&self) {
+        self.lock().connected = true;
+    }
+
+    /// The connection has been closed, all pending probes are cancelled
+    pub fn disconnect(&self) {
+        let mut state = self.lock();
+        state.connected = false;
+        state.pending.clear();
+    }
+
+    /// Start a probe that is resolved with the time of the next received frame. Returns `None`
+    /// when there is no connection.
+    pub fn start(&self) -> Option<oneshot::Receiver<time::Instant>> {
+        let mut state = self.lock();
+        if !state.connected {
+            return None;
+        }
+        let (sender, receiver) = oneshot::channel();
+        state.pending.push(sender);
+        Some(receiver)
+    }
+
+    /// Resolve all pending probes with a frame received at `now`
+    pub fn frame_received(&self, now: time::Instant) {
+        let mut state = self.lock();
+        for sender in state.pending.drain(..) {
+            // The probe may have timed out in the meantime
+            let _ = sender.send(now);
+        }
+    }
+
+    pub fn record(&self, probe: Probe) {
+        self.lock().history.push(probe);
+    }
+
+    /// Returns recent probes in chronological order
+    pub fn history(&self) -> Vec<Probe> {
+        self.lock().history.iter().cloned().collect()
+    }
+
+    pub fn export(&self, request: &diagnostics::ExportDiagnostics) -> diagnostics::Chunk {
+        self.lock().history.export(request, |id, probe| {
+            serde_json::to_string(&Exported { id, probe })
+                .expect("BUG: cannot serialize latency probe")
+        })
+    }
+}
+
+impl Default for Prober {
+    fn default() -> Self {
+        Self {
+            state: StdMutex::new(State {
+                connected: false,
+                pending: Vec::new(),
+                history: diagnostics::Ring::new(Self::HISTORY_CAPACITY),
+            }),
+        }
+    }
+}
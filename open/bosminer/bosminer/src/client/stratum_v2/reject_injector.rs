@@ -0,0 +1,77 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Test hook that acknowledges submitted shares in place of the pool. Shares are not sent to the
+//! pool at all, the client processes synthesized `SubmitSharesSuccess` and `SubmitSharesError`
+//! messages instead, so that rejects can be injected deterministically according to a pattern.
+//! It is available only with the `reject-injection` feature.
+
+/// Selection of submitted shares that are rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// Reject every n-th share (the n-th, 2n-th, ... share)
+    EveryNth(usize),
+    /// Reject shares marked with `true`, the sequence repeats
+    Sequence(Vec<bool>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Injector {
+    pattern: Pattern,
+    /// Error code of the synthesized `SubmitSharesError`
+    code: String,
+    /// Number of shares acknowledged so far
+    submitted: usize,
+}
+
+impl Injector {
+    pub const DEFAULT_CODE: &'static str = "injected-reject";
+
+    pub fn new(pattern: Pattern) -> Self {
+        Self::with_code(pattern, Self::DEFAULT_CODE)
+    }
+
+    /// The code has to fit into `Str0_32`
+    pub fn with_code(pattern: Pattern, code: &str) -> Self {
+        assert!(code.len() <= 32, "BUG: injected error code is too long");
+        Self {
+            pattern,
+            code: code.to_string(),
+            submitted: 0,
+        }
+    }
+
+    /// Returns the error code when the next submitted share is to be rejected
+    pub fn next_share(&mut self) -> Option<&str> {
+        let index = self.submitted;
+        self.submitted += 1;
+        let reject = match &self.pattern {
+            Pattern::EveryNth(n) => *n != 0 && (index + 1) % n == 0,
+            Pattern::Sequence(sequence) => !sequence.is_empty() && sequence[index % sequence.len()],
+        };
+        if reject {
+            Some(self.code.as_str())
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Detection of shares that are consecutively rejected for the same reason. A long streak of
+//! identical rejects (e.g. `invalid-user` or `invalid-version`) is not bad luck but a systemic
+//! problem that won't go away by itself, it is escalated once per streak. The streak is broken by
+//! an accepted share or by a reject with a different reason.
+
+use super::notices;
+
+use serde::Serialize;
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Streak {
+    /// Error code of `SubmitSharesError`, it is always sanitized (see `notices::sanitize`)
+    pub reason: String,
+    /// Number of consecutive rejects with the reason
+    pub count: usize,
+}
+
+impl fmt::Display for Streak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} consecutive shares rejected with '{}'",
+            self.count, self.reason
+        )
+    }
+}
+
+/// State of the current streak within a single session
+#[derive(Debug, Default)]
+pub struct Detector {
+    reason: Option<String>,
+    count: usize,
+    /// The current streak has been escalated already
+    escalated: bool,
+}
+
+impl Detector {
+    /// Account an accepted share, it ends the streak
+    pub fn accepted(&mut self) {
+        self.reason = None;
+        self.count = 0;
+        self.escalated = false;
+    }
+
+    /// Account a share rejected with `reason`. Returns the streak when it has just reached
+    /// `threshold`, a streak is escalated only once. The reason comes from the pool and it is
+    /// sanitized before it is stored.
+    pub fn rejected(&mut self, reason: &str, threshold: usize) -> Option<Streak> {
+        let reason = notices::sanitize(reason);
+        if self.reason.as_ref() != Some(&reason) {
+            self.accepted();
+            self.reason = Some(reason.clone());
+        }
+        self.count += 1;
+        if self.escalated || self.count < threshold {
+            return None;
+        }
+        self.escalated = true;
+        Some(Streak {
+            reason,
+            count: self.count,
+        })
+    }
+}
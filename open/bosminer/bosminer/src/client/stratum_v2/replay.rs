@@ -0,0 +1,199 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Captured Stratum V2 sessions can be replayed against the client to reproduce field reports
+//! deterministically.
+//!
+//! The capture is a text document with one frame per line. Each line contains the offset of the
+//! frame in milliseconds relative to the start of the session and hex encoded bytes of the frame
+//! as they appeared on the (unencrypted) wire. Empty lines and lines starting with `#` are ignored:
+//!
+//! ```text
+//! # server side of a session
+//! 0 0000010600000200000000
+//! 12 00801104...
+//! ```
+
+use super::{StratumClient, StratumConnectionHandler, StratumEventHandler};
+use crate::error;
+
+use ii_async_compat::bytes::BytesMut;
+use ii_async_compat::prelude::*;
+use ii_async_compat::tokio;
+use ii_async_compat::tokio_util::codec::{Decoder, Encoder};
+
+use futures::lock::Mutex;
+use futures::task::{Context, Poll};
+
+use ii_stratum::v2::{Codec, Frame};
+
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Time since the start of the session
+    pub offset: time::Duration,
+    /// Serialized frame
+    pub bytes: Vec<u8>,
+}
+
+impl Record {
+    pub fn into_frame(self) -> error::Result<Frame> {
+        let mut bytes = BytesMut::from(&self.bytes[..]);
+        Codec::default()
+            .decode(&mut bytes)?
+            .ok_or_else(|| "Incomplete frame in session capture".into())
+    }
+}
+
+/// Sequence of frames received during a session
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Capture {
+    pub records: Vec<Record>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn push(&mut self, offset: time::Duration, frame: Frame) -> error::Result<()> {
+        let mut bytes = BytesMut::new();
+        Codec::default().encode(frame, &mut bytes)?;
+        self.records.push(Record {
+            offset,
+            bytes: bytes.to_vec(),
+        });
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> error::Result<Self> {
+        let mut records = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid_line = || format!("Invalid session capture at line {}", idx + 1);
+            let mut fields = line.split_whitespace();
+            let offset = fields
+                .next()
+                .and_then(|offset| offset.parse().ok())
+                .map(time::Duration::from_millis)
+                .ok_or_else(invalid_line)?;
+            let bytes = fields
+                .next()
+                .and_then(|bytes| hex::decode(bytes).ok())
+                .ok_or_else(invalid_line)?;
+            if fields.next().is_some() {
+                Err(invalid_line())?;
+            }
+            records.push(Record { offset, bytes });
+        }
+        Ok(Self { records })
+    }
+}
+
+impl fmt::Display for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for record in &self.records {
+            writeln!(
+                f,
+                "{} {}",
+                record.offset.as_millis(),
+                hex::encode(&record.bytes)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Sink that collects all frames sent by the client during the replay
+#[derive(Debug, Default)]
+pub(super) struct FrameCollector {
+    pub(super) frames: Vec<Frame>,
+}
+
+impl Sink<Frame> for FrameCollector {
+    type Error = ii_stratum::error::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, frame: Frame) -> Result<(), Self::Error> {
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Feed captured server side of a session into the `client` through the same handlers that
+/// process a live connection. When `realtime` is set, the frames are delivered with their
+/// original timing. Returns all frames that the client sent during the replay.
+pub async fn replay_session(
+    client: Arc<StratumClient>,
+    capture: &Capture,
+    realtime: bool,
+) -> error::Result<Vec<Frame>> {
+    let start = tokio::time::Instant::now();
+    let records = capture.records.clone();
+    let mut connection_rx = stream::iter(records)
+        .then(move |record| async move {
+            if realtime {
+                tokio::time::delay_until(start + record.offset).await;
+            }
+            record
+                .into_frame()
+                .map_err(|e| ii_stratum::error::ErrorKind::General(e.to_string()).into())
+        })
+        .boxed();
+    let connection_tx = Arc::new(Mutex::new(FrameCollector::default()));
+
+    let context = client.new_connection_context();
+    client.refresh_user(context)?;
+    let (session, context, buffered_frames, first_job) =
+        StratumConnectionHandler::new(client.clone(), context)
+            .init_mining_session(&mut connection_rx, connection_tx.clone())
+            .await?;
+    let mut event_handler = StratumEventHandler::new(client.clone(), session, context);
+    event_handler.first_job = Some(first_job);
+    for frame in buffered_frames {
+        client.handle_frame(frame, &mut event_handler).await?;
+    }
+    while let Some(frame) = connection_rx.next().await {
+        client.handle_frame(frame?, &mut event_handler).await?;
+    }
+
+    let frames = std::mem::replace(&mut connection_tx.lock().await.frames, Vec::new());
+    Ok(frames)
+}
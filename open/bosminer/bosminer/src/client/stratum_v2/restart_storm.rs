@@ -0,0 +1,242 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Detection of rolling restarts of the pool. Each frontend of the pool closes its connections
+//! and a client that reconnects right away lands on the same frontend that is still restarting,
+//! which produces a burst of very short sessions. Once enough short sessions end within the window
+//! the client enters the "pool restarting" mode (storm):
+//! - the connection is retried at a fixed interval instead of right away, the host is resolved
+//!   again by every attempt so that drained frontends are not used
+//! - the scheduler doesn't fail over to another pool
+//! - failures of the individual cycles are not reported as events, a single summary is reported
+//!   when the storm ends
+//!
+//! The storm ends when a session outlasts the short session threshold (recovery) or when it lasts
+//! for longer than the bound. In the latter case the failures are handled as usual until the pool
+//! recovers, a new storm is not started in the meantime.
+
+use serde::Serialize;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time;
+
+/// Thresholds of the detection resolved from the configuration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    /// A session that ends within this time after the connection attempt is short
+    pub short_session: time::Duration,
+    /// Number of short sessions within `window` that start the storm
+    pub sessions: usize,
+    pub window: time::Duration,
+    /// Interval of connection attempts during the storm
+    pub retry_interval: time::Duration,
+    /// Maximal duration of the storm
+    pub max_duration: time::Duration,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// A session has outlasted the short session threshold
+    Recovered,
+    /// The storm has lasted for longer than the bound
+    Exceeded,
+}
+
+/// Summary of a finished storm reported as a single advisory event
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    /// Short sessions counted since the storm has started (including those that started it)
+    pub cycles: usize,
+    pub duration: time::Duration,
+    pub outcome: Outcome,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = match self.outcome {
+            Outcome::Recovered => "pool has recovered",
+            Outcome::Exceeded => "bound exceeded, falling back to normal failure handling",
+        };
+        write!(
+            f,
+            "{} short sessions within {}s, {}",
+            self.cycles,
+            self.duration.as_secs(),
+            outcome
+        )
+    }
+}
+
+/// Storm in progress as reported in the status document
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Status {
+    pub cycles: usize,
+    /// Seconds since the storm has started
+    pub duration: u64,
+    /// Seconds until normal failure handling applies again
+    pub expires_in: u64,
+}
+
+/// Result of accounting a failed session
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// The failure is handled as usual
+    Normal,
+    /// The storm has just started with `cycles` short sessions
+    Started { cycles: usize },
+    /// The storm goes on
+    Continuing,
+    /// The storm has just ended
+    Ended(Summary),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Storm {
+    since: time::Instant,
+    cycles: usize,
+}
+
+impl Storm {
+    fn summary(&self, now: time::Instant, outcome: Outcome) -> Summary {
+        Summary {
+            cycles: self.cycles,
+            duration: now.saturating_duration_since(self.since),
+            outcome,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Detector {
+    /// Start of the current connection attempt
+    attempt: Option<time::Instant>,
+    /// End of the recent short sessions within the window
+    short_sessions: VecDeque<time::Instant>,
+    storm: Option<Storm>,
+    /// The last storm has exceeded the bound and the pool hasn't recovered since
+    exceeded: bool,
+}
+
+impl Detector {
+    pub fn attempt_started(&mut self, now: time::Instant) {
+        self.attempt = Some(now);
+    }
+
+    /// Remaining time of the current session until it outlasts the short session threshold, it is
+    /// `None` when there is nothing to recover from
+    pub fn recovery_delay(
+        &self,
+        now: time::Instant,
+        thresholds: &Thresholds,
+    ) -> Option<time::Duration> {
+        if self.storm.is_none() && !self.exceeded {
+            return None;
+        }
+        self.attempt.map(|attempt| {
+            thresholds
+                .short_session
+                .checked_sub(now.saturating_duration_since(attempt))
+                .unwrap_or_default()
+        })
+    }
+
+    /// The current session has outlasted the short session threshold, returns the summary of the
+    /// storm that has ended by that
+    pub fn session_survived(&mut self, now: time::Instant) -> Option<Summary> {
+        self.short_sessions.clear();
+        self.exceeded = false;
+        self.storm
+            .take()
+            .map(|storm| storm.summary(now, Outcome::Recovered))
+    }
+
+    /// Account the failure of the session started by the last connection attempt
+    pub fn session_failed(&mut self, now: time::Instant, thresholds: &Thresholds) -> Verdict {
+        let attempt = match self.attempt.take() {
+            Some(attempt) => attempt,
+            None => return Verdict::Normal,
+        };
+        if now.saturating_duration_since(attempt) >= thresholds.short_session {
+            return match self.session_survived(now) {
+                Some(summary) => Verdict::Ended(summary),
+                None => Verdict::Normal,
+            };
+        }
+        self.short_sessions.push_back(now);
+        while let Some(end) = self.short_sessions.front() {
+            if now.saturating_duration_since(*end) < thresholds.window {
+                break;
+            }
+            self.short_sessions.pop_front();
+        }
+        if let Some(summary) = self.expire_at(now, thresholds) {
+            return Verdict::Ended(summary);
+        }
+        match self.storm.as_mut() {
+            Some(storm) => {
+                storm.cycles += 1;
+                Verdict::Continuing
+            }
+            None if !self.exceeded && self.short_sessions.len() >= thresholds.sessions => {
+                let cycles = self.short_sessions.len();
+                self.storm = Some(Storm { since: now, cycles });
+                Verdict::Started { cycles }
+            }
+            None => Verdict::Normal,
+        }
+    }
+
+    /// End the storm that has lasted for longer than the bound
+    pub fn expire_at(&mut self, now: time::Instant, thresholds: &Thresholds) -> Option<Summary> {
+        match self.storm {
+            Some(storm)
+                if now.saturating_duration_since(storm.since) >= thresholds.max_duration =>
+            {
+                self.storm = None;
+                self.exceeded = true;
+                Some(storm.summary(now, Outcome::Exceeded))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_storm(&self) -> bool {
+        self.storm.is_some()
+    }
+
+    pub fn status(&self, now: time::Instant, thresholds: &Thresholds) -> Option<Status> {
+        self.storm.map(|storm| {
+            let duration = now.saturating_duration_since(storm.since);
+            Status {
+                cycles: storm.cycles,
+                duration: duration.as_secs(),
+                expires_in: thresholds
+                    .max_duration
+                    .checked_sub(duration)
+                    .unwrap_or_default()
+                    .as_secs(),
+            }
+        })
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! State derived from the handshake with the pool (`SetupConnectionSuccess` and opening of the
+//! channel). The same endpoint may negotiate different parameters after a reconnect (e.g. during
+//! a rolling deploy of the pool), so the state is never patched: the connection handler builds
+//! a new one on every successful handshake and it replaces the previous one as a whole. Lifetime
+//! state (statistics, histories, configuration) stays on the client.
+
+use super::status;
+use super::VERSION_MASK;
+
+use serde::Serialize;
+
+use std::time;
+
+/// Recipient of a message received from the pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Addressee {
+    /// The channel of the session
+    Channel,
+    /// The group channel the channel of the session belongs to, the message applies to all
+    /// channels of the group
+    Group,
+}
+
+/// Identifiers assigned by the pool when the channel has been opened. The connection handler is
+/// the only one that builds the identity (from `OpenStandardMiningChannelSuccess`), all others
+/// read it from the state of the session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChannelIdentity {
+    /// Channel opened for the session, shares of all jobs are submitted to it
+    pub channel_id: u32,
+    /// Group channel the channel belongs to. It is `None` when the pool has assigned the ID of
+    /// the channel itself as its group.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<u32>,
+    /// Time when the channel has been opened
+    pub assigned_at: time::SystemTime,
+}
+
+impl ChannelIdentity {
+    pub fn new(channel_id: u32, group_channel_id: u32, assigned_at: time::SystemTime) -> Self {
+        Self {
+            channel_id,
+            group_id: Some(group_channel_id).filter(|group_id| *group_id != channel_id),
+            assigned_at,
+        }
+    }
+
+    /// Resolve the recipient of a message addressed to `channel_id`, `None` is returned for a
+    /// foreign channel
+    pub fn addressee(&self, channel_id: u32) -> Option<Addressee> {
+        if channel_id == self.channel_id {
+            Some(Addressee::Channel)
+        } else if Some(channel_id) == self.group_id {
+            Some(Addressee::Group)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct State {
+    /// Parameters of `SetupConnectionSuccess`
+    pub negotiated: status::Negotiated,
+    /// Identifiers of the channel opened for the session
+    pub channel: ChannelIdentity,
+    /// Extranonce prefix assigned to the channel by the pool
+    pub extranonce_prefix: Vec<u8>,
+    /// Target requested by the pool when the channel has been opened
+    pub init_target: ii_bitcoin::Target,
+    /// The pool has provided a usable target when the channel has been opened, `init_target`
+    /// is the difficulty 1 fallback otherwise
+    pub init_target_provided: bool,
+    /// Version rolling mask exposed to the backend in all jobs of the session, it is resolved
+    /// from the configuration and the negotiated flags
+    /// TODO: the mask is fixed for the whole session because Stratum V2 (and `ii_stratum`) has no
+    ///  message for changing it mid-session, version rolling is negotiated only by the flags of
+    ///  `SetupConnection`. Replacing the mask and invalidating jobs built with the old one belongs
+    ///  to the event handler once such a message exists.
+    pub version_mask: u32,
+}
+
+impl State {
+    /// Extranonce prefix of the channel, `None` when the pool has not assigned any
+    pub fn extranonce_prefix(&self) -> Option<ExtranoncePrefix> {
+        if self.extranonce_prefix.is_empty() {
+            return None;
+        }
+        Some(ExtranoncePrefix {
+            channel_id: self.channel.channel_id,
+            size: self.extranonce_prefix.len(),
+            prefix: self.extranonce_prefix.clone(),
+        })
+    }
+}
+
+impl Default for State {
+    /// Session with a pool that has negotiated no flags, it is used when the event handler is
+    /// driven without a handshake
+    fn default() -> Self {
+        Self {
+            negotiated: Default::default(),
+            channel: ChannelIdentity::new(0, 0, time::SystemTime::now()),
+            extranonce_prefix: Vec::new(),
+            init_target: Default::default(),
+            init_target_provided: true,
+            version_mask: VERSION_MASK,
+        }
+    }
+}
+
+/// Extranonce prefix granted to a channel when it has been opened, external tools use it to
+/// reconstruct the coinbase layout of submitted shares
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExtranoncePrefix {
+    pub channel_id: u32,
+    /// Size of the prefix in bytes
+    pub size: usize,
+    pub prefix: Vec<u8>,
+}
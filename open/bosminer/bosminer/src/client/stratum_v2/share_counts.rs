@@ -0,0 +1,118 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Counts of share outcomes in two tiers, the current session and the whole lifetime of the
+//! client. The session counts are reset whenever a new connection is established, so comparing
+//! the tiers tells whether a problem is confined to the current session or whether it persists
+//! across reconnects.
+//!
+//! Unlike the client statistics (see `accounting`), the counts are updated right when the
+//! acknowledgement is processed.
+
+use super::Outcome;
+
+use serde::Serialize;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counts {
+    pub accepted: u64,
+    pub rejected: u64,
+    pub stale: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+    stale: AtomicU64,
+}
+
+impl Counters {
+    fn counter(&self, outcome: Outcome) -> &AtomicU64 {
+        match outcome {
+            Outcome::Accepted => &self.accepted,
+            Outcome::Rejected => &self.rejected,
+            Outcome::Stale => &self.stale,
+        }
+    }
+
+    fn counts(&self) -> Counts {
+        Counts {
+            accepted: self.accepted.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            stale: self.stale.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        for outcome in &[Outcome::Accepted, Outcome::Rejected, Outcome::Stale] {
+            self.counter(*outcome).store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Status {
+    /// Shares of the current connection
+    pub session: Counts,
+    /// Shares since the client has been created
+    pub cumulative: Counts,
+}
+
+#[derive(Debug, Default)]
+pub struct Tiers {
+    session: Counters,
+    cumulative: Counters,
+}
+
+impl Tiers {
+    pub fn account(&self, outcome: Outcome) {
+        self.session
+            .counter(outcome)
+            .fetch_add(1, Ordering::Relaxed);
+        self.cumulative
+            .counter(outcome)
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A new connection starts a new session
+    pub fn new_session(&self) {
+        self.session.reset();
+    }
+
+    pub fn session(&self) -> Counts {
+        self.session.counts()
+    }
+
+    pub fn cumulative(&self) -> Counts {
+        self.cumulative.counts()
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            session: self.session(),
+            cumulative: self.cumulative(),
+        }
+    }
+}
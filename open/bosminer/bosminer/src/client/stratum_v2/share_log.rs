@@ -0,0 +1,216 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Log of share outcomes for offline analysis. Every accepted, rejected and stale share is
+//! appended to a CSV file as a single line:
+//!
+//! ```text
+//! time_ms,seq_num,outcome,difficulty,reason,annotation
+//! 1588000000000,17,rejected,4096,"stale-share","rack-7"
+//! ```
+//!
+//! The annotation is the one attached to the submitted share (empty when the share has been
+//! submitted without it).
+//!
+//! The file is written by a dedicated thread. Records are handed over through a bounded queue
+//! that never blocks the caller, records that don't fit into the queue are dropped and counted.
+//! Failed writes of the file are counted as well, they are reported once for each streak of
+//! failures (e.g. while the disk is full).
+
+use ii_logging::macros::*;
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+/// Header written to a new (empty) file
+pub const HEADER: &str = "time_ms,seq_num,outcome,difficulty,reason,annotation";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Accepted,
+    Rejected,
+    Stale,
+}
+
+impl Outcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Accepted => "accepted",
+            Self::Rejected => "rejected",
+            Self::Stale => "stale",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub time: time::SystemTime,
+    pub seq_num: u32,
+    pub outcome: Outcome,
+    pub difficulty: usize,
+    /// Error code of the pool for rejected shares, the cause for stale shares
+    pub reason: String,
+    /// Annotation attached to the submitted share
+    pub annotation: String,
+}
+
+impl Record {
+    /// Format the record as a CSV line without the line terminator
+    pub fn to_line(&self) -> String {
+        let time_ms = self
+            .time
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        // The reason comes from the pool so it is always quoted (and so is the annotation)
+        let quote = |field: &str| {
+            field
+                .chars()
+                .filter(|c| !c.is_control())
+                .collect::<String>()
+                .replace('"', "\"\"")
+        };
+        format!(
+            "{},{},{},{},\"{}\",\"{}\"",
+            time_ms,
+            self.seq_num,
+            self.outcome.as_str(),
+            self.difficulty,
+            quote(&self.reason),
+            quote(&self.annotation)
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct Sink {
+    path: PathBuf,
+    sender: mpsc::SyncSender<Record>,
+    dropped: AtomicUsize,
+    failed_writes: Arc<AtomicUsize>,
+}
+
+impl Sink {
+    /// Maximal number of records waiting for the writer
+    pub const QUEUE_CAPACITY: usize = 1024;
+
+    /// Open the file at `path` for appending and start the writer thread
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let mut writer = io::BufWriter::new(file);
+        if writer.get_ref().metadata()?.len() == 0 {
+            writeln!(writer, "{}", HEADER)?;
+        }
+        let (sender, receiver) = mpsc::sync_channel(Self::QUEUE_CAPACITY);
+        let thread_path = path.to_path_buf();
+        let failed_writes = Arc::new(AtomicUsize::new(0));
+        let thread_failed_writes = failed_writes.clone();
+        thread::Builder::new()
+            .name("share-log".to_string())
+            .spawn(move || {
+                Self::write_records(receiver, writer, thread_path, thread_failed_writes)
+            })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            sender,
+            dropped: AtomicUsize::new(0),
+            failed_writes,
+        })
+    }
+
+    /// Writer thread: the buffer is flushed whenever there is no record waiting, the thread ends
+    /// when the sink is dropped. Only the first failure of a streak is logged, the streak ends
+    /// with the next successful flush.
+    fn write_records(
+        receiver: mpsc::Receiver<Record>,
+        mut writer: io::BufWriter<fs::File>,
+        path: PathBuf,
+        failed_writes: Arc<AtomicUsize>,
+    ) {
+        let mut failing = false;
+        while let Ok(mut record) = receiver.recv() {
+            loop {
+                if let Err(e) = writeln!(writer, "{}", record.to_line()) {
+                    Self::write_failed(&mut failing, &failed_writes, &path, e);
+                }
+                record = match receiver.try_recv() {
+                    Ok(record) => record,
+                    Err(_) => break,
+                };
+            }
+            match writer.flush() {
+                Ok(()) if failing => {
+                    info!(
+                        "Share log: writing to {} has recovered ({} writes failed in total)",
+                        path.display(),
+                        failed_writes.load(Ordering::Relaxed)
+                    );
+                    failing = false;
+                }
+                Ok(()) => {}
+                Err(e) => Self::write_failed(&mut failing, &failed_writes, &path, e),
+            }
+        }
+    }
+
+    fn write_failed(failing: &mut bool, failed_writes: &AtomicUsize, path: &Path, e: io::Error) {
+        failed_writes.fetch_add(1, Ordering::Relaxed);
+        if !*failing {
+            warn!(
+                "Share log: cannot write to {}: {} (further failures are not reported until writing succeeds)",
+                path.display(),
+                e
+            );
+            *failing = true;
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Hand the record over to the writer, it is dropped when the writer lags behind
+    pub fn log(&self, record: Record) {
+        if self.sender.try_send(record).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Number of records dropped because the writer has been lagging behind
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Number of failed writes of records and flushes of the file
+    pub fn failed_writes(&self) -> usize {
+        self.failed_writes.load(Ordering::Relaxed)
+    }
+}
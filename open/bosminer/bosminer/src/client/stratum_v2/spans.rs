@@ -0,0 +1,185 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Spans of distributed tracing around the connection attempt and the submissions (the `tracing`
+//! ecosystem, e.g. exported to OpenTelemetry by a layer of the subscriber). The spans let
+//! operators correlate the behavior of the miner with traces of the pool:
+//! - `connect` - establishment of the connection including the Noise handshake
+//! - `open_channel` - every attempt to open the channel
+//! - `submit` - submitted share until it is acknowledged or accounted as stale
+//!
+//! Every span carries the pool label (host and port), the spans of the channel carry
+//! `channel_id` and the spans of shares carry `seq_num`. The spans are closed with the recorded
+//! `outcome`.
+//!
+//! Builds without the `tracing-spans` feature consult `Disabled`, whose methods are the empty
+//! defaults of the trait and are optimized away.
+
+use super::connect_timing;
+use super::Outcome;
+
+/// Points of the connection handlers at which spans are started and finished. The defaults
+/// record nothing.
+pub trait Spans {
+    /// Start span of the connection attempt to `pool`, the spans of the previous connection that
+    /// haven't been finished are dropped
+    fn connect_started(&self, _pool: &str) {}
+
+    fn connect_finished(&self, _outcome: connect_timing::Outcome) {}
+
+    fn open_channel_started(&self) {}
+
+    /// Finish the attempt to open the channel, `channel_id` is known when the channel is open
+    fn open_channel_finished(&self, _channel_id: Option<u32>, _outcome: connect_timing::Outcome) {}
+
+    fn submitted(&self, _channel_id: u32, _seq_num: u32) {}
+
+    /// Finish span of the share with `seq_num`
+    fn finished(&self, _seq_num: u32, _outcome: Outcome) {}
+}
+
+/// Spans used when the client is built without the `tracing-spans` feature
+#[derive(Debug, Default)]
+pub struct Disabled;
+
+impl Spans for Disabled {}
+
+#[cfg(feature = "tracing-spans")]
+pub type Tracing = Tracer;
+#[cfg(not(feature = "tracing-spans"))]
+pub type Tracing = Disabled;
+
+#[cfg(feature = "tracing-spans")]
+pub use tracer::Tracer;
+
+#[cfg(feature = "tracing-spans")]
+mod tracer {
+    use super::*;
+
+    use std::collections::VecDeque;
+    use std::sync::Mutex as StdMutex;
+
+    fn outcome_name(outcome: Outcome) -> &'static str {
+        match outcome {
+            Outcome::Accepted => "accepted",
+            Outcome::Rejected => "rejected",
+            Outcome::Stale => "stale",
+        }
+    }
+
+    fn connect_outcome_name(outcome: connect_timing::Outcome) -> &'static str {
+        match outcome {
+            connect_timing::Outcome::Ok => "ok",
+            connect_timing::Outcome::Failed => "failed",
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct State {
+        pool: String,
+        connect: Option<tracing::Span>,
+        open_channel: Option<tracing::Span>,
+        /// Spans of shares waiting for acknowledgement
+        shares: VecDeque<(u32, tracing::Span)>,
+    }
+
+    #[derive(Debug, Default)]
+    pub struct Tracer {
+        state: StdMutex<State>,
+    }
+
+    impl Tracer {
+        /// Shares that are never acknowledged nor accounted as stale must not grow the spans
+        /// indefinitely, the span of the oldest share is closed once the capacity is reached
+        pub const CAPACITY: usize = 1024;
+
+        fn lock(&self) -> std::sync::MutexGuard<State> {
+            self.state.lock().expect("BUG: cannot lock tracing spans")
+        }
+    }
+
+    impl Spans for Tracer {
+        fn connect_started(&self, pool: &str) {
+            let mut state = self.lock();
+            state.pool = pool.to_string();
+            state.open_channel = None;
+            state.connect = Some(tracing::info_span!(
+                "connect",
+                pool = %pool,
+                outcome = tracing::field::Empty
+            ));
+        }
+
+        fn connect_finished(&self, outcome: connect_timing::Outcome) {
+            if let Some(span) = self.lock().connect.take() {
+                span.record("outcome", &connect_outcome_name(outcome));
+            }
+        }
+
+        fn open_channel_started(&self) {
+            let mut state = self.lock();
+            let span = tracing::info_span!(
+                "open_channel",
+                pool = %state.pool,
+                channel_id = tracing::field::Empty,
+                outcome = tracing::field::Empty
+            );
+            state.open_channel = Some(span);
+        }
+
+        fn open_channel_finished(&self, channel_id: Option<u32>, outcome: connect_timing::Outcome) {
+            if let Some(span) = self.lock().open_channel.take() {
+                if let Some(channel_id) = channel_id {
+                    span.record("channel_id", &channel_id);
+                }
+                span.record("outcome", &connect_outcome_name(outcome));
+            }
+        }
+
+        fn submitted(&self, channel_id: u32, seq_num: u32) {
+            let mut state = self.lock();
+            let span = tracing::info_span!(
+                "submit",
+                pool = %state.pool,
+                channel_id,
+                seq_num,
+                outcome = tracing::field::Empty
+            );
+            state.shares.retain(|(other, _)| *other != seq_num);
+            if state.shares.len() >= Self::CAPACITY {
+                state.shares.pop_front();
+            }
+            state.shares.push_back((seq_num, span));
+        }
+
+        fn finished(&self, seq_num: u32, outcome: Outcome) {
+            let mut state = self.lock();
+            let index = match state.shares.iter().position(|(other, _)| *other == seq_num) {
+                Some(index) => index,
+                None => return,
+            };
+            if let Some((_, span)) = state.shares.remove(index) {
+                span.record("outcome", &outcome_name(outcome));
+            }
+        }
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Overall deadline of the startup. Every step of the startup has its own timeout, chained they
+//! could keep the client starting for many minutes without a definitive outcome. The budget bounds
+//! the time from the start of the client (or from the beginning of a reconnect attempt) to the
+//! first dispatched job, the client fails once the budget runs out.
+//!
+//! The total is split between the phases of the attempt. Phases that are not configured share the
+//! rest of the total by their weights, a phase that finishes early leaves its time to the total
+//! (the total is always enforced). The failure reports the most specific error encountered during
+//! the attempt (e.g. the rejection of a channel that has been retried) and the exhaustion of the
+//! budget otherwise.
+//!
+//! The budget is only consulted when a step is limited by a timeout anyway and at the boundaries
+//! of the phases, a startup that completes in time isn't delayed by it.
+
+use super::connect_timing;
+
+use crate::error;
+
+use bosminer_config::StratumV2StartupBudget;
+
+use serde::Serialize;
+
+use std::fmt;
+use std::sync::Arc;
+use std::time;
+
+/// Phases of the startup in the order they are entered
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Name resolution, TCP connect and the Noise handshake
+    Connect,
+    /// `SetupConnection` and opening of the channel (including its retries)
+    Handshake,
+    /// Time from the opened channel to the first dispatched job
+    FirstJob,
+}
+
+impl Phase {
+    const ALL: [Self; 3] = [Self::Connect, Self::Handshake, Self::FirstJob];
+
+    fn index(self) -> usize {
+        match self {
+            Self::Connect => 0,
+            Self::Handshake => 1,
+            Self::FirstJob => 2,
+        }
+    }
+
+    /// Share of the rest of the total of a phase that is not configured
+    fn weight(self) -> u64 {
+        match self {
+            Self::Connect | Self::FirstJob => 1,
+            Self::Handshake => 2,
+        }
+    }
+
+    fn configured(self, config: &StratumV2StartupBudget) -> Option<u64> {
+        match self {
+            Self::Connect => config.connect,
+            Self::Handshake => config.handshake,
+            Self::FirstJob => config.first_job,
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect => write!(f, "connect"),
+            Self::Handshake => write!(f, "handshake"),
+            Self::FirstJob => write!(f, "first job"),
+        }
+    }
+}
+
+/// Budgets resolved from the configuration
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Budgets {
+    pub total: time::Duration,
+    phases: [time::Duration; 3],
+}
+
+impl Budgets {
+    /// Resolve budgets of the startup (`reconnect` is false) or of a reconnect attempt
+    pub fn resolve(config: &StratumV2StartupBudget, reconnect: bool) -> Self {
+        let total = config.resolve_total(reconnect);
+        let configured: u64 = Phase::ALL
+            .iter()
+            .filter_map(|phase| phase.configured(config))
+            .sum();
+        let rest = total.saturating_sub(configured);
+        let weights: u64 = Phase::ALL
+            .iter()
+            .filter(|phase| phase.configured(config).is_none())
+            .map(|phase| phase.weight())
+            .sum();
+        let mut phases = [time::Duration::from_millis(0); 3];
+        for phase in Phase::ALL.iter() {
+            phases[phase.index()] = time::Duration::from_millis(
+                phase
+                    .configured(config)
+                    .unwrap_or_else(|| rest * phase.weight() / weights.max(1)),
+            );
+        }
+        Self {
+            total: time::Duration::from_millis(total),
+            phases,
+        }
+    }
+
+    pub fn phase(&self, phase: Phase) -> time::Duration {
+        self.phases[phase.index()]
+    }
+}
+
+/// Remaining budget of the startup in progress
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Status {
+    /// The budget of a reconnect attempt is in effect
+    pub reconnect: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phase: Option<Phase>,
+    /// Remaining time of the running phase in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phase_remaining_ms: Option<u64>,
+    /// Remaining time of the total budget in milliseconds
+    pub remaining_ms: u64,
+}
+
+/// Budget of a single startup or reconnect attempt
+#[derive(Debug)]
+pub struct Budget {
+    clock: Arc<dyn connect_timing::Clock>,
+    budgets: Budgets,
+    reconnect: bool,
+    deadline: time::Instant,
+    /// Running phase with its deadline
+    phase: Option<(Phase, time::Instant)>,
+    /// Most specific failure encountered during the attempt
+    failure: Option<error::ErrorKind>,
+}
+
+impl Budget {
+    /// The budget is measured from `started`
+    pub fn new(
+        budgets: Budgets,
+        reconnect: bool,
+        clock: Arc<dyn connect_timing::Clock>,
+        started: time::Instant,
+    ) -> Self {
+        Self {
+            clock,
+            budgets,
+            reconnect,
+            deadline: started + budgets.total,
+            phase: None,
+            failure: None,
+        }
+    }
+
+    /// Start `phase`, its deadline never exceeds the deadline of the total
+    pub fn start(&mut self, phase: Phase) {
+        let deadline = (self.clock.now() + self.budgets.phase(phase)).min(self.deadline);
+        self.phase = Some((phase, deadline));
+    }
+
+    fn current_deadline(&self) -> time::Instant {
+        self.phase.map_or(self.deadline, |(_, deadline)| deadline)
+    }
+
+    /// Time remaining until the deadline of the running phase
+    pub fn remaining(&self) -> time::Duration {
+        self.current_deadline()
+            .saturating_duration_since(self.clock.now())
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.clock.now() >= self.current_deadline()
+    }
+
+    /// Remember failure of a step that is going to be retried
+    pub fn record(&mut self, failure: error::ErrorKind) {
+        self.failure = Some(failure);
+    }
+
+    /// Error the attempt fails with when the budget has run out
+    pub fn error(&self) -> error::Error {
+        match self.failure.clone() {
+            Some(failure) => failure.into(),
+            None => error::Client::StartupBudgetExhausted(match self.phase {
+                Some((phase, _)) => format!("{} phase has not completed in time", phase),
+                None => format!(
+                    "{} ms have elapsed before connecting",
+                    self.budgets.total.as_millis()
+                ),
+            })
+            .into(),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        let now = self.clock.now();
+        Status {
+            reconnect: self.reconnect,
+            phase: self.phase.map(|(phase, _)| phase),
+            phase_remaining_ms: self
+                .phase
+                .map(|(_, deadline)| deadline.saturating_duration_since(now).as_millis() as u64),
+            remaining_ms: self.deadline.saturating_duration_since(now).as_millis() as u64,
+        }
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Status document summarizes the state of the client for the operator UI
+
+use super::accounting;
+use super::bandwidth;
+use super::clock_skew;
+use super::connect_timing;
+use super::context;
+use super::faults;
+use super::filters;
+use super::hashrate;
+use super::health;
+use super::held;
+use super::hourly;
+use super::job_taps;
+use super::notices;
+use super::restart_storm;
+use super::session;
+use super::share_counts;
+use super::startup_budget;
+use super::suggestion;
+use super::template_quality;
+use super::transcript;
+use super::user_file;
+
+use serde::Serialize;
+
+use std::time;
+
+/// Targets of the current session
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Targets {
+    /// Difficulty used locally for solving jobs
+    pub local_difficulty: usize,
+    /// Difficulty requested by the pool
+    pub pool_difficulty: usize,
+    /// The local target is determined by the startup target policy
+    pub startup_policy: bool,
+}
+
+/// Retries of the client counted separately for each domain
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Retries {
+    /// Connections that have been established again after the first one
+    pub connection: usize,
+    /// Attempts to open the channel again on an established connection
+    pub channel_open: usize,
+    /// Connections reset by the pool during the handshake (retried as transient failures)
+    pub handshake_reset: usize,
+}
+
+/// Parameters of the current connection negotiated with the pool
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Negotiated {
+    /// Protocol version selected by the pool in `SetupConnectionSuccess`
+    pub used_version: u16,
+    /// Flags of `SetupConnectionSuccess`
+    pub flags: u32,
+    /// The connection is secured with the noise protocol
+    pub encrypted: bool,
+    /// Host advertised to the pool in `SetupConnection`
+    pub endpoint_host: String,
+    /// Port advertised to the pool in `SetupConnection`
+    pub endpoint_port: u16,
+}
+
+/// State of the ceiling on the rate of job switches
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DispatchLimit {
+    /// Number of job switches that can be dispatched right away
+    pub tokens: f64,
+    /// Number of job updates that have never been dispatched because of the ceiling
+    pub suppressed: usize,
+}
+
+/// Failure of the client with a stable code for programmatic handling (see `error::error_codes`)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LastError {
+    pub code: &'static str,
+    /// Description of the failure for the operator, its wording may change between releases
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Document {
+    /// Time when the document has been assembled (a polled document may be served from a cache)
+    pub generated_at: time::SystemTime,
+    /// State of the client
+    pub status: String,
+    /// The client mines jobs of the pool and submits shares
+    pub ready: bool,
+    /// Synthetic faults armed for a resilience drill
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub injected_faults: Vec<faults::Active>,
+    /// Identifiers of the current connection and session
+    #[serde(flatten)]
+    pub context: context::Context,
+    pub health: health::Health,
+    pub targets: Targets,
+    /// Most recent distinct notices received from the pool
+    pub pool_messages: Vec<notices::Notice>,
+    /// Share acceptance per hour for the last 24 hours in chronological order
+    pub hourly_shares: Vec<hourly::Slot>,
+    pub retries: Retries,
+    /// Parameters negotiated with the pool, missing until the connection has been set up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub negotiated: Option<Negotiated>,
+    /// Identifiers of the channel of the current session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<session::ChannelIdentity>,
+    /// State of the job dispatch limit when it is configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dispatch_limit: Option<DispatchLimit>,
+    /// Solutions held back while the submission window is full
+    pub held_solutions: held::Stats,
+    /// Skew between `min_ntime` sent by the pool and the local clock
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool_time_skew: Option<clock_skew::Skew>,
+    /// Freshness of job templates served by the pool
+    pub template_quality: template_quality::Quality,
+    /// Hashrate estimated from shares accepted in the last finished bucket
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hashrate: Option<hashrate::Bucket>,
+    /// Backlog of the accounting of acknowledged solutions into the client statistics
+    pub accounting: accounting::Status,
+    /// Outcomes of shares of the current session and of all sessions
+    pub shares: share_counts::Status,
+    /// Solutions found by the backend broken down by the local filters
+    pub filters: filters::Status,
+    /// Difficulty suggested by an external difficulty manager while it is in effect
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggested_difficulty: Option<suggestion::Status>,
+    /// Rolling restart of the pool in progress
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restart_storm: Option<restart_storm::Status>,
+    /// Remaining budget of the startup while the client is starting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startup_budget: Option<startup_budget::Status>,
+    /// Median and 95th percentile of the phases of recent connection attempts
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub connect_phases: Vec<connect_timing::PhaseSummary>,
+    /// Traffic of the pool connection
+    pub bandwidth: bandwidth::Status,
+    /// Secondary consumers of dispatched jobs
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub job_taps: Vec<job_taps::Status>,
+    /// Source of the pool user when it is read from a file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_file: Option<user_file::Status>,
+    /// The most recent failure that has stopped the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<LastError>,
+    /// Frames exchanged during the last failed handshake (verbose document only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake_transcript: Option<transcript::Transcript>,
+}
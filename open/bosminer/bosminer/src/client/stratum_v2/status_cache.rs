@@ -0,0 +1,95 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Cache of the status document for consumers that poll it frequently (e.g. a fleet manager
+//! polling every miner once a second). Assembly of the document touches the state of most
+//! features of the client, the cached copy is therefore served to all polls within the refresh
+//! interval.
+//!
+//! There is no timer, the document is refreshed lazily by the first poll after the interval has
+//! elapsed. Only that poll assembles the document, polls that arrive meanwhile are served the
+//! previous copy. The lock of the cache is never held while the document is assembled, so a slow
+//! assembly doesn't block other polls nor the protocol.
+
+use super::status;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time;
+
+#[derive(Debug, Default)]
+pub struct Cache {
+    /// The most recent document and the time when it has been assembled
+    document: StdMutex<Option<(time::Instant, Arc<status::Document>)>>,
+    /// A poll is assembling a new document
+    refreshing: AtomicBool,
+    /// Number of assembled documents
+    generation: AtomicU64,
+}
+
+impl Cache {
+    fn lock_document(
+        &self,
+    ) -> std::sync::MutexGuard<Option<(time::Instant, Arc<status::Document>)>> {
+        self.document
+            .lock()
+            .expect("BUG: cannot lock cached status document")
+    }
+
+    /// Returns the cached document when it isn't older than `interval` at `now`, otherwise the
+    /// document is refreshed with `assemble`
+    pub fn get<F>(
+        &self,
+        now: time::Instant,
+        interval: time::Duration,
+        assemble: F,
+    ) -> Arc<status::Document>
+    where
+        F: FnOnce() -> status::Document,
+    {
+        let cached = self.lock_document().clone();
+        if let Some((assembled, document)) = cached.as_ref() {
+            if now.saturating_duration_since(*assembled) < interval {
+                return document.clone();
+            }
+        }
+        let refresh = !self.refreshing.swap(true, Ordering::AcqRel);
+        if !refresh {
+            // Another poll is refreshing the document
+            if let Some((_, document)) = cached {
+                return document;
+            }
+        }
+        let document = Arc::new(assemble());
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        if refresh {
+            self.lock_document().replace((now, document.clone()));
+            self.refreshing.store(false, Ordering::Release);
+        }
+        document
+    }
+
+    /// Returns the number of documents assembled by the cache
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Difficulty suggested by an external difficulty manager (e.g. a farm-level optimizer that
+//! balances share cadence across many workers). The suggestion overrides the difficulty the
+//! client derives locally for `max_target` of `OpenStandardMiningChannel` until it expires, see
+//! `StratumClient::channel_max_target` for the order of precedence.
+//!
+//! TODO: the client doesn't send any runtime difficulty suggestion (`UpdateChannel` is not
+//!  implemented in `ii_stratum`), the suggestion takes effect when the channel is opened next
+//!  time. Runtime updates have to go through `channel_max_target` as well once implemented.
+
+use serde::Serialize;
+
+use std::time;
+
+/// Originator of the suggestion
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// Automated manager (e.g. farm-level optimizer)
+    Optimizer,
+    /// Operator through the administration interface
+    Operator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Suggestion {
+    pub difficulty: f64,
+    pub source: Source,
+    /// Time when the suggestion has been set
+    pub set: time::Instant,
+}
+
+impl Suggestion {
+    /// The suggestion is not used anymore after `ttl` and the local heuristics apply again
+    pub fn is_expired(&self, ttl: time::Duration, now: time::Instant) -> bool {
+        now.saturating_duration_since(self.set) >= ttl
+    }
+
+    pub fn status(&self, ttl: time::Duration, now: time::Instant) -> Status {
+        let age = now.saturating_duration_since(self.set);
+        Status {
+            difficulty: self.difficulty,
+            source: self.source,
+            age: age.as_secs(),
+            expires_in: ttl.checked_sub(age).unwrap_or_default().as_secs(),
+        }
+    }
+}
+
+/// Effective suggestion as reported in the status document
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Status {
+    pub difficulty: f64,
+    pub source: Source,
+    /// Seconds since the suggestion has been set
+    pub age: u64,
+    /// Seconds until the suggestion expires
+    pub expires_in: u64,
+}
@@ -0,0 +1,125 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Cross-check of the target after reconnect for pools that don't send `SetTarget` right after
+//! the channel has been opened (they assume that the client remembers the target of the previous
+//! session while their vardiff may have moved in the meantime).
+//!
+//! Until the first authoritative `SetTarget` of the session arrives, the target is corrected from
+//! the evidence of the new session:
+//! - a target echoed in `OpenStandardMiningChannelSuccess` that is harder than the target of the
+//!   previous session is adopted right away (and reported)
+//! - several consecutive shares rejected as `difficulty-too-low` imply that the real target is
+//!   harder, the target is hardened by one band at a time for a bounded number of steps
+
+use serde::Serialize;
+
+use std::fmt;
+
+/// Error code of `SubmitSharesError` for shares that don't meet the target of the pool
+pub const DIFFICULTY_TOO_LOW: &str = "difficulty-too-low";
+
+/// Evidence that has lead to the correction of the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    /// Target echoed when the channel has been opened is harder than the remembered one
+    ChannelOpen,
+    /// Consecutive shares have been rejected for low difficulty
+    LowDifficultyRejects,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Correction {
+    pub reason: Reason,
+    pub from_difficulty: usize,
+    pub to_difficulty: usize,
+}
+
+impl fmt::Display for Correction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.reason {
+            Reason::ChannelOpen => "target echoed at channel open",
+            Reason::LowDifficultyRejects => "shares rejected for low difficulty",
+        };
+        write!(
+            f,
+            "difficulty {} -> {} ({})",
+            self.from_difficulty, self.to_difficulty, reason
+        )
+    }
+}
+
+/// State of the reject driven correction within a single session
+#[derive(Debug, Default)]
+pub struct Backfill {
+    /// The pool has sent `SetTarget` in this session
+    stopped: bool,
+    consecutive_rejects: usize,
+    steps: usize,
+}
+
+impl Backfill {
+    /// Number of consecutive low difficulty rejects that trigger a single correction step
+    pub const REJECT_THRESHOLD: usize = 3;
+    /// Maximal number of correction steps within a session
+    pub const MAX_STEPS: usize = 4;
+    /// Multiplier of the difficulty applied in a single correction step
+    pub const BAND: usize = 2;
+
+    /// The pool has sent an authoritative target, no correction is done anymore
+    pub fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.stopped
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Account an accepted share, it breaks the sequence of rejects
+    pub fn accepted(&mut self) {
+        self.consecutive_rejects = 0;
+    }
+
+    /// Account a share rejected with `code`. Returns the difficulty that should be used instead of
+    /// `difficulty` when the target has to be hardened by one band.
+    pub fn rejected(&mut self, code: &str, difficulty: usize) -> Option<usize> {
+        if self.stopped || self.steps >= Self::MAX_STEPS {
+            return None;
+        }
+        if code != DIFFICULTY_TOO_LOW {
+            self.consecutive_rejects = 0;
+            return None;
+        }
+        self.consecutive_rejects += 1;
+        if self.consecutive_rejects < Self::REJECT_THRESHOLD {
+            return None;
+        }
+        self.consecutive_rejects = 0;
+        self.steps += 1;
+        Some(difficulty.max(1).saturating_mul(Self::BAND))
+    }
+}
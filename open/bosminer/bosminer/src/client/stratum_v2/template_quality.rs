@@ -0,0 +1,253 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Freshness of job templates served by the pool. A pool that refreshes its template between
+//! prevhash changes (to include newly arrived transactions with high fees) sends jobs with the
+//! same prevhash and a new merkle root, a lazy pool sends a single template per block.
+//!
+//! The templates are counted per prevhash interval:
+//! - only distinct merkle roots are counted, duplicates of a known job (whether aliased or not)
+//!   are not a refresh
+//! - future jobs belong to the next prevhash, they are counted once activated. A pool that
+//!   activates the refresh sent as a future job with the same prevhash refreshes the template of
+//!   the current interval.
+
+use ii_stratum::v2::types::Uint256Bytes;
+
+use serde::Serialize;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time;
+
+/// Templates of a single prevhash
+#[derive(Debug)]
+struct Interval {
+    prev_hash: Uint256Bytes,
+    started: time::Instant,
+    merkle_roots: Vec<Uint256Bytes>,
+    /// Time since the previous template for each refresh
+    refreshes: Vec<time::Duration>,
+    last_template: time::Instant,
+}
+
+impl Interval {
+    fn new(prev_hash: Uint256Bytes, merkle_root: Uint256Bytes, now: time::Instant) -> Self {
+        Self {
+            prev_hash,
+            started: now,
+            merkle_roots: vec![merkle_root],
+            refreshes: Vec::new(),
+            last_template: now,
+        }
+    }
+
+    fn template(&mut self, merkle_root: Uint256Bytes, now: time::Instant) {
+        if self.merkle_roots.contains(&merkle_root) {
+            return;
+        }
+        self.merkle_roots.push(merkle_root);
+        self.refreshes
+            .push(now.saturating_duration_since(self.last_template));
+        self.last_template = now;
+    }
+}
+
+/// Interval that has been finished by a new prevhash
+#[derive(Debug, Clone)]
+struct Block {
+    duration: time::Duration,
+    refreshes: Vec<time::Duration>,
+}
+
+/// The rate of refreshes has fallen below the floor
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Advisory {
+    /// Refreshes per minute
+    pub refresh_rate: f64,
+    pub floor: f64,
+    /// Number of blocks the rate has been measured over
+    pub blocks: usize,
+}
+
+impl fmt::Display for Advisory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pool refreshes job templates {:.2} times per minute over the last {} blocks \
+             (floor {:.2}), it may be missing fees of recent transactions",
+            self.refresh_rate, self.blocks, self.floor
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Quality {
+    /// Distinct templates received for the current prevhash
+    pub current_templates: usize,
+    /// Number of recent blocks the statistics are computed from
+    pub blocks: usize,
+    /// Template refreshes per minute over the recent blocks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_rate: Option<f64>,
+    /// Median time between template refreshes (including the current prevhash)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub median_refresh_interval_ms: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct Tracker {
+    capacity: usize,
+    blocks: VecDeque<Block>,
+    current: Option<Interval>,
+    /// The advisory has been emitted in the current session
+    advised: bool,
+    last_advisory: Option<time::Instant>,
+}
+
+impl Tracker {
+    /// Number of the most recent blocks the statistics are computed from
+    pub const DEFAULT_CAPACITY: usize = 20;
+    /// The rate has to be measured over this many blocks before it is reported
+    pub const MIN_BLOCKS: usize = 3;
+    /// Minimal interval between advisories (a new session may report the rate again)
+    pub const ADVISORY_INTERVAL: time::Duration = time::Duration::from_secs(60 * 60);
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            blocks: VecDeque::with_capacity(capacity),
+            current: None,
+            advised: false,
+            last_advisory: None,
+        }
+    }
+
+    /// Job with `merkle_root` has been activated by `prev_hash`. A new prevhash finishes the
+    /// current interval, the refresh rate is checked against `floor` then. Returns an advisory at
+    /// most once per session.
+    pub fn prev_hash(
+        &mut self,
+        prev_hash: Uint256Bytes,
+        merkle_root: Uint256Bytes,
+        now: time::Instant,
+        floor: f64,
+    ) -> Option<Advisory> {
+        if let Some(current) = self.current.as_mut() {
+            if current.prev_hash == prev_hash {
+                current.template(merkle_root, now);
+                return None;
+            }
+        }
+        let finished = self
+            .current
+            .replace(Interval::new(prev_hash, merkle_root, now));
+        if let Some(interval) = finished {
+            if self.blocks.len() >= self.capacity {
+                self.blocks.pop_front();
+            }
+            self.blocks.push_back(Block {
+                duration: now.saturating_duration_since(interval.started),
+                refreshes: interval.refreshes,
+            });
+        }
+        self.check(now, floor)
+    }
+
+    /// Immediate job with `merkle_root` has been received for the current prevhash
+    pub fn template(&mut self, merkle_root: Uint256Bytes, now: time::Instant) {
+        if let Some(current) = self.current.as_mut() {
+            current.template(merkle_root, now);
+        }
+    }
+
+    /// A new connection doesn't continue the interval of the previous one, the statistics of
+    /// finished blocks are kept
+    pub fn new_session(&mut self) {
+        self.current = None;
+        self.advised = false;
+    }
+
+    fn check(&mut self, now: time::Instant, floor: f64) -> Option<Advisory> {
+        if !(floor > 0.0) || self.advised || self.blocks.len() < Self::MIN_BLOCKS {
+            return None;
+        }
+        let refresh_rate = self.refresh_rate()?;
+        if refresh_rate >= floor {
+            return None;
+        }
+        if let Some(last_advisory) = self.last_advisory {
+            if now.saturating_duration_since(last_advisory) < Self::ADVISORY_INTERVAL {
+                return None;
+            }
+        }
+        self.advised = true;
+        self.last_advisory = Some(now);
+        Some(Advisory {
+            refresh_rate,
+            floor,
+            blocks: self.blocks.len(),
+        })
+    }
+
+    /// Refreshes per minute over the finished blocks
+    fn refresh_rate(&self) -> Option<f64> {
+        let duration: time::Duration = self.blocks.iter().map(|block| block.duration).sum();
+        if duration == time::Duration::from_secs(0) {
+            return None;
+        }
+        let refreshes: usize = self.blocks.iter().map(|block| block.refreshes.len()).sum();
+        Some(refreshes as f64 * 60.0 / duration.as_secs_f64())
+    }
+
+    pub fn quality(&self) -> Quality {
+        let mut refreshes: Vec<_> = self
+            .blocks
+            .iter()
+            .flat_map(|block| block.refreshes.iter())
+            .chain(
+                self.current
+                    .iter()
+                    .flat_map(|interval| interval.refreshes.iter()),
+            )
+            .cloned()
+            .collect();
+        refreshes.sort_unstable();
+        Quality {
+            current_templates: self
+                .current
+                .as_ref()
+                .map_or(0, |interval| interval.merkle_roots.len()),
+            blocks: self.blocks.len(),
+            refresh_rate: self.refresh_rate(),
+            median_refresh_interval_ms: refreshes
+                .get(refreshes.len() / 2)
+                .map(|interval| interval.as_millis() as u64),
+        }
+    }
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
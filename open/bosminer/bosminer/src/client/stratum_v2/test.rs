@@ -0,0 +1,497 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+mod acknowledgements;
+#[cfg(feature = "fault-injection")]
+mod drills;
+mod error_codes;
+mod feature_gates;
+mod handshake;
+mod jobs;
+mod monitoring;
+mod solution_filters;
+mod state_handover;
+mod submissions;
+mod targets;
+mod traffic;
+
+use super::*;
+
+use crate::job;
+use crate::work;
+
+use bosminer_config::StratumV2JobDispatchLimit;
+use bosminer_config::StratumV2StartupBudget;
+
+fn build_connection_details(config: StratumV2Config) -> ConnectionDetails {
+    ConnectionDetails {
+        protocol: ClientProtocol::StratumV2Insecure,
+        user: "user".to_string(),
+        host: "localhost".to_string(),
+        port: 3336,
+        config: Arc::new(config),
+    }
+}
+
+fn build_solver() -> job::Solver {
+    let (_solution_sender, solution_receiver) = mpsc::unbounded();
+    job::Solver::new(Arc::new(work::EngineSender::new(None)), solution_receiver)
+}
+
+fn build_client(config: StratumV2Config) -> Arc<StratumClient> {
+    Arc::new(StratumClient::new(
+        build_connection_details(config),
+        None,
+        build_solver(),
+        None,
+    ))
+}
+
+/// Process `message` as if it has been received from the pool
+async fn handle_message<M>(
+    client: &Arc<StratumClient>,
+    event_handler: &mut StratumEventHandler,
+    message: M,
+) where
+    M: TryInto<<Framing as ii_wire::Framing>::Rx, Error = <Framing as ii_wire::Framing>::Error>,
+{
+    let frame = message.try_into().expect("BUG: cannot build frame");
+    client
+        .handle_frame(frame, event_handler)
+        .await
+        .expect("BUG: cannot handle frame");
+}
+
+async fn set_target(
+    client: &Arc<StratumClient>,
+    event_handler: &mut StratumEventHandler,
+    difficulty: usize,
+) {
+    let message = SetTarget {
+        channel_id: 0,
+        max_target: target_util::target_from_difficulty(difficulty).into(),
+    };
+    handle_message(client, event_handler, message).await;
+}
+
+async fn submit_shares_error(
+    client: &Arc<StratumClient>,
+    event_handler: &mut StratumEventHandler,
+    code: &str,
+) {
+    let message = SubmitSharesError {
+        channel_id: 0,
+        seq_num: 0,
+        code: Str0_32::from_str(code),
+    };
+    handle_message(client, event_handler, message).await;
+}
+
+/// Queue solution of the last dispatched job as submitted under `seq_num` and let the pool
+/// reject it with `code`
+async fn reject_share(
+    client: &Arc<StratumClient>,
+    event_handler: &mut StratumEventHandler,
+    seq_num: u32,
+    code: &str,
+) {
+    let solution = build_solution(client).await;
+    client.solutions.lock().await.push_back((solution, seq_num));
+    let message = SubmitSharesError {
+        channel_id: 0,
+        seq_num,
+        code: Str0_32::from_str(code),
+    };
+    handle_message(client, event_handler, message).await;
+}
+
+fn build_session_capture() -> replay::Capture {
+    let mut capture = replay::Capture::new();
+    let mut push = |offset_ms, frame| {
+        capture
+            .push(time::Duration::from_millis(offset_ms), frame)
+            .expect("BUG: cannot capture frame")
+    };
+    push(
+        0,
+        SetupConnectionSuccess {
+            used_version: 2,
+            flags: 0,
+        }
+        .try_into()
+        .expect("BUG: cannot build frame"),
+    );
+    push(
+        5,
+        OpenStandardMiningChannelSuccess {
+            req_id: 10,
+            channel_id: 0,
+            target: ii_bitcoin::Target::from_pool_difficulty(4).into(),
+            extranonce_prefix: Bytes0_32::new(),
+            group_channel_id: 0,
+        }
+        .try_into()
+        .expect("BUG: cannot build frame"),
+    );
+    push(
+        10,
+        NewMiningJob {
+            channel_id: 0,
+            job_id: 1,
+            future_job: true,
+            version: 0x20000000,
+            merkle_root: Uint256Bytes([0xaa; 32]),
+        }
+        .try_into()
+        .expect("BUG: cannot build frame"),
+    );
+    push(
+        15,
+        SetNewPrevHash {
+            channel_id: 0,
+            job_id: 1,
+            prev_hash: Uint256Bytes([0xbb; 32]),
+            min_ntime: 0x5e000000,
+            nbits: 0x1d00ffff,
+        }
+        .try_into()
+        .expect("BUG: cannot build frame"),
+    );
+    push(
+        20,
+        SetTarget {
+            channel_id: 0,
+            max_target: ii_bitcoin::Target::from_pool_difficulty(16).into(),
+        }
+        .try_into()
+        .expect("BUG: cannot build frame"),
+    );
+    capture
+}
+
+fn build_rejected_session_capture() -> replay::Capture {
+    let mut capture = replay::Capture::new();
+    capture
+        .push(
+            time::Duration::from_millis(0),
+            SetupConnectionSuccess {
+                used_version: 2,
+                flags: 0,
+            }
+            .try_into()
+            .expect("BUG: cannot build frame"),
+        )
+        .expect("BUG: cannot capture frame");
+    capture
+        .push(
+            time::Duration::from_millis(5),
+            OpenStandardMiningChannelError {
+                req_id: 10,
+                code: Str0_32::from_str("unknown-user"),
+            }
+            .try_into()
+            .expect("BUG: cannot build frame"),
+        )
+        .expect("BUG: cannot capture frame");
+    capture
+}
+
+/// Path of a user file that is unique for the test
+fn user_file_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("bosminer-{}-{}", std::process::id(), name))
+}
+
+/// Replay a session and return the request for opening the channel
+async fn replay_open_channel(client: &Arc<StratumClient>) -> OpenStandardMiningChannel {
+    let sent_frames = replay::replay_session(client.clone(), &build_session_capture(), false)
+        .await
+        .expect("BUG: replay failed");
+    let frame = sent_frames
+        .into_iter()
+        .nth(1)
+        .expect("BUG: no channel has been opened");
+    OpenStandardMiningChannel::try_from(frame).expect("BUG: cannot decode open channel")
+}
+
+async fn new_job(
+    client: &Arc<StratumClient>,
+    event_handler: &mut StratumEventHandler,
+    job_id: u32,
+    future_job: bool,
+) {
+    let message = NewMiningJob {
+        channel_id: 0,
+        job_id,
+        future_job,
+        version: 0x20000000,
+        merkle_root: Uint256Bytes([job_id as u8; 32]),
+    };
+    handle_message(client, event_handler, message).await;
+}
+
+async fn new_prev_hash(
+    client: &Arc<StratumClient>,
+    event_handler: &mut StratumEventHandler,
+    job_id: u32,
+) {
+    let message = SetNewPrevHash {
+        channel_id: 0,
+        job_id,
+        prev_hash: Uint256Bytes([0xbb; 32]),
+        min_ntime: 0x5e000000,
+        nbits: 0x1d00ffff,
+    };
+    handle_message(client, event_handler, message).await;
+}
+
+fn last_job_id(client: &Arc<StratumClient>) -> Option<u32> {
+    client.last_job().map(|job| job.id)
+}
+
+#[derive(Debug)]
+struct TestSolution {
+    target: ii_bitcoin::Target,
+}
+
+impl hal::BackendSolution for TestSolution {
+    fn nonce(&self) -> u32 {
+        0x12345678
+    }
+
+    fn midstate_idx(&self) -> usize {
+        0
+    }
+
+    fn solution_idx(&self) -> usize {
+        0
+    }
+
+    fn target(&self) -> &ii_bitcoin::Target {
+        &self.target
+    }
+}
+
+/// Build solution of the last job dispatched by the client
+async fn build_solution(client: &Arc<StratumClient>) -> work::Solution {
+    let time = client
+        .last_job()
+        .expect("BUG: no job has been dispatched")
+        .time;
+    build_solution_at(client, time).await
+}
+
+/// Build solution of the last job dispatched by the client with ntime set to `time`
+async fn build_solution_at(client: &Arc<StratumClient>, time: u32) -> work::Solution {
+    let job = client.last_job().expect("BUG: no job has been dispatched");
+    let midstate = work::Midstate {
+        version: job.version,
+        state: Default::default(),
+    };
+    work::Solution::new(
+        work::Assignment::new(job, vec![midstate], time),
+        TestSolution {
+            target: Default::default(),
+        },
+        None,
+    )
+}
+
+/// Collects shares from frames sent by the client
+#[derive(Default)]
+struct ShareCollector {
+    shares: Vec<SubmitSharesStandard>,
+}
+
+#[async_trait]
+impl Handler for ShareCollector {
+    async fn visit_submit_shares_standard(
+        &mut self,
+        _header: &Header,
+        share_msg: &SubmitSharesStandard,
+    ) {
+        self.shares.push(share_msg.clone());
+    }
+}
+
+/// Start mining a job with the easiest possible pool target so that every solution is submitted
+async fn start_mining(client: &Arc<StratumClient>) -> StratumEventHandler {
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    let message = SetTarget {
+        channel_id: 0,
+        max_target: Uint256Bytes([0xff; 32]),
+    };
+    handle_message(client, &mut event_handler, message).await;
+    new_job(client, &mut event_handler, 1, true).await;
+    new_prev_hash(client, &mut event_handler, 1).await;
+    event_handler
+}
+
+async fn submit_solutions(client: &Arc<StratumClient>, count: usize) {
+    let (connection_tx, _connection_rx) = mpsc::unbounded();
+    let mut solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+    for _ in 0..count {
+        let solution = build_solution(client).await;
+        assert!(solution_handler.process_solution(solution).await.is_ok());
+    }
+}
+
+fn transmit_stall_config() -> StratumV2Config {
+    StratumV2Config {
+        transmit_stall: Some(StratumV2TransmitStall {
+            slow_send: None,
+            ack_timeout: Some(50),
+            grace: Some(50),
+        }),
+        ..Default::default()
+    }
+}
+
+fn job_frame(job_id: u32) -> <Framing as ii_wire::Framing>::Rx {
+    NewMiningJob {
+        channel_id: 0,
+        job_id,
+        future_job: true,
+        version: 0x20000000,
+        merkle_root: Uint256Bytes([job_id as u8; 32]),
+    }
+    .try_into()
+    .expect("BUG: cannot build frame")
+}
+
+fn transmit_stalls(client: &Arc<StratumClient>) -> usize {
+    client
+        .events()
+        .into_iter()
+        .filter(|record| match record.event {
+            events::Event::TransmitStalled(_) => true,
+            _ => false,
+        })
+        .count()
+}
+
+async fn acknowledge(
+    client: &Arc<StratumClient>,
+    event_handler: &mut StratumEventHandler,
+    last_seq_num: u32,
+) {
+    let message = SubmitSharesSuccess {
+        channel_id: 0,
+        last_seq_num,
+        new_submits_accepted_count: 1,
+        new_shares_sum: 1,
+    };
+    handle_message(client, event_handler, message).await;
+}
+
+async fn pending_seq_nums(client: &Arc<StratumClient>) -> Vec<u32> {
+    client
+        .pending_submissions()
+        .await
+        .iter()
+        .map(|pending| pending.seq_num)
+        .collect()
+}
+
+/// Build acknowledgement frame of `count` shares ending with `seq_num` (error acknowledges a
+/// single share)
+fn ack_frame(seq_num: u32, count: u32, success: bool) -> <Framing as ii_wire::Framing>::Rx {
+    if success {
+        SubmitSharesSuccess {
+            channel_id: 0,
+            last_seq_num: seq_num,
+            new_submits_accepted_count: count,
+            new_shares_sum: count,
+        }
+        .try_into()
+    } else {
+        SubmitSharesError {
+            channel_id: 0,
+            seq_num,
+            code: Str0_32::from_str("invalid-share"),
+        }
+        .try_into()
+    }
+    .expect("BUG: cannot build frame")
+}
+
+/// Wait until all queued records have been accounted
+async fn settle_accounting(accounting: &accounting::Queue) {
+    while accounting.status_at(time::Instant::now()).queued > 0 {
+        tokio::time::delay_for(time::Duration::from_millis(1)).await;
+    }
+}
+
+/// Bring the client into `Restarting` state (stopped and started again while running)
+fn restart_client(client: &Arc<StratumClient>) {
+    assert!(client.status.initiate_starting());
+    assert!(client.status.initiate_running());
+    assert!(client.status.initiate_stopping());
+    client.status.initiate_starting();
+    assert!(client.status.status() == sync::Status::Restarting);
+}
+
+/// Build capture of a session in which the pool negotiates `flags` and opens channel `channel_id`
+fn build_negotiated_capture(flags: u32, channel_id: u32) -> replay::Capture {
+    let mut capture = replay::Capture::new();
+    capture
+        .push(
+            time::Duration::from_millis(0),
+            SetupConnectionSuccess {
+                used_version: 2,
+                flags,
+            }
+            .try_into()
+            .expect("BUG: cannot build frame"),
+        )
+        .expect("BUG: cannot capture frame");
+    capture
+        .push(
+            time::Duration::from_millis(5),
+            OpenStandardMiningChannelSuccess {
+                req_id: 10,
+                channel_id,
+                target: ii_bitcoin::Target::from_pool_difficulty(4).into(),
+                extranonce_prefix: Bytes0_32::from_slice(&[channel_id as u8; 4]),
+                group_channel_id: 0,
+            }
+            .try_into()
+            .expect("BUG: cannot build frame"),
+        )
+        .expect("BUG: cannot capture frame");
+    capture
+        .records
+        .extend(build_session_capture().records.into_iter().skip(2));
+    capture
+}
+
+fn event_kinds(client: &StratumClient) -> Vec<&'static str> {
+    client
+        .events()
+        .into_iter()
+        .map(|record| record.event.kind())
+        .collect()
+}
@@ -0,0 +1,767 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+#[tokio::test]
+async fn test_share_ordering_check() {
+    for &share_ordering_check in [
+        None,
+        Some(ShareOrderingCheck::Log),
+        Some(ShareOrderingCheck::Reconnect),
+    ]
+    .iter()
+    {
+        let client = build_client(StratumV2Config {
+            share_ordering_check,
+            ..Default::default()
+        });
+        let mut event_handler = start_mining(&client).await;
+        submit_solutions(&client, 3).await;
+
+        let message = SubmitSharesSuccess {
+            channel_id: 0,
+            last_seq_num: 1,
+            new_submits_accepted_count: 2,
+            new_shares_sum: 2,
+        };
+        handle_message(&client, &mut event_handler, message).await;
+        assert_eq!(*client.ordering_violations().take_snapshot(), 0);
+
+        // The pool rejects a share that hasn't been submitted
+        let frame = SubmitSharesError {
+            channel_id: 0,
+            seq_num: 7,
+            code: Str0_32::from_str("invalid-share"),
+        }
+        .try_into()
+        .expect("BUG: cannot build frame");
+        let result = client.handle_frame(frame, &mut event_handler).await;
+        let expected_violations = if share_ordering_check.is_some() { 1 } else { 0 };
+        assert_eq!(
+            *client.ordering_violations().take_snapshot(),
+            expected_violations
+        );
+        assert_eq!(
+            result.is_err(),
+            share_ordering_check == Some(ShareOrderingCheck::Reconnect)
+        );
+    }
+}
+
+#[test]
+fn test_share_ordering_verifier() {
+    let verifier = ordering::Verifier::default();
+    assert!(verifier.acknowledged(0).is_err());
+
+    // Sequence numbers wrap around
+    for seq_num in [u32::max_value() - 1, u32::max_value(), 0, 1].iter() {
+        assert!(verifier.submitted(*seq_num).is_ok());
+    }
+    assert!(verifier.acknowledged(u32::max_value()).is_ok());
+    assert!(verifier.acknowledged(1).is_ok());
+    assert!(verifier.acknowledged(2).is_err());
+    assert!(verifier.acknowledged(u32::max_value() - 2).is_err());
+    assert_eq!(
+        verifier.submitted(3),
+        Err(ordering::Violation::NonMonotonicSubmit {
+            expected: 2,
+            actual: 3
+        })
+    );
+
+    verifier.reset();
+    assert!(verifier.acknowledged(1).is_err());
+}
+
+#[tokio::test]
+async fn test_ack_sequencing() {
+    // (seq_num, count, success, anomaly in strict mode)
+    let script = [
+        // The first acknowledgement sets the baseline
+        (1, 2, true, false),
+        // In-order acknowledgements
+        (2, 1, true, false),
+        (3, 1, false, false),
+        // Duplicate
+        (3, 1, false, true),
+        // Regressed
+        (1, 1, true, true),
+        // Skips 4..=6
+        (8, 2, true, false),
+        // Fill the gap partially, only 6 remains outstanding
+        (4, 1, false, false),
+        (5, 1, true, false),
+        // The gap has already been filled
+        (4, 1, true, true),
+    ];
+    let strict = StratumV2AckSequencing {
+        gap_window: Some(60),
+        reaction: None,
+    };
+    let reconnect = StratumV2AckSequencing {
+        reaction: Some(ShareOrderingCheck::Reconnect),
+        ..strict.clone()
+    };
+    for ack_sequencing in [None, Some(strict), Some(reconnect)].iter() {
+        let client = build_client(StratumV2Config {
+            ack_sequencing: ack_sequencing.clone(),
+            ..Default::default()
+        });
+        let mut event_handler = start_mining(&client).await;
+        submit_solutions(&client, 10).await;
+
+        let mut expected_anomalies = 0;
+        for &(seq_num, count, success, anomaly) in script.iter() {
+            let result = client
+                .handle_frame(ack_frame(seq_num, count, success), &mut event_handler)
+                .await;
+            let anomaly = anomaly && ack_sequencing.is_some();
+            if anomaly {
+                expected_anomalies += 1;
+            }
+            assert_eq!(
+                *client.sequencing_anomalies().take_snapshot(),
+                expected_anomalies,
+                "seq_num={}",
+                seq_num
+            );
+            let reconnect = ack_sequencing
+                .as_ref()
+                .and_then(|ack_sequencing| ack_sequencing.reaction)
+                == Some(ShareOrderingCheck::Reconnect);
+            assert_eq!(result.is_err(), anomaly && reconnect, "seq_num={}", seq_num);
+        }
+
+        // The remaining gap is reported once it exceeds the gap window
+        let now = time::Instant::now();
+        event_handler.expire_ack_gaps(now + time::Duration::from_secs(30));
+        assert_eq!(
+            *client.sequencing_anomalies().take_snapshot(),
+            expected_anomalies
+        );
+        event_handler.expire_ack_gaps(now + time::Duration::from_secs(61));
+        event_handler.expire_ack_gaps(now + time::Duration::from_secs(120));
+        let recorded: Vec<_> = client
+            .events()
+            .into_iter()
+            .filter_map(|record| match record.event {
+                events::Event::SequencingAnomaly(anomaly) => Some(anomaly),
+                _ => None,
+            })
+            .collect();
+        if ack_sequencing.is_some() {
+            assert_eq!(
+                *client.sequencing_anomalies().take_snapshot(),
+                expected_anomalies + 1
+            );
+            assert_eq!(recorded.len(), expected_anomalies + 1);
+            assert_eq!(
+                recorded[0],
+                ordering::Anomaly::Regressed {
+                    expected: 4,
+                    received: 3
+                }
+            );
+            match recorded.last() {
+                Some(ordering::Anomaly::UnfilledGap {
+                    first: 6,
+                    last: 6,
+                    received: 8,
+                    ..
+                }) => (),
+                anomaly => panic!("unexpected anomaly {:?}", anomaly),
+            }
+        } else {
+            assert_eq!(*client.sequencing_anomalies().take_snapshot(), 0);
+            assert!(recorded.is_empty());
+        }
+    }
+}
+
+#[test]
+fn test_ack_sequencer() {
+    let now = time::Instant::now();
+    let window = time::Duration::from_secs(10);
+    let mut sequencer = ordering::Sequencer::default();
+    assert!(StratumV2Config {
+        ack_sequencing: Some(StratumV2AckSequencing {
+            gap_window: Some(0),
+            reaction: None,
+        }),
+        ..Default::default()
+    }
+    .validate()
+    .is_err());
+
+    // Sequence numbers wrap around
+    assert!(sequencer
+        .acknowledged(u32::max_value() - 1, 1, now)
+        .is_empty());
+    assert!(sequencer.acknowledged(2, 1, now).is_empty());
+    assert_eq!(sequencer.outstanding(), 3);
+    assert!(sequencer.acknowledged(0, 2, now).is_empty());
+    assert_eq!(sequencer.outstanding(), 1);
+    assert_eq!(
+        sequencer.expire(window, now + window),
+        vec![ordering::Anomaly::UnfilledGap {
+            first: 1,
+            last: 1,
+            received: 2,
+            age: window,
+        }]
+    );
+    assert_eq!(sequencer.outstanding(), 0);
+    // Expired gap cannot be filled anymore
+    assert_eq!(
+        sequencer.acknowledged(1, 1, now),
+        vec![ordering::Anomaly::Regressed {
+            expected: 3,
+            received: 1
+        }]
+    );
+
+    // The number of gaps is bounded
+    let mut seq_num = 2;
+    for _ in 0..ordering::Sequencer::MAX_GAPS {
+        seq_num += 2;
+        assert!(sequencer.acknowledged(seq_num, 1, now).is_empty());
+    }
+    assert_eq!(
+        sequencer.acknowledged(seq_num + 2, 1, now),
+        vec![ordering::Anomaly::UnfilledGap {
+            first: 3,
+            last: 3,
+            received: 4,
+            age: time::Duration::from_secs(0),
+        }]
+    );
+    assert_eq!(
+        sequencer.outstanding(),
+        ordering::Sequencer::MAX_GAPS as u64
+    );
+
+    sequencer.reset();
+    assert!(sequencer.acknowledged(1, 1, now).is_empty());
+    assert_eq!(sequencer.outstanding(), 0);
+}
+
+/// Number of solutions that have been accounted as accepted, rejected or stale
+async fn accounted_solutions(client: &Arc<StratumClient>) -> usize {
+    let stats = &client.client_stats;
+    (stats.accepted.take_snapshot().await.solutions
+        + stats.rejected.take_snapshot().await.solutions
+        + stats.stale.take_snapshot().await.solutions) as usize
+}
+
+async fn assert_solutions_consistent(client: &Arc<StratumClient>) {
+    settle_accounting(&client.accounting).await;
+    let pending = client.solutions.lock().await.len();
+    assert_eq!(
+        *client.submitted().take_snapshot(),
+        accounted_solutions(client).await + pending
+    );
+}
+
+#[tokio::test]
+async fn test_acknowledgement_cancellation() {
+    let client = build_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    let (connection_tx, _connection_rx) = mpsc::unbounded();
+    let mut solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+
+    for round in 0..24u32 {
+        for _ in 0..3 {
+            let solution = build_solution(&client).await;
+            assert!(solution_handler.process_solution(solution).await.is_ok());
+        }
+        // Acknowledge part of the solutions submitted in this round
+        let seq_num = round * 3 + 1;
+        let frame = if round % 2 == 0 {
+            SubmitSharesSuccess {
+                channel_id: 0,
+                last_seq_num: seq_num,
+                new_submits_accepted_count: 2,
+                new_shares_sum: 2,
+            }
+            .try_into()
+        } else {
+            SubmitSharesError {
+                channel_id: 0,
+                seq_num,
+                code: Str0_32::from_str("stale-share"),
+            }
+            .try_into()
+        }
+        .expect("BUG: cannot build frame");
+
+        // Cancel the handling at various await points, possibly while the queue is contended
+        let contention = if round % 4 == 3 {
+            Some(client.solutions.lock().await)
+        } else {
+            None
+        };
+        let mut handling = Box::pin(client.handle_frame(frame, &mut event_handler));
+        for _ in 0..round % 3 {
+            let _ = futures::poll!(handling.as_mut());
+        }
+        drop(handling);
+        drop(contention);
+        assert_solutions_consistent(&client).await;
+    }
+    assert!(accounted_solutions(&client).await > 0);
+
+    // Solutions that are still pending are accounted as stale once the connection is closed
+    client.discard_pending().await;
+    assert!(client.solutions.lock().await.is_empty());
+    assert_solutions_consistent(&client).await;
+    assert_eq!(*client.submitted().take_snapshot(), 24 * 3);
+}
+
+#[tokio::test]
+async fn test_acknowledgement_detached_accounting() {
+    let client = build_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    submit_solutions(&client, 3).await;
+
+    let frame = SubmitSharesSuccess {
+        channel_id: 0,
+        last_seq_num: 1,
+        new_submits_accepted_count: 2,
+        new_shares_sum: 2,
+    }
+    .try_into()
+    .expect("BUG: cannot build frame");
+    let mut handling = Box::pin(client.handle_frame(frame, &mut event_handler));
+    // The queue is drained in a single step and the handling is cancelled right after it (unless
+    // it has already completed), the accounting doesn't depend on it
+    let _ = futures::poll!(handling.as_mut());
+    drop(handling);
+    assert_eq!(pending_seq_nums(&client).await, vec![2]);
+
+    // The accounting isn't lost
+    assert_solutions_consistent(&client).await;
+    assert_eq!(
+        client.client_stats.accepted.take_snapshot().await.solutions,
+        2
+    );
+}
+
+/// Sink of the accounting that blocks while the gate is locked
+#[derive(Default)]
+struct GatedSink {
+    gate: Mutex<()>,
+    accounted: StdMutex<Vec<Outcome>>,
+}
+
+#[async_trait]
+impl accounting::Sink for GatedSink {
+    async fn account(&self, record: accounting::Record) {
+        let _gate = self.gate.lock().await;
+        self.accounted
+            .lock()
+            .expect("BUG: cannot lock")
+            .push(record.outcome);
+    }
+}
+
+#[tokio::test]
+async fn test_accounting_slow_sink() {
+    let client = build_client(Default::default());
+    let sink = Arc::new(GatedSink::default());
+    let gate = sink.gate.lock().await;
+    client.accounting.start(sink.clone());
+    let mut event_handler = start_mining(&client).await;
+    submit_solutions(&client, 3).await;
+
+    // Acknowledgements don't wait for the stuck accounting
+    let frame = SubmitSharesSuccess {
+        channel_id: 0,
+        last_seq_num: 1,
+        new_submits_accepted_count: 2,
+        new_shares_sum: 2,
+    }
+    .try_into()
+    .expect("BUG: cannot build frame");
+    tokio::time::timeout(
+        time::Duration::from_secs(1),
+        client.handle_frame(frame, &mut event_handler),
+    )
+    .await
+    .expect("BUG: acknowledgement waits for the accounting")
+    .expect("BUG: cannot handle acknowledgement");
+    assert_eq!(pending_seq_nums(&client).await, vec![2]);
+    tokio::time::delay_for(time::Duration::from_millis(20)).await;
+    let status = client.accounting.status_at(time::Instant::now());
+    assert_eq!(status.queued, 2);
+    assert!(
+        status
+            .oldest_age
+            .expect("BUG: no record is being accounted")
+            >= 20
+    );
+    assert_eq!(status.dropped, 0);
+    assert!(sink.accounted.lock().expect("BUG: cannot lock").is_empty());
+
+    // The accounting catches up once the sink recovers
+    drop(gate);
+    settle_accounting(&client.accounting).await;
+    assert_eq!(
+        *sink.accounted.lock().expect("BUG: cannot lock"),
+        vec![Outcome::Accepted, Outcome::Accepted]
+    );
+    let status = client.accounting.status_at(time::Instant::now());
+    assert_eq!((status.queued, status.oldest_age), (0, None));
+}
+
+#[tokio::test]
+async fn test_accounting_drops() {
+    let record = |outcome| accounting::Record {
+        outcome,
+        target: Default::default(),
+        time: time::Instant::now(),
+    };
+    let accounting = accounting::Queue::new(2);
+    let sink = Arc::new(GatedSink::default());
+    let gate = sink.gate.lock().await;
+    accounting.start(sink.clone());
+
+    // Records are dropped past the capacity while the sink is stuck
+    assert!(accounting.push(record(Outcome::Accepted)));
+    assert!(accounting.push(record(Outcome::Rejected)));
+    assert!(!accounting.push(record(Outcome::Stale)));
+    assert!(!accounting.push(record(Outcome::Accepted)));
+    let status = accounting.status_at(time::Instant::now());
+    assert_eq!((status.queued, status.dropped), (2, 2));
+
+    // The queued records are accounted once the sink recovers and the queue accepts records again
+    drop(gate);
+    settle_accounting(&accounting).await;
+    assert!(accounting.push(record(Outcome::Stale)));
+    settle_accounting(&accounting).await;
+    assert_eq!(
+        *sink.accounted.lock().expect("BUG: cannot lock"),
+        vec![Outcome::Accepted, Outcome::Rejected, Outcome::Stale]
+    );
+    assert_eq!(accounting.status_at(time::Instant::now()).dropped, 2);
+}
+
+#[cfg(feature = "reject-injection")]
+#[tokio::test]
+async fn test_reject_injection() {
+    let client = build_client(Default::default());
+    client.set_reject_injector(Some(reject_injector::Injector::new(
+        reject_injector::Pattern::EveryNth(3),
+    )));
+    let mut event_handler = start_mining(&client).await;
+    let (connection_tx, mut connection_rx) = mpsc::unbounded();
+    let mut solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+    for _ in 0..6 {
+        let solution = build_solution(&client).await;
+        assert!(solution_handler.process_solution(solution).await.is_ok());
+    }
+    // No share has been sent to the pool
+    assert!(connection_rx.try_next().is_err());
+    assert_eq!(solution_handler.injected_acks.len(), 6);
+
+    while let Some(frame) = solution_handler.injected_acks.pop_front() {
+        assert!(client.handle_frame(frame, &mut event_handler).await.is_ok());
+    }
+    assert!(client.solutions.lock().await.is_empty());
+    assert_eq!(
+        client.client_stats.accepted.take_snapshot().await.solutions,
+        4
+    );
+    assert_eq!(
+        client.client_stats.rejected.take_snapshot().await.solutions,
+        2
+    );
+    let notices = client.pool_messages();
+    assert_eq!(notices.len(), 1);
+    assert_eq!(notices[0].text, reject_injector::Injector::DEFAULT_CODE);
+}
+
+#[cfg(feature = "reject-injection")]
+#[test]
+fn test_reject_injection_pattern() {
+    let mut injector = reject_injector::Injector::with_code(
+        reject_injector::Pattern::Sequence(vec![false, true, true]),
+        "stale-share",
+    );
+    let rejects: Vec<_> = (0..6)
+        .map(|_| injector.next_share().map(|code| code.to_string()))
+        .collect();
+    let reject = Some("stale-share".to_string());
+    assert_eq!(
+        rejects,
+        vec![
+            None,
+            reject.clone(),
+            reject.clone(),
+            None,
+            reject.clone(),
+            reject
+        ]
+    );
+
+    // Degenerate patterns never reject
+    let mut injector = reject_injector::Injector::new(reject_injector::Pattern::EveryNth(0));
+    assert_eq!(injector.next_share(), None);
+    let mut injector = reject_injector::Injector::new(reject_injector::Pattern::Sequence(vec![]));
+    assert_eq!(injector.next_share(), None);
+}
+
+async fn try_acknowledge(
+    client: &Arc<StratumClient>,
+    event_handler: &mut StratumEventHandler,
+    last_seq_num: u32,
+) -> bool {
+    let message = SubmitSharesSuccess {
+        channel_id: 0,
+        last_seq_num,
+        new_submits_accepted_count: 1,
+        new_shares_sum: 1,
+    };
+    let frame = message.try_into().expect("BUG: cannot build frame");
+    client.handle_frame(frame, event_handler).await.is_ok()
+}
+
+#[tokio::test]
+async fn test_unexpected_acks() {
+    // Acknowledgements before any submission are only counted by default
+    let client = build_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    acknowledge(&client, &mut event_handler, 0).await;
+    assert_eq!(*client.unexpected_acks().take_snapshot(), 1);
+    submit_shares_error(&client, &mut event_handler, "stale-share").await;
+    assert_eq!(*client.unexpected_acks().take_snapshot(), 2);
+
+    // Acknowledgement of a submitted share is not an anomaly
+    submit_solutions(&client, 1).await;
+    acknowledge(&client, &mut event_handler, 0).await;
+    assert_eq!(*client.unexpected_acks().take_snapshot(), 2);
+    let stats = &client.client_stats;
+    assert_eq!(stats.accepted.take_snapshot().await.solutions, 1);
+
+    // The connection is restarted once the limit is exceeded
+    let client = build_client(StratumV2Config {
+        unexpected_ack_limit: Some(1),
+        ..Default::default()
+    });
+    let mut event_handler = start_mining(&client).await;
+    assert!(try_acknowledge(&client, &mut event_handler, 0).await);
+    assert!(!try_acknowledge(&client, &mut event_handler, 1).await);
+    assert_eq!(*client.unexpected_acks().take_snapshot(), 2);
+}
+
+fn reject_streaks(client: &StratumClient) -> Vec<reject_streak::Streak> {
+    client
+        .events()
+        .into_iter()
+        .filter_map(|record| match record.event {
+            events::Event::RejectStreak(streak) => Some(streak),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_reject_streak() {
+    assert!(StratumV2Config {
+        reject_streak: Some(StratumV2RejectStreak {
+            threshold: Some(1),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+    .validate()
+    .is_err());
+
+    // Streaks are not tracked unless configured
+    let client = build_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    for seq_num in 0..StratumV2RejectStreak::DEFAULT_THRESHOLD as u32 {
+        reject_share(&client, &mut event_handler, seq_num, "invalid-user").await;
+    }
+    assert!(reject_streaks(&client).is_empty());
+
+    let client = build_client(StratumV2Config {
+        reject_streak: Some(StratumV2RejectStreak {
+            threshold: Some(3),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    let mut event_handler = start_mining(&client).await;
+
+    // A different reason starts a new streak
+    reject_share(&client, &mut event_handler, 0, "invalid-user").await;
+    reject_share(&client, &mut event_handler, 1, "invalid-user").await;
+    reject_share(&client, &mut event_handler, 2, "stale-share").await;
+    assert!(reject_streaks(&client).is_empty());
+
+    // An accepted share ends the streak
+    reject_share(&client, &mut event_handler, 3, "stale-share").await;
+    let solution = build_solution(&client).await;
+    client.solutions.lock().await.push_back((solution, 4));
+    acknowledge(&client, &mut event_handler, 4).await;
+    reject_share(&client, &mut event_handler, 5, "stale-share").await;
+    reject_share(&client, &mut event_handler, 6, "stale-share").await;
+    assert!(reject_streaks(&client).is_empty());
+
+    // The streak is escalated only once
+    for seq_num in 7..12 {
+        reject_share(&client, &mut event_handler, seq_num, "stale-share").await;
+    }
+    assert_eq!(
+        reject_streaks(&client),
+        vec![reject_streak::Streak {
+            reason: "stale-share".to_string(),
+            count: 3,
+        }]
+    );
+    assert!(event_handler.fatal_error.is_none());
+}
+
+#[tokio::test]
+async fn test_reject_streak_reconnect() {
+    let client = build_client(StratumV2Config {
+        reject_streak: Some(StratumV2RejectStreak {
+            threshold: Some(2),
+            reaction: Some(ShareOrderingCheck::Reconnect),
+        }),
+        ..Default::default()
+    });
+    let mut event_handler = start_mining(&client).await;
+    reject_share(&client, &mut event_handler, 0, "invalid-version").await;
+    assert!(event_handler.fatal_error.is_none());
+    reject_share(&client, &mut event_handler, 1, "invalid-version").await;
+    match event_handler
+        .fatal_error
+        .take()
+        .expect("BUG: missing fatal error")
+        .kind()
+    {
+        error::ErrorKind::Client(error::Client::RejectStreak(_)) => {}
+        kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+#[tokio::test]
+async fn test_reject_streak_sanitized() {
+    let client = build_client(StratumV2Config {
+        reject_streak: Some(StratumV2RejectStreak {
+            threshold: Some(2),
+            reaction: Some(ShareOrderingCheck::Reconnect),
+        }),
+        ..Default::default()
+    });
+    let mut event_handler = start_mining(&client).await;
+    let code = "invalid\u{1b}[2J-user\n";
+    reject_share(&client, &mut event_handler, 0, code).await;
+    reject_share(&client, &mut event_handler, 1, code).await;
+
+    // Neither the event nor the error carry the control characters of the pool code
+    let streak = reject_streak::Streak {
+        reason: "invalid[2J-user".to_string(),
+        count: 2,
+    };
+    assert_eq!(reject_streaks(&client), vec![streak.clone()]);
+    let error = event_handler
+        .fatal_error
+        .take()
+        .expect("BUG: missing fatal error");
+    match error.kind() {
+        error::ErrorKind::Client(error::Client::RejectStreak(text)) => {
+            assert_eq!(*text, streak.to_string());
+        }
+        kind => panic!("unexpected error: {:?}", kind),
+    }
+    assert!(!error.to_string().chars().any(char::is_control));
+
+    // The streak is detected even when the codes differ only in control characters
+    let mut detector = reject_streak::Detector::default();
+    assert!(detector.rejected("stale\u{7}-share", 2).is_none());
+    assert_eq!(
+        detector.rejected("stale-share", 2),
+        Some(reject_streak::Streak {
+            reason: "stale-share".to_string(),
+            count: 2,
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_share_counts() {
+    let client = build_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    let solution = build_solution(&client).await;
+    client.solutions.lock().await.push_back((solution, 0));
+    acknowledge(&client, &mut event_handler, 0).await;
+    reject_share(&client, &mut event_handler, 1, "invalid-share").await;
+    let solution = build_solution(&client).await;
+    client.solutions.lock().await.push_back((solution, 2));
+    client.discard_pending().await;
+
+    let counts = share_counts::Counts {
+        accepted: 1,
+        rejected: 1,
+        stale: 1,
+    };
+    assert_eq!(client.session_shares(), counts);
+    assert_eq!(client.cumulative_shares(), counts);
+
+    // A new connection resets only the counts of the session
+    client.share_counts.new_session();
+    let solution = build_solution(&client).await;
+    client.solutions.lock().await.push_back((solution, 3));
+    acknowledge(&client, &mut event_handler, 3).await;
+    assert_eq!(
+        client.session_shares(),
+        share_counts::Counts {
+            accepted: 1,
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        client.cumulative_shares(),
+        share_counts::Counts {
+            accepted: 2,
+            ..counts
+        }
+    );
+    assert_eq!(
+        client.status_document().shares,
+        share_counts::Status {
+            session: client.session_shares(),
+            cumulative: client.cumulative_shares(),
+        }
+    );
+}
@@ -0,0 +1,235 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+fn drill_client(config: StratumV2Config) -> Arc<StratumClient> {
+    build_client(StratumV2Config {
+        fault_injection: Some(true),
+        ..config
+    })
+}
+
+fn injected_faults(client: &StratumClient) -> Vec<faults::Fault> {
+    client
+        .status_document()
+        .injected_faults
+        .into_iter()
+        .map(|active| active.fault)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_fault_injection_config() {
+    let client = build_client(Default::default());
+    match client
+        .arm_fault(faults::Fault::CorruptNextJobTarget)
+        .expect_err("BUG: fault armed without configuration")
+        .kind()
+    {
+        error::ErrorKind::Client(error::Client::FaultInjectionDisabled) => {}
+        kind => panic!("unexpected error: {:?}", kind),
+    }
+    assert!(injected_faults(&client).is_empty());
+    assert!(!event_kinds(&client).contains(&"fault_armed"));
+
+    // Arming a fault of the same kind replaces the armed one
+    let client = drill_client(Default::default());
+    let fault = faults::Fault::DropAcks { count: 1 };
+    client.arm_fault(fault).expect("BUG: cannot arm fault");
+    client
+        .arm_fault(faults::Fault::DropAcks { count: 2 })
+        .expect("BUG: cannot arm fault");
+    assert_eq!(
+        injected_faults(&client),
+        vec![faults::Fault::DropAcks { count: 2 }]
+    );
+    client.disarm_faults();
+    assert!(injected_faults(&client).is_empty());
+    assert_eq!(
+        event_kinds(&client),
+        vec![
+            "fault_armed",
+            "fault_disarmed",
+            "fault_armed",
+            "fault_disarmed"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_fault_drop_acks() {
+    let client = drill_client(transmit_stall_config());
+    let mut event_handler = start_mining(&client).await;
+    submit_solutions(&client, 2).await;
+    client
+        .arm_fault(faults::Fault::DropAcks { count: 1 })
+        .expect("BUG: cannot arm fault");
+
+    // The dropped acknowledgement leaves the shares pending
+    acknowledge(&client, &mut event_handler, 0).await;
+    assert_eq!(client.solutions.lock().await.len(), 2);
+    assert!(injected_faults(&client).is_empty());
+    assert!(event_kinds(&client).contains(&"fault_disarmed"));
+
+    // The shares stall until the pool acknowledges them again
+    tokio::time::delay_for(time::Duration::from_millis(60)).await;
+    assert!(client
+        .handle_frame(job_frame(2), &mut event_handler)
+        .await
+        .is_ok());
+    assert_eq!(transmit_stalls(&client), 1);
+    acknowledge(&client, &mut event_handler, 1).await;
+    assert!(client.solutions.lock().await.is_empty());
+    assert!(client.health().is_healthy());
+}
+
+#[tokio::test]
+async fn test_fault_delay_acks() {
+    let client = drill_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    submit_solutions(&client, 2).await;
+    client
+        .arm_fault(faults::Fault::DelayAcks {
+            delay_ms: 50,
+            count: 1,
+        })
+        .expect("BUG: cannot arm fault");
+
+    let started = time::Instant::now();
+    acknowledge(&client, &mut event_handler, 0).await;
+    assert!(started.elapsed() >= time::Duration::from_millis(50));
+    // The delayed acknowledgement is processed
+    assert_eq!(client.solutions.lock().await.len(), 1);
+
+    let started = time::Instant::now();
+    acknowledge(&client, &mut event_handler, 1).await;
+    assert!(started.elapsed() < time::Duration::from_millis(50));
+    assert!(client.solutions.lock().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_fault_corrupt_job_target() {
+    let client = drill_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    set_target(&client, &mut event_handler, 1024).await;
+    client
+        .arm_fault(faults::Fault::CorruptNextJobTarget)
+        .expect("BUG: cannot arm fault");
+
+    new_job(&client, &mut event_handler, 2, true).await;
+    new_prev_hash(&client, &mut event_handler, 2).await;
+    let job = client.last_job().expect("BUG: missing job");
+    assert_eq!(job.target.get_difficulty(), 1);
+    // Solutions of the corrupted job are still checked against the target of the pool
+    assert_eq!(job.pool_target.get_difficulty(), 1024);
+    assert!(injected_faults(&client).is_empty());
+
+    // Only the next job is corrupted
+    new_job(&client, &mut event_handler, 3, true).await;
+    new_prev_hash(&client, &mut event_handler, 3).await;
+    let job = client.last_job().expect("BUG: missing job");
+    assert_eq!(job.target.get_difficulty(), 1024);
+}
+
+#[tokio::test]
+async fn test_fault_blackhole_submissions() {
+    let client = drill_client(transmit_stall_config());
+    let mut event_handler = start_mining(&client).await;
+    let (connection_tx, mut connection_rx) = mpsc::unbounded();
+    let mut solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+    client
+        .arm_fault(faults::Fault::BlackholeSubmissions { secs: 60 })
+        .expect("BUG: cannot arm fault");
+
+    // The shares are accounted as submitted while nothing is sent to the pool
+    for _ in 0..2 {
+        let solution = build_solution(&client).await;
+        assert!(solution_handler.process_solution(solution).await.is_ok());
+    }
+    assert!(connection_rx.try_next().is_err());
+    assert_eq!(*client.submitted().take_snapshot(), 2);
+
+    // The watchdog restarts the connection when the stall persists
+    let mut job_id = 2;
+    let e = loop {
+        tokio::time::delay_for(time::Duration::from_millis(10)).await;
+        if let Err(e) = client
+            .handle_frame(job_frame(job_id), &mut event_handler)
+            .await
+        {
+            break e;
+        }
+        job_id += 1;
+        assert!(job_id < 100, "BUG: stall hasn't been detected");
+    };
+    match e.kind() {
+        error::ErrorKind::Client(error::Client::TransmitStalled(_)) => {}
+        kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    // Shares are sent again once the fault is disarmed
+    client.disarm_faults();
+    let solution = build_solution(&client).await;
+    assert!(solution_handler.process_solution(solution).await.is_ok());
+    assert!(connection_rx
+        .try_next()
+        .expect("BUG: share hasn't been sent")
+        .is_some());
+}
+
+#[tokio::test]
+async fn test_fault_force_disconnect() {
+    let client = drill_client(Default::default());
+    let context = client.context();
+    client
+        .arm_fault(faults::Fault::ForceDisconnect { after_secs: 60 })
+        .expect("BUG: cannot arm fault");
+    assert!(
+        client.faults.disconnect_delay(time::Instant::now()) > Some(time::Duration::from_secs(59))
+    );
+    assert!(client.force_disconnect(context).is_ok());
+    assert_eq!(
+        injected_faults(&client),
+        vec![faults::Fault::ForceDisconnect { after_secs: 60 }]
+    );
+
+    // The failure is recorded with the code of the injected fault so that the restart is not
+    // mistaken for a real incident
+    client
+        .arm_fault(faults::Fault::ForceDisconnect { after_secs: 0 })
+        .expect("BUG: cannot arm fault");
+    assert_eq!(
+        client.faults.disconnect_delay(time::Instant::now()),
+        Some(time::Duration::from_secs(0))
+    );
+    let e = client
+        .force_disconnect(context)
+        .expect_err("BUG: connection hasn't been closed");
+    assert_eq!(e.error_code(), "stratum.drill.injected_fault");
+    assert!(injected_faults(&client).is_empty());
+    assert!(client.force_disconnect(context).is_ok());
+}
@@ -0,0 +1,97 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+#[test]
+fn test_error_codes_golden() {
+    // Codes are append-only: new codes are added to the end of the golden file, existing ones are
+    // never renamed nor removed
+    let golden: Vec<String> = serde_json::from_str(include_str!("../golden/error_codes.json"))
+        .expect("BUG: invalid golden file");
+    let registry = error::error_codes();
+    let codes: Vec<_> = registry.iter().map(|code| code.code.to_string()).collect();
+    assert_eq!(codes, golden);
+    assert_eq!(
+        codes.iter().collect::<std::collections::HashSet<_>>().len(),
+        codes.len()
+    );
+    assert!(registry.iter().all(|code| !code.description.is_empty()));
+
+    // The code of a wrapped error is the code of the most specific cause
+    let e: error::Error = error::Error::from(error::Client::NotConnected)
+        .context("Cannot probe latency")
+        .into();
+    assert_eq!(e.error_code(), "stratum.command.not_connected");
+    assert_eq!(
+        error::Error::from("Standard application shutdown").error_code(),
+        "general"
+    );
+}
+
+#[tokio::test]
+async fn test_error_code_dns_failure() {
+    let client = build_client(Default::default());
+    client
+        .connection_details
+        .lock()
+        .expect("BUG: cannot lock connection details")
+        .host = "pool.invalid".to_string();
+    client.clone().run().await;
+
+    let last_error = client
+        .status_document()
+        .last_error
+        .expect("BUG: missing last error");
+    assert_eq!(last_error.code, "stratum.connect.dns_failure");
+    let event = client.events.snapshot().pop().expect("BUG: missing event");
+    assert_eq!(
+        event.event.error_code(),
+        Some("stratum.connect.dns_failure")
+    );
+}
+
+#[tokio::test]
+async fn test_error_code_channel_rejected() {
+    let client = build_client(Default::default());
+    let e = replay::replay_session(client.clone(), &build_rejected_session_capture(), false)
+        .await
+        .err()
+        .expect("BUG: handshake succeeded");
+    assert_eq!(e.error_code(), "stratum.channel.open_rejected");
+
+    let document = serde_json::to_value(client.status_document())
+        .expect("BUG: cannot serialize status document");
+    assert_eq!(
+        document["last_error"]["code"],
+        serde_json::json!("stratum.channel.open_rejected")
+    );
+    let chunk = client.export_diagnostics(&diagnostics::ExportDiagnostics {
+        section: diagnostics::Section::Events,
+        since_cursor: 0,
+        max_bytes: usize::MAX,
+    });
+    assert!(chunk
+        .records
+        .iter()
+        .any(|record| record.contains("\"error_code\":\"stratum.channel.open_rejected\"")));
+}
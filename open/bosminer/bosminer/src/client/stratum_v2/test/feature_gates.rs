@@ -0,0 +1,348 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+// The goldens cover the default feature set and the full feature set (`--all-features`), other
+// combinations of features are not checked
+#[cfg(not(any(
+    feature = "reject-injection",
+    feature = "conformance",
+    feature = "fault-injection",
+    feature = "tracing-spans"
+)))]
+const GOLDEN_CAPABILITIES: &str = include_str!("../golden/capabilities_default.json");
+
+#[cfg(all(
+    feature = "reject-injection",
+    feature = "conformance",
+    feature = "fault-injection",
+    feature = "tracing-spans"
+))]
+const GOLDEN_CAPABILITIES: &str = include_str!("../golden/capabilities_full.json");
+
+#[cfg(any(
+    not(any(
+        feature = "reject-injection",
+        feature = "conformance",
+        feature = "fault-injection",
+        feature = "tracing-spans"
+    )),
+    all(
+        feature = "reject-injection",
+        feature = "conformance",
+        feature = "fault-injection",
+        feature = "tracing-spans"
+    )
+))]
+#[test]
+fn test_capabilities_golden() {
+    let serialized =
+        serde_json::to_string_pretty(&capabilities()).expect("BUG: cannot serialize capabilities");
+    assert_eq!(serialized.trim_end(), GOLDEN_CAPABILITIES.trim_end());
+}
+
+#[test]
+fn test_capabilities_features() {
+    // Every cargo feature of the crate provides a capability and vice versa
+    let manifest = include_str!("../../../../Cargo.toml");
+    let mut features: Vec<_> = manifest
+        .lines()
+        .skip_while(|line| line.trim() != "[features]")
+        .skip(1)
+        .take_while(|line| !line.starts_with('['))
+        .filter_map(|line| line.split('=').next())
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && !name.starts_with('#') && *name != "default")
+        .collect();
+    features.sort();
+    let mut capability_features: Vec<_> = capabilities()
+        .capabilities
+        .into_iter()
+        .filter_map(|capability| capability.feature)
+        .collect();
+    capability_features.sort();
+    assert_eq!(features, capability_features);
+
+    let matrix = capabilities();
+    let reject_injection = matrix
+        .get(capabilities::REJECT_INJECTION)
+        .expect("BUG: missing reject injection capability");
+    assert_eq!(
+        reject_injection.status == capabilities::Status::CompiledIn,
+        cfg!(feature = "reject-injection")
+    );
+}
+
+#[tokio::test]
+async fn test_active_capabilities() {
+    let client = build_client(StratumV2Config {
+        share_ordering_check: Some(ShareOrderingCheck::Log),
+        ..Default::default()
+    });
+    let expected = vec![
+        capabilities::VERSION_ROLLING,
+        capabilities::SWITCH_JOURNAL,
+        capabilities::SHARE_ORDERING_CHECK,
+    ];
+    assert_eq!(client.active_capabilities(), expected);
+    let matrix = capabilities();
+    assert!(client
+        .active_capabilities()
+        .iter()
+        .all(|name| matrix.get(name).is_some()));
+
+    // The pool disables version rolling
+    let mut capture = replay::Capture::new();
+    capture
+        .push(
+            time::Duration::from_millis(0),
+            SetupConnectionSuccess {
+                used_version: 2,
+                flags: capabilities::REQUIRES_FIXED_VERSION,
+            }
+            .try_into()
+            .expect("BUG: cannot build frame"),
+        )
+        .expect("BUG: cannot capture frame");
+    capture
+        .records
+        .extend(build_session_capture().records.into_iter().skip(1));
+    replay::replay_session(client.clone(), &capture, false)
+        .await
+        .expect("BUG: replay failed");
+    assert_eq!(
+        client.active_capabilities(),
+        vec![
+            capabilities::SWITCH_JOURNAL,
+            capabilities::SHARE_ORDERING_CHECK,
+        ]
+    );
+    let job = client.last_job().expect("BUG: no job has been received");
+    assert_eq!(job::Bitcoin::version_mask(job.as_ref()), 0);
+
+    // Next session negotiates the flags again
+    replay::replay_session(client.clone(), &build_session_capture(), false)
+        .await
+        .expect("BUG: replay failed");
+    assert_eq!(client.active_capabilities(), expected);
+}
+
+#[tokio::test]
+async fn test_conformance_vectors() {
+    let vectors = conformance::parse(conformance::VECTORS).expect("BUG: invalid test vectors");
+    assert!(vectors.len() >= 25);
+    let mut names: Vec<_> = vectors.iter().map(|vector| vector.name.clone()).collect();
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), vectors.len(), "vector names are not unique");
+
+    let failures = conformance::run_suite(&vectors).await;
+    let report: Vec<_> = failures.iter().map(ToString::to_string).collect();
+    assert!(failures.is_empty(), "{}", report.join("\n"));
+}
+
+#[tokio::test]
+async fn test_conformance_mismatch() {
+    // The runner reports outcomes that differ from the expected ones
+    let vectors = conformance::parse(
+        r#"[{
+            "name": "wrong_dispatch",
+            "session": {"init_difficulty": 0},
+            "steps": [
+                {"receive": {"type": "new_mining_job", "job_id": 1, "future_job": true}},
+                {"receive": {"type": "set_new_prev_hash", "job_id": 1},
+                 "expect": {"dispatched": [{"job_id": 2}]}}
+            ]
+        }]"#,
+    )
+    .expect("BUG: invalid test vectors");
+    let failures = conformance::run_suite(&vectors).await;
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].vector, "wrong_dispatch");
+    assert_eq!(failures[0].step, 1);
+}
+
+/// Subscriber that captures names and fields of the spans created by the client
+#[cfg(feature = "tracing-spans")]
+#[derive(Default)]
+struct SpanCapture {
+    spans: Arc<
+        StdMutex<
+            Vec<(
+                &'static str,
+                std::collections::BTreeMap<&'static str, String>,
+            )>,
+        >,
+    >,
+}
+
+#[cfg(feature = "tracing-spans")]
+struct SpanFields<'a>(&'a mut std::collections::BTreeMap<&'static str, String>);
+
+#[cfg(feature = "tracing-spans")]
+impl<'a> tracing::field::Visit for SpanFields<'a> {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name(), format!("{:?}", value));
+    }
+}
+
+#[cfg(feature = "tracing-spans")]
+impl tracing::Subscriber for SpanCapture {
+    fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attributes: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+        let mut spans = self.spans.lock().expect("BUG: cannot lock captured spans");
+        let mut fields = std::collections::BTreeMap::new();
+        attributes.record(&mut SpanFields(&mut fields));
+        spans.push((attributes.metadata().name(), fields));
+        tracing::span::Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+        let mut spans = self.spans.lock().expect("BUG: cannot lock captured spans");
+        let (_, fields) = &mut spans[span.into_u64() as usize - 1];
+        values.record(&mut SpanFields(fields));
+    }
+
+    fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+    fn event(&self, _event: &tracing::Event<'_>) {}
+
+    fn enter(&self, _span: &tracing::span::Id) {}
+
+    fn exit(&self, _span: &tracing::span::Id) {}
+}
+
+#[cfg(feature = "tracing-spans")]
+#[tokio::test]
+async fn test_tracing_spans() {
+    let capture = SpanCapture::default();
+    let spans = capture.spans.clone();
+    let dispatch = tracing::Dispatch::new(capture);
+
+    tracing::dispatcher::with_default(&dispatch, || {
+        let tracer = spans::Tracer::default();
+        tracer.connect_started("pool.example.com:3336");
+        tracer.connect_finished(connect_timing::Outcome::Ok);
+        tracer.open_channel_started();
+        tracer.open_channel_finished(Some(7), connect_timing::Outcome::Ok);
+        tracer.submitted(7, 3);
+        tracer.finished(3, Outcome::Accepted);
+        // Unknown shares are ignored
+        tracer.finished(4, Outcome::Stale);
+    });
+    let field = |value: &str| value.to_string();
+    assert_eq!(
+        *spans.lock().expect("BUG: cannot lock captured spans"),
+        vec![
+            (
+                "connect",
+                vec![
+                    ("outcome", field("ok")),
+                    ("pool", field("pool.example.com:3336"))
+                ]
+                .into_iter()
+                .collect()
+            ),
+            (
+                "open_channel",
+                vec![
+                    ("channel_id", field("7")),
+                    ("outcome", field("ok")),
+                    ("pool", field("pool.example.com:3336"))
+                ]
+                .into_iter()
+                .collect()
+            ),
+            (
+                "submit",
+                vec![
+                    ("channel_id", field("7")),
+                    ("outcome", field("accepted")),
+                    ("pool", field("pool.example.com:3336")),
+                    ("seq_num", field("3"))
+                ]
+                .into_iter()
+                .collect()
+            ),
+        ]
+    );
+
+    // Submissions of the client are traced
+    let client = build_client(Default::default());
+    let _event_handler = start_mining(&client).await;
+    let capture = SpanCapture::default();
+    let spans = capture.spans.clone();
+    let _guard = tracing::dispatcher::set_default(&tracing::Dispatch::new(capture));
+    client.spans.connect_started("pool.example.com:3336");
+    submit_solutions(&client, 2).await;
+    let submitted: Vec<_> = spans
+        .lock()
+        .expect("BUG: cannot lock captured spans")
+        .iter()
+        .filter(|(name, _)| *name == "submit")
+        .map(|(_, fields)| {
+            (
+                fields["pool"].clone(),
+                fields["channel_id"].clone(),
+                fields["seq_num"].clone(),
+            )
+        })
+        .collect();
+    let channel_id = client
+        .last_job()
+        .expect("BUG: no job has been dispatched")
+        .channel_id
+        .to_string();
+    assert_eq!(
+        submitted,
+        vec![
+            (
+                field("pool.example.com:3336"),
+                channel_id.clone(),
+                field("0")
+            ),
+            (field("pool.example.com:3336"), channel_id, field("1")),
+        ]
+    );
+}
+
+#[cfg(not(feature = "tracing-spans"))]
+#[test]
+fn test_tracing_spans_disabled() {
+    // Without the feature the hooks are the empty defaults of a zero-sized type
+    assert_eq!(std::mem::size_of::<spans::Tracing>(), 0);
+    let tracer: spans::Tracing = Default::default();
+    tracer.connect_started("pool.example.com:3336");
+    tracer.connect_finished(connect_timing::Outcome::Ok);
+    tracer.open_channel_started();
+    tracer.open_channel_finished(Some(7), connect_timing::Outcome::Ok);
+    tracer.submitted(7, 3);
+    tracer.finished(3, Outcome::Accepted);
+}
@@ -0,0 +1,1694 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+#[test]
+fn test_session_capture_format() {
+    let capture = build_session_capture();
+    let text = format!("# captured session\n\n{}", capture);
+    assert_eq!(
+        replay::Capture::parse(&text).expect("BUG: cannot parse capture"),
+        capture
+    );
+
+    assert!(replay::Capture::parse("10").is_err());
+    assert!(replay::Capture::parse("x 0000").is_err());
+    assert!(replay::Capture::parse("10 0g").is_err());
+    assert!(replay::Capture::parse("10 00 00").is_err());
+}
+
+#[tokio::test]
+async fn test_session_replay() {
+    let client = build_client(Default::default());
+    let capture = replay::Capture::parse(&build_session_capture().to_string())
+        .expect("BUG: cannot parse capture");
+
+    let sent_frames = replay::replay_session(client.clone(), &capture, false)
+        .await
+        .expect("BUG: replay failed");
+    let sent_msg_types: Vec<_> = sent_frames
+        .iter()
+        .map(|frame| frame.header.msg_type)
+        .collect();
+    assert_eq!(
+        sent_msg_types,
+        vec![
+            v2::messages::MessageType::SetupConnection as u8,
+            v2::messages::MessageType::OpenStandardMiningChannel as u8,
+        ]
+    );
+
+    let job = client.last_job().expect("BUG: no job has been received");
+    assert_eq!(job.id, 1);
+    assert_eq!(job.time, 0x5e000000);
+    // The job has been started before the target has changed
+    assert_eq!(job.target.get_difficulty(), 4);
+}
+
+#[tokio::test]
+async fn test_session_context() {
+    // Clamping the target requested in the middle of the session makes each session emit an event
+    let client = build_client(StratumV2Config {
+        max_difficulty: Some(8),
+        ..Default::default()
+    });
+    let capture = build_session_capture();
+
+    // Replaying the capture twice simulates a reconnect of the client
+    for expected_id in 1..=2 {
+        replay::replay_session(client.clone(), &capture, false)
+            .await
+            .expect("BUG: replay failed");
+
+        let context = client.context();
+        assert_eq!(context.connection_id, expected_id);
+        assert_eq!(context.session_id, expected_id);
+        assert_eq!(client.status_document().context, context);
+
+        let records = client.events();
+        let last_record = records.last().expect("BUG: no event has been recorded");
+        assert_eq!(last_record.context, context);
+    }
+
+    let records = client.events();
+    assert!(records
+        .iter()
+        .all(|record| record.context.client_id == records[0].context.client_id));
+    assert!(records.iter().any(|record| record.context.session_id == 1));
+}
+
+async fn replay_setup_connection(client: &Arc<StratumClient>) -> SetupConnection {
+    let sent_frames = replay::replay_session(client.clone(), &build_session_capture(), false)
+        .await
+        .expect("BUG: replay failed");
+    let frame = sent_frames
+        .into_iter()
+        .next()
+        .expect("BUG: no frame has been sent");
+    SetupConnection::try_from(frame).expect("BUG: cannot decode setup connection")
+}
+
+#[tokio::test]
+async fn test_endpoint_identity() {
+    // The dial target is advertised by default
+    let client = build_client(Default::default());
+    let setup_msg = replay_setup_connection(&client).await;
+    assert_eq!(setup_msg.endpoint_host, Str0_255::from_str("localhost"));
+    assert_eq!(setup_msg.endpoint_port, 3336);
+
+    let config = StratumV2Config {
+        endpoint_host: Some("pool.example.com".to_string()),
+        endpoint_port: Some(443),
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+    let client = build_client(config);
+    let setup_msg = replay_setup_connection(&client).await;
+    assert_eq!(
+        setup_msg.endpoint_host,
+        Str0_255::from_str("pool.example.com")
+    );
+    assert_eq!(setup_msg.endpoint_port, 443);
+    // Advertised values don't affect the dial target
+    assert_eq!(
+        client.connection_details().get_host_and_port(),
+        "localhost:3336"
+    );
+
+    // Only the port may be overridden
+    let client = build_client(StratumV2Config {
+        endpoint_port: Some(3337),
+        ..Default::default()
+    });
+    let setup_msg = replay_setup_connection(&client).await;
+    assert_eq!(setup_msg.endpoint_host, Str0_255::from_str("localhost"));
+    assert_eq!(setup_msg.endpoint_port, 3337);
+}
+
+#[tokio::test]
+async fn test_negotiated_parameters() {
+    let client = build_client(StratumV2Config {
+        endpoint_host: Some("pool.example.com".to_string()),
+        ..Default::default()
+    });
+    assert!(client.negotiated().is_none());
+    assert!(client.status_document().negotiated.is_none());
+
+    replay::replay_session(client.clone(), &build_session_capture(), false)
+        .await
+        .expect("BUG: replay failed");
+    let negotiated = status::Negotiated {
+        used_version: 2,
+        flags: 0,
+        encrypted: false,
+        endpoint_host: "pool.example.com".to_string(),
+        endpoint_port: 3336,
+    };
+    assert_eq!(client.negotiated(), Some(negotiated.clone()));
+    assert_eq!(client.status_document().negotiated, Some(negotiated));
+}
+
+#[test]
+fn test_endpoint_identity_validation() {
+    let config = |endpoint_host: String| StratumV2Config {
+        endpoint_host: Some(endpoint_host),
+        ..Default::default()
+    };
+    let max_length = StratumV2Config::MAX_ENDPOINT_HOST_LENGTH;
+    assert!(config("h".repeat(max_length)).validate().is_ok());
+    assert!(config("h".repeat(max_length + 1)).validate().is_err());
+    assert!(config(String::new()).validate().is_err());
+}
+
+fn set_user(client: &Arc<StratumClient>, user: &str) {
+    client
+        .connection_details
+        .lock()
+        .expect("BUG: cannot lock connection details")
+        .user = user.to_string();
+}
+
+#[tokio::test]
+async fn test_handshake_transcript() {
+    let client = build_client(Default::default());
+    set_user(&client, "operator.rig1");
+    let capture = build_rejected_session_capture();
+
+    assert!(client.last_handshake_transcript().is_none());
+    assert!(replay::replay_session(client.clone(), &capture, false)
+        .await
+        .is_err());
+
+    let transcript = client
+        .last_handshake_transcript()
+        .expect("BUG: missing transcript of failed handshake");
+    assert_eq!(transcript.dropped, 0);
+    let directions: Vec<_> = transcript
+        .entries
+        .iter()
+        .map(|entry| entry.direction)
+        .collect();
+    assert_eq!(
+        directions,
+        vec![
+            transcript::Direction::Sent,
+            transcript::Direction::Received,
+            transcript::Direction::Sent,
+            transcript::Direction::Received,
+        ]
+    );
+    assert!(transcript
+        .entries
+        .windows(2)
+        .all(|entries| entries[0].time <= entries[1].time));
+    assert!(transcript.entries[0]
+        .summary
+        .starts_with("SetupConnection "));
+    assert!(transcript.entries[1]
+        .summary
+        .starts_with("SetupConnectionSuccess "));
+    assert!(transcript.entries[2]
+        .summary
+        .starts_with("OpenStandardMiningChannel "));
+    assert!(transcript.entries[3].summary.contains("unknown-user"));
+
+    // Received frames are recorded exactly as they have arrived
+    assert_eq!(
+        transcript.entries[1].bytes,
+        Some(hex::encode(&capture.records[0].bytes))
+    );
+    assert_eq!(
+        transcript.entries[3].bytes,
+        Some(hex::encode(&capture.records[1].bytes))
+    );
+
+    // The user name is redacted and the frame that carries it is recorded without raw bytes
+    assert!(transcript
+        .entries
+        .iter()
+        .all(|entry| !entry.summary.contains("operator.rig1")));
+    assert!(transcript.entries[2].summary.contains(transcript::REDACTED));
+    assert_eq!(transcript.entries[2].bytes, None);
+
+    // The transcript is part of the verbose status document only
+    assert!(client.status_document().handshake_transcript.is_none());
+    assert_eq!(
+        client.verbose_status_document().handshake_transcript,
+        Some(transcript.clone())
+    );
+
+    // Successful handshake doesn't replace the transcript of the failed one
+    replay::replay_session(client.clone(), &build_session_capture(), false)
+        .await
+        .expect("BUG: replay failed");
+    assert_eq!(client.last_handshake_transcript(), Some(transcript));
+}
+
+#[tokio::test]
+async fn test_handshake_transcript_raw_bytes() {
+    let client = build_client(StratumV2Config {
+        handshake_transcript_raw_bytes: Some(true),
+        ..Default::default()
+    });
+    set_user(&client, "operator.rig1");
+    assert!(
+        replay::replay_session(client.clone(), &build_rejected_session_capture(), false)
+            .await
+            .is_err()
+    );
+
+    let transcript = client
+        .last_handshake_transcript()
+        .expect("BUG: missing transcript of failed handshake");
+    let bytes = transcript.entries[2]
+        .bytes
+        .as_ref()
+        .expect("BUG: missing raw bytes");
+    assert!(bytes.contains(&hex::encode("operator.rig1")));
+    assert!(!transcript.entries[2].summary.contains("operator.rig1"));
+}
+
+#[tokio::test]
+async fn test_handshake_transcript_discard() {
+    let client = build_client(Default::default());
+    replay::replay_session(client.clone(), &build_session_capture(), false)
+        .await
+        .expect("BUG: replay failed");
+    assert!(client.last_handshake_transcript().is_none());
+}
+
+#[test]
+fn test_handshake_transcript_limits() {
+    let mut recorder = transcript::Recorder::new("user".to_string(), false);
+    let now = time::SystemTime::now();
+    for _ in 0..transcript::MAX_ENTRIES {
+        recorder.record(
+            transcript::Direction::Sent,
+            &[0; transcript::MAX_FRAME_BYTES + 1],
+            "sent".to_string(),
+            now,
+        );
+    }
+    recorder.record(
+        transcript::Direction::Received,
+        &[0; 8],
+        "received".to_string(),
+        now,
+    );
+    // Dropped frame cannot be described
+    recorder.describe_received("decoded".to_string());
+
+    let transcript = recorder.finish();
+    assert_eq!(transcript.entries.len(), transcript::MAX_ENTRIES);
+    assert_eq!(transcript.dropped, 1);
+    let entry = transcript.entries.last().expect("BUG: empty transcript");
+    assert_eq!(entry.summary, "sent");
+    assert!(entry.truncated);
+    assert_eq!(
+        entry.bytes.as_ref().map(String::len),
+        Some(2 * transcript::MAX_FRAME_BYTES)
+    );
+}
+
+fn user_file_config(path: &std::path::Path) -> StratumV2Config {
+    StratumV2Config {
+        user_file: Some(path.to_path_buf()),
+        ..Default::default()
+    }
+}
+
+/// Replay a session and return the user of the opened channel
+async fn replay_channel_user(client: &Arc<StratumClient>) -> String {
+    replay_open_channel(client).await.user.to_string()
+}
+
+#[tokio::test]
+async fn test_worker_name_hook() {
+    let client = build_client(Default::default());
+    assert_eq!(replay_channel_user(&client).await, "user");
+
+    client.set_worker_name_hook(Some(WorkerNameHook::new(|user| {
+        format!("farm7.{}", user.to_uppercase())
+    })));
+    assert_eq!(replay_channel_user(&client).await, "farm7.USER");
+
+    // The transformed name must fit into the open channel message
+    client.set_worker_name_hook(Some(WorkerNameHook::new(|user| user.repeat(100))));
+    assert!(
+        replay::replay_session(client.clone(), &build_session_capture(), false)
+            .await
+            .is_err()
+    );
+
+    client.set_worker_name_hook(None);
+    assert_eq!(replay_channel_user(&client).await, "user");
+}
+
+#[tokio::test]
+async fn test_user_file() {
+    let path = user_file_path("user-file");
+    std::fs::write(&path, "  account.token-a\n").expect("BUG: cannot write user file");
+    let config = user_file_config(&path);
+    assert!(config.validate().is_ok());
+
+    let client = build_client(config);
+    assert_eq!(replay_channel_user(&client).await, "account.token-a");
+
+    // The status shows only the path and the time of the last read
+    let status = client
+        .status_document()
+        .user_file
+        .expect("BUG: missing user file status");
+    assert_eq!(status.path, path);
+    assert!(status.last_read.is_some());
+    assert!(!format!("{:?}", client.status_document()).contains("account.token-a"));
+    assert!(!format!("{:?}", client.connection_details()).contains("account.token-a"));
+    assert!(!client.to_string().contains("account.token-a"));
+
+    // Rotation is detected and the channel is opened again with the new user
+    let context = client.context();
+    assert!(client.poll_user_file(context).is_ok());
+    std::fs::write(&path, "account.token-b2\n").expect("BUG: cannot write user file");
+    assert!(client.poll_user_file(context).is_err());
+    assert_eq!(replay_channel_user(&client).await, "account.token-b2");
+    assert!(client.poll_user_file(client.context()).is_ok());
+
+    std::fs::remove_file(&path).expect("BUG: cannot remove user file");
+}
+
+#[tokio::test]
+async fn test_user_file_removal() {
+    let path = user_file_path("user-file-removal");
+    std::fs::write(&path, "account.token").expect("BUG: cannot write user file");
+    let client = build_client(user_file_config(&path));
+    assert_eq!(replay_channel_user(&client).await, "account.token");
+    let last_read = client
+        .status_document()
+        .user_file
+        .and_then(|status| status.last_read);
+
+    // The current session is kept alive when the file disappears
+    std::fs::remove_file(&path).expect("BUG: cannot remove user file");
+    assert!(client.poll_user_file(client.context()).is_ok());
+    assert!(client.poll_user_file(client.context()).is_ok());
+
+    // New session uses the previously read user
+    assert_eq!(replay_channel_user(&client).await, "account.token");
+    assert_eq!(
+        client
+            .status_document()
+            .user_file
+            .and_then(|status| status.last_read),
+        last_read
+    );
+}
+
+#[tokio::test]
+async fn test_user_file_unreadable() {
+    let path = user_file_path("user-file-missing");
+    let config = user_file_config(&path);
+    let e = config.validate().expect_err("BUG: missing file accepted");
+    assert!(e.to_string().contains(&path.display().to_string()));
+
+    // Session cannot be started without the user
+    let client = build_client(config);
+    assert!(
+        replay::replay_session(client.clone(), &build_session_capture(), false)
+            .await
+            .is_err()
+    );
+
+    // Files without a valid user are rejected as well
+    for content in &["", " \n", "user\u{7}"] {
+        std::fs::write(&path, content).expect("BUG: cannot write user file");
+        assert!(user_file_config(&path).validate().is_err());
+    }
+    std::fs::remove_file(&path).expect("BUG: cannot remove user file");
+}
+
+/// Build a capture of a session that fails to open the channel with `error_code` first and
+/// succeeds on the next attempt. Frames of `interleaved` are sent by the pool in between the
+/// attempts.
+fn build_channel_retry_capture(
+    error_code: &str,
+    interleaved: Vec<<Framing as ii_wire::Framing>::Rx>,
+) -> replay::Capture {
+    let mut capture = replay::Capture::new();
+    let mut push = |frame| {
+        capture
+            .push(time::Duration::from_millis(0), frame)
+            .expect("BUG: cannot capture frame")
+    };
+    push(
+        SetupConnectionSuccess {
+            used_version: 2,
+            flags: 0,
+        }
+        .try_into()
+        .expect("BUG: cannot build frame"),
+    );
+    push(
+        OpenStandardMiningChannelError {
+            req_id: 10,
+            code: Str0_32::from_str(error_code),
+        }
+        .try_into()
+        .expect("BUG: cannot build frame"),
+    );
+    for frame in interleaved {
+        push(frame);
+    }
+    // Rest of the successful session starts with the open channel response
+    for record in build_session_capture().records.into_iter().skip(1) {
+        capture.records.push(record);
+    }
+    capture
+}
+
+#[tokio::test]
+async fn test_channel_open_retry() {
+    let config = StratumV2Config {
+        channel_open_backoff: Some(vec![0, 0]),
+        ..Default::default()
+    };
+
+    // Transient failure is recovered on the same connection
+    let client = build_client(config.clone());
+    let capture = build_channel_retry_capture("temporarily-unavailable", vec![]);
+    let sent_frames = replay::replay_session(client.clone(), &capture, false)
+        .await
+        .expect("BUG: replay failed");
+    let sent_msg_types: Vec<_> = sent_frames
+        .iter()
+        .map(|frame| frame.header.msg_type)
+        .collect();
+    assert_eq!(
+        sent_msg_types,
+        vec![
+            v2::messages::MessageType::SetupConnection as u8,
+            v2::messages::MessageType::OpenStandardMiningChannel as u8,
+            v2::messages::MessageType::OpenStandardMiningChannel as u8,
+        ]
+    );
+    assert_eq!(
+        client.retries(),
+        status::Retries {
+            connection: 0,
+            channel_open: 1,
+            handshake_reset: 0,
+        }
+    );
+    assert_eq!(client.status_document().retries, client.retries());
+    let job = client.last_job().expect("BUG: no job has been received");
+    assert_eq!(job.id, 1);
+
+    // Permanent failure is reported right away without any retry
+    let client = build_client(config);
+    assert!(
+        replay::replay_session(client.clone(), &build_rejected_session_capture(), false)
+            .await
+            .is_err()
+    );
+    assert_eq!(client.retries(), Default::default());
+    let transcript = client
+        .last_handshake_transcript()
+        .expect("BUG: missing transcript of failed handshake");
+    assert_eq!(transcript.entries.len(), 4);
+
+    // Retries are disabled with empty backoff
+    let client = build_client(StratumV2Config {
+        channel_open_backoff: Some(vec![]),
+        ..Default::default()
+    });
+    assert!(replay::replay_session(client.clone(), &capture, false)
+        .await
+        .is_err());
+    assert_eq!(client.retries(), Default::default());
+}
+
+#[tokio::test]
+async fn test_handshake_connection_reset() {
+    let client = build_client(Default::default());
+    let is_reset = |e: &error::Error| match e.kind() {
+        error::ErrorKind::Client(error::Client::ConnectionReset(_)) => true,
+        _ => false,
+    };
+
+    // The pool accepts the connection and closes it right away
+    let e = replay::replay_session(client.clone(), &replay::Capture::new(), false)
+        .await
+        .err()
+        .expect("BUG: handshake succeeded");
+    assert!(is_reset(&e), "{}", e);
+
+    // The pool closes the connection while the channel is being opened
+    let mut capture = build_session_capture();
+    capture.records.truncate(1);
+    let e = replay::replay_session(client.clone(), &capture, false)
+        .await
+        .err()
+        .expect("BUG: handshake succeeded");
+    assert!(is_reset(&e), "{}", e);
+    assert_eq!(client.retries().handshake_reset, 2);
+    assert!(client.negotiated().is_none());
+
+    // The pool resets the connection, only failures of the connection are transient
+    let e = StratumConnectionHandler::receive_error(
+        std::io::Error::from(std::io::ErrorKind::ConnectionReset).into(),
+    );
+    assert!(is_reset(&e), "{}", e);
+    let e = StratumConnectionHandler::receive_error(
+        ii_stratum::error::ErrorKind::Serde("invalid message".to_string()).into(),
+    );
+    assert!(!is_reset(&e), "{}", e);
+}
+
+#[tokio::test]
+async fn test_channel_open_retry_buffered_frames() {
+    let client = build_client(StratumV2Config {
+        channel_open_backoff: Some(vec![0]),
+        ..Default::default()
+    });
+    // The pool sends a future job before the channel is opened again
+    let capture = build_channel_retry_capture(
+        "temporarily-unavailable",
+        vec![NewMiningJob {
+            channel_id: 0,
+            job_id: 7,
+            future_job: true,
+            version: 0x20000000,
+            merkle_root: Uint256Bytes([0xcc; 32]),
+        }
+        .try_into()
+        .expect("BUG: cannot build frame")],
+    );
+    replay::replay_session(client.clone(), &capture, false)
+        .await
+        .expect("BUG: replay failed");
+    assert_eq!(client.retries().channel_open, 1);
+    // The buffered job has been handed over to the event handler and the session continues
+    let job = client.last_job().expect("BUG: no job has been received");
+    assert_eq!(job.id, 1);
+    assert_eq!(job.time, 0x5e000000);
+
+    // Too many frames before the channel is opened fail the handshake
+    let client = build_client(StratumV2Config {
+        channel_open_backoff: Some(vec![0]),
+        ..Default::default()
+    });
+    let frames = (0..=StratumConnectionHandler::MAX_BUFFERED_FRAMES)
+        .map(|_| {
+            SetTarget {
+                channel_id: 0,
+                max_target: ii_bitcoin::Target::from_pool_difficulty(16).into(),
+            }
+            .try_into()
+            .expect("BUG: cannot build frame")
+        })
+        .collect();
+    let capture = build_channel_retry_capture("temporarily-unavailable", frames);
+    assert!(replay::replay_session(client.clone(), &capture, false)
+        .await
+        .is_err());
+    assert!(client.last_job().is_none());
+}
+
+/// Submit solution of the last job dispatched by the client and return the share sent to the pool
+async fn submit_last_job(client: &Arc<StratumClient>) -> SubmitSharesStandard {
+    let (connection_tx, mut connection_rx) = mpsc::unbounded();
+    let mut solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+    let solution = build_solution(client).await;
+    assert!(solution_handler.submit_solution(solution).await.is_ok());
+    let frame = connection_rx
+        .try_next()
+        .expect("BUG: no frame has been sent")
+        .expect("BUG: connection closed");
+    let mut share_collector = ShareCollector::default();
+    v2::build_message_from_frame(frame)
+        .expect("BUG: cannot build message")
+        .accept(&mut share_collector)
+        .await;
+    share_collector.shares.pop().expect("BUG: no share sent")
+}
+
+#[tokio::test]
+async fn test_session_state_after_reconnect() {
+    let client = build_client(Default::default());
+    assert!(client.session().is_none());
+
+    // The first incarnation of the pool disables version rolling
+    replay::replay_session(
+        client.clone(),
+        &build_negotiated_capture(capabilities::REQUIRES_FIXED_VERSION, 7),
+        false,
+    )
+    .await
+    .expect("BUG: replay failed");
+    let session = client.session().expect("BUG: no session");
+    assert_eq!(
+        session.negotiated.flags,
+        capabilities::REQUIRES_FIXED_VERSION
+    );
+    assert_eq!(session.channel.channel_id, 7);
+    assert_eq!(session.extranonce_prefix, vec![7; 4]);
+    assert_eq!(
+        client.extranonce_prefix(),
+        Some(session::ExtranoncePrefix {
+            channel_id: 7,
+            size: 4,
+            prefix: vec![7; 4],
+        })
+    );
+    assert_eq!(session.version_mask, 0);
+    let job = client.last_job().expect("BUG: no job has been received");
+    assert_eq!(job::Bitcoin::version_mask(job.as_ref()), 0);
+    assert_eq!(submit_last_job(&client).await.channel_id, 7);
+
+    // The pool is redeployed and negotiates different parameters after reconnect
+    replay::replay_session(client.clone(), &build_negotiated_capture(0, 9), false)
+        .await
+        .expect("BUG: replay failed");
+    let new_session = client.session().expect("BUG: no session");
+    assert!(!Arc::ptr_eq(&session, &new_session));
+    assert_eq!(new_session.negotiated.flags, 0);
+    assert_eq!(new_session.channel.channel_id, 9);
+    assert_eq!(new_session.extranonce_prefix, vec![9; 4]);
+    assert_eq!(
+        client
+            .extranonce_prefix()
+            .map(|extranonce_prefix| extranonce_prefix.channel_id),
+        Some(9)
+    );
+    assert_eq!(new_session.version_mask, VERSION_MASK);
+    assert!(client
+        .active_capabilities()
+        .contains(&capabilities::VERSION_ROLLING));
+    let job = client.last_job().expect("BUG: no job has been received");
+    assert_eq!(job::Bitcoin::version_mask(job.as_ref()), VERSION_MASK);
+    assert_eq!(submit_last_job(&client).await.channel_id, 9);
+
+    // Failed handshake leaves no session behind
+    let mut capture = build_negotiated_capture(capabilities::REQUIRES_FIXED_VERSION, 11);
+    capture.records.truncate(1);
+    assert!(replay::replay_session(client.clone(), &capture, false)
+        .await
+        .is_err());
+    assert!(client.session().is_none());
+    assert!(client.negotiated().is_none());
+    assert!(client.extranonce_prefix().is_none());
+
+    // The pool assigns no prefix to the channel
+    replay::replay_session(client.clone(), &build_session_capture(), false)
+        .await
+        .expect("BUG: replay failed");
+    assert!(client.session().is_some());
+    assert!(client.extranonce_prefix().is_none());
+}
+
+fn set_connection_details(client: &Arc<StratumClient>, connection_details: ConnectionDetails) {
+    *client
+        .connection_details
+        .lock()
+        .expect("BUG: cannot lock connection details") = connection_details;
+}
+
+#[tokio::test]
+async fn test_user_redaction() {
+    let build_details = |user_redaction| ConnectionDetails {
+        protocol: ClientProtocol::StratumV2Insecure,
+        user: "account.worker1".to_string(),
+        host: "localhost".to_string(),
+        port: 3336,
+        config: Arc::new(StratumV2Config {
+            user_redaction,
+            ..Default::default()
+        }),
+    };
+    let client = build_client(Default::default());
+
+    let details = build_details(None);
+    assert_eq!(details.display_user(), "account.worker1");
+    set_connection_details(&client, details);
+    let client_id = client.client_id();
+    assert!(client.to_string().ends_with("@account.worker1"));
+
+    let details = build_details(Some(StratumV2UserRedaction::Prefix));
+    assert_eq!(details.display_user(), "acco***");
+    assert!(!format!("{:?}", details).contains("account.worker1"));
+
+    let details = build_details(Some(StratumV2UserRedaction::Hash));
+    let redacted = details.display_user();
+    assert_eq!(redacted.len(), 10);
+    assert!(!redacted.contains("account"));
+    // The same user always maps to the same hash
+    assert_eq!(
+        redacted,
+        build_details(Some(StratumV2UserRedaction::Hash)).display_user()
+    );
+    set_connection_details(&client, details);
+    assert!(!client.to_string().contains("account.worker1"));
+    // Redaction doesn't change the identity of the client
+    assert_eq!(client.client_id(), client_id);
+}
+
+#[test]
+fn test_startup_delay() {
+    // No delay by default
+    let client = build_client(Default::default());
+    assert_eq!(client.startup_delay(), time::Duration::from_secs(0));
+
+    let client = build_client(StratumV2Config {
+        startup_delay: Some(2000),
+        ..Default::default()
+    });
+    assert_eq!(client.startup_delay(), time::Duration::from_millis(2000));
+
+    // The random part is drawn again for every start and it is bounded
+    let client = build_client(StratumV2Config {
+        startup_delay: Some(2000),
+        startup_jitter: Some(1000),
+        ..Default::default()
+    });
+    let delays: Vec<_> = (0..32).map(|_| client.startup_delay()).collect();
+    assert!(delays
+        .iter()
+        .all(|delay| *delay >= time::Duration::from_millis(2000)
+            && *delay <= time::Duration::from_millis(3000)));
+    assert!(delays.iter().any(|delay| *delay != delays[0]));
+}
+
+#[tokio::test]
+async fn test_unknown_pool_flags() {
+    let flags = capabilities::REQUIRES_FIXED_VERSION | 0x80;
+    // Unknown flags are ignored by default
+    let client = build_client(Default::default());
+    replay::replay_session(client.clone(), &build_negotiated_capture(flags, 7), false)
+        .await
+        .expect("BUG: replay failed");
+    let session = client.session().expect("BUG: no session");
+    assert_eq!(session.negotiated.flags, flags);
+    assert_eq!(session.version_mask, 0);
+
+    let client = build_client(StratumV2Config {
+        unknown_pool_flags: Some(UnknownPoolFlags::Reject),
+        ..Default::default()
+    });
+    let e = replay::replay_session(client.clone(), &build_negotiated_capture(flags, 7), false)
+        .await
+        .err()
+        .expect("BUG: handshake with unknown flags succeeded");
+    assert_eq!(e.error_code(), "stratum.handshake.unknown_flags");
+    assert!(client.session().is_none());
+
+    // Known flags pass in the strict mode
+    replay::replay_session(
+        client.clone(),
+        &build_negotiated_capture(capabilities::REQUIRES_FIXED_VERSION, 7),
+        false,
+    )
+    .await
+    .expect("BUG: replay failed");
+    assert!(client.session().is_some());
+}
+
+/// Duration of a session cut short by the restarting pool
+const RESTART_STORM_SESSION: time::Duration = time::Duration::from_secs(2);
+
+/// Connection attempt at `now` that fails after a short session, returns the time of the next
+/// attempt
+fn restart_storm_cycle(client: &Arc<StratumClient>, now: time::Instant) -> time::Instant {
+    let context = client.context();
+    client.lock_restart_storm().attempt_started(now);
+    let failed = now + RESTART_STORM_SESSION;
+    client.record_failure(
+        context,
+        &error::Client::ConnectFailed("connection refused".to_string()).into(),
+    );
+    client.restart_storm_session_failed_at(context, failed);
+    failed + client.reconnect_delay_at(failed).unwrap_or_default()
+}
+
+#[tokio::test]
+async fn test_restart_storm() {
+    assert!(StratumV2Config {
+        restart_storm: Some(StratumV2RestartStorm {
+            sessions: Some(1),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+    .validate()
+    .is_err());
+    assert!(StratumV2Config {
+        restart_storm: Some(StratumV2RestartStorm {
+            retry_interval: Some(0),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+    .validate()
+    .is_err());
+
+    let client = build_client(StratumV2Config {
+        restart_storm: Some(Default::default()),
+        ..Default::default()
+    });
+    let retry_interval = time::Duration::from_secs(StratumV2RestartStorm::DEFAULT_RETRY_INTERVAL);
+    let session = RESTART_STORM_SESSION;
+
+    // A storm of 6 cycles, the client reconnects right away until the storm is detected
+    let start = time::Instant::now();
+    let mut attempts = vec![start];
+    for _ in 0..6 {
+        let next = restart_storm_cycle(&client, *attempts.last().unwrap());
+        attempts.push(next);
+    }
+    let intervals: Vec<_> = attempts
+        .windows(2)
+        .map(|attempts| attempts[1] - attempts[0])
+        .collect();
+    assert_eq!(
+        intervals,
+        vec![
+            session,
+            session,
+            session + retry_interval,
+            session + retry_interval,
+            session + retry_interval,
+            session + retry_interval,
+        ]
+    );
+    let status = client
+        .restart_storm_at(*attempts.last().unwrap())
+        .expect("BUG: storm has not been detected");
+    assert_eq!(status.cycles, 6);
+    // The scheduler doesn't fail over to another pool during the storm
+    assert!(node::Client::holds_failover(&*client));
+
+    // The pool recovers, the storm is summarized by a single event
+    let now = *attempts.last().unwrap();
+    client.lock_restart_storm().attempt_started(now);
+    assert_eq!(
+        client.restart_storm_recovery_delay(now),
+        Some(time::Duration::from_secs(
+            StratumV2RestartStorm::DEFAULT_SHORT_SESSION
+        ))
+    );
+    client.restart_storm_session_survived_at(
+        client.context(),
+        now + time::Duration::from_secs(StratumV2RestartStorm::DEFAULT_SHORT_SESSION),
+    );
+    assert!(client.restart_storm_at(now).is_none());
+    assert!(client.reconnect_delay_at(now).is_none());
+    assert!(!node::Client::holds_failover(&*client));
+    assert_eq!(
+        event_kinds(&client),
+        vec!["failure", "failure", "failure", "pool_restart"]
+    );
+    match client.events().pop().map(|record| record.event) {
+        Some(events::Event::PoolRestart(summary)) => {
+            assert_eq!(summary.cycles, 6);
+            assert_eq!(summary.outcome, restart_storm::Outcome::Recovered);
+        }
+        event => panic!("BUG: unexpected event {:?}", event),
+    }
+
+    // A storm that outlasts the bound falls back to normal failure handling
+    let client = build_client(StratumV2Config {
+        restart_storm: Some(Default::default()),
+        ..Default::default()
+    });
+    let max_duration = time::Duration::from_secs(StratumV2RestartStorm::DEFAULT_MAX_DURATION);
+    let mut now = time::Instant::now();
+    for _ in 0..3 {
+        now = restart_storm_cycle(&client, now);
+    }
+    let storm_start = now - retry_interval;
+    while now < storm_start + max_duration {
+        assert!(client.restart_storm_at(now).is_some());
+        now = restart_storm_cycle(&client, now);
+    }
+    assert!(client.restart_storm_at(now).is_none());
+    assert!(client.reconnect_delay_at(now).is_none());
+    assert!(!node::Client::holds_failover(&*client));
+    match client.events().pop().map(|record| record.event) {
+        Some(events::Event::PoolRestart(summary)) => {
+            assert_eq!(summary.outcome, restart_storm::Outcome::Exceeded);
+        }
+        event => panic!("BUG: unexpected event {:?}", event),
+    }
+    // Further short sessions are reported as failures and don't start another storm until the
+    // pool recovers
+    for _ in 0..3 {
+        now = restart_storm_cycle(&client, now);
+    }
+    assert!(client.restart_storm_at(now).is_none());
+    let kinds = event_kinds(&client);
+    assert_eq!(kinds[kinds.len() - 3..], ["failure", "failure", "failure"]);
+}
+
+/// Clock of the connection timing that is moved by the test
+#[derive(Debug)]
+struct ManualClock {
+    start: time::Instant,
+    offset: StdMutex<time::Duration>,
+}
+
+impl ManualClock {
+    fn new() -> Self {
+        Self {
+            start: time::Instant::now(),
+            offset: StdMutex::new(Default::default()),
+        }
+    }
+
+    fn set(&self, offset: time::Duration) {
+        *self.offset.lock().expect("BUG: cannot lock clock") = offset;
+    }
+
+    fn advance(&self, millis: u64) {
+        *self.offset.lock().expect("BUG: cannot lock clock") += time::Duration::from_millis(millis);
+    }
+}
+
+impl connect_timing::Clock for ManualClock {
+    fn now(&self) -> time::Instant {
+        self.start + *self.offset.lock().expect("BUG: cannot lock clock")
+    }
+}
+
+fn connect_phases(
+    attempt: &connect_timing::Attempt,
+) -> Vec<(connect_timing::Phase, u64, connect_timing::Outcome)> {
+    attempt
+        .phases
+        .iter()
+        .map(|timing| (timing.phase, timing.duration_ms, timing.outcome))
+        .collect()
+}
+
+/// Time a scripted phase of `timer` that takes `millis`
+fn script_phase(
+    timer: &mut connect_timing::Timer,
+    clock: &ManualClock,
+    phase: connect_timing::Phase,
+    address: Option<&str>,
+    millis: u64,
+    outcome: connect_timing::Outcome,
+) {
+    timer.start(phase, address.map(str::to_string));
+    clock.advance(millis);
+    timer.end(outcome);
+}
+
+#[tokio::test]
+async fn test_connect_timing_slow_channel_open() {
+    use connect_timing::{Outcome, Phase};
+
+    let client = build_client(Default::default());
+    let clock = Arc::new(ManualClock::new());
+    // The pool responds to `SetupConnection` after 40ms, it takes 5s to open the channel and
+    // the first job arrives 100ms later
+    let mut records = build_negotiated_capture(capabilities::REQUIRES_FIXED_VERSION, 7).records;
+    for (index, record) in records.iter_mut().enumerate() {
+        record.offset = time::Duration::from_millis(match index {
+            0 => 40,
+            1 => 5040,
+            _ => 5140,
+        });
+    }
+    let stream_clock = clock.clone();
+    let mut connection_rx = stream::iter(records)
+        .map(move |record| {
+            stream_clock.set(record.offset);
+            record
+                .into_frame()
+                .map_err(|e| ii_stratum::error::ErrorKind::General(e.to_string()).into())
+        })
+        .boxed();
+    let connection_tx = Arc::new(Mutex::new(replay::FrameCollector::default()));
+
+    let context = client.new_connection_context();
+    client
+        .refresh_user(context)
+        .expect("BUG: cannot refresh user");
+    let timer = connect_timing::Timer::builder()
+        .clock(clock.clone())
+        .build();
+    let (session, context, buffered_frames, first_job) =
+        StratumConnectionHandler::new(client.clone(), context)
+            .with_timer(timer)
+            .init_mining_session(&mut connection_rx, connection_tx)
+            .await
+            .expect("BUG: handshake failed");
+    let mut event_handler = StratumEventHandler::new(client.clone(), session, context);
+    event_handler.first_job = Some(first_job);
+    for frame in buffered_frames {
+        client
+            .handle_frame(frame, &mut event_handler)
+            .await
+            .expect("BUG: cannot handle frame");
+    }
+    while let Some(frame) = connection_rx.next().await {
+        client
+            .handle_frame(frame.expect("BUG: invalid frame"), &mut event_handler)
+            .await
+            .expect("BUG: cannot handle frame");
+    }
+    assert!(client.last_job().is_some());
+
+    let history = client.connection_history();
+    assert_eq!(history.len(), 1);
+    assert_eq!(
+        connect_phases(&history[0]),
+        vec![
+            (Phase::SetupConnection, 40, Outcome::Ok),
+            (Phase::ChannelOpen, 5000, Outcome::Ok),
+            (Phase::FirstJob, 100, Outcome::Ok),
+        ]
+    );
+    assert_eq!(history[0].failed_phase, None);
+}
+
+#[tokio::test]
+async fn test_connect_timing_slow_dns() {
+    use connect_timing::{Outcome, Phase};
+
+    let client = build_client(Default::default());
+    let clock = Arc::new(ManualClock::new());
+    for &dns in &[20, 30, 3000] {
+        let mut timer = connect_timing::Timer::builder()
+            .clock(clock.clone())
+            .build();
+        script_phase(&mut timer, &clock, Phase::Dns, None, dns, Outcome::Ok);
+        // The first address is not reachable
+        script_phase(
+            &mut timer,
+            &clock,
+            Phase::TcpConnect,
+            Some("192.0.2.1:3336"),
+            15,
+            Outcome::Failed,
+        );
+        script_phase(
+            &mut timer,
+            &clock,
+            Phase::TcpConnect,
+            Some("192.0.2.2:3336"),
+            10,
+            Outcome::Ok,
+        );
+        script_phase(
+            &mut timer,
+            &clock,
+            Phase::SetupConnection,
+            None,
+            40,
+            Outcome::Ok,
+        );
+        script_phase(
+            &mut timer,
+            &clock,
+            Phase::ChannelOpen,
+            None,
+            50,
+            Outcome::Ok,
+        );
+        client.lock_connect_history().push(timer.finish());
+    }
+
+    let history = client.connection_history();
+    assert_eq!(
+        connect_phases(&history[2]),
+        vec![
+            (Phase::Dns, 3000, Outcome::Ok),
+            (Phase::TcpConnect, 15, Outcome::Failed),
+            (Phase::TcpConnect, 10, Outcome::Ok),
+            (Phase::SetupConnection, 40, Outcome::Ok),
+            (Phase::ChannelOpen, 50, Outcome::Ok),
+        ]
+    );
+    let addresses: Vec<_> = history[2]
+        .phases
+        .iter()
+        .filter_map(|timing| timing.address.as_deref())
+        .collect();
+    assert_eq!(addresses, vec!["192.0.2.1:3336", "192.0.2.2:3336"]);
+    // A failed address doesn't fail the attempt
+    assert_eq!(history[2].failed_phase, None);
+
+    // Aggregations are computed on demand for the status document
+    let summary = client.status_document().connect_phases;
+    let dns = summary
+        .iter()
+        .find(|summary| summary.phase == Phase::Dns)
+        .expect("BUG: missing DNS phase");
+    assert_eq!((dns.count, dns.median_ms, dns.p95_ms), (3, 30, 3000));
+    let tcp_connect = summary
+        .iter()
+        .find(|summary| summary.phase == Phase::TcpConnect)
+        .expect("BUG: missing TCP connect phase");
+    assert_eq!(
+        (tcp_connect.count, tcp_connect.failed, tcp_connect.median_ms),
+        (3, 3, 10)
+    );
+}
+
+#[tokio::test]
+async fn test_connect_timing_failed_phase() {
+    use connect_timing::{Outcome, Phase};
+
+    let client = build_client(Default::default());
+    let clock = Arc::new(ManualClock::new());
+    // Verification of the pool certificate fails in the Noise handshake
+    let mut timer = connect_timing::Timer::builder()
+        .clock(clock.clone())
+        .build();
+    script_phase(&mut timer, &clock, Phase::Dns, None, 20, Outcome::Ok);
+    script_phase(
+        &mut timer,
+        &clock,
+        Phase::TcpConnect,
+        Some("192.0.2.1:3336"),
+        10,
+        Outcome::Ok,
+    );
+    script_phase(
+        &mut timer,
+        &clock,
+        Phase::NoiseHandshake,
+        None,
+        25,
+        Outcome::Failed,
+    );
+    client.lock_connect_history().push(timer.finish());
+    // The time to the first job is not recorded for a failed attempt
+    client.lock_connect_history().first_job(
+        connect_timing::Timer::builder()
+            .build()
+            .pending(Phase::FirstJob)
+            .finish(Outcome::Failed),
+    );
+
+    // The pool doesn't respond to `SetupConnection` until the timeout interrupts the phase
+    let mut timer = connect_timing::Timer::builder()
+        .clock(clock.clone())
+        .build();
+    timer.start(Phase::SetupConnection, None);
+    clock.advance(10_000);
+    client.lock_connect_history().push(timer.finish());
+
+    let history = client.connection_history();
+    assert_eq!(history[0].failed_phase, Some(Phase::NoiseHandshake));
+    assert_eq!(
+        connect_phases(&history[0]).last(),
+        Some(&(Phase::NoiseHandshake, 25, Outcome::Failed))
+    );
+    assert_eq!(history[1].failed_phase, Some(Phase::SetupConnection));
+    assert_eq!(
+        connect_phases(&history[1]),
+        vec![(Phase::SetupConnection, 10_000, Outcome::Failed)]
+    );
+}
+
+#[tokio::test]
+async fn test_group_addressed_messages() {
+    let client = build_client(Default::default());
+    let session = Arc::new(session::State {
+        channel: session::ChannelIdentity::new(3, 9, time::SystemTime::now()),
+        ..Default::default()
+    });
+    let mut event_handler = StratumEventHandler::new(client.clone(), session, client.context());
+
+    // Target and prevhash sent to the group apply to the channel of the session
+    handle_message(
+        &client,
+        &mut event_handler,
+        SetTarget {
+            channel_id: 9,
+            max_target: ii_bitcoin::Target::from_pool_difficulty(16).into(),
+        },
+    )
+    .await;
+    assert_eq!(client.current_difficulty(), Some(16));
+    let job = |channel_id, job_id| NewMiningJob {
+        channel_id,
+        job_id,
+        future_job: true,
+        version: 0x20000000,
+        merkle_root: Uint256Bytes([job_id as u8; 32]),
+    };
+    let prev_hash = |channel_id, job_id| SetNewPrevHash {
+        channel_id,
+        job_id,
+        prev_hash: Uint256Bytes([0xbb; 32]),
+        min_ntime: 0x5e000000,
+        nbits: 0x1d00ffff,
+    };
+    handle_message(&client, &mut event_handler, job(3, 1)).await;
+    handle_message(&client, &mut event_handler, prev_hash(9, 1)).await;
+    let last_job = client.last_job().expect("BUG: no job has been received");
+    assert_eq!((last_job.id, last_job.channel_id), (1, 3));
+    assert_eq!(*client.foreign_channel_messages().take_snapshot(), 0);
+
+    // Messages for other channels are not applied
+    handle_message(
+        &client,
+        &mut event_handler,
+        SetTarget {
+            channel_id: 5,
+            max_target: ii_bitcoin::Target::from_pool_difficulty(64).into(),
+        },
+    )
+    .await;
+    assert_eq!(client.current_difficulty(), Some(16));
+    handle_message(&client, &mut event_handler, job(5, 2)).await;
+    handle_message(&client, &mut event_handler, prev_hash(5, 2)).await;
+    assert_eq!(last_job_id(&client), Some(1));
+    assert_eq!(*client.foreign_channel_messages().take_snapshot(), 3);
+
+    // Shares are acknowledged only to the channel they have been submitted to
+    let solution = build_solution(&client).await;
+    client.solutions.lock().await.push_back((solution, 0));
+    for channel_id in [9, 5].iter() {
+        handle_message(
+            &client,
+            &mut event_handler,
+            SubmitSharesSuccess {
+                channel_id: *channel_id,
+                last_seq_num: 0,
+                new_submits_accepted_count: 1,
+                new_shares_sum: 1,
+            },
+        )
+        .await;
+    }
+    assert_eq!(client.solutions.lock().await.len(), 1);
+    assert_eq!(*client.foreign_channel_messages().take_snapshot(), 5);
+}
+
+/// Capture of a session whose channel is assigned `channel_id` and `group_id`, the jobs and the
+/// target are addressed to `addressed_to`
+fn build_group_capture(channel_id: u32, group_id: u32, addressed_to: u32) -> replay::Capture {
+    let mut capture = replay::Capture::new();
+    let frames: Vec<<Framing as ii_wire::Framing>::Rx> = vec![
+        SetupConnectionSuccess {
+            used_version: 2,
+            flags: 0,
+        }
+        .try_into()
+        .expect("BUG: cannot build frame"),
+        OpenStandardMiningChannelSuccess {
+            req_id: 10,
+            channel_id,
+            target: ii_bitcoin::Target::from_pool_difficulty(4).into(),
+            extranonce_prefix: Bytes0_32::new(),
+            group_channel_id: group_id,
+        }
+        .try_into()
+        .expect("BUG: cannot build frame"),
+        NewMiningJob {
+            channel_id: addressed_to,
+            job_id: channel_id,
+            future_job: true,
+            version: 0x20000000,
+            merkle_root: Uint256Bytes([0xaa; 32]),
+        }
+        .try_into()
+        .expect("BUG: cannot build frame"),
+        SetNewPrevHash {
+            channel_id: addressed_to,
+            job_id: channel_id,
+            prev_hash: Uint256Bytes([0xbb; 32]),
+            min_ntime: 0x5e000000,
+            nbits: 0x1d00ffff,
+        }
+        .try_into()
+        .expect("BUG: cannot build frame"),
+        SetTarget {
+            channel_id: addressed_to,
+            max_target: ii_bitcoin::Target::from_pool_difficulty(16).into(),
+        }
+        .try_into()
+        .expect("BUG: cannot build frame"),
+    ];
+    for (i, frame) in frames.into_iter().enumerate() {
+        capture
+            .push(time::Duration::from_millis(i as u64 * 5), frame)
+            .expect("BUG: cannot capture frame");
+    }
+    capture
+}
+
+#[tokio::test]
+async fn test_channel_identity_after_reconnect() {
+    let client = build_client(Default::default());
+    assert!(client.channel_identity().is_none());
+
+    replay::replay_session(client.clone(), &build_group_capture(3, 9, 9), false)
+        .await
+        .expect("BUG: replay failed");
+    let identity = client.channel_identity().expect("BUG: no session");
+    assert_eq!((identity.channel_id, identity.group_id), (3, Some(9)));
+    assert_eq!(client.status_document().channel, Some(identity.clone()));
+    assert_eq!(client.job_pipeline().channel, Some(identity));
+    assert_eq!(last_job_id(&client), Some(3));
+    assert_eq!(client.current_difficulty(), Some(16));
+    assert_eq!(submit_last_job(&client).await.channel_id, 3);
+
+    // The pool assigns different identifiers after reconnect
+    replay::replay_session(client.clone(), &build_group_capture(4, 12, 12), false)
+        .await
+        .expect("BUG: replay failed");
+    let identity = client.channel_identity().expect("BUG: no session");
+    assert_eq!((identity.channel_id, identity.group_id), (4, Some(12)));
+    assert_eq!(last_job_id(&client), Some(4));
+    assert_eq!(submit_last_job(&client).await.channel_id, 4);
+    assert_eq!(*client.foreign_channel_messages().take_snapshot(), 0);
+
+    // The group of the previous session is foreign now, a channel without a group is reported
+    // as such
+    replay::replay_session(client.clone(), &build_group_capture(5, 5, 12), false)
+        .await
+        .expect("BUG: replay failed");
+    let identity = client.channel_identity().expect("BUG: no session");
+    assert_eq!((identity.channel_id, identity.group_id), (5, None));
+    assert_eq!(last_job_id(&client), Some(4));
+    assert_eq!(*client.foreign_channel_messages().take_snapshot(), 3);
+}
+
+#[test]
+fn test_startup_budget_config() {
+    use startup_budget::{Budgets, Phase};
+
+    let seconds = time::Duration::from_secs;
+    // Phases share the default total
+    let budgets = Budgets::resolve(&Default::default(), false);
+    assert_eq!(budgets.total, seconds(180));
+    assert_eq!(budgets.phase(Phase::Connect), seconds(45));
+    assert_eq!(budgets.phase(Phase::Handshake), seconds(90));
+    assert_eq!(budgets.phase(Phase::FirstJob), seconds(45));
+
+    // Configured phases are taken from the total, the rest is shared by the remaining phases
+    let config = StratumV2StartupBudget {
+        total: Some(120_000),
+        reconnect_total: Some(60_000),
+        handshake: Some(40_000),
+        ..Default::default()
+    };
+    let budgets = Budgets::resolve(&config, false);
+    assert_eq!(budgets.phase(Phase::Connect), seconds(40));
+    assert_eq!(budgets.phase(Phase::Handshake), seconds(40));
+    assert_eq!(budgets.phase(Phase::FirstJob), seconds(40));
+    let budgets = Budgets::resolve(&config, true);
+    assert_eq!(budgets.total, seconds(60));
+    assert_eq!(budgets.phase(Phase::Connect), seconds(10));
+    assert_eq!(budgets.phase(Phase::Handshake), seconds(40));
+
+    let validate = |startup_budget: StratumV2StartupBudget, startup_delay: Option<u64>| {
+        StratumV2Config {
+            startup_budget: Some(startup_budget),
+            startup_delay,
+            ..Default::default()
+        }
+        .validate()
+    };
+    assert!(validate(config.clone(), None).is_ok());
+    // Phases exceed the reconnect total
+    assert!(validate(
+        StratumV2StartupBudget {
+            connect: Some(30_000),
+            ..config.clone()
+        },
+        None
+    )
+    .is_err());
+    // Phases derived from the total would be left without time
+    assert!(validate(
+        StratumV2StartupBudget {
+            total: Some(10_000),
+            connect: Some(5_000),
+            handshake: Some(5_000),
+            ..Default::default()
+        },
+        None
+    )
+    .is_err());
+    // Phases may take the whole total when all of them are configured
+    assert!(validate(
+        StratumV2StartupBudget {
+            total: Some(10_000),
+            connect: Some(2_000),
+            handshake: Some(5_000),
+            first_job: Some(3_000),
+            ..Default::default()
+        },
+        None
+    )
+    .is_ok());
+    assert!(validate(
+        StratumV2StartupBudget {
+            first_job: Some(0),
+            ..Default::default()
+        },
+        None
+    )
+    .is_err());
+    // The delay of the first connection has to fit into the startup budget
+    assert!(validate(config.clone(), Some(100_000)).is_ok());
+    assert!(validate(config, Some(120_000)).is_err());
+}
+
+/// Start the startup budget resolved from `config` at the current time of `clock`
+fn start_budget_with_clock(
+    client: &StratumClient,
+    clock: &Arc<ManualClock>,
+    config: &StratumV2StartupBudget,
+) {
+    let started = connect_timing::Clock::now(clock.as_ref());
+    client
+        .lock_startup_budget()
+        .replace(startup_budget::Budget::new(
+            startup_budget::Budgets::resolve(config, false),
+            false,
+            clock.clone(),
+            started,
+        ));
+}
+
+/// Run the startup on a connection that replays `records`, the offset of each record sets
+/// `clock` when the record is received. Frames following the handshake are handled until the
+/// connection is closed.
+async fn replay_timed_startup(
+    client: &Arc<StratumClient>,
+    clock: &Arc<ManualClock>,
+    records: Vec<replay::Record>,
+) -> error::Result<()> {
+    let stream_clock = clock.clone();
+    let mut connection_rx = stream::iter(records)
+        .map(move |record| {
+            stream_clock.set(record.offset);
+            record
+                .into_frame()
+                .map_err(|e| ii_stratum::error::ErrorKind::General(e.to_string()).into())
+        })
+        .boxed();
+    let connection_tx = Arc::new(Mutex::new(replay::FrameCollector::default()));
+
+    let context = client.new_connection_context();
+    client
+        .refresh_user(context)
+        .expect("BUG: cannot refresh user");
+    let timer = connect_timing::Timer::builder()
+        .clock(clock.clone())
+        .build();
+    let (session, context, buffered_frames, first_job) =
+        StratumConnectionHandler::new(client.clone(), context)
+            .with_timer(timer)
+            .init_mining_session(&mut connection_rx, connection_tx)
+            .await?;
+    let mut event_handler = StratumEventHandler::new(client.clone(), session, context);
+    event_handler.first_job = Some(first_job);
+    for frame in buffered_frames {
+        client.handle_frame(frame, &mut event_handler).await?;
+    }
+    while let Some(frame) = connection_rx.next().await {
+        client
+            .handle_frame(frame.expect("BUG: invalid frame"), &mut event_handler)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Set offsets of the records of the handshake (the response to `SetupConnection` and to the
+/// channel open), the rest of the session follows the last one
+fn set_handshake_offsets(records: &mut [replay::Record], setup: u64, channel_open: u64) {
+    for (index, record) in records.iter_mut().enumerate() {
+        record.offset = time::Duration::from_millis(if index == 0 { setup } else { channel_open });
+    }
+}
+
+#[tokio::test]
+async fn test_startup_budget_preempts_slow_phases() {
+    use connect_timing::{Outcome, Phase};
+
+    let config = StratumV2StartupBudget {
+        total: Some(6000),
+        ..Default::default()
+    };
+
+    // The first connection is delayed by 3 s and connecting takes 1 s. Each step of the handshake
+    // fits into the budget of its phase but the chained phases exceed the total.
+    let client = build_client(Default::default());
+    let clock = Arc::new(ManualClock::new());
+    start_budget_with_clock(&client, &clock, &config);
+    clock.advance(3000);
+    client.start_startup_phase(startup_budget::Phase::Connect);
+    clock.advance(1000);
+    assert!(client.startup_budget_error().is_none());
+    let mut records = build_negotiated_capture(0, 7).records;
+    set_handshake_offsets(&mut records, 5000, 6500);
+    let e = replay_timed_startup(&client, &clock, records)
+        .await
+        .expect_err("BUG: startup completed over budget");
+    assert_eq!(e.error_code(), "stratum.startup.budget_exhausted");
+    let status = client
+        .startup_budget()
+        .expect("BUG: missing startup budget");
+    assert_eq!(status.phase, Some(startup_budget::Phase::Handshake));
+    assert_eq!(status.remaining_ms, 0);
+    let history = client.connection_history();
+    assert_eq!(
+        connect_phases(&history[0]),
+        vec![
+            (Phase::SetupConnection, 1000, Outcome::Ok),
+            (Phase::ChannelOpen, 1500, Outcome::Failed),
+        ]
+    );
+    assert_eq!(
+        client
+            .status_document()
+            .last_error
+            .expect("BUG: missing last error")
+            .code,
+        "stratum.startup.budget_exhausted"
+    );
+
+    // The channel open has been rejected and the budget doesn't leave time for another attempt,
+    // the startup fails with the rejection right away
+    let client = build_client(StratumV2Config {
+        channel_open_backoff: Some(vec![2000]),
+        ..Default::default()
+    });
+    let clock = Arc::new(ManualClock::new());
+    start_budget_with_clock(&client, &clock, &config);
+    let mut records = build_channel_retry_capture("temporarily-unavailable", vec![]).records;
+    set_handshake_offsets(&mut records, 500, 1500);
+    let e = replay_timed_startup(&client, &clock, records)
+        .await
+        .expect_err("BUG: rejected channel opened");
+    assert_eq!(e.error_code(), "stratum.channel.open_rejected");
+    assert_eq!(client.retries().channel_open, 0);
+
+    // The budget runs out while the channel is being opened again, the rejection is reported
+    // instead of the exhausted budget
+    let client = build_client(StratumV2Config {
+        channel_open_backoff: Some(vec![0]),
+        ..Default::default()
+    });
+    let clock = Arc::new(ManualClock::new());
+    start_budget_with_clock(&client, &clock, &config);
+    let mut records = build_channel_retry_capture("temporarily-unavailable", vec![]).records;
+    set_handshake_offsets(&mut records, 500, 3500);
+    records[1].offset = time::Duration::from_millis(1500);
+    let e = replay_timed_startup(&client, &clock, records)
+        .await
+        .expect_err("BUG: startup completed over budget");
+    assert_eq!(e.error_code(), "stratum.channel.open_rejected");
+    assert_eq!(client.retries().channel_open, 1);
+
+    // The first job doesn't arrive within the budget of its phase
+    let client = build_client(Default::default());
+    let clock = Arc::new(ManualClock::new());
+    start_budget_with_clock(&client, &clock, &config);
+    let mut records = build_negotiated_capture(0, 7).records;
+    set_handshake_offsets(&mut records, 1000, 2500);
+    records.truncate(2);
+    replay_timed_startup(&client, &clock, records)
+        .await
+        .expect("BUG: handshake failed");
+    assert!(client.startup_budget_error().is_none());
+    clock.advance(1500);
+    let e = client
+        .startup_budget_error()
+        .expect("BUG: budget of the first job not exhausted");
+    assert_eq!(e.error_code(), "stratum.startup.budget_exhausted");
+    assert!(e.to_string().contains("first job"), "{}", e);
+}
+
+#[tokio::test]
+async fn test_startup_budget_fast_startup() {
+    use connect_timing::{Outcome, Phase};
+
+    let client = build_client(Default::default());
+    let clock = Arc::new(ManualClock::new());
+    start_budget_with_clock(&client, &clock, &Default::default());
+    let status = client
+        .status_document()
+        .startup_budget
+        .expect("BUG: missing startup budget");
+    assert_eq!(status.phase, None);
+    assert_eq!(status.remaining_ms, 180_000);
+    client.start_startup_phase(startup_budget::Phase::Connect);
+    clock.advance(20);
+    // Steps are limited by their own timeouts while the budget is plentiful
+    assert_eq!(
+        client.startup_timeout(StratumClient::CONNECTION_TIMEOUT),
+        StratumClient::CONNECTION_TIMEOUT
+    );
+
+    let mut records = build_negotiated_capture(0, 7).records;
+    set_handshake_offsets(&mut records, 60, 100);
+    replay_timed_startup(&client, &clock, records)
+        .await
+        .expect("BUG: startup failed");
+    assert!(client.last_job().is_some());
+    // The budget ends with the first dispatched job
+    assert!(client.startup_budget().is_none());
+    assert!(client.status_document().startup_budget.is_none());
+    assert!(client.startup_budget_error().is_none());
+    let history = client.connection_history();
+    assert_eq!(
+        connect_phases(&history[0]),
+        vec![
+            (Phase::SetupConnection, 40, Outcome::Ok),
+            (Phase::ChannelOpen, 40, Outcome::Ok),
+            (Phase::FirstJob, 0, Outcome::Ok),
+        ]
+    );
+    assert_eq!(client.retries(), Default::default());
+}
@@ -0,0 +1,1154 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+#[tokio::test]
+async fn test_jobs_when_stopping() {
+    for &dispatch_jobs_when_stopping in [None, Some(true)].iter() {
+        let client = build_client(StratumV2Config {
+            dispatch_jobs_when_stopping,
+            ..Default::default()
+        });
+        let mut event_handler =
+            StratumEventHandler::new(client.clone(), Default::default(), client.context());
+        assert!(client.status.initiate_starting());
+        assert!(client.status.initiate_running());
+
+        new_job(&client, &mut event_handler, 1, true).await;
+        new_prev_hash(&client, &mut event_handler, 1).await;
+        assert_eq!(last_job_id(&client), Some(1));
+
+        assert!(client.status.initiate_stopping());
+        new_job(&client, &mut event_handler, 2, false).await;
+        let expected_job_id = if dispatch_jobs_when_stopping == Some(true) {
+            2
+        } else {
+            1
+        };
+        assert_eq!(last_job_id(&client), Some(expected_job_id));
+    }
+}
+
+#[tokio::test]
+async fn test_duplicate_jobs() {
+    let client = build_client(Default::default());
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    assert_eq!(last_job_id(&client), Some(1));
+
+    // Immediate job with the payload of the active job is not dispatched again
+    let job_msg = NewMiningJob {
+        job_id: 2,
+        future_job: false,
+        ..event_handler.all_jobs[&1].clone()
+    };
+    handle_message(&client, &mut event_handler, job_msg).await;
+    assert_eq!(last_job_id(&client), Some(1));
+    assert!(!event_handler.all_jobs.contains_key(&2));
+    assert_eq!(client.job_aliases.resolve(2), 1);
+    assert_eq!(*client.duplicate_jobs().take_snapshot(), 1);
+
+    // Duplicate future jobs collapse into a single stored job
+    new_job(&client, &mut event_handler, 3, true).await;
+    let job_msg = NewMiningJob {
+        job_id: 4,
+        ..event_handler.all_jobs[&3].clone()
+    };
+    handle_message(&client, &mut event_handler, job_msg).await;
+    assert!(!event_handler.all_jobs.contains_key(&4));
+    assert_eq!(*client.duplicate_jobs().take_snapshot(), 2);
+
+    // New prevhash activates the job under the most recently announced ID and flushes aliases
+    new_prev_hash(&client, &mut event_handler, 3).await;
+    assert_eq!(last_job_id(&client), Some(4));
+    assert_eq!(client.job_aliases.len(), 0);
+    assert_eq!(client.job_aliases.resolve(2), 2);
+}
+
+#[tokio::test]
+async fn test_job_id_reuse() {
+    for &(policy, expected_root) in [
+        (None, 0xcc),
+        (Some(StratumV2JobIdReuse::Ignore), 1),
+        (Some(StratumV2JobIdReuse::Reconnect), 1),
+    ]
+    .iter()
+    {
+        let client = build_client(StratumV2Config {
+            job_id_reuse: policy,
+            ..Default::default()
+        });
+        let mut event_handler =
+            StratumEventHandler::new(client.clone(), Default::default(), client.context());
+
+        new_job(&client, &mut event_handler, 1, true).await;
+        // Repeated announcement of the same job is not a reuse
+        new_job(&client, &mut event_handler, 1, true).await;
+        assert_eq!(*client.reused_job_ids().take_snapshot(), 0);
+
+        let job_msg = NewMiningJob {
+            merkle_root: Uint256Bytes([0xcc; 32]),
+            ..event_handler.all_jobs[&1].clone()
+        };
+        handle_message(&client, &mut event_handler, job_msg).await;
+        assert_eq!(*client.reused_job_ids().take_snapshot(), 1);
+        assert_eq!(
+            event_handler.all_jobs[&1].merkle_root,
+            Uint256Bytes([expected_root; 32])
+        );
+        match policy {
+            Some(StratumV2JobIdReuse::Reconnect) => assert_eq!(
+                event_handler
+                    .fatal_error
+                    .take()
+                    .expect("BUG: missing fatal error")
+                    .kind(),
+                error::ErrorKind::Client(error::Client::JobIdReused(1))
+            ),
+            _ => assert!(event_handler.fatal_error.is_none()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_pending_jobs_warning() {
+    let config = StratumV2Config {
+        pending_jobs_log_interval: Some(60),
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+    let client = build_client(config);
+    let mut event_handler = start_mining(&client).await;
+    assert!(event_handler.pending_future_jobs.is_none());
+
+    new_job(&client, &mut event_handler, 2, true).await;
+    new_job(&client, &mut event_handler, 3, true).await;
+    // Immediate jobs are not pending
+    new_job(&client, &mut event_handler, 4, false).await;
+    let (count, oldest) = event_handler
+        .pending_future_jobs
+        .expect("BUG: no pending future jobs");
+    assert_eq!(count, 2);
+    let after = |secs| oldest + time::Duration::from_secs(secs);
+    assert!(!event_handler.warn_pending_future_jobs(after(59)));
+    assert!(event_handler.warn_pending_future_jobs(after(60)));
+    // The warning is repeated at most once per interval
+    assert!(!event_handler.warn_pending_future_jobs(after(90)));
+    assert!(event_handler.warn_pending_future_jobs(after(120)));
+
+    // New prevhash activates one of the jobs and the others are not pending anymore
+    new_prev_hash(&client, &mut event_handler, 3).await;
+    assert!(event_handler.pending_future_jobs.is_none());
+    assert!(!event_handler.warn_pending_future_jobs(after(600)));
+
+    // The warnings are disabled by default
+    let client = build_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    new_job(&client, &mut event_handler, 2, true).await;
+    assert!(!event_handler.warn_pending_future_jobs(after(3600)));
+
+    let config = StratumV2Config {
+        pending_jobs_log_interval: Some(0),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_future_job_max_age() {
+    let config = StratumV2Config {
+        future_job_max_age: Some(60),
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+    let client = build_client(config);
+    let mut event_handler = start_mining(&client).await;
+    let active_job_id = last_job_id(&client);
+
+    new_job(&client, &mut event_handler, 2, true).await;
+    new_job(&client, &mut event_handler, 3, true).await;
+    let (_, received) = *event_handler
+        .future_jobs_received
+        .front()
+        .expect("BUG: no future jobs");
+    let after = |secs| received + time::Duration::from_secs(secs);
+    assert_eq!(event_handler.evict_aged_future_jobs(after(59)), 0);
+    assert_eq!(event_handler.evict_aged_future_jobs(after(61)), 2);
+    assert!(!event_handler.all_jobs.contains_key(&2));
+    assert!(!event_handler.all_jobs.contains_key(&3));
+    assert_eq!(*client.aged_future_jobs().take_snapshot(), 2);
+    let (_, future_jobs) = client.pipeline.snapshot(after(61));
+    assert!(future_jobs.is_empty());
+
+    // A delayed prevhash doesn't promote the aged out job, it waits for the job instead
+    new_prev_hash(&client, &mut event_handler, 2).await;
+    assert_eq!(last_job_id(&client), active_job_id);
+    assert!(event_handler.orphan_prevhash.is_some());
+    assert_eq!(*client.orphan_prevhashes().take_snapshot(), 1);
+
+    // Future jobs are kept by default
+    let client = build_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    new_job(&client, &mut event_handler, 2, true).await;
+    assert_eq!(event_handler.evict_aged_future_jobs(after(3600)), 0);
+    new_prev_hash(&client, &mut event_handler, 2).await;
+    assert_eq!(last_job_id(&client), Some(2));
+
+    let config = StratumV2Config {
+        future_job_max_age: Some(0),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+/// Build a solver whose engine sender records the IDs of dispatched jobs
+fn recording_solver() -> (job::Solver, Arc<StdMutex<Vec<u32>>>) {
+    let dispatched = Arc::new(StdMutex::new(Vec::new()));
+    let engine_sender = work::EngineSender::new(None);
+    let recorder = dispatched.clone();
+    engine_sender.replace_engine_generator(Box::new(move |job| {
+        let job = job
+            .downcast_ref::<StratumJob>()
+            .expect("BUG: unexpected job type");
+        recorder.lock().expect("BUG: cannot lock").push(job.id);
+        Arc::new(work::engine::ExhaustedWork)
+    }));
+    let (_solution_sender, solution_receiver) = mpsc::unbounded();
+    (
+        job::Solver::new(Arc::new(engine_sender), solution_receiver),
+        dispatched,
+    )
+}
+
+#[tokio::test]
+async fn test_replace_solver() {
+    let client = build_client(Default::default());
+    assert!(client.status.initiate_starting());
+    assert!(client.status.initiate_running());
+    let mut event_handler = start_mining(&client).await;
+
+    // The last job is dispatched to the new solver right away
+    let (solver, dispatched) = recording_solver();
+    let _old_solver = client.replace_solver(solver).await;
+    assert_eq!(*dispatched.lock().expect("BUG: cannot lock"), vec![1]);
+
+    // New jobs go to the new solver only
+    new_job(&client, &mut event_handler, 2, false).await;
+    let (solver, replaced) = recording_solver();
+    let _old_solver = client.replace_solver(solver).await;
+    assert_eq!(*dispatched.lock().expect("BUG: cannot lock"), vec![1, 2]);
+    assert_eq!(*replaced.lock().expect("BUG: cannot lock"), vec![2]);
+
+    // Job dispatched after the swap reaches the new solver and not the old one
+    new_job(&client, &mut event_handler, 3, false).await;
+    assert_eq!(last_job_id(&client), Some(3));
+    assert_eq!(*replaced.lock().expect("BUG: cannot lock"), vec![2, 3]);
+    assert_eq!(*dispatched.lock().expect("BUG: cannot lock"), vec![1, 2]);
+
+    // Nothing is dispatched while the client isn't running
+    let client = build_client(Default::default());
+    let (solver, dispatched) = recording_solver();
+    let _old_solver = client.replace_solver(solver).await;
+    assert!(dispatched.lock().expect("BUG: cannot lock").is_empty());
+}
+
+/// A run started while the previous one is still tearing down doesn't take the job solver out of
+/// the client, it just waits until the solution receiver is released
+#[tokio::test]
+async fn test_start_during_teardown() {
+    let client = build_client(StratumV2Config {
+        // The new run doesn't get to connecting in the test
+        startup_delay: Some(60_000),
+        ..Default::default()
+    });
+
+    // The previous run holds the solution receiver while it flushes the solutions
+    let teardown = client.solution_receiver.lock().await;
+    let mut main_task = Box::pin(client.clone().main_task());
+    for _ in 0..3 {
+        assert!(futures::poll!(main_task.as_mut()).is_pending());
+        assert!(client.lock_startup_budget().is_none());
+    }
+
+    // Once the previous run is done the new one carries on with the connection attempt
+    drop(teardown);
+    assert!(futures::poll!(main_task.as_mut()).is_pending());
+    assert!(client.lock_startup_budget().is_some());
+    let _solution_receiver = client
+        .solution_receiver
+        .try_lock()
+        .expect("BUG: solution receiver is held by the waiting run");
+}
+
+/// Notification received by `RecordingObserver`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PipelineEvent {
+    Sent { job_id: u32, generation: u64 },
+    Invalidated { job_id: u32, generation: u64 },
+}
+
+/// Fake work pipeline that records the interleaving of jobs and invalidations
+#[derive(Debug, Default)]
+struct RecordingObserver {
+    events: StdMutex<Vec<PipelineEvent>>,
+}
+
+impl RecordingObserver {
+    fn job_id(job: &Arc<dyn job::Bitcoin>) -> u32 {
+        job.downcast_ref::<StratumJob>()
+            .expect("BUG: unexpected job type")
+            .id
+    }
+
+    fn events(&self) -> Vec<PipelineEvent> {
+        self.events.lock().expect("BUG: cannot lock").clone()
+    }
+}
+
+impl job::Observer for RecordingObserver {
+    fn job_sent(&self, job: &Arc<dyn job::Bitcoin>, generation: u64) {
+        self.events
+            .lock()
+            .expect("BUG: cannot lock")
+            .push(PipelineEvent::Sent {
+                job_id: Self::job_id(job),
+                generation,
+            });
+    }
+
+    fn invalidate_preceding(&self, job: &Arc<dyn job::Bitcoin>, generation: u64) -> Option<usize> {
+        self.events
+            .lock()
+            .expect("BUG: cannot lock")
+            .push(PipelineEvent::Invalidated {
+                job_id: Self::job_id(job),
+                generation,
+            });
+        Some(3)
+    }
+}
+
+#[tokio::test]
+async fn test_invalidate_preceding() {
+    let client = build_client(Default::default());
+    assert!(client.status.initiate_starting());
+    assert!(client.status.initiate_running());
+    let mut event_handler = start_mining(&client).await;
+
+    let observer = Arc::new(RecordingObserver::default());
+    let (mut solver, _dispatched) = recording_solver();
+    solver.job_sender = solver.job_sender.with_observer(observer.clone());
+    // The last job is re-dispatched without any invalidation
+    let _old_solver = client.replace_solver(solver).await;
+
+    // An immediate job on the same block doesn't invalidate anything
+    new_job(&client, &mut event_handler, 2, false).await;
+    // Target change re-dispatches the job without any invalidation either
+    let message = SetTarget {
+        channel_id: 0,
+        max_target: Uint256Bytes([0x0f; 32]),
+    };
+    handle_message(&client, &mut event_handler, message).await;
+    // New block invalidates all preceding work
+    new_job(&client, &mut event_handler, 3, true).await;
+    let message = SetNewPrevHash {
+        channel_id: 0,
+        job_id: 3,
+        prev_hash: Uint256Bytes([0xcc; 32]),
+        min_ntime: 0x5e000000,
+        nbits: 0x1d00ffff,
+    };
+    handle_message(&client, &mut event_handler, message).await;
+
+    let events = observer.events();
+    let invalidations: Vec<_> = events
+        .iter()
+        .enumerate()
+        .filter_map(|(i, event)| match *event {
+            PipelineEvent::Invalidated { job_id, generation } => Some((i, job_id, generation)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(invalidations.len(), 1, "{:?}", events);
+    let (i, job_id, generation) = invalidations[0];
+    assert_eq!(job_id, 3);
+    // The invalidation immediately follows its job
+    assert_eq!(events[i - 1], PipelineEvent::Sent { job_id, generation });
+    assert_eq!(i, events.len() - 1);
+    assert!(events[..i].iter().any(|event| match *event {
+        PipelineEvent::Sent { job_id, .. } => job_id == 2,
+        _ => false,
+    }));
+    assert_eq!(*client.invalidations().take_snapshot(), 1);
+    assert_eq!(*client.discarded_work().take_snapshot(), 3);
+}
+
+#[tokio::test]
+async fn test_dispatch_latency() {
+    let client = build_client(Default::default());
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+
+    // Future job is not dispatched
+    new_job(&client, &mut event_handler, 1, true).await;
+    assert_eq!(client.dispatch_latency(), None);
+
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    assert_eq!(last_job_id(&client), Some(1));
+    let latency = client
+        .dispatch_latency()
+        .expect("BUG: missing dispatch latency");
+    assert!(latency < time::Duration::from_secs(1));
+    assert!(event_handler.frame_received.is_none());
+}
+
+#[test]
+fn test_dispatch_limit_bucket() {
+    let start = time::Instant::now();
+    let at = |secs| start + time::Duration::from_secs(secs);
+
+    // One token every 2 seconds
+    let mut bucket = dispatch_limit::Bucket::new(30.0, 2, start);
+    assert_eq!(bucket.tokens(start), 2.0);
+    assert!(bucket.try_take(start));
+    assert!(bucket.try_take(start));
+    assert!(!bucket.try_take(start));
+    assert_eq!(bucket.until_token(start), time::Duration::from_secs(2));
+    assert_eq!(bucket.tokens(at(1)), 0.5);
+    assert_eq!(bucket.until_token(at(1)), time::Duration::from_secs(1));
+    assert!(!bucket.try_take(at(1)));
+    assert!(bucket.try_take(at(2)));
+    assert_eq!(bucket.tokens(at(2)), 0.0);
+    // The bucket never holds more than the burst
+    assert_eq!(bucket.tokens(at(100)), 2.0);
+    assert_eq!(bucket.until_token(at(100)), time::Duration::from_secs(0));
+}
+
+#[test]
+fn test_dispatch_limit_engagement() {
+    let start = time::Instant::now();
+    let at = |secs| start + time::Duration::from_secs(secs);
+    let limit = StratumV2JobDispatchLimit {
+        rate: 30.0,
+        burst: None,
+    };
+
+    // Disabled limiter never limits anything
+    let limiter = dispatch_limit::Limiter::default();
+    assert!(limiter.try_acquire(start));
+    assert!(limiter.try_acquire(start));
+    assert_eq!(limiter.tokens(start), None);
+    assert_eq!(limiter.job_update(start), None);
+
+    limiter.configure(Some(limit.clone()), start);
+    assert_eq!(limiter.tokens(start), Some(1.0));
+    assert!(limiter.try_acquire(start));
+    assert!(!limiter.try_acquire(start));
+    // New session with the same limit keeps the state of the bucket
+    limiter.configure(Some(limit.clone()), start);
+    assert_eq!(limiter.tokens(start), Some(0.0));
+
+    // More than half of job updates suppressed within an hour is reported once the hour elapses
+    for _ in 0..3 {
+        assert_eq!(limiter.job_update(at(1)), None);
+    }
+    limiter.suppressed();
+    limiter.suppressed();
+    assert_eq!(
+        limiter.job_update(at(3600)),
+        Some(dispatch_limit::Engagement {
+            updates: 3,
+            suppressed: 2,
+        })
+    );
+    // The advisory is reported only once
+    for _ in 0..3 {
+        limiter.job_update(at(3601));
+        limiter.suppressed();
+    }
+    assert_eq!(limiter.job_update(at(7200)), None);
+
+    // Half of job updates suppressed is fine
+    let limiter = dispatch_limit::Limiter::default();
+    limiter.configure(Some(limit), start);
+    limiter.job_update(start);
+    limiter.job_update(start);
+    limiter.suppressed();
+    assert_eq!(limiter.job_update(at(3600)), None);
+
+    let config = |rate, burst| StratumV2Config {
+        job_dispatch_limit: Some(StratumV2JobDispatchLimit { rate, burst }),
+        ..Default::default()
+    };
+    assert!(config(0.5, Some(1)).validate().is_ok());
+    assert!(config(0.0, None).validate().is_err());
+    assert!(config(std::f64::NAN, None).validate().is_err());
+    assert!(config(1.0, Some(0)).validate().is_err());
+}
+
+#[tokio::test]
+async fn test_job_dispatch_limit() {
+    let client = build_client(StratumV2Config {
+        job_dispatch_limit: Some(StratumV2JobDispatchLimit {
+            rate: 60.0,
+            burst: None,
+        }),
+        ..Default::default()
+    });
+    // New prevhash doesn't take the token
+    let mut event_handler = start_mining(&client).await;
+    assert_eq!(last_job_id(&client), Some(1));
+    new_job(&client, &mut event_handler, 2, false).await;
+    assert_eq!(last_job_id(&client), Some(2));
+
+    // Flood of job updates is not queued, the most recent one is held back
+    for job_id in 3..=10 {
+        new_job(&client, &mut event_handler, job_id, false).await;
+    }
+    assert_eq!(last_job_id(&client), Some(2));
+    assert_eq!(
+        event_handler
+            .held_job_msg
+            .as_ref()
+            .map(|job_msg| job_msg.job_id),
+        Some(10)
+    );
+    assert_eq!(*client.suppressed_dispatches().take_snapshot(), 7);
+    let dispatch_limit = client
+        .dispatch_limit()
+        .expect("BUG: missing dispatch limit status");
+    assert_eq!(dispatch_limit.suppressed, 7);
+    assert!(dispatch_limit.tokens < 1.0);
+    assert!(client.status_document().dispatch_limit.is_some());
+
+    // The held job update is dispatched once the next token is available
+    let delay = event_handler
+        .held_job_delay()
+        .expect("BUG: no job update is held");
+    assert!(delay <= time::Duration::from_secs(1));
+    event_handler.dispatch_held_job().await;
+    assert_eq!(last_job_id(&client), Some(2));
+    tokio::time::delay_for(delay).await;
+    event_handler.dispatch_held_job().await;
+    assert_eq!(last_job_id(&client), Some(10));
+    assert!(event_handler.held_job_delay().is_none());
+
+    // New prevhash bypasses the limit and it drops the held job update
+    new_job(&client, &mut event_handler, 11, false).await;
+    assert_eq!(last_job_id(&client), Some(10));
+    new_job(&client, &mut event_handler, 12, true).await;
+    new_prev_hash(&client, &mut event_handler, 12).await;
+    assert_eq!(last_job_id(&client), Some(12));
+    assert!(event_handler.held_job_msg.is_none());
+    assert_eq!(*client.suppressed_dispatches().take_snapshot(), 8);
+
+    // The limit is disabled by default
+    let client = build_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    for job_id in 2..=10 {
+        new_job(&client, &mut event_handler, job_id, false).await;
+        assert_eq!(last_job_id(&client), Some(job_id));
+    }
+    assert!(client.dispatch_limit().is_none());
+}
+
+#[tokio::test]
+async fn test_ntime_regression() {
+    for &ntime_tolerance in [None, Some(2)].iter() {
+        let client = build_client(StratumV2Config {
+            ntime_tolerance,
+            ..Default::default()
+        });
+        let _event_handler = start_mining(&client).await;
+        let min_ntime = client.last_job().expect("BUG: no job").time;
+        let (connection_tx, _connection_rx) = mpsc::unbounded();
+        let mut solution_handler = StratumSolutionHandler::new(
+            client.clone(),
+            Arc::new(Mutex::new(connection_tx)),
+            client.context(),
+        );
+
+        // Solution at the boundary is submitted
+        let solution = build_solution_at(&client, min_ntime).await;
+        assert!(solution_handler.process_solution(solution).await.is_ok());
+        assert_eq!(client.solutions.lock().await.len(), 1);
+
+        // Solution within the tolerance is submitted
+        let solution = build_solution_at(&client, min_ntime - 2).await;
+        assert!(solution_handler.process_solution(solution).await.is_ok());
+        let expected = if ntime_tolerance.is_some() { 2 } else { 1 };
+        assert_eq!(client.solutions.lock().await.len(), expected);
+        assert_eq!(*client.ntime_regressions().take_snapshot(), 2 - expected);
+
+        // Solution below the tolerance is dropped and counted
+        let solution = build_solution_at(&client, min_ntime - 3).await;
+        assert!(solution_handler.process_solution(solution).await.is_ok());
+        assert_eq!(client.solutions.lock().await.len(), expected);
+        assert_eq!(*client.ntime_regressions().take_snapshot(), 3 - expected);
+    }
+}
+
+fn tap_status(client: &Arc<StratumClient>) -> Vec<(usize, usize, usize)> {
+    client
+        .status_document()
+        .job_taps
+        .into_iter()
+        .map(|tap| (tap.id, tap.buffered, tap.dropped))
+        .collect()
+}
+
+async fn tap_job_ids(
+    tap: &mut (impl Stream<Item = Arc<StratumJob>> + Unpin),
+    count: usize,
+) -> Vec<u32> {
+    let mut job_ids = Vec::new();
+    for _ in 0..count {
+        let job = tokio::time::timeout(time::Duration::from_secs(1), tap.next())
+            .await
+            .expect("BUG: no job in the tap")
+            .expect("BUG: tap has been closed");
+        job_ids.push(job.id);
+    }
+    job_ids
+}
+
+#[tokio::test]
+async fn test_job_taps() {
+    let client = build_client(Default::default());
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    let mut slow_tap = client.job_stream(2);
+    let mut tap_a = client.job_stream(10);
+    let mut tap_b = client.job_stream(10);
+
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    let mut solver_job_ids = vec![last_job_id(&client).expect("BUG: no job dispatched")];
+    for job_id in 2..=5 {
+        new_job(&client, &mut event_handler, job_id, false).await;
+        solver_job_ids.push(last_job_id(&client).expect("BUG: no job dispatched"));
+    }
+    // The slow tap doesn't affect the solver
+    assert_eq!(solver_job_ids, vec![1, 2, 3, 4, 5]);
+    assert_eq!(tap_status(&client), vec![(0, 2, 3), (1, 5, 0), (2, 5, 0)]);
+
+    // Taps that keep up see the same sequence as the solver, the slow tap has lost the oldest jobs
+    assert_eq!(tap_job_ids(&mut tap_a, 5).await, solver_job_ids);
+    assert_eq!(tap_job_ids(&mut tap_b, 5).await, solver_job_ids);
+    assert_eq!(tap_job_ids(&mut slow_tap, 2).await, vec![4, 5]);
+    assert_eq!(tap_status(&client), vec![(0, 0, 3), (1, 0, 0), (2, 0, 0)]);
+
+    // Dropped tap is released right away
+    drop(tap_b);
+    assert_eq!(tap_status(&client), vec![(0, 0, 3), (1, 0, 0)]);
+    new_job(&client, &mut event_handler, 6, false).await;
+    assert_eq!(tap_job_ids(&mut tap_a, 1).await, vec![6]);
+    assert_eq!(tap_job_ids(&mut slow_tap, 1).await, vec![6]);
+}
+
+#[tokio::test]
+async fn test_prevhash_before_job() {
+    let client = build_client(Default::default());
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    assert_eq!(last_job_id(&client), Some(1));
+
+    // The prevhash waits for its job, mining continues on the previous job
+    new_prev_hash(&client, &mut event_handler, 2).await;
+    assert_eq!(last_job_id(&client), Some(1));
+    assert_eq!(*client.orphan_prevhashes().take_snapshot(), 1);
+    assert!(event_handler.orphan_prevhash_delay().is_some());
+
+    // The job is promoted once it arrives
+    new_job(&client, &mut event_handler, 2, true).await;
+    assert_eq!(last_job_id(&client), Some(2));
+    assert!(event_handler.orphan_prevhash_delay().is_none());
+    assert_eq!(
+        event_handler
+            .current_prevhash_msg
+            .as_ref()
+            .map(|prevhash_msg| prevhash_msg.job_id),
+        Some(2)
+    );
+    assert!(event_handler.all_jobs.contains_key(&2));
+
+    // Prevhash of a known job supersedes the waiting one
+    new_prev_hash(&client, &mut event_handler, 3).await;
+    new_job(&client, &mut event_handler, 4, true).await;
+    new_prev_hash(&client, &mut event_handler, 4).await;
+    assert_eq!(last_job_id(&client), Some(4));
+    assert!(event_handler.orphan_prevhash_delay().is_none());
+    new_job(&client, &mut event_handler, 3, true).await;
+    assert_eq!(last_job_id(&client), Some(4));
+
+    // The job is waited for only for a limited time
+    new_prev_hash(&client, &mut event_handler, 5).await;
+    event_handler
+        .orphan_prevhash
+        .as_mut()
+        .expect("BUG: no prevhash is waiting")
+        .1 = time::Instant::now();
+    assert_eq!(
+        event_handler.orphan_prevhash_delay(),
+        Some(time::Duration::from_secs(0))
+    );
+    let e = event_handler.orphan_prevhash_expired();
+    assert_eq!(
+        e.kind(),
+        error::ErrorKind::Client(error::Client::MissingPrevHashJob(5))
+    );
+    assert!(event_handler.orphan_prevhash_delay().is_none());
+    assert_eq!(last_job_id(&client), Some(4));
+}
+
+fn unix_time(time: time::SystemTime) -> u32 {
+    time.duration_since(time::UNIX_EPOCH)
+        .expect("BUG: time before epoch")
+        .as_secs() as u32
+}
+
+#[test]
+fn test_clock_skew_pool_behind() {
+    let mut tracker = clock_skew::Tracker::default();
+    let start = time::UNIX_EPOCH + time::Duration::from_secs(1_600_000_000);
+    let mut advisories = Vec::new();
+    for i in 0..30 {
+        let now = start + time::Duration::from_secs(i * 60);
+        // The pool is consistently 2 minutes behind with a little jitter
+        let min_ntime = unix_time(now) - 120 + (i % 3) as u32;
+        advisories.extend(tracker.observe(min_ntime, now, 30));
+    }
+    // The advisory is emitted once when the skew persists
+    assert_eq!(
+        advisories,
+        vec![clock_skew::Advisory {
+            median: -119,
+            threshold: 30,
+        }]
+    );
+    assert!(advisories[0]
+        .to_string()
+        .starts_with("pool time appears to be 119s behind"));
+    let skew = tracker.skew().expect("BUG: no skew measured");
+    assert_eq!(skew.samples, 30);
+    assert!(skew.median <= -118 && skew.median >= -120);
+    assert!(skew.spread <= 2);
+
+    // Local clock behind the pool is distinguished
+    let advisory = clock_skew::Advisory {
+        median: 45,
+        threshold: 30,
+    };
+    assert!(advisory
+        .to_string()
+        .starts_with("local clock appears to be 45s behind the pool"));
+}
+
+#[test]
+fn test_clock_skew_outliers() {
+    let mut tracker = clock_skew::Tracker::default();
+    let start = time::UNIX_EPOCH + time::Duration::from_secs(1_600_000_000);
+    for i in 0..100 {
+        let now = start + time::Duration::from_secs(i * 60);
+        let mut min_ntime = unix_time(now);
+        if i % 10 == 0 {
+            // Occasional template far in the past
+            min_ntime -= 600;
+        }
+        assert_eq!(tracker.observe(min_ntime, now, 30), None);
+    }
+    let skew = tracker.skew().expect("BUG: no skew measured");
+    assert_eq!(skew.samples, clock_skew::Tracker::DEFAULT_CAPACITY);
+    assert_eq!(skew.median, 0);
+    assert_eq!(skew.spread, 0);
+}
+
+#[tokio::test]
+async fn test_min_ntime_tolerance() {
+    let client = build_client(StratumV2Config {
+        min_ntime_tolerance: Some(30),
+        ..Default::default()
+    });
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    let prev_hash = |job_id, skew: i64| SetNewPrevHash {
+        channel_id: 0,
+        job_id,
+        prev_hash: Uint256Bytes([job_id as u8; 32]),
+        min_ntime: (unix_time(time::SystemTime::now()) as i64 + skew) as u32,
+        nbits: 0x1d00ffff,
+    };
+
+    // A pool that is known to be 2 minutes behind is validated against its own time
+    for job_id in 1..=3 {
+        new_job(&client, &mut event_handler, job_id, true).await;
+        handle_message(&client, &mut event_handler, prev_hash(job_id, -120)).await;
+        assert_eq!(last_job_id(&client), Some(job_id));
+    }
+    assert_eq!(*client.implausible_prevhashes().take_snapshot(), 0);
+    let skew = client
+        .status_document()
+        .pool_time_skew
+        .expect("BUG: no skew measured");
+    assert!(skew.median <= -119 && skew.median >= -121);
+
+    // Time that matches the local clock is implausible for this pool
+    new_job(&client, &mut event_handler, 4, true).await;
+    handle_message(&client, &mut event_handler, prev_hash(4, 0)).await;
+    assert_eq!(last_job_id(&client), Some(3));
+    assert_eq!(*client.implausible_prevhashes().take_snapshot(), 1);
+}
+
+#[tokio::test]
+async fn test_max_ntime_roll() {
+    for &(max_ntime_roll, window) in [
+        (None, StratumV2Config::DEFAULT_MAX_NTIME_ROLL),
+        (Some(10), 10),
+    ]
+    .iter()
+    {
+        let client = build_client(StratumV2Config {
+            max_ntime_roll,
+            ..Default::default()
+        });
+        let _event_handler = start_mining(&client).await;
+        let job = client.last_job().expect("BUG: no job");
+        let min_ntime = job.time;
+        assert_eq!(job::Bitcoin::max_time(&*job), min_ntime + window);
+        let (connection_tx, _connection_rx) = mpsc::unbounded();
+        let mut solution_handler = StratumSolutionHandler::new(
+            client.clone(),
+            Arc::new(Mutex::new(connection_tx)),
+            client.context(),
+        );
+
+        // Solution at the end of the window is submitted
+        let solution = build_solution_at(&client, min_ntime + window).await;
+        assert!(solution_handler.process_solution(solution).await.is_ok());
+        assert_eq!(client.solutions.lock().await.len(), 1);
+
+        // Solution beyond the window is skipped and counted
+        let solution = build_solution_at(&client, min_ntime + window + 1).await;
+        assert!(solution_handler.process_solution(solution).await.is_ok());
+        assert_eq!(client.solutions.lock().await.len(), 1);
+        assert_eq!(*client.ntime_overruns().take_snapshot(), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_config_shared() {
+    let client = build_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    let job = client.last_job().expect("BUG: no job");
+    assert_eq!(
+        job::Bitcoin::max_time(&*job),
+        job.time + StratumV2Config::DEFAULT_MAX_NTIME_ROLL
+    );
+
+    // Readers share the configuration instead of copying it
+    let config = client.config();
+    assert!(Arc::ptr_eq(&config, &client.config()));
+    assert!(Arc::ptr_eq(&config, &client.connection_details().config));
+
+    // Changed configuration is used by the very next job without restarting the session
+    client
+        .connection_details
+        .lock()
+        .expect("BUG: cannot lock connection details")
+        .config = Arc::new(StratumV2Config {
+        max_ntime_roll: Some(10),
+        ..Default::default()
+    });
+    assert!(!Arc::ptr_eq(&config, &client.config()));
+    new_job(&client, &mut event_handler, 2, false).await;
+    assert_eq!(last_job_id(&client), Some(2));
+    let job = client.last_job().expect("BUG: no job");
+    assert_eq!(job::Bitcoin::max_time(&*job), job.time + 10);
+}
+
+#[tokio::test]
+async fn test_last_job_age() {
+    let client = build_client(Default::default());
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    assert_eq!(client.last_job_age(), None);
+
+    new_job(&client, &mut event_handler, 1, true).await;
+    let received = time::Instant::now();
+    assert!(client.last_job_age().expect("BUG: no job age") < time::Duration::from_secs(1));
+    let age = client
+        .last_job_age_at(received + time::Duration::from_secs(30))
+        .expect("BUG: no job age");
+    assert!(age >= time::Duration::from_secs(30) && age < time::Duration::from_secs(31));
+
+    // New prevhash counts as new work too
+    tokio::time::delay_for(time::Duration::from_millis(10)).await;
+    let before_prevhash = time::Instant::now();
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    let age = client
+        .last_job_age_at(received + time::Duration::from_secs(30))
+        .expect("BUG: no job age");
+    assert!(age <= time::Duration::from_secs(30) - before_prevhash.duration_since(received));
+}
+
+#[tokio::test]
+async fn test_export_job_pipeline() {
+    let client = build_client(Default::default());
+    let pipeline = client.job_pipeline();
+    assert!(pipeline.prev_hash.is_none());
+    assert!(pipeline.last_job.is_none());
+
+    let mut event_handler = start_mining(&client).await;
+    new_job(&client, &mut event_handler, 3, true).await;
+    new_job(&client, &mut event_handler, 2, true).await;
+    let pipeline = client.job_pipeline();
+    let prev_hash = pipeline.prev_hash.expect("BUG: missing prevhash");
+    assert_eq!(prev_hash.job_id, 1);
+    assert_eq!(prev_hash.prev_hash, "bb".repeat(32));
+    assert_eq!(
+        pipeline
+            .future_jobs
+            .iter()
+            .map(|job| job.job_id)
+            .collect::<Vec<_>>(),
+        vec![2, 3]
+    );
+    let last_job = pipeline.last_job.expect("BUG: missing last job");
+    assert_eq!(last_job.job_id, 1);
+    assert_eq!(last_job.merkle_root, "01".repeat(32));
+    assert_eq!(pipeline.current_difficulty, client.current_difficulty());
+
+    // Future jobs received before a new prevhash are not pending anymore
+    new_prev_hash(&client, &mut event_handler, 2).await;
+    let exported: serde_json::Value = serde_json::from_str(&client.export_job_pipeline())
+        .expect("BUG: invalid JSON of job pipeline");
+    assert_eq!(exported["prev_hash"]["job_id"], serde_json::json!(2));
+    assert_eq!(exported["future_jobs"], serde_json::json!([]));
+    assert_eq!(exported["last_job"]["job_id"], serde_json::json!(2));
+    assert!(exported["targets"]["pool_difficulty"].is_number());
+}
+
+#[tokio::test]
+async fn test_job_interceptor() {
+    let client = build_client(Default::default());
+    let session = Arc::new(session::State {
+        version_mask: 0x1fffe000,
+        ..Default::default()
+    });
+    let mut event_handler = StratumEventHandler::new(client.clone(), session, client.context());
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    assert_eq!(last_job_id(&client), Some(1));
+
+    // Jobs with odd IDs are vetoed and version rolling is restricted for the others
+    let intercepted = Arc::new(AtomicU64::new(0));
+    let counter = intercepted.clone();
+    client.set_job_interceptor(Some(JobInterceptor::new(move |mut job| {
+        counter.fetch_add(1, Ordering::Relaxed);
+        if job.id() % 2 == 1 {
+            return None;
+        }
+        job.restrict_version_mask(0x00ffe000);
+        Some(job)
+    })));
+    new_job(&client, &mut event_handler, 3, false).await;
+    assert_eq!(last_job_id(&client), Some(1));
+    new_job(&client, &mut event_handler, 4, false).await;
+    assert_eq!(last_job_id(&client), Some(4));
+    let job = client.last_job().expect("BUG: no job has been dispatched");
+    assert_eq!(job::Bitcoin::version_mask(job.as_ref()), 0x00ffe000);
+    assert_eq!(intercepted.load(Ordering::Relaxed), 2);
+
+    // Jobs are passed through as they are when the interceptor is removed
+    client.set_job_interceptor(None);
+    new_job(&client, &mut event_handler, 5, false).await;
+    assert_eq!(last_job_id(&client), Some(5));
+    let job = client.last_job().expect("BUG: no job has been dispatched");
+    assert_eq!(job::Bitcoin::version_mask(job.as_ref()), 0x1fffe000);
+    assert_eq!(intercepted.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test]
+async fn test_job_dispatch_policy() {
+    // Job updates are dispatched right away by default
+    let client = build_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    new_job(&client, &mut event_handler, 2, false).await;
+    assert_eq!(last_job_id(&client), Some(2));
+    assert_eq!(*client.deferred_jobs().take_snapshot(), 0);
+
+    let client = build_client(StratumV2Config {
+        job_dispatch_policy: Some(JobDispatchPolicy::PrevHashBoundary),
+        ..Default::default()
+    });
+    let mut event_handler = start_mining(&client).await;
+    assert_eq!(last_job_id(&client), Some(1));
+    new_job(&client, &mut event_handler, 2, false).await;
+    new_job(&client, &mut event_handler, 3, false).await;
+    assert_eq!(last_job_id(&client), Some(1));
+    assert_eq!(*client.deferred_jobs().take_snapshot(), 2);
+
+    // A new prevhash starts solving its job
+    new_job(&client, &mut event_handler, 4, true).await;
+    assert_eq!(last_job_id(&client), Some(1));
+    new_prev_hash(&client, &mut event_handler, 4).await;
+    assert_eq!(last_job_id(&client), Some(4));
+    assert_eq!(*client.deferred_jobs().take_snapshot(), 2);
+}
+
+/// Script a pool that mines 5 blocks of a minute each. The pool refreshes the template every
+/// `refresh` seconds (a single template per block when not set) and it repeats the current
+/// template every second when `duplicates` is set.
+fn script_template_pool(
+    refresh: Option<u64>,
+    duplicates: bool,
+) -> (template_quality::Tracker, Vec<template_quality::Advisory>) {
+    let mut tracker = template_quality::Tracker::default();
+    let mut advisories = Vec::new();
+    let start = time::Instant::now();
+    let merkle_root = |block: u64, template: u64| {
+        let mut merkle_root = [0; 32];
+        merkle_root[0] = block as u8;
+        merkle_root[1] = template as u8;
+        Uint256Bytes(merkle_root)
+    };
+    for block in 0..=5 {
+        let block_start = start + time::Duration::from_secs(block * 60);
+        advisories.extend(tracker.prev_hash(
+            Uint256Bytes([block as u8; 32]),
+            merkle_root(block, 0),
+            block_start,
+            1.0,
+        ));
+        if block == 5 {
+            break;
+        }
+        for second in 1..60 {
+            let refreshed = refresh.map_or(false, |refresh| second % refresh == 0);
+            if refreshed || duplicates {
+                tracker.template(
+                    merkle_root(block, refresh.map_or(0, |refresh| second / refresh)),
+                    block_start + time::Duration::from_secs(second),
+                );
+            }
+        }
+    }
+    (tracker, advisories)
+}
+
+#[test]
+fn test_template_quality() {
+    let fresh = template_quality::Quality {
+        current_templates: 1,
+        blocks: 5,
+        refresh_rate: Some(11.0),
+        median_refresh_interval_ms: Some(5000),
+    };
+    let (tracker, advisories) = script_template_pool(Some(5), false);
+    assert_eq!(tracker.quality(), fresh);
+    assert!(advisories.is_empty());
+
+    // Duplicates of known templates are not refreshes
+    let (tracker, advisories) = script_template_pool(Some(5), true);
+    assert_eq!(tracker.quality(), fresh);
+    assert!(advisories.is_empty());
+
+    // The lazy pool is reported once the rate has been measured over enough blocks
+    let (mut tracker, advisories) = script_template_pool(None, false);
+    assert_eq!(
+        tracker.quality(),
+        template_quality::Quality {
+            current_templates: 1,
+            blocks: 5,
+            refresh_rate: Some(0.0),
+            median_refresh_interval_ms: None,
+        }
+    );
+    assert_eq!(
+        advisories,
+        vec![template_quality::Advisory {
+            refresh_rate: 0.0,
+            floor: 1.0,
+            blocks: template_quality::Tracker::MIN_BLOCKS,
+        }]
+    );
+    assert!(advisories[0]
+        .to_string()
+        .starts_with("pool refreshes job templates 0.00 times per minute"));
+
+    // A new session doesn't repeat the advisory right away
+    tracker.new_session();
+    let now = time::Instant::now() + time::Duration::from_secs(600);
+    assert_eq!(
+        tracker.prev_hash(Uint256Bytes([6; 32]), Uint256Bytes([6; 32]), now, 1.0),
+        None
+    );
+    assert_eq!(
+        tracker.prev_hash(
+            Uint256Bytes([7; 32]),
+            Uint256Bytes([7; 32]),
+            now + template_quality::Tracker::ADVISORY_INTERVAL,
+            1.0
+        ),
+        Some(template_quality::Advisory {
+            refresh_rate: 0.0,
+            floor: 1.0,
+            blocks: 6,
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_template_quality_jobs() {
+    let client = build_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    assert_eq!(client.template_quality().current_templates, 1);
+    new_job(&client, &mut event_handler, 2, false).await;
+    assert_eq!(client.template_quality().current_templates, 2);
+
+    // Aliased duplicate of the active job is not a refresh
+    let duplicate = NewMiningJob {
+        channel_id: 0,
+        job_id: 3,
+        future_job: false,
+        version: 0x20000000,
+        merkle_root: Uint256Bytes([2; 32]),
+    };
+    handle_message(&client, &mut event_handler, duplicate).await;
+    assert_eq!(*client.duplicate_jobs().take_snapshot(), 1);
+    assert_eq!(client.template_quality().current_templates, 2);
+
+    // The refresh sent as a future job is counted once it is activated with the same prevhash
+    new_job(&client, &mut event_handler, 4, true).await;
+    assert_eq!(client.template_quality().current_templates, 2);
+    new_prev_hash(&client, &mut event_handler, 4).await;
+    let quality = client.status_document().template_quality;
+    assert_eq!(quality.current_templates, 3);
+    assert_eq!(quality.blocks, 0);
+    assert!(quality.median_refresh_interval_ms.is_some());
+}
@@ -0,0 +1,784 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+#[tokio::test]
+async fn test_pool_messages_sanitization() {
+    let client = build_client(Default::default());
+    let mut connection_handler = StratumConnectionHandler::new(client.clone(), client.context());
+
+    let code = format!("maintenance\x1b[2J\r\n at 10:00 UTC{}", "x".repeat(200));
+    let frame = SetupConnectionError {
+        flags: 0,
+        code: Str0_255::from_string(code),
+    }
+    .try_into()
+    .expect("BUG: cannot build frame");
+    build_message_from_frame(frame)
+        .expect("BUG: cannot build message")
+        .accept(&mut connection_handler)
+        .await;
+
+    let pool_messages = client.status_document().pool_messages;
+    assert_eq!(pool_messages.len(), 1);
+    let notice = &pool_messages[0];
+    assert_eq!(notice.source, notices::Source::SetupConnectionError);
+    assert!(notice.text.starts_with("maintenance[2J at 10:00 UTC"));
+    assert!(!notice.text.chars().any(char::is_control));
+    assert_eq!(notice.text.chars().count(), notices::MAX_LENGTH);
+    // The connection error carries the sanitized string too
+    let error = connection_handler
+        .status
+        .expect("BUG: missing status")
+        .expect_err("BUG: connection error expected");
+    assert!(error.to_string().contains(&notice.text));
+}
+
+#[tokio::test]
+async fn test_pool_messages_deduplication() {
+    let client = build_client(Default::default());
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+
+    submit_shares_error(&client, &mut event_handler, "stale-share").await;
+    submit_shares_error(&client, &mut event_handler, "stale-share").await;
+    submit_shares_error(&client, &mut event_handler, "invalid\u{7}-nonce").await;
+    submit_shares_error(&client, &mut event_handler, "stale-share").await;
+
+    let texts: Vec<_> = client
+        .pool_messages()
+        .into_iter()
+        .map(|notice| notice.text)
+        .collect();
+    assert_eq!(texts, vec!["stale-share", "invalid-nonce"]);
+    let notified = client
+        .events()
+        .into_iter()
+        .filter(|record| match record.event {
+            events::Event::PoolNotice(_) => true,
+            _ => false,
+        })
+        .count();
+    assert_eq!(notified, 2);
+}
+
+#[test]
+fn test_pool_messages_retention() {
+    let dedup_window = time::Duration::from_secs(60);
+    let board = notices::Board::new(2, dedup_window);
+    let source = notices::Source::SubmitSharesError;
+    let start = time::SystemTime::UNIX_EPOCH;
+
+    assert!(board.post(source, "first", start).is_some());
+    assert!(board.post(source, "second", start).is_some());
+    assert!(board.post(source, "third", start).is_some());
+    let texts: Vec<_> = board.snapshot().into_iter().map(|n| n.text).collect();
+    assert_eq!(texts, vec!["second", "third"]);
+
+    // Repeated notice is reported again once the deduplication window passes
+    assert!(board
+        .post(source, "second", start + dedup_window / 2)
+        .is_none());
+    assert!(board.post(source, "second", start + dedup_window).is_some());
+    let texts: Vec<_> = board.snapshot().into_iter().map(|n| n.text).collect();
+    assert_eq!(texts, vec!["third", "second"]);
+
+    // Strings without any printable content are ignored
+    assert!(board.post(source, "\r\n\t", start).is_none());
+}
+
+#[tokio::test]
+async fn test_share_log() {
+    let path = user_file_path("share-log.csv");
+    let _ = std::fs::remove_file(&path);
+    let client = build_client(StratumV2Config {
+        share_log: Some(path.clone()),
+        ..Default::default()
+    });
+    assert_eq!(client.share_log_dropped(), Some(0));
+    let mut event_handler = start_mining(&client).await;
+    submit_solutions(&client, 3).await;
+    acknowledge(&client, &mut event_handler, 0).await;
+    let message = SubmitSharesError {
+        channel_id: 0,
+        seq_num: 1,
+        code: Str0_32::from_str("stale,\"share\u{7}"),
+    };
+    handle_message(&client, &mut event_handler, message).await;
+    client.discard_pending().await;
+
+    // The file is written in the background
+    let mut lines = Vec::new();
+    for _ in 0..100 {
+        lines = std::fs::read_to_string(&path)
+            .expect("BUG: cannot read share log")
+            .lines()
+            .map(str::to_string)
+            .collect();
+        if lines.len() == 4 {
+            break;
+        }
+        tokio::time::delay_for(time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], share_log::HEADER);
+    let fields: Vec<_> = lines[1..]
+        .iter()
+        .map(|line| {
+            let fields: Vec<_> = line.splitn(5, ',').map(str::to_string).collect();
+            // The reason may contain a comma, the annotation is the last field
+            let mut rest = fields[4].rsplitn(2, ',');
+            let annotation = rest.next().expect("BUG: missing annotation").to_string();
+            let reason = rest.next().expect("BUG: missing reason").to_string();
+            (fields[1].clone(), fields[2].clone(), reason, annotation)
+        })
+        .collect();
+    let field = |seq_num: &str, outcome: &str, reason: &str| {
+        (
+            seq_num.to_string(),
+            outcome.to_string(),
+            reason.to_string(),
+            "\"\"".to_string(),
+        )
+    };
+    assert_eq!(
+        fields,
+        vec![
+            field("0", "accepted", "\"\""),
+            field("1", "rejected", "\"stale,\"\"share\""),
+            field("2", "stale", "\"connection closed\""),
+        ]
+    );
+    assert_eq!(client.share_log_dropped(), Some(0));
+    assert_eq!(client.share_log_failed_writes(), Some(0));
+    std::fs::remove_file(&path).expect("BUG: cannot remove share log");
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_share_log_write_failures() {
+    // Every write to the device fails as if the disk has been full
+    let sink = share_log::Sink::open(std::path::Path::new("/dev/full"))
+        .expect("BUG: cannot open share log");
+    for seq_num in 0..3 {
+        sink.log(share_log::Record {
+            time: time::SystemTime::now(),
+            seq_num,
+            outcome: share_log::Outcome::Accepted,
+            difficulty: 1,
+            reason: String::new(),
+            annotation: String::new(),
+        });
+        // Let the writer flush each record separately
+        for _ in 0..100 {
+            if sink.failed_writes() > seq_num as usize {
+                break;
+            }
+            tokio::time::delay_for(time::Duration::from_millis(10)).await;
+        }
+    }
+    // The failures are counted and the records are not accounted as dropped
+    assert!(sink.failed_writes() >= 3);
+    assert_eq!(sink.dropped(), 0);
+}
+
+/// Beginning of `hour` since the Unix epoch
+fn epoch_hour(hour: u64) -> time::SystemTime {
+    time::UNIX_EPOCH + time::Duration::from_secs(hour * 3600)
+}
+
+#[test]
+fn test_hourly_shares_rollover() {
+    let ring = hourly::Ring::default();
+    // Midnight of 2020-01-01 UTC
+    let base = 438_288;
+    ring.account_accepted(8, epoch_hour(base) + time::Duration::from_secs(10));
+    ring.account_accepted(16, epoch_hour(base) + time::Duration::from_secs(3599));
+    ring.account_rejected(epoch_hour(base + 1));
+    ring.account_reconnect(epoch_hour(base + 1) + time::Duration::from_secs(60));
+
+    let slots = ring.snapshot(epoch_hour(base + 1) + time::Duration::from_secs(1800));
+    assert_eq!(slots.len(), hourly::Ring::HOURS as usize);
+    for (idx, slot) in slots.iter().enumerate() {
+        assert_eq!(slot.time, epoch_hour(base + idx as u64 - 22));
+    }
+    assert_eq!((slots[22].accepted, slots[22].accepted_difficulty), (2, 24));
+    assert_eq!((slots[23].rejected, slots[23].reconnects), (1, 1));
+    assert!(slots[..22].iter().all(|slot| slot.accepted == 0));
+
+    // The window moves with the clock even when nothing is accounted
+    let slots = ring.snapshot(epoch_hour(base + 23));
+    assert_eq!(slots[0].time, epoch_hour(base));
+    assert_eq!(slots[0].accepted, 2);
+    let slots = ring.snapshot(epoch_hour(base + 24));
+    assert_eq!(slots[0].time, epoch_hour(base + 1));
+    assert_eq!(slots[0].rejected, 1);
+
+    ring.reset();
+    assert!(ring
+        .snapshot(epoch_hour(base + 24))
+        .iter()
+        .all(|slot| slot.rejected == 0));
+}
+
+#[test]
+fn test_hourly_shares_gaps() {
+    let ring = hourly::Ring::default();
+    let base = 438_288;
+    ring.account_accepted(1, epoch_hour(base));
+    // The miner has been stopped for several hours, the hours in between stay empty
+    ring.account_stale(epoch_hour(base + 6));
+    let slots = ring.snapshot(epoch_hour(base + 6));
+    assert_eq!(slots[17].time, epoch_hour(base));
+    assert_eq!(slots[17].accepted, 1);
+    let activity =
+        |slot: &hourly::Slot| slot.accepted + slot.rejected + slot.stale + slot.reconnects;
+    assert!(slots[18..23].iter().all(|slot| activity(slot) == 0));
+    assert_eq!(slots[23].stale, 1);
+
+    // Late update within the window lands in its own hour, older one is dropped
+    ring.account_rejected(epoch_hour(base + 3));
+    ring.account_rejected(epoch_hour(base - 20));
+    let slots = ring.snapshot(epoch_hour(base + 6));
+    assert_eq!(slots[20].rejected, 1);
+    assert_eq!(slots.iter().map(|slot| slot.rejected).sum::<u64>(), 1);
+
+    // A gap longer than the window leaves no data
+    ring.account_accepted(1, epoch_hour(base + 40));
+    let slots = ring.snapshot(epoch_hour(base + 40));
+    assert_eq!(slots.iter().map(|slot| slot.accepted).sum::<u64>(), 1);
+    assert_eq!(slots.iter().map(|slot| slot.stale).sum::<u64>(), 0);
+}
+
+#[tokio::test]
+async fn test_hourly_shares_sessions() {
+    let client = build_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    submit_solutions(&client, 3).await;
+    acknowledge(&client, &mut event_handler, 1).await;
+
+    // New session doesn't reset the ring, pending shares of the closed connection are stale
+    let _event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    client.discard_pending().await;
+
+    // The test may cross the hour boundary
+    let slots = client.status_document().hourly_shares;
+    assert_eq!(slots.len(), hourly::Ring::HOURS as usize);
+    assert_eq!(slots.iter().map(|slot| slot.accepted).sum::<u64>(), 2);
+    assert_eq!(slots.iter().map(|slot| slot.stale).sum::<u64>(), 1);
+}
+
+/// Receive the next notification, the receiving must not hang
+async fn next_notification(
+    subscription: &mut notifications::Subscription,
+) -> Result<Option<notifications::Notification>, notifications::Lagged> {
+    tokio::time::timeout(time::Duration::from_secs(1), subscription.recv())
+        .await
+        .expect("BUG: receiving notification has timed out")
+}
+
+fn is_switch_event(notification: &notifications::Notification, seq_num: u64) -> bool {
+    match notification {
+        notifications::Notification::Event(record) => match &record.event {
+            events::Event::Switch(annotation) => annotation.seq_num == seq_num,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn switch_annotation(seq_num: u64) -> switches::Annotation {
+    switches::Annotation {
+        seq_num,
+        time: time::SystemTime::now(),
+        role: switches::Role::Activated,
+        reason: switches::Reason::Manual,
+        peer: "localhost:3336".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_notifications_shutdown() {
+    let client = build_client(Default::default());
+    let mut subscription = client.subscribe();
+
+    assert!(client.status.initiate_starting());
+    assert!(client.status.initiate_running());
+    client.annotate_switch(&switch_annotation(1));
+    assert!(client.status.initiate_stopping());
+    client.annotate_switch(&switch_annotation(2));
+    assert!(client.try_finish());
+    // Nothing is published once the client has stopped
+    client.annotate_switch(&switch_annotation(3));
+
+    let notification = next_notification(&mut subscription).await.unwrap();
+    assert!(is_switch_event(&notification.unwrap(), 1));
+    let notification = next_notification(&mut subscription).await.unwrap();
+    assert!(is_switch_event(&notification.unwrap(), 2));
+    let notification = next_notification(&mut subscription).await.unwrap().unwrap();
+    assert!(notification.is_terminal());
+    match notification {
+        notifications::Notification::StatusChanged(status) => {
+            assert_eq!(status, sync::Status::Stopped)
+        }
+        _ => panic!("BUG: unexpected notification"),
+    }
+    assert!(next_notification(&mut subscription)
+        .await
+        .unwrap()
+        .is_none());
+
+    // Late subscriber receives the terminal state immediately
+    let mut subscription = client.subscribe();
+    let notification = next_notification(&mut subscription).await.unwrap().unwrap();
+    assert!(notification.is_terminal());
+    assert!(next_notification(&mut subscription)
+        .await
+        .unwrap()
+        .is_none());
+
+    // The client is started again and subscriptions are live
+    assert!(client.status.initiate_starting());
+    client.notifications.reopen();
+    let mut subscription = client.subscribe();
+    client.status.initiate_failing();
+    assert!(client.try_finish());
+    let notification = next_notification(&mut subscription).await.unwrap().unwrap();
+    match notification {
+        notifications::Notification::StatusChanged(status) => {
+            assert_eq!(status, sync::Status::Failed)
+        }
+        _ => panic!("BUG: unexpected notification"),
+    }
+    assert!(next_notification(&mut subscription)
+        .await
+        .unwrap()
+        .is_none());
+
+    // Restarting doesn't terminate subscriptions
+    let client = build_client(Default::default());
+    let mut subscription = client.subscribe();
+    restart_client(&client);
+    assert!(!client.try_finish());
+    client.annotate_switch(&switch_annotation(4));
+    let notification = next_notification(&mut subscription).await.unwrap();
+    assert!(is_switch_event(&notification.unwrap(), 4));
+}
+
+#[tokio::test]
+async fn test_notifications_lagged() {
+    let hub = notifications::Hub::new(2);
+    let mut subscription = hub.subscribe();
+    for _ in 0..5 {
+        hub.publish(notifications::Notification::StatusChanged(
+            sync::Status::Running,
+        ));
+    }
+    assert!(hub.terminate(|| Some(sync::Status::Stopped)));
+
+    // The lagging subscriber is told how many notifications it has missed and then it receives
+    // the remaining ones including the terminal one
+    assert_eq!(
+        next_notification(&mut subscription).await.unwrap_err(),
+        notifications::Lagged(4)
+    );
+    let notification = next_notification(&mut subscription).await.unwrap().unwrap();
+    assert!(!notification.is_terminal());
+    let notification = next_notification(&mut subscription).await.unwrap().unwrap();
+    assert!(notification.is_terminal());
+    assert!(next_notification(&mut subscription)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_connected_status() {
+    let client = build_client(Default::default());
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    let mut subscription = client.subscribe();
+    assert!(client.status.initiate_starting());
+    assert!(client.status.initiate_connected());
+    assert_eq!(client.status.status(), sync::Status::Connected);
+
+    // The client runs only once the first job is dispatched
+    new_job(&client, &mut event_handler, 1, true).await;
+    assert_eq!(client.status.status(), sync::Status::Connected);
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    assert_eq!(client.status.status(), sync::Status::Running);
+    match next_notification(&mut subscription).await.unwrap().unwrap() {
+        notifications::Notification::StatusChanged(status) => {
+            assert_eq!(status, sync::Status::Running)
+        }
+        _ => panic!("BUG: unexpected notification"),
+    }
+
+    // Stopping wins over running
+    let client = build_client(Default::default());
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    assert!(client.status.initiate_starting());
+    assert!(client.status.initiate_connected());
+    assert!(client.status.initiate_stopping());
+    assert!(!client.status.initiate_connected());
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    assert_eq!(client.status.status(), sync::Status::Stopping);
+
+    // Failing before the first job is not a failure of a running client
+    let client = build_client(Default::default());
+    assert!(client.status.initiate_starting());
+    assert!(client.status.initiate_connected());
+    client.status.initiate_failing();
+    assert_eq!(client.status.status(), sync::Status::Declining);
+}
+
+/// Fill event log with `count` events pushed at the same time so that the serialized records
+/// differ only in their ids
+fn fill_event_log(log: &events::Log, count: usize) {
+    let time = time::UNIX_EPOCH + time::Duration::from_secs(1_600_000_000);
+    for i in 0..count {
+        let event = events::Event::TargetClamped {
+            requested_difficulty: 1000 + i,
+            applied_difficulty: 1000,
+        };
+        log.push_at(Default::default(), event, time);
+    }
+}
+
+fn export_events(log: &events::Log, since_cursor: u64, max_bytes: usize) -> diagnostics::Chunk {
+    log.export(&diagnostics::ExportDiagnostics {
+        section: diagnostics::Section::Events,
+        since_cursor,
+        max_bytes,
+    })
+}
+
+fn exported_ids(chunk: &diagnostics::Chunk) -> Vec<u64> {
+    chunk
+        .records
+        .iter()
+        .map(|record| {
+            let value: serde_json::Value =
+                serde_json::from_str(record).expect("BUG: invalid exported record");
+            value["id"].as_u64().expect("BUG: missing id")
+        })
+        .collect()
+}
+
+#[test]
+fn test_export_diagnostics_chunks() {
+    let log = events::Log::new(100);
+    fill_event_log(&log, 30);
+
+    let all = export_events(&log, 0, usize::max_value());
+    assert_eq!(exported_ids(&all), (1..=30).collect::<Vec<_>>());
+    assert!(!all.more && !all.truncated);
+    let max_size = all
+        .records
+        .iter()
+        .map(|record| record.len())
+        .max()
+        .expect("BUG: no records");
+
+    let mut cursor = 0;
+    let mut ids = Vec::new();
+    let mut chunks = 0;
+    loop {
+        let chunk = export_events(&log, cursor, 10 * max_size);
+        assert!(!chunk.truncated);
+        assert!(chunk.records.iter().map(|r| r.len()).sum::<usize>() <= 10 * max_size);
+        ids.extend(exported_ids(&chunk));
+        cursor = chunk.cursor;
+        chunks += 1;
+        if !chunk.more {
+            break;
+        }
+    }
+    assert_eq!(chunks, 3);
+    assert_eq!(ids, (1..=30).collect::<Vec<_>>());
+    assert_eq!(cursor, 30);
+
+    // A record larger than the limit is still exported
+    let chunk = export_events(&log, 0, 1);
+    assert_eq!(exported_ids(&chunk), vec![1]);
+    assert!(chunk.more);
+}
+
+#[test]
+fn test_export_diagnostics_eviction() {
+    let log = events::Log::new(10);
+    fill_event_log(&log, 10);
+    let max_size = export_events(&log, 0, usize::max_value())
+        .records
+        .iter()
+        .map(|record| record.len())
+        .max()
+        .expect("BUG: no records");
+
+    let chunk = export_events(&log, 0, 4 * max_size);
+    assert_eq!(exported_ids(&chunk), vec![1, 2, 3, 4]);
+    assert!(chunk.more);
+
+    // Records following the cursor are evicted before the next pull
+    fill_event_log(&log, 6);
+    let chunk = export_events(&log, chunk.cursor, usize::max_value());
+    assert!(chunk.truncated);
+    assert!(!chunk.more);
+    assert_eq!(exported_ids(&chunk), (7..=16).collect::<Vec<_>>());
+
+    // The export continues without truncation from the new cursor
+    fill_event_log(&log, 2);
+    let chunk = export_events(&log, chunk.cursor, usize::max_value());
+    assert!(!chunk.truncated);
+    assert_eq!(exported_ids(&chunk), vec![17, 18]);
+}
+
+#[tokio::test]
+async fn test_export_diagnostics_up_to_date() {
+    let client = build_client(Default::default());
+    let request = |section, since_cursor| diagnostics::ExportDiagnostics {
+        section,
+        since_cursor,
+        max_bytes: 4096,
+    };
+
+    // Nothing has been collected yet
+    let chunk = client.export_diagnostics(&request(diagnostics::Section::Events, 0));
+    assert!(chunk.records.is_empty() && !chunk.more && !chunk.truncated);
+    assert_eq!(chunk.cursor, 0);
+
+    let now = time::SystemTime::now();
+    client
+        .notices
+        .post(notices::Source::SubmitSharesError, "first", now);
+    client
+        .notices
+        .post(notices::Source::SubmitSharesError, "second", now);
+    let chunk = client.export_diagnostics(&request(diagnostics::Section::PoolNotices, 0));
+    assert_eq!(exported_ids(&chunk), vec![1, 2]);
+    assert_eq!(chunk.cursor, 2);
+
+    // Cursor of the newest record returns an empty chunk
+    let chunk = client.export_diagnostics(&request(diagnostics::Section::PoolNotices, 2));
+    assert!(chunk.records.is_empty() && !chunk.more && !chunk.truncated);
+    assert_eq!(chunk.cursor, 2);
+}
+
+/// Feed `estimator` with a synthetic stream at constant hashrate of 1 difficulty 1 share per
+/// second: a steady bucket at difficulty 1, a bucket with 4x difficulty step in the middle in
+/// which `lucky_shares` shares are found after the step (7.5 are expected) and a steady bucket at
+/// difficulty 4. Returns estimates of the three buckets relative to the true hashrate.
+fn hashrate_step_stream(lucky_shares: u32) -> Vec<(f64, f64)> {
+    const HASHES_PER_SHARE: f64 = 4_294_967_296.0;
+    let mut estimator = hashrate::Estimator::new(time::Duration::from_secs(60), 10);
+    let start = time::Instant::now();
+    let at = |secs: f64| start + time::Duration::from_secs_f64(secs);
+
+    estimator.set_difficulty(1.0, start);
+    for i in 0..90 {
+        estimator.account_share(1.0, at(i as f64 + 0.5));
+    }
+    estimator.set_difficulty(4.0, at(90.0));
+    for i in 0..lucky_shares {
+        estimator.account_share(
+            4.0,
+            at(90.0 + 30.0 * (i as f64 + 0.5) / lucky_shares as f64),
+        );
+    }
+    for i in 0..15 {
+        estimator.account_share(4.0, at(120.0 + 4.0 * i as f64 + 2.0));
+    }
+    estimator
+        .buckets(at(180.0))
+        .into_iter()
+        .map(|bucket| {
+            (
+                bucket.raw / HASHES_PER_SHARE,
+                bucket.smoothed / HASHES_PER_SHARE,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_hashrate_target_step() {
+    for &lucky_shares in [6, 9].iter() {
+        let estimates = hashrate_step_stream(lucky_shares);
+        assert_eq!(estimates.len(), 3);
+        // Buckets without the step are estimated exactly
+        for &(raw, smoothed) in [estimates[0], estimates[2]].iter() {
+            assert!((raw - 1.0).abs() < 1e-9);
+            assert!((smoothed - 1.0).abs() < 1e-9);
+        }
+        // The raw estimate swings with the luck of the few shares after the step
+        let (raw, smoothed) = estimates[1];
+        assert!((raw - 1.0).abs() > 0.08, "raw estimate {}", raw);
+        assert!(
+            (smoothed - 1.0).abs() < 0.05,
+            "smoothed estimate {}",
+            smoothed
+        );
+    }
+}
+
+#[test]
+fn test_hashrate_buckets() {
+    let mut estimator = hashrate::Estimator::new(time::Duration::from_secs(10), 3);
+    let start = time::Instant::now();
+    let at = |secs: u64| start + time::Duration::from_secs(secs);
+    assert!(estimator.buckets(start).is_empty());
+
+    estimator.set_difficulty(4.0, start);
+    estimator.account_share(4.0, at(5));
+    // Share solved for the previous target is counted relative to the new one
+    estimator.set_difficulty(8.0, at(10));
+    estimator.account_share(4.0, at(12));
+    let buckets = estimator.buckets(at(20));
+    assert_eq!(buckets.len(), 2);
+    assert_eq!(buckets[1].shares, 1);
+    assert_eq!(buckets[1].regimes, 1);
+    assert_eq!(buckets[1].raw, buckets[1].smoothed);
+
+    // Idle time finishes empty buckets, only `capacity` of them are retained
+    let buckets = estimator.buckets(at(1000));
+    assert_eq!(buckets.len(), 3);
+    assert!(buckets.iter().all(|bucket| bucket.shares == 0));
+}
+
+fn is_not_connected(result: &error::Result<probe::Probe>) -> bool {
+    match result {
+        Err(e) => e.kind() == error::ErrorKind::Client(error::Client::NotConnected),
+        Ok(_) => false,
+    }
+}
+
+#[tokio::test]
+async fn test_probe_latency_not_connected() {
+    let client = build_client(Default::default());
+    // The probe fails right away instead of waiting for the timeout or a connection
+    let result = tokio::time::timeout(
+        time::Duration::from_millis(100),
+        client.probe_latency(time::Duration::from_secs(10)),
+    )
+    .await
+    .expect("BUG: probe waits without connection");
+    assert!(is_not_connected(&result));
+    assert!(client.latency_probes().is_empty());
+}
+
+#[tokio::test]
+async fn test_probe_latency() {
+    let client = build_client(Default::default());
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    client.prober.connect();
+
+    // The next frame from the pool resolves the probe
+    let delay = time::Duration::from_millis(20);
+    let (result, _) = future::join(client.probe_latency(time::Duration::from_secs(5)), async {
+        tokio::time::delay_for(delay).await;
+        set_target(&client, &mut event_handler, 1).await;
+    })
+    .await;
+    let probe = result.expect("BUG: probe failed");
+    assert_eq!(probe.method, probe::Method::NextFrame);
+    let rtt_ms = probe.rtt_ms.expect("BUG: probe timed out");
+    assert!(rtt_ms >= delay.as_millis() as u64 && rtt_ms < 5000);
+
+    // The pool hasn't sent anything in time
+    let probe = client
+        .probe_latency(time::Duration::from_millis(20))
+        .await
+        .expect("BUG: probe failed");
+    assert_eq!(probe.rtt_ms, None);
+
+    // Both results are retained for trending
+    assert_eq!(client.latency_probes().len(), 2);
+    let chunk = client.export_diagnostics(&diagnostics::ExportDiagnostics {
+        section: diagnostics::Section::LatencyProbes,
+        since_cursor: 0,
+        max_bytes: 4096,
+    });
+    assert_eq!(exported_ids(&chunk), vec![1, 2]);
+
+    // Closing the connection cancels a pending probe
+    let (result, _) = future::join(client.probe_latency(time::Duration::from_secs(5)), async {
+        tokio::time::delay_for(delay).await;
+        client.prober.disconnect();
+    })
+    .await;
+    assert!(is_not_connected(&result));
+    assert_eq!(client.latency_probes().len(), 2);
+}
+
+#[tokio::test]
+async fn test_polled_status_document() {
+    let client = build_client(Default::default());
+    let now = time::Instant::now();
+    let document = client.polled_status_document_at(now);
+    assert_eq!(client.status_cache.generation(), 1);
+    assert_eq!(document.status, "Created");
+    assert!(!document.ready);
+
+    // Concurrent polls within the refresh interval are served the cached document
+    let polls: Vec<_> = (0..4)
+        .map(|_| {
+            let client = client.clone();
+            std::thread::spawn(move || {
+                client.polled_status_document_at(now + time::Duration::from_millis(100))
+            })
+        })
+        .collect();
+    for poll in polls {
+        let polled = poll.join().expect("BUG: poll has panicked");
+        assert_eq!(polled.generated_at, document.generated_at);
+    }
+    assert_eq!(client.status_cache.generation(), 1);
+
+    // The status of the client is always current
+    assert!(client.status.initiate_starting());
+    assert!(client.status.initiate_connected());
+    assert!(client.status.initiate_running());
+    let polled = client.polled_status_document_at(now + time::Duration::from_millis(200));
+    assert_eq!(client.status_cache.generation(), 1);
+    assert_eq!(polled.generated_at, document.generated_at);
+    assert_eq!(polled.status, "Running");
+    assert!(polled.ready);
+
+    // The first poll after the interval refreshes the document
+    let interval = time::Duration::from_millis(StratumV2Config::DEFAULT_STATUS_REFRESH_INTERVAL);
+    client.polled_status_document_at(now + interval);
+    assert_eq!(client.status_cache.generation(), 2);
+
+    // The document is assembled on every poll when the cache is disabled
+    let client = build_client(StratumV2Config {
+        status_refresh_interval: Some(0),
+        ..Default::default()
+    });
+    client.polled_status_document_at(now);
+    client.polled_status_document_at(now);
+    assert_eq!(client.status_cache.generation(), 2);
+}
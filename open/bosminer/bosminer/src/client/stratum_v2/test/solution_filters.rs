@@ -0,0 +1,189 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+#[tokio::test]
+async fn test_filter_stats() {
+    let client = build_client(StratumV2Config {
+        submission_window: Some(1),
+        submission_window_policy: Some(SubmissionWindowPolicy::Drop),
+        ..Default::default()
+    });
+    let _event_handler = start_mining(&client).await;
+    let (connection_tx, _connection_rx) = mpsc::unbounded();
+    let mut solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+    let job = client.last_job().expect("BUG: no job has been dispatched");
+    for &time in &[
+        job.time,
+        // The submission window is full
+        job.time,
+        job.time - 10,
+        job.max_time + 1,
+        job.time - 10,
+    ] {
+        let solution = build_solution_at(&client, time).await;
+        assert!(solution_handler.process_solution(solution).await.is_ok());
+    }
+
+    let status = client.status_document().filters;
+    assert_eq!(status.found, 5);
+    assert_eq!(status.passed, 1);
+    assert_eq!(status.dropped, 4);
+    assert_eq!(status.reasons[&filters::Reason::WindowFull], 1);
+    assert_eq!(status.reasons[&filters::Reason::NtimeRegression], 2);
+    assert_eq!(status.reasons[&filters::Reason::NtimeOverrun], 1);
+    assert_eq!(status.reasons[&filters::Reason::BelowPoolTarget], 0);
+    assert_eq!(*client.ntime_regressions().take_snapshot(), 2);
+    let document = serde_json::to_value(client.status_document())
+        .expect("BUG: cannot serialize status document");
+    assert_eq!(
+        document["filters"]["reasons"]["ntime_regression"],
+        serde_json::json!(2)
+    );
+
+    // Repeated drops for the same reason are reported only once
+    let filtered: Vec<_> = client
+        .events()
+        .into_iter()
+        .filter_map(|record| match record.event {
+            events::Event::SolutionsFiltered(dropped) => Some(dropped),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        filtered,
+        vec![
+            filters::Dropped {
+                reason: filters::Reason::WindowFull,
+                total: 1
+            },
+            filters::Dropped {
+                reason: filters::Reason::NtimeRegression,
+                total: 1
+            },
+            filters::Dropped {
+                reason: filters::Reason::NtimeOverrun,
+                total: 1
+            },
+        ]
+    );
+
+    client.reset_filter_stats();
+    assert_eq!(
+        client.filter_stats().status(),
+        filters::Status {
+            reasons: filters::Reason::ALL
+                .iter()
+                .map(|reason| (*reason, 0))
+                .collect(),
+            ..Default::default()
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_foreign_solution() {
+    let client = build_client(Default::default());
+    let _event_handler = start_mining(&client).await;
+    let (connection_tx, _connection_rx) = mpsc::unbounded();
+    let mut solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+    // Solution of a job that hasn't been generated by any Stratum client
+    let test_block = crate::test_utils::TEST_BLOCKS[0];
+    let foreign_solution = work::Solution::new(
+        work::Assignment::new(
+            Arc::new(test_block),
+            vec![work::Midstate {
+                version: test_block.version,
+                state: Default::default(),
+            }],
+            test_block.time,
+        ),
+        TestSolution {
+            target: Default::default(),
+        },
+        None,
+    );
+    assert_eq!(
+        foreign_solution
+            .checked_job::<StratumJob>(client.clone())
+            .expect_err("BUG: foreign job has been accepted"),
+        work::OriginMismatch::JobType
+    );
+    for _ in 0..2 {
+        assert!(solution_handler
+            .process_solution(foreign_solution.clone())
+            .await
+            .is_ok());
+    }
+    assert_eq!(*client.submitted().take_snapshot(), 0);
+    assert!(client.solutions.lock().await.is_empty());
+    assert_eq!(*client.foreign_solutions().take_snapshot(), 2);
+    let status = client.status_document().filters;
+    assert_eq!(status.found, 2);
+    assert_eq!(status.reasons[&filters::Reason::ForeignJob], 2);
+    let foreign: Vec<_> = client
+        .events()
+        .into_iter()
+        .filter_map(|record| match record.event {
+            events::Event::ForeignSolution(mismatch) => Some(mismatch),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(foreign, vec![work::OriginMismatch::JobType]);
+
+    // Solutions of the own jobs keep flowing
+    let solution = build_solution(&client).await;
+    assert!(solution.checked_job::<StratumJob>(client.clone()).is_ok());
+    assert!(solution_handler.process_solution(solution).await.is_ok());
+    assert_eq!(*client.submitted().take_snapshot(), 1);
+    assert_eq!(client.solutions.lock().await.len(), 1);
+    assert_eq!(*client.foreign_solutions().take_snapshot(), 2);
+}
+
+#[test]
+fn test_filter_reasons_golden() {
+    // Reasons are append-only the same way as error codes
+    let golden: Vec<String> = serde_json::from_str(include_str!("../golden/filter_reasons.json"))
+        .expect("BUG: invalid golden file");
+    let names: Vec<_> = filters::Reason::ALL
+        .iter()
+        .map(|reason| {
+            let serialized = serde_json::to_value(reason).expect("BUG: cannot serialize reason");
+            assert_eq!(serialized, serde_json::json!(reason.name()));
+            reason.name().to_string()
+        })
+        .collect();
+    assert_eq!(names, golden);
+    assert!(filters::Reason::ALL
+        .iter()
+        .enumerate()
+        .all(|(idx, reason)| *reason as usize == idx));
+}
@@ -0,0 +1,289 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+const HANDOVER_ENDPOINT: &str = "localhost:3336";
+
+fn handover_snapshot() -> handover::Snapshot {
+    let hour = time::UNIX_EPOCH + time::Duration::from_secs(444_444 * 3600);
+    handover::Snapshot {
+        endpoint: HANDOVER_ENDPOINT.to_string(),
+        exported_at: hour + time::Duration::from_secs(3720),
+        targets: status::Targets {
+            local_difficulty: 512,
+            pool_difficulty: 1024,
+            startup_policy: false,
+        },
+        hashrate: Some(hashrate::Bucket {
+            shares: 42,
+            regimes: 2,
+            raw: 1.5e12,
+            smoothed: 1.25e12,
+        }),
+        lifetime: handover::Lifetime {
+            accepted: handover::Meter {
+                solutions: 40,
+                shares: 40960,
+            },
+            rejected: handover::Meter {
+                solutions: 2,
+                shares: 2048,
+            },
+            stale: handover::Meter {
+                solutions: 1,
+                shares: 1024,
+            },
+            submitted: 43,
+        },
+        retries: status::Retries {
+            connection: 3,
+            channel_open: 1,
+            handshake_reset: 0,
+        },
+        hourly_shares: vec![hourly::Slot {
+            time: hour,
+            accepted: 40,
+            accepted_difficulty: 40960,
+            rejected: 2,
+            stale: 1,
+            reconnects: 3,
+        }],
+        best_share: 123_456,
+    }
+}
+
+/// Decode the state as if it has been handed over right after the export
+fn decode_handover(state: &[u8]) -> handover::Result<handover::Snapshot> {
+    let now = handover_snapshot().exported_at + time::Duration::from_secs(1);
+    handover::Snapshot::decode(
+        state,
+        HANDOVER_ENDPOINT,
+        time::Duration::from_secs(StratumV2Handover::DEFAULT_MAX_AGE),
+        now,
+    )
+}
+
+#[test]
+fn test_handover_codec() {
+    let snapshot = handover_snapshot();
+    let state = snapshot.encode();
+    assert_eq!(decode_handover(&state), Ok(snapshot.clone()));
+
+    let snapshot = handover::Snapshot {
+        hashrate: None,
+        hourly_shares: vec![],
+        ..snapshot
+    };
+    assert_eq!(decode_handover(&snapshot.encode()), Ok(snapshot));
+}
+
+#[test]
+fn test_handover_corruption() {
+    let state = handover_snapshot().encode();
+    // Any damaged byte is detected
+    for i in 0..state.len() {
+        let mut corrupted = state.clone();
+        corrupted[i] ^= 0x01;
+        assert!(decode_handover(&corrupted).is_err(), "byte {}", i);
+    }
+    let mut corrupted = state.clone();
+    corrupted[0] = b'X';
+    assert_eq!(decode_handover(&corrupted), Err(handover::Error::BadMagic));
+    let mut corrupted = state.clone();
+    corrupted[4] = 2;
+    assert_eq!(
+        decode_handover(&corrupted),
+        Err(handover::Error::UnsupportedVersion(2))
+    );
+    let mut corrupted = state.clone();
+    corrupted[20] ^= 0xff;
+    assert_eq!(
+        decode_handover(&corrupted),
+        Err(handover::Error::ChecksumMismatch)
+    );
+    for length in &[0, 8, state.len() - 1] {
+        assert_eq!(
+            decode_handover(&state[..*length]),
+            Err(handover::Error::Truncated)
+        );
+    }
+
+    // Snapshot older than the maximal age is refused
+    let exported_at = handover_snapshot().exported_at;
+    assert_eq!(
+        handover::Snapshot::decode(
+            &state,
+            HANDOVER_ENDPOINT,
+            time::Duration::from_secs(60),
+            exported_at + time::Duration::from_secs(61),
+        ),
+        Err(handover::Error::Expired {
+            age: 61,
+            max_age: 60
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_handover_endpoint_mismatch() {
+    let state = handover_snapshot().encode();
+    assert_eq!(
+        handover::Snapshot::decode(
+            &state,
+            "localhost:3337",
+            time::Duration::from_secs(StratumV2Handover::DEFAULT_MAX_AGE),
+            handover_snapshot().exported_at,
+        ),
+        Err(handover::Error::EndpointMismatch {
+            expected: "localhost:3337".to_string(),
+            found: HANDOVER_ENDPOINT.to_string(),
+        })
+    );
+
+    let client = build_client(Default::default());
+    let state = client.export_state().await;
+    let mut connection_details = build_connection_details(Default::default());
+    connection_details.port = 3337;
+    match StratumClient::with_imported_state(connection_details, None, build_solver(), None, &state)
+    {
+        Err(handover::Error::EndpointMismatch { expected, found }) => {
+            assert_eq!(expected, "localhost:3337");
+            assert_eq!(found, HANDOVER_ENDPOINT);
+        }
+        _ => panic!("BUG: state of another endpoint has been imported"),
+    }
+}
+
+#[test]
+fn test_handover_forward_compatibility() {
+    let snapshot = handover_snapshot();
+    let state = snapshot.encode();
+    // A later release appends fields to the payload
+    let unknown_fields = [0xde, 0xad, 0xbe, 0xef, 0x01, 0x02];
+    let payload_end = state.len() - 4;
+    let mut extended = state[..payload_end].to_vec();
+    extended.extend_from_slice(&unknown_fields);
+    let length = (payload_end - 9 + unknown_fields.len()) as u32;
+    extended[5..9].copy_from_slice(&length.to_le_bytes());
+    let checksum = ii_bitcoin::DHash::hash(&extended).into_inner();
+    extended.extend_from_slice(&checksum[..4]);
+
+    assert_eq!(decode_handover(&extended), Ok(snapshot));
+}
+
+#[tokio::test]
+async fn test_handover_round_trip() {
+    let client = build_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    submit_solutions(&client, 3).await;
+    let message = SubmitSharesSuccess {
+        channel_id: 0,
+        last_seq_num: 1,
+        new_submits_accepted_count: 2,
+        new_shares_sum: 2,
+    };
+    handle_message(&client, &mut event_handler, message).await;
+    client.discard_pending().await;
+    client.connection_retries.inc();
+    client
+        .hourly_shares
+        .account_reconnect(time::SystemTime::now());
+    settle_accounting(&client.accounting).await;
+
+    let state = client.export_state().await;
+    let imported = StratumClient::with_imported_state(
+        build_connection_details(Default::default()),
+        None,
+        build_solver(),
+        None,
+        &state,
+    )
+    .expect("BUG: state has not been imported");
+    assert_eq!(imported.targets(), client.targets());
+    assert_eq!(imported.retries().connection, 1);
+    assert_eq!(*imported.submitted().take_snapshot(), 3);
+    assert_eq!(
+        imported
+            .client_stats
+            .accepted
+            .take_snapshot()
+            .await
+            .solutions,
+        2
+    );
+    assert_eq!(
+        imported.client_stats.stale.take_snapshot().await.solutions,
+        1
+    );
+    assert_eq!(
+        imported.client_stats.best_share.take_snapshot().map(|d| *d),
+        client.client_stats.best_share.take_snapshot().map(|d| *d)
+    );
+
+    // The imported state is handed over again unchanged
+    let now = time::SystemTime::now();
+    let max_age = time::Duration::from_secs(StratumV2Handover::DEFAULT_MAX_AGE);
+    let snapshot = handover::Snapshot::decode(&state, HANDOVER_ENDPOINT, max_age, now)
+        .expect("BUG: cannot decode state");
+    let reexported = handover::Snapshot::decode(
+        &imported.export_state().await,
+        HANDOVER_ENDPOINT,
+        max_age,
+        now,
+    )
+    .expect("BUG: cannot decode state");
+    assert_eq!(
+        handover::Snapshot {
+            exported_at: snapshot.exported_at,
+            ..reexported
+        },
+        snapshot
+    );
+}
+
+#[tokio::test]
+async fn test_handover_file() {
+    let path = user_file_path("handover.bin");
+    let _ = std::fs::remove_file(&path);
+    let config = StratumV2Config {
+        handover: Some(StratumV2Handover {
+            path: path.clone(),
+            max_age: None,
+        }),
+        ..Default::default()
+    };
+    // Nothing is imported when there is no snapshot
+    let client = StratumClient::with_handover(
+        build_connection_details(config.clone()),
+        None,
+        build_solver(),
+        None,
+    );
+    client.connection_retries.inc();
+    client.write_handover().await;
+
+    let client =
+        StratumClient::with_handover(build_connection_details(config), None, build_solver(), None);
+    assert_eq!(client.retries().connection, 1);
+    std::fs::remove_file(&path).expect("BUG: cannot remove handover file");
+}
@@ -0,0 +1,947 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+#[tokio::test]
+async fn test_wedged_submit() {
+    let client = build_client(Default::default());
+    let share_msg = SubmitSharesStandard {
+        channel_id: 0,
+        seq_num: 0,
+        job_id: 0,
+        nonce: 0,
+        ntime: 0,
+        version: 0,
+    };
+
+    let (connection_tx, _connection_rx) = mpsc::unbounded();
+    let solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+    assert!(solution_handler.submit(share_msg.clone()).await.is_ok());
+    assert_eq!(*client.wedged_sends().take_snapshot(), 0);
+
+    // The receiving end is never read so the sender cannot complete the send
+    let (connection_tx, _connection_rx) = mpsc::channel(0);
+    let solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+    assert!(solution_handler.submit(share_msg).await.is_err());
+    assert_eq!(*client.wedged_sends().take_snapshot(), 1);
+}
+
+/// Sink that fails the first `failures` sends and collects all frames sent afterwards
+#[derive(Debug, Default)]
+struct FlakySink {
+    failures: usize,
+    frames: Vec<<Framing as ii_wire::Framing>::Tx>,
+}
+
+impl Sink<<Framing as ii_wire::Framing>::Tx> for FlakySink {
+    type Error = ii_stratum::error::Error;
+
+    fn poll_ready(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut futures::task::Context<'_>,
+    ) -> futures::task::Poll<Result<(), Self::Error>> {
+        futures::task::Poll::Ready(Ok(()))
+    }
+
+    fn start_send(
+        mut self: std::pin::Pin<&mut Self>,
+        frame: <Framing as ii_wire::Framing>::Tx,
+    ) -> Result<(), Self::Error> {
+        if self.failures > 0 {
+            self.failures -= 1;
+            Err(ii_stratum::error::ErrorKind::General("transient failure".to_string()).into())
+        } else {
+            self.frames.push(frame);
+            Ok(())
+        }
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut futures::task::Context<'_>,
+    ) -> futures::task::Poll<Result<(), Self::Error>> {
+        futures::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut futures::task::Context<'_>,
+    ) -> futures::task::Poll<Result<(), Self::Error>> {
+        futures::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn test_submit_retry() {
+    let share_msg = SubmitSharesStandard {
+        channel_id: 0,
+        seq_num: 7,
+        job_id: 0,
+        nonce: 0,
+        ntime: 0,
+        version: 0,
+    };
+
+    // Every share is sent only once by default
+    let client = build_client(Default::default());
+    let connection_tx = Arc::new(Mutex::new(FlakySink {
+        failures: 1,
+        ..Default::default()
+    }));
+    let solution_handler =
+        StratumSolutionHandler::new(client.clone(), connection_tx.clone(), client.context());
+    assert!(solution_handler.submit(share_msg.clone()).await.is_err());
+    assert!(connection_tx.lock().await.frames.is_empty());
+    assert_eq!(*client.submit_retries().take_snapshot(), 0);
+
+    let client = build_client(StratumV2Config {
+        submit_attempts: Some(3),
+        ..Default::default()
+    });
+    let connection_tx = Arc::new(Mutex::new(FlakySink {
+        failures: 2,
+        ..Default::default()
+    }));
+    let solution_handler =
+        StratumSolutionHandler::new(client.clone(), connection_tx.clone(), client.context());
+    assert!(solution_handler.submit(share_msg.clone()).await.is_ok());
+    assert_eq!(*client.submit_retries().take_snapshot(), 2);
+    let frames = std::mem::replace(&mut connection_tx.lock().await.frames, Vec::new());
+    assert_eq!(frames.len(), 1);
+    let expected_frame: <Framing as ii_wire::Framing>::Tx = share_msg
+        .clone()
+        .try_into()
+        .expect("BUG: cannot build frame");
+    assert_eq!(format!("{:?}", frames[0]), format!("{:?}", expected_frame));
+
+    // All attempts are exhausted
+    let connection_tx = Arc::new(Mutex::new(FlakySink {
+        failures: 3,
+        ..Default::default()
+    }));
+    let solution_handler =
+        StratumSolutionHandler::new(client.clone(), connection_tx.clone(), client.context());
+    assert!(solution_handler.submit(share_msg).await.is_err());
+    assert!(connection_tx.lock().await.frames.is_empty());
+    assert_eq!(*client.submit_retries().take_snapshot(), 4);
+}
+
+#[tokio::test]
+async fn test_out_of_mask_versions() {
+    let job_version = 0x20000000;
+    let rolled_version = job_version | 0x00002000;
+    // Bit 30 is outside of the BIP320 range
+    let out_of_mask_version = rolled_version | 0x40000000;
+    for &(policy, submitted_version) in &[
+        (None, None),
+        (Some(OutOfMaskVersions::Drop), None),
+        (Some(OutOfMaskVersions::Mask), Some(rolled_version)),
+        (Some(OutOfMaskVersions::Submit), Some(out_of_mask_version)),
+    ] {
+        let client = build_client(StratumV2Config {
+            out_of_mask_versions: policy,
+            ..Default::default()
+        });
+        let _event_handler = start_mining(&client).await;
+        let (connection_tx, mut connection_rx) = mpsc::unbounded();
+        let mut solution_handler = StratumSolutionHandler::new(
+            client.clone(),
+            Arc::new(Mutex::new(connection_tx)),
+            client.context(),
+        );
+        for &version in &[rolled_version, out_of_mask_version] {
+            let job = client.last_job().expect("BUG: no job has been dispatched");
+            assert_eq!(job.version, job_version);
+            let time = job.time;
+            let midstate = work::Midstate {
+                version,
+                state: Default::default(),
+            };
+            let solution = work::Solution::new(
+                work::Assignment::new(job, vec![midstate], time),
+                TestSolution {
+                    target: Default::default(),
+                },
+                None,
+            );
+            assert!(solution_handler.process_solution(solution).await.is_ok());
+        }
+        assert_eq!(*client.out_of_mask_versions().take_snapshot(), 1);
+
+        let mut share_collector = ShareCollector::default();
+        while let Ok(Some(frame)) = connection_rx.try_next() {
+            v2::build_message_from_frame(frame)
+                .expect("BUG: cannot build message")
+                .accept(&mut share_collector)
+                .await;
+        }
+        let versions: Vec<_> = share_collector
+            .shares
+            .iter()
+            .map(|share| share.version)
+            .collect();
+        let expected: Vec<_> = std::iter::once(rolled_version)
+            .chain(submitted_version)
+            .collect();
+        assert_eq!(versions, expected, "{:?}", policy);
+    }
+}
+
+#[tokio::test]
+async fn test_configured_version_mask_clipped() {
+    // Bits 13-14 are within the negotiated mask, bit 30 and bits 0-3 are outside of it
+    let configured_mask = 0x40006000 | 0x0000000f;
+    let client = build_client(StratumV2Config {
+        version_mask: Some(configured_mask),
+        ..Default::default()
+    });
+
+    // The bits outside of the negotiated mask are the ones reported by the warning
+    assert_eq!(
+        StratumClient::clip_version_mask(configured_mask),
+        (0x00006000, 0x4000000f)
+    );
+    // A subset of the negotiated mask is used as it is and it isn't warned about
+    assert_eq!(
+        StratumClient::clip_version_mask(0x00006000),
+        (0x00006000, 0)
+    );
+
+    // Sessions roll only the negotiated bits of the configured mask
+    replay::replay_session(client.clone(), &build_negotiated_capture(0, 1), false)
+        .await
+        .expect("BUG: replay failed");
+    let session = client.session().expect("BUG: no session");
+    assert_eq!(session.version_mask, 0x00006000);
+    let job = client.last_job().expect("BUG: no job has been received");
+    assert_eq!(job::Bitcoin::version_mask(job.as_ref()), 0x00006000);
+
+    // Pool that requires fixed version disables the rolling regardless of the configured mask
+    replay::replay_session(
+        client.clone(),
+        &build_negotiated_capture(capabilities::REQUIRES_FIXED_VERSION, 2),
+        false,
+    )
+    .await
+    .expect("BUG: replay failed");
+    assert_eq!(client.session().expect("BUG: no session").version_mask, 0);
+}
+
+#[tokio::test]
+async fn test_duplicate_job_submission() {
+    let client = build_client(Default::default());
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    // Every solution meets the easiest possible pool target
+    let message = SetTarget {
+        channel_id: 0,
+        max_target: Uint256Bytes([0xff; 32]),
+    };
+    handle_message(&client, &mut event_handler, message).await;
+
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    let job_msg = NewMiningJob {
+        job_id: 2,
+        future_job: false,
+        ..event_handler.all_jobs[&1].clone()
+    };
+    handle_message(&client, &mut event_handler, job_msg).await;
+
+    // The solution of the dispatched job is submitted under the most recent ID
+    let solution = build_solution(&client).await;
+    let (connection_tx, mut connection_rx) = mpsc::unbounded();
+    let mut solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+    assert!(solution_handler.process_solution(solution).await.is_ok());
+
+    let frame = connection_rx
+        .try_next()
+        .expect("BUG: no frame has been sent")
+        .expect("BUG: connection closed");
+    let mut share_collector = ShareCollector::default();
+    v2::build_message_from_frame(frame)
+        .expect("BUG: cannot build message")
+        .accept(&mut share_collector)
+        .await;
+    assert_eq!(share_collector.shares.len(), 1);
+    assert_eq!(share_collector.shares[0].job_id, 2);
+    assert_eq!(client.solutions.lock().await.len(), 1);
+
+    // Acknowledgement of the share submitted under the alias resolves the solution
+    let message = SubmitSharesSuccess {
+        channel_id: 0,
+        last_seq_num: share_collector.shares[0].seq_num,
+        new_submits_accepted_count: 1,
+        new_shares_sum: 1,
+    };
+    handle_message(&client, &mut event_handler, message).await;
+    assert!(client.solutions.lock().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_flushed_job_solutions() {
+    for &(policy, submitted, counted) in &[
+        (None, 0, 1),
+        (Some(FlushedJobSolutions::Drop), 0, 1),
+        (Some(FlushedJobSolutions::Submit), 1, 0),
+        (Some(FlushedJobSolutions::SubmitAndCount), 1, 1),
+    ] {
+        let client = build_client(StratumV2Config {
+            flushed_job_solutions: policy,
+            ..Default::default()
+        });
+        let mut event_handler = start_mining(&client).await;
+        // Solutions of the current job are not affected
+        submit_solutions(&client, 1).await;
+        assert_eq!(*client.flushed_job_solutions().take_snapshot(), 0);
+
+        // The job is flushed by a new block before its solution is processed
+        let solution = build_solution(&client).await;
+        new_job(&client, &mut event_handler, 2, true).await;
+        let message = SetNewPrevHash {
+            channel_id: 0,
+            job_id: 2,
+            prev_hash: Uint256Bytes([0xcc; 32]),
+            min_ntime: 0x5e000000,
+            nbits: 0x1d00ffff,
+        };
+        handle_message(&client, &mut event_handler, message).await;
+        assert_eq!(last_job_id(&client), Some(2));
+
+        let (connection_tx, _connection_rx) = mpsc::unbounded();
+        let mut solution_handler = StratumSolutionHandler::new(
+            client.clone(),
+            Arc::new(Mutex::new(connection_tx)),
+            client.context(),
+        );
+        assert!(solution_handler.process_solution(solution).await.is_ok());
+        assert_eq!(
+            *client.submitted().take_snapshot(),
+            1 + submitted,
+            "{:?}",
+            policy
+        );
+        assert_eq!(
+            *client.flushed_job_solutions().take_snapshot(),
+            counted,
+            "{:?}",
+            policy
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_pending_submissions() {
+    let client = build_client(Default::default());
+    let mut event_handler = start_mining(&client).await;
+    submit_solutions(&client, 3).await;
+
+    let pending = client.pending_submissions().await;
+    let seq_nums: Vec<_> = pending.iter().map(|pending| pending.seq_num).collect();
+    assert_eq!(seq_nums, vec![0, 1, 2]);
+    assert!(pending.iter().all(|pending| pending.difficulty == 0));
+    assert!(pending[0].age >= pending[2].age);
+    // Taking the snapshot doesn't disturb the queue
+    assert_eq!(client.solutions.lock().await.len(), 3);
+
+    let message = SubmitSharesSuccess {
+        channel_id: 0,
+        last_seq_num: 1,
+        new_submits_accepted_count: 2,
+        new_shares_sum: 2,
+    };
+    handle_message(&client, &mut event_handler, message).await;
+    let pending = client.pending_submissions().await;
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].seq_num, 2);
+}
+
+#[tokio::test]
+async fn test_submission_window_hold() {
+    let config = StratumV2Config {
+        submission_window: Some(2),
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+    let client = build_client(config);
+    let mut event_handler = start_mining(&client).await;
+    let (connection_tx, mut connection_rx) = mpsc::unbounded();
+    let mut solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+
+    // Two solutions are submitted, two most recent ones are held and the remaining one dropped
+    for _ in 0..5 {
+        let solution = build_solution(&client).await;
+        assert!(solution_handler.process_solution(solution).await.is_ok());
+    }
+    assert_eq!(pending_seq_nums(&client).await, vec![0, 1]);
+    assert_eq!(solution_handler.held.len(), 2);
+    assert_eq!(*client.window_drops().take_snapshot(), 1);
+
+    // Acknowledgements free space for the held solutions
+    acknowledge(&client, &mut event_handler, 0).await;
+    assert!(solution_handler.submit_held().await.is_ok());
+    assert_eq!(pending_seq_nums(&client).await, vec![1, 2]);
+    acknowledge(&client, &mut event_handler, 2).await;
+    assert!(solution_handler.submit_held().await.is_ok());
+    assert_eq!(pending_seq_nums(&client).await, vec![3]);
+    assert!(solution_handler.held.is_empty());
+
+    // New solution is submitted immediately when there is space in the window
+    let solution = build_solution(&client).await;
+    assert!(solution_handler.process_solution(solution).await.is_ok());
+    assert_eq!(pending_seq_nums(&client).await, vec![3, 4]);
+
+    let mut share_collector = ShareCollector::default();
+    while let Ok(Some(frame)) = connection_rx.try_next() {
+        v2::build_message_from_frame(frame)
+            .expect("BUG: cannot build message")
+            .accept(&mut share_collector)
+            .await;
+    }
+    let seq_nums: Vec<_> = share_collector
+        .shares
+        .iter()
+        .map(|share| share.seq_num)
+        .collect();
+    assert_eq!(seq_nums, vec![0, 1, 2, 3, 4]);
+}
+
+#[tokio::test]
+async fn test_submission_window_drop() {
+    let client = build_client(StratumV2Config {
+        submission_window: Some(2),
+        submission_window_policy: Some(SubmissionWindowPolicy::Drop),
+        ..Default::default()
+    });
+    let mut event_handler = start_mining(&client).await;
+    submit_solutions(&client, 4).await;
+    assert_eq!(pending_seq_nums(&client).await, vec![0, 1]);
+    assert_eq!(*client.window_drops().take_snapshot(), 2);
+
+    acknowledge(&client, &mut event_handler, 1).await;
+    submit_solutions(&client, 1).await;
+    assert_eq!(client.pending_submissions().await.len(), 1);
+    assert_eq!(*client.window_drops().take_snapshot(), 2);
+
+    assert!(StratumV2Config {
+        submission_window: Some(0),
+        ..Default::default()
+    }
+    .validate()
+    .is_err());
+}
+
+#[tokio::test]
+async fn test_restart_drain() {
+    let grace = time::Duration::from_millis(200);
+    let config = StratumV2Config {
+        restart_drain_grace: Some(grace.as_millis() as u64),
+        ..Default::default()
+    };
+
+    // Shares are drained only when restarting
+    let client = build_client(config.clone());
+    let _event_handler = start_mining(&client).await;
+    submit_solutions(&client, 3).await;
+    assert!(client.status.initiate_starting());
+    assert!(client.status.initiate_running());
+    assert!(client.status.initiate_stopping());
+    let mut run = future::pending::<()>().fuse();
+    client.drain_submissions(&mut run).await;
+    assert_eq!(client.solutions.lock().await.len(), 3);
+
+    // All shares are acknowledged within the grace period
+    let client = build_client(config.clone());
+    let mut event_handler = start_mining(&client).await;
+    submit_solutions(&client, 3).await;
+    restart_client(&client);
+    let start = time::Instant::now();
+    let acknowledge = async {
+        tokio::time::delay_for(time::Duration::from_millis(20)).await;
+        let message = SubmitSharesSuccess {
+            channel_id: 0,
+            last_seq_num: 2,
+            new_submits_accepted_count: 3,
+            new_shares_sum: 3,
+        };
+        handle_message(&client, &mut event_handler, message).await;
+    };
+    future::join(client.drain_submissions(&mut run), acknowledge).await;
+    assert!(start.elapsed() < grace);
+    client.discard_pending().await;
+    let stats = &client.client_stats;
+    assert_eq!(stats.accepted.take_snapshot().await.solutions, 3);
+    assert_eq!(stats.stale.take_snapshot().await.solutions, 0);
+
+    // Unacknowledged shares are accounted as stale once the grace period expires
+    let client = build_client(config);
+    let mut event_handler = start_mining(&client).await;
+    submit_solutions(&client, 3).await;
+    restart_client(&client);
+    let start = time::Instant::now();
+    let acknowledge = async {
+        tokio::time::delay_for(time::Duration::from_millis(20)).await;
+        let message = SubmitSharesSuccess {
+            channel_id: 0,
+            last_seq_num: 0,
+            new_submits_accepted_count: 1,
+            new_shares_sum: 1,
+        };
+        handle_message(&client, &mut event_handler, message).await;
+        // Nothing is submitted while draining
+        assert!(client.is_draining());
+        submit_solutions(&client, 1).await;
+        assert_eq!(client.solutions.lock().await.len(), 2);
+    };
+    future::join(client.drain_submissions(&mut run), acknowledge).await;
+    assert!(start.elapsed() >= grace);
+    assert!(!client.is_draining());
+    client.discard_pending().await;
+    let stats = &client.client_stats;
+    assert_eq!(stats.accepted.take_snapshot().await.solutions, 1);
+    assert_eq!(stats.stale.take_snapshot().await.solutions, 2);
+}
+
+/// Held entry with a synthetic retained size
+struct SyntheticEntry {
+    size: usize,
+    shared: Option<(usize, usize)>,
+}
+
+impl held::Retained for SyntheticEntry {
+    fn retained_size(&self) -> usize {
+        self.size
+    }
+
+    fn shared(&self) -> Option<(usize, usize)> {
+        self.shared
+    }
+}
+
+#[tokio::test]
+async fn test_held_solutions_accounting() {
+    // Entries that share a part are accounted with the part only once
+    let mut queue = held::Queue::new(usize::MAX, Default::default());
+    for _ in 0..10 {
+        let entry = SyntheticEntry {
+            size: 100,
+            shared: Some((1, 1000)),
+        };
+        assert_eq!(queue.push_back(entry, usize::MAX), 0);
+    }
+    assert_eq!(queue.stats().bytes, 10 * 100 + 1000);
+    queue.push_back(
+        SyntheticEntry {
+            size: 100,
+            shared: Some((2, 500)),
+        },
+        usize::MAX,
+    );
+    assert_eq!(queue.stats().bytes, 11 * 100 + 1000 + 500);
+    for _ in 0..10 {
+        assert!(queue.pop_front().is_some());
+    }
+    assert_eq!(queue.stats().bytes, 100 + 500);
+    assert!(queue.pop_front().is_some());
+    assert!(queue.pop_front().is_none());
+    assert_eq!(queue.stats().bytes, 0);
+    assert_eq!(queue.stats().entries, 0);
+
+    // Real solutions of a single job share the job
+    let client = build_client(Default::default());
+    let _event_handler = start_mining(&client).await;
+    let mut queue = held::Queue::new(usize::MAX, Default::default());
+    let solution = build_solution(&client).await;
+    let own_size = solution.retained_size();
+    let (_, job_size) = solution.shared_job();
+    queue.push_back(solution, usize::MAX);
+    queue.push_back(build_solution(&client).await, usize::MAX);
+    assert_eq!(queue.stats().bytes, 2 * own_size + job_size);
+}
+
+#[tokio::test]
+async fn test_held_solutions_byte_cap() {
+    let large_entry = || SyntheticEntry {
+        size: 400,
+        shared: None,
+    };
+    let mut queue = held::Queue::new(1000, Default::default());
+    assert_eq!(queue.push_back(large_entry(), 10), 0);
+    assert_eq!(queue.push_back(large_entry(), 10), 0);
+    // The byte cap is reached long before the count cap
+    assert_eq!(queue.push_back(large_entry(), 10), 1);
+    assert_eq!(queue.len(), 2);
+    let stats = queue.stats();
+    assert_eq!(stats.bytes, 800);
+    assert_eq!(
+        stats.evictions,
+        held::Evictions {
+            count_cap: 0,
+            byte_cap: 1,
+        }
+    );
+
+    // The count cap still applies
+    let mut queue = held::Queue::new(1000, stats);
+    for _ in 0..3 {
+        queue.push_back(
+            SyntheticEntry {
+                size: 10,
+                shared: None,
+            },
+            2,
+        );
+    }
+    assert_eq!(queue.len(), 2);
+    assert_eq!(
+        queue.stats().evictions,
+        held::Evictions {
+            count_cap: 1,
+            byte_cap: 1,
+        }
+    );
+
+    // An entry larger than the cap is kept alone
+    let mut queue = held::Queue::new(100, Default::default());
+    queue.push_back(large_entry(), 10);
+    queue.push_back(large_entry(), 10);
+    assert_eq!(queue.len(), 1);
+    assert_eq!(queue.stats().bytes, 400);
+}
+
+#[tokio::test]
+async fn test_held_solutions_high_water() {
+    let config = StratumV2Config {
+        submission_window: Some(1),
+        ..Default::default()
+    };
+    let client = build_client(config);
+    let mut event_handler = start_mining(&client).await;
+    let (connection_tx, _connection_rx) = mpsc::unbounded();
+    let mut solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+    for _ in 0..3 {
+        let solution = build_solution(&client).await;
+        assert!(solution_handler.process_solution(solution).await.is_ok());
+    }
+    let stats = client.held_solutions();
+    assert_eq!(stats.entries, 1);
+    assert_eq!(stats.high_water_entries, 1);
+    assert_eq!(stats.evictions.count_cap, 1);
+    let held_bytes = stats.bytes;
+    assert!(held_bytes > 0);
+
+    acknowledge(&client, &mut event_handler, 0).await;
+    assert!(solution_handler.submit_held().await.is_ok());
+    let stats = client.status_document().held_solutions;
+    assert_eq!(stats.entries, 0);
+    assert_eq!(stats.bytes, 0);
+    // High-water marks persist across connections
+    assert_eq!(stats.high_water_bytes, held_bytes);
+    let (connection_tx, _connection_rx) = mpsc::unbounded();
+    let _solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+    assert_eq!(client.held_solutions().high_water_entries, 1);
+    assert_eq!(client.held_solutions().evictions.count_cap, 1);
+}
+
+#[tokio::test]
+async fn test_nonce_byte_order() {
+    for &(byte_order, submitted_nonce) in &[
+        (None, 0x12345678),
+        (Some(NonceByteOrder::Spec), 0x12345678),
+        (Some(NonceByteOrder::Swapped), 0x78563412),
+    ] {
+        let client = build_client(StratumV2Config {
+            nonce_byte_order: byte_order,
+            ..Default::default()
+        });
+        let _event_handler = start_mining(&client).await;
+        let (connection_tx, mut connection_rx) = mpsc::unbounded();
+        let mut solution_handler = StratumSolutionHandler::new(
+            client.clone(),
+            Arc::new(Mutex::new(connection_tx)),
+            client.context(),
+        );
+        let solution = build_solution(&client).await;
+        assert!(solution_handler.process_solution(solution).await.is_ok());
+
+        let mut share_collector = ShareCollector::default();
+        while let Ok(Some(frame)) = connection_rx.try_next() {
+            v2::build_message_from_frame(frame)
+                .expect("BUG: cannot build message")
+                .accept(&mut share_collector)
+                .await;
+        }
+        let nonces: Vec<_> = share_collector
+            .shares
+            .iter()
+            .map(|share| share.nonce)
+            .collect();
+        assert_eq!(nonces, vec![submitted_nonce], "{:?}", byte_order);
+    }
+}
+
+/// Flag of `SetupConnectionSuccess` by which the mock pool accepts annotated submissions
+const MOCK_ANNOTATION_FLAG: u32 = 0x8000_0000;
+
+/// Extension type of submissions annotated by the mock carrier
+const MOCK_ANNOTATION_EXTENSION: u16 = 0x4242;
+
+/// Carrier that fakes the extension: the payload of the share is prefixed with the length of the
+/// annotation and the annotation
+#[derive(Debug)]
+struct MockCarrier;
+
+impl annotation::Carrier for MockCarrier {
+    fn accepts(&self, negotiated: &status::Negotiated) -> bool {
+        negotiated.flags & MOCK_ANNOTATION_FLAG != 0
+    }
+
+    fn annotate(
+        &self,
+        share_msg: SubmitSharesStandard,
+        annotation: &str,
+    ) -> ii_stratum::error::Result<v2::Frame> {
+        let frame: v2::Frame = share_msg.try_into()?;
+        let (header, payload) = frame.split();
+        let mut annotated = ii_async_compat::bytes::BytesMut::new();
+        annotated.extend_from_slice(&[annotation.len() as u8]);
+        annotated.extend_from_slice(annotation.as_bytes());
+        annotated.extend_from_slice(&payload.into_bytes_mut()?);
+        Ok(v2::Frame::from_serialized_payload(
+            header.is_channel_message,
+            MOCK_ANNOTATION_EXTENSION,
+            header.msg_type,
+            annotated,
+        ))
+    }
+}
+
+fn set_submission_annotation(client: &Arc<StratumClient>, annotation: Option<&str>) {
+    let mut connection_details = client
+        .connection_details
+        .lock()
+        .expect("BUG: cannot lock connection details");
+    Arc::make_mut(&mut connection_details.config).submission_annotation =
+        annotation.map(str::to_string);
+}
+
+fn set_negotiated_flags(client: &Arc<StratumClient>, flags: u32) {
+    client.set_session(Some(Arc::new(session::State {
+        negotiated: status::Negotiated {
+            flags,
+            ..Default::default()
+        },
+        ..Default::default()
+    })));
+}
+
+/// Submit a share and return its sequence number along with the annotation it carries
+async fn submit_annotated_share<S, E>(
+    client: &Arc<StratumClient>,
+    solution_handler: &mut StratumSolutionHandler<S>,
+    connection_rx: &mut mpsc::UnboundedReceiver<v2::Frame>,
+) -> (u32, Option<String>)
+where
+    E: Into<error::Error>,
+    S: Sink<v2::Frame, Error = E> + std::marker::Unpin + std::fmt::Debug + 'static,
+{
+    let solution = build_solution(client).await;
+    assert!(solution_handler.submit_solution(solution).await.is_ok());
+    let frame = connection_rx
+        .try_next()
+        .expect("BUG: no frame has been sent")
+        .expect("BUG: connection closed");
+    if frame.header.extension_type != MOCK_ANNOTATION_EXTENSION {
+        let share = SubmitSharesStandard::try_from(frame).expect("BUG: cannot decode share");
+        return (share.seq_num, None);
+    }
+    let (_, payload) = frame.split();
+    let payload = payload.into_bytes_mut().expect("BUG: cannot read payload");
+    let share_offset = 1 + payload[0] as usize;
+    let annotation = String::from_utf8(payload[1..share_offset].to_vec())
+        .expect("BUG: annotation is not a string");
+    let share = SubmitSharesStandard::try_from(&payload[share_offset..])
+        .expect("BUG: cannot decode annotated share");
+    (share.seq_num, Some(annotation))
+}
+
+#[tokio::test]
+async fn test_submission_annotation() {
+    let client = build_client(StratumV2Config {
+        submission_annotation: Some("rack-7".to_string()),
+        ..Default::default()
+    });
+    client.set_annotation_carrier(Arc::new(MockCarrier));
+    let _event_handler = start_mining(&client).await;
+    let (connection_tx, mut connection_rx) = mpsc::unbounded();
+    let mut solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+
+    // The pool doesn't accept the annotation, its omission is reported only once
+    set_negotiated_flags(&client, 0);
+    for seq_num in 0..2 {
+        assert_eq!(
+            submit_annotated_share(&client, &mut solution_handler, &mut connection_rx).await,
+            (seq_num, None)
+        );
+    }
+    assert_eq!(
+        event_kinds(&client)
+            .into_iter()
+            .filter(|kind| *kind == "annotation_omitted")
+            .count(),
+        1
+    );
+    assert!(!client
+        .active_capabilities()
+        .contains(&capabilities::SUBMISSION_ANNOTATION));
+
+    // The capability has been negotiated
+    set_negotiated_flags(&client, MOCK_ANNOTATION_FLAG);
+    assert_eq!(
+        submit_annotated_share(&client, &mut solution_handler, &mut connection_rx).await,
+        (2, Some("rack-7".to_string()))
+    );
+    assert!(client
+        .active_capabilities()
+        .contains(&capabilities::SUBMISSION_ANNOTATION));
+
+    // A change of the configuration takes effect on the next submission
+    set_submission_annotation(&client, Some("rack-8"));
+    assert_eq!(
+        submit_annotated_share(&client, &mut solution_handler, &mut connection_rx).await,
+        (3, Some("rack-8".to_string()))
+    );
+    set_submission_annotation(&client, None);
+    assert_eq!(
+        submit_annotated_share(&client, &mut solution_handler, &mut connection_rx).await,
+        (4, None)
+    );
+
+    // The carrier of this build never accepts the annotation
+    set_submission_annotation(&client, Some("rack-7"));
+    client.set_annotation_carrier(Arc::new(annotation::Dormant));
+    assert_eq!(
+        submit_annotated_share(&client, &mut solution_handler, &mut connection_rx).await,
+        (5, None)
+    );
+}
+
+#[tokio::test]
+async fn test_submission_annotation_share_log() {
+    let path = user_file_path("annotation-share-log.csv");
+    let _ = std::fs::remove_file(&path);
+    let client = build_client(StratumV2Config {
+        share_log: Some(path.clone()),
+        submission_annotation: Some("rack-7".to_string()),
+        ..Default::default()
+    });
+    client.set_annotation_carrier(Arc::new(MockCarrier));
+    let mut event_handler = start_mining(&client).await;
+    let (connection_tx, mut connection_rx) = mpsc::unbounded();
+    let mut solution_handler = StratumSolutionHandler::new(
+        client.clone(),
+        Arc::new(Mutex::new(connection_tx)),
+        client.context(),
+    );
+    set_negotiated_flags(&client, MOCK_ANNOTATION_FLAG);
+    submit_annotated_share(&client, &mut solution_handler, &mut connection_rx).await;
+    set_negotiated_flags(&client, 0);
+    submit_annotated_share(&client, &mut solution_handler, &mut connection_rx).await;
+    acknowledge(&client, &mut event_handler, 1).await;
+
+    // The file is written in the background
+    let mut lines = Vec::new();
+    for _ in 0..100 {
+        lines = std::fs::read_to_string(&path)
+            .expect("BUG: cannot read share log")
+            .lines()
+            .map(str::to_string)
+            .collect();
+        if lines.len() == 3 {
+            break;
+        }
+        tokio::time::delay_for(time::Duration::from_millis(10)).await;
+    }
+    let annotations: Vec<_> = lines[1..]
+        .iter()
+        .map(|line| {
+            line.rsplit(',')
+                .next()
+                .expect("BUG: empty line")
+                .to_string()
+        })
+        .collect();
+    assert_eq!(annotations, vec!["\"rack-7\"", "\"\""]);
+    std::fs::remove_file(&path).expect("BUG: cannot remove share log");
+}
+
+#[test]
+fn test_submission_annotation_validation() {
+    let config = |annotation: &str| StratumV2Config {
+        submission_annotation: Some(annotation.to_string()),
+        ..Default::default()
+    };
+    assert!(config("rack-7").validate().is_ok());
+    assert!(
+        config(&"a".repeat(StratumV2Config::MAX_SUBMISSION_ANNOTATION_LENGTH))
+            .validate()
+            .is_ok()
+    );
+    for annotation in &[
+        "".to_string(),
+        "a".repeat(StratumV2Config::MAX_SUBMISSION_ANNOTATION_LENGTH + 1),
+        "rack 7".to_string(),
+        "rack\u{7}".to_string(),
+        "r\u{e1}ck".to_string(),
+    ] {
+        assert!(config(annotation).validate().is_err(), "{:?}", annotation);
+    }
+}
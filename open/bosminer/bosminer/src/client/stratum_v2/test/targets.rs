@@ -0,0 +1,1124 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+/// Build state of a session with the pool that has opened the channel with `init_target`
+fn session_with_target(init_target: ii_bitcoin::Target) -> Arc<session::State> {
+    Arc::new(session::State {
+        init_target,
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn test_max_difficulty_clamp() {
+    let client = build_client(StratumV2Config {
+        max_difficulty: Some(8),
+        ..Default::default()
+    });
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    assert!(client.health().is_healthy());
+
+    set_target(&client, &mut event_handler, 64).await;
+    assert_eq!(event_handler.current_target.get_difficulty(), 8);
+    assert_eq!(event_handler.current_pool_target.get_difficulty(), 64);
+    assert_eq!(*client.clamped_targets().take_snapshot(), 1);
+    assert_eq!(
+        client.health(),
+        health::Health::Degraded(vec![health::DegradedReason::TargetClamped])
+    );
+    let recorded: Vec<_> = client.events().into_iter().map(|r| r.event).collect();
+    assert_eq!(
+        recorded,
+        vec![events::Event::TargetClamped {
+            requested_difficulty: 64,
+            applied_difficulty: 8,
+        }]
+    );
+
+    // Persistent clamping is counted but reported only once
+    set_target(&client, &mut event_handler, 128).await;
+    assert_eq!(*client.clamped_targets().take_snapshot(), 2);
+    assert_eq!(client.events().len(), 1);
+}
+
+#[tokio::test]
+async fn test_max_difficulty_precedence() {
+    // Initial target negotiated when opening the channel is subject to the same policy
+    let client = build_client(StratumV2Config {
+        max_difficulty: Some(8),
+        ..Default::default()
+    });
+    let event_handler = StratumEventHandler::new(
+        client.clone(),
+        session_with_target(ii_bitcoin::Target::from_pool_difficulty(1024)),
+        client.context(),
+    );
+    assert_eq!(event_handler.current_target.get_difficulty(), 8);
+    assert!(!client.health().is_healthy());
+
+    // No adjustments are applied without configuration
+    let client = build_client(Default::default());
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    set_target(&client, &mut event_handler, 1024).await;
+    assert_eq!(event_handler.current_target.get_difficulty(), 1024);
+    assert_eq!(
+        event_handler.current_target,
+        event_handler.current_pool_target
+    );
+    assert_eq!(*client.clamped_targets().take_snapshot(), 0);
+    assert!(client.health().is_healthy());
+}
+
+#[tokio::test]
+async fn test_difficulty_range_precedence() {
+    // The ceiling is applied before the floor
+    let client = build_client(StratumV2Config {
+        min_difficulty: Some(8),
+        max_difficulty: Some(64),
+        ..Default::default()
+    });
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    for &(requested_difficulty, applied_difficulty) in &[(2, 8), (16, 16), (1024, 64)] {
+        set_target(&client, &mut event_handler, requested_difficulty).await;
+        assert_eq!(
+            event_handler.current_target.get_difficulty(),
+            applied_difficulty
+        );
+        assert_eq!(
+            event_handler.current_pool_target.get_difficulty(),
+            requested_difficulty
+        );
+    }
+
+    // The floor wins over the ceiling when they overlap (such configuration doesn't pass the
+    // validation but the order of precedence is defined for it anyway)
+    let config = StratumV2Config {
+        min_difficulty: Some(64),
+        max_difficulty: Some(8),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+    let client = build_client(config);
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    for &requested_difficulty in &[2, 16, 1024] {
+        set_target(&client, &mut event_handler, requested_difficulty).await;
+        assert_eq!(event_handler.current_target.get_difficulty(), 64);
+    }
+}
+
+#[tokio::test]
+async fn test_startup_target_precedence() {
+    let startup_target = target_util::target_from_hashrate(
+        ii_bitcoin::HashesUnit::TeraHashes(100.0),
+        StratumV2StartupTarget::DEFAULT_SHARES_PER_MINUTE,
+    );
+    let build_event_handler = |config| {
+        let client = build_client(config);
+        let event_handler = StratumEventHandler::new(
+            client.clone(),
+            session_with_target(target_util::difficulty_1_target()),
+            client.context(),
+        );
+        (client, event_handler)
+    };
+
+    // The floor is applied after the startup target and it wins when it is harder
+    let floor_target = target_util::target_from_difficulty(1 << 20);
+    assert!(target_util::is_harder(&floor_target, &startup_target));
+    let (client, event_handler) = build_event_handler(StratumV2Config {
+        min_difficulty: Some(1 << 20),
+        ..startup_target_config(100.0, None)
+    });
+    assert!(client.targets().startup_policy);
+    assert_eq!(event_handler.current_target, floor_target);
+
+    // Startup target harder than the floor is used as it is
+    let (client, event_handler) = build_event_handler(StratumV2Config {
+        min_difficulty: Some(1024),
+        ..startup_target_config(100.0, None)
+    });
+    assert!(client.targets().startup_policy);
+    assert_eq!(event_handler.current_target, startup_target);
+
+    // The ceiling and the early share accommodation disable the startup target
+    for config in &[
+        StratumV2Config {
+            max_difficulty: Some(1 << 30),
+            ..startup_target_config(100.0, None)
+        },
+        StratumV2Config {
+            early_share: early_share_config(100.0, 120).early_share,
+            ..startup_target_config(100.0, None)
+        },
+    ] {
+        let (client, event_handler) = build_event_handler(config.clone());
+        assert!(!client.targets().startup_policy);
+        assert_eq!(
+            event_handler.current_target,
+            target_util::difficulty_1_target()
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_max_difficulty_health_recovery() {
+    let client = build_client(StratumV2Config {
+        max_difficulty: Some(8),
+        ..Default::default()
+    });
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+
+    set_target(&client, &mut event_handler, 64).await;
+    assert!(!client.health().is_healthy());
+
+    // Pool lowers the difficulty within the configured maximum
+    set_target(&client, &mut event_handler, 8).await;
+    assert_eq!(event_handler.current_target.get_difficulty(), 8);
+    assert!(client.health().is_healthy());
+}
+
+#[tokio::test]
+async fn test_min_difficulty_clamp() {
+    let client = build_client(StratumV2Config {
+        min_difficulty: Some(8),
+        ..Default::default()
+    });
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    // The default initial target is clamped as well
+    assert_eq!(event_handler.current_target.get_difficulty(), 8);
+    assert_eq!(*client.clamped_targets().take_snapshot(), 1);
+
+    set_target(&client, &mut event_handler, 2).await;
+    assert_eq!(event_handler.current_target.get_difficulty(), 8);
+    assert_eq!(event_handler.current_pool_target.get_difficulty(), 2);
+    assert_eq!(*client.clamped_targets().take_snapshot(), 2);
+    assert!(!client.health().is_healthy());
+
+    // Pool raises the difficulty within the configured minimum
+    set_target(&client, &mut event_handler, 16).await;
+    assert_eq!(event_handler.current_target.get_difficulty(), 16);
+    assert!(client.health().is_healthy());
+}
+
+async fn try_set_target(
+    client: &Arc<StratumClient>,
+    event_handler: &mut StratumEventHandler,
+    difficulty: usize,
+) -> bool {
+    let message = SetTarget {
+        channel_id: 0,
+        max_target: target_util::target_from_difficulty(difficulty).into(),
+    };
+    let frame = message.try_into().expect("BUG: cannot build frame");
+    client.handle_frame(frame, event_handler).await.is_ok()
+}
+
+#[tokio::test]
+async fn test_difficulty_reconnect() {
+    let client = build_client(StratumV2Config {
+        min_difficulty: Some(8),
+        max_difficulty: Some(64),
+        difficulty_reconnect_factor: Some(4.0),
+        ..Default::default()
+    });
+    let mut event_handler = StratumEventHandler::new(
+        client.clone(),
+        session_with_target(target_util::target_from_difficulty(16)),
+        client.context(),
+    );
+
+    // Targets that are not too far outside of the range are only clamped
+    assert!(try_set_target(&client, &mut event_handler, 2).await);
+    assert_eq!(event_handler.current_target.get_difficulty(), 8);
+    assert!(try_set_target(&client, &mut event_handler, 256).await);
+    assert_eq!(event_handler.current_target.get_difficulty(), 64);
+
+    // The connection is restarted and the target is not applied
+    assert!(!try_set_target(&client, &mut event_handler, 1).await);
+    assert_eq!(event_handler.current_pool_target.get_difficulty(), 256);
+    assert!(!try_set_target(&client, &mut event_handler, 257).await);
+    assert_eq!(event_handler.current_pool_target.get_difficulty(), 256);
+}
+
+#[tokio::test]
+async fn test_difficulty_reconnect_open_channel() {
+    // The capture opens the channel with diff=4
+    let client = build_client(StratumV2Config {
+        min_difficulty: Some(64),
+        difficulty_reconnect_factor: Some(4.0),
+        ..Default::default()
+    });
+    assert!(
+        replay::replay_session(client.clone(), &build_session_capture(), false)
+            .await
+            .is_err()
+    );
+    assert!(client.last_job().is_none());
+
+    // Without the factor the initial target is just clamped
+    let client = build_client(StratumV2Config {
+        min_difficulty: Some(64),
+        ..Default::default()
+    });
+    assert!(
+        replay::replay_session(client.clone(), &build_session_capture(), false)
+            .await
+            .is_ok()
+    );
+    let job = client.last_job().expect("BUG: no job has been received");
+    assert_eq!(job.target.get_difficulty(), 64);
+}
+
+#[tokio::test]
+async fn test_current_target() {
+    let client = build_client(StratumV2Config {
+        max_difficulty: Some(8),
+        ..Default::default()
+    });
+    assert_eq!(client.current_target(), None);
+    assert_eq!(client.current_difficulty(), None);
+
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    assert_eq!(client.current_target(), Some(event_handler.current_target));
+    set_target(&client, &mut event_handler, 4).await;
+    assert_eq!(client.current_difficulty(), Some(4));
+    // The effective target is reported, not the one requested by the pool
+    set_target(&client, &mut event_handler, 64).await;
+    assert_eq!(client.current_difficulty(), Some(8));
+    assert_eq!(client.current_target(), Some(event_handler.current_target));
+
+    // The capture opens the channel with diff=4 and then sets diff=16, the value is retained
+    // after the session is closed
+    let client = build_client(Default::default());
+    assert!(
+        replay::replay_session(client.clone(), &build_session_capture(), false)
+            .await
+            .is_ok()
+    );
+    assert_eq!(client.current_difficulty(), Some(16));
+}
+
+#[test]
+fn test_difficulty_range_validation() {
+    let config = StratumV2Config {
+        min_difficulty: Some(64),
+        max_difficulty: Some(8),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+
+    let config = StratumV2Config {
+        difficulty_reconnect_factor: Some(0.5),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+
+    let config = StratumV2Config {
+        min_difficulty: Some(8),
+        max_difficulty: Some(8),
+        difficulty_reconnect_factor: Some(1.0),
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+}
+
+fn target_corrections(client: &Arc<StratumClient>) -> Vec<target_backfill::Correction> {
+    client
+        .events()
+        .into_iter()
+        .filter_map(|record| match record.event {
+            events::Event::TargetCorrected(correction) => Some(correction),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_target_backfill_channel_open() {
+    let client = build_client(Default::default());
+    let session = |difficulty| session_with_target(target_util::target_from_difficulty(difficulty));
+    StratumEventHandler::new(client.clone(), session(4), client.context());
+    assert!(target_corrections(&client).is_empty());
+
+    // The pool echoes a harder target after reconnect, it is adopted immediately
+    let event_handler = StratumEventHandler::new(client.clone(), session(64), client.context());
+    assert_eq!(event_handler.current_pool_target.get_difficulty(), 64);
+    assert_eq!(
+        target_corrections(&client),
+        vec![target_backfill::Correction {
+            reason: target_backfill::Reason::ChannelOpen,
+            from_difficulty: 4,
+            to_difficulty: 64,
+        }]
+    );
+
+    // Easier target is adopted as well but it is not a correction
+    let event_handler = StratumEventHandler::new(client.clone(), session(16), client.context());
+    assert_eq!(event_handler.current_pool_target.get_difficulty(), 16);
+    assert_eq!(target_corrections(&client).len(), 1);
+}
+
+#[tokio::test]
+async fn test_target_backfill_rejects() {
+    let client = build_client(Default::default());
+    let mut event_handler = StratumEventHandler::new(
+        client.clone(),
+        session_with_target(target_util::target_from_difficulty(4)),
+        client.context(),
+    );
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    let difficulty =
+        |event_handler: &StratumEventHandler| event_handler.current_pool_target.get_difficulty();
+    let low = target_backfill::DIFFICULTY_TOO_LOW;
+
+    // Only consecutive low difficulty rejects imply a harder target
+    reject_share(&client, &mut event_handler, 0, low).await;
+    reject_share(&client, &mut event_handler, 1, low).await;
+    reject_share(&client, &mut event_handler, 2, "stale-share").await;
+    reject_share(&client, &mut event_handler, 3, low).await;
+    reject_share(&client, &mut event_handler, 4, low).await;
+    assert_eq!(difficulty(&event_handler), 4);
+    reject_share(&client, &mut event_handler, 5, low).await;
+    assert_eq!(difficulty(&event_handler), 8);
+    assert_eq!(
+        target_corrections(&client),
+        vec![target_backfill::Correction {
+            reason: target_backfill::Reason::LowDifficultyRejects,
+            from_difficulty: 4,
+            to_difficulty: 8,
+        }]
+    );
+
+    // Shares solved with the previous target don't count
+    for seq_num in 6..9 {
+        reject_share(&client, &mut event_handler, seq_num, low).await;
+    }
+    assert_eq!(difficulty(&event_handler), 8);
+
+    // Next step is taken only with the evidence of the corrected target
+    new_job(&client, &mut event_handler, 2, false).await;
+    for seq_num in 9..12 {
+        reject_share(&client, &mut event_handler, seq_num, low).await;
+    }
+    assert_eq!(difficulty(&event_handler), 16);
+
+    // Accepted share breaks the sequence of rejects
+    new_job(&client, &mut event_handler, 3, false).await;
+    reject_share(&client, &mut event_handler, 12, low).await;
+    reject_share(&client, &mut event_handler, 13, low).await;
+    let solution = build_solution(&client).await;
+    client.solutions.lock().await.push_back((solution, 14));
+    acknowledge(&client, &mut event_handler, 14).await;
+    reject_share(&client, &mut event_handler, 15, low).await;
+    assert_eq!(difficulty(&event_handler), 16);
+
+    // Authoritative target overrides the correction and ends it
+    set_target(&client, &mut event_handler, 4).await;
+    assert_eq!(difficulty(&event_handler), 4);
+    new_job(&client, &mut event_handler, 4, false).await;
+    for seq_num in 16..22 {
+        reject_share(&client, &mut event_handler, seq_num, low).await;
+    }
+    assert_eq!(difficulty(&event_handler), 4);
+    assert_eq!(target_corrections(&client).len(), 2);
+}
+
+#[tokio::test]
+async fn test_target_backfill_bounded() {
+    let client = build_client(Default::default());
+    let mut event_handler = StratumEventHandler::new(
+        client.clone(),
+        session_with_target(target_util::target_from_difficulty(4)),
+        client.context(),
+    );
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    let mut seq_num = 0;
+    for job_id in 2..=target_backfill::Backfill::MAX_STEPS as u32 + 2 {
+        new_job(&client, &mut event_handler, job_id, false).await;
+        for _ in 0..target_backfill::Backfill::REJECT_THRESHOLD {
+            reject_share(
+                &client,
+                &mut event_handler,
+                seq_num,
+                target_backfill::DIFFICULTY_TOO_LOW,
+            )
+            .await;
+            seq_num += 1;
+        }
+    }
+    assert_eq!(
+        event_handler.current_pool_target.get_difficulty(),
+        4 << target_backfill::Backfill::MAX_STEPS
+    );
+    assert_eq!(
+        target_corrections(&client).len(),
+        target_backfill::Backfill::MAX_STEPS
+    );
+}
+
+#[tokio::test]
+async fn test_invalid_target() {
+    let client = build_client(Default::default());
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    set_target(&client, &mut event_handler, 4).await;
+
+    // Zero target is ignored and the previous target is kept
+    let message = SetTarget {
+        channel_id: 0,
+        max_target: Uint256Bytes([0; 32]),
+    };
+    handle_message(&client, &mut event_handler, message).await;
+    assert_eq!(event_handler.current_target.get_difficulty(), 4);
+    assert_eq!(event_handler.current_pool_target.get_difficulty(), 4);
+    assert_eq!(*client.invalid_targets().take_snapshot(), 1);
+
+    // The easiest possible target is valid
+    let message = SetTarget {
+        channel_id: 0,
+        max_target: Uint256Bytes([0xff; 32]),
+    };
+    handle_message(&client, &mut event_handler, message).await;
+    assert_eq!(event_handler.current_target.get_difficulty(), 0);
+    assert_eq!(*client.invalid_targets().take_snapshot(), 1);
+}
+
+#[tokio::test]
+async fn test_invalid_open_channel_target() {
+    let absurd_target = Uint256Bytes({
+        let mut bytes = [0; 32];
+        bytes[0] = 1;
+        bytes
+    });
+    for target in vec![Uint256Bytes([0; 32]), absurd_target] {
+        let client = build_client(Default::default());
+        let mut connection_handler =
+            StratumConnectionHandler::new(client.clone(), client.context());
+        let frame = OpenStandardMiningChannelSuccess {
+            req_id: 10,
+            channel_id: 0,
+            target,
+            extranonce_prefix: Bytes0_32::new(),
+            group_channel_id: 0,
+        }
+        .try_into()
+        .expect("BUG: cannot build frame");
+        build_message_from_frame(frame)
+            .expect("BUG: cannot build message")
+            .accept(&mut connection_handler)
+            .await;
+
+        // The session is opened with the safe default target, the pool is expected to fix it
+        // with `SetTarget`
+        assert!(connection_handler
+            .status
+            .take()
+            .expect("BUG: missing status")
+            .is_ok());
+        assert_eq!(
+            connection_handler.init_target,
+            target_util::difficulty_1_target()
+        );
+        assert!(!connection_handler.init_target_provided);
+        assert_eq!(*client.invalid_targets().take_snapshot(), 1);
+
+        let mut event_handler = StratumEventHandler::new(
+            client.clone(),
+            session_with_target(connection_handler.init_target),
+            connection_handler.context,
+        );
+        assert_eq!(client.current_difficulty(), Some(1));
+        set_target(&client, &mut event_handler, 16).await;
+        assert_eq!(client.current_difficulty(), Some(16));
+    }
+}
+
+#[tokio::test]
+async fn test_missing_set_target_warning() {
+    let config = StratumV2Config {
+        set_target_window: Some(300),
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+    let client = build_client(config);
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    // The window starts with the first job
+    assert!(!event_handler
+        .warn_missing_set_target(time::Instant::now() + time::Duration::from_secs(3600)));
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    let first_job_received = event_handler
+        .first_job_received
+        .expect("BUG: no job received");
+    let after = |secs| first_job_received + time::Duration::from_secs(secs);
+    assert!(!event_handler.warn_missing_set_target(after(299)));
+    assert!(event_handler.warn_missing_set_target(after(300)));
+    // The warning is logged only once per session
+    assert!(!event_handler.warn_missing_set_target(after(900)));
+
+    // A pool that sends the target is fine
+    let mut event_handler = start_mining(&client).await;
+    assert!(!event_handler.warn_missing_set_target(after(3600)));
+
+    // Nothing is checked by default
+    let client = build_client(Default::default());
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    new_job(&client, &mut event_handler, 1, true).await;
+    assert!(!event_handler.warn_missing_set_target(after(3600)));
+
+    let config = StratumV2Config {
+        set_target_window: Some(0),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+fn startup_target_config(nominal_hashrate: f64, window: Option<u64>) -> StratumV2Config {
+    StratumV2Config {
+        startup_target: Some(StratumV2StartupTarget {
+            nominal_hashrate,
+            shares_per_minute: None,
+            window,
+        }),
+        ..Default::default()
+    }
+}
+
+fn early_share_config(nominal_hashrate: f64, deadline: u64) -> StratumV2Config {
+    StratumV2Config {
+        early_share: Some(StratumV2EarlyShare {
+            nominal_hashrate,
+            deadline,
+            expected_shares: None,
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_early_share_target() {
+    for &(nominal_hashrate, deadline) in &[
+        (14.0, 120),
+        (100.0, 120),
+        (100.0, 30),
+        (1000.0, 300),
+        (0.5, 60),
+    ] {
+        let config = early_share_config(nominal_hashrate, deadline);
+        assert!(config.validate().is_ok());
+        let early_share = config.early_share.expect("BUG: missing early share");
+        let target = StratumClient::early_share_target(&early_share);
+        let shares_within_deadline = target_util::expected_shares_per_minute(
+            ii_bitcoin::HashesUnit::TeraHashes(nominal_hashrate),
+            &target,
+        ) * deadline as f64
+            / 60.0;
+        // The first share is expected well within the deadline
+        assert!(
+            (shares_within_deadline - StratumV2EarlyShare::DEFAULT_EXPECTED_SHARES).abs() < 0.01,
+            "{} TH/s, {}s: {} shares",
+            nominal_hashrate,
+            deadline,
+            shares_within_deadline
+        );
+    }
+    // Tiny miner is limited by difficulty 1
+    let config = early_share_config(0.000_001, 120);
+    let target = StratumClient::early_share_target(&config.early_share.unwrap());
+    assert_eq!(target, target_util::difficulty_1_target());
+
+    for config in vec![
+        early_share_config(0.0, 120),
+        early_share_config(std::f64::NAN, 120),
+        early_share_config(100.0, 0),
+        StratumV2Config {
+            early_share: Some(StratumV2EarlyShare {
+                nominal_hashrate: 100.0,
+                deadline: 120,
+                expected_shares: Some(0.0),
+            }),
+            ..Default::default()
+        },
+    ] {
+        assert!(config.validate().is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_early_share_request() {
+    let config = early_share_config(100.0, 120);
+    let early_share = config.early_share.clone().unwrap();
+    let client = build_client(config);
+    let channel_msg = replay_open_channel(&client).await;
+    assert_eq!(
+        channel_msg.max_target,
+        Uint256Bytes(target_util::target_into_le_bytes(
+            &StratumClient::early_share_target(&early_share)
+        ))
+    );
+    assert_eq!(channel_msg.nominal_hashrate, 100e12);
+
+    // The startup target policy doesn't make the target harder
+    let event_handler = StratumEventHandler::new(
+        client.clone(),
+        session_with_target(target_util::difficulty_1_target()),
+        client.context(),
+    );
+    assert!(event_handler.startup_target.is_none());
+    assert_eq!(
+        event_handler.current_target,
+        target_util::difficulty_1_target()
+    );
+
+    // The difficulty 1 target is requested by default
+    let client = build_client(Default::default());
+    let channel_msg = replay_open_channel(&client).await;
+    assert_eq!(
+        channel_msg.max_target,
+        Uint256Bytes(target_util::target_into_le_bytes(
+            &target_util::difficulty_1_target()
+        ))
+    );
+}
+
+#[test]
+fn test_early_share_drop() {
+    let client = build_client(early_share_config(100.0, 120));
+    let secs = time::Duration::from_secs;
+    // The channel has been dropped at the deadline without any share
+    assert_eq!(
+        client.early_share_deadline_missed(secs(120), 0),
+        Some(secs(120))
+    );
+    assert_eq!(
+        client.early_share_deadline_missed(secs(118), 0),
+        Some(secs(120))
+    );
+    // Generic disconnects
+    assert_eq!(client.early_share_deadline_missed(secs(30), 0), None);
+    assert_eq!(client.early_share_deadline_missed(secs(120), 1), None);
+    assert_eq!(client.early_share_deadline_missed(secs(600), 3), None);
+
+    // Nothing is reported without the early share configuration
+    let client = build_client(Default::default());
+    assert_eq!(client.early_share_deadline_missed(secs(120), 0), None);
+}
+
+#[tokio::test]
+async fn test_startup_target_big_asic() {
+    // Large miner would flood the pool with shares at difficulty 1
+    let client = build_client(startup_target_config(100.0, None));
+    let mut event_handler = StratumEventHandler::new(
+        client.clone(),
+        session_with_target(target_util::difficulty_1_target()),
+        client.context(),
+    );
+    let startup_target = target_util::target_from_hashrate(
+        ii_bitcoin::HashesUnit::TeraHashes(100.0),
+        StratumV2StartupTarget::DEFAULT_SHARES_PER_MINUTE,
+    );
+    assert_eq!(event_handler.current_target, startup_target);
+    assert_eq!(
+        event_handler.current_pool_target,
+        target_util::difficulty_1_target()
+    );
+    assert_eq!(
+        client.status_document().targets,
+        status::Targets {
+            local_difficulty: target_util::difficulty_from_target(&startup_target),
+            pool_difficulty: 1,
+            startup_policy: true,
+        }
+    );
+
+    // The pool takes over the control in the middle of the window
+    set_target(&client, &mut event_handler, 1024).await;
+    assert_eq!(event_handler.current_target.get_difficulty(), 1024);
+    assert_eq!(
+        client.targets(),
+        status::Targets {
+            local_difficulty: 1024,
+            pool_difficulty: 1024,
+            startup_policy: false,
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_startup_target_tiny_rig() {
+    // The starting target is never easier than the target requested by the pool
+    let client = build_client(startup_target_config(0.001, None));
+    let pool_target = target_util::target_from_difficulty(65536);
+    let event_handler = StratumEventHandler::new(
+        client.clone(),
+        session_with_target(pool_target),
+        client.context(),
+    );
+    assert_eq!(event_handler.current_target, pool_target);
+    assert_eq!(
+        client.targets(),
+        status::Targets {
+            local_difficulty: 65536,
+            pool_difficulty: 65536,
+            startup_policy: true,
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_startup_target_window() {
+    // The window ends before the first job is dispatched
+    let client = build_client(startup_target_config(100.0, Some(0)));
+    let mut event_handler = StratumEventHandler::new(
+        client.clone(),
+        session_with_target(target_util::difficulty_1_target()),
+        client.context(),
+    );
+    assert!(client.targets().startup_policy);
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    let job = client.last_job().expect("BUG: no job has been dispatched");
+    assert_eq!(job.target, target_util::difficulty_1_target());
+    assert!(!client.targets().startup_policy);
+
+    // Explicit difficulty settings disable the policy
+    let client = build_client(StratumV2Config {
+        max_difficulty: Some(1 << 20),
+        ..startup_target_config(100.0, None)
+    });
+    let event_handler = StratumEventHandler::new(
+        client.clone(),
+        session_with_target(target_util::difficulty_1_target()),
+        client.context(),
+    );
+    assert_eq!(
+        event_handler.current_target,
+        target_util::difficulty_1_target()
+    );
+    assert!(!client.targets().startup_policy);
+}
+
+fn last_job_difficulty(client: &Arc<StratumClient>) -> Option<usize> {
+    client
+        .last_job()
+        .map(|job| target_util::difficulty_from_target(&job.target))
+}
+
+#[tokio::test]
+async fn test_target_application() {
+    for &(target_application, expected_difficulty) in [
+        (None, 4),
+        (Some(TargetApplication::NextJob), 4),
+        (Some(TargetApplication::Immediate), 8),
+    ]
+    .iter()
+    {
+        let client = build_client(StratumV2Config {
+            target_application,
+            ..Default::default()
+        });
+        let mut event_handler =
+            StratumEventHandler::new(client.clone(), Default::default(), client.context());
+        set_target(&client, &mut event_handler, 4).await;
+        new_job(&client, &mut event_handler, 1, true).await;
+        new_prev_hash(&client, &mut event_handler, 1).await;
+        assert_eq!(last_job_difficulty(&client), Some(4));
+
+        set_target(&client, &mut event_handler, 8).await;
+        assert_eq!(last_job_id(&client), Some(1));
+        assert_eq!(last_job_difficulty(&client), Some(expected_difficulty));
+
+        // The next job always uses the new target
+        new_job(&client, &mut event_handler, 2, false).await;
+        assert_eq!(last_job_id(&client), Some(2));
+        assert_eq!(last_job_difficulty(&client), Some(8));
+    }
+}
+
+#[tokio::test]
+async fn test_target_application_without_job() {
+    let client = build_client(StratumV2Config {
+        target_application: Some(TargetApplication::Immediate),
+        ..Default::default()
+    });
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    // There is no active job to be dispatched again
+    set_target(&client, &mut event_handler, 8).await;
+    assert_eq!(last_job_id(&client), None);
+
+    // An invalid target doesn't dispatch the active job again
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    let job = client.last_job();
+    let message = SetTarget {
+        channel_id: 0,
+        max_target: Uint256Bytes([0; 32]),
+    };
+    handle_message(&client, &mut event_handler, message).await;
+    let last_job = client.last_job();
+    assert!(Arc::ptr_eq(
+        job.as_ref().expect("BUG: no job"),
+        last_job.as_ref().expect("BUG: no job")
+    ));
+}
+
+#[tokio::test]
+async fn test_suggested_difficulty_validation() {
+    let client = build_client(StratumV2Config {
+        min_difficulty: Some(64),
+        max_difficulty: Some(4096),
+        ..Default::default()
+    });
+    for &difficulty in &[f64::NAN, f64::INFINITY, 0.5, 32.0, 8192.0] {
+        let e = client
+            .set_suggested_difficulty(difficulty, suggestion::Source::Optimizer)
+            .expect_err("BUG: invalid suggestion accepted");
+        assert_eq!(e.error_code(), "stratum.command.invalid_suggestion");
+    }
+    assert!(client.suggested_difficulty().is_none());
+
+    // Command round-trip: the accepted suggestion is visible in the status document and it is
+    // requested when opening the channel
+    client
+        .set_suggested_difficulty(1024.0, suggestion::Source::Operator)
+        .expect("BUG: valid suggestion rejected");
+    let document = serde_json::to_value(client.status_document())
+        .expect("BUG: cannot serialize status document");
+    assert_eq!(
+        document["suggested_difficulty"]["source"],
+        serde_json::json!("operator")
+    );
+    assert_eq!(
+        document["suggested_difficulty"]["difficulty"],
+        serde_json::json!(1024.0)
+    );
+    assert_eq!(
+        target_util::difficulty_from_target(&client.channel_max_target(time::Instant::now())),
+        1024
+    );
+}
+
+#[tokio::test]
+async fn test_suggested_difficulty_precedence() {
+    let early_share = StratumV2EarlyShare {
+        nominal_hashrate: 14.0,
+        deadline: 60,
+        expected_shares: None,
+    };
+    let early_share_target = StratumClient::early_share_target(&early_share);
+    let client = build_client(StratumV2Config {
+        early_share: Some(early_share),
+        ..Default::default()
+    });
+    let now = time::Instant::now();
+    assert_eq!(client.channel_max_target(now), early_share_target);
+    // The suggestion overrides the early share target
+    client
+        .set_suggested_difficulty_at(512.0, suggestion::Source::Optimizer, now)
+        .expect("BUG: valid suggestion rejected");
+    assert_eq!(
+        target_util::difficulty_from_target(&client.channel_max_target(now)),
+        512
+    );
+
+    // The suggestion suspends the startup target policy
+    let startup_target = StratumV2StartupTarget {
+        nominal_hashrate: 14.0,
+        shares_per_minute: None,
+        window: None,
+    };
+    let client = build_client(StratumV2Config {
+        startup_target: Some(startup_target),
+        ..Default::default()
+    });
+    let event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    assert!(event_handler.startup_target.is_some());
+    client
+        .set_suggested_difficulty(512.0, suggestion::Source::Optimizer)
+        .expect("BUG: valid suggestion rejected");
+    let event_handler =
+        StratumEventHandler::new(client.clone(), Default::default(), client.context());
+    assert!(event_handler.startup_target.is_none());
+    assert!(!client.targets().startup_policy);
+
+    // The floor and the ceiling limit a suggestion accepted before they have been changed
+    let client = build_client(Default::default());
+    client
+        .set_suggested_difficulty_at(100_000.0, suggestion::Source::Optimizer, now)
+        .expect("BUG: valid suggestion rejected");
+    client
+        .connection_details
+        .lock()
+        .expect("BUG: cannot lock connection details")
+        .config = Arc::new(StratumV2Config {
+        max_difficulty: Some(4096),
+        ..Default::default()
+    });
+    assert_eq!(
+        target_util::difficulty_from_target(&client.channel_max_target(now)),
+        4096
+    );
+    client
+        .set_suggested_difficulty_at(2.0, suggestion::Source::Optimizer, now)
+        .expect("BUG: valid suggestion rejected");
+    client
+        .connection_details
+        .lock()
+        .expect("BUG: cannot lock connection details")
+        .config = Arc::new(StratumV2Config {
+        min_difficulty: Some(16),
+        ..Default::default()
+    });
+    assert_eq!(
+        target_util::difficulty_from_target(&client.channel_max_target(now)),
+        16
+    );
+}
+
+#[tokio::test]
+async fn test_suggested_difficulty_expiry() {
+    let client = build_client(StratumV2Config {
+        difficulty_suggestion_ttl: Some(60),
+        ..Default::default()
+    });
+    let now = time::Instant::now();
+    client
+        .set_suggested_difficulty_at(512.0, suggestion::Source::Optimizer, now)
+        .expect("BUG: valid suggestion rejected");
+    let later = now + time::Duration::from_secs(59);
+    assert_eq!(
+        client
+            .effective_suggestion(later)
+            .map(|suggestion| suggestion.status(time::Duration::from_secs(60), later)),
+        Some(suggestion::Status {
+            difficulty: 512.0,
+            source: suggestion::Source::Optimizer,
+            age: 59,
+            expires_in: 1,
+        })
+    );
+    // The local heuristics apply again once the suggestion expires
+    let expired = now + time::Duration::from_secs(60);
+    assert_eq!(
+        client.channel_max_target(expired),
+        target_util::difficulty_1_target()
+    );
+    assert!(client.effective_suggestion(now).is_none());
+    assert!(client.suggested_difficulty().is_none());
+}
+
+/// Build state of a session whose channel has been opened without a usable target
+fn session_without_target() -> Arc<session::State> {
+    Arc::new(session::State {
+        init_target: target_util::difficulty_1_target(),
+        init_target_provided: false,
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn test_missing_target_policy() {
+    assert!(StratumV2Config {
+        missing_target_wait: Some(StratumV2Config::MAX_MISSING_TARGET_WAIT + 1),
+        ..Default::default()
+    }
+    .validate()
+    .is_err());
+
+    // The target provided when the channel has been opened is used right away
+    let client = build_client(Default::default());
+    let mut event_handler = StratumEventHandler::new(
+        client.clone(),
+        session_with_target(ii_bitcoin::Target::from_pool_difficulty(4)),
+        client.context(),
+    );
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    assert_eq!(last_job_difficulty(&client), Some(4));
+    assert!(event_handler.target_wait_delay().is_none());
+
+    // The first job waits for the target by default, a newer job replaces it
+    let client = build_client(Default::default());
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), session_without_target(), client.context());
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    assert_eq!(last_job_id(&client), None);
+    let delay = event_handler
+        .target_wait_delay()
+        .expect("BUG: job doesn't wait for target");
+    assert!(delay <= time::Duration::from_millis(StratumV2Config::DEFAULT_MISSING_TARGET_WAIT));
+    new_job(&client, &mut event_handler, 2, false).await;
+    assert_eq!(last_job_id(&client), None);
+    set_target(&client, &mut event_handler, 16).await;
+    assert_eq!(last_job_id(&client), Some(2));
+    assert_eq!(last_job_difficulty(&client), Some(16));
+    assert!(event_handler.target_wait_delay().is_none());
+
+    // The wait is bounded, the job falls back to difficulty 1 when it elapses
+    let client = build_client(StratumV2Config {
+        missing_target_wait: Some(0),
+        ..Default::default()
+    });
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), session_without_target(), client.context());
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    assert_eq!(last_job_id(&client), None);
+    assert_eq!(
+        event_handler.target_wait_delay(),
+        Some(time::Duration::from_secs(0))
+    );
+    event_handler.target_wait_expired().await;
+    assert_eq!(last_job_id(&client), Some(1));
+    assert_eq!(last_job_difficulty(&client), Some(1));
+    new_job(&client, &mut event_handler, 2, false).await;
+    assert_eq!(last_job_id(&client), Some(2));
+
+    // The fallback policy mines at difficulty 1 right away
+    let client = build_client(StratumV2Config {
+        missing_target_policy: Some(MissingTargetPolicy::Fallback),
+        ..Default::default()
+    });
+    let mut event_handler =
+        StratumEventHandler::new(client.clone(), session_without_target(), client.context());
+    new_job(&client, &mut event_handler, 1, true).await;
+    new_prev_hash(&client, &mut event_handler, 1).await;
+    assert_eq!(last_job_id(&client), Some(1));
+    assert_eq!(last_job_difficulty(&client), Some(1));
+    assert!(event_handler.target_wait_delay().is_none());
+}
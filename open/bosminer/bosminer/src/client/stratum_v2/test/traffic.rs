@@ -0,0 +1,359 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+#[tokio::test]
+async fn test_transmit_stall() {
+    let client = build_client(transmit_stall_config());
+    let mut event_handler = start_mining(&client).await;
+    // The pool doesn't read the submissions but it keeps sending jobs
+    submit_solutions(&client, 3).await;
+
+    let mut job_id = 2;
+    let e = loop {
+        tokio::time::delay_for(time::Duration::from_millis(10)).await;
+        if let Err(e) = client
+            .handle_frame(job_frame(job_id), &mut event_handler)
+            .await
+        {
+            break e;
+        }
+        job_id += 1;
+        assert!(job_id < 100, "BUG: stall hasn't been detected");
+        if transmit_stalls(&client) > 0 {
+            assert_eq!(
+                client.health(),
+                health::Health::Degraded(vec![health::DegradedReason::TransmitStalled])
+            );
+        }
+    };
+    match e.kind() {
+        error::ErrorKind::Client(error::Client::TransmitStalled(_)) => {}
+        kind => panic!("unexpected error: {:?}", kind),
+    }
+    // The stall is reported once before the connection is restarted
+    assert_eq!(transmit_stalls(&client), 1);
+
+    // An acknowledgement means that the submissions flow again
+    let mut event_handler = start_mining(&client).await;
+    assert!(client
+        .handle_frame(ack_frame(2, 3, true), &mut event_handler)
+        .await
+        .is_ok());
+    assert!(client.health().is_healthy());
+}
+
+#[tokio::test]
+async fn test_transmit_quiet_period() {
+    let client = build_client(transmit_stall_config());
+    let mut event_handler = start_mining(&client).await;
+    submit_solutions(&client, 3).await;
+
+    // The pool is quiet for longer than the timeout and the grace period, then it acknowledges
+    // all the shares
+    tokio::time::delay_for(time::Duration::from_millis(150)).await;
+    assert!(client
+        .handle_frame(ack_frame(2, 3, true), &mut event_handler)
+        .await
+        .is_ok());
+
+    // A single share may wait for a batched acknowledgement while jobs keep arriving
+    submit_solutions(&client, 1).await;
+    for job_id in 2..12 {
+        tokio::time::delay_for(time::Duration::from_millis(15)).await;
+        assert!(client
+            .handle_frame(job_frame(job_id), &mut event_handler)
+            .await
+            .is_ok());
+    }
+    assert_eq!(transmit_stalls(&client), 0);
+    assert!(client.health().is_healthy());
+}
+
+#[test]
+fn test_transmit_slow_sends() {
+    let thresholds = transmit::Thresholds {
+        slow_send: time::Duration::from_millis(500),
+        ack_timeout: time::Duration::from_secs(30),
+        grace: time::Duration::from_secs(10),
+    };
+    let start = time::Instant::now();
+    let at = |ms| start + time::Duration::from_millis(ms);
+    let mut monitor = transmit::Monitor::default();
+
+    // A fast send breaks the sequence of slow sends
+    monitor.send_completed(at(0), at(600), &thresholds);
+    monitor.send_completed(at(600), at(1200), &thresholds);
+    monitor.send_completed(at(1200), at(1210), &thresholds);
+    monitor.frame_received(at(1300));
+    assert_eq!(
+        monitor.check_at(at(1300), &thresholds),
+        transmit::Verdict::Healthy
+    );
+
+    for i in 0..transmit::Monitor::SLOW_SENDS as u64 {
+        monitor.send_completed(at(2000 + i * 700), at(2700 + i * 700), &thresholds);
+    }
+    // Slow sends without any frame from the pool are left to the receive watchdog
+    assert_eq!(
+        monitor.check_at(at(5000), &thresholds),
+        transmit::Verdict::Healthy
+    );
+
+    monitor.frame_received(at(5000));
+    let stall = transmit::Stall::SlowSends {
+        count: transmit::Monitor::SLOW_SENDS,
+        slowest: time::Duration::from_millis(700),
+    };
+    assert_eq!(
+        monitor.check_at(at(5000), &thresholds),
+        transmit::Verdict::Stalled(stall.clone())
+    );
+    assert_eq!(
+        monitor.check_at(at(14000), &thresholds),
+        transmit::Verdict::Persisting
+    );
+    assert_eq!(
+        monitor.check_at(at(15000), &thresholds),
+        transmit::Verdict::Expired(stall)
+    );
+
+    monitor.send_completed(at(15000), at(15010), &thresholds);
+    assert_eq!(
+        monitor.check_at(at(15010), &thresholds),
+        transmit::Verdict::Recovered
+    );
+}
+
+#[tokio::test]
+async fn test_io_timeout() {
+    let timeout = Some(time::Duration::from_millis(50));
+
+    // A stream that keeps delivering frames doesn't time out
+    let (mut frame_tx, frame_rx) = mpsc::unbounded::<std::io::Result<u32>>();
+    let mut stream = io_timeout::TimedStream::new(frame_rx, timeout);
+    for frame in 0..3 {
+        tokio::time::delay_for(time::Duration::from_millis(30)).await;
+        frame_tx
+            .send(Ok(frame))
+            .await
+            .expect("BUG: cannot send frame");
+        assert_eq!(
+            stream
+                .next()
+                .await
+                .expect("BUG: stream has finished")
+                .expect("BUG: read has failed"),
+            frame
+        );
+    }
+    // The read of a silent (half-open) connection fails
+    let error = stream
+        .next()
+        .await
+        .expect("BUG: stream has finished")
+        .expect_err("BUG: read has not timed out");
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    // The connection is not timed when there is no timeout
+    let mut stream =
+        io_timeout::TimedStream::new(futures::stream::pending::<std::io::Result<u32>>(), None);
+    assert!(stream
+        .next()
+        .timeout(time::Duration::from_millis(100))
+        .await
+        .is_err());
+
+    // A write that cannot complete (the pool doesn't read the socket) fails
+    let (frame_tx, _frame_rx) = mpsc::channel::<u32>(0);
+    let mut sink = io_timeout::TimedSink::new(
+        frame_tx.sink_map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string())),
+        timeout,
+    );
+    sink.send(1).await.expect("BUG: cannot send frame");
+    let error = sink
+        .send(2)
+        .await
+        .expect_err("BUG: write has not timed out");
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+}
+
+fn build_bandwidth_frame() -> <Framing as ii_wire::Framing>::Tx {
+    SetTarget {
+        channel_id: 0,
+        max_target: Uint256Bytes([0xff; 32]),
+    }
+    .try_into()
+    .expect("BUG: cannot build frame")
+}
+
+#[test]
+fn test_bandwidth_bucket() {
+    let now = time::Instant::now();
+    let mut bucket = bandwidth::Bucket::new(
+        bandwidth::Limit {
+            rate: 100,
+            burst: 50,
+        },
+        now,
+    );
+    // A transfer is allowed as long as the bucket is not in debt
+    bucket.consume(50, now);
+    assert_eq!(bucket.delay(now), None);
+    bucket.consume(50, now);
+    assert_eq!(bucket.delay(now), Some(time::Duration::from_millis(500)));
+    assert_eq!(bucket.delay(now + time::Duration::from_millis(500)), None);
+    // The bucket doesn't fill above the burst
+    let later = now + time::Duration::from_secs(60);
+    bucket.consume(100, later);
+    assert_eq!(bucket.delay(later), Some(time::Duration::from_millis(500)));
+}
+
+#[tokio::test]
+async fn test_bandwidth_throttling() {
+    let (frame_size, _) =
+        transcript::serialize_frame(build_bandwidth_frame()).expect("BUG: cannot serialize frame");
+    let frame_size = frame_size.len();
+    assert_eq!(
+        bandwidth::WireSize::wire_size(build_bandwidth_frame())
+            .expect("BUG: cannot determine frame size")
+            .0,
+        frame_size
+    );
+    // The first two frames are transferred right away, each of the following ones waits 100ms
+    // until the debt is paid off
+    let limit = bandwidth::Limit {
+        rate: frame_size as u64 * 10,
+        burst: frame_size as u64,
+    };
+    let meter = Arc::new(bandwidth::Meter::default());
+
+    let (frame_tx, mut frame_rx) = mpsc::unbounded();
+    let mut sink = bandwidth::ThrottledSink::new(
+        frame_tx.sink_map_err(|e| -> ii_stratum::error::Error {
+            ii_stratum::error::ErrorKind::General(e.to_string()).into()
+        }),
+        meter.clone(),
+        Some(limit),
+    );
+    let started = time::Instant::now();
+    for _ in 0..4 {
+        sink.send(build_bandwidth_frame())
+            .await
+            .expect("BUG: cannot send frame");
+    }
+    assert!(started.elapsed() >= time::Duration::from_millis(190));
+    for _ in 0..4 {
+        let frame = frame_rx.next().await.expect("BUG: frame has not been sent");
+        SetTarget::try_from(frame).expect("BUG: cannot decode sent frame");
+    }
+
+    let mut frames = bandwidth::ThrottledStream::new(
+        stream::iter((0..4).map(|_| Ok::<_, ii_stratum::error::Error>(build_bandwidth_frame()))),
+        meter.clone(),
+        Some(limit),
+    );
+    let started = time::Instant::now();
+    for _ in 0..4 {
+        frames
+            .next()
+            .await
+            .expect("BUG: stream has finished")
+            .expect("BUG: read has failed");
+    }
+    assert!(started.elapsed() >= time::Duration::from_millis(190));
+
+    let status = meter.status_at(time::Instant::now(), Some(limit), None);
+    for direction in &[status.send, status.receive] {
+        assert_eq!(direction.bytes, 4 * frame_size as u64);
+        assert_eq!(
+            direction.rate,
+            4 * frame_size as u64 / bandwidth::Meter::RATE_WINDOW.as_secs()
+        );
+        assert!(direction.throttled >= 2);
+    }
+    assert_eq!(status.send.limit, Some(limit.rate));
+    assert_eq!(status.receive.limit, None);
+    let throttled = status.send.throttled;
+
+    // Frames are only accounted when there is no limit
+    let (frame_tx, _frame_rx) = mpsc::unbounded();
+    let mut sink = bandwidth::ThrottledSink::new(
+        frame_tx.sink_map_err(|e| -> ii_stratum::error::Error {
+            ii_stratum::error::ErrorKind::General(e.to_string()).into()
+        }),
+        meter.clone(),
+        None,
+    );
+    for _ in 0..8 {
+        sink.send(build_bandwidth_frame())
+            .await
+            .expect("BUG: cannot send frame");
+    }
+    let status = meter.status_at(time::Instant::now(), None, None);
+    assert_eq!(status.send.bytes, 12 * frame_size as u64);
+    assert_eq!(status.send.throttled, throttled);
+}
+
+#[test]
+fn test_bandwidth_limit_config() {
+    let client = build_client(Default::default());
+    let status = client.status_document().bandwidth;
+    assert_eq!((status.send.limit, status.receive.limit), (None, None));
+
+    let config = StratumV2Config {
+        bandwidth_limit: Some(StratumV2BandwidthLimit {
+            send_rate: Some(1000),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+    let client = build_client(config);
+    assert_eq!(
+        client.connection_details().bandwidth_limits(),
+        (
+            Some(bandwidth::Limit {
+                rate: 1000,
+                burst: StratumV2BandwidthLimit::DEFAULT_BURST,
+            }),
+            None
+        )
+    );
+    assert_eq!(client.bandwidth().send.limit, Some(1000));
+
+    for &(send_rate, receive_rate, burst) in &[
+        (Some(0), None, None),
+        (None, Some(0), None),
+        (Some(1000), None, Some(0)),
+    ] {
+        let config = StratumV2Config {
+            bandwidth_limit: Some(StratumV2BandwidthLimit {
+                send_rate,
+                receive_rate,
+                burst,
+            }),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Frames exchanged while setting up the mining connection and opening the channel are recorded
+//! so that a failed handshake can be investigated without capturing the network traffic. Only
+//! the transcript of the last failed handshake is kept by the client.
+//!
+//! The user name is redacted in the decoded summaries. Raw bytes of a frame that carries the
+//! user name cannot be redacted without breaking the frame so they are recorded only when
+//! explicitly allowed.
+
+use crate::error;
+
+use ii_async_compat::bytes::BytesMut;
+use ii_async_compat::tokio_util::codec::{Decoder, Encoder};
+
+use ii_stratum::v2::{Codec, Frame};
+
+use serde::Serialize;
+
+use std::time;
+
+/// Maximal number of frames retained in a single transcript
+pub const MAX_ENTRIES: usize = 16;
+/// Maximal number of bytes retained from a single frame
+pub const MAX_FRAME_BYTES: usize = 512;
+/// Replacement of the user name in the decoded summaries
+pub const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Entry {
+    pub time: time::SystemTime,
+    pub direction: Direction,
+    /// Decoded message with the user name redacted
+    pub summary: String,
+    /// Hex encoded bytes of the frame. Missing when the frame carries the user name and raw bytes
+    /// haven't been allowed.
+    pub bytes: Option<String>,
+    /// Only the first `MAX_FRAME_BYTES` of the frame have been retained
+    pub truncated: bool,
+}
+
+/// Frames of a single handshake in the order they have been exchanged
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Transcript {
+    pub entries: Vec<Entry>,
+    /// Number of frames that haven't fit into the transcript
+    pub dropped: usize,
+}
+
+/// Records frames of a handshake that is in progress
+#[derive(Debug)]
+pub struct Recorder {
+    user: String,
+    raw_bytes: bool,
+    /// The last received frame has been recorded and it hasn't been decoded yet
+    undecoded: bool,
+    transcript: Transcript,
+}
+
+impl Recorder {
+    pub fn new(user: String, raw_bytes: bool) -> Self {
+        Self {
+            user,
+            raw_bytes,
+            undecoded: false,
+            transcript: Default::default(),
+        }
+    }
+
+    fn redact(&self, summary: String) -> String {
+        if self.user.is_empty() {
+            return summary;
+        }
+        // Debug output of the message quotes the user name the same way as a standalone string.
+        // Matching the quoted name prevents redacting unrelated parts of the summary.
+        summary.replace(&format!("{:?}", self.user), &format!("{:?}", REDACTED))
+    }
+
+    fn carries_user(&self, bytes: &[u8]) -> bool {
+        let user = self.user.as_bytes();
+        !user.is_empty() && bytes.windows(user.len()).any(|window| window == user)
+    }
+
+    pub fn record(
+        &mut self,
+        direction: Direction,
+        bytes: &[u8],
+        summary: String,
+        now: time::SystemTime,
+    ) {
+        self.undecoded = false;
+        if self.transcript.entries.len() >= MAX_ENTRIES {
+            self.transcript.dropped += 1;
+            return;
+        }
+        let truncated = bytes.len() > MAX_FRAME_BYTES;
+        let bytes = if self.raw_bytes || !self.carries_user(bytes) {
+            Some(hex::encode(&bytes[..bytes.len().min(MAX_FRAME_BYTES)]))
+        } else {
+            None
+        };
+        let entry = Entry {
+            time: now,
+            direction,
+            summary: self.redact(summary),
+            bytes,
+            truncated,
+        };
+        self.transcript.entries.push(entry);
+        self.undecoded = direction == Direction::Received;
+    }
+
+    /// Replace the summary of the last received frame once the frame has been decoded
+    pub fn describe_received(&mut self, summary: String) {
+        if !self.undecoded {
+            return;
+        }
+        let summary = self.redact(summary);
+        if let Some(entry) = self.transcript.entries.last_mut() {
+            entry.summary = summary;
+        }
+        self.undecoded = false;
+    }
+
+    pub fn finish(self) -> Transcript {
+        self.transcript
+    }
+}
+
+/// Serialize `frame` for the transcript. The frame is consumed by the encoder, therefore an
+/// equivalent frame is decoded back from the serialized bytes.
+pub fn serialize_frame(frame: Frame) -> error::Result<(Vec<u8>, Frame)> {
+    let mut bytes = BytesMut::new();
+    Codec::default().encode(frame, &mut bytes)?;
+    let serialized = bytes.to_vec();
+    let frame = Codec::default()
+        .decode(&mut bytes)?
+        .ok_or("BUG: cannot decode serialized frame")?;
+    Ok((serialized, frame))
+}
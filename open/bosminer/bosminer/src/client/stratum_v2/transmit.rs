@@ -0,0 +1,208 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Detection of a connection that keeps delivering frames from the pool while share submissions
+//! stall (e.g. a middlebox that no longer forwards the transmit direction). The receive watchdog
+//! doesn't notice it because jobs keep arriving. The transmit direction is classified as stalled
+//! when:
+//! - several consecutive share sends take longer than the threshold, or
+//! - a submitted share hasn't been acknowledged within the timeout although newer shares have
+//!   been submitted after it
+//!
+//! In both cases the pool must have sent a frame since the stall has started. A quiet pool that
+//! sends nothing is left to the receive watchdog, it cannot be told apart from a dead connection.
+
+use std::fmt;
+use std::time;
+
+/// Thresholds of the classification resolved from the configuration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    /// A share send that takes at least this long is slow
+    pub slow_send: time::Duration,
+    /// Age of the oldest unacknowledged share that is considered stalled
+    pub ack_timeout: time::Duration,
+    /// Time for which a stall may persist before the connection is restarted
+    pub grace: time::Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stall {
+    /// Consecutive share sends have been slow
+    SlowSends {
+        count: usize,
+        slowest: time::Duration,
+    },
+    /// Shares haven't been acknowledged for `age` while the pool keeps sending frames
+    AcksStalled {
+        outstanding: usize,
+        age: time::Duration,
+    },
+}
+
+impl fmt::Display for Stall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SlowSends { count, slowest } => write!(
+                f,
+                "{} consecutive slow share sends (slowest {}ms)",
+                count,
+                slowest.as_millis()
+            ),
+            Self::AcksStalled { outstanding, age } => write!(
+                f,
+                "{} shares unacknowledged for {}ms while the pool keeps sending frames",
+                outstanding,
+                age.as_millis()
+            ),
+        }
+    }
+}
+
+/// Result of a single check of the transmit direction
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Healthy,
+    /// The stall has just been detected
+    Stalled(Stall),
+    /// The stall is still within the grace period
+    Persisting,
+    /// The stall has outlasted the grace period, the connection has to be restarted
+    Expired(Stall),
+    /// The stall has disappeared
+    Recovered,
+}
+
+/// State of the transmit direction within a single session
+#[derive(Debug, Default)]
+pub struct Monitor {
+    /// Submission time of the oldest share submitted since the last acknowledgement
+    waiting_since: Option<time::Instant>,
+    /// Number of shares submitted since the last acknowledgement
+    outstanding: usize,
+    /// Start of the first send in the current sequence of slow sends
+    slow_since: Option<time::Instant>,
+    slow_sends: usize,
+    slowest_send: time::Duration,
+    last_frame: Option<time::Instant>,
+    stalled_since: Option<time::Instant>,
+}
+
+impl Monitor {
+    /// Number of consecutive slow sends that are classified as a stall
+    pub const SLOW_SENDS: usize = 3;
+    /// Minimal number of unacknowledged shares, a single share may legitimately wait for a batched
+    /// acknowledgement when shares are rare
+    pub const MIN_OUTSTANDING: usize = 2;
+
+    pub fn reset(&mut self) {
+        *self = Default::default();
+    }
+
+    pub fn submitted(&mut self, now: time::Instant) {
+        self.waiting_since.get_or_insert(now);
+        self.outstanding += 1;
+    }
+
+    /// Account a send of a share that has been started at `started` and completed at `now`
+    pub fn send_completed(
+        &mut self,
+        started: time::Instant,
+        now: time::Instant,
+        thresholds: &Thresholds,
+    ) {
+        let duration = now.saturating_duration_since(started);
+        if duration < thresholds.slow_send {
+            self.slow_since = None;
+            self.slow_sends = 0;
+            self.slowest_send = Default::default();
+            return;
+        }
+        self.slow_since.get_or_insert(started);
+        self.slow_sends += 1;
+        self.slowest_send = self.slowest_send.max(duration);
+    }
+
+    /// The pool has acknowledged (accepted or rejected) submitted shares, the transmit direction
+    /// works
+    pub fn acknowledged(&mut self) {
+        self.waiting_since = None;
+        self.outstanding = 0;
+    }
+
+    pub fn frame_received(&mut self, now: time::Instant) {
+        self.last_frame = Some(now);
+    }
+
+    /// Returns true when the pool has sent a frame after `since`
+    fn received_after(&self, since: time::Instant) -> bool {
+        self.last_frame
+            .map_or(false, |last_frame| last_frame > since)
+    }
+
+    fn stall(&self, now: time::Instant, thresholds: &Thresholds) -> Option<Stall> {
+        if let Some(slow_since) = self.slow_since {
+            if self.slow_sends >= Self::SLOW_SENDS && self.received_after(slow_since) {
+                return Some(Stall::SlowSends {
+                    count: self.slow_sends,
+                    slowest: self.slowest_send,
+                });
+            }
+        }
+        if let Some(waiting_since) = self.waiting_since {
+            let age = now.saturating_duration_since(waiting_since);
+            if self.outstanding >= Self::MIN_OUTSTANDING
+                && age >= thresholds.ack_timeout
+                && self.received_after(waiting_since)
+            {
+                return Some(Stall::AcksStalled {
+                    outstanding: self.outstanding,
+                    age,
+                });
+            }
+        }
+        None
+    }
+
+    /// Classify the transmit direction at `now`
+    pub fn check_at(&mut self, now: time::Instant, thresholds: &Thresholds) -> Verdict {
+        let stall = match self.stall(now, thresholds) {
+            Some(stall) => stall,
+            None => {
+                return match self.stalled_since.take() {
+                    Some(_) => Verdict::Recovered,
+                    None => Verdict::Healthy,
+                }
+            }
+        };
+        match self.stalled_since {
+            None => {
+                self.stalled_since = Some(now);
+                Verdict::Stalled(stall)
+            }
+            Some(since) if now.saturating_duration_since(since) >= thresholds.grace => {
+                Verdict::Expired(stall)
+            }
+            Some(_) => Verdict::Persisting,
+        }
+    }
+}
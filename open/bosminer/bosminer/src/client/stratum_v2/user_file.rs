@@ -0,0 +1,152 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! The pool user (typically an account token) may be provisioned into a separate file that is
+//! rotated by an external agent. The file is read when connecting to the pool and its
+//! modification time is polled during the session to detect a new user.
+
+use crate::error;
+
+use bosminer_config::StratumV2Config;
+
+use serde::Serialize;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time;
+
+/// Status of the user file, it never contains the user itself
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Status {
+    pub path: PathBuf,
+    /// Time of the last successful read of the file
+    pub last_read: Option<time::SystemTime>,
+}
+
+/// Result of polling the user file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Poll {
+    Unchanged,
+    /// The file contains a different user than the one that has been read last time
+    Changed,
+    /// The file has become unavailable (reported only once until it is available again)
+    Unavailable(String),
+}
+
+/// Observed state of the file
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Stamp {
+    modified: Option<time::SystemTime>,
+    len: u64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> std::io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Source {
+    path: PathBuf,
+    /// User that has been read last time
+    user: Option<String>,
+    stamp: Option<Stamp>,
+    last_read: Option<time::SystemTime>,
+    unavailable: bool,
+}
+
+impl Source {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            user: None,
+            stamp: None,
+            last_read: None,
+            unavailable: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// User that has been read last time
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Read the user from the file
+    pub fn read(&mut self) -> error::Result<String> {
+        let stamp = Stamp::of(&self.path).ok();
+        let user = StratumV2Config::read_user_file(&self.path)
+            .map_err(|e| error::Client::UserFile(e.to_string()))?;
+        self.user = Some(user.clone());
+        self.stamp = stamp;
+        self.last_read = Some(time::SystemTime::now());
+        self.unavailable = false;
+        Ok(user)
+    }
+
+    /// Check whether the file has been modified and whether it contains a different user
+    pub fn poll(&mut self) -> Poll {
+        let stamp = match Stamp::of(&self.path) {
+            Ok(stamp) => stamp,
+            Err(e) => return self.report_unavailable(e.to_string()),
+        };
+        if self.stamp == Some(stamp) && !self.unavailable {
+            return Poll::Unchanged;
+        }
+        match StratumV2Config::read_user_file(&self.path) {
+            Ok(user) => {
+                self.stamp = Some(stamp);
+                self.unavailable = false;
+                if self.user.as_ref() == Some(&user) {
+                    Poll::Unchanged
+                } else {
+                    Poll::Changed
+                }
+            }
+            Err(e) => self.report_unavailable(e.to_string()),
+        }
+    }
+
+    fn report_unavailable(&mut self, reason: String) -> Poll {
+        if self.unavailable {
+            Poll::Unchanged
+        } else {
+            self.unavailable = true;
+            Poll::Unavailable(reason)
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            path: self.path.clone(),
+            last_read: self.last_read,
+        }
+    }
+}
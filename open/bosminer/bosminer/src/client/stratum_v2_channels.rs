@@ -24,6 +24,8 @@
 
 use ii_logging::macros::*;
 
+use super::target_util;
+
 use crate::error;
 use crate::job;
 use crate::node;
@@ -185,6 +187,15 @@ impl job::Bitcoin for StratumJob {
 /// up with the protocol.
 type SolutionQueue = Mutex<VecDeque<(work::Solution, u32)>>;
 
+/// Final state of a submitted solution that determines the meter it is accounted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Accepted,
+    Rejected,
+    /// The solution hasn't been acknowledged before the connection has been closed
+    Stale,
+}
+
 /// Helper task for `StratumClient` that implements Stratum V2 visitor which processes incoming
 /// messages from remote server.
 struct StratumEventHandler {
@@ -222,55 +233,57 @@ impl StratumEventHandler {
     }
 
     fn update_target(&mut self, value: Uint256Bytes) {
-        let new_target: ii_bitcoin::Target = value.into();
+        let new_target = match target_util::checked_pool_target_from_le_bytes(value.as_ref()) {
+            Ok(target) => target,
+            Err(e) => {
+                // Keep mining with the previous target
+                warn!("Stratum: ignoring new target: {}", e);
+                return;
+            }
+        };
         info!(
             "Stratum: changing target to {} diff={}",
             new_target,
-            new_target.get_difficulty()
+            target_util::difficulty_from_target(&new_target)
         );
         self.current_target = new_target;
     }
 
     async fn process_accepted_shares(&self, success_msg: &SubmitSharesSuccess) {
-        let now = std::time::Instant::now();
-        while let Some((solution, seq_num)) = self.client.solutions.lock().await.pop_front() {
+        let (acknowledged, found) = self
+            .client
+            .take_acknowledged(success_msg.last_seq_num)
+            .await;
+        let mut outcomes = Vec::with_capacity(acknowledged.len());
+        for (solution, seq_num) in acknowledged {
             info!(
-                "Stratum: accepted solution #{} with nonce={:08x}",
+                "Stratum: accepted solution #{} with nonce={:08x} diff={}",
                 seq_num,
-                solution.nonce()
+                solution.nonce(),
+                target_util::difficulty_from_target(&solution.job_target())
             );
-            self.client
-                .client_stats
-                .accepted
-                .account_solution(&solution.job_target(), now)
-                .await;
-            if success_msg.last_seq_num == seq_num {
-                // all accepted solutions have been found
-                return;
-            }
+            outcomes.push((Outcome::Accepted, solution));
         }
-        warn!(
-            "Stratum: last accepted solution #{} hasn't been found!",
-            success_msg.last_seq_num
-        );
+        if !found {
+            warn!(
+                "Stratum: last accepted solution #{} hasn't been found!",
+                success_msg.last_seq_num
+            );
+        }
+        self.client.account_solutions(outcomes).await;
     }
 
     async fn process_rejected_shares(&self, error_msg: &SubmitSharesError) {
-        let now = std::time::Instant::now();
-        while let Some((solution, seq_num)) = self.client.solutions.lock().await.pop_front() {
+        let (acknowledged, found) = self.client.take_acknowledged(error_msg.seq_num).await;
+        let mut outcomes = Vec::with_capacity(acknowledged.len());
+        for (solution, seq_num) in acknowledged {
             if error_msg.seq_num == seq_num {
                 info!(
                     "Stratum: rejected solution #{} with nonce={:08x}!",
                     seq_num,
                     solution.nonce()
                 );
-                self.client
-                    .client_stats
-                    .rejected
-                    .account_solution(&solution.job_target(), now)
-                    .await;
-                // the rejected solution has been found
-                return;
+                outcomes.push((Outcome::Rejected, solution));
             } else {
                 // TODO: this is currently not according to stratum V2 specification
                 // preceding solutions are treated as accepted
@@ -279,11 +292,6 @@ impl StratumEventHandler {
                     seq_num,
                     solution.nonce()
                 );
-                self.client
-                    .client_stats
-                    .accepted
-                    .account_solution(&solution.job_target(), now)
-                    .await;
                 warn!(
                     "Stratum: the solution #{} precedes rejected solution #{}!",
                     seq_num, error_msg.seq_num
@@ -292,12 +300,16 @@ impl StratumEventHandler {
                     "Stratum: the solution #{} is treated as an accepted one",
                     seq_num
                 );
+                outcomes.push((Outcome::Accepted, solution));
             }
         }
-        warn!(
-            "Stratum: rejected solution #{} hasn't been found!",
-            error_msg.seq_num
-        );
+        if !found {
+            warn!(
+                "Stratum: rejected solution #{} hasn't been found!",
+                error_msg.seq_num
+            );
+        }
+        self.client.account_solutions(outcomes).await;
     }
 }
 
@@ -418,7 +430,13 @@ where
     }
 
     async fn process_solution(&mut self, solution: work::Solution) -> error::Result<()> {
-        let job: &StratumJob = solution.job();
+        let job: &StratumJob = match solution.checked_job(self.client.clone()) {
+            Ok(job) => job,
+            Err(mismatch) => {
+                warn!("Stratum: dropping solution of a {}", mismatch);
+                return Ok(());
+            }
+        };
 
         let seq_num = self.seq_num;
         self.seq_num = self.seq_num.wrapping_add(1);
@@ -456,7 +474,7 @@ impl StratumConnectionHandler {
     pub fn new(client: Arc<StratumClient>) -> Self {
         Self {
             client,
-            init_target: Default::default(),
+            init_target: target_util::difficulty_1_target(),
             status: None,
         }
     }
@@ -529,7 +547,9 @@ impl StratumConnectionHandler {
                 .expect("BUG: cannot convert 'OpenStandardMiningChannel::user'"),
             nominal_hashrate: 1e9,
             // Maximum bitcoin target is 0xffff << 208 (= difficulty 1 share)
-            max_target: ii_bitcoin::Target::default().into(),
+            max_target: Uint256Bytes(target_util::target_into_le_bytes(
+                &target_util::difficulty_1_target(),
+            )),
         };
 
         StratumClient::send_msg(connection_tx, channel_msg)
@@ -613,7 +633,10 @@ impl Handler for StratumConnectionHandler {
         _header: &Header,
         success_msg: &OpenStandardMiningChannelSuccess,
     ) {
-        self.init_target = success_msg.target.into();
+        match target_util::checked_pool_target_from_le_bytes(success_msg.target.as_ref()) {
+            Ok(target) => self.init_target = target,
+            Err(e) => warn!("Stratum: ignoring initial target: {}", e),
+        }
         self.status = Ok(()).into();
     }
 
@@ -668,6 +691,55 @@ impl StratumClient {
         self.last_job.lock().await.replace(Arc::downgrade(&job));
     }
 
+    /// Remove solutions up to and including `seq_num` from the queue of submitted solutions in a
+    /// single critical section. Returns the removed solutions and whether `seq_num` has been
+    /// found.
+    async fn take_acknowledged(&self, seq_num: u32) -> (Vec<(work::Solution, u32)>, bool) {
+        let mut solutions = self.solutions.lock().await;
+        let mut acknowledged = Vec::new();
+        while let Some((solution, solution_seq_num)) = solutions.pop_front() {
+            acknowledged.push((solution, solution_seq_num));
+            if solution_seq_num == seq_num {
+                return (acknowledged, true);
+            }
+        }
+        (acknowledged, false)
+    }
+
+    /// Account solutions removed from the queue of submitted solutions in a separate task so
+    /// that cancellation of the caller cannot lose them
+    async fn account_solutions(self: &Arc<Self>, outcomes: Vec<(Outcome, work::Solution)>) {
+        if outcomes.is_empty() {
+            return;
+        }
+        let client = self.clone();
+        let now = time::Instant::now();
+        tokio::spawn(async move {
+            for (outcome, solution) in outcomes {
+                let meter = match outcome {
+                    Outcome::Accepted => &client.client_stats.accepted,
+                    Outcome::Rejected => &client.client_stats.rejected,
+                    Outcome::Stale => &client.client_stats.stale,
+                };
+                meter.account_solution(&solution.job_target(), now).await;
+            }
+        })
+        .await
+        .expect("BUG: accounting of solutions failed");
+    }
+
+    /// Account all solutions that are still waiting for acknowledgement as stale
+    async fn discard_pending(self: &Arc<Self>) {
+        let outcomes = self
+            .solutions
+            .lock()
+            .await
+            .drain(..)
+            .map(|(solution, _)| (Outcome::Stale, solution))
+            .collect();
+        self.account_solutions(outcomes).await;
+    }
+
     /// Send a message down a specified Tx Sink
     async fn send_msg<M, S>(connection_tx: &mut S, message: M) -> error::Result<()>
     where
@@ -789,7 +861,7 @@ impl StratumClient {
             // Flush all unprocessed solutions to empty buffer
             // TODO: Count as a discarded solution?
             self.solution_receiver.lock().await.flush();
-            self.solutions.lock().await.clear();
+            self.discard_pending().await;
 
             if self.status.can_stop() {
                 // NOTE: it is not safe to add here any code!
@@ -0,0 +1,146 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Annotations of switches of the active client made by the scheduler. Every switch moves the
+//! hashrate from one client to another and both clients record it in their journal, so that the
+//! switch can be correlated with the shares of both clients. Annotations of the same switch are
+//! paired by the sequence number assigned by the scheduler.
+
+use crate::sync;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex as StdMutex;
+use std::time;
+
+/// Cause of the switch determined by the scheduler
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    /// The previously active client stopped running, it has been left in the attached status
+    Failover(sync::Status),
+    /// The hashrate has been moved to another group to keep the configured quota or share ratio
+    Quota,
+    /// The previously active client has been disabled by a command
+    Manual,
+    /// Client preceding the active one within the same group is running again
+    PrimaryRecovered,
+    /// A window of the client schedule has opened or closed
+    Schedule,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Failover(status) => write!(f, "failover (client is {})", status),
+            Self::Quota => write!(f, "quota"),
+            Self::Manual => write!(f, "manual"),
+            Self::PrimaryRecovered => write!(f, "primary recovered"),
+            Self::Schedule => write!(f, "schedule"),
+        }
+    }
+}
+
+/// Role of the annotated client in the switch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Activated,
+    Deactivated,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    /// Sequence number of the switch assigned by the scheduler
+    pub seq_num: u64,
+    pub time: time::SystemTime,
+    pub role: Role,
+    pub reason: Reason,
+    /// Endpoint (URL without the user) of the other client of the switch
+    pub peer: String,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    annotations: VecDeque<Annotation>,
+    last_seq_num: Option<u64>,
+    /// Number of switches since the last snapshot of the switch count
+    switches: usize,
+}
+
+/// Journal keeps only a limited number of the most recent annotations
+#[derive(Debug)]
+pub struct Journal {
+    capacity: usize,
+    state: StdMutex<State>,
+}
+
+impl Journal {
+    pub const DEFAULT_CAPACITY: usize = 32;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: StdMutex::new(Default::default()),
+        }
+    }
+
+    /// Record the annotation unless it has been already recorded. Annotations of repeated
+    /// switches (with a sequence number that is not newer than the last one) are ignored.
+    /// Returns false when the annotation has been ignored.
+    pub fn annotate(&self, annotation: Annotation) -> bool {
+        let mut state = self.state.lock().expect("BUG: cannot lock switch journal");
+        if let Some(last_seq_num) = state.last_seq_num {
+            if annotation.seq_num <= last_seq_num {
+                return false;
+            }
+        }
+        state.last_seq_num = Some(annotation.seq_num);
+        state.switches += 1;
+        if state.annotations.len() >= self.capacity {
+            state.annotations.pop_front();
+        }
+        state.annotations.push_back(annotation);
+        true
+    }
+
+    /// Returns all retained annotations in chronological order
+    pub fn snapshot(&self) -> Vec<Annotation> {
+        self.state
+            .lock()
+            .expect("BUG: cannot lock switch journal")
+            .annotations
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the number of switches since the previous call
+    pub fn take_switch_count(&self) -> usize {
+        let mut state = self.state.lock().expect("BUG: cannot lock switch journal");
+        std::mem::replace(&mut state.switches, 0)
+    }
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
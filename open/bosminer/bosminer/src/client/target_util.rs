@@ -0,0 +1,344 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Conversions between mining targets, pool difficulties and hashrate. All difficulty arithmetic
+//! used by clients should go through this module so that the rounding stays consistent.
+//!
+//! The conversions saturate instead of panicking at the extremes: a zero target has the maximal
+//! difficulty, targets easier than difficulty 1 have difficulty 0 and difficulty 0 is treated
+//! as difficulty 1.
+
+use crate::error;
+
+use ii_bitcoin::{HashesUnit, Target};
+
+/// Number of hashes needed on average to find a solution at difficulty 1
+const DIFFICULTY_1_HASHES: f64 = 4_294_967_296.0;
+
+/// Target that represents difficulty 1 (the easiest target that can be requested by clients)
+#[inline]
+pub fn difficulty_1_target() -> Target {
+    Target::default()
+}
+
+/// Convert target received from the remote server as a 256-bit little endian number. Every such
+/// number is represented by `Target` exactly so there is no rounding. A zero target is rejected
+/// because no solution can ever meet it.
+pub fn checked_target_from_le_bytes(bytes: &[u8; 32]) -> error::Result<Target> {
+    let target = Target::from(*bytes);
+    if target.into_inner().is_zero() {
+        Err(error::Client::InvalidTarget(format!(
+            "zero target (raw bytes: {})",
+            hex::encode(bytes)
+        )))?
+    }
+    Ok(target)
+}
+
+/// Binary logarithm of pool difficulty that no pool can reasonably request, it is orders of
+/// magnitude above the difficulty of the Bitcoin network
+const ABSURD_POOL_DIFFICULTY_BITS: usize = 56;
+
+/// Convert target received from the pool like `checked_target_from_le_bytes`. Targets of absurd
+/// difficulty are rejected as well because a single malformed message would otherwise
+/// miscalibrate the whole session.
+pub fn checked_pool_target_from_le_bytes(bytes: &[u8; 32]) -> error::Result<Target> {
+    let target = checked_target_from_le_bytes(bytes)?;
+    let hardest_target =
+        Target::from(difficulty_1_target().into_inner() >> ABSURD_POOL_DIFFICULTY_BITS);
+    if is_harder(&target, &hardest_target) {
+        Err(error::Client::InvalidTarget(format!(
+            "absurd difficulty (raw bytes: {})",
+            hex::encode(bytes)
+        )))?
+    }
+    Ok(target)
+}
+
+/// Convert target into a 256-bit little endian number, the exact inverse of
+/// `checked_target_from_le_bytes`
+pub fn target_into_le_bytes(target: &Target) -> [u8; 32] {
+    (*target).into()
+}
+
+/// Convert target to pool difficulty rounded down
+pub fn difficulty_from_target(target: &Target) -> usize {
+    let target = target.into_inner();
+    if target.is_zero() {
+        return usize::max_value();
+    }
+    let difficulty = difficulty_1_target().into_inner() / target;
+    if difficulty > (usize::max_value() as u64).into() {
+        usize::max_value()
+    } else {
+        difficulty.low_u64() as usize
+    }
+}
+
+/// Convert pool difficulty to target
+pub fn target_from_difficulty(difficulty: usize) -> Target {
+    Target::from_pool_difficulty(difficulty.max(1))
+}
+
+/// Precise difficulty of target including fractions below difficulty 1
+fn difficulty_from_target_f64(target: &Target) -> f64 {
+    target_to_f64(&difficulty_1_target()) / target_to_f64(target)
+}
+
+/// Approximate the 256-bit target with floating point number
+fn target_to_f64(target: &Target) -> f64 {
+    let value = target.into_inner();
+    let shift = value.bits().saturating_sub(64);
+    (value >> shift).low_u64() as f64 * 2f64.powi(shift as i32)
+}
+
+/// Determine target at which a device with specified `hashrate` finds `shares_per_minute`
+/// solutions on average. The result is limited to difficulty 1 for very low hashrate (or zero
+/// hashrate) and it saturates to the hardest pool target when no shares are expected.
+pub fn target_from_hashrate(hashrate: HashesUnit, shares_per_minute: f64) -> Target {
+    let hashes_per_minute = hashrate.into_hashes().into_f64() * 60.0;
+    let difficulty = hashes_per_minute / (shares_per_minute * DIFFICULTY_1_HASHES);
+    // NaN is also mapped to difficulty 1 because `max` returns the other operand
+    let difficulty = difficulty.max(1.0).min(usize::max_value() as f64);
+    target_from_difficulty(difficulty.round() as usize)
+}
+
+/// Expected number of solutions per minute that a device with specified `hashrate` finds at
+/// `target`
+pub fn expected_shares_per_minute(hashrate: HashesUnit, target: &Target) -> f64 {
+    if target.into_inner().is_zero() {
+        return 0.0;
+    }
+    let hashes_per_minute = hashrate.into_hashes().into_f64() * 60.0;
+    hashes_per_minute / (difficulty_from_target_f64(target) * DIFFICULTY_1_HASHES)
+}
+
+/// Returns true when `target` represents higher difficulty than `other`
+#[inline]
+pub fn is_harder(target: &Target, other: &Target) -> bool {
+    target < other
+}
+
+/// Returns true when `target` represents lower difficulty than `other`
+#[inline]
+pub fn is_easier(target: &Target, other: &Target) -> bool {
+    target > other
+}
+
+/// Select the target that represents higher difficulty
+#[inline]
+pub fn harder_of(target: Target, other: Target) -> Target {
+    if is_harder(&target, &other) {
+        target
+    } else {
+        other
+    }
+}
+
+/// Select the target that represents lower difficulty
+#[inline]
+pub fn easier_of(target: Target, other: Target) -> Target {
+    if is_easier(&target, &other) {
+        target
+    } else {
+        other
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use ii_stratum::v2::types::Uint256Bytes;
+
+    /// Deterministic pseudo-random sequence of difficulties spread over the whole range
+    fn sample_difficulties() -> impl Iterator<Item = usize> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..1000).map(move |_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let bits = (state % usize::max_value().count_ones() as u64) as u32;
+            (state as usize >> (usize::max_value().count_ones() - bits - 1)).max(1)
+        })
+    }
+
+    fn target_from_hex(hex: &str) -> Target {
+        Target::from_hex(hex).expect("BUG: invalid target")
+    }
+
+    #[test]
+    fn test_golden_values() {
+        let golden = [
+            (
+                1,
+                "00000000ffff0000000000000000000000000000000000000000000000000000",
+            ),
+            (
+                1024,
+                "00000000003fffc0000000000000000000000000000000000000000000000000",
+            ),
+            (
+                65536,
+                "000000000000ffff000000000000000000000000000000000000000000000000",
+            ),
+            (
+                1_000_000,
+                "00000000000010c6e6d9be4cd74927913e81450efdc9c4da9003eea209aaa3ad",
+            ),
+        ];
+        for (difficulty, hex) in golden.iter() {
+            let target = target_from_hex(hex);
+            assert_eq!(target_from_difficulty(*difficulty), target);
+            assert_eq!(difficulty_from_target(&target), *difficulty);
+        }
+        // Maximal target used by pools is the difficulty 1 target
+        assert_eq!(difficulty_1_target(), target_from_difficulty(1));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for difficulty in sample_difficulties() {
+            let target = target_from_difficulty(difficulty);
+            assert_eq!(difficulty_from_target(&target), difficulty);
+
+            // Any target maps to a target of the same difficulty that is at most one step of
+            // the representation easier
+            let other = Target::from(target.into_inner() - 1);
+            let round_trip = target_from_difficulty(difficulty_from_target(&other));
+            assert_eq!(
+                difficulty_from_target(&round_trip),
+                difficulty_from_target(&other)
+            );
+            assert!(!is_harder(&round_trip, &other));
+        }
+    }
+
+    #[test]
+    fn test_extremes() {
+        let zero = Target::from(Uint256Bytes([0; 32]));
+        let max = Target::from(Uint256Bytes([0xff; 32]));
+        assert_eq!(difficulty_from_target(&zero), usize::max_value());
+        assert_eq!(difficulty_from_target(&max), 0);
+        assert_eq!(target_from_difficulty(0), difficulty_1_target());
+        assert_eq!(
+            difficulty_from_target(&target_from_difficulty(usize::max_value())),
+            usize::max_value()
+        );
+
+        let hashrate = HashesUnit::TeraHashes(14.0);
+        assert_eq!(expected_shares_per_minute(hashrate, &zero), 0.0);
+        assert!(expected_shares_per_minute(hashrate, &max) > 0.0);
+        assert_eq!(
+            target_from_hashrate(HashesUnit::Hashes(0), 20.0),
+            difficulty_1_target()
+        );
+        assert_eq!(
+            target_from_hashrate(hashrate, 0.0),
+            target_from_difficulty(usize::max_value())
+        );
+    }
+
+    #[test]
+    fn test_le_bytes_round_trip() {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        for _ in 0..1000 {
+            let mut bytes = [0u8; 32];
+            for byte in bytes.iter_mut() {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                *byte = state as u8;
+            }
+            // Shorter numbers are tested as well
+            let len = (state % 33) as usize;
+            for byte in bytes[len..].iter_mut() {
+                *byte = 0;
+            }
+            if bytes == [0; 32] {
+                continue;
+            }
+
+            // The conversion is exact in both directions
+            let target = checked_target_from_le_bytes(&bytes).expect("BUG: valid target rejected");
+            assert_eq!(target_into_le_bytes(&target), bytes);
+            assert_eq!(
+                checked_target_from_le_bytes(&target_into_le_bytes(&target))
+                    .expect("BUG: valid target rejected"),
+                target
+            );
+        }
+
+        let max = checked_target_from_le_bytes(&[0xff; 32]).expect("BUG: valid target rejected");
+        assert_eq!(target_into_le_bytes(&max), [0xff; 32]);
+        assert_eq!(difficulty_from_target(&max), 0);
+        assert!(checked_target_from_le_bytes(&[0; 32]).is_err());
+        assert!(checked_pool_target_from_le_bytes(&[0; 32]).is_err());
+        // Targets of the hardest sane difficulty are accepted, harder ones are absurd
+        let hardest =
+            Target::from(difficulty_1_target().into_inner() >> ABSURD_POOL_DIFFICULTY_BITS);
+        assert!(checked_pool_target_from_le_bytes(&target_into_le_bytes(&hardest)).is_ok());
+        let absurd = Target::from(hardest.into_inner() - 1);
+        assert!(checked_pool_target_from_le_bytes(&target_into_le_bytes(&absurd)).is_err());
+        assert!(checked_pool_target_from_le_bytes(&[0xff; 32]).is_ok());
+
+        // Maximal target advertised when opening a channel passes the validation when the
+        // remote server echoes it back
+        let advertised = target_into_le_bytes(&difficulty_1_target());
+        assert_eq!(
+            checked_target_from_le_bytes(&advertised).expect("BUG: valid target rejected"),
+            difficulty_1_target()
+        );
+    }
+
+    #[test]
+    fn test_hashrate() {
+        // 2^32 hashes per second at difficulty 1 yield one share per second
+        let hashrate = HashesUnit::Hashes(1 << 32);
+        assert_eq!(
+            expected_shares_per_minute(hashrate, &difficulty_1_target()),
+            60.0
+        );
+        assert_eq!(target_from_hashrate(hashrate, 60.0), difficulty_1_target());
+        assert_eq!(
+            target_from_hashrate(hashrate, 1.0),
+            target_from_difficulty(60)
+        );
+
+        let hashrate = HashesUnit::TeraHashes(14.0);
+        let target = target_from_hashrate(hashrate, 20.0);
+        let shares_per_minute = expected_shares_per_minute(hashrate, &target);
+        assert!((shares_per_minute - 20.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_comparison() {
+        let easy = target_from_difficulty(2);
+        let hard = target_from_difficulty(1024);
+        assert!(is_harder(&hard, &easy));
+        assert!(!is_harder(&easy, &hard));
+        assert!(is_easier(&easy, &hard));
+        assert!(!is_easier(&easy, &easy));
+        assert_eq!(harder_of(easy, hard), hard);
+        assert_eq!(easier_of(easy, hard), easy);
+    }
+}
@@ -24,6 +24,7 @@
 
 mod client;
 
+pub use client::ErrorCode;
 pub use client::ErrorKind as Client;
 
 use ii_async_compat::prelude::*;
@@ -90,6 +91,63 @@ impl Error {
     pub fn kind(&self) -> ErrorKind {
         self.inner.get_context().clone()
     }
+
+    /// Stable code of the most specific error in the chain of causes. Errors are often wrapped
+    /// with a general context describing the failed step, the code of the wrapped error is used
+    /// then.
+    pub fn error_code(&self) -> &'static str {
+        let mut code = self.inner.get_context().error_code();
+        let mut cause = self.cause();
+        while code == ErrorKind::GENERAL {
+            let fail = match cause {
+                Some(fail) => fail,
+                None => break,
+            };
+            if let Some(error) = fail.downcast_ref::<Error>() {
+                code = error.inner.get_context().error_code();
+            }
+            cause = fail.cause();
+        }
+        code
+    }
+}
+
+impl ErrorKind {
+    const GENERAL: &'static str = "general";
+
+    /// Stable code of the error, see `client::ErrorKind::error_code`
+    pub fn error_code(&self) -> &'static str {
+        self.info().code
+    }
+
+    fn info(&self) -> ErrorCode {
+        let (code, description) = match self {
+            Self::Io(_) => ("io", "Input/output error"),
+            Self::General(_) => (Self::GENERAL, "Error without a specific code"),
+            Self::Backend(_) => ("backend", "Error of the mining backend"),
+            Self::Stratum(_) => (
+                "stratum.protocol",
+                "The pool has violated the stratum protocol",
+            ),
+            Self::Client(client) => return client.info(),
+        };
+        ErrorCode { code, description }
+    }
+}
+
+/// Codes of all errors with their descriptions for documentation of the API. The list is
+/// append-only.
+pub fn error_codes() -> Vec<ErrorCode> {
+    [
+        ErrorKind::Io(String::new()),
+        ErrorKind::General(String::new()),
+        ErrorKind::Backend(String::new()),
+        ErrorKind::Stratum(String::new()),
+    ]
+    .iter()
+    .map(ErrorKind::info)
+    .chain(Client::registry())
+    .collect()
 }
 
 impl From<ErrorKind> for Error {
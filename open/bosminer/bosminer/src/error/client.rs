@@ -21,6 +21,7 @@
 // contact us at opensource@braiins.com.
 
 use failure::Fail;
+use serde::Serialize;
 
 #[derive(Clone, Eq, PartialEq, Debug, Fail)]
 pub enum ErrorKind {
@@ -32,4 +33,247 @@ pub enum ErrorKind {
     OnlyFixedShareRatio,
     #[fail(display = "total fixed share ratio is greater than or equal to 1.0")]
     FixedShareRatioOverflow,
+    #[fail(display = "sending to the remote server has not completed in time")]
+    SendTimeout,
+    #[fail(display = "invalid target received from the remote server: {}", _0)]
+    InvalidTarget(String),
+    #[fail(display = "share ordering violation: {}", _0)]
+    ShareOrderingViolation(String),
+    #[fail(
+        display = "difficulty requested by the remote server is out of range: {}",
+        _0
+    )]
+    DifficultyOutOfRange(String),
+    #[fail(display = "{}", _0)]
+    UserFile(String),
+    #[fail(display = "the user in the user file has changed")]
+    UserChanged,
+    #[fail(
+        display = "the remote server has acknowledged shares {} times while none has been submitted",
+        _0
+    )]
+    UnexpectedAcks(usize),
+    #[fail(display = "the remote server has not opened the channel: {}", code)]
+    ChannelOpen { code: String, permanent: bool },
+    #[fail(
+        display = "the remote server has not sent job {} referenced by the new prevhash",
+        _0
+    )]
+    MissingPrevHashJob(u32),
+    #[fail(
+        display = "the remote server has reused job ID {} for a different job",
+        _0
+    )]
+    JobIdReused(u32),
+    #[fail(display = "the client is not connected to the remote server")]
+    NotConnected,
+    #[fail(
+        display = "the remote server has reset the connection during the handshake: {}",
+        _0
+    )]
+    ConnectionReset(String),
+    #[fail(display = "invalid address of the remote server: {}", _0)]
+    InvalidAddress(String),
+    #[fail(display = "cannot resolve the remote server: {}", _0)]
+    DnsFailure(String),
+    #[fail(display = "cannot connect to the remote server: {}", _0)]
+    ConnectFailed(String),
+    #[fail(display = "connecting to the remote server has not completed in time")]
+    ConnectTimeout,
+    #[fail(
+        display = "share submissions stall while the remote server keeps sending frames: {}",
+        _0
+    )]
+    TransmitStalled(String),
+    #[fail(
+        display = "the remote server has dropped the channel before the first share: {}",
+        _0
+    )]
+    DroppedBeforeFirstShare(String),
+    #[fail(display = "invalid difficulty suggestion: {}", _0)]
+    InvalidSuggestion(String),
+    #[fail(
+        display = "the remote server has set unknown flags {:#x} in SetupConnectionSuccess",
+        _0
+    )]
+    UnknownPoolFlags(u32),
+    #[fail(display = "shares keep being rejected by the remote server: {}", _0)]
+    RejectStreak(String),
+    #[fail(display = "fault injection is not enabled in the configuration")]
+    FaultInjectionDisabled,
+    #[fail(display = "injected fault: {}", _0)]
+    InjectedFault(String),
+    #[fail(display = "startup has not completed within its budget: {}", _0)]
+    StartupBudgetExhausted(String),
+}
+
+/// Stable code of an error kind with a human readable description. Codes are never renamed nor
+/// removed (see `golden/error_codes.json`), the description may change.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize)]
+pub struct ErrorCode {
+    pub code: &'static str,
+    pub description: &'static str,
+}
+
+impl ErrorKind {
+    /// Stable code of the error for programmatic handling, unlike the displayed message it doesn't
+    /// change between releases
+    pub fn error_code(&self) -> &'static str {
+        self.info().code
+    }
+
+    pub(crate) fn info(&self) -> ErrorCode {
+        let (code, description) = match self {
+            Self::Missing => ("client.registry.missing", "The client is not registered"),
+            Self::Additional => (
+                "client.registry.additional",
+                "The client is already registered",
+            ),
+            Self::OnlyFixedShareRatio => (
+                "client.config.only_fixed_share_ratio",
+                "All client groups have only fixed share ratio",
+            ),
+            Self::FixedShareRatioOverflow => (
+                "client.config.fixed_share_ratio_overflow",
+                "Total fixed share ratio of client groups is not lower than 1.0",
+            ),
+            Self::SendTimeout => (
+                "stratum.submit.send_timeout",
+                "Sending to the pool has not completed in time",
+            ),
+            Self::InvalidTarget(_) => (
+                "stratum.target.invalid",
+                "The pool has sent a target that cannot be used",
+            ),
+            Self::ShareOrderingViolation(_) => (
+                "stratum.submit.ordering_violation",
+                "The pool has acknowledged shares out of the order of submission",
+            ),
+            Self::DifficultyOutOfRange(_) => (
+                "stratum.target.difficulty_out_of_range",
+                "The pool has requested a difficulty outside of the configured range",
+            ),
+            Self::UserFile(_) => (
+                "stratum.config.user_file",
+                "The pool user cannot be read from the user file",
+            ),
+            Self::UserChanged => (
+                "stratum.config.user_changed",
+                "The pool user in the user file has changed",
+            ),
+            Self::UnexpectedAcks(_) => (
+                "stratum.submit.unexpected_acks",
+                "The pool has acknowledged shares that have not been submitted",
+            ),
+            Self::ChannelOpen { .. } => (
+                "stratum.channel.open_rejected",
+                "The pool has rejected opening of the mining channel",
+            ),
+            Self::MissingPrevHashJob(_) => (
+                "stratum.job.missing_prevhash_job",
+                "The pool has not sent the job referenced by the new prevhash",
+            ),
+            Self::JobIdReused(_) => (
+                "stratum.job.id_reused",
+                "The pool has reused a job ID for a different job",
+            ),
+            Self::NotConnected => (
+                "stratum.command.not_connected",
+                "The command requires a connection to the pool",
+            ),
+            Self::ConnectionReset(_) => (
+                "stratum.handshake.connection_reset",
+                "The pool has reset the connection during the handshake",
+            ),
+            Self::InvalidAddress(_) => (
+                "stratum.connect.invalid_address",
+                "The address of the pool is not valid",
+            ),
+            Self::DnsFailure(_) => (
+                "stratum.connect.dns_failure",
+                "The host name of the pool cannot be resolved",
+            ),
+            Self::ConnectFailed(_) => (
+                "stratum.connect.failed",
+                "The connection to the pool cannot be established",
+            ),
+            Self::ConnectTimeout => (
+                "stratum.connect.timeout",
+                "Connecting to the pool has not completed in time",
+            ),
+            Self::TransmitStalled(_) => (
+                "stratum.submit.transmit_stalled",
+                "Share submissions stall while the pool keeps sending jobs",
+            ),
+            Self::DroppedBeforeFirstShare(_) => (
+                "stratum.channel.dropped_before_first_share",
+                "The pool has dropped the channel before the first share within its deadline",
+            ),
+            Self::InvalidSuggestion(_) => (
+                "stratum.command.invalid_suggestion",
+                "The suggested difficulty is not valid or is outside of the configured range",
+            ),
+            Self::UnknownPoolFlags(_) => (
+                "stratum.handshake.unknown_flags",
+                "The pool has set flags of the connection that the client doesn't recognize",
+            ),
+            Self::RejectStreak(_) => (
+                "stratum.submit.reject_streak",
+                "The pool keeps rejecting shares for the same reason",
+            ),
+            Self::FaultInjectionDisabled => (
+                "stratum.command.fault_injection_disabled",
+                "Faults cannot be armed unless fault injection is enabled in the configuration",
+            ),
+            Self::InjectedFault(_) => (
+                "stratum.drill.injected_fault",
+                "The failure has been caused by a fault armed for a resilience drill",
+            ),
+            Self::StartupBudgetExhausted(_) => (
+                "stratum.startup.budget_exhausted",
+                "The client has not started mining within the overall startup deadline",
+            ),
+        };
+        ErrorCode { code, description }
+    }
+
+    /// Codes of all error kinds, a sample of every variant has to be listed here
+    pub fn registry() -> Vec<ErrorCode> {
+        [
+            Self::Missing,
+            Self::Additional,
+            Self::OnlyFixedShareRatio,
+            Self::FixedShareRatioOverflow,
+            Self::SendTimeout,
+            Self::InvalidTarget(String::new()),
+            Self::ShareOrderingViolation(String::new()),
+            Self::DifficultyOutOfRange(String::new()),
+            Self::UserFile(String::new()),
+            Self::UserChanged,
+            Self::UnexpectedAcks(0),
+            Self::ChannelOpen {
+                code: String::new(),
+                permanent: false,
+            },
+            Self::MissingPrevHashJob(0),
+            Self::JobIdReused(0),
+            Self::NotConnected,
+            Self::ConnectionReset(String::new()),
+            Self::InvalidAddress(String::new()),
+            Self::DnsFailure(String::new()),
+            Self::ConnectFailed(String::new()),
+            Self::ConnectTimeout,
+            Self::TransmitStalled(String::new()),
+            Self::DroppedBeforeFirstShare(String::new()),
+            Self::InvalidSuggestion(String::new()),
+            Self::UnknownPoolFlags(0),
+            Self::RejectStreak(String::new()),
+            Self::FaultInjectionDisabled,
+            Self::InjectedFault(String::new()),
+            Self::StartupBudgetExhausted(String::new()),
+        ]
+        .iter()
+        .map(Self::info)
+        .collect()
+    }
 }
@@ -36,6 +36,7 @@ use ii_async_compat::futures;
 use std::convert::TryInto;
 use std::fmt::Debug;
 use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Weak};
 
 use downcast_rs::{impl_downcast, Downcast};
@@ -56,9 +57,10 @@ pub trait Bitcoin: Debug + Downcast + Send + Sync {
     fn merkle_root(&self) -> &ii_bitcoin::DHash;
     /// Current block timestamp as seconds since 1970-01-01T00:00 UTC
     fn time(&self) -> u32;
-    /// Maximal timestamp for current block as seconds since 1970-01-01T00:00 UTC
+    /// Maximal timestamp for current block as seconds since 1970-01-01T00:00 UTC. The work engine
+    /// never rolls ntime beyond it (the engine has its own limit when the job has none).
     fn max_time(&self) -> u32 {
-        self.time()
+        u32::max_value()
     }
     /// Current network target in compact format (network difficulty)
     /// https://en.bitcoin.it/wiki/Difficulty
@@ -101,15 +103,45 @@ impl Solver {
     }
 }
 
+/// Consumer of the jobs broadcast by `Sender` that keeps work queued ahead (e.g. a backend that
+/// swaps work only at the head of its queue). All notifications are no-op by default.
+pub trait Observer: Debug + Send + Sync {
+    /// `job` has been broadcast, `generation` increases with every broadcast job
+    fn job_sent(&self, _job: &Arc<dyn Bitcoin>, _generation: u64) {}
+
+    /// All work generated from jobs broadcast before `job` of `generation` is worthless (the job
+    /// builds on a new block). It is always signalled after `job` has been sent, an observer that
+    /// has seen a newer generation in the meantime may ignore it. Returns the number of queued
+    /// work items that have been discarded when the consumer is able to tell.
+    fn invalidate_preceding(&self, _job: &Arc<dyn Bitcoin>, _generation: u64) -> Option<usize> {
+        None
+    }
+}
+
 /// This is the entrypoint for new jobs and updates into processing.
 /// Typically the mining protocol handler will inject new jobs through it
+#[derive(Debug)]
 pub struct Sender {
     engine_sender: Arc<work::EngineSender>,
+    observer: Option<Arc<dyn Observer>>,
+    /// Generation of the last broadcast job
+    generation: AtomicU64,
 }
 
 impl Sender {
     pub fn new(engine_sender: Arc<work::EngineSender>) -> Self {
-        Self { engine_sender }
+        Self {
+            engine_sender,
+            observer: None,
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_observer(self, observer: Arc<dyn Observer>) -> Self {
+        Self {
+            observer: Some(observer),
+            ..self
+        }
     }
 
     /// Check if the job has valid attributes
@@ -132,24 +164,45 @@ impl Sender {
         valid
     }
 
-    pub fn send(&self, job: Arc<dyn job::Bitcoin>) {
+    /// Broadcast `job` and return its generation, nothing is returned when the job has been
+    /// discarded
+    pub fn send(&self, job: Arc<dyn job::Bitcoin>) -> Option<u64> {
         let origin = job.origin().upgrade();
         if !Self::job_sanity_check(&job, &origin) {
             origin.map(|origin| origin.client_stats().invalid_jobs().inc());
-            return;
+            return None;
         }
 
         // send only jobs with correct data
         if let Some(origin) = origin {
             origin.client_stats().valid_jobs().inc();
             info!("--- broadcasting new job ---");
-            self.engine_sender.broadcast_job(job);
+            let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+            self.engine_sender.broadcast_job(job.clone());
+            if let Some(observer) = self.observer.as_ref() {
+                observer.job_sent(&job, generation);
+            }
+            Some(generation)
         } else {
             // Origin has been removed and no one will receive any solution
             info!("--- discarding job ---");
+            None
         }
     }
 
+    /// Signal that work of all jobs broadcast before `job` of `generation` is worthless (see
+    /// `Observer::invalidate_preceding`). Returns the number of discarded work items when the
+    /// observer reports it.
+    pub fn invalidate_preceding(
+        &self,
+        job: &Arc<dyn job::Bitcoin>,
+        generation: u64,
+    ) -> Option<usize> {
+        self.observer
+            .as_ref()?
+            .invalidate_preceding(job, generation)
+    }
+
     #[inline]
     pub fn invalidate(&self) {
         self.engine_sender.invalidate();
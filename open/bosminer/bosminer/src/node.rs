@@ -20,6 +20,7 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
+use crate::client;
 use crate::job;
 use crate::stats;
 use crate::sync;
@@ -60,6 +61,14 @@ pub trait Client: Info + ClientStats {
     async fn get_last_job(&self) -> Option<Arc<dyn job::Bitcoin>>;
     /// FIXME: Do not allow dynamic descriptor changes
     fn change_connection_details(&self, _descriptor: &bosminer_config::ClientDescriptor) {}
+    /// Client has been activated or deactivated by the scheduler. The annotation has been already
+    /// recorded in the switch journal of the client handle.
+    fn annotate_switch(&self, _annotation: &client::switches::Annotation) {}
+    /// The client is not running but the scheduler should not fail over to another client (e.g.
+    /// the pool is restarting)
+    fn holds_failover(&self) -> bool {
+        false
+    }
 }
 
 pub trait ClientStats: Stats {
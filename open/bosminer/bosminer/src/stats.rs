@@ -171,6 +171,17 @@ impl Meter {
         Snapshot::new(self.inner.lock().await.clone())
     }
 
+    /// Restore totals of a meter that hasn't been used yet (e.g. handed over by the previous
+    /// instance of the process). The time means start from scratch.
+    pub(crate) fn restore(&self, solutions: u64, shares: ii_bitcoin::Shares) {
+        let mut meter = self
+            .inner
+            .try_lock()
+            .expect("BUG: meter is restored while in use");
+        meter.solutions = solutions;
+        meter.shares = shares;
+    }
+
     pub(crate) async fn account_solution(&self, target: &ii_bitcoin::Target, time: time::Instant) {
         let mut meter = self.inner.lock().await;
         let kilo_hashes = ii_bitcoin::Shares::new(target)
@@ -251,7 +262,10 @@ impl BestShare {
     }
 
     pub(crate) fn account_solution(&self, target: &ii_bitcoin::Target) {
-        let new_diff = target.get_difficulty();
+        self.account_difficulty(target.get_difficulty());
+    }
+
+    pub(crate) fn account_difficulty(&self, new_diff: usize) {
         let mut old_diff = self.inner.load(Ordering::Relaxed);
 
         while old_diff < new_diff {
@@ -287,6 +301,8 @@ pub trait AtomicCounter: Debug {
     fn add(&self, value: Self::Type);
     /// Loads a value from the atomic type
     fn load(&self) -> Self::Type;
+    /// Set the value back to the default
+    fn reset(&self);
 }
 
 macro_rules! atomic_counter_impl (
@@ -313,6 +329,11 @@ macro_rules! atomic_counter_impl (
             fn load(&self) -> Self::Type {
                 self.load(Ordering::Relaxed)
             }
+
+            #[inline]
+            fn reset(&self) {
+                self.store(Default::default(), Ordering::Relaxed);
+            }
         }
     )
 );
@@ -346,6 +367,11 @@ where
     pub fn add(&self, count: T::Type) {
         self.inner.add(count);
     }
+
+    #[inline]
+    pub fn reset(&self) {
+        self.inner.reset();
+    }
 }
 
 impl<T> Default for Counter<T>
@@ -34,6 +34,8 @@ pub enum Status {
     Created,
     Starting,
     Retrying,
+    /// Connection has been established but no job has been dispatched yet
+    Connected,
     Running,
     Stopping,
     Failing,
@@ -110,6 +112,7 @@ impl StatusMonitor {
                 // Client is currently started
                 Status::Starting
                 | Status::Retrying
+                | Status::Connected
                 | Status::Running
                 | Status::Restarting
                 | Status::Recovering => break,
@@ -130,7 +133,7 @@ impl StatusMonitor {
                 Status::Created | Status::Stopped | Status::Failing | Status::Failed => {
                     panic!("BUG: 'report_fail': unexpected state '{:?}'", status)
                 }
-                Status::Starting | Status::Retrying => {
+                Status::Starting | Status::Retrying | Status::Connected => {
                     status =
                         self.status
                             .compare_and_swap(status, Status::Running, Ordering::Relaxed);
@@ -152,6 +155,38 @@ impl StatusMonitor {
         true
     }
 
+    /// The client has connected but it doesn't mine yet, `initiate_running` is expected to be
+    /// called once the first job is dispatched
+    pub fn initiate_connected(&self) -> bool {
+        let mut status = self.status();
+
+        loop {
+            let previous = status;
+            match status {
+                Status::Created | Status::Stopped | Status::Failing | Status::Failed => {
+                    panic!("BUG: 'initiate_connected': unexpected state '{:?}'", status)
+                }
+                Status::Starting | Status::Retrying => {
+                    status =
+                        self.status
+                            .compare_and_swap(status, Status::Connected, Ordering::Relaxed);
+                    if status == previous {
+                        // Connected has been set successfully
+                        break;
+                    }
+                }
+                Status::Connected | Status::Running => break,
+                Status::Stopping | Status::Declining | Status::Restarting | Status::Recovering => {
+                    return false
+                }
+            }
+            // Try it again because another task change the state
+        }
+
+        // Connected can be done
+        true
+    }
+
     pub fn initiate_stopping(&self) -> bool {
         let mut status = self.status();
 
@@ -165,7 +200,7 @@ impl StatusMonitor {
                 | Status::Stopped
                 | Status::Failed => break,
                 // Client is currently started
-                Status::Starting | Status::Running | Status::Restarting => {
+                Status::Starting | Status::Connected | Status::Running | Status::Restarting => {
                     status =
                         self.status
                             .compare_and_swap(status, Status::Stopping, Ordering::Relaxed);
@@ -209,7 +244,8 @@ impl StatusMonitor {
                         break;
                     }
                 }
-                Status::Starting | Status::Retrying => {
+                // The client hasn't been mining yet
+                Status::Starting | Status::Retrying | Status::Connected => {
                     status =
                         self.status
                             .compare_and_swap(status, Status::Declining, Ordering::Relaxed);
@@ -231,6 +267,7 @@ impl StatusMonitor {
             Status::Stopping | Status::Failing | Status::Declining => true,
             Status::Created
             | Status::Starting
+            | Status::Connected
             | Status::Running
             | Status::Retrying
             | Status::Restarting
@@ -249,6 +286,7 @@ impl StatusMonitor {
                 Status::Created
                 | Status::Starting
                 | Status::Retrying
+                | Status::Connected
                 | Status::Running
                 | Status::Stopped
                 | Status::Failed => panic!("BUG: 'can_stop': unexpected state '{:?}'", status),
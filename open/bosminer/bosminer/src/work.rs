@@ -132,6 +132,24 @@ impl Assignment {
     }
 }
 
+/// Reason why a solution cannot be handled by the client that has received it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OriginMismatch {
+    /// The job of the solution is of another type than the jobs of the client
+    JobType,
+    /// The job has been generated by another client (or by a client that no longer exists)
+    Client,
+}
+
+impl fmt::Display for OriginMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::JobType => write!(f, "job of a foreign type"),
+            Self::Client => write!(f, "job generated by another client"),
+        }
+    }
+}
+
 /// Container with mining work and a corresponding solution received at a particular time
 /// This data structure is used when posting work+solution pairs for further submission upstream.
 #[derive(Clone)]
@@ -142,6 +160,9 @@ pub struct Solution {
     work: Assignment,
     /// Solution of the PoW puzzle
     solution: Arc<dyn hal::BackendSolution>,
+    /// Version corrected after the solution has been found, the version of the midstate is used
+    /// otherwise
+    version: Option<u32>,
     /// Lazy evaluated double hash of this solution
     hash: OnceCell<ii_bitcoin::DHash>,
     /// Lazy evaluated job target to ensure that the value is stable for this solution
@@ -160,6 +181,7 @@ impl Solution {
             timestamp: timestamp.unwrap_or_else(|| time::Instant::now()),
             work,
             solution: Arc::new(solution),
+            version: None,
             hash: OnceCell::new(),
             backend_target: OnceCell::new(),
             job_target: OnceCell::new(),
@@ -178,12 +200,40 @@ impl Solution {
     }
 
     pub fn job<T: job::Bitcoin>(&self) -> &T {
-        self.work
-            .job
-            .downcast_ref::<T>()
+        self.try_job::<T>()
             .expect("cannot downcast to original job")
     }
 
+    /// Return the original job when it is of type `T`
+    #[inline]
+    pub fn try_job<T: job::Bitcoin>(&self) -> Option<&T> {
+        self.work.job.downcast_ref::<T>()
+    }
+
+    /// Return the original job when it is of type `T` and it has been generated by `client`. All
+    /// submission paths of a client check the solution with it before the job is used.
+    /// NOTE: The client is compared the same way as in `client::Handle::matching_solution`.
+    pub fn checked_job<T: job::Bitcoin>(
+        &self,
+        client: Arc<dyn node::Client>,
+    ) -> Result<&T, OriginMismatch> {
+        let job = self.try_job::<T>().ok_or(OriginMismatch::JobType)?;
+        let same_client = job
+            .origin()
+            .upgrade()
+            .map(|origin| Arc::ptr_eq(&client.get_unique_ptr(), &origin.get_unique_ptr()));
+        // Solutions are routed to the client that has generated their job, a solution of another
+        // existing client is a bug of the routing and it is only rejected in release builds
+        debug_assert!(
+            same_client.unwrap_or(true),
+            "BUG: solution of a job generated by another client"
+        );
+        match same_client {
+            Some(true) => Ok(job),
+            _ => Err(OriginMismatch::Client),
+        }
+    }
+
     #[inline]
     pub fn nonce(&self) -> u32 {
         self.solution.nonce()
@@ -197,7 +247,20 @@ impl Solution {
     #[inline]
     pub fn version(&self) -> u32 {
         let i = self.midstate_idx();
-        self.work.midstates[i].version
+        self.version.unwrap_or(self.work.midstates[i].version)
+    }
+
+    /// Returns the solution with a corrected `version` (the hash is evaluated again)
+    pub fn with_version(&self, version: u32) -> Self {
+        Self {
+            timestamp: self.timestamp,
+            work: self.work.clone(),
+            solution: self.solution.clone(),
+            version: Some(version),
+            hash: OnceCell::new(),
+            job_target: self.job_target.clone(),
+            backend_target: self.backend_target.clone(),
+        }
     }
 
     #[inline]
@@ -247,6 +310,23 @@ impl Solution {
         self.work.job.is_valid()
     }
 
+    /// Estimate of memory retained by the solution excluding the job that is shared with other
+    /// solutions
+    pub fn retained_size(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.work.path.capacity() * mem::size_of::<node::DynInfo>()
+            + self.work.midstates.capacity() * mem::size_of::<Midstate>()
+            + mem::size_of_val(&*self.solution)
+    }
+
+    /// Address and size of the job that is shared with other solutions of the same job
+    pub fn shared_job(&self) -> (usize, usize) {
+        (
+            &*self.work.job as *const dyn job::Bitcoin as *const () as usize,
+            mem::size_of_val(&*self.work.job),
+        )
+    }
+
     /// Return the whole unique path starting from job origin and ending in backend.
     pub fn path(&self) -> node::Path {
         // Arc does not support dynamic casting to trait bounds so there must be used another Arc
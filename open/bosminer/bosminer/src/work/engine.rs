@@ -131,7 +131,8 @@ impl AtomicRange {
 /// Version rolling implements WorkEngine trait and represents a shared source of work for mining
 /// backends. Each instance takes care of atomically allocating version field ranges until the
 /// range is full exhausted. After version has been rolled over, ntime is incremented and version
-/// resetted to 0. The limit of `ntime` range is determined by `ROLL_NTIME_SECONDS`.
+/// resetted to 0. The limit of `ntime` range is determined by `ROLL_NTIME_SECONDS` or by maximal
+/// time of the job when it is lower.
 ///
 /// TODO: Rolling ntime together with version IS A HACK. This needs to be fixed properly by raising
 /// `ntime` in sync with real-time clock.
@@ -157,12 +158,18 @@ impl VersionRolling {
             BIP320_UPPER_BOUND_EXCLUSIVE_INDEX % (midstate_count as u32),
             0
         );
+        // ntime is rolled up to the maximal time of the job (inclusive)
+        let roll_ntime_seconds = job
+            .max_time()
+            .saturating_sub(job.time())
+            .saturating_add(1)
+            .min(ROLL_NTIME_SECONDS);
         Self {
             job,
             midstate_count,
             curr_range: AtomicRange::new(
                 0,
-                BIP320_UPPER_BOUND_EXCLUSIVE_INDEX * ROLL_NTIME_SECONDS,
+                BIP320_UPPER_BOUND_EXCLUSIVE_INDEX * roll_ntime_seconds,
                 midstate_count as u32,
             ),
             base_version,
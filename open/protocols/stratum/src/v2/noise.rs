@@ -36,6 +36,8 @@ use ii_wire;
 use crate::error::{Error, ErrorKind, Result, ResultExt};
 use crate::v2;
 
+use ii_logging::macros::*;
+
 pub mod codec;
 pub use codec::Codec;
 
@@ -82,14 +84,28 @@ pub fn generate_keypair() -> Result<StaticKeypair> {
 pub struct Initiator {
     stage: usize,
     handshake_state: HandshakeState,
-    /// Public key that the Initiatior will use to construct a 'Certificate' on the fly from
+    /// Public keys that the Initiatior will use to construct a 'Certificate' on the fly from
     /// the SignatureNoiseMessage and of the static public key of the `Responder` and will verify
-    /// the authenticity of the static public key of the Responder
-    authority_public_key: ed25519_dalek::PublicKey,
+    /// the authenticity of the static public key of the Responder. The static key is accepted
+    /// when it has been signed by any of the keys, this allows the upstream to rotate its
+    /// authority key without a downtime.
+    authority_public_keys: Vec<ed25519_dalek::PublicKey>,
 }
 
 impl Initiator {
     pub fn new(authority_public_key: ed25519_dalek::PublicKey) -> Self {
+        Self::with_authority_public_keys(vec![authority_public_key])
+    }
+
+    /// Build initiator that accepts the remote static key signed by any of the authority keys.
+    /// The keys are tried in the order they have been provided.
+    pub fn with_authority_public_keys(
+        authority_public_keys: Vec<ed25519_dalek::PublicKey>,
+    ) -> Self {
+        assert!(
+            !authority_public_keys.is_empty(),
+            "BUG: at least one authority public key is required"
+        );
         let params: NoiseParams = PARAMS.parse().expect("BUG: cannot parse noise parameters");
 
         // Initialize our initiator using a builder.
@@ -101,7 +117,7 @@ impl Initiator {
         Self {
             stage: 0,
             handshake_state,
-            authority_public_key,
+            authority_public_keys,
         }
     }
 
@@ -114,10 +130,7 @@ impl Initiator {
         Ok(transport_mode.into_stratum_framed_stream(noise_framed_stream))
     }
 
-    /// Verify the signature of the remote static key
-    /// TODO: verify the signature of the remote static public key based on:
-    ///  - remote central authority public key (must be provided to Initiator instance upon
-    ///    creation)
+    /// Verify the signature of the remote static key against all accepted authority public keys
     fn verify_remote_static_key_signature(
         &mut self,
         signature_noise_message: BytesMut,
@@ -130,16 +143,33 @@ impl Initiator {
         let signature_noise_message =
             auth::SignatureNoiseMessage::try_from(&signature_noise_message[..])?;
 
-        let certificate = auth::Certificate::from_noise_message(
-            signature_noise_message,
-            remote_static_key,
-            self.authority_public_key,
-        );
-        certificate
-            .validate()
-            .context("Validation of certificate")?;
+        let mut last_error = None;
+        for (i, authority_public_key) in self.authority_public_keys.iter().enumerate() {
+            let certificate = auth::Certificate::from_noise_message(
+                signature_noise_message.clone(),
+                remote_static_key.clone(),
+                *authority_public_key,
+            );
+            match certificate.validate() {
+                Ok(()) => {
+                    info!(
+                        "Noise: remote static key signed by authority key {} ({} of {})",
+                        auth::EncodedEd25519PublicKey::new(*authority_public_key),
+                        i + 1,
+                        self.authority_public_keys.len()
+                    );
+                    return Ok(());
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
 
-        Ok(())
+        Err(last_error.expect("BUG: no authority public key"))
+            .context(format!(
+                "Validation of certificate against {} authority key(s)",
+                self.authority_public_keys.len()
+            ))
+            .map_err(Into::into)
     }
 }
 
@@ -433,6 +463,69 @@ pub(crate) mod test {
         assert_eq!(&message[..], &decrypted_msg, "Messages don't match");
     }
 
+    /// Runs the handshake with an initiator accepting the specified authority keys, the result
+    /// of the final initiator step (verification of the responder certificate) is returned
+    fn handshake_with_authority_keys(
+        authority_public_keys: Vec<ed25519_dalek::PublicKey>,
+        signature_noise_message: Bytes,
+        static_keypair: &StaticKeypair,
+    ) -> Result<handshake::StepResult> {
+        let mut initiator = Initiator::with_authority_public_keys(authority_public_keys);
+        let mut responder = Responder::new(static_keypair, signature_noise_message);
+
+        responder
+            .step(None, BytesMut::new())
+            .expect("BUG: responder failed in the first step");
+        let initiator_out_msg = match initiator
+            .step(None, BytesMut::new())
+            .expect("BUG: Initiator failed")
+        {
+            handshake::StepResult::ExpectReply(msg) => msg,
+            result => panic!("BUG: unexpected initiator step result {:?}", result),
+        };
+        let responder_out_msg = match responder
+            .step(Some(initiator_out_msg), BytesMut::new())
+            .expect("BUG: responder failed")
+        {
+            handshake::StepResult::NoMoreReply(msg) => msg,
+            result => panic!("BUG: unexpected responder step result {:?}", result),
+        };
+        initiator.step(Some(responder_out_msg), BytesMut::new())
+    }
+
+    /// Verifies that the initiator accepts a certificate signed by any of the authority keys and
+    /// rejects it when none of them matches
+    #[test]
+    fn test_handshake_authority_key_rotation() {
+        let mut csprng = rand::rngs::OsRng {};
+        let current_keypair = ed25519_dalek::Keypair::generate(&mut csprng);
+        let unrelated_keypair = ed25519_dalek::Keypair::generate(&mut csprng);
+
+        // The responder already uses the next authority key
+        let (signature_noise_message, next_keypair, static_keypair) =
+            build_serialized_signature_noise_message_and_keypairs();
+
+        let result = handshake_with_authority_keys(
+            vec![current_keypair.public, next_keypair.public],
+            signature_noise_message.clone(),
+            &static_keypair,
+        );
+        assert_eq!(
+            result.expect("BUG: secondary authority key not accepted"),
+            handshake::StepResult::Done
+        );
+
+        let result = handshake_with_authority_keys(
+            vec![current_keypair.public, unrelated_keypair.public],
+            signature_noise_message,
+            &static_keypair,
+        );
+        assert!(
+            result.is_err(),
+            "BUG: certificate accepted without matching authority key"
+        );
+    }
+
     fn bind_test_server() -> Option<(ii_wire::Server, ii_wire::Address)> {
         const ADDR: &'static str = "127.0.0.1";
         const MIN_PORT: u16 = 9999;